@@ -21,6 +21,12 @@ fn main() {
     generate_contract_with_config("BalancerV2Authorizer", |builder| {
         builder.contract_mod_override("balancer_v2_authorizer")
     });
+    generate_contract_with_config("BalancerV2AaveLinearPoolFactory", |builder| {
+        builder
+            .contract_mod_override("balancer_v2_aave_linear_pool_factory")
+            .add_network_str("1", "0xD7FAD3bd59D6477cbe1BE7f646F7f1BA25b230f8")
+    });
+    generate_contract("BalancerV2AaveLinearPool");
     generate_contract_with_config("BalancerV2BasePool", |builder| {
         builder.contract_mod_override("balancer_v2_base_pool")
     });
@@ -31,6 +37,18 @@ fn main() {
     // Mainnet: https://github.com/balancer-labs/balancer-subgraph-v2/blob/master/subgraph.yaml
     // Rinkeby: https://github.com/balancer-labs/balancer-subgraph-v2/blob/master/subgraph.rinkeby.yaml
     // Görli: https://github.com/balancer-labs/balancer-subgraph-v2/blob/master/subgraph.goerli.yaml
+    generate_contract_with_config("BalancerV2ComposableStablePoolFactory", |builder| {
+        builder
+            .contract_mod_override("balancer_v2_composable_stable_pool_factory")
+            .add_network_str("1", "0xf9ac7B9dF2b3454E841110CdE8B4B71b4c0cB6D2")
+    });
+    generate_contract("BalancerV2ComposableStablePool");
+    generate_contract_with_config("BalancerV2GyroEclpPoolFactory", |builder| {
+        builder
+            .contract_mod_override("balancer_v2_gyro_eclp_pool_factory")
+            .add_network_str("1", "0x412a5B2e7a678471985542757A6855847D4931D5")
+    });
+    generate_contract("BalancerV2GyroEclpPool");
     generate_contract_with_config("BalancerV2Vault", |builder| {
         builder
             .contract_mod_override("balancer_v2_vault")
@@ -157,6 +175,11 @@ fn main() {
             )
         // Not deployed on Görli
     });
+    generate_contract_with_config("BalancerV2ManagedPoolFactory", |builder| {
+        builder
+            .contract_mod_override("balancer_v2_managed_pool_factory")
+            .add_network_str("1", "0xBF904F9F340745B4f0c4702c7B6Ab1e808eA6b93")
+    });
     generate_contract_with_config(
         "BalancerV2NoProtocolFeeLiquidityBootstrappingPoolFactory",
         |builder| {
@@ -191,14 +214,21 @@ fn main() {
         )
     });
     generate_contract("BalancerV2LiquidityBootstrappingPool");
+    generate_contract("BalancerV2ManagedPool");
     generate_contract_with_config("BaoswapFactory", |builder| {
         builder.add_network_str("100", "0x45DE240fbE2077dd3e711299538A09854FAE9c9b")
     });
     generate_contract_with_config("BaoswapRouter", |builder| {
         builder.add_network_str("100", "0x6093AeBAC87d62b1A5a4cEec91204e35020E38bE")
     });
+    generate_contract_with_config("CurveAddressProvider", |builder| {
+        builder.add_network_str("1", "0x0000000022D53366457F9d5E68Ec105046FC4383")
+    });
+    generate_contract("CurveRegistry");
     generate_contract("ERC20");
+    generate_contract("ERC20Bytes32Metadata");
     generate_contract("ERC20Mintable");
+    generate_contract("IERC4626");
     generate_contract("GPv2AllowListAuthentication");
     generate_contract_with_config("GPv2Settlement", |builder| {
         builder
@@ -251,6 +281,19 @@ fn main() {
     generate_contract("IUniswapLikePair");
     // EIP-1271 contract - SignatureValidator
     generate_contract("ERC1271SignatureValidator");
+    generate_contract("MakerGemJoin");
+    // The USDC-A PSM, see <https://chainlog.makerdao.com/>.
+    generate_contract_with_config("MakerPsm", |builder| {
+        builder.add_network_str("1", "0x89B78CfA322F6C5dE0aBcEecab66Aee45393cC5A")
+    });
+    generate_contract("MakerVat");
+    generate_contract("MStablePool");
+    generate_contract_with_config("PancakeSwapFactory", |builder| {
+        builder.add_network_str("56", "0xcA143Ce32Fe78f1f7019d7d551a6402fC5350c73")
+    });
+    generate_contract_with_config("PancakeSwapRouter", |builder| {
+        builder.add_network_str("56", "0x10ED43C718714eb63d5aA57B78B54704E256024E")
+    });
     generate_contract_with_config("SushiSwapFactory", |builder| {
         builder
             .add_network_str("1", "0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac")
@@ -272,25 +315,80 @@ fn main() {
         builder.add_network_str("100", "0xE43e60736b1cb4a75ad25240E2f9a62Bff65c0C0")
     });
     generate_contract("ISwaprPair");
+    generate_contract_with_config("TraderJoeFactory", |builder| {
+        builder.add_network_str("43114", "0x9Ad6C38BE94206cA50bb0d90783181662f0Cfa10")
+    });
+    generate_contract_with_config("TraderJoeRouter", |builder| {
+        builder.add_network_str("43114", "0x60aE616a2155Ee3d9A68541Ba4544862310933d4")
+    });
+    generate_contract("IFraxswapPair");
     generate_contract_with_config("UniswapV2Factory", |builder| {
         builder
             .add_network_str("1", "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f")
             .add_network_str("4", "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f")
             .add_network_str("5", "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f")
+            .add_network_str("11155111", "0xF62c03E08ada871A0bEb309762E260a7a6a880E6")
     });
     generate_contract_with_config("UniswapV2Router02", |builder| {
         builder
             .add_network_str("1", "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D")
             .add_network_str("4", "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D")
             .add_network_str("5", "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D")
+            .add_network_str("11155111", "0xeE567Fe1712Faf6149d80dA1E6934E354124CfE3")
     });
     generate_contract_with_config("WETH9", |builder| {
         builder
             .add_network_str("1", "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")
             .add_network_str("4", "0xc778417E063141139Fce010982780140Aa0cD5Ab")
             .add_network_str("5", "0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6")
+            .add_network_str("56", "0xbb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c")
             .add_network_str("100", "0xe91D153E0b41518A2Ce8Dd3D7944Fa863463a97d")
+            .add_network_str("43114", "0xB31f66AA3C1e785363F0875A1B74E27b85FD66c7")
+            .add_network_str("11155111", "0xfFf9976782d46CC05630D1f6eBAb18b2324d6B14")
+    });
+    generate_contract_with_config("WstETH", |builder| {
+        builder.add_network_str("1", "0x7f39C581F595B53c5cb19bD0b3f8dA6c935E2Ca0")
+    });
+    generate_contract("SaddlePoolRegistry");
+    generate_contract("SaddleSwap");
+    generate_contract("SolidlyPool");
+    generate_contract_with_config("SolidlyPoolFactory", |builder| {
+        builder
+            // Velodrome V2
+            .add_network_str("10", "0xF1046053aa5682b4F9a81b5481394DA16BE5FF5a")
+            // Aerodrome
+            .add_network_str("8453", "0x420DD381b31aEf6683db6B902084cB0FFECe40Da")
+    });
+    generate_contract_with_config("UniswapV4PoolManager", |builder| {
+        builder
+            .contract_mod_override("uniswap_v4_pool_manager")
+            .add_network(
+                "1",
+                Network {
+                    address: addr("0x000000000004444c5dc75cB358380D2e3dE08A90"),
+                    deployment_information: Some(DeploymentInformation::BlockNumber(21688329)),
+                },
+            )
+    });
+    generate_contract_with_config("UniswapV4StateView", |builder| {
+        builder
+            .contract_mod_override("uniswap_v4_state_view")
+            .add_network_str("1", "0x7fFE42C4a5DEeA5b0feC41C94C136Cf115597227")
+    });
+    generate_contract_with_config("AmbientCrocQuery", |builder| {
+        builder.add_network_str("1", "0xc2e1f740E11294C64adE66f69a1271C5B32004c8")
     });
+    generate_contract("IntegralTwapFactory");
+    generate_contract("IntegralTwapRelayer");
+    generate_contract("IZiSwapFactory");
+    generate_contract("IZiSwapPool");
+    generate_contract("SynthetixAddressResolver");
+    generate_contract("SynthetixExchanger");
+    generate_contract("SynthetixExchangeRates");
+    generate_contract("SynthetixSynth");
+    generate_contract("SynthetixSystemSettings");
+    generate_contract("WombatAsset");
+    generate_contract("WombatPool");
     generate_contract_with_config("IUniswapV3Factory", |builder| {
         builder
             .add_network_str("1", "0x1F98431c8aD98523631AE4a59f267346ea31F984")
@@ -117,6 +117,10 @@ fn run() -> Result<()> {
             "ERC20",
             "@openzeppelin/contracts@3.3.0/build/contracts/ERC20.json",
         )?
+        .manual(
+            "ERC20Bytes32Metadata",
+            "Manually vendored ABI for tokens predating ERC20 returning bytes32 metadata",
+        )
         .manual(
             "ERC1271SignatureValidator",
             "Manually vendored ABI for ERC-1271 signature validation",
@@ -141,6 +145,147 @@ fn run() -> Result<()> {
             "BalancerV2BasePoolFactory",
             "Balancer does not publish ABIs for base contracts",
         )
+        .manual(
+            "BalancerV2AaveLinearPool",
+            "Manually vendored ABI with the subset of linear pool methods we use",
+        )
+        .manual(
+            "BalancerV2AaveLinearPoolFactory",
+            "Manually vendored ABI with the subset of linear pool factory methods we use",
+        )
+        .manual(
+            "BalancerV2ComposableStablePool",
+            "Manually vendored ABI with the subset of composable stable pool methods we use",
+        )
+        .manual(
+            "BalancerV2ComposableStablePoolFactory",
+            "Manually vendored ABI with the subset of composable stable pool factory methods \
+             we use",
+        )
+        .manual(
+            "BalancerV2GyroEclpPool",
+            "Manually vendored ABI with the subset of Gyroscope E-CLP pool methods we use",
+        )
+        .manual(
+            "BalancerV2GyroEclpPoolFactory",
+            "Manually vendored ABI with the subset of Gyroscope E-CLP pool factory methods we use",
+        )
+        .manual(
+            "BalancerV2ManagedPool",
+            "Manually vendored ABI with the subset of managed pool methods we use",
+        )
+        .manual(
+            "BalancerV2ManagedPoolFactory",
+            "Manually vendored ABI with the subset of managed pool factory methods we use",
+        )
+        .manual(
+            "CurveAddressProvider",
+            "Manually vendored ABI with the subset of address provider methods we use",
+        )
+        .manual(
+            "CurveRegistry",
+            "Manually vendored ABI with the subset of main registry methods we use",
+        )
+        .manual(
+            "MakerGemJoin",
+            "Manually vendored ABI with the subset of gem join adapter methods we use",
+        )
+        .manual(
+            "MakerPsm",
+            "Manually vendored ABI with the subset of PSM methods we use",
+        )
+        .manual(
+            "MakerVat",
+            "Manually vendored ABI with the subset of Vat methods we use",
+        )
+        .manual(
+            "MStablePool",
+            "Manually vendored ABI with the subset of mStable mAsset and feeder pool methods we use",
+        )
+        .manual(
+            "IERC4626",
+            "Manually vendored ABI with the subset of ERC-4626 methods we use",
+        )
+        .manual(
+            "WstETH",
+            "Manually vendored ABI with the subset of wstETH methods we use",
+        )
+        .manual(
+            "SaddlePoolRegistry",
+            "Manually vendored ABI with the subset of Saddle pool registry methods we use",
+        )
+        .manual(
+            "SaddleSwap",
+            "Manually vendored ABI with the subset of Saddle swap methods we use",
+        )
+        .manual(
+            "SolidlyPool",
+            "Manually vendored ABI with the subset of Velodrome V2 pool methods we use",
+        )
+        .manual(
+            "SolidlyPoolFactory",
+            "Manually vendored ABI with the subset of Velodrome V2 pool factory methods we use",
+        )
+        .manual(
+            "UniswapV4PoolManager",
+            "Manually vendored ABI with the subset of Uniswap V4 pool manager events we use",
+        )
+        .manual(
+            "UniswapV4StateView",
+            "Manually vendored ABI with the subset of Uniswap V4 state view methods we use",
+        )
+        .manual(
+            "AmbientCrocQuery",
+            "Manually vendored ABI with the subset of Ambient query lens methods we use",
+        )
+        .manual(
+            "IntegralTwapFactory",
+            "Manually vendored ABI with the subset of Integral factory methods we use",
+        )
+        .manual(
+            "IntegralTwapRelayer",
+            "Manually vendored ABI with the subset of Integral relayer methods we use",
+        )
+        .manual(
+            "IZiSwapFactory",
+            "Manually vendored ABI with the subset of iZiSwap factory methods we use",
+        )
+        .manual(
+            "IZiSwapPool",
+            "Manually vendored ABI with the subset of iZiSwap pool methods we use",
+        )
+        .manual(
+            "IFraxswapPair",
+            "Manually vendored ABI with the subset of Fraxswap pair methods we use",
+        )
+        .manual(
+            "SynthetixAddressResolver",
+            "Manually vendored ABI with the subset of Synthetix address resolver methods we use",
+        )
+        .manual(
+            "SynthetixExchanger",
+            "Manually vendored ABI with the subset of Synthetix exchanger methods we use",
+        )
+        .manual(
+            "SynthetixExchangeRates",
+            "Manually vendored ABI with the subset of Synthetix exchange rates methods we use",
+        )
+        .manual(
+            "SynthetixSynth",
+            "Manually vendored ABI with the subset of Synthetix synth methods we use",
+        )
+        .manual(
+            "SynthetixSystemSettings",
+            "Manually vendored ABI with the subset of Synthetix system settings methods we use",
+        )
+        .manual(
+            "WombatAsset",
+            "Manually vendored ABI with the subset of Wombat asset methods we use",
+        )
+        .manual(
+            "WombatPool",
+            "Manually vendored ABI with the subset of Wombat pool methods we use",
+        )
         .npm(
             "IUniswapV3Factory",
             "@uniswap/v3-core@1.0.0/artifacts/contracts/interfaces/IUniswapV3Factory.sol/IUniswapV3Factory.json",
@@ -2,9 +2,27 @@
 pub mod paths;
 pub mod vault;
 
+include!(concat!(env!("OUT_DIR"), "/BalancerV2AaveLinearPool.rs"));
+include!(concat!(
+    env!("OUT_DIR"),
+    "/BalancerV2AaveLinearPoolFactory.rs"
+));
 include!(concat!(env!("OUT_DIR"), "/BalancerV2Authorizer.rs"));
 include!(concat!(env!("OUT_DIR"), "/BalancerV2BasePool.rs"));
 include!(concat!(env!("OUT_DIR"), "/BalancerV2BasePoolFactory.rs"));
+include!(concat!(
+    env!("OUT_DIR"),
+    "/BalancerV2ComposableStablePool.rs"
+));
+include!(concat!(
+    env!("OUT_DIR"),
+    "/BalancerV2ComposableStablePoolFactory.rs"
+));
+include!(concat!(env!("OUT_DIR"), "/BalancerV2GyroEclpPool.rs"));
+include!(concat!(
+    env!("OUT_DIR"),
+    "/BalancerV2GyroEclpPoolFactory.rs"
+));
 include!(concat!(
     env!("OUT_DIR"),
     "/BalancerV2LiquidityBootstrappingPool.rs"
@@ -13,6 +31,8 @@ include!(concat!(
     env!("OUT_DIR"),
     "/BalancerV2LiquidityBootstrappingPoolFactory.rs"
 ));
+include!(concat!(env!("OUT_DIR"), "/BalancerV2ManagedPool.rs"));
+include!(concat!(env!("OUT_DIR"), "/BalancerV2ManagedPoolFactory.rs"));
 include!(concat!(
     env!("OUT_DIR"),
     "/BalancerV2NoProtocolFeeLiquidityBootstrappingPoolFactory.rs"
@@ -31,8 +51,12 @@ include!(concat!(
 ));
 include!(concat!(env!("OUT_DIR"), "/BaoswapFactory.rs"));
 include!(concat!(env!("OUT_DIR"), "/BaoswapRouter.rs"));
+include!(concat!(env!("OUT_DIR"), "/CurveAddressProvider.rs"));
+include!(concat!(env!("OUT_DIR"), "/CurveRegistry.rs"));
 include!(concat!(env!("OUT_DIR"), "/ERC20.rs"));
+include!(concat!(env!("OUT_DIR"), "/ERC20Bytes32Metadata.rs"));
 include!(concat!(env!("OUT_DIR"), "/ERC20Mintable.rs"));
+include!(concat!(env!("OUT_DIR"), "/IERC4626.rs"));
 include!(concat!(env!("OUT_DIR"), "/GPv2AllowListAuthentication.rs"));
 include!(concat!(env!("OUT_DIR"), "/GPv2Settlement.rs"));
 include!(concat!(env!("OUT_DIR"), "/GnosisSafe.rs"));
@@ -46,14 +70,42 @@ include!(concat!(env!("OUT_DIR"), "/HoneyswapRouter.rs"));
 include!(concat!(env!("OUT_DIR"), "/IUniswapLikePair.rs"));
 include!(concat!(env!("OUT_DIR"), "/IUniswapLikeRouter.rs"));
 include!(concat!(env!("OUT_DIR"), "/ERC1271SignatureValidator.rs"));
+include!(concat!(env!("OUT_DIR"), "/MakerGemJoin.rs"));
+include!(concat!(env!("OUT_DIR"), "/MakerPsm.rs"));
+include!(concat!(env!("OUT_DIR"), "/MakerVat.rs"));
+include!(concat!(env!("OUT_DIR"), "/MStablePool.rs"));
+include!(concat!(env!("OUT_DIR"), "/PancakeSwapFactory.rs"));
+include!(concat!(env!("OUT_DIR"), "/PancakeSwapRouter.rs"));
 include!(concat!(env!("OUT_DIR"), "/SushiSwapFactory.rs"));
 include!(concat!(env!("OUT_DIR"), "/SushiSwapRouter.rs"));
 include!(concat!(env!("OUT_DIR"), "/SwaprFactory.rs"));
 include!(concat!(env!("OUT_DIR"), "/SwaprRouter.rs"));
 include!(concat!(env!("OUT_DIR"), "/ISwaprPair.rs"));
+include!(concat!(env!("OUT_DIR"), "/TraderJoeFactory.rs"));
+include!(concat!(env!("OUT_DIR"), "/TraderJoeRouter.rs"));
+include!(concat!(env!("OUT_DIR"), "/IFraxswapPair.rs"));
 include!(concat!(env!("OUT_DIR"), "/UniswapV2Factory.rs"));
 include!(concat!(env!("OUT_DIR"), "/UniswapV2Router02.rs"));
 include!(concat!(env!("OUT_DIR"), "/WETH9.rs"));
+include!(concat!(env!("OUT_DIR"), "/WstETH.rs"));
+include!(concat!(env!("OUT_DIR"), "/SaddlePoolRegistry.rs"));
+include!(concat!(env!("OUT_DIR"), "/SaddleSwap.rs"));
+include!(concat!(env!("OUT_DIR"), "/SolidlyPool.rs"));
+include!(concat!(env!("OUT_DIR"), "/SolidlyPoolFactory.rs"));
+include!(concat!(env!("OUT_DIR"), "/UniswapV4PoolManager.rs"));
+include!(concat!(env!("OUT_DIR"), "/UniswapV4StateView.rs"));
+include!(concat!(env!("OUT_DIR"), "/AmbientCrocQuery.rs"));
+include!(concat!(env!("OUT_DIR"), "/IntegralTwapFactory.rs"));
+include!(concat!(env!("OUT_DIR"), "/IntegralTwapRelayer.rs"));
+include!(concat!(env!("OUT_DIR"), "/IZiSwapFactory.rs"));
+include!(concat!(env!("OUT_DIR"), "/IZiSwapPool.rs"));
+include!(concat!(env!("OUT_DIR"), "/SynthetixAddressResolver.rs"));
+include!(concat!(env!("OUT_DIR"), "/SynthetixExchanger.rs"));
+include!(concat!(env!("OUT_DIR"), "/SynthetixExchangeRates.rs"));
+include!(concat!(env!("OUT_DIR"), "/SynthetixSynth.rs"));
+include!(concat!(env!("OUT_DIR"), "/SynthetixSystemSettings.rs"));
+include!(concat!(env!("OUT_DIR"), "/WombatAsset.rs"));
+include!(concat!(env!("OUT_DIR"), "/WombatPool.rs"));
 include!(concat!(env!("OUT_DIR"), "/IUniswapV3Factory.rs"));
 include!(concat!(env!("OUT_DIR"), "/IZeroEx.rs"));
 include!(concat!(env!("OUT_DIR"), "/CowProtocolToken.rs"));
@@ -137,9 +189,45 @@ mod tests {
             assert_has_deployment_address!(UniswapV2Router02 for *network);
         }
         #[allow(clippy::single_element_loop)]
+        for network in &[1] {
+            assert_has_deployment_address!(BalancerV2AaveLinearPoolFactory for *network);
+            assert_has_deployment_address!(BalancerV2ComposableStablePoolFactory for *network);
+            assert_has_deployment_address!(BalancerV2GyroEclpPoolFactory for *network);
+            assert_has_deployment_address!(BalancerV2ManagedPoolFactory for *network);
+            assert_has_deployment_address!(CurveAddressProvider for *network);
+            assert_has_deployment_address!(MakerPsm for *network);
+            assert_has_deployment_address!(WstETH for *network);
+            assert_has_deployment_address!(UniswapV4PoolManager for *network);
+            assert_has_deployment_address!(UniswapV4StateView for *network);
+            assert_has_deployment_address!(AmbientCrocQuery for *network);
+        }
+        #[allow(clippy::single_element_loop)]
         for network in &[100] {
             assert_has_deployment_address!(HoneyswapFactory for *network);
             assert_has_deployment_address!(HoneyswapRouter for *network);
+            assert_has_deployment_address!(SwaprFactory for *network);
+            assert_has_deployment_address!(SwaprRouter for *network);
+        }
+        for network in &[10, 8453] {
+            assert_has_deployment_address!(SolidlyPoolFactory for *network);
+        }
+        #[allow(clippy::single_element_loop)]
+        for network in &[56] {
+            assert_has_deployment_address!(PancakeSwapFactory for *network);
+            assert_has_deployment_address!(PancakeSwapRouter for *network);
+            assert_has_deployment_address!(WETH9 for *network);
+        }
+        #[allow(clippy::single_element_loop)]
+        for network in &[43114] {
+            assert_has_deployment_address!(TraderJoeFactory for *network);
+            assert_has_deployment_address!(TraderJoeRouter for *network);
+            assert_has_deployment_address!(WETH9 for *network);
+        }
+        #[allow(clippy::single_element_loop)]
+        for network in &[11155111] {
+            assert_has_deployment_address!(UniswapV2Factory for *network);
+            assert_has_deployment_address!(UniswapV2Router02 for *network);
+            assert_has_deployment_address!(WETH9 for *network);
         }
     }
 
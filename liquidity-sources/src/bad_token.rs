@@ -0,0 +1,271 @@
+//! Detecting tokens that can't be sold, like honeypots.
+//!
+//! Some tokens can be bought from pools, but holders can't transfer them
+//! afterwards, for example because the token only allows transfers from
+//! pools. Routes through such tokens strand the trader, so suspicious tokens
+//! are probed by simulating a transfer out of a pool holding the token and
+//! back. Tokens that fail the probe are quarantined by denying them in the
+//! shared token filter, see [`crate::token_filter`].
+
+use crate::{
+    maintenance::Maintaining,
+    token_filter::TokenFilter,
+    transfer_fee::{self, balance_of, decode_balance, simulate, transfer, RECIPIENT},
+    Web3,
+};
+use anyhow::{Context as _, Result};
+use ethcontract::{BlockId, BlockNumber, H160};
+use futures::future;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+/// The result of probing a token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TokenQuality {
+    Good,
+    Bad { reason: String },
+}
+
+#[mockall::automock]
+#[async_trait::async_trait]
+pub trait BadTokenDetecting: Send + Sync {
+    /// Probes the token at the block, using a holder of the token, usually a
+    /// pool, as the counterparty.
+    ///
+    /// Returns an error if the token couldn't be probed, for example because
+    /// the holder has no balance.
+    async fn detect(&self, token: H160, holder: H160, block: BlockId) -> Result<TokenQuality>;
+}
+
+/// Detects bad tokens by simulating transfers with `trace_callMany`.
+pub struct TraceCallDetector {
+    pub web3: Web3,
+}
+
+#[async_trait::async_trait]
+impl BadTokenDetecting for TraceCallDetector {
+    async fn detect(&self, token: H160, holder: H160, block: BlockId) -> Result<TokenQuality> {
+        let amount = transfer_fee::probe_amount(&self.web3, token, holder, block).await?;
+        let transfer_in = transfer(token, holder, RECIPIENT, amount);
+
+        // The amount received is only known after simulating the transfer, as
+        // the token may charge a fee.
+        let traces = simulate(
+            &self.web3,
+            vec![
+                balance_of(token, RECIPIENT),
+                transfer_in.clone(),
+                balance_of(token, RECIPIENT),
+            ],
+            block,
+        )
+        .await
+        .context("failed to simulate transfer")?;
+        let received = decode_balance(&traces[2])?.saturating_sub(decode_balance(&traces[0])?);
+        if received.is_zero() {
+            return Ok(TokenQuality::Bad {
+                reason: "holder can't transfer the token".to_string(),
+            });
+        }
+
+        let traces = simulate(
+            &self.web3,
+            vec![
+                balance_of(token, RECIPIENT),
+                transfer_in,
+                transfer(token, RECIPIENT, holder, received),
+                balance_of(token, RECIPIENT),
+            ],
+            block,
+        )
+        .await
+        .context("failed to simulate transfer back")?;
+        if decode_balance(&traces[3])? != decode_balance(&traces[0])? {
+            return Ok(TokenQuality::Bad {
+                reason: "recipient can't transfer the received token".to_string(),
+            });
+        }
+        Ok(TokenQuality::Good)
+    }
+}
+
+/// Probes suspicious tokens on maintenance runs, quarantining bad tokens by
+/// denying them in a token filter.
+pub struct BadTokenQuarantine {
+    detector: Arc<dyn BadTokenDetecting>,
+    filter: Arc<TokenFilter>,
+    /// Suspicious tokens with the holders to probe them with.
+    suspects: Mutex<HashMap<H160, H160>>,
+    /// Tokens that were probed and found to be good.
+    cleared: Mutex<HashSet<H160>>,
+}
+
+impl BadTokenQuarantine {
+    pub fn new(detector: Arc<dyn BadTokenDetecting>, filter: Arc<TokenFilter>) -> Self {
+        Self {
+            detector,
+            filter,
+            suspects: Default::default(),
+            cleared: Default::default(),
+        }
+    }
+
+    pub fn is_quarantined(&self, token: &H160) -> bool {
+        self.filter.is_denied(token)
+    }
+
+    /// Reports a token as suspicious, for example because a quote selling it
+    /// failed to verify, so that it is probed with the holder on the next
+    /// maintenance run. Tokens that were already probed are not probed again.
+    pub fn suspect(&self, token: H160, holder: H160) {
+        if self.is_quarantined(&token) || self.cleared.lock().unwrap().contains(&token) {
+            return;
+        }
+        self.suspects.lock().unwrap().insert(token, holder);
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for BadTokenQuarantine {
+    async fn run_maintenance(&self) -> Result<()> {
+        let suspects = std::mem::take(&mut *self.suspects.lock().unwrap())
+            .into_iter()
+            .collect::<Vec<_>>();
+        let block = BlockId::Number(BlockNumber::Latest);
+        let results = future::join_all(
+            suspects
+                .iter()
+                .map(|(token, holder)| self.detector.detect(*token, *holder, block)),
+        )
+        .await;
+
+        for ((token, holder), result) in suspects.into_iter().zip(results) {
+            match result {
+                Ok(TokenQuality::Good) => {
+                    self.cleared.lock().unwrap().insert(token);
+                }
+                Ok(TokenQuality::Bad { reason }) => {
+                    tracing::warn!(?token, %reason, "quarantining bad token");
+                    self.filter.deny(token);
+                }
+                Err(err) => {
+                    // Probe the token again on the next run.
+                    tracing::warn!(?token, ?err, "failed to probe suspicious token");
+                    self.suspects.lock().unwrap().entry(token).or_insert(holder);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::test_transport::TestTransport;
+    use anyhow::anyhow;
+    use ethcontract::dyns::DynTransport;
+    use maplit::hashset;
+    use serde_json::{json, Value};
+    use web3::{
+        ethabi::{self, Token},
+        types::Bytes,
+    };
+
+    fn output(amount: u64) -> Value {
+        json!(Bytes(ethabi::encode(&[Token::Uint(amount.into())])))
+    }
+
+    fn balance(amount: u64) -> Value {
+        json!({ "output": output(amount) })
+    }
+
+    #[tokio::test]
+    async fn detects_tokens_that_recipients_cant_transfer() {
+        let mut transport = TestTransport::new();
+        let detector = TraceCallDetector {
+            web3: Web3::new(DynTransport::new(transport.clone())),
+        };
+        let (token, pool) = (H160([0x01; 20]), H160([0x02; 20]));
+        let block = BlockId::Number(BlockNumber::Latest);
+
+        // Transferring 100 of the pool's 1000 tokens delivers 95, and the
+        // recipient can send them back.
+        transport.add_response(output(1000));
+        transport.add_response(json!([balance(0), { "output": "0x" }, balance(95)]));
+        transport.add_response(json!([
+            balance(0),
+            { "output": "0x" },
+            { "output": "0x" },
+            balance(0),
+        ]));
+        assert_eq!(
+            detector.detect(token, pool, block).await.unwrap(),
+            TokenQuality::Good
+        );
+
+        transport.assert_request_params("eth_call");
+        transport.assert_request_params("trace_callMany");
+        let params = transport.assert_request_params("trace_callMany");
+        let calls = params[0].as_array().unwrap();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[2][0]["from"], json!(RECIPIENT));
+        transport.assert_no_more_requests();
+
+        // The transfer back fails.
+        transport.add_response(output(1000));
+        transport.add_response(json!([balance(0), { "output": "0x" }, balance(100)]));
+        transport.add_response(json!([
+            balance(0),
+            { "output": "0x" },
+            { "output": "0x" },
+            balance(100),
+        ]));
+        assert!(matches!(
+            detector.detect(token, pool, block).await.unwrap(),
+            TokenQuality::Bad { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn quarantines_bad_suspects() {
+        let (good, bad, unprobed) = (H160([0x01; 20]), H160([0x02; 20]), H160([0x03; 20]));
+        let pool = H160([0xff; 20]);
+
+        let mut detector = MockBadTokenDetecting::new();
+        detector
+            .expect_detect()
+            .times(4)
+            .returning(move |token, _, _| {
+                if token == good {
+                    Ok(TokenQuality::Good)
+                } else if token == bad {
+                    Ok(TokenQuality::Bad {
+                        reason: "honeypot".to_string(),
+                    })
+                } else {
+                    Err(anyhow!("node error"))
+                }
+            });
+        let filter = Arc::new(TokenFilter::default());
+        let quarantine = BadTokenQuarantine::new(Arc::new(detector), filter.clone());
+
+        for token in [good, bad, unprobed] {
+            quarantine.suspect(token, pool);
+        }
+        quarantine.run_maintenance().await.unwrap();
+        assert!(!quarantine.is_quarantined(&good));
+        assert!(quarantine.is_quarantined(&bad));
+        assert!(!quarantine.is_quarantined(&unprobed));
+        assert!(filter.is_denied(&bad) && !filter.is_allowed(&bad));
+
+        // Only the token that failed to be probed is probed again.
+        quarantine.suspect(good, pool);
+        quarantine.suspect(bad, pool);
+        quarantine.run_maintenance().await.unwrap();
+
+        assert_eq!(filter.denied(), hashset! { bad });
+    }
+}
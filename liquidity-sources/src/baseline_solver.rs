@@ -1,11 +1,22 @@
 //! Module containing basic path-finding logic to get quotes/routes for the best onchain liquidity.
 
-use crate::token_pair::TokenPair;
+use crate::{
+    conversions::{f64_to_u256, Rounding},
+    gas_price::GasPriceEstimate,
+    token_pair::TokenPair,
+};
 use ethcontract::{H160, U256};
 use std::collections::{HashMap, HashSet};
 
 /// The maximum number of hops to use when trading with AMMs along a path.
 const DEFAULT_MAX_HOPS: usize = 2;
+/// The maximum number of path candidates for a pair of tokens.
+const DEFAULT_MAX_PATH_CANDIDATES: usize = 100;
+/// The fraction of the trade size used to approximate the spot price of liquidity.
+const SPOT_PRICE_PROBE_DIVISOR: u64 = 10_000;
+/// The price impact below which liquidity is considered infinitely deep, such as limit orders with
+/// a fixed price.
+const MIN_PRICE_IMPACT: f64 = 1e-9;
 
 type PathCandidate = Vec<H160>;
 
@@ -21,8 +32,30 @@ pub trait BaselineSolvable {
     // Given the input token, the amount and token we want output, return the required amount of input token that needs to be provided.
     fn get_amount_in(&self, in_token: H160, out: (U256, H160)) -> Option<U256>;
 
+    // Given the desired output token, several amounts and the input token, returns the expected
+    // amount of output token for each of the amounts. Liquidity that quotes large amounts by
+    // walking through its state, like concentrated liquidity, can quote all amounts at once.
+    fn get_amounts_out(
+        &self,
+        out_token: H160,
+        (in_amounts, in_token): (&[U256], H160),
+    ) -> Vec<Option<U256>> {
+        in_amounts
+            .iter()
+            .map(|in_amount| self.get_amount_out(out_token, (*in_amount, in_token)))
+            .collect()
+    }
+
     // Returns the approximate amount of gas that using this piece of liquidity would incur
     fn gas_cost(&self) -> usize;
+
+    // Returns the largest amount of the input token the liquidity can take for the output token,
+    // if it is limited by the liquidity itself, like concentrated liquidity beyond its last
+    // initialized tick. Other liquidity is only limited by its price impact, see
+    // `max_sell_amount`.
+    fn max_amount_in(&self, _in_token: H160, _out_token: H160) -> Option<U256> {
+        None
+    }
 }
 
 pub struct Estimate<'a, V, L> {
@@ -30,6 +63,9 @@ pub struct Estimate<'a, V, L> {
     pub value: V,
     // The liquidity path used to derive at that estimate
     pub path: Vec<&'a L>,
+    // The amounts traded along the path, starting with the sell amount and ending with the buy
+    // amount, so that buy estimates don't need to be replayed hop by hop
+    pub amounts: Vec<V>,
 }
 
 impl<'a, V, L: BaselineSolvable> Estimate<'a, V, L> {
@@ -53,9 +89,9 @@ pub fn estimate_buy_amount<'a, L: BaselineSolvable>(
     path.iter()
         .skip(1)
         .fold(
-            Some((sell_amount, *sell_token, Vec::new())),
+            Some((sell_amount, *sell_token, Vec::new(), vec![sell_amount])),
             |previous, current| {
-                let (amount, previous, mut path) = previous?;
+                let (amount, previous, mut path, mut amounts) = previous?;
                 let (best_liquidity, amount) = liquidity
                     .get(&TokenPair::new(*current, previous)?)?
                     .iter()
@@ -67,12 +103,15 @@ pub fn estimate_buy_amount<'a, L: BaselineSolvable>(
                     })
                     .max_by(|(_, amount_a), (_, amount_b)| amount_a.cmp(amount_b))?;
                 path.push(best_liquidity);
-                Some((amount?, *current, path))
+                let amount = amount?;
+                amounts.push(amount);
+                Some((amount, *current, path, amounts))
             },
         )
-        .map(|(amount, _, liquidity)| Estimate {
+        .map(|(amount, _, liquidity, amounts)| Estimate {
             value: amount,
             path: liquidity,
+            amounts,
         })
 }
 
@@ -89,9 +128,9 @@ pub fn estimate_sell_amount<'a, L: BaselineSolvable>(
         .rev()
         .skip(1)
         .fold(
-            Some((buy_amount, *buy_token, Vec::new())),
+            Some((buy_amount, *buy_token, Vec::new(), vec![buy_amount])),
             |previous, current| {
-                let (amount, previous, mut path) = previous?;
+                let (amount, previous, mut path, mut amounts) = previous?;
                 let (best_liquidity, amount) = liquidity
                     .get(&TokenPair::new(*current, previous)?)?
                     .iter()
@@ -107,14 +146,325 @@ pub fn estimate_sell_amount<'a, L: BaselineSolvable>(
                             .cmp(&amount_b.unwrap_or_else(U256::max_value))
                     })?;
                 path.push(best_liquidity);
-                Some((amount?, *current, path))
+                let amount = amount?;
+                amounts.push(amount);
+                Some((amount, *current, path, amounts))
             },
         )
-        .map(|(amount, _, liquidity)| Estimate {
+        .map(|(amount, _, liquidity, amounts)| Estimate {
             value: amount,
             // Since we reversed the path originally, we need to re-reverse it here.
             path: liquidity.into_iter().rev().collect(),
+            amounts: amounts.into_iter().rev().collect(),
+        })
+}
+
+/// Returns the price impact of selling the amount of the sell token for the buy token with the
+/// liquidity, as the relative decrease of the execution price compared to the spot price. For
+/// example, 0.01 means that the trade receives 1% less than it would at the spot price.
+///
+/// Works with any kind of liquidity by approximating the spot price with a quote for a tiny
+/// fraction of the trade size, so fees are part of both prices and don't count as impact. Returns
+/// None if the liquidity can't quote the trade or the probing trade.
+pub fn price_impact<L: BaselineSolvable>(
+    liquidity: &L,
+    sell_token: H160,
+    buy_token: H160,
+    sell_amount: U256,
+) -> Option<f64> {
+    if sell_amount.is_zero() {
+        return None;
+    }
+    let spot_price = marginal_price(liquidity, sell_token, buy_token, probe_amount(sell_amount))?;
+    let buy_amount = liquidity.get_amount_out(buy_token, (sell_amount, sell_token))?;
+
+    let execution_price = buy_amount.to_f64_lossy() / sell_amount.to_f64_lossy();
+    // Rounding of the probing trade can make the spot price look slightly worse than the
+    // execution price of larger trades.
+    Some((1. - execution_price / spot_price).max(0.))
+}
+
+/// A point on the effective price curve of a token pair.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PricePoint {
+    pub sell_amount: U256,
+    /// The most buy tokens that any single piece of liquidity offers for the sell amount.
+    pub buy_amount: U256,
+}
+
+impl PricePoint {
+    /// The effective price of the trade in buy token atoms per sell token atom.
+    pub fn price(&self) -> f64 {
+        self.buy_amount.to_f64_lossy() / self.sell_amount.to_f64_lossy()
+    }
+}
+
+/// Quotes selling each of the sell amounts with the best piece of the liquidity, returning the
+/// effective price curve of the pair in the order of the amounts. Points are `None` for amounts
+/// that none of the liquidity can trade.
+///
+/// Every piece of liquidity is only asked once for all amounts, see
+/// [`BaselineSolvable::get_amounts_out`].
+pub fn price_curve<'a, L: BaselineSolvable + 'a>(
+    liquidity: impl IntoIterator<Item = &'a L>,
+    sell_token: H160,
+    buy_token: H160,
+    sell_amounts: &[U256],
+) -> Vec<Option<PricePoint>> {
+    let mut best = vec![None::<U256>; sell_amounts.len()];
+    for liquidity in liquidity {
+        let buy_amounts = liquidity.get_amounts_out(buy_token, (sell_amounts, sell_token));
+        for (best, buy_amount) in best.iter_mut().zip(buy_amounts) {
+            *best = (*best).max(buy_amount);
+        }
+    }
+    sell_amounts
+        .iter()
+        .zip(best)
+        .map(|(sell_amount, buy_amount)| {
+            Some(PricePoint {
+                sell_amount: *sell_amount,
+                buy_amount: buy_amount?,
+            })
+        })
+        .collect()
+}
+
+/// Returns `count` amounts from `min` to `max` that grow by a constant factor, for example to
+/// quote the price curve of a pair over several orders of magnitude.
+pub fn logarithmic_amounts(min: U256, max: U256, count: usize) -> Vec<U256> {
+    match count {
+        0 => return Vec::new(),
+        1 => return vec![min],
+        _ => (),
+    }
+    let (min_f64, max_f64) = (min.to_f64_lossy(), max.to_f64_lossy());
+    let factor = (max_f64 / min_f64).powf(1. / (count - 1) as f64);
+    (0..count)
+        .map(|i| match i {
+            0 => min,
+            i if i == count - 1 => max,
+            i => U256::from_f64_lossy(min_f64 * factor.powi(i as i32)),
+        })
+        .collect()
+}
+
+/// Returns the largest amount of the sell token the liquidity can take for the buy token, so that
+/// routing can skip liquidity that can't fill an order instead of quoting it at an absurd price.
+///
+/// This is the limit of the liquidity itself if it has one, see
+/// [`BaselineSolvable::max_amount_in`]. Otherwise it is the largest amount with a price impact of
+/// at most `max_price_impact`, found by searching amounts around the order's sell amount, which
+/// assumes that the price impact grows with the amount. Returns None if the liquidity can't trade
+/// the pair.
+pub fn max_sell_amount<L: BaselineSolvable>(
+    liquidity: &L,
+    sell_token: H160,
+    buy_token: H160,
+    sell_amount: U256,
+    max_price_impact: f64,
+) -> Option<U256> {
+    if let Some(max_amount) = liquidity.max_amount_in(sell_token, buy_token) {
+        return Some(max_amount);
+    }
+    if sell_amount.is_zero() {
+        return None;
+    }
+    // Unlike `price_impact`, every amount is compared to the same spot price, so that the impact
+    // of small amounts isn't distorted by rounding of even smaller probes.
+    let probe_amount = probe_amount(sell_amount);
+    let spot_price = marginal_price(liquidity, sell_token, buy_token, probe_amount)?;
+    let within_bound = |amount: U256| {
+        liquidity
+            .get_amount_out(buy_token, (amount, sell_token))
+            .map_or(false, |buy_amount| {
+                let execution_price = buy_amount.to_f64_lossy() / amount.to_f64_lossy();
+                1. - execution_price / spot_price <= max_price_impact
+            })
+    };
+
+    // Find amounts below and above the bound by doubling, and bisect between them.
+    let (mut low, mut high) = (probe_amount, sell_amount);
+    while within_bound(high) {
+        low = high;
+        high = match high.checked_mul(2.into()) {
+            Some(high) => high,
+            None => return Some(U256::max_value()),
+        };
+    }
+    while high - low > U256::one() {
+        let middle = low + (high - low) / 2;
+        if within_bound(middle) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    Some(low)
+}
+
+/// The marginal prices of a token pair over several pieces of liquidity, in buy token atoms per
+/// sell token atom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpotPrice {
+    /// The best marginal price for selling the sell token with any of the liquidity, after fees.
+    pub best: f64,
+    /// The average of the mid prices between selling and buying the sell token with each piece of
+    /// liquidity, weighted by its depth. This is `None` if no liquidity can trade the pair in both
+    /// directions.
+    pub mid: Option<f64>,
+}
+
+/// Returns the marginal prices of selling the sell token for the buy token over all of the
+/// liquidity, or `None` if none of it can trade the pair.
+///
+/// Like [`price_impact`], marginal prices are approximated with quotes for a tiny fraction of the
+/// specified sell amount. The depth used for weighting mid prices is the inverse of the price
+/// impact of selling the full amount, which is proportional to the reserves of constant product
+/// pools.
+pub fn spot_price<'a, L: BaselineSolvable + 'a>(
+    liquidity: impl IntoIterator<Item = &'a L>,
+    sell_token: H160,
+    buy_token: H160,
+    sell_amount: U256,
+) -> Option<SpotPrice> {
+    if sell_amount.is_zero() {
+        return None;
+    }
+    let probe_amount = probe_amount(sell_amount);
+    let mut best = None::<f64>;
+    let (mut weighted_mids, mut total_depth) = (0., 0.);
+    for liquidity in liquidity {
+        let bid = match marginal_price(liquidity, sell_token, buy_token, probe_amount) {
+            Some(bid) => bid,
+            None => continue,
+        };
+        best = Some(best.map_or(bid, |best| best.max(bid)));
+
+        // Probe the reverse direction with about the same value.
+        let reverse = f64_to_u256(probe_amount.to_f64_lossy() * bid, Rounding::Nearest)
+            .ok()
+            .and_then(|amount| marginal_price(liquidity, buy_token, sell_token, amount));
+        let impact = price_impact(liquidity, sell_token, buy_token, sell_amount);
+        if let (Some(reverse), Some(impact)) = (reverse, impact) {
+            let depth = 1. / impact.max(MIN_PRICE_IMPACT);
+            weighted_mids += depth * (bid / reverse).sqrt();
+            total_depth += depth;
+        }
+    }
+    Some(SpotPrice {
+        best: best?,
+        mid: (total_depth > 0.).then(|| weighted_mids / total_depth),
+    })
+}
+
+fn probe_amount(sell_amount: U256) -> U256 {
+    (sell_amount / SPOT_PRICE_PROBE_DIVISOR).max(U256::one())
+}
+
+/// Approximates the marginal price of the liquidity with a quote for the probing amount.
+pub(crate) fn marginal_price<L: BaselineSolvable>(
+    liquidity: &L,
+    sell_token: H160,
+    buy_token: H160,
+    probe_amount: U256,
+) -> Option<f64> {
+    if probe_amount.is_zero() {
+        return None;
+    }
+    let probe_out = liquidity.get_amount_out(buy_token, (probe_amount, sell_token))?;
+    if probe_out.is_zero() {
+        return None;
+    }
+    Some(probe_out.to_f64_lossy() / probe_amount.to_f64_lossy())
+}
+
+/// Prices the gas for executing a route in the token its estimate is denominated in, so that a
+/// route with slightly better amounts but more hops doesn't beat a cheaper route.
+#[derive(Clone, Copy, Debug)]
+pub struct GasCostModel {
+    /// The price per gas in native token atoms, usually the effective gas price of the latest
+    /// [`GasPriceEstimate`].
+    pub gas_price: U256,
+    /// The value of one native token (1e18 atoms) in atoms of the token the estimates are
+    /// denominated in, the buy token for buy amounts and the sell token for sell amounts.
+    pub native_token_price: U256,
+}
+
+impl GasCostModel {
+    /// Prices gas at the effective gas price of the estimate paying the priority fee of the
+    /// configured percentile.
+    pub fn new(
+        estimate: &GasPriceEstimate,
+        percentile: f64,
+        native_token_price: U256,
+    ) -> Option<Self> {
+        Some(Self {
+            gas_price: estimate.effective_gas_price(percentile)?,
+            native_token_price,
         })
+    }
+
+    /// Returns the cost of the gas in atoms of the estimated token, or `None` on overflow.
+    pub fn cost(&self, gas: usize) -> Option<U256> {
+        let cost = U256::from(gas)
+            .checked_mul(self.gas_price)?
+            .checked_mul(self.native_token_price)?;
+        Some(cost / U256::exp10(18))
+    }
+
+    /// Returns the buy amount of the estimate after paying for the gas of its route.
+    pub fn net_buy_amount<L: BaselineSolvable>(&self, estimate: &Estimate<U256, L>) -> U256 {
+        match self.cost(estimate.gas_cost()) {
+            Some(cost) => estimate.value.saturating_sub(cost),
+            None => U256::zero(),
+        }
+    }
+
+    /// Returns the sell amount of the estimate including the payment for the gas of its route.
+    pub fn gross_sell_amount<L: BaselineSolvable>(&self, estimate: &Estimate<U256, L>) -> U256 {
+        self.cost(estimate.gas_cost())
+            .map(|cost| estimate.value.saturating_add(cost))
+            .unwrap_or_else(U256::max_value)
+    }
+
+    /// Returns the buy amount estimate with the highest buy amount net of gas costs.
+    pub fn best_buy_estimate<'a, L: BaselineSolvable>(
+        &self,
+        estimates: impl IntoIterator<Item = Estimate<'a, U256, L>>,
+    ) -> Option<Estimate<'a, U256, L>> {
+        estimates
+            .into_iter()
+            .max_by_key(|estimate| self.net_buy_amount(estimate))
+    }
+
+    /// Returns the sell amount estimate with the lowest sell amount including gas costs.
+    pub fn best_sell_estimate<'a, L: BaselineSolvable>(
+        &self,
+        estimates: impl IntoIterator<Item = Estimate<'a, U256, L>>,
+    ) -> Option<Estimate<'a, U256, L>> {
+        estimates
+            .into_iter()
+            .min_by_key(|estimate| self.gross_sell_amount(estimate))
+    }
+}
+
+/// Configuration of the generation of path candidates.
+#[derive(Clone, Copy, Debug)]
+pub struct PathConfig {
+    /// The maximum number of intermediate base tokens on a path, usually 1 to 3.
+    pub max_hops: usize,
+    /// The maximum number of path candidates, where paths with fewer hops are kept first.
+    pub max_candidates: usize,
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        Self {
+            max_hops: DEFAULT_MAX_HOPS,
+            max_candidates: DEFAULT_MAX_PATH_CANDIDATES,
+        }
+    }
 }
 
 pub struct BaseTokens {
@@ -170,6 +520,34 @@ impl BaseTokens {
     pub fn path_candidates(&self, sell_token: H160, buy_token: H160) -> HashSet<PathCandidate> {
         path_candidates(sell_token, buy_token, &self.tokens, DEFAULT_MAX_HOPS)
     }
+
+    /// Returns possible paths from sell_token to buy_token as the sequences of token pairs of the
+    /// pools to trade with along the path.
+    ///
+    /// Paths with fewer hops come first, followed by the others in a deterministic order, and only
+    /// the configured maximum number of paths is returned.
+    pub fn pair_path_candidates(
+        &self,
+        sell_token: H160,
+        buy_token: H160,
+        config: &PathConfig,
+    ) -> Vec<Vec<TokenPair>> {
+        let mut paths = path_candidates(sell_token, buy_token, &self.tokens, config.max_hops)
+            .into_iter()
+            .collect::<Vec<_>>();
+        paths.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+        let mut seen = HashSet::new();
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                path.windows(2)
+                    .map(|tokens| TokenPair::new(tokens[0], tokens[1]))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .filter(|pairs| seen.insert(pairs.clone()))
+            .take(config.max_candidates)
+            .collect()
+    }
 }
 
 fn path_candidates(
@@ -307,6 +685,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pair_path_candidates_prefer_fewer_hops() {
+        let base_tokens = [H160::from_low_u64_be(1), H160::from_low_u64_be(2)];
+        let base = BaseTokens::new(H160::from_low_u64_be(0), &base_tokens);
+        let sell_token = H160::from_low_u64_be(4);
+        let buy_token = H160::from_low_u64_be(5);
+        let pair = |a: H160, b: H160| TokenPair::new(a, b).unwrap();
+
+        let config = PathConfig {
+            max_hops: 1,
+            max_candidates: 3,
+        };
+        assert_eq!(
+            base.pair_path_candidates(sell_token, buy_token, &config),
+            vec![
+                vec![pair(sell_token, buy_token)],
+                vec![
+                    pair(sell_token, H160::from_low_u64_be(0)),
+                    pair(H160::from_low_u64_be(0), buy_token)
+                ],
+                vec![
+                    pair(sell_token, base_tokens[0]),
+                    pair(base_tokens[0], buy_token)
+                ],
+            ]
+        );
+
+        let config = PathConfig {
+            max_hops: 3,
+            max_candidates: usize::MAX,
+        };
+        let paths = base.pair_path_candidates(sell_token, buy_token, &config);
+        assert_eq!(paths.len(), 16);
+        assert!(paths
+            .windows(2)
+            .all(|paths| paths[0].len() <= paths[1].len()));
+        assert_eq!(paths.last().unwrap().len(), 4);
+    }
+
     #[test]
     fn test_estimate_amount_returns_none_if_it_contains_pair_without_pool() {
         let sell_token = H160::from_low_u64_be(1);
@@ -342,17 +759,16 @@ mod tests {
             pools[1].tokens => vec![pools[1]],
         };
 
-        assert_eq!(
-            estimate_buy_amount(10.into(), &path, &pools).unwrap().value,
-            2.into()
-        );
+        let estimate = estimate_buy_amount(10.into(), &path, &pools).unwrap();
+        assert_eq!(estimate.value, 2.into());
+        assert_eq!(estimate.amounts, [10.into(), 9.into(), 2.into()]);
 
-        assert_eq!(
-            estimate_sell_amount(10.into(), &path, &pools)
-                .unwrap()
-                .value,
-            105.into()
-        );
+        // Buy estimates are computed backwards from the buy amount, one hop at a time.
+        let estimate = estimate_sell_amount(10.into(), &path, &pools).unwrap();
+        assert_eq!(estimate.value, 105.into());
+        assert_eq!(estimate.amounts, [105.into(), 51.into(), 10.into()]);
+        let pool = |i: usize| &pools[&TokenPair::new(path[i], path[i + 1]).unwrap()][0];
+        assert_eq!(estimate.path, [pool(0), pool(1)]);
     }
 
     #[test]
@@ -479,4 +895,171 @@ mod tests {
         assert!(pairs.contains(&TokenPair::new(tokens[1], tokens[4]).unwrap()));
         assert!(pairs.contains(&TokenPair::new(tokens[3], tokens[4]).unwrap()));
     }
+
+    #[test]
+    fn ranks_routes_net_of_gas_costs() {
+        let pool = Pool::uniswap(
+            TokenPair::new(H160::from_low_u64_be(1), H160::from_low_u64_be(2)).unwrap(),
+            (1, 1),
+        );
+        let estimate = |value: u64, hops: usize| Estimate {
+            value: U256::from(value),
+            path: vec![&pool; hops],
+            amounts: Vec::new(),
+        };
+        // 100 gwei gas price and a native token worth 2000 USDC.
+        let gas = GasCostModel {
+            gas_price: U256::from(100_000_000_000u64),
+            native_token_price: U256::from(2_000_000_000u64),
+        };
+        assert_eq!(gas.cost(110_000), Some(U256::from(22_000_000)));
+        assert_eq!(
+            gas.net_buy_amount(&estimate(1_000_000_000, 1)),
+            978_000_000.into()
+        );
+        assert_eq!(
+            gas.gross_sell_amount(&estimate(1_000_000_000, 1)),
+            1_022_000_000.into()
+        );
+
+        let best = gas
+            .best_buy_estimate([estimate(1_000_000_000, 1), estimate(1_010_000_000, 3)])
+            .unwrap();
+        assert_eq!(best.path.len(), 1);
+        let best = gas
+            .best_sell_estimate([estimate(1_000_000_000, 1), estimate(990_000_000, 3)])
+            .unwrap();
+        assert_eq!(best.path.len(), 1);
+
+        // Without gas costs the route with the better amount wins.
+        let free = GasCostModel {
+            gas_price: U256::zero(),
+            ..gas
+        };
+        let best = free
+            .best_buy_estimate([estimate(1_000_000_000, 1), estimate(1_010_000_000, 3)])
+            .unwrap();
+        assert_eq!(best.path.len(), 3);
+
+        let overflowing = GasCostModel {
+            gas_price: U256::max_value(),
+            ..gas
+        };
+        assert_eq!(overflowing.net_buy_amount(&estimate(1, 1)), U256::zero());
+    }
+
+    #[test]
+    fn price_impact_of_constant_product_pool() {
+        let tokens = [H160::from_low_u64_be(1), H160::from_low_u64_be(2)];
+        let reserve = 10u128.pow(24);
+        let pool = Pool::uniswap(
+            TokenPair::new(tokens[0], tokens[1]).unwrap(),
+            (reserve, reserve),
+        );
+
+        // Selling 1% of the reserves moves the price by 997 / (100_000 + 997).
+        let impact = price_impact(&pool, tokens[0], tokens[1], U256::from(reserve / 100)).unwrap();
+        assert!((impact - 997. / 100_997.).abs() < 1e-5, "{}", impact);
+
+        let impact = price_impact(&pool, tokens[1], tokens[0], U256::from(reserve / 2)).unwrap();
+        assert!((impact - 0.3326).abs() < 1e-3, "{}", impact);
+
+        assert_eq!(
+            price_impact(&pool, tokens[0], tokens[1], U256::zero()),
+            None
+        );
+        // Trades too small to receive anything have no meaningful price.
+        assert_eq!(price_impact(&pool, tokens[0], tokens[1], U256::one()), None);
+    }
+
+    #[test]
+    fn spot_price_over_several_pools() {
+        let tokens = [H160::from_low_u64_be(1), H160::from_low_u64_be(2)];
+        let pair = TokenPair::new(tokens[0], tokens[1]).unwrap();
+        // A deep pool at a price of 2 and a ten times shallower pool at a price of 4.
+        let pools = [
+            Pool::uniswap(pair, (10u128.pow(24), 2 * 10u128.pow(24))),
+            Pool::uniswap(pair, (10u128.pow(23), 4 * 10u128.pow(23))),
+        ];
+        let amount = U256::exp10(21);
+
+        let price = spot_price(&pools, tokens[0], tokens[1], amount).unwrap();
+        assert!((price.best - 4. * 0.997).abs() < 1e-3, "{:?}", price);
+        // The mid prices are weighted about 10:1 by the reserves of the pools.
+        let mid = price.mid.unwrap();
+        assert!((mid - (10. * 2. + 4.) / 11.).abs() < 1e-2, "{:?}", price);
+
+        let price = spot_price(&pools, tokens[1], tokens[0], amount).unwrap();
+        assert!((price.best - 0.5 * 0.997).abs() < 1e-3, "{:?}", price);
+
+        let unknown = H160::from_low_u64_be(3);
+        assert_eq!(spot_price(&pools, tokens[0], unknown, amount), None);
+        assert_eq!(spot_price(&pools, tokens[0], tokens[1], U256::zero()), None);
+    }
+
+    #[test]
+    fn max_sell_amount_of_constant_product_pool() {
+        let tokens = [H160::from_low_u64_be(1), H160::from_low_u64_be(2)];
+        let reserve = 10u128.pow(24);
+        let pool = Pool::uniswap(
+            TokenPair::new(tokens[0], tokens[1]).unwrap(),
+            (reserve, reserve),
+        );
+
+        // A price impact of 1% is reached when selling 1/99 of the reserves after fees.
+        let expected = reserve as f64 / 99. / 0.997;
+        for sell_amount in [U256::exp10(18), U256::exp10(21), U256::exp10(30)] {
+            let max = max_sell_amount(&pool, tokens[0], tokens[1], sell_amount, 0.01).unwrap();
+            assert!(
+                (max.to_f64_lossy() / expected - 1.).abs() < 1e-4,
+                "{} {}",
+                sell_amount,
+                max
+            );
+        }
+
+        let unknown = H160::from_low_u64_be(3);
+        assert_eq!(
+            max_sell_amount(&pool, tokens[0], unknown, U256::exp10(18), 0.01),
+            None
+        );
+    }
+
+    #[test]
+    fn price_curve_over_several_pools() {
+        let tokens = [H160::from_low_u64_be(1), H160::from_low_u64_be(2)];
+        let pair = TokenPair::new(tokens[0], tokens[1]).unwrap();
+        // A shallow pool at a better price and a deep pool at a worse one.
+        let pools = [
+            Pool::uniswap(pair, (10u128.pow(20), 2 * 10u128.pow(20))),
+            Pool::uniswap(pair, (10u128.pow(24), 10u128.pow(24))),
+        ];
+        let amounts = logarithmic_amounts(U256::exp10(15), U256::exp10(21), 7);
+        assert_eq!(amounts[0], U256::exp10(15));
+        assert!((amounts[3].to_f64_lossy() / 1e18 - 1.).abs() < 1e-9);
+        assert_eq!(amounts[6], U256::exp10(21));
+
+        let curve = price_curve(&pools, tokens[0], tokens[1], &amounts);
+        for (amount, point) in amounts.iter().zip(&curve) {
+            let best = pools
+                .iter()
+                .filter_map(|pool| pool.get_amount_out(tokens[1], (*amount, tokens[0])))
+                .max()
+                .unwrap();
+            assert_eq!(point.unwrap().buy_amount, best);
+        }
+        // Small trades get the better price of the shallow pool, large ones the deep pool.
+        assert!((curve[0].unwrap().price() - 2. * 0.997).abs() < 1e-3);
+        assert!((curve[6].unwrap().price() - 0.997).abs() < 1e-2);
+        assert!(curve
+            .windows(2)
+            .all(|points| points[0].unwrap().price() >= points[1].unwrap().price()));
+
+        let unknown = H160::from_low_u64_be(3);
+        assert_eq!(
+            price_curve(&pools, tokens[0], unknown, &amounts),
+            vec![None; amounts.len()]
+        );
+        assert_eq!(logarithmic_amounts(1.into(), 2.into(), 0), Vec::new());
+    }
 }
@@ -0,0 +1,508 @@
+//! Configuration of the chains supported by the liquidity sources.
+//!
+//! Everything that differs between chains, such as the default baseline
+//! sources, subgraphs, factory addresses, wrapped native token and reorg depth,
+//! is described by a `ChainConfig` instead of being hardcoded per source. A
+//! default configuration is embedded in the crate, and additional chains can be
+//! added or existing ones replaced with a JSON or TOML override file, which is
+//! loaded once at startup for the whole process with [`ChainConfigs::load`].
+
+use crate::{
+    current_block::{BlockFinality, BlockStreamConfig, SafeBlockTag},
+    sources::{native::NATIVE_TOKEN, BaselineSource},
+    subgraph::SubgraphClient,
+    Web3,
+};
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use ethcontract::H160;
+use once_cell::sync::OnceCell;
+use reqwest::Client;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path, time::Duration};
+
+/// The key of the Algebra subgraphs.
+pub const ALGEBRA: &str = "algebra";
+/// The key of the Balancer V2 subgraphs.
+pub const BALANCER_V2: &str = "balancer_v2";
+/// The key of the Integral relayers.
+pub const INTEGRAL: &str = "integral";
+/// The key of the iZiSwap factories.
+pub const IZISWAP: &str = "iziswap";
+/// The key of the Saddle pool registries.
+pub const SADDLE: &str = "saddle";
+/// The key of the Uniswap V3 subgraphs and factories.
+pub const UNISWAP_V3: &str = "uniswap_v3";
+
+lazy_static::lazy_static! {
+    static ref EMBEDDED: ChainConfigs =
+        ChainConfigs::from_toml(include_str!("chain_config.toml"))
+            .expect("invalid embedded chain configuration");
+}
+
+/// The configuration loaded with [`ChainConfigs::load`].
+static LOADED: OnceCell<ChainConfigs> = OnceCell::new();
+
+/// The configuration of a single chain.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    pub name: String,
+    /// The wrapped native token of the chain, or `None` if the native token is
+    /// already an ERC20 token.
+    #[serde(default)]
+    pub wrapped_native_token: Option<H160>,
+    /// The number of blocks behind the chain head that are assumed to be safe
+    /// from reorgs.
+    pub reorg_block_count: u64,
+    /// The block tag that the nodes of the chain resolve to blocks that are
+    /// safe from reorgs, used instead of the reorg block count where nodes
+    /// support it. This is mostly useful for rollups.
+    #[serde(default)]
+    pub finality_tag: Option<SafeBlockTag>,
+    /// The average block time of the chain in milliseconds.
+    pub block_time_ms: u64,
+    /// How often to poll the node for the current block in milliseconds, or
+    /// `None` to poll once per block time.
+    #[serde(default)]
+    pub poll_interval_ms: Option<u64>,
+    /// The baseline sources that are enabled by default, or `None` if the
+    /// sources always need to be configured explicitly.
+    #[serde(default)]
+    pub baseline_sources: Option<Vec<BaselineSource>>,
+    /// The subgraphs of the chain by liquidity source.
+    #[serde(default)]
+    pub subgraphs: BTreeMap<String, Subgraph>,
+    /// The factory contract addresses of the chain by liquidity source, or the
+    /// contracts that serve the same purpose for sources without a factory,
+    /// like the Saddle pool registry or the Integral relayer.
+    #[serde(default)]
+    pub factories: BTreeMap<String, H160>,
+}
+
+/// Retrieves the ID of the chain that the node is connected to.
+pub async fn chain_id(web3: &Web3) -> Result<u64> {
+    let chain_id = web3
+        .eth()
+        .chain_id()
+        .await
+        .context("failed to get chain ID")?;
+    u64::try_from(chain_id).map_err(|_| anyhow!("chain ID {} out of range", chain_id))
+}
+
+/// Verifies that the node is connected to the expected chain.
+pub async fn ensure_chain_id(web3: &Web3, expected: u64) -> Result<()> {
+    let actual = chain_id(web3).await?;
+    ensure!(
+        actual == expected,
+        "node is connected to chain {} but chain {} is configured",
+        actual,
+        expected,
+    );
+    Ok(())
+}
+
+impl ChainConfig {
+    /// Returns the configuration for the specified chain ID from the current
+    /// configuration, see [`ChainConfigs::current`].
+    pub fn for_chain(chain_id: u64) -> Result<&'static Self> {
+        ChainConfigs::current().get(chain_id)
+    }
+
+    /// Returns the configuration for the chain that the node is connected to
+    /// from the current configuration.
+    pub async fn for_web3(web3: &Web3) -> Result<&'static Self> {
+        Self::for_chain(chain_id(web3).await?)
+    }
+
+    /// Returns how the blocks of the chain become final.
+    pub fn block_finality(&self) -> BlockFinality {
+        match self.finality_tag {
+            Some(tag) => BlockFinality::Tagged {
+                tag,
+                fallback_depth: self.reorg_block_count,
+            },
+            None => BlockFinality::Depth(self.reorg_block_count),
+        }
+    }
+
+    /// Returns the average block time of the chain.
+    pub fn block_time(&self) -> Duration {
+        Duration::from_millis(self.block_time_ms)
+    }
+
+    /// Returns the configuration of the current block stream of the chain.
+    pub fn block_stream_config(&self) -> BlockStreamConfig {
+        BlockStreamConfig {
+            poll_interval: Duration::from_millis(
+                self.poll_interval_ms.unwrap_or(self.block_time_ms),
+            ),
+            reorg_threshold: self.reorg_block_count,
+        }
+    }
+
+    /// Returns the token that liquidity is fetched for in place of the token,
+    /// which is the wrapped native token for the native token sentinel
+    /// address, see [`NATIVE_TOKEN`], and the token itself otherwise.
+    ///
+    /// The sentinel is kept on chains without a wrapped native token.
+    pub fn normalize_token(&self, token: H160) -> H160 {
+        match self.wrapped_native_token {
+            Some(wrapped) if token == NATIVE_TOKEN => wrapped,
+            _ => token,
+        }
+    }
+
+    /// Returns the baseline sources that are enabled by default.
+    pub fn default_baseline_sources(&self) -> Result<Vec<BaselineSource>> {
+        match &self.baseline_sources {
+            Some(sources) => Ok(sources.clone()),
+            None => bail!("no default baseline sources for chain {}", self.chain_id),
+        }
+    }
+
+    /// Creates a client for the subgraph of the specified liquidity source.
+    pub fn subgraph_client(&self, source: &str, client: Client) -> Result<SubgraphClient> {
+        match self.subgraphs.get(source) {
+            Some(subgraph) => subgraph.client(client),
+            None => bail!("no {} subgraph for chain {}", source, self.chain_id),
+        }
+    }
+
+    /// Returns the factory contract address of the specified liquidity source.
+    pub fn factory(&self, source: &str) -> Result<H160> {
+        match self.factories.get(source) {
+            Some(factory) => Ok(*factory),
+            None => bail!("no {} factory for chain {}", source, self.chain_id),
+        }
+    }
+}
+
+/// The location of a subgraph.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Subgraph {
+    /// A subgraph on the hosted service.
+    Hosted { org: String, name: String },
+    /// A subgraph at the specified URL, for example a subgraph on the
+    /// decentralized network gateway.
+    Url { url: String },
+}
+
+impl Subgraph {
+    /// Creates a client for the subgraph.
+    pub fn client(&self, client: Client) -> Result<SubgraphClient> {
+        match self {
+            Self::Hosted { org, name } => SubgraphClient::new(org, name, client),
+            Self::Url { url } => SubgraphClient::with_url(url.as_str(), client),
+        }
+    }
+}
+
+/// The configurations of all supported chains.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChainConfigs(BTreeMap<u64, ChainConfig>);
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    chains: Vec<ChainConfig>,
+}
+
+impl ChainConfigs {
+    /// Returns the default configuration embedded in the crate.
+    pub fn embedded() -> &'static Self {
+        &EMBEDDED
+    }
+
+    /// Returns the configuration used by all liquidity sources, which is the
+    /// loaded configuration if [`Self::load`] was called and the embedded
+    /// default configuration otherwise.
+    pub fn current() -> &'static Self {
+        LOADED.get().unwrap_or(&*EMBEDDED)
+    }
+
+    /// Loads the embedded default configuration, with the chains from the
+    /// optional override file replacing the default configuration of the same
+    /// chain, and makes it the current configuration of the process.
+    ///
+    /// This should be called once at startup before creating any liquidity
+    /// sources. Fails if a configuration was already loaded.
+    pub fn load(override_path: Option<&Path>) -> Result<&'static Self> {
+        let mut configs = Self::embedded().clone();
+        if let Some(path) = override_path {
+            configs.extend(Self::from_file(path)?);
+        }
+        LOADED
+            .set(configs)
+            .map_err(|_| anyhow!("chain configuration already loaded"))?;
+        Ok(Self::current())
+    }
+
+    /// Reads the configuration from a JSON or TOML file, depending on its
+    /// extension.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read chain config {}", path.display()))?;
+        let configs = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&contents),
+            Some("toml") => Self::from_toml(&contents),
+            _ => bail!("unknown chain config format {}", path.display()),
+        };
+        configs.with_context(|| format!("invalid chain config {}", path.display()))
+    }
+
+    /// Parses the configuration from a JSON document.
+    pub fn from_json(contents: &str) -> Result<Self> {
+        Self::from_chains(serde_json::from_str::<ConfigFile>(contents)?.chains)
+    }
+
+    /// Parses the configuration from a TOML document.
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        Self::from_chains(toml::from_str::<ConfigFile>(contents)?.chains)
+    }
+
+    fn from_chains(chains: Vec<ChainConfig>) -> Result<Self> {
+        let mut configs = BTreeMap::new();
+        for chain in chains {
+            let chain_id = chain.chain_id;
+            ensure!(
+                configs.insert(chain_id, chain).is_none(),
+                "duplicate configuration for chain {}",
+                chain_id,
+            );
+        }
+        Ok(Self(configs))
+    }
+
+    /// Adds the chains of another configuration, replacing the configuration
+    /// of chains that are already configured.
+    pub fn extend(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    /// Returns the configuration of the specified chain.
+    pub fn get(&self, chain_id: u64) -> Result<&ChainConfig> {
+        match self.0.get(&chain_id) {
+            Some(config) => Ok(config),
+            None => bail!("unsupported chain {}", chain_id),
+        }
+    }
+
+    /// Returns the configurations of all chains, ordered by chain ID.
+    pub fn chains(&self) -> impl Iterator<Item = &ChainConfig> + '_ {
+        self.0.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::MAX_REORG_BLOCK_COUNT;
+    use ethcontract_mock::Mock;
+
+    #[test]
+    fn embedded_config_is_valid() {
+        let configs = ChainConfigs::embedded();
+        for config in configs.chains() {
+            assert_eq!(configs.get(config.chain_id).unwrap(), config);
+            assert!(config.block_time_ms > 0);
+        }
+
+        let mainnet = ChainConfig::for_chain(1).unwrap();
+        assert_eq!(mainnet.reorg_block_count, MAX_REORG_BLOCK_COUNT);
+        assert_eq!(mainnet.block_time(), Duration::from_secs(12));
+        assert_eq!(
+            mainnet.block_stream_config().poll_interval,
+            Duration::from_secs(12)
+        );
+        assert_eq!(
+            ChainConfig::for_chain(42161)
+                .unwrap()
+                .block_stream_config()
+                .poll_interval,
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            mainnet.wrapped_native_token,
+            Some(addr!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")),
+        );
+        assert_eq!(
+            mainnet.subgraphs[BALANCER_V2],
+            Subgraph::Hosted {
+                org: "balancer-labs".to_owned(),
+                name: "balancer-v2".to_owned(),
+            },
+        );
+        assert_eq!(
+            ChainConfig::for_chain(42161).unwrap().block_finality(),
+            BlockFinality::Tagged {
+                tag: SafeBlockTag::Safe,
+                fallback_depth: 1200,
+            },
+        );
+        assert_eq!(mainnet.block_finality(), BlockFinality::default());
+        assert!(ChainConfig::for_chain(42220)
+            .unwrap()
+            .wrapped_native_token
+            .is_none());
+        assert_eq!(
+            mainnet.normalize_token(NATIVE_TOKEN),
+            addr!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+        );
+        assert_eq!(
+            ChainConfig::for_chain(42220)
+                .unwrap()
+                .normalize_token(NATIVE_TOKEN),
+            NATIVE_TOKEN,
+        );
+        assert!(ChainConfig::for_chain(1337).is_err());
+    }
+
+    #[tokio::test]
+    async fn detects_chain_of_node() {
+        let web3 = Mock::new(100).web3();
+        assert_eq!(chain_id(&web3).await.unwrap(), 100);
+        assert_eq!(ChainConfig::for_web3(&web3).await.unwrap().chain_id, 100);
+        assert!(ensure_chain_id(&web3, 100).await.is_ok());
+
+        let err = ensure_chain_id(&web3, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "node is connected to chain 100 but chain 1 is configured",
+        );
+    }
+
+    #[test]
+    fn overrides_replace_and_add_chains() {
+        let overrides = ChainConfigs::from_json(
+            r#"{
+                "chains": [
+                    {
+                        "chain_id": 8453,
+                        "name": "Base",
+                        "wrapped_native_token": "0x4200000000000000000000000000000000000006",
+                        "reorg_block_count": 10,
+                        "block_time_ms": 2000,
+                        "subgraphs": {
+                            "uniswap_v3": { "url": "https://example.com/subgraphs/uniswap-v3" }
+                        },
+                        "factories": {
+                            "uniswap_v3": "0x33128a8fC17869897dcE68Ed026d694621f6FDfD"
+                        }
+                    },
+                    {
+                        "chain_id": 1337,
+                        "name": "Devnet",
+                        "reorg_block_count": 0,
+                        "block_time_ms": 1000,
+                        "baseline_sources": ["UniswapV2"]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let mut configs = ChainConfigs::embedded().clone();
+        configs.extend(overrides);
+
+        let base = configs.get(8453).unwrap();
+        assert_eq!(base.reorg_block_count, 10);
+        assert!(base.subgraph_client(UNISWAP_V3, Client::new()).is_ok());
+        assert!(ChainConfig::for_chain(8453)
+            .unwrap()
+            .subgraph_client(UNISWAP_V3, Client::new())
+            .is_err());
+
+        let devnet = configs.get(1337).unwrap();
+        assert_eq!(
+            devnet.default_baseline_sources().unwrap(),
+            vec![BaselineSource::UniswapV2],
+        );
+        assert_eq!(configs.get(1).unwrap(), ChainConfig::for_chain(1).unwrap());
+    }
+
+    #[test]
+    fn parses_toml_overrides() {
+        let configs = ChainConfigs::from_toml(
+            r#"
+            [[chains]]
+            chain_id = 1337
+            name = "Devnet"
+            reorg_block_count = 0
+            block_time_ms = 1000
+
+            [chains.subgraphs]
+            balancer_v2 = { org = "devnet", name = "balancer" }
+            "#,
+        )
+        .unwrap();
+
+        let devnet = configs.get(1337).unwrap();
+        assert!(devnet.default_baseline_sources().is_err());
+        assert!(devnet.subgraph_client(BALANCER_V2, Client::new()).is_ok());
+        assert!(devnet.subgraph_client(UNISWAP_V3, Client::new()).is_err());
+    }
+
+    #[test]
+    fn loaded_overrides_are_used_by_sources() {
+        use crate::sources::{self, uniswap_v3::graph_api::Deployment};
+
+        // Loading the configuration affects the whole test process, so only
+        // a chain that is not used by any other test is overridden.
+        let path = std::env::temp_dir().join(format!(
+            "liquidity-sources-chain-config-{}.toml",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            r#"
+            [[chains]]
+            chain_id = 31337
+            name = "Devnet"
+            reorg_block_count = 5
+            block_time_ms = 1000
+            baseline_sources = ["Curve"]
+
+            [chains.factories]
+            uniswap_v3 = "0x1111111111111111111111111111111111111111"
+            "#,
+        )
+        .unwrap();
+        let loaded = ChainConfigs::load(Some(&path));
+        fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+
+        assert_eq!(
+            sources::defaults_for_chain(31337).unwrap(),
+            vec![BaselineSource::Curve],
+        );
+        let deployment = Deployment::for_chain(31337).unwrap();
+        assert_eq!(deployment.factory, H160([0x11; 20]));
+        assert_eq!(deployment.reorg_block_count, 5);
+        assert_eq!(
+            ChainConfig::for_chain(1).unwrap(),
+            &ChainConfigs::embedded().0[&1]
+        );
+        assert!(ChainConfigs::load(None).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_chains() {
+        assert!(ChainConfigs::from_toml(
+            r#"
+            [[chains]]
+            chain_id = 1
+            name = "Ethereum"
+            reorg_block_count = 25
+            block_time_ms = 12000
+
+            [[chains]]
+            chain_id = 1
+            name = "Ethereum"
+            reorg_block_count = 25
+            block_time_ms = 12000
+            "#,
+        )
+        .is_err());
+    }
+}
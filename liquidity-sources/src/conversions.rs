@@ -1,5 +1,9 @@
-use anyhow::{ensure, Result};
-use num::{bigint::Sign, rational::Ratio, BigInt, BigRational, ToPrimitive as _, Zero as _};
+pub use crate::fixed_point::Rounding;
+use crate::sources::uniswap_v3::swap_math;
+use anyhow::{anyhow, ensure, Result};
+use num::{
+    bigint::Sign, rational::Ratio, BigInt, BigRational, Signed as _, ToPrimitive as _, Zero as _,
+};
 use primitive_types::U256;
 
 pub fn big_rational_to_float(ratio: &BigRational) -> Option<f64> {
@@ -14,6 +18,52 @@ pub fn big_rational_to_u256(ratio: &BigRational) -> Result<U256> {
     big_int_to_u256(&(ratio.numer() / ratio.denom()))
 }
 
+pub fn big_rational_to_big_int(ratio: &BigRational, rounding: Rounding) -> Result<BigInt> {
+    ensure!(
+        !ratio.denom().is_zero(),
+        "Division by 0 in BigRational to BigInt conversion"
+    );
+    let rounded = match rounding {
+        Rounding::Down => ratio.floor(),
+        Rounding::Up => ratio.ceil(),
+        Rounding::Nearest => ratio.round(),
+    };
+    Ok(rounded.to_integer())
+}
+
+pub fn big_rational_to_u256_rounded(ratio: &BigRational, rounding: Rounding) -> Result<U256> {
+    big_int_to_u256(&big_rational_to_big_int(ratio, rounding)?)
+}
+
+pub fn big_int_to_u128(input: &BigInt) -> Result<u128> {
+    ensure!(!input.is_negative(), "Negative BigInt to u128 conversion");
+    input
+        .to_u128()
+        .ok_or_else(|| anyhow!("BigInt too big for u128 conversion"))
+}
+
+pub fn u256_to_u128(input: &U256) -> Result<u128> {
+    ensure!(input.bits() <= 128, "U256 too big for u128 conversion");
+    Ok(input.low_u128())
+}
+
+/// Converts to the nearest float, with ties to even.
+pub fn u256_to_f64(input: &U256) -> f64 {
+    u256_to_big_int(input)
+        .to_f64()
+        .expect("U256 is always in the range of f64")
+}
+
+/// Converts a finite float to the exactly equal rational number.
+pub fn f64_to_big_rational(input: f64) -> Result<BigRational> {
+    BigRational::from_float(input)
+        .ok_or_else(|| anyhow!("Non-finite f64 {} to BigRational conversion", input))
+}
+
+pub fn f64_to_u256(input: f64, rounding: Rounding) -> Result<U256> {
+    big_rational_to_u256_rounded(&f64_to_big_rational(input)?, rounding)
+}
+
 pub fn u256_to_big_int(input: &U256) -> BigInt {
     let mut bytes = [0; 32];
     input.to_big_endian(&mut bytes);
@@ -35,6 +85,53 @@ pub fn big_int_to_u256(input: &BigInt) -> Result<U256> {
     Ok(U256::from_big_endian(&bytes))
 }
 
+// Uniswap V3 prices:
+//
+// Pools store the square root of the price of token 0 in token 1 as a 96-bit
+// fixed point number of token atoms. These convert it to and from the price
+// in whole tokens, which is what is usually displayed or configured.
+
+/// Returns the price of token 0 in token 1, in whole tokens, for the square
+/// root price of a pool.
+pub fn sqrt_price_x96_to_price(sqrt_price_x96: U256, decimals0: u8, decimals1: u8) -> BigRational {
+    let sqrt_price = sqrt_price_x96.to_big_int();
+    let price_in_atoms = BigRational::new(&sqrt_price * &sqrt_price, BigInt::from(1) << 192);
+    price_in_atoms * decimals_scale(decimals0, decimals1)
+}
+
+/// Returns the square root price of a pool for the price of token 0 in token
+/// 1, in whole tokens, rounded down.
+pub fn price_to_sqrt_price_x96(price: &BigRational, decimals0: u8, decimals1: u8) -> Result<U256> {
+    ensure!(!price.is_negative(), "invalid price {}", price);
+    let price_in_atoms = price / decimals_scale(decimals0, decimals1);
+    // The floor of the square root of the floor is the floor of the square
+    // root, so rounding the scaled price down first doesn't lose precision.
+    let scaled = (price_in_atoms.numer() << 192) / price_in_atoms.denom();
+    big_int_to_u256(&scaled.sqrt())
+}
+
+/// Returns the price of token 0 in token 1, in whole tokens, at the tick.
+pub fn tick_to_price(tick: i32, decimals0: u8, decimals1: u8) -> Result<BigRational> {
+    let sqrt_price =
+        swap_math::sqrt_ratio_at_tick(tick).ok_or_else(|| anyhow!("tick {} out of range", tick))?;
+    Ok(sqrt_price_x96_to_price(sqrt_price, decimals0, decimals1))
+}
+
+/// Returns the largest tick whose price is at most the price of token 0 in
+/// token 1, in whole tokens.
+pub fn price_to_tick(price: &BigRational, decimals0: u8, decimals1: u8) -> Result<i32> {
+    let sqrt_price = price_to_sqrt_price_x96(price, decimals0, decimals1)?;
+    swap_math::tick_at_sqrt_ratio(sqrt_price)
+        .ok_or_else(|| anyhow!("price {} out of the tick range", price))
+}
+
+/// Returns the factor converting prices of token 0 in token 1 from atoms to
+/// whole tokens.
+fn decimals_scale(decimals0: u8, decimals1: u8) -> BigRational {
+    let power = |decimals: u8| BigInt::from(10).pow(decimals.into());
+    BigRational::new(power(decimals0), power(decimals1))
+}
+
 // Convenience:
 
 pub trait RatioExt<T> {
@@ -90,4 +187,145 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn rounds_big_rationals() {
+        let convert = |numer: i64, denom: i64, rounding| {
+            big_rational_to_big_int(&BigRational::new(numer.into(), denom.into()), rounding)
+                .unwrap()
+        };
+        assert_eq!(convert(7, 2, Rounding::Down), BigInt::from(3));
+        assert_eq!(convert(7, 2, Rounding::Up), BigInt::from(4));
+        assert_eq!(convert(7, 2, Rounding::Nearest), BigInt::from(4));
+        assert_eq!(convert(10, 3, Rounding::Nearest), BigInt::from(3));
+        assert_eq!(convert(-7, 2, Rounding::Down), BigInt::from(-4));
+        assert_eq!(convert(-7, 2, Rounding::Up), BigInt::from(-3));
+        assert_eq!(convert(-7, 2, Rounding::Nearest), BigInt::from(-4));
+        assert_eq!(convert(6, 2, Rounding::Up), BigInt::from(3));
+
+        let max = u256_to_big_rational(&U256::MAX);
+        assert_eq!(
+            big_rational_to_u256_rounded(&max, Rounding::Up).unwrap(),
+            U256::MAX
+        );
+        let above_max = max + BigRational::new(1.into(), 2.into());
+        assert_eq!(
+            big_rational_to_u256_rounded(&above_max, Rounding::Down).unwrap(),
+            U256::MAX
+        );
+        assert!(big_rational_to_u256_rounded(&above_max, Rounding::Up).is_err());
+        assert!(big_rational_to_u256_rounded(
+            &BigRational::new((-1).into(), 2.into()),
+            Rounding::Down
+        )
+        .is_err());
+        assert_eq!(
+            big_rational_to_u256_rounded(&BigRational::new((-1).into(), 2.into()), Rounding::Up)
+                .unwrap(),
+            U256::zero()
+        );
+    }
+
+    #[test]
+    fn checked_u128_conversions() {
+        assert_eq!(u256_to_u128(&U256::from(u128::MAX)).unwrap(), u128::MAX);
+        assert!(u256_to_u128(&(U256::from(u128::MAX) + 1)).is_err());
+        assert_eq!(
+            big_int_to_u128(&BigInt::from(u128::MAX)).unwrap(),
+            u128::MAX
+        );
+        assert!(big_int_to_u128(&(BigInt::from(u128::MAX) + 1)).is_err());
+        assert!(big_int_to_u128(&BigInt::from(-1)).is_err());
+    }
+
+    #[test]
+    fn float_conversions() {
+        assert_eq!(u256_to_f64(&U256::from(1337)), 1337.);
+        assert_eq!(u256_to_f64(&U256::MAX), 2f64.powi(256));
+        // 2^53 + 1 is not representable and rounds to the nearest even float.
+        assert_eq!(u256_to_f64(&(U256::from(1u64 << 53) + 1)), 2f64.powi(53));
+        assert_eq!(
+            u256_to_f64(&(U256::from(1u64 << 53) + 3)),
+            2f64.powi(53) + 4.
+        );
+
+        assert_eq!(
+            f64_to_big_rational(0.375).unwrap(),
+            BigRational::new(3.into(), 8.into())
+        );
+        assert!(f64_to_big_rational(f64::NAN).is_err());
+        assert!(f64_to_big_rational(f64::INFINITY).is_err());
+
+        assert_eq!(f64_to_u256(2.5, Rounding::Down).unwrap(), U256::from(2));
+        assert_eq!(f64_to_u256(2.5, Rounding::Up).unwrap(), U256::from(3));
+        assert_eq!(f64_to_u256(2.5, Rounding::Nearest).unwrap(), U256::from(3));
+        assert_eq!(f64_to_u256(1e18, Rounding::Down).unwrap(), U256::exp10(18));
+        assert_eq!(
+            f64_to_u256(2f64.powi(255), Rounding::Down).unwrap(),
+            U256::one() << 255
+        );
+        assert!(f64_to_u256(2f64.powi(256), Rounding::Down).is_err());
+        assert!(f64_to_u256(-1., Rounding::Nearest).is_err());
+        assert!(f64_to_u256(f64::NAN, Rounding::Down).is_err());
+    }
+
+    fn ratio(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    #[test]
+    fn sqrt_prices_of_tokens_with_equal_decimals() {
+        let q96 = U256::one() << 96;
+        assert_eq!(sqrt_price_x96_to_price(q96, 18, 18), ratio(1, 1));
+        assert_eq!(sqrt_price_x96_to_price(q96 * 3, 18, 18), ratio(9, 1));
+        assert_eq!(sqrt_price_x96_to_price(q96 / 2, 18, 18), ratio(1, 4));
+        assert_eq!(price_to_sqrt_price_x96(&ratio(1, 1), 18, 18).unwrap(), q96);
+        assert_eq!(
+            price_to_sqrt_price_x96(&ratio(9, 1), 18, 18).unwrap(),
+            q96 * 3
+        );
+        assert_eq!(
+            price_to_sqrt_price_x96(&ratio(1, 4), 18, 18).unwrap(),
+            q96 / 2
+        );
+        assert!(price_to_sqrt_price_x96(&ratio(-1, 1), 18, 18).is_err());
+    }
+
+    #[test]
+    fn sqrt_prices_of_tokens_with_different_decimals() {
+        // USDC (6 decimals) and WETH (18 decimals) with ETH at 2000 USDC.
+        let sqrt_price = price_to_sqrt_price_x96(&ratio(1, 2000), 6, 18).unwrap();
+        assert_eq!(
+            sqrt_price,
+            U256::from_dec_str("1771595571142957102961017161607260").unwrap()
+        );
+        let price = sqrt_price_x96_to_price(sqrt_price, 6, 18);
+        assert!((big_rational_to_float(&price).unwrap() * 2000. - 1.).abs() < 1e-12);
+        // Rounding the square root price down never overestimates the price.
+        assert!(price <= ratio(1, 2000));
+
+        // The same pool with the tokens the other way around.
+        let sqrt_price = price_to_sqrt_price_x96(&ratio(2000, 1), 18, 6).unwrap();
+        let price = sqrt_price_x96_to_price(sqrt_price, 18, 6);
+        assert!((big_rational_to_float(&price).unwrap() / 2000. - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tick_prices() {
+        assert_eq!(tick_to_price(0, 18, 18).unwrap(), ratio(1, 1));
+        let price = big_rational_to_float(&tick_to_price(1, 18, 18).unwrap()).unwrap();
+        assert!((price - 1.0001).abs() < 1e-12);
+        assert!(tick_to_price(swap_math::MAX_TICK + 1, 18, 18).is_err());
+
+        for tick in [-200_000, -1, 0, 1, 200_000] {
+            let price = tick_to_price(tick, 6, 18).unwrap();
+            assert_eq!(price_to_tick(&price, 6, 18).unwrap(), tick);
+        }
+        // Prices between ticks round down to the lower tick.
+        assert_eq!(price_to_tick(&ratio(100_005, 100_000), 18, 18).unwrap(), 0);
+        assert_eq!(price_to_tick(&ratio(99_995, 100_000), 18, 18).unwrap(), -1);
+        // ETH at 2000 USDC is around tick 200311 in the USDC/WETH pool.
+        assert_eq!(price_to_tick(&ratio(1, 2000), 6, 18).unwrap(), 200_311);
+        assert!(price_to_tick(&ratio(0, 1), 18, 18).is_err());
+    }
 }
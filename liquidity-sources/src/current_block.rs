@@ -1,7 +1,9 @@
-use crate::Web3;
+use crate::{event_handling::MAX_REORG_BLOCK_COUNT, metrics::get_metric_storage_registry, Web3};
 use anyhow::{anyhow, Context as _, Result};
-use primitive_types::H256;
-use std::time::Duration;
+use primitive_types::{H256, U256};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
 use tokio::sync::watch;
 use tokio_stream::wrappers::WatchStream;
 use web3::{
@@ -9,52 +11,134 @@ use web3::{
     Transport,
 };
 
+pub mod multiplexer;
+pub mod watchdog;
+#[cfg(feature = "ws")]
+pub mod ws;
+
 pub type Block = web3::types::Block<H256>;
 
+/// Configuration of a current block stream.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockStreamConfig {
+    /// How often the node is polled for the current block.
+    pub poll_interval: Duration,
+    /// Blocks that are at most this many blocks behind the last yielded block are not yielded.
+    ///
+    /// Such blocks are usually returned by a node that lags behind, for example one of several
+    /// nodes behind a load balancer, and yielding them would spuriously trigger consumers.
+    pub reorg_threshold: u64,
+}
+
+impl Default for BlockStreamConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            reorg_threshold: MAX_REORG_BLOCK_COUNT,
+        }
+    }
+}
+
 /// Creates a cloneable stream that yields the current block whenever it changes.
 ///
 /// The stream is not guaranteed to yield *every* block individually without gaps but it does yield
 /// the newest block whenever it changes. In practice this means that if the node changes the
 /// current block in quick succession we might only observe the last block, skipping some blocks in
-/// between.
+/// between. Blocks with the same number as the last yielded block, or that are behind it by no more
+/// than the configured reorg threshold, are not yielded.
 ///
 /// The stream is cloneable so that we only have to poll the node once while being able to share the
 /// result with several consumers. Calling this function again would create a new poller so it is
 /// preferable to clone an existing stream instead.
 pub async fn current_block_stream(
     web3: Web3,
-    poll_interval: Duration,
-) -> Result<watch::Receiver<Block>> {
-    let first_block = web3.current_block().await?;
-    let first_hash = first_block.hash.ok_or_else(|| anyhow!("missing hash"))?;
+    config: BlockStreamConfig,
+) -> Result<CurrentBlockStream> {
+    let first_block = BlockInfo::try_from(web3.current_block().await?)?;
+
+    let (sender, receiver) = watch::channel(first_block);
+
+    let update_future = async move {
+        let mut filter = BlockFilter::new(first_block, &config);
+        poll_blocks(&web3, &config, &sender, &mut filter, None).await;
+    };
+
+    tokio::task::spawn(update_future);
+    Ok(receiver)
+}
+
+/// Creates a current block stream like [`current_block_stream`] that retrieves the current block
+/// from several nodes in order of preference.
+///
+/// Blocks are retrieved from one node at a time. If that node fails to return the current block or
+/// does not advance for the stall timeout, the stream switches to the next node so that a single
+/// flaky node does not freeze all consumers of the stream.
+pub async fn current_block_stream_with_failover(
+    nodes: Vec<Web3>,
+    config: BlockStreamConfig,
+    stall_timeout: Duration,
+) -> Result<CurrentBlockStream> {
+    anyhow::ensure!(
+        !nodes.is_empty(),
+        "no nodes for retrieving the current block"
+    );
+    let metrics = Metrics::instance(get_metric_storage_registry()).unwrap();
+
+    let mut active = 0;
+    let first_block = loop {
+        let block = nodes[active]
+            .current_block()
+            .await
+            .and_then(BlockInfo::try_from);
+        match block {
+            Ok(block) => break block,
+            Err(err) if active + 1 < nodes.len() => {
+                tracing::warn!(node = active, "failed to get current block: {:?}", err);
+                active += 1;
+                metrics
+                    .node_switches
+                    .with_label_values(&[&active.to_string()])
+                    .inc();
+            }
+            Err(err) => return Err(err),
+        }
+    };
 
     let (sender, receiver) = watch::channel(first_block);
 
     let update_future = async move {
-        let mut previous_hash = first_hash;
+        let mut filter = BlockFilter::new(first_block, &config);
+        let mut last_advance = Instant::now();
         loop {
-            tokio::time::sleep(poll_interval).await;
-            let block = match web3.current_block().await {
-                Ok(block) => block,
-                Err(err) => {
-                    tracing::warn!("failed to get current block: {:?}", err);
-                    continue;
+            tokio::time::sleep(config.poll_interval).await;
+            match nodes[active].current_block().await {
+                Ok(block) => {
+                    let last_block = filter.last.number;
+                    if !update_block(&sender, &mut filter, block) {
+                        break;
+                    }
+                    if filter.last.number != last_block {
+                        last_advance = Instant::now();
+                        continue;
+                    }
+                    if last_advance.elapsed() < stall_timeout {
+                        continue;
+                    }
+                    tracing::warn!(node = active, "node stopped advancing");
                 }
-            };
-            let hash = match block.hash {
-                Some(hash) => hash,
-                None => {
-                    tracing::warn!("missing hash");
-                    continue;
+                Err(err) => {
+                    tracing::warn!(node = active, "failed to get current block: {:?}", err);
                 }
-            };
-            if hash == previous_hash {
-                continue;
             }
-            if sender.send(block.clone()).is_err() {
-                break;
+            if nodes.len() > 1 {
+                active = (active + 1) % nodes.len();
+                tracing::warn!(node = active, "switching current block retrieval to node");
+                metrics
+                    .node_switches
+                    .with_label_values(&[&active.to_string()])
+                    .inc();
             }
-            previous_hash = hash;
+            last_advance = Instant::now();
         }
     };
 
@@ -62,9 +146,113 @@ pub async fn current_block_stream(
     Ok(receiver)
 }
 
-pub type CurrentBlockStream = watch::Receiver<Block>;
+#[derive(prometheus_metric_storage::MetricStorage, Clone, Debug)]
+#[metric(subsystem = "current_block")]
+struct Metrics {
+    /// Number of times current block retrieval switched to another node, by the index of the node
+    /// that was switched to.
+    #[metric(labels("node"))]
+    node_switches: prometheus::IntCounterVec,
+}
+
+/// Decides which blocks are yielded by a current block stream based on the last yielded block.
+struct BlockFilter {
+    last: BlockInfo,
+    reorg_threshold: u64,
+}
+
+impl BlockFilter {
+    fn new(first: BlockInfo, config: &BlockStreamConfig) -> Self {
+        Self {
+            last: first,
+            reorg_threshold: config.reorg_threshold,
+        }
+    }
+
+    /// Returns `true` and records the block as the last yielded block if it advances past the last
+    /// yielded block or jumps back further than the reorg threshold.
+    fn accept(&mut self, block: BlockInfo) -> bool {
+        let accepted = block.number > self.last.number
+            || self.last.number - block.number > self.reorg_threshold;
+        if accepted {
+            self.last = block;
+        }
+        accepted
+    }
+}
+
+/// Polls the node for the current block and sends it whenever it changes, until the deadline
+/// passes. Returns `false` if the receivers were dropped.
+async fn poll_blocks(
+    web3: &Web3,
+    config: &BlockStreamConfig,
+    sender: &watch::Sender<BlockInfo>,
+    filter: &mut BlockFilter,
+    deadline: Option<Instant>,
+) -> bool {
+    loop {
+        if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            return true;
+        }
+        tokio::time::sleep(config.poll_interval).await;
+        match web3.current_block().await {
+            Ok(block) => {
+                if !update_block(sender, filter, block) {
+                    return false;
+                }
+            }
+            Err(err) => tracing::warn!("failed to get current block: {:?}", err),
+        }
+    }
+}
+
+/// Sends the block if the filter accepts it. Returns `false` if the receivers were dropped.
+fn update_block(sender: &watch::Sender<BlockInfo>, filter: &mut BlockFilter, block: Block) -> bool {
+    let block = match BlockInfo::try_from(block) {
+        Ok(block) => block,
+        Err(err) => {
+            tracing::warn!("invalid current block: {:?}", err);
+            return true;
+        }
+    };
+    if !filter.accept(block) {
+        return true;
+    }
+    sender.send(block).is_ok()
+}
+
+/// The information about a block that is yielded by the current block stream, so that consumers
+/// do not need to request it from the node for every block.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BlockInfo {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    /// The timestamp of the block in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The base fee per gas of the block, or `None` for chains without EIP-1559.
+    pub base_fee: Option<U256>,
+}
+
+impl TryFrom<Block> for BlockInfo {
+    type Error = anyhow::Error;
 
-pub fn into_stream(receiver: watch::Receiver<Block>) -> WatchStream<Block> {
+    fn try_from(block: Block) -> Result<Self> {
+        Ok(Self {
+            number: block_number(&block)?,
+            hash: block.hash.ok_or_else(|| anyhow!("missing hash"))?,
+            parent_hash: block.parent_hash,
+            timestamp: block.timestamp.as_u64(),
+            base_fee: block.base_fee_per_gas,
+        })
+    }
+}
+
+/// A cloneable stream of the current block, shared between all sources of a
+/// chain so that they can invalidate cached state whenever a new block arrives.
+pub type CurrentBlockStream = watch::Receiver<BlockInfo>;
+
+pub fn into_stream(receiver: CurrentBlockStream) -> WatchStream<BlockInfo> {
     WatchStream::new(receiver)
 }
 
@@ -75,12 +263,92 @@ pub fn block_number(block: &Block) -> Result<u64> {
         .ok_or_else(|| anyhow!("no block number"))
 }
 
+/// A block tag that nodes resolve to a recent block that is safe from reorgs.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SafeBlockTag {
+    Safe,
+    Finalized,
+}
+
+impl SafeBlockTag {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Safe => "safe",
+            Self::Finalized => "finalized",
+        }
+    }
+}
+
+/// How the blocks of a chain become final.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BlockFinality {
+    /// Blocks are final once they are the specified number of blocks behind
+    /// the current block.
+    Depth(u64),
+    /// Blocks are final once they are at or before the block that the node
+    /// resolves the tag to, falling back to the specified depth for nodes that
+    /// do not support the tag.
+    ///
+    /// On rollups, a fixed depth is a poor measure since L2 blocks are
+    /// produced at a variable rate and reorgs are caused by the sequencer or by
+    /// batches being reorged on L1. Their nodes resolve the `safe` and
+    /// `finalized` tags to the latest L2 blocks whose batches were posted to
+    /// and finalized on L1 respectively.
+    Tagged {
+        tag: SafeBlockTag,
+        fallback_depth: u64,
+    },
+}
+
+impl Default for BlockFinality {
+    fn default() -> Self {
+        Self::Depth(MAX_REORG_BLOCK_COUNT)
+    }
+}
+
 /// Trait for abstracting the retrieval of the block information such as the
 /// latest block number.
+///
+/// Block numbers are always the numbers of the chain the node is connected
+/// to, which on rollups are L2 block numbers even though some responses also
+/// reference the L1 block a block was included in.
 #[async_trait::async_trait]
 pub trait BlockRetrieving {
     async fn current_block(&self) -> Result<Block>;
     async fn current_block_number(&self) -> Result<u64>;
+    /// Returns the number of the block that the node resolves the tag to, or
+    /// `None` if the node does not know such a block.
+    async fn tagged_block_number(&self, tag: SafeBlockTag) -> Result<Option<u64>>;
+    /// Returns the hash of the block with the specified number, or `None` if
+    /// the node does not know such a block.
+    async fn block_hash(&self, number: u64) -> Result<Option<H256>>;
+}
+
+/// Returns the number of the most recent block that is final with the
+/// specified finality.
+pub async fn safe_block_number(
+    retriever: &impl BlockRetrieving,
+    finality: BlockFinality,
+) -> Result<u64> {
+    let depth = match finality {
+        BlockFinality::Depth(depth) => depth,
+        BlockFinality::Tagged {
+            tag,
+            fallback_depth,
+        } => {
+            match retriever.tagged_block_number(tag).await {
+                Ok(Some(block)) => return Ok(block),
+                Ok(None) => tracing::warn!(?tag, "node does not know tagged block"),
+                Err(err) => tracing::warn!(?tag, ?err, "failed to get tagged block"),
+            }
+            fallback_depth
+        }
+    };
+    Ok(retriever
+        .current_block_number()
+        .await?
+        .saturating_sub(depth))
 }
 
 #[async_trait::async_trait]
@@ -105,6 +373,32 @@ where
             .context("failed to get current block number")?
             .as_u64())
     }
+
+    async fn tagged_block_number(&self, tag: SafeBlockTag) -> Result<Option<u64>> {
+        // The tags are not supported by the `BlockNumber` type of the version
+        // of `web3` that we use, so the request is made manually.
+        let block = self
+            .transport()
+            .execute(
+                "eth_getBlockByNumber",
+                vec![json!(tag.as_str()), json!(false)],
+            )
+            .await
+            .with_context(|| format!("failed to get {} block", tag.as_str()))?;
+        let block = serde_json::from_value::<Option<Block>>(block)?;
+        Ok(block
+            .and_then(|block| block.number)
+            .map(|number| number.as_u64()))
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<H256>> {
+        Ok(self
+            .eth()
+            .block(BlockId::Number(BlockNumber::Number(number.into())))
+            .await
+            .with_context(|| format!("failed to get block {}", number))?
+            .and_then(|block| block.hash))
+    }
 }
 
 #[cfg(test)]
@@ -113,7 +407,6 @@ mod tests {
     use crate::test::test_transport::TestTransport;
     use ethcontract::dyns::DynTransport;
     use futures::StreamExt;
-    use serde_json::json;
     use web3::types::U64;
 
     #[tokio::test]
@@ -131,13 +424,19 @@ mod tests {
             transport.add_response(block);
         }
         let web3 = Web3::new(dyn_transport);
-        let receiver = current_block_stream(web3, Duration::from_secs(1))
-            .await
-            .unwrap();
+        let receiver = current_block_stream(
+            web3,
+            BlockStreamConfig {
+                poll_interval: Duration::from_secs(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
         let mut stream = into_stream(receiver);
         for _ in 0..3 {
             let block = stream.next().await.unwrap();
-            println!("new block number {}", block.number.unwrap().as_u64());
+            println!("new block number {}", block.number);
         }
 
         transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
@@ -145,4 +444,100 @@ mod tests {
         transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
         transport.assert_no_more_requests();
     }
+
+    #[tokio::test]
+    async fn block_stream_fails_over_to_next_node() {
+        let mut primary = TestTransport::new();
+        let mut secondary = TestTransport::new();
+        let block = |number: u64| {
+            serde_json::to_value(Block {
+                hash: Some(H256::from_low_u64_be(number)),
+                number: Some(U64::from(number)),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        // The primary node fails after returning the first block.
+        primary.add_response(block(1));
+        secondary.add_response(block(2));
+
+        let mut receiver = current_block_stream_with_failover(
+            vec![
+                Web3::new(DynTransport::new(primary.clone())),
+                Web3::new(DynTransport::new(secondary.clone())),
+            ],
+            BlockStreamConfig {
+                poll_interval: Duration::from_millis(1),
+                ..Default::default()
+            },
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+        assert_eq!(receiver.borrow().number, 1);
+        receiver.changed().await.unwrap();
+        assert_eq!(receiver.borrow().number, 2);
+    }
+
+    #[test]
+    fn block_filter_suppresses_repeated_and_lagging_blocks() {
+        let block = |number: u64, hash: u64| BlockInfo {
+            number,
+            hash: H256::from_low_u64_be(hash),
+            ..Default::default()
+        };
+        let mut filter = BlockFilter::new(
+            block(100, 0),
+            &BlockStreamConfig {
+                reorg_threshold: 10,
+                ..Default::default()
+            },
+        );
+
+        assert!(!filter.accept(block(100, 0)));
+        assert!(!filter.accept(block(100, 1)));
+        assert!(filter.accept(block(101, 2)));
+        assert!(!filter.accept(block(95, 3)));
+        assert!(!filter.accept(block(91, 4)));
+        assert!(filter.accept(block(90, 5)));
+        assert_eq!(filter.last, block(90, 5));
+    }
+
+    #[tokio::test]
+    async fn safe_block_from_tag() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(DynTransport::new(transport.clone()));
+        let finality = BlockFinality::Tagged {
+            tag: SafeBlockTag::Safe,
+            fallback_depth: 10,
+        };
+
+        transport.add_response(
+            serde_json::to_value(Block {
+                number: Some(U64::from(90)),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        assert_eq!(safe_block_number(&web3, finality).await.unwrap(), 90);
+
+        // Falls back to the depth for nodes that do not know the tag.
+        transport.add_response(json!(null));
+        transport.add_response(json!("0x64"));
+        assert_eq!(safe_block_number(&web3, finality).await.unwrap(), 90);
+
+        transport.add_response(json!("0x64"));
+        assert_eq!(
+            safe_block_number(&web3, BlockFinality::Depth(25))
+                .await
+                .unwrap(),
+            75
+        );
+
+        transport.assert_request("eth_getBlockByNumber", &[json!("safe"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("safe"), json!(false)]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_no_more_requests();
+    }
 }
@@ -0,0 +1,152 @@
+//! Sharing a single current block stream between many consumers.
+
+use super::{BlockInfo, CurrentBlockStream};
+use crate::metrics::get_metric_storage_registry;
+use tokio::sync::watch;
+
+#[derive(prometheus_metric_storage::MetricStorage, Clone, Debug)]
+#[metric(subsystem = "block_stream")]
+struct Metrics {
+    /// Number of blocks that consumers missed because they were not waiting for new blocks when
+    /// the blocks arrived.
+    #[metric(labels("consumer"))]
+    lagged_blocks: prometheus::IntCounterVec,
+
+    /// Number of subscriptions to the current block.
+    #[metric(labels("consumer"))]
+    subscriptions: prometheus::IntGaugeVec,
+}
+
+/// Fans out one upstream current block stream, for example from polling the node or from a
+/// WebSocket subscription, to any number of consumers.
+///
+/// Every consumer always observes the newest block, so a consumer that is busy while several blocks
+/// arrive only observes the last of them. Subscriptions report how many blocks were missed this
+/// way, so that consumers can account for it and lagging consumers show up in the metrics.
+pub struct BlockStreamMultiplexer {
+    upstream: CurrentBlockStream,
+    blocks: watch::Receiver<(u64, BlockInfo)>,
+    metrics: &'static Metrics,
+}
+
+impl BlockStreamMultiplexer {
+    pub fn new(mut upstream: CurrentBlockStream) -> Self {
+        // Blocks are numbered in the order they are received so that consumers can tell how many
+        // they missed, independently of gaps in the block numbers of the upstream stream.
+        let (sender, blocks) = watch::channel((0, *upstream.borrow()));
+        let receiver = upstream.clone();
+        tokio::task::spawn(async move {
+            let mut sequence = 0;
+            while upstream.changed().await.is_ok() {
+                sequence += 1;
+                let block = *upstream.borrow();
+                if sender.send((sequence, block)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            upstream: receiver,
+            blocks,
+            metrics: Metrics::instance(get_metric_storage_registry()).unwrap(),
+        }
+    }
+
+    /// Returns a receiver of the current block, for consumers that only need the newest block.
+    pub fn receiver(&self) -> CurrentBlockStream {
+        self.upstream.clone()
+    }
+
+    /// Subscribes a consumer to new blocks, labelling its metrics with the name of the consumer.
+    pub fn subscribe(&self, consumer: &'static str) -> BlockSubscription {
+        let mut blocks = self.blocks.clone();
+        let (last_sequence, _) = *blocks.borrow_and_update();
+        self.metrics
+            .subscriptions
+            .with_label_values(&[consumer])
+            .inc();
+        BlockSubscription {
+            consumer,
+            blocks,
+            last_sequence,
+            metrics: self.metrics,
+        }
+    }
+}
+
+/// A subscription of a consumer to new blocks, see [`BlockStreamMultiplexer`].
+pub struct BlockSubscription {
+    consumer: &'static str,
+    blocks: watch::Receiver<(u64, BlockInfo)>,
+    last_sequence: u64,
+    metrics: &'static Metrics,
+}
+
+impl BlockSubscription {
+    /// Returns the current block.
+    pub fn current(&self) -> BlockInfo {
+        self.blocks.borrow().1
+    }
+
+    /// Waits for a new block and returns it along with the number of blocks that were missed since
+    /// the previously returned block, or `None` if the upstream stream ended.
+    pub async fn next(&mut self) -> Option<(BlockInfo, u64)> {
+        self.blocks.changed().await.ok()?;
+        let (sequence, block) = *self.blocks.borrow();
+        let missed = sequence.saturating_sub(self.last_sequence + 1);
+        self.last_sequence = sequence;
+        if missed > 0 {
+            tracing::debug!(
+                consumer = self.consumer,
+                "missed {} blocks before block {}",
+                missed,
+                block.number,
+            );
+            self.metrics
+                .lagged_blocks
+                .with_label_values(&[self.consumer])
+                .inc_by(missed);
+        }
+        Some((block, missed))
+    }
+}
+
+impl Drop for BlockSubscription {
+    fn drop(&mut self) {
+        self.metrics
+            .subscriptions
+            .with_label_values(&[self.consumer])
+            .dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64) -> BlockInfo {
+        BlockInfo {
+            number,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_missed_blocks() {
+        let (sender, upstream) = watch::channel(block(10));
+        let multiplexer = BlockStreamMultiplexer::new(upstream);
+        let mut fast = multiplexer.subscribe("fast");
+        let mut slow = multiplexer.subscribe("slow");
+        assert_eq!(slow.current(), block(10));
+
+        for number in 11..=13 {
+            sender.send(block(number)).unwrap();
+            assert_eq!(fast.next().await, Some((block(number), 0)));
+        }
+        assert_eq!(slow.next().await, Some((block(13), 2)));
+        assert_eq!(multiplexer.receiver().borrow().number, 13);
+
+        drop(sender);
+        assert_eq!(fast.next().await, None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Detecting a chain head that stopped advancing.
+
+use super::CurrentBlockStream;
+use crate::metrics::get_metric_storage_registry;
+use anyhow::{anyhow, Result};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+#[derive(prometheus_metric_storage::MetricStorage, Clone, Debug)]
+#[metric(subsystem = "chain_liveness")]
+struct Metrics {
+    /// Whether the chain head stopped advancing, 1 if it did and 0 otherwise.
+    #[metric(labels("chain"))]
+    stalled: prometheus::IntGaugeVec,
+
+    /// Number of times the chain head stopped advancing.
+    #[metric(labels("chain"))]
+    stalls: prometheus::IntCounterVec,
+}
+
+/// A chain head that has not advanced for longer than the maximum head age.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Stall {
+    /// The number of the last observed block.
+    pub block: u64,
+    /// When the last observed block arrived.
+    pub since: Instant,
+}
+
+/// Watches a current block stream and reports when the chain head does not advance for longer
+/// than the maximum head age, for example because the node stopped syncing.
+///
+/// Liquidity fetched while the chain head is stalled is likely frozen, so the service should
+/// consider it untrustworthy until the chain head advances again.
+pub struct ChainWatchdog {
+    stall: watch::Receiver<Option<Stall>>,
+    max_head_age: Duration,
+}
+
+impl ChainWatchdog {
+    /// Spawns a task watching the block stream, labelling the metrics with the name of the chain.
+    pub fn new(
+        chain: &'static str,
+        mut block_stream: CurrentBlockStream,
+        max_head_age: Duration,
+    ) -> Self {
+        let metrics = Metrics::instance(get_metric_storage_registry()).unwrap();
+        metrics.stalled.with_label_values(&[chain]).set(0);
+        let (sender, stall) = watch::channel(None);
+        tokio::task::spawn(async move {
+            let mut last_advance = Instant::now();
+            loop {
+                let block = block_stream.borrow().number;
+                let (advanced, ended) =
+                    match tokio::time::timeout(max_head_age, block_stream.changed()).await {
+                        Ok(Ok(())) => (true, false),
+                        // The stream ended so the chain head can not advance anymore.
+                        Ok(Err(_)) => {
+                            tokio::time::sleep(max_head_age.saturating_sub(last_advance.elapsed()))
+                                .await;
+                            (false, true)
+                        }
+                        Err(_) => (false, false),
+                    };
+                let stalled = sender.borrow().is_some();
+                if advanced {
+                    last_advance = Instant::now();
+                    if stalled {
+                        tracing::info!(chain, "chain head advanced again");
+                        metrics.stalled.with_label_values(&[chain]).set(0);
+                        if sender.send(None).is_err() {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                if !stalled {
+                    tracing::warn!(
+                        chain,
+                        "chain head has not advanced for {:?} since block {}",
+                        last_advance.elapsed(),
+                        block,
+                    );
+                    metrics.stalled.with_label_values(&[chain]).set(1);
+                    metrics.stalls.with_label_values(&[chain]).inc();
+                    let stall = Stall {
+                        block,
+                        since: last_advance,
+                    };
+                    if sender.send(Some(stall)).is_err() {
+                        break;
+                    }
+                }
+                if ended {
+                    break;
+                }
+            }
+        });
+        Self {
+            stall,
+            max_head_age,
+        }
+    }
+
+    /// Returns the current stall of the chain head, or `None` if it is advancing.
+    pub fn stall(&self) -> Option<Stall> {
+        *self.stall.borrow()
+    }
+
+    /// Returns an error if the chain head is stalled.
+    pub fn ensure_live(&self) -> Result<()> {
+        match self.stall() {
+            Some(stall) => Err(anyhow!(
+                "chain head has not advanced for more than {:?} since block {}",
+                self.max_head_age,
+                stall.block,
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::current_block::BlockInfo;
+
+    fn block(number: u64) -> BlockInfo {
+        BlockInfo {
+            number,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_stalled_chain_head() {
+        let (sender, block_stream) = watch::channel(block(1));
+        let watchdog = ChainWatchdog::new("test", block_stream, Duration::from_millis(100));
+        assert!(watchdog.ensure_live().is_ok());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        sender.send(block(2)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(watchdog.stall(), None);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(watchdog.stall().map(|stall| stall.block), Some(2));
+        assert!(watchdog.ensure_live().is_err());
+
+        sender.send(block(3)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(watchdog.ensure_live().is_ok());
+    }
+}
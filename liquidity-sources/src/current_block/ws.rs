@@ -0,0 +1,112 @@
+//! A current block stream driven by a WebSocket `newHeads` subscription.
+
+use super::{
+    poll_blocks, update_block, Block, BlockFilter, BlockInfo, BlockRetrieving, BlockStreamConfig,
+    CurrentBlockStream,
+};
+use crate::Web3;
+use anyhow::Result;
+use futures::StreamExt;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use web3::{api::SubscriptionStream, transports::WebSocket, types::BlockHeader};
+
+/// How long to poll for new blocks after the subscription dropped before
+/// subscribing again.
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Creates a cloneable stream that yields the current block whenever it
+/// changes, like [`super::current_block_stream`], but is notified of new blocks
+/// through an `eth_subscribe("newHeads")` subscription over a WebSocket
+/// connection to the node.
+///
+/// This reduces both the latency with which new blocks are observed and the
+/// number of requests to the node. Whenever the subscription cannot be
+/// established or drops, the current block is polled with the HTTP node
+/// instead until subscribing again.
+pub async fn current_block_ws_stream(
+    ws_url: String,
+    web3: Web3,
+    config: BlockStreamConfig,
+) -> Result<CurrentBlockStream> {
+    let first_block = BlockInfo::try_from(web3.current_block().await?)?;
+    let mut filter = BlockFilter::new(first_block, &config);
+
+    let (sender, receiver) = watch::channel(first_block);
+
+    let update_future = async move {
+        loop {
+            match subscribe_new_heads(&ws_url).await {
+                Ok(mut heads) => {
+                    while let Some(header) = heads.next().await {
+                        match header {
+                            Ok(header) => {
+                                if !update_block(&sender, &mut filter, header_block(header)) {
+                                    return;
+                                }
+                            }
+                            Err(err) => {
+                                tracing::warn!("newHeads subscription failed: {:?}", err);
+                                break;
+                            }
+                        }
+                    }
+                    tracing::warn!("newHeads subscription dropped, polling for blocks");
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "failed to subscribe to newHeads, polling for blocks: {:?}",
+                        err
+                    );
+                }
+            }
+            let deadline = Instant::now() + RESUBSCRIBE_INTERVAL;
+            if !poll_blocks(&web3, &config, &sender, &mut filter, Some(deadline)).await {
+                return;
+            }
+        }
+    };
+
+    tokio::task::spawn(update_future);
+    Ok(receiver)
+}
+
+async fn subscribe_new_heads(ws_url: &str) -> Result<SubscriptionStream<WebSocket, BlockHeader>> {
+    let transport = WebSocket::new(ws_url).await?;
+    Ok(web3::Web3::new(transport)
+        .eth_subscribe()
+        .subscribe_new_heads()
+        .await?)
+}
+
+fn header_block(header: BlockHeader) -> Block {
+    Block {
+        hash: header.hash,
+        parent_hash: header.parent_hash,
+        number: header.number,
+        base_fee_per_gas: header.base_fee_per_gas,
+        timestamp: header.timestamp,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{current_block::into_stream, transport::create_env_test_transport};
+
+    #[tokio::test]
+    #[ignore]
+    async fn ws_block_stream() {
+        let web3 = Web3::new(create_env_test_transport());
+        let ws_url = std::env::var("NODE_WS_URL").unwrap();
+        let receiver = current_block_ws_stream(ws_url, web3, Default::default())
+            .await
+            .unwrap();
+        let mut stream = into_stream(receiver);
+        for _ in 0..3 {
+            let block = stream.next().await.unwrap();
+            println!("new block number {}", block.number);
+        }
+    }
+}
@@ -1,18 +1,73 @@
-use crate::{current_block::BlockRetrieving, maintenance::Maintaining};
-use anyhow::{Context, Error, Result};
+use crate::{
+    current_block::{safe_block_number, BlockFinality, BlockRetrieving},
+    maintenance::Maintaining,
+};
+use anyhow::{Context, Result};
 use ethcontract::contract::{AllEventsBuilder, ParseLog};
 use ethcontract::errors::ExecutionError;
 use ethcontract::{
     dyns::DynTransport, BlockNumber as Web3BlockNumber, Event as EthcontractEvent, EventMetadata,
 };
-use futures::{Stream, StreamExt, TryStreamExt};
-use std::ops::RangeInclusive;
+use futures::{stream, StreamExt as _};
+use once_cell::sync::OnceCell;
+use primitive_types::H256;
+use std::{
+    collections::VecDeque,
+    mem,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
 use tokio::sync::Mutex;
 
+pub mod backfill;
+pub mod indexer;
+pub mod memory;
+pub mod multi;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod snapshot;
+
 // We expect that there is never a reorg that changes more than the last n blocks.
 pub const MAX_REORG_BLOCK_COUNT: u64 = 25;
 // Saving events, we process at most this many at a time.
 const INSERT_EVENT_BATCH_SIZE: usize = 10_000;
+// The number of recently handled blocks whose hashes are tracked for detecting reorgs.
+const BLOCK_HASH_HISTORY_SIZE: usize = 64;
+// The number of blocks to initially query events for at once. Nodes limit the block range or the
+// number of results of log queries differently, so this is halved until queries succeed.
+const MAX_BLOCK_PAGE_SIZE: u64 = 10_000;
+// The number of consecutive successful queries after which a reduced page size is doubled again,
+// since the number of results in a block range varies over time.
+const PAGE_SIZE_GROWTH_INTERVAL: u64 = 16;
+// The number of times that a log query is retried when the node rate limits it, with the backoff
+// doubling from the initial backoff after every attempt.
+const RATE_LIMIT_RETRIES: u32 = 5;
+const RATE_LIMIT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+// The number of pages of blocks whose events are queried concurrently by default.
+const CONCURRENT_PAGE_FETCHES: usize = 4;
+// Parts of the error messages that nodes return for log queries with too large block ranges or
+// too many results.
+const RANGE_TOO_LARGE_ERRORS: &[&str] = &[
+    "query returned more than",
+    "response size exceeded",
+    "response size should not greater than",
+    "maximum block range",
+    "block range is too wide",
+    "too many results",
+    "too many blocks",
+    "range is too large",
+];
+// Parts of the error messages that nodes return for rate limited requests, which includes the
+// status of HTTP responses, see `transport::http`.
+const RATE_LIMIT_ERRORS: &[&str] = &[
+    "too many requests",
+    "rate limit",
+    "compute units per second",
+];
 
 pub struct EventHandler<B, C, S>
 where
@@ -24,6 +79,11 @@ where
     contract: C,
     store: S,
     last_handled_block: Option<u64>,
+    finality: BlockFinality,
+    block_hashes: BlockHashHistory,
+    block_page_size: BlockPageSize,
+    concurrent_page_fetches: usize,
+    rollback_handlers: Vec<Arc<dyn RollbackHandling>>,
 }
 
 /// `EventStoring` is used by `EventHandler` for the purpose of giving the user freedom
@@ -32,6 +92,11 @@ where
 /// # Examples
 /// Databases: might transform, filter and classify which events are inserted
 /// HashSet: For less persistent (in memory) storing, insert events into a set.
+///
+/// [`memory::InMemoryEventStore`] keeps all events in memory, and the
+/// `postgres::PostgresEventStore` enabled by the `postgres` feature persists
+/// them so that indexed events survive restarts. [`indexer::IndexedStore`] only
+/// keeps the state derived from the events.
 #[async_trait::async_trait]
 pub trait EventStoring<T>: Send + Sync {
     /// Returns ok, on successful execution, otherwise an appropriate error
@@ -54,6 +119,44 @@ pub trait EventStoring<T>: Send + Sync {
     async fn last_event_block(&self) -> Result<u64>;
 }
 
+/// Re-derives state that depends on handled events after a reorg that was deeper than the finality
+/// of the chain accounts for.
+///
+/// The events of the reorged blocks are already replaced in the event store, which is responsible
+/// for re-deriving its own state such as pool registries, but other state derived from the events
+/// or the reorged blocks, such as cached pool state, needs to be rolled back as well.
+#[async_trait::async_trait]
+pub trait RollbackHandling: Send + Sync {
+    /// Rolls back the state derived from the specified block onwards.
+    async fn rollback(&self, from_block: u64) -> Result<()>;
+}
+
+/// A rollback handler for state that can only be created after the event handler, like a cache of
+/// the pools that are indexed by the event handler itself.
+///
+/// The state is only referenced weakly, so that it can own the event handler.
+#[derive(Default)]
+pub struct DeferredRollbackHandler(OnceCell<Weak<dyn RollbackHandling>>);
+
+impl DeferredRollbackHandler {
+    /// Sets the state that is rolled back. Fails if it was already set.
+    pub fn set(&self, handler: Weak<dyn RollbackHandling>) -> Result<()> {
+        self.0
+            .set(handler)
+            .map_err(|_| anyhow::anyhow!("rollback handler already set"))
+    }
+}
+
+#[async_trait::async_trait]
+impl RollbackHandling for DeferredRollbackHandler {
+    async fn rollback(&self, from_block: u64) -> Result<()> {
+        match self.0.get().and_then(Weak::upgrade) {
+            Some(handler) => handler.rollback(from_block).await,
+            None => Ok(()),
+        }
+    }
+}
+
 pub trait EventRetrieving {
     type Event: ParseLog;
     fn get_events(&self) -> AllEventsBuilder<DynTransport, Self::Event>;
@@ -76,9 +179,34 @@ where
             contract,
             store,
             last_handled_block: start_sync_at_block,
+            finality: Default::default(),
+            block_hashes: Default::default(),
+            block_page_size: Default::default(),
+            concurrent_page_fetches: CONCURRENT_PAGE_FETCHES,
+            rollback_handlers: Vec::new(),
         }
     }
 
+    /// Adds state that is rolled back after the events of a deep reorg were replaced.
+    pub fn with_rollback_handler(mut self, handler: Arc<dyn RollbackHandling>) -> Self {
+        self.rollback_handlers.push(handler);
+        self
+    }
+
+    /// Sets how blocks of the chain become final, which determines the range
+    /// of blocks for which events are updated in case of reorgs.
+    pub fn with_block_finality(mut self, finality: BlockFinality) -> Self {
+        self.finality = finality;
+        self
+    }
+
+    /// Sets the maximum number of pages of blocks whose events are queried
+    /// concurrently when catching up on many blocks.
+    pub fn with_concurrent_page_fetches(mut self, fetches: usize) -> Self {
+        self.concurrent_page_fetches = fetches;
+        self
+    }
+
     pub fn store(&self) -> &S {
         &self.store
     }
@@ -87,7 +215,9 @@ where
         self.last_handled_block
     }
 
-    async fn event_block_range(&self) -> Result<RangeInclusive<BlockNumber>> {
+    /// Returns the range of blocks to update events for, the hash of the current block and the first
+    /// reorged block if a reorg deeper than the chain finality was detected.
+    async fn event_block_range(&self) -> Result<(RangeInclusive<BlockNumber>, H256, Option<u64>)> {
         // Instead of using only the most recent event block from the db we also store the last
         // handled block in self so that during long times of no events we do not query needlessly
         // large block ranges.
@@ -95,30 +225,52 @@ where
             Some(block) => block,
             None => self.store.last_event_block().await?,
         };
-        let current_block = self.block_retriever.current_block_number().await?;
-        let from_block = last_handled_block.saturating_sub(MAX_REORG_BLOCK_COUNT);
+        let current_block = self.block_retriever.current_block().await?;
+        let current_hash = current_block.hash.context("current block missing hash")?;
+        let current_block = current_block
+            .number
+            .context("current block missing number")?
+            .as_u64();
+        let mut from_block = match self.finality {
+            BlockFinality::Depth(depth) => last_handled_block.saturating_sub(depth),
+            finality => {
+                let safe_block = safe_block_number(&self.block_retriever, finality).await?;
+                last_handled_block.min(safe_block)
+            }
+        };
+        // The finality of the chain is only an assumption, so additionally check that the recently
+        // handled blocks were not reorged and update the events from the fork point otherwise.
+        let reorged_block = self
+            .block_hashes
+            .first_reorged_block(&self.block_retriever)
+            .await?;
+        let deep_reorg = reorged_block.filter(|block| *block < from_block);
+        if let Some(reorged_block) = deep_reorg {
+            tracing::warn!(
+                "detected reorg from block {} which is deeper than the chain finality accounts for",
+                reorged_block,
+            );
+            from_block = reorged_block;
+        }
         anyhow::ensure!(
             from_block <= current_block,
             format!(
-                "current block number according to node is {} which is more than {} blocks in the \
-                 past compared to last handled block {}",
-                current_block, MAX_REORG_BLOCK_COUNT, last_handled_block
+                "current block number according to node is {} which is before block {} from \
+                 which events are updated after last handled block {}",
+                current_block, from_block, last_handled_block
             )
         );
-        Ok(BlockNumber::Specific(from_block)..=BlockNumber::Latest(current_block))
+        Ok((
+            BlockNumber::Specific(from_block)..=BlockNumber::Latest(current_block),
+            current_hash,
+            deep_reorg,
+        ))
     }
 
     /// Get new events from the contract and insert them into the database.
     pub async fn update_events(&mut self) -> Result<()> {
-        let range = self.event_block_range().await?;
+        let (range, current_hash, deep_reorg) = self.event_block_range().await?;
         tracing::debug!("updating events in block range {:?}", range);
-        let events = self
-            .past_events(&range)
-            .await
-            .context("failed to get past events")?
-            .chunks(INSERT_EVENT_BATCH_SIZE)
-            .map(|chunk| chunk.into_iter().collect::<Result<Vec<_>, _>>());
-        futures::pin_mut!(events);
         // We intentionally do not go with the obvious approach of deleting old events first and
         // then inserting new ones. Instead, we make sure that the deletion and the insertion of the
         // first batch of events happen in one transaction.
@@ -141,44 +293,200 @@ where
         // Or we could make the batch size unlimited but this runs into problems when we have not
         // updated it in a long time resulting in many missing events which we would all have to
         // in one transaction.
+        self.store_events_in_range(range.clone()).await?;
+        if let Some(reorged_block) = deep_reorg {
+            for handler in &self.rollback_handlers {
+                handler.rollback(reorged_block).await?;
+            }
+        }
+        self.last_handled_block = Some(range.end().to_u64());
+        self.block_hashes.record(range.end().to_u64(), current_hash);
+        Ok(())
+    }
+
+    /// Retrieves the events in the block range and replaces the stored events from the start of the
+    /// range with them.
+    ///
+    /// The events of several pages of blocks are retrieved concurrently, which speeds up catching up
+    /// on large ranges, but they are stored strictly in the order of the pages.
+    async fn store_events_in_range(&mut self, range: RangeInclusive<BlockNumber>) -> Result<()> {
+        let contract = &self.contract;
+        let block_page_size = &self.block_page_size;
+        let pages = block_pages(&range, block_page_size.get());
+        // `buffered` yields the results in the order of the pages regardless of the order in which
+        // the queries complete.
+        let mut pages = stream::iter(pages)
+            .map(|(from_block, to_block)| {
+                past_events(contract, block_page_size, from_block, to_block)
+            })
+            .buffered(self.concurrent_page_fetches.max(1));
         let mut have_deleted_old_events = false;
-        while let Some(events_chunk) = events.next().await {
+        while let Some(events) = pages.next().await {
             // Early return on error (through `?`) is important here so that the second
-            // !have_deleted_old_eventsS check (after the loop) is correct.
-            let unwrapped_events = events_chunk.context("failed to get next chunk of events")?;
-            if !have_deleted_old_events {
-                self.store
-                    .replace_events(unwrapped_events, range.clone())
-                    .await?;
-                have_deleted_old_events = true;
-            } else {
-                self.store.append_events(unwrapped_events).await?;
-            };
+            // !have_deleted_old_events check (after the loop) is correct.
+            let mut events = events.context("failed to get past events")?;
+            while !events.is_empty() {
+                let rest = events.split_off(events.len().min(INSERT_EVENT_BATCH_SIZE));
+                let events_chunk = mem::replace(&mut events, rest);
+                if !have_deleted_old_events {
+                    self.store
+                        .replace_events(events_chunk, range.clone())
+                        .await?;
+                    have_deleted_old_events = true;
+                } else {
+                    self.store.append_events(events_chunk).await?;
+                };
+            }
         }
-        // The `chunks` adaptor does not return an empty chunk if the stream was completely empty.
-        // However we do want to delete old events in this case as a rerorg might have removed
-        // events without adding new ones.
+        // We do want to delete old events if there are no new events as a rerorg might have
+        // removed events without adding new ones.
         if !have_deleted_old_events {
             self.store.replace_events(Vec::new(), range.clone()).await?;
         }
-        self.last_handled_block = Some(range.end().to_u64());
         Ok(())
     }
+}
+
+/// Splits the block range into pages of at most the page size, returning the first and last block
+/// of each page.
+fn block_pages(range: &RangeInclusive<BlockNumber>, page_size: u64) -> Vec<(u64, BlockNumber)> {
+    let end = *range.end();
+    let mut pages = Vec::new();
+    let mut from_block = range.start().to_u64();
+    while from_block <= end.to_u64() {
+        let to_block = from_block.saturating_add(page_size.max(1) - 1);
+        if to_block < end.to_u64() {
+            pages.push((from_block, BlockNumber::Specific(to_block)));
+            from_block = to_block + 1;
+        } else {
+            pages.push((from_block, end));
+            break;
+        }
+    }
+    pages
+}
+
+/// The number of blocks whose events are queried at once, which adapts to the limits of the node.
+///
+/// The page size is tracked by each event handler separately, so handlers that query the same node
+/// each find its limits on their own.
+struct BlockPageSize {
+    size: AtomicU64,
+    successful_queries: AtomicU64,
+}
+
+impl Default for BlockPageSize {
+    fn default() -> Self {
+        Self {
+            size: AtomicU64::new(MAX_BLOCK_PAGE_SIZE),
+            successful_queries: AtomicU64::new(0),
+        }
+    }
+}
+
+impl BlockPageSize {
+    fn get(&self) -> u64 {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Halves the page size after a query for a page of the specified size was rejected.
+    fn shrink(&self, rejected_size: u64) -> u64 {
+        self.successful_queries.store(0, Ordering::Relaxed);
+        let size = (rejected_size / 2).max(1);
+        self.size.fetch_min(size, Ordering::Relaxed);
+        size
+    }
 
-    async fn past_events(
-        &self,
-        block_range: &RangeInclusive<BlockNumber>,
-    ) -> Result<impl Stream<Item = Result<EthcontractEvent<C::Event>>>, ExecutionError> {
-        Ok(self
-            .contract
+    /// Records a successful query, doubling the page size up to the maximum after every
+    /// `PAGE_SIZE_GROWTH_INTERVAL` consecutive successful queries.
+    fn record_success(&self) {
+        let successful_queries = self.successful_queries.fetch_add(1, Ordering::Relaxed) + 1;
+        if successful_queries % PAGE_SIZE_GROWTH_INTERVAL == 0 {
+            let _ = self
+                .size
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+                    (size < MAX_BLOCK_PAGE_SIZE).then(|| (size * 2).min(MAX_BLOCK_PAGE_SIZE))
+                });
+        }
+    }
+}
+
+/// Retrieves the events of the blocks from the first to the last block.
+///
+/// The page size is halved whenever the node rejects a query because of its block range or number
+/// of results, in which case the blocks are retrieved in smaller pages. The reduced page size is
+/// kept for subsequent queries of the event handler, and grows again after enough of them
+/// succeeded. Rate limited queries are retried with an exponential backoff.
+async fn past_events<C>(
+    contract: &C,
+    block_page_size: &BlockPageSize,
+    mut from_block: u64,
+    last_block: BlockNumber,
+) -> Result<Vec<EthcontractEvent<C::Event>>>
+where
+    C: EventRetrieving,
+{
+    let mut events = Vec::new();
+    let mut rate_limit_retries = 0;
+    while from_block <= last_block.to_u64() {
+        let page_size = block_page_size.get();
+        let to_block = from_block.saturating_add(page_size - 1);
+        let (to_block, to) = if to_block < last_block.to_u64() {
+            (to_block, Web3BlockNumber::from(to_block))
+        } else {
+            (last_block.to_u64(), last_block.block_number())
+        };
+        let result = contract
             .get_events()
-            .from_block((*block_range.start()).block_number())
-            .to_block((*block_range.end()).block_number())
-            .block_page_size(500)
-            .query_paginated()
-            .await?
-            .map_err(Error::from))
+            .from_block(Web3BlockNumber::from(from_block))
+            .to_block(to)
+            .query()
+            .await;
+        match result {
+            Ok(page) => {
+                events.extend(page);
+                from_block = to_block + 1;
+                rate_limit_retries = 0;
+                block_page_size.record_success();
+            }
+            Err(err) if page_size > 1 && is_range_too_large(&err) => {
+                let page_size = block_page_size.shrink(page_size);
+                tracing::debug!(
+                    "reducing event query block page size to {}: {:?}",
+                    page_size,
+                    err
+                );
+            }
+            Err(err) if rate_limit_retries < RATE_LIMIT_RETRIES && is_rate_limited(&err) => {
+                let backoff = RATE_LIMIT_INITIAL_BACKOFF * 2_u32.pow(rate_limit_retries);
+                rate_limit_retries += 1;
+                tracing::debug!(
+                    "retrying rate limited event query in {:?}: {:?}",
+                    backoff,
+                    err
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            Err(err) => return Err(err.into()),
+        }
     }
+    Ok(events)
+}
+
+/// Returns `true` if the error was caused by the block range or number of results of a log query
+/// being too large for the node.
+fn is_range_too_large(err: &ExecutionError) -> bool {
+    error_matches(err, RANGE_TOO_LARGE_ERRORS)
+}
+
+/// Returns `true` if the node rejected the query because of its rate limit.
+fn is_rate_limited(err: &ExecutionError) -> bool {
+    error_matches(err, RATE_LIMIT_ERRORS)
+}
+
+fn error_matches(err: &ExecutionError, patterns: &[&str]) -> bool {
+    let message = err.to_string().to_lowercase();
+    patterns.iter().any(|pattern| message.contains(pattern))
 }
 
 #[async_trait::async_trait]
@@ -194,6 +502,42 @@ where
     }
 }
 
+/// Hashes of recently handled blocks, used for detecting reorgs that are deeper than what the
+/// finality of the chain accounts for.
+#[derive(Debug, Default)]
+struct BlockHashHistory(VecDeque<(u64, H256)>);
+
+impl BlockHashHistory {
+    /// Records the hash of a handled block, replacing the hashes of the same or later blocks.
+    fn record(&mut self, block: u64, hash: H256) {
+        self.0.retain(|(number, _)| *number < block);
+        if self.0.len() == BLOCK_HASH_HISTORY_SIZE {
+            self.0.pop_front();
+        }
+        self.0.push_back((block, hash));
+    }
+
+    /// Verifies the tracked blocks against the node, starting with the most recent one, and
+    /// returns the first block that may have been reorged, or `None` if the most recent tracked
+    /// block is still part of the chain.
+    ///
+    /// If none of the tracked blocks are still part of the chain, the reorg is deeper than the
+    /// tracked history and the oldest tracked block is returned.
+    async fn first_reorged_block(&self, retriever: &impl BlockRetrieving) -> Result<Option<u64>> {
+        let mut first_reorged = None;
+        for (number, hash) in self.0.iter().rev() {
+            if retriever.block_hash(*number).await? == Some(*hash) {
+                return Ok(first_reorged.map(|_| number + 1));
+            }
+            first_reorged = Some(*number);
+        }
+        if first_reorged.is_some() {
+            tracing::warn!("reorg is deeper than the tracked block hash history");
+        }
+        Ok(first_reorged)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct EventIndex {
     pub block_number: u64,
@@ -263,3 +607,203 @@ macro_rules! impl_event_retrieving {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::current_block::{Block, SafeBlockTag};
+    use std::collections::HashMap;
+
+    struct FakeChain(HashMap<u64, H256>);
+
+    #[async_trait::async_trait]
+    impl BlockRetrieving for FakeChain {
+        async fn current_block(&self) -> Result<Block> {
+            unimplemented!()
+        }
+
+        async fn current_block_number(&self) -> Result<u64> {
+            unimplemented!()
+        }
+
+        async fn tagged_block_number(&self, _: SafeBlockTag) -> Result<Option<u64>> {
+            unimplemented!()
+        }
+
+        async fn block_hash(&self, number: u64) -> Result<Option<H256>> {
+            Ok(self.0.get(&number).copied())
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_reorged_blocks() {
+        let mut history = BlockHashHistory::default();
+        assert_eq!(
+            history
+                .first_reorged_block(&FakeChain(Default::default()))
+                .await
+                .unwrap(),
+            None
+        );
+
+        for block in [10, 20, 30] {
+            history.record(block, H256::from_low_u64_be(block));
+        }
+        let chain = |reorged_from: u64| {
+            FakeChain(
+                (0..=40)
+                    .map(|block| {
+                        let fork = if block >= reorged_from { 0x100 } else { 0 };
+                        (block, H256::from_low_u64_be(block + fork))
+                    })
+                    .collect(),
+            )
+        };
+
+        assert_eq!(history.first_reorged_block(&chain(31)).await.unwrap(), None);
+        assert_eq!(
+            history.first_reorged_block(&chain(25)).await.unwrap(),
+            Some(21)
+        );
+        assert_eq!(
+            history.first_reorged_block(&chain(15)).await.unwrap(),
+            Some(11)
+        );
+        // Reorgs deeper than the history are reported from the oldest block.
+        assert_eq!(
+            history.first_reorged_block(&chain(5)).await.unwrap(),
+            Some(10)
+        );
+
+        // Recording a block replaces the hashes of later blocks.
+        history.record(20, H256::from_low_u64_be(20 + 0x100));
+        assert_eq!(history.first_reorged_block(&chain(15)).await.unwrap(), None);
+    }
+
+    #[test]
+    fn detects_range_too_large_errors() {
+        let rpc_error = |message: &str| {
+            ExecutionError::Web3(web3::Error::Rpc(ethcontract::jsonrpc::Error {
+                code: ethcontract::jsonrpc::ErrorCode::ServerError(-32005),
+                message: message.to_owned(),
+                data: None,
+            }))
+        };
+
+        for message in [
+            "query returned more than 10000 results",
+            "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block \
+             range and no limit on the response size",
+            "exceed maximum block range: 5000",
+        ] {
+            assert!(is_range_too_large(&rpc_error(message)), "{}", message);
+            assert!(!is_rate_limited(&rpc_error(message)), "{}", message);
+        }
+        assert!(!is_range_too_large(&rpc_error("execution reverted")));
+
+        for message in [
+            "daily request count exceeded, request rate limited",
+            "Your app has exceeded its compute units per second capacity",
+            "response status code is not success: 429 Too Many Requests",
+            "rate limit exceeded for block range queries",
+        ] {
+            assert!(is_rate_limited(&rpc_error(message)), "{}", message);
+            assert!(!is_range_too_large(&rpc_error(message)), "{}", message);
+        }
+    }
+
+    #[test]
+    fn block_page_size_shrinks_and_grows_again() {
+        let page_size = BlockPageSize::default();
+        assert_eq!(
+            page_size.shrink(MAX_BLOCK_PAGE_SIZE),
+            MAX_BLOCK_PAGE_SIZE / 2
+        );
+        assert_eq!(
+            page_size.shrink(MAX_BLOCK_PAGE_SIZE / 2),
+            MAX_BLOCK_PAGE_SIZE / 4
+        );
+        // Concurrent queries that were rejected with a larger page size don't grow it.
+        page_size.shrink(MAX_BLOCK_PAGE_SIZE);
+        assert_eq!(page_size.get(), MAX_BLOCK_PAGE_SIZE / 4);
+        assert_eq!(page_size.shrink(1), 1);
+
+        let page_size = BlockPageSize::default();
+        page_size.shrink(MAX_BLOCK_PAGE_SIZE / 2);
+        for _ in 1..PAGE_SIZE_GROWTH_INTERVAL {
+            page_size.record_success();
+        }
+        assert_eq!(page_size.get(), MAX_BLOCK_PAGE_SIZE / 4);
+        page_size.record_success();
+        assert_eq!(page_size.get(), MAX_BLOCK_PAGE_SIZE / 2);
+
+        // A rejected query restarts the count of successful queries.
+        for _ in 1..PAGE_SIZE_GROWTH_INTERVAL {
+            page_size.record_success();
+        }
+        page_size.shrink(MAX_BLOCK_PAGE_SIZE / 2);
+        page_size.record_success();
+        assert_eq!(page_size.get(), MAX_BLOCK_PAGE_SIZE / 4);
+
+        // The page size doesn't grow beyond the maximum.
+        for _ in 0..4 * PAGE_SIZE_GROWTH_INTERVAL {
+            page_size.record_success();
+        }
+        assert_eq!(page_size.get(), MAX_BLOCK_PAGE_SIZE);
+    }
+
+    #[test]
+    fn block_hash_history_is_bounded() {
+        let mut history = BlockHashHistory::default();
+        for block in 0..2 * BLOCK_HASH_HISTORY_SIZE as u64 {
+            history.record(block, H256::from_low_u64_be(block));
+        }
+        assert_eq!(history.0.len(), BLOCK_HASH_HISTORY_SIZE);
+        assert_eq!(history.0[0].0, BLOCK_HASH_HISTORY_SIZE as u64);
+    }
+
+    #[test]
+    fn splits_block_range_into_pages() {
+        let pages = block_pages(&(BlockNumber::Specific(5)..=BlockNumber::Latest(29)), 10);
+        assert_eq!(
+            pages
+                .iter()
+                .map(|(from, to)| (*from, to.to_u64()))
+                .collect::<Vec<_>>(),
+            vec![(5, 14), (15, 24), (25, 29)]
+        );
+        assert!(matches!(pages[1].1, BlockNumber::Specific(_)));
+        assert!(matches!(pages[2].1, BlockNumber::Latest(_)));
+
+        let pages = block_pages(&(BlockNumber::Specific(5)..=BlockNumber::Specific(5)), 10);
+        assert!(matches!(pages[..], [(5, BlockNumber::Specific(5))]));
+    }
+
+    #[tokio::test]
+    async fn deferred_rollback_handler_forwards_once_set() {
+        struct Recorder(std::sync::Mutex<Vec<u64>>);
+
+        #[async_trait::async_trait]
+        impl RollbackHandling for Recorder {
+            async fn rollback(&self, from_block: u64) -> Result<()> {
+                self.0.lock().unwrap().push(from_block);
+                Ok(())
+            }
+        }
+
+        let deferred = DeferredRollbackHandler::default();
+        deferred.rollback(1).await.unwrap();
+
+        let recorder = Arc::new(Recorder(Default::default()));
+        let handler: Arc<dyn RollbackHandling> = recorder.clone();
+        deferred.set(Arc::downgrade(&handler)).unwrap();
+        assert!(deferred.set(Arc::downgrade(&handler)).is_err());
+        deferred.rollback(2).await.unwrap();
+        assert_eq!(*recorder.0.lock().unwrap(), vec![2]);
+
+        // The state is dropped independently of the event handler.
+        drop(handler);
+        drop(recorder);
+        deferred.rollback(3).await.unwrap();
+    }
+}
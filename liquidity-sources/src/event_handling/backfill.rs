@@ -0,0 +1,196 @@
+//! Backfilling of historical events in rate-limited batches.
+//!
+//! Backfills can take a long time for contracts with a long history, so the
+//! progress is persisted in a checkpoint after every batch, allowing an
+//! interrupted backfill to resume where it left off.
+
+use super::{BlockNumber, EventHandler, EventRetrieving, EventStoring};
+use crate::current_block::{safe_block_number, BlockRetrieving};
+use anyhow::{Context as _, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Clone, Debug)]
+pub struct BackfillConfig {
+    /// The first block to backfill events from, usually the deployment block
+    /// of the contract.
+    pub start_block: u64,
+    /// The number of blocks to backfill events for before persisting the
+    /// progress.
+    pub batch_size: u64,
+    /// The time to wait between batches, limiting the rate of requests to the
+    /// node.
+    pub batch_interval: Duration,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            start_block: 0,
+            batch_size: 100_000,
+            batch_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Persists the progress of a backfill.
+#[async_trait::async_trait]
+pub trait BackfillCheckpoint: Send + Sync {
+    /// Returns the last block for which events were backfilled, or `None` if
+    /// no backfill was started yet.
+    async fn load(&self) -> Result<Option<u64>>;
+
+    /// Records that events were backfilled up to and including the block.
+    async fn save(&mut self, block: u64) -> Result<()>;
+}
+
+/// A checkpoint that is stored as a block number in a file.
+pub struct FileCheckpoint {
+    path: PathBuf,
+}
+
+impl FileCheckpoint {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait::async_trait]
+impl BackfillCheckpoint for FileCheckpoint {
+    async fn load(&self) -> Result<Option<u64>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read checkpoint {}", self.path.display()))
+            }
+        };
+        let block = contents
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid checkpoint {}", self.path.display()))?;
+        Ok(Some(block))
+    }
+
+    async fn save(&mut self, block: u64) -> Result<()> {
+        // Write to a temporary file first so that the checkpoint is never left
+        // partially written when the process is interrupted.
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, block.to_string())
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .with_context(|| format!("failed to write checkpoint {}", self.path.display()))
+    }
+}
+
+/// Returns the range of blocks of the next batch of a backfill, or `None` if
+/// the backfill is complete.
+fn next_batch(
+    config: &BackfillConfig,
+    checkpoint: Option<u64>,
+    safe_block: u64,
+) -> Option<(u64, u64)> {
+    let from_block = match checkpoint {
+        Some(block) => (block + 1).max(config.start_block),
+        None => config.start_block,
+    };
+    if from_block > safe_block {
+        return None;
+    }
+    let to_block = from_block
+        .saturating_add(config.batch_size.max(1) - 1)
+        .min(safe_block);
+    Some((from_block, to_block))
+}
+
+impl<B, C, S> EventHandler<B, C, S>
+where
+    B: BlockRetrieving,
+    C: EventRetrieving,
+    S: EventStoring<C::Event>,
+{
+    /// Backfills the events from the configured start block, or from the
+    /// checkpoint of an earlier interrupted backfill, to the current safe
+    /// block.
+    ///
+    /// The events of each batch replace the stored events from the start of
+    /// the batch, so events stored after the last checkpoint by an interrupted
+    /// backfill are not duplicated. Once complete, regular event updates
+    /// continue from the last backfilled block.
+    pub async fn backfill(
+        &mut self,
+        config: &BackfillConfig,
+        checkpoint: &mut impl BackfillCheckpoint,
+    ) -> Result<()> {
+        let safe_block = safe_block_number(&self.block_retriever, self.finality).await?;
+        let mut last_block = checkpoint.load().await?;
+        if let Some(block) = last_block {
+            tracing::info!("resuming event backfill after block {}", block);
+        }
+        while let Some((from_block, to_block)) = next_batch(config, last_block, safe_block) {
+            if last_block.is_some() {
+                tokio::time::sleep(config.batch_interval).await;
+            }
+            tracing::debug!(
+                "backfilling events in blocks {}..={} up to block {}",
+                from_block,
+                to_block,
+                safe_block,
+            );
+            self.store_events_in_range(
+                BlockNumber::Specific(from_block)..=BlockNumber::Specific(to_block),
+            )
+            .await?;
+            checkpoint.save(to_block).await?;
+            self.last_handled_block = Some(to_block);
+            last_block = Some(to_block);
+        }
+        tracing::info!("backfilled events up to block {}", safe_block);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_backfill_into_batches() {
+        let config = BackfillConfig {
+            start_block: 100,
+            batch_size: 50,
+            batch_interval: Duration::default(),
+        };
+
+        assert_eq!(next_batch(&config, None, 220), Some((100, 149)));
+        assert_eq!(next_batch(&config, Some(149), 220), Some((150, 199)));
+        assert_eq!(next_batch(&config, Some(199), 220), Some((200, 220)));
+        assert_eq!(next_batch(&config, Some(220), 220), None);
+        // A checkpoint from before the start block does not backfill earlier
+        // blocks.
+        assert_eq!(next_batch(&config, Some(10), 220), Some((100, 149)));
+        assert_eq!(next_batch(&config, None, 50), None);
+    }
+
+    #[tokio::test]
+    async fn file_checkpoint_roundtrip() {
+        let path =
+            std::env::temp_dir().join(format!("event-backfill-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut checkpoint = FileCheckpoint::new(&path);
+        assert_eq!(checkpoint.load().await.unwrap(), None);
+        checkpoint.save(1337).await.unwrap();
+        assert_eq!(checkpoint.load().await.unwrap(), Some(1337));
+        assert_eq!(FileCheckpoint::new(&path).load().await.unwrap(), Some(1337));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
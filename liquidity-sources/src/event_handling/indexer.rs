@@ -0,0 +1,342 @@
+//! A generic indexer maintaining state derived from the events of a contract.
+//!
+//! Indexers only need to implement [`EventIndexing`] with the logic of how an
+//! event changes their state. Querying and decoding the logs, replacing the
+//! events of reorged blocks and storing the events is handled here.
+
+use super::{
+    memory::event_block, multi::EventFilter, BlockNumber, EventHandler, EventRetrieving,
+    EventStoring, MAX_REORG_BLOCK_COUNT,
+};
+use crate::{current_block::BlockRetrieving, Web3};
+use anyhow::Result;
+use ethcontract::{
+    contract::{AllEventsBuilder, ParseLog},
+    dyns::DynTransport,
+    Event,
+};
+use std::{marker::PhantomData, ops::RangeInclusive};
+
+/// State that is derived from events by applying them in the order they were
+/// emitted.
+pub trait EventIndexing: Clone + Send + Sync + 'static {
+    type Event: Send + Sync + 'static;
+
+    /// Applies an event to the state. Events always have metadata.
+    fn apply_event(&mut self, event: &Event<Self::Event>);
+}
+
+/// Stores the events of an indexer by applying them to its state.
+///
+/// Events in blocks that can still be reorged are kept, so that the state can
+/// be recomputed without them when they are replaced. This clones the state
+/// whenever events are replaced, so large states should share data that
+/// rarely changes, for example with `Arc`s.
+///
+/// Events are additionally kept for the rollback depth, so that the state can
+/// also be recomputed after reorgs that are deeper than the finality of the
+/// chain accounts for.
+pub struct IndexedStore<I>
+where
+    I: EventIndexing,
+{
+    /// The state with all events before the pending ones applied.
+    finalized: I,
+    /// The last block whose events are all applied to the finalized state.
+    finalized_block: u64,
+    /// The events that can still be replaced.
+    pending: Vec<Event<I::Event>>,
+    /// The finalized state with the pending events applied.
+    state: I,
+    /// The number of blocks before the replaced events for which events are
+    /// kept for rolling back deep reorgs.
+    rollback_depth: u64,
+}
+
+impl<I> IndexedStore<I>
+where
+    I: EventIndexing,
+{
+    pub fn new(state: I) -> Self {
+        Self {
+            finalized: state.clone(),
+            finalized_block: 0,
+            pending: Vec::new(),
+            state,
+            rollback_depth: MAX_REORG_BLOCK_COUNT,
+        }
+    }
+
+    /// Sets the number of blocks before the replaced events for which events
+    /// are kept, which is the depth of reorgs beyond the chain finality that
+    /// can be rolled back.
+    pub fn with_rollback_depth(mut self, blocks: u64) -> Self {
+        self.rollback_depth = blocks;
+        self
+    }
+
+    /// Replaces the state with one that includes all events up to and
+    /// including the block.
+    pub(super) fn reset(&mut self, state: I, finalized_block: u64) {
+        self.finalized = state.clone();
+        self.finalized_block = finalized_block;
+        self.pending.clear();
+        self.state = state;
+    }
+
+    /// Returns the last block whose events are all applied to the finalized
+    /// state, along with the finalized state.
+    pub(super) fn finalized(&self) -> (u64, &I) {
+        (self.finalized_block, &self.finalized)
+    }
+
+    /// Returns the state with all stored events applied.
+    pub fn state(&self) -> &I {
+        &self.state
+    }
+
+    /// Applies events that can still be replaced, skipping the ones that are
+    /// already part of the finalized state.
+    ///
+    /// Events are only queried again for finalized blocks after reorgs that are
+    /// deeper than the rollback depth, or after restoring a snapshot, in which
+    /// case they are already applied.
+    fn apply_pending(&mut self, events: Vec<Event<I::Event>>) {
+        let finalized_block = self.finalized_block;
+        let (skipped, events): (Vec<_>, Vec<_>) = events
+            .into_iter()
+            .partition(|event| event_block(event).unwrap_or_default() <= finalized_block);
+        if !skipped.is_empty() {
+            tracing::debug!(
+                "skipping {} events up to finalized block {}",
+                skipped.len(),
+                finalized_block,
+            );
+        }
+        for event in &events {
+            self.state.apply_event(event);
+        }
+        self.pending.extend(events);
+    }
+
+    /// Creates an event handler indexing the logs matching the filter.
+    pub fn into_event_handler<B>(
+        self,
+        web3: Web3,
+        block_retriever: B,
+        filter: EventFilter,
+        start_sync_at_block: Option<u64>,
+    ) -> EventIndexer<B, I>
+    where
+        B: BlockRetrieving,
+        I::Event: ParseLog,
+    {
+        let contract = FilteredEvents {
+            web3,
+            filter,
+            _event: PhantomData,
+        };
+        EventHandler::new(block_retriever, contract, self, start_sync_at_block)
+    }
+}
+
+/// An event handler for an indexer, see [`IndexedStore`].
+pub type EventIndexer<B, I> =
+    EventHandler<B, FilteredEvents<<I as EventIndexing>::Event>, IndexedStore<I>>;
+
+/// Retrieves the logs matching a filter, decoded as `E`.
+pub struct FilteredEvents<E> {
+    web3: Web3,
+    filter: EventFilter,
+    _event: PhantomData<fn() -> E>,
+}
+
+impl<E> EventRetrieving for FilteredEvents<E>
+where
+    E: ParseLog,
+{
+    type Event = E;
+
+    fn get_events(&self) -> AllEventsBuilder<DynTransport, Self::Event> {
+        self.filter.events(self.web3.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl<I> EventStoring<I::Event> for IndexedStore<I>
+where
+    I: EventIndexing,
+{
+    async fn replace_events(
+        &mut self,
+        events: Vec<Event<I::Event>>,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Result<()> {
+        for event in &events {
+            event_block(event)?;
+        }
+        let from_block = range.start().to_u64();
+
+        // Events before the replaced range are final as far as the event
+        // handler is concerned, so they no longer need to be kept once they
+        // are older than the rollback depth.
+        let rollback_block = from_block.saturating_sub(self.rollback_depth);
+        let block_index = |pending: &[Event<I::Event>], block: u64| {
+            pending.partition_point(|event| event_block(event).unwrap_or_default() < block)
+        };
+        let replaced = self
+            .pending
+            .split_off(block_index(&self.pending, from_block));
+        let finalized = block_index(&self.pending, rollback_block);
+        for event in self.pending.drain(..finalized) {
+            self.finalized.apply_event(&event);
+        }
+        self.finalized_block = self.finalized_block.max(rollback_block.saturating_sub(1));
+        if !replaced.is_empty() {
+            self.state = self.finalized.clone();
+            for event in &self.pending {
+                self.state.apply_event(event);
+            }
+        }
+
+        self.apply_pending(events);
+        Ok(())
+    }
+
+    async fn append_events(&mut self, events: Vec<Event<I::Event>>) -> Result<()> {
+        for event in &events {
+            event_block(event)?;
+        }
+        self.apply_pending(events);
+        Ok(())
+    }
+
+    async fn last_event_block(&self) -> Result<u64> {
+        match self.pending.last() {
+            Some(event) => event_block(event),
+            None => Ok(self.finalized_block),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethcontract::{EventMetadata, H160, H256};
+
+    /// Sums up the values of all events.
+    #[derive(Clone, Default)]
+    struct Sum(u32);
+
+    impl EventIndexing for Sum {
+        type Event = u32;
+
+        fn apply_event(&mut self, event: &Event<u32>) {
+            self.0 += event.data;
+        }
+    }
+
+    fn event(block_number: u64, data: u32) -> Event<u32> {
+        Event {
+            data,
+            meta: Some(EventMetadata {
+                address: H160([1; 20]),
+                block_hash: H256::from_low_u64_be(block_number),
+                block_number,
+                transaction_hash: H256([2; 32]),
+                transaction_index: 0,
+                log_index: 0,
+                transaction_log_index: None,
+                log_type: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_events_and_reverts_replaced_ones() {
+        let mut store = IndexedStore::new(Sum::default()).with_rollback_depth(0);
+        assert_eq!(store.last_event_block().await.unwrap(), 0);
+
+        store
+            .append_events(vec![event(1, 1), event(2, 2), event(3, 4)])
+            .await
+            .unwrap();
+        assert_eq!(store.state().0, 7);
+        assert_eq!(store.last_event_block().await.unwrap(), 3);
+
+        store
+            .replace_events(
+                vec![event(3, 8)],
+                BlockNumber::Specific(2)..=BlockNumber::Latest(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.state().0, 9);
+        assert_eq!(store.finalized.0, 1);
+        assert_eq!(store.last_event_block().await.unwrap(), 3);
+
+        // A reorg removing events without adding new ones.
+        store
+            .replace_events(
+                Vec::new(),
+                BlockNumber::Specific(3)..=BlockNumber::Latest(6),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.state().0, 1);
+        assert_eq!(store.last_event_block().await.unwrap(), 2);
+
+        // Events of finalized blocks are already applied.
+        store
+            .append_events(vec![event(2, 32), event(4, 64)])
+            .await
+            .unwrap();
+        assert_eq!(store.state().0, 65);
+        store
+            .replace_events(
+                Vec::new(),
+                BlockNumber::Specific(4)..=BlockNumber::Latest(6),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.state().0, 1);
+
+        assert!(store
+            .append_events(vec![Event {
+                data: 16,
+                meta: None
+            }])
+            .await
+            .is_err());
+        assert_eq!(store.state().0, 1);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_deep_reorgs_within_rollback_depth() {
+        let mut store = IndexedStore::new(Sum::default()).with_rollback_depth(10);
+        store
+            .append_events(vec![event(1, 1), event(12, 2), event(20, 4)])
+            .await
+            .unwrap();
+        store
+            .replace_events(
+                Vec::new(),
+                BlockNumber::Specific(25)..=BlockNumber::Latest(30),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.finalized.0, 3);
+        assert_eq!(store.state().0, 7);
+
+        // The event in block 20 is still kept, so a reorg from block 18 can
+        // be rolled back.
+        store
+            .replace_events(
+                vec![event(19, 8)],
+                BlockNumber::Specific(18)..=BlockNumber::Latest(30),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.state().0, 11);
+    }
+}
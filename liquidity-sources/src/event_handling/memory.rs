@@ -0,0 +1,133 @@
+//! An event store keeping all events in memory.
+
+use super::{BlockNumber, EventStoring};
+use anyhow::{anyhow, Result};
+use ethcontract::Event;
+use std::ops::RangeInclusive;
+
+/// Stores all handled events in memory, in the order they were emitted.
+///
+/// The events are lost on restart. Use a persistent store, such as the
+/// Postgres store of the `postgres` feature, for events that need to survive
+/// restarts.
+pub struct InMemoryEventStore<T> {
+    events: Vec<Event<T>>,
+}
+
+impl<T> Default for InMemoryEventStore<T> {
+    fn default() -> Self {
+        Self { events: Vec::new() }
+    }
+}
+
+impl<T> InMemoryEventStore<T> {
+    /// Returns all stored events.
+    pub fn events(&self) -> &[Event<T>] {
+        &self.events
+    }
+}
+
+/// Returns the block number of an event, failing for events without metadata.
+pub(super) fn event_block<T>(event: &Event<T>) -> Result<u64> {
+    Ok(event
+        .meta
+        .as_ref()
+        .ok_or_else(|| anyhow!("event missing metadata"))?
+        .block_number)
+}
+
+#[async_trait::async_trait]
+impl<T> EventStoring<T> for InMemoryEventStore<T>
+where
+    T: Send + Sync + 'static,
+{
+    async fn replace_events(
+        &mut self,
+        events: Vec<Event<T>>,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Result<()> {
+        let from_block = range.start().to_u64();
+        self.events.retain(|event| {
+            event
+                .meta
+                .as_ref()
+                .map(|meta| meta.block_number < from_block)
+                .unwrap_or(false)
+        });
+        self.append_events(events).await
+    }
+
+    async fn append_events(&mut self, events: Vec<Event<T>>) -> Result<()> {
+        for event in &events {
+            event_block(event)?;
+        }
+        self.events.extend(events);
+        Ok(())
+    }
+
+    async fn last_event_block(&self) -> Result<u64> {
+        Ok(self
+            .events
+            .last()
+            .and_then(|event| event.meta.as_ref())
+            .map(|meta| meta.block_number)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethcontract::{EventMetadata, H160, H256};
+
+    fn event(block_number: u64, data: u32) -> Event<u32> {
+        Event {
+            data,
+            meta: Some(EventMetadata {
+                address: H160([1; 20]),
+                block_hash: H256::from_low_u64_be(block_number),
+                block_number,
+                transaction_hash: H256([2; 32]),
+                transaction_index: 0,
+                log_index: data as _,
+                transaction_log_index: None,
+                log_type: None,
+            }),
+        }
+    }
+
+    fn data(store: &InMemoryEventStore<u32>) -> Vec<u32> {
+        store.events().iter().map(|event| event.data).collect()
+    }
+
+    #[tokio::test]
+    async fn replaces_events_from_range_start() {
+        let mut store = InMemoryEventStore::default();
+        assert_eq!(store.last_event_block().await.unwrap(), 0);
+
+        store
+            .append_events(vec![event(1, 1), event(2, 2), event(3, 3)])
+            .await
+            .unwrap();
+        assert_eq!(store.last_event_block().await.unwrap(), 3);
+
+        store
+            .replace_events(
+                vec![event(2, 4)],
+                BlockNumber::Specific(2)..=BlockNumber::Latest(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(data(&store), vec![1, 4]);
+        assert_eq!(store.last_event_block().await.unwrap(), 2);
+
+        assert!(store
+            .append_events(vec![Event {
+                data: 5,
+                meta: None
+            }])
+            .await
+            .is_err());
+        assert_eq!(data(&store), vec![1, 4]);
+    }
+}
@@ -0,0 +1,382 @@
+//! Event handling for several contracts with shared block scanning.
+//!
+//! Running a separate [`EventHandler`] for every contract, for example for
+//! every pool factory of a liquidity source, queries the logs of every block
+//! range once per contract. Instead, the sources registered here are combined
+//! into a single log query per block range whose logs are then decoded and
+//! dispatched to the store of each source whose filter they match.
+
+use super::{BlockNumber, EventHandler, EventRetrieving, EventStoring};
+use crate::{current_block::BlockRetrieving, Web3};
+use anyhow::Result;
+use ethcontract::{
+    common::abi::{RawLog, Topic},
+    contract::{AllEventsBuilder, ParseLog},
+    dyns::DynTransport,
+    errors::ExecutionError,
+    Event, H160, H256,
+};
+use std::{any::Any, marker::PhantomData, ops::RangeInclusive};
+
+/// An undecoded log, which is decoded by the source it is dispatched to.
+#[derive(Clone, Debug)]
+pub struct RawEvent(pub RawLog);
+
+impl ParseLog for RawEvent {
+    fn parse_log(log: RawLog) -> Result<Self, ExecutionError> {
+        Ok(Self(log))
+    }
+}
+
+/// The logs that a source is interested in.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    /// The contracts emitting the logs, where an empty list matches any
+    /// contract.
+    pub addresses: Vec<H160>,
+    /// The signatures of the events, where an empty list matches any event.
+    pub topics: Vec<H256>,
+}
+
+impl EventFilter {
+    /// Returns a query for the logs matching the filter, decoded as `E`.
+    pub(super) fn events<E>(&self, web3: Web3) -> AllEventsBuilder<DynTransport, E>
+    where
+        E: ParseLog,
+    {
+        let mut events = AllEventsBuilder::new(web3, H160::zero(), None);
+        events.filter = events.filter.address(self.addresses.clone());
+        if self.topics.is_empty() {
+            events
+        } else {
+            events.topic0(Topic::OneOf(self.topics.clone()))
+        }
+    }
+
+    fn matches(&self, event: &Event<RawEvent>) -> bool {
+        let address = event.meta.as_ref().map(|meta| meta.address);
+        let topic = event.data.0.topics.first();
+        (self.addresses.is_empty() || address.map_or(false, |a| self.addresses.contains(&a)))
+            && (self.topics.is_empty() || topic.map_or(false, |t| self.topics.contains(t)))
+    }
+
+    /// Returns a filter matching all logs that are matched by any of the
+    /// filters.
+    fn union<'a>(filters: impl IntoIterator<Item = &'a EventFilter>) -> Self {
+        let mut union = Self::default();
+        let (mut any_address, mut any_topic) = (false, false);
+        for filter in filters {
+            any_address |= filter.addresses.is_empty();
+            any_topic |= filter.topics.is_empty();
+            union.addresses.extend(&filter.addresses);
+            union.topics.extend(&filter.topics);
+        }
+        if any_address {
+            union.addresses.clear();
+        }
+        if any_topic {
+            union.topics.clear();
+        }
+        union.addresses.sort_unstable();
+        union.addresses.dedup();
+        union.topics.sort_unstable();
+        union.topics.dedup();
+        union
+    }
+}
+
+/// Identifies a source registered with [`MultiEventSources`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SourceId(usize);
+
+/// The events of a single source, decoded from raw logs.
+#[async_trait::async_trait]
+trait SourceStoring: Send + Sync {
+    async fn replace_events(
+        &mut self,
+        events: Vec<Event<RawEvent>>,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Result<()>;
+    async fn append_events(&mut self, events: Vec<Event<RawEvent>>) -> Result<()>;
+    async fn last_event_block(&self) -> Result<u64>;
+    fn store(&self) -> &dyn Any;
+}
+
+struct DecodingStore<E, S> {
+    store: S,
+    _event: PhantomData<fn() -> E>,
+}
+
+impl<E, S> DecodingStore<E, S>
+where
+    E: ParseLog,
+{
+    fn decode(events: Vec<Event<RawEvent>>) -> Result<Vec<Event<E>>> {
+        events
+            .into_iter()
+            .map(|event| {
+                Ok(Event {
+                    data: E::parse_log(event.data.0)?,
+                    meta: event.meta,
+                })
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl<E, S> SourceStoring for DecodingStore<E, S>
+where
+    E: ParseLog + 'static,
+    S: EventStoring<E> + 'static,
+{
+    async fn replace_events(
+        &mut self,
+        events: Vec<Event<RawEvent>>,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Result<()> {
+        self.store
+            .replace_events(Self::decode(events)?, range)
+            .await
+    }
+
+    async fn append_events(&mut self, events: Vec<Event<RawEvent>>) -> Result<()> {
+        self.store.append_events(Self::decode(events)?).await
+    }
+
+    async fn last_event_block(&self) -> Result<u64> {
+        self.store.last_event_block().await
+    }
+
+    fn store(&self) -> &dyn Any {
+        &self.store
+    }
+}
+
+/// The sources whose events are handled by a single event handler.
+#[derive(Default)]
+pub struct MultiEventSources {
+    sources: Vec<(EventFilter, Box<dyn SourceStoring>)>,
+}
+
+impl MultiEventSources {
+    /// Registers a source whose logs matching the filter are decoded as `E`
+    /// and stored in the store.
+    pub fn add_source<E, S>(&mut self, filter: EventFilter, store: S) -> SourceId
+    where
+        E: ParseLog + 'static,
+        S: EventStoring<E> + 'static,
+    {
+        self.sources.push((
+            filter,
+            Box::new(DecodingStore {
+                store,
+                _event: PhantomData::<fn() -> E>,
+            }),
+        ));
+        SourceId(self.sources.len() - 1)
+    }
+
+    /// Creates an event handler querying the logs of all sources at once.
+    pub fn into_event_handler<B>(
+        self,
+        web3: Web3,
+        block_retriever: B,
+        start_sync_at_block: Option<u64>,
+    ) -> MultiEventHandler<B>
+    where
+        B: BlockRetrieving,
+    {
+        let contract = MultiContract {
+            web3,
+            filter: EventFilter::union(self.sources.iter().map(|(filter, _)| filter)),
+        };
+        let store = MultiEventStore {
+            sources: self.sources,
+        };
+        EventHandler::new(block_retriever, contract, store, start_sync_at_block)
+    }
+}
+
+/// An event handler for several sources, see [`MultiEventSources`].
+pub type MultiEventHandler<B> = EventHandler<B, MultiContract, MultiEventStore>;
+
+/// Retrieves the logs of all sources with a single query.
+pub struct MultiContract {
+    web3: Web3,
+    filter: EventFilter,
+}
+
+impl EventRetrieving for MultiContract {
+    type Event = RawEvent;
+
+    fn get_events(&self) -> AllEventsBuilder<DynTransport, Self::Event> {
+        self.filter.events(self.web3.clone())
+    }
+}
+
+/// Dispatches the logs to the stores of the sources whose filters they match.
+pub struct MultiEventStore {
+    sources: Vec<(EventFilter, Box<dyn SourceStoring>)>,
+}
+
+impl MultiEventStore {
+    /// Returns the store of a source, or `None` if the source was registered
+    /// with a store of another type.
+    pub fn source<S>(&self, id: SourceId) -> Option<&S>
+    where
+        S: 'static,
+    {
+        self.sources.get(id.0)?.1.store().downcast_ref()
+    }
+
+    fn partition(&self, events: &[Event<RawEvent>]) -> Vec<Vec<Event<RawEvent>>> {
+        self.sources
+            .iter()
+            .map(|(filter, _)| {
+                events
+                    .iter()
+                    .filter(|event| filter.matches(event))
+                    .cloned()
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl EventStoring<RawEvent> for MultiEventStore {
+    async fn replace_events(
+        &mut self,
+        events: Vec<Event<RawEvent>>,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Result<()> {
+        // Every source replaces its events, even without new ones, as a reorg
+        // might have removed events without adding new ones.
+        let partitions = self.partition(&events);
+        for ((_, source), events) in self.sources.iter_mut().zip(partitions) {
+            source.replace_events(events, range.clone()).await?;
+        }
+        Ok(())
+    }
+
+    async fn append_events(&mut self, events: Vec<Event<RawEvent>>) -> Result<()> {
+        let partitions = self.partition(&events);
+        for ((_, source), events) in self.sources.iter_mut().zip(partitions) {
+            if !events.is_empty() {
+                source.append_events(events).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn last_event_block(&self) -> Result<u64> {
+        // Events are only queried once for all sources, so resume from the
+        // source that is furthest behind.
+        let mut last_event_block = None;
+        for (_, source) in &self.sources {
+            let block = source.last_event_block().await?;
+            last_event_block = Some(last_event_block.map_or(block, |last: u64| last.min(block)));
+        }
+        Ok(last_event_block.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::memory::InMemoryEventStore;
+    use ethcontract::EventMetadata;
+
+    #[derive(Debug)]
+    struct TestEvent(H256);
+
+    impl ParseLog for TestEvent {
+        fn parse_log(log: RawLog) -> Result<Self, ExecutionError> {
+            Ok(Self(log.topics[0]))
+        }
+    }
+
+    fn event(address: u64, topic: u64, block_number: u64) -> Event<RawEvent> {
+        Event {
+            data: RawEvent(RawLog {
+                topics: vec![H256::from_low_u64_be(topic)],
+                data: Vec::new(),
+            }),
+            meta: Some(EventMetadata {
+                address: H160::from_low_u64_be(address),
+                block_hash: H256::from_low_u64_be(block_number),
+                block_number,
+                transaction_hash: H256([2; 32]),
+                transaction_index: 0,
+                log_index: 0,
+                transaction_log_index: None,
+                log_type: None,
+            }),
+        }
+    }
+
+    fn filter(addresses: &[u64], topics: &[u64]) -> EventFilter {
+        EventFilter {
+            addresses: addresses
+                .iter()
+                .copied()
+                .map(H160::from_low_u64_be)
+                .collect(),
+            topics: topics.iter().copied().map(H256::from_low_u64_be).collect(),
+        }
+    }
+
+    #[test]
+    fn combines_filters() {
+        let union = EventFilter::union(&[filter(&[2, 1], &[7]), filter(&[1, 3], &[8])]);
+        assert_eq!(union.addresses, filter(&[1, 2, 3], &[]).addresses);
+        assert_eq!(union.topics, filter(&[], &[7, 8]).topics);
+
+        let union = EventFilter::union(&[filter(&[1], &[7]), filter(&[], &[7])]);
+        assert!(union.addresses.is_empty());
+        assert_eq!(union.topics, filter(&[], &[7]).topics);
+    }
+
+    #[tokio::test]
+    async fn dispatches_events_to_sources() {
+        let mut sources = MultiEventSources::default();
+        let first = sources.add_source(
+            filter(&[1], &[]),
+            InMemoryEventStore::<TestEvent>::default(),
+        );
+        let second = sources.add_source(
+            filter(&[1, 2], &[8]),
+            InMemoryEventStore::<TestEvent>::default(),
+        );
+        let mut store = MultiEventStore {
+            sources: sources.sources,
+        };
+
+        store
+            .append_events(vec![event(1, 7, 10), event(1, 8, 11), event(2, 8, 12)])
+            .await
+            .unwrap();
+        store
+            .replace_events(
+                vec![event(2, 7, 12)],
+                BlockNumber::Specific(12)..=BlockNumber::Latest(20),
+            )
+            .await
+            .unwrap();
+
+        let blocks = |id| {
+            store
+                .source::<InMemoryEventStore<TestEvent>>(id)
+                .unwrap()
+                .events()
+                .iter()
+                .map(|event| event.meta.as_ref().unwrap().block_number)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(blocks(first), vec![10, 11]);
+        assert_eq!(blocks(second), vec![11]);
+        assert!(store.source::<InMemoryEventStore<u32>>(first).is_none());
+        assert_eq!(store.last_event_block().await.unwrap(), 11);
+    }
+}
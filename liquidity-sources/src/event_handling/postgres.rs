@@ -0,0 +1,258 @@
+//! An event store persisting events in a Postgres database, so that indexed
+//! events survive restarts.
+
+use super::{memory::event_block, BlockNumber, EventStoring};
+use anyhow::{anyhow, ensure, Result};
+use ethcontract::{Event, EventMetadata, H160, H256};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{postgres::PgRow, types::Json, PgConnection, PgPool, Row};
+use std::{marker::PhantomData, ops::RangeInclusive};
+
+/// Stores events in a Postgres table, with the event data encoded as JSON.
+///
+/// Each table should only be used for the events of a single contract.
+pub struct PostgresEventStore<T> {
+    pool: PgPool,
+    table: String,
+    _event: PhantomData<fn() -> T>,
+}
+
+impl<T> PostgresEventStore<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Creates a new store for the events in the specified table, creating the
+    /// table if it does not exist yet.
+    pub async fn new(pool: PgPool, table: impl Into<String>) -> Result<Self> {
+        let table = table.into();
+        ensure!(
+            !table.is_empty() && table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "invalid event table name {:?}",
+            table,
+        );
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+                block_number BIGINT NOT NULL, \
+                log_index BIGINT NOT NULL, \
+                address BYTEA NOT NULL, \
+                block_hash BYTEA NOT NULL, \
+                transaction_hash BYTEA NOT NULL, \
+                transaction_index BIGINT NOT NULL, \
+                data JSONB NOT NULL, \
+                PRIMARY KEY (block_number, log_index)\
+            )",
+            table,
+        ))
+        .execute(&pool)
+        .await?;
+
+        Ok(Self {
+            pool,
+            table,
+            _event: PhantomData,
+        })
+    }
+
+    /// Loads all stored events, in the order they were emitted.
+    pub async fn events(&self) -> Result<Vec<Event<T>>> {
+        sqlx::query(&format!(
+            "SELECT * FROM {} ORDER BY block_number, log_index",
+            self.table,
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(decode_event)
+        .collect()
+    }
+
+    /// Appends all stored events to another store, for example to restore the
+    /// in-memory state of a source after a restart.
+    pub async fn restore<S>(&self, store: &mut S) -> Result<()>
+    where
+        S: EventStoring<T>,
+    {
+        store.append_events(self.events().await?).await
+    }
+
+    async fn insert_events(
+        &self,
+        connection: &mut PgConnection,
+        events: &[Event<T>],
+    ) -> Result<()> {
+        let query = format!(
+            "INSERT INTO {} \
+                (block_number, log_index, address, block_hash, transaction_hash, \
+                 transaction_index, data) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7) \
+             ON CONFLICT (block_number, log_index) DO UPDATE SET \
+                address = EXCLUDED.address, \
+                block_hash = EXCLUDED.block_hash, \
+                transaction_hash = EXCLUDED.transaction_hash, \
+                transaction_index = EXCLUDED.transaction_index, \
+                data = EXCLUDED.data",
+            self.table,
+        );
+        for event in events {
+            event_block(event)?;
+            let meta = event.meta.as_ref().expect("checked event metadata");
+            sqlx::query(&query)
+                .bind(i64::try_from(meta.block_number)?)
+                .bind(i64::try_from(meta.log_index)?)
+                .bind(meta.address.as_bytes())
+                .bind(meta.block_hash.as_bytes())
+                .bind(meta.transaction_hash.as_bytes())
+                .bind(i64::try_from(meta.transaction_index)?)
+                .bind(Json(&event.data))
+                .execute(&mut *connection)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn decode_event<T>(row: &PgRow) -> Result<Event<T>>
+where
+    T: DeserializeOwned,
+{
+    let bytes = |column: &str| -> Result<Vec<u8>> { Ok(row.try_get(column)?) };
+    let index =
+        |column: &str| -> Result<u64> { Ok(u64::try_from(row.try_get::<i64, _>(column)?)?) };
+    let hash = |column: &str| -> Result<H256> {
+        let bytes = bytes(column)?;
+        ensure!(bytes.len() == 32, "invalid {} length", column);
+        Ok(H256::from_slice(&bytes))
+    };
+
+    let address = bytes("address")?;
+    ensure!(address.len() == 20, "invalid address length");
+    Ok(Event {
+        data: row.try_get::<Json<T>, _>("data")?.0,
+        meta: Some(EventMetadata {
+            address: H160::from_slice(&address),
+            block_hash: hash("block_hash")?,
+            block_number: index("block_number")?,
+            transaction_hash: hash("transaction_hash")?,
+            transaction_index: index("transaction_index")?.try_into()?,
+            log_index: index("log_index")?.try_into()?,
+            transaction_log_index: None,
+            log_type: None,
+        }),
+    })
+}
+
+#[async_trait::async_trait]
+impl<T> EventStoring<T> for PostgresEventStore<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    async fn replace_events(
+        &mut self,
+        events: Vec<Event<T>>,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Result<()> {
+        // Deleting the replaced events and inserting the new ones happens in a
+        // single transaction, so the table is never observed without them.
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE block_number >= $1",
+            self.table,
+        ))
+        .bind(i64::try_from(range.start().to_u64())?)
+        .execute(&mut *transaction)
+        .await?;
+        self.insert_events(&mut transaction, &events).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn append_events(&mut self, events: Vec<Event<T>>) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        self.insert_events(&mut transaction, &events).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn last_event_block(&self) -> Result<u64> {
+        let block: Option<i64> =
+            sqlx::query(&format!("SELECT MAX(block_number) FROM {}", self.table))
+                .fetch_one(&self.pool)
+                .await?
+                .try_get(0)?;
+        block
+            .map(u64::try_from)
+            .transpose()
+            .map_err(|_| anyhow!("negative block number"))
+            .map(Option::unwrap_or_default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::memory::InMemoryEventStore;
+    use serde::Deserialize;
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    struct TestEvent {
+        value: u64,
+    }
+
+    fn event(block_number: u64, log_index: usize) -> Event<TestEvent> {
+        Event {
+            data: TestEvent {
+                value: block_number * 100 + log_index as u64,
+            },
+            meta: Some(EventMetadata {
+                address: H160([1; 20]),
+                block_hash: H256::from_low_u64_be(block_number),
+                block_number,
+                transaction_hash: H256([2; 32]),
+                transaction_index: 0,
+                log_index,
+                transaction_log_index: None,
+                log_type: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn postgres_event_store_roundtrip() {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or_else(|_| "postgresql://localhost/postgres".to_owned());
+        let pool = PgPool::connect(&url).await.unwrap();
+        sqlx::query("DROP TABLE IF EXISTS test_events")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut store = PostgresEventStore::new(pool, "test_events").await.unwrap();
+        assert_eq!(store.last_event_block().await.unwrap(), 0);
+
+        store
+            .append_events(vec![event(1, 0), event(2, 0), event(2, 1)])
+            .await
+            .unwrap();
+        store
+            .replace_events(
+                vec![event(2, 2), event(3, 0)],
+                BlockNumber::Specific(2)..=BlockNumber::Latest(3),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.last_event_block().await.unwrap(), 3);
+
+        let mut restored = InMemoryEventStore::default();
+        store.restore(&mut restored).await.unwrap();
+        assert_eq!(
+            restored
+                .events()
+                .iter()
+                .map(|event| event.data.value)
+                .collect::<Vec<_>>(),
+            vec![100, 202, 300],
+        );
+    }
+}
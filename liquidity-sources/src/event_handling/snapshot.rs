@@ -0,0 +1,202 @@
+//! Snapshots of the state of event handlers.
+//!
+//! Sources with a long event history take a long time to sync. Persisting a
+//! snapshot of the handled events, for example on shutdown, and restoring it at
+//! startup allows event handling to continue where it left off instead.
+
+use super::{
+    indexer::{EventIndexing, IndexedStore},
+    EventHandler, EventRetrieving, EventStoring,
+};
+use crate::current_block::BlockRetrieving;
+use anyhow::{Context as _, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Stores whose contents can be persisted in snapshots.
+pub trait SnapshotStoring {
+    type Snapshot: Serialize + DeserializeOwned;
+
+    /// Returns a snapshot of the stored events that were handled up to and
+    /// including the last handled block, along with the last block whose
+    /// events are all included in the snapshot, which may be earlier.
+    fn snapshot(&self, last_handled_block: u64) -> (u64, Self::Snapshot);
+
+    /// Replaces the stored events with the ones of the snapshot.
+    fn restore(&mut self, snapshot: Self::Snapshot) -> Result<()>;
+}
+
+/// A snapshot of an event handler.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EventHandlerSnapshot<T> {
+    /// The last block whose events are included in the snapshot.
+    pub last_handled_block: u64,
+    /// The snapshot of the store of the event handler.
+    pub store: T,
+}
+
+impl<B, C, S> EventHandler<B, C, S>
+where
+    B: BlockRetrieving,
+    C: EventRetrieving,
+    S: EventStoring<C::Event> + SnapshotStoring,
+{
+    /// Returns a snapshot of the handled events, or `None` if no events were
+    /// handled yet.
+    pub fn snapshot(&self) -> Option<EventHandlerSnapshot<S::Snapshot>> {
+        let (last_handled_block, store) = self.store.snapshot(self.last_handled_block?);
+        Some(EventHandlerSnapshot {
+            last_handled_block,
+            store,
+        })
+    }
+
+    /// Restores the handled events from a snapshot, so that the next update
+    /// continues after the last block of the snapshot instead of syncing all
+    /// events again.
+    pub fn restore(&mut self, snapshot: EventHandlerSnapshot<S::Snapshot>) -> Result<()> {
+        self.store.restore(snapshot.store)?;
+        self.last_handled_block = Some(snapshot.last_handled_block);
+        self.block_hashes = Default::default();
+        Ok(())
+    }
+}
+
+/// The snapshot of an [`IndexedStore`], which only includes its finalized
+/// state since the events that can still be replaced are queried again anyway.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IndexedSnapshot<I> {
+    pub finalized_block: u64,
+    pub state: I,
+}
+
+impl<I> SnapshotStoring for IndexedStore<I>
+where
+    I: EventIndexing + Serialize + DeserializeOwned,
+{
+    type Snapshot = IndexedSnapshot<I>;
+
+    fn snapshot(&self, last_handled_block: u64) -> (u64, Self::Snapshot) {
+        let (finalized_block, state) = self.finalized();
+        (
+            last_handled_block.min(finalized_block),
+            IndexedSnapshot {
+                finalized_block,
+                state: state.clone(),
+            },
+        )
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) -> Result<()> {
+        self.reset(snapshot.state, snapshot.finalized_block);
+        Ok(())
+    }
+}
+
+/// Writes a snapshot to a JSON file.
+pub fn write_snapshot<T>(path: &Path, snapshot: &T) -> Result<()>
+where
+    T: Serialize,
+{
+    // Write to a temporary file first so that the snapshot is never left
+    // partially written when the process is interrupted.
+    let temporary = path.with_extension("tmp");
+    let contents = serde_json::to_vec(snapshot)?;
+    fs::write(&temporary, contents)
+        .and_then(|_| fs::rename(&temporary, path))
+        .with_context(|| format!("failed to write snapshot {}", path.display()))
+}
+
+/// Reads a snapshot from a JSON file, returning `None` if the file does not
+/// exist.
+pub fn read_snapshot<T>(path: &Path) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read snapshot {}", path.display()))
+        }
+    };
+    let snapshot = serde_json::from_slice(&contents)
+        .with_context(|| format!("invalid snapshot {}", path.display()))?;
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::BlockNumber;
+    use ethcontract::{Event, EventMetadata, H160, H256};
+
+    #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+    struct Sum(u32);
+
+    impl EventIndexing for Sum {
+        type Event = u32;
+
+        fn apply_event(&mut self, event: &Event<u32>) {
+            self.0 += event.data;
+        }
+    }
+
+    fn event(block_number: u64, data: u32) -> Event<u32> {
+        Event {
+            data,
+            meta: Some(EventMetadata {
+                address: H160([1; 20]),
+                block_hash: H256::from_low_u64_be(block_number),
+                block_number,
+                transaction_hash: H256([2; 32]),
+                transaction_index: 0,
+                log_index: 0,
+                transaction_log_index: None,
+                log_type: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn restores_indexed_store_from_snapshot_file() {
+        let mut store = IndexedStore::new(Sum::default()).with_rollback_depth(0);
+        store
+            .append_events(vec![event(1, 1), event(2, 2)])
+            .await
+            .unwrap();
+        store
+            .replace_events(
+                vec![event(3, 4)],
+                BlockNumber::Specific(2)..=BlockNumber::Latest(5),
+            )
+            .await
+            .unwrap();
+
+        // Only the events before the replaced range are final.
+        let (block, snapshot) = store.snapshot(5);
+        assert_eq!(block, 1);
+        let path = std::env::temp_dir().join(format!("event-snapshot-{}", std::process::id()));
+        write_snapshot(&path, &snapshot).unwrap();
+        let snapshot = read_snapshot(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(read_snapshot::<IndexedSnapshot<Sum>>(&path)
+            .unwrap()
+            .is_none());
+
+        let mut restored = IndexedStore::new(Sum::default());
+        restored.restore(snapshot).unwrap();
+        assert_eq!(restored.state(), &Sum(1));
+        assert_eq!(restored.last_event_block().await.unwrap(), 1);
+
+        // Updating from the snapshot block applies the remaining events once.
+        restored
+            .replace_events(
+                vec![event(1, 1), event(3, 4)],
+                BlockNumber::Specific(1)..=BlockNumber::Latest(6),
+            )
+            .await
+            .unwrap();
+        assert_eq!(restored.state(), store.state());
+    }
+}
@@ -0,0 +1,291 @@
+//! Fixed point arithmetic shared by the swap math of the liquidity sources.
+//!
+//! Pools compute with unsigned fixed point numbers in a few formats: square
+//! root prices of concentrated liquidity pools are Q64.96 numbers, fee growth
+//! is tracked as Q128.128 and most other contracts use 18 decimals. Quotes
+//! only match on-chain swaps when every operation rounds in the same direction
+//! as the contracts, so all operations take an explicit [`Rounding`].
+//!
+//! Multiplications and divisions are computed with full precision for the
+//! intermediate product, like the `FullMath.mulDiv` library of Uniswap V3,
+//! and return `None` if the result doesn't fit into 256 bits or when dividing
+//! by zero. Math that has to fail whenever the contracts overflow, like the
+//! Balancer `FixedPoint` library, needs to check intermediate products itself.
+
+use ethcontract::U256;
+use primitive_types::U512;
+use std::{convert::TryFrom, marker::PhantomData};
+
+/// How to round results that can't be represented exactly.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rounding {
+    /// Towards negative infinity.
+    Down,
+    /// Towards positive infinity.
+    Up,
+    /// To the nearest value, with ties away from zero.
+    Nearest,
+}
+
+impl Rounding {
+    /// Returns `Up` or `Down` for the `roundUp` flags of the contracts' math
+    /// libraries.
+    pub fn up_if(round_up: bool) -> Self {
+        if round_up {
+            Self::Up
+        } else {
+            Self::Down
+        }
+    }
+}
+
+/// Computes `a * b / denominator` with full precision for the intermediate
+/// product.
+pub fn mul_div(a: U256, b: U256, denominator: U256, rounding: Rounding) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let denominator = U512::from(denominator);
+    let (quotient, remainder) = a.full_mul(b).div_mod(denominator);
+    let quotient = U256::try_from(quotient).ok()?;
+    if rounds_up(remainder, denominator, rounding) {
+        quotient.checked_add(U256::one())
+    } else {
+        Some(quotient)
+    }
+}
+
+/// Computes `a / b`.
+pub fn div(a: U256, b: U256, rounding: Rounding) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    let (quotient, remainder) = a.div_mod(b);
+    if rounds_up(U512::from(remainder), U512::from(b), rounding) {
+        quotient.checked_add(U256::one())
+    } else {
+        Some(quotient)
+    }
+}
+
+/// Returns `true` if a quotient with the remainder needs to be incremented.
+fn rounds_up(remainder: U512, denominator: U512, rounding: Rounding) -> bool {
+    match rounding {
+        _ if remainder.is_zero() => false,
+        Rounding::Down => false,
+        Rounding::Up => true,
+        Rounding::Nearest => remainder >= denominator - remainder,
+    }
+}
+
+/// The format of a fixed point number.
+pub trait Scale {
+    /// Returns the raw value representing one.
+    fn one() -> U256;
+}
+
+/// Numbers with 96 fractional bits, like square root prices.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct X96;
+
+impl Scale for X96 {
+    fn one() -> U256 {
+        U256::one() << 96
+    }
+}
+
+/// Numbers with 128 fractional bits, like fee growth.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct X128;
+
+impl Scale for X128 {
+    fn one() -> U256 {
+        U256::one() << 128
+    }
+}
+
+/// Numbers with 18 decimals.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct E18;
+
+impl Scale for E18 {
+    fn one() -> U256 {
+        U256::exp10(18)
+    }
+}
+
+/// An unsigned fixed point number.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FixedPoint<S>(U256, PhantomData<S>);
+
+/// A Q64.96 number, as used for square root prices.
+pub type Q64x96 = FixedPoint<X96>;
+/// A Q128.128 number, as used for fee growth.
+pub type Q128x128 = FixedPoint<X128>;
+/// A number with 18 decimals, often called a wad.
+pub type Wad = FixedPoint<E18>;
+
+impl<S: Scale> FixedPoint<S> {
+    pub fn from_raw(raw: U256) -> Self {
+        Self(raw, PhantomData)
+    }
+
+    pub fn raw(self) -> U256 {
+        self.0
+    }
+
+    pub fn zero() -> Self {
+        Self::from_raw(U256::zero())
+    }
+
+    pub fn one() -> Self {
+        Self::from_raw(S::one())
+    }
+
+    /// Returns the number for an integer, or `None` if it is out of range.
+    pub fn from_integer(value: U256) -> Option<Self> {
+        Some(Self::from_raw(value.checked_mul(S::one())?))
+    }
+
+    /// Returns the number closest to `numerator / denominator`.
+    pub fn from_ratio(numerator: U256, denominator: U256, rounding: Rounding) -> Option<Self> {
+        Some(Self::from_raw(mul_div(
+            numerator,
+            S::one(),
+            denominator,
+            rounding,
+        )?))
+    }
+
+    /// Returns the integer part of the number with the rounding.
+    pub fn to_integer(self, rounding: Rounding) -> U256 {
+        div(self.0, S::one(), rounding).expect("rounding a fixed point number never overflows")
+    }
+
+    pub fn mul(self, other: Self, rounding: Rounding) -> Option<Self> {
+        Some(Self::from_raw(mul_div(
+            self.0,
+            other.0,
+            S::one(),
+            rounding,
+        )?))
+    }
+
+    pub fn div(self, other: Self, rounding: Rounding) -> Option<Self> {
+        Some(Self::from_raw(mul_div(
+            self.0,
+            S::one(),
+            other.0,
+            rounding,
+        )?))
+    }
+
+    /// Multiplies an integer, like a token amount, with the number.
+    pub fn mul_integer(self, value: U256, rounding: Rounding) -> Option<U256> {
+        mul_div(value, self.0, S::one(), rounding)
+    }
+
+    /// Divides an integer, like a token amount, by the number.
+    pub fn div_integer(self, value: U256, rounding: Rounding) -> Option<U256> {
+        mul_div(value, S::one(), self.0, rounding)
+    }
+
+    pub fn to_f64_lossy(self) -> f64 {
+        self.0.to_f64_lossy() / S::one().to_f64_lossy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_with_rounding() {
+        let mul_div = |a: u64, b: u64, denominator: u64, rounding| {
+            mul_div(a.into(), b.into(), denominator.into(), rounding).map(|r| r.as_u64())
+        };
+        assert_eq!(mul_div(7, 5, 10, Rounding::Down), Some(3));
+        assert_eq!(mul_div(7, 5, 10, Rounding::Up), Some(4));
+        assert_eq!(mul_div(7, 5, 10, Rounding::Nearest), Some(4));
+        assert_eq!(mul_div(7, 3, 10, Rounding::Nearest), Some(2));
+        assert_eq!(mul_div(6, 5, 10, Rounding::Up), Some(3));
+        assert_eq!(mul_div(1, 1, 0, Rounding::Down), None);
+
+        // The intermediate product may exceed 256 bits, but not the result.
+        assert_eq!(
+            super::mul_div(U256::MAX, U256::MAX, U256::MAX, Rounding::Up),
+            Some(U256::MAX)
+        );
+        assert_eq!(
+            super::mul_div(U256::MAX, 2.into(), 3.into(), Rounding::Up),
+            Some(U256::MAX / 3 * 2)
+        );
+        assert_eq!(
+            super::mul_div(U256::MAX, 2.into(), 1.into(), Rounding::Down),
+            None
+        );
+        assert_eq!(
+            super::mul_div(U256::MAX, U256::MAX - 1, U256::MAX, Rounding::Up),
+            Some(U256::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn div_with_rounding() {
+        assert_eq!(div(7.into(), 2.into(), Rounding::Down), Some(3.into()));
+        assert_eq!(div(7.into(), 2.into(), Rounding::Up), Some(4.into()));
+        assert_eq!(div(7.into(), 2.into(), Rounding::Nearest), Some(4.into()));
+        assert_eq!(div(8.into(), 3.into(), Rounding::Nearest), Some(3.into()));
+        assert_eq!(div(7.into(), 0.into(), Rounding::Down), None);
+        assert_eq!(
+            div(U256::MAX, 2.into(), Rounding::Nearest),
+            Some(U256::one() << 255)
+        );
+    }
+
+    #[test]
+    fn binary_fixed_point_numbers() {
+        let half = Q64x96::from_ratio(1.into(), 2.into(), Rounding::Down).unwrap();
+        assert_eq!(half.raw(), U256::one() << 95);
+        assert_eq!(half.mul(half, Rounding::Down).unwrap().to_f64_lossy(), 0.25);
+        assert_eq!(
+            Q64x96::one().div(half, Rounding::Down),
+            Q64x96::from_integer(2.into())
+        );
+        assert_eq!(half.mul_integer(3.into(), Rounding::Down), Some(1.into()));
+        assert_eq!(half.mul_integer(3.into(), Rounding::Up), Some(2.into()));
+        assert_eq!(half.div_integer(3.into(), Rounding::Down), Some(6.into()));
+        assert_eq!(half.to_integer(Rounding::Nearest), 1.into());
+        assert_eq!(half.to_integer(Rounding::Down), 0.into());
+
+        assert_eq!(Q128x128::one().raw(), U256::one() << 128);
+        assert!(Q128x128::from_integer(U256::one() << 128).is_none());
+        assert_eq!(Q128x128::zero().div(Q128x128::zero(), Rounding::Down), None);
+    }
+
+    #[test]
+    fn decimal_fixed_point_numbers() {
+        let third = Wad::from_ratio(1.into(), 3.into(), Rounding::Down).unwrap();
+        assert_eq!(third.raw(), U256::from(333_333_333_333_333_333u64));
+        let third_up = Wad::from_ratio(1.into(), 3.into(), Rounding::Up).unwrap();
+        assert_eq!(third_up.raw(), U256::from(333_333_333_333_333_334u64));
+        assert_eq!(
+            third.mul(Wad::from_integer(3.into()).unwrap(), Rounding::Up),
+            Some(Wad::from_raw(999_999_999_999_999_999u64.into()))
+        );
+        assert_eq!(
+            Wad::one()
+                .div(third_up, Rounding::Up)
+                .unwrap()
+                .to_integer(Rounding::Down),
+            2.into()
+        );
+        assert_eq!(
+            Wad::one()
+                .div(third, Rounding::Up)
+                .unwrap()
+                .to_integer(Rounding::Up),
+            4.into()
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Tracking of EIP-1559 gas prices, for ranking routes by their gas costs.
+//!
+//! The base fee of the next block is known from the current block while the
+//! priority fee that needs to be paid for inclusion is estimated from the
+//! priority fees paid in recent blocks, as reported by `eth_feeHistory`.
+
+use crate::{current_block::CurrentBlockStream, Web3};
+use anyhow::{Context as _, Result};
+use primitive_types::U256;
+use tokio::sync::watch;
+use web3::types::{BlockNumber, FeeHistory};
+
+/// Configuration of a gas price stream.
+#[derive(Clone, Debug)]
+pub struct GasPriceConfig {
+    /// The number of recent blocks to estimate priority fees from.
+    pub block_count: u64,
+    /// The percentiles of the priority fees paid in a block, from 0 to 100,
+    /// for which estimates are computed.
+    pub percentiles: Vec<f64>,
+}
+
+impl Default for GasPriceConfig {
+    fn default() -> Self {
+        Self {
+            block_count: 20,
+            percentiles: vec![10., 50., 90.],
+        }
+    }
+}
+
+/// The gas price estimate for the block following the current block.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GasPriceEstimate {
+    /// The number of the current block.
+    pub block: u64,
+    /// The base fee of the current block.
+    pub base_fee: U256,
+    /// The base fee of the next block.
+    pub next_base_fee: U256,
+    /// The estimated priority fees for each configured percentile, in the
+    /// order of the configuration.
+    pub priority_fees: Vec<(f64, U256)>,
+}
+
+impl GasPriceEstimate {
+    /// Returns the estimated priority fee for a configured percentile.
+    pub fn priority_fee(&self, percentile: f64) -> Option<U256> {
+        self.priority_fees
+            .iter()
+            .find(|(p, _)| *p == percentile)
+            .map(|(_, fee)| *fee)
+    }
+
+    /// Returns the estimated price per gas of a transaction in the next block
+    /// paying the priority fee of a configured percentile.
+    pub fn effective_gas_price(&self, percentile: f64) -> Option<U256> {
+        Some(
+            self.next_base_fee
+                .saturating_add(self.priority_fee(percentile)?),
+        )
+    }
+}
+
+pub type GasPriceStream = watch::Receiver<GasPriceEstimate>;
+
+/// Creates a cloneable stream that yields a new gas price estimate for every
+/// block of the current block stream.
+///
+/// If the fee history cannot be retrieved for a block, the previous priority
+/// fee estimates are kept while the base fees are updated from the block.
+pub async fn gas_price_stream(
+    web3: Web3,
+    mut block_stream: CurrentBlockStream,
+    config: GasPriceConfig,
+) -> Result<GasPriceStream> {
+    let block = *block_stream.borrow();
+    let first_estimate = estimate(&web3, &config, block.number).await?;
+    let (sender, receiver) = watch::channel(first_estimate);
+
+    let update_future = async move {
+        while block_stream.changed().await.is_ok() {
+            let block = *block_stream.borrow();
+            let estimate = match estimate(&web3, &config, block.number).await {
+                Ok(estimate) => estimate,
+                Err(err) => {
+                    tracing::warn!("failed to estimate gas price: {:?}", err);
+                    let base_fee = block.base_fee.unwrap_or_default();
+                    GasPriceEstimate {
+                        block: block.number,
+                        base_fee,
+                        next_base_fee: base_fee,
+                        priority_fees: sender.borrow().priority_fees.clone(),
+                    }
+                }
+            };
+            if sender.send(estimate).is_err() {
+                break;
+            }
+        }
+    };
+
+    tokio::task::spawn(update_future);
+    Ok(receiver)
+}
+
+async fn estimate(web3: &Web3, config: &GasPriceConfig, block: u64) -> Result<GasPriceEstimate> {
+    let history = web3
+        .eth()
+        .fee_history(
+            config.block_count.into(),
+            BlockNumber::Number(block.into()),
+            Some(config.percentiles.clone()),
+        )
+        .await
+        .context("failed to get fee history")?;
+    estimate_from_history(block, &config.percentiles, &history)
+}
+
+/// Estimates the gas price from the fee history, using the median of the
+/// priority fees paid at each percentile over the recent blocks.
+fn estimate_from_history(
+    block: u64,
+    percentiles: &[f64],
+    history: &FeeHistory,
+) -> Result<GasPriceEstimate> {
+    // The base fees include the one of the block after the newest block.
+    let (next_base_fee, base_fees) = history
+        .base_fee_per_gas
+        .split_last()
+        .context("fee history without base fees")?;
+    let base_fee = base_fees.last().copied().unwrap_or(*next_base_fee);
+    let rewards = history.reward.as_deref().unwrap_or_default();
+    let priority_fees = percentiles
+        .iter()
+        .enumerate()
+        .map(|(i, percentile)| {
+            let mut fees = rewards
+                .iter()
+                .filter_map(|block| block.get(i).copied())
+                .collect::<Vec<_>>();
+            fees.sort_unstable();
+            let median = fees.get(fees.len() / 2).copied().unwrap_or_default();
+            (*percentile, median)
+        })
+        .collect();
+    Ok(GasPriceEstimate {
+        block,
+        base_fee,
+        next_base_fee: *next_base_fee,
+        priority_fees,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        current_block::{current_block_stream, BlockStreamConfig},
+        transport::create_env_test_transport,
+    };
+
+    #[test]
+    fn estimates_median_priority_fees() {
+        let history = FeeHistory {
+            oldest_block: BlockNumber::Number(8.into()),
+            base_fee_per_gas: vec![100.into(), 110.into(), 120.into(), 115.into()],
+            gas_used_ratio: vec![0.9, 0.9, 0.3],
+            reward: Some(vec![
+                vec![1.into(), 10.into()],
+                vec![3.into(), 30.into()],
+                vec![2.into(), 20.into()],
+            ]),
+        };
+        let estimate = estimate_from_history(10, &[10., 90.], &history).unwrap();
+        assert_eq!(
+            estimate,
+            GasPriceEstimate {
+                block: 10,
+                base_fee: 120.into(),
+                next_base_fee: 115.into(),
+                priority_fees: vec![(10., 2.into()), (90., 20.into())],
+            }
+        );
+        assert_eq!(estimate.effective_gas_price(90.), Some(135.into()));
+        assert_eq!(estimate.effective_gas_price(50.), None);
+
+        let empty = FeeHistory {
+            base_fee_per_gas: Vec::new(),
+            ..history
+        };
+        assert!(estimate_from_history(10, &[10.], &empty).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn mainnet_gas_price_stream() {
+        let web3 = Web3::new(create_env_test_transport());
+        let blocks = current_block_stream(web3.clone(), BlockStreamConfig::default())
+            .await
+            .unwrap();
+        let mut stream = gas_price_stream(web3, blocks, Default::default())
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            println!("{:?}", *stream.borrow());
+            stream.changed().await.unwrap();
+        }
+    }
+}
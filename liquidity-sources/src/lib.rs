@@ -1,18 +1,26 @@
 #[macro_use]
 pub mod macros;
 
+pub mod bad_token;
 pub mod baseline_solver;
+pub mod chain_config;
 pub mod conversions;
 pub mod current_block;
 pub mod ethcontract_error;
 pub mod event_handling;
+pub mod fixed_point;
+pub mod gas_price;
+pub mod liquidity;
 pub mod maintenance;
 pub mod metrics;
 pub mod recent_block_cache;
 pub mod sources;
 pub mod subgraph;
+pub mod token_filter;
 pub mod token_info;
+pub mod token_list;
 pub mod token_pair;
+pub mod transfer_fee;
 #[cfg(test)]
 #[allow(missing_docs)]
 pub mod transport;
@@ -21,6 +29,7 @@ pub mod u256_decimal;
 #[cfg(test)]
 #[allow(missing_docs)]
 mod test {
+    pub mod amm;
     pub mod test_transport;
     pub mod tokens;
 }
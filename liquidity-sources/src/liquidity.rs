@@ -0,0 +1,517 @@
+//! A unified view over the liquidity of heterogeneous sources.
+//!
+//! Each liquidity source exposes its own fetching trait and pool types. The
+//! `LiquidityCollector` owns a set of these fetchers and fetches from all of
+//! them at once, returning the results as a single `Liquidity` type so that
+//! consumers only need to integrate with one interface.
+
+pub mod graph;
+pub mod interaction;
+pub mod multichain;
+pub mod quote;
+pub mod registry;
+pub mod slippage;
+pub mod verification;
+
+use crate::{
+    baseline_solver::BaselineSolvable,
+    recent_block_cache::Block,
+    sources::{
+        ambient,
+        balancer_v2::pool_fetching::{
+            BalancerPoolFetching, ComposableStablePool, GyroEclpPool, LinearPool, StablePool,
+            WeightedPool,
+        },
+        curve, erc4626, integral, iziswap, maker_psm, mstable, native, rfq, solidly, synthetix,
+        uniswap_v2, uniswap_v3, uniswap_v4, wombat, wsteth, BaselineSource,
+    },
+    token_filter::TokenFilter,
+    token_pair::TokenPair,
+};
+use anyhow::Result;
+use ethcontract::{H160, U256};
+use futures::future::{self, BoxFuture, FutureExt as _};
+use std::{collections::HashSet, sync::Arc};
+
+/// Filling a limit order requires verifying its signature and transferring
+/// both tokens, which costs about as much as an AMM swap.
+const LIMIT_ORDER_GAS_COST: usize = 110_000;
+
+/// Liquidity from any of the supported sources.
+#[derive(Clone, Debug)]
+pub enum Liquidity {
+    ConstantProduct(uniswap_v2::pool_fetching::Pool),
+    WeightedProduct(WeightedPool),
+    Stable(StableLiquidity),
+    /// Balancer linear pools, trading a token with its wrapped yield bearing
+    /// version and the pool token at a fixed rate within their target range.
+    Linear(LinearPool),
+    ConcentratedLiquidity(uniswap_v3::pool_fetching::PoolInfo),
+    /// Balancer Gyroscope E-CLP pools, concentrating liquidity on an ellipse.
+    EllipticConcentratedLiquidity(GyroEclpPool),
+    LimitOrder(LimitOrder),
+    QuoteOnly(QuoteOnlyLiquidity),
+}
+
+/// Liquidity following the StableSwap invariant.
+#[derive(Clone, Debug)]
+pub enum StableLiquidity {
+    Balancer(StablePool),
+    BalancerComposable(ComposableStablePool),
+    Curve(curve::pool_fetching::Pool),
+}
+
+/// Liquidity that can be used for quoting, but whose swaps can't be encoded
+/// as interactions or verified yet.
+#[derive(Clone, Debug)]
+pub enum QuoteOnlyLiquidity {
+    /// Algebra pools, which share the concentrated liquidity math of Uniswap
+    /// V3 pools but can't be swapped through the Uniswap V3 router.
+    Algebra(uniswap_v3::pool_fetching::PoolInfo),
+    Ambient(ambient::pool_fetching::Pool),
+    Erc4626(erc4626::Pool),
+    Integral(integral::pool_fetching::Pool),
+    IZiSwap(iziswap::pool_fetching::Pool),
+    MakerPsm(maker_psm::Pool),
+    MStable(mstable::pool_fetching::Pool),
+    Native(native::Pool),
+    /// Saddle pools, which share the StableSwap math of Curve pools but have a
+    /// different swap interface.
+    Saddle(curve::pool_fetching::Pool),
+    Solidly(solidly::pool_fetching::Pool),
+    Synthetix(synthetix::pool_fetching::Pool),
+    UniswapV4(uniswap_v4::pool_fetching::Pool),
+    Wombat(wombat::pool_fetching::Pool),
+    WstEth(wsteth::Pool),
+}
+
+/// An order to sell up to a fixed amount of one token for a limit price in
+/// another token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LimitOrder {
+    /// An identifier for the order that is unique to its source.
+    pub id: String,
+    pub sell_token: H160,
+    pub buy_token: H160,
+    pub sell_amount: U256,
+    pub buy_amount: U256,
+    /// Whether or not the order can be executed for a fraction of its amounts
+    /// at the same limit price.
+    pub partially_fillable: bool,
+}
+
+impl QuoteOnlyLiquidity {
+    /// Returns the source of the liquidity.
+    pub fn source(&self) -> BaselineSource {
+        match self {
+            QuoteOnlyLiquidity::Algebra(_) => BaselineSource::Algebra,
+            QuoteOnlyLiquidity::Ambient(_) => BaselineSource::Ambient,
+            QuoteOnlyLiquidity::Erc4626(_) => BaselineSource::Erc4626,
+            QuoteOnlyLiquidity::Integral(_) => BaselineSource::Integral,
+            QuoteOnlyLiquidity::IZiSwap(_) => BaselineSource::IZiSwap,
+            QuoteOnlyLiquidity::MakerPsm(_) => BaselineSource::MakerPsm,
+            QuoteOnlyLiquidity::MStable(_) => BaselineSource::MStable,
+            QuoteOnlyLiquidity::Native(_) => BaselineSource::Native,
+            QuoteOnlyLiquidity::Saddle(_) => BaselineSource::Saddle,
+            QuoteOnlyLiquidity::Solidly(_) => BaselineSource::Solidly,
+            QuoteOnlyLiquidity::Synthetix(_) => BaselineSource::Synthetix,
+            QuoteOnlyLiquidity::UniswapV4(_) => BaselineSource::UniswapV4,
+            QuoteOnlyLiquidity::Wombat(_) => BaselineSource::Wombat,
+            QuoteOnlyLiquidity::WstEth(_) => BaselineSource::WstEth,
+        }
+    }
+}
+
+impl Liquidity {
+    fn as_baseline_solvable(&self) -> &dyn BaselineSolvable {
+        match self {
+            Liquidity::ConstantProduct(pool) => pool,
+            Liquidity::WeightedProduct(pool) => pool,
+            Liquidity::Stable(StableLiquidity::Balancer(pool)) => pool,
+            Liquidity::Stable(StableLiquidity::BalancerComposable(pool)) => pool,
+            Liquidity::Stable(StableLiquidity::Curve(pool)) => pool,
+            Liquidity::Linear(pool) => pool,
+            Liquidity::ConcentratedLiquidity(pool) => pool,
+            Liquidity::EllipticConcentratedLiquidity(pool) => pool,
+            Liquidity::LimitOrder(order) => order,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Algebra(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Ambient(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Erc4626(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Integral(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::IZiSwap(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::MakerPsm(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::MStable(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Native(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Saddle(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Solidly(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Synthetix(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::UniswapV4(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::Wombat(pool)) => pool,
+            Liquidity::QuoteOnly(QuoteOnlyLiquidity::WstEth(pool)) => pool,
+        }
+    }
+}
+
+impl BaselineSolvable for Liquidity {
+    fn get_amount_out(&self, out_token: H160, input: (U256, H160)) -> Option<U256> {
+        self.as_baseline_solvable().get_amount_out(out_token, input)
+    }
+
+    fn get_amount_in(&self, in_token: H160, out: (U256, H160)) -> Option<U256> {
+        self.as_baseline_solvable().get_amount_in(in_token, out)
+    }
+
+    fn get_amounts_out(&self, out_token: H160, input: (&[U256], H160)) -> Vec<Option<U256>> {
+        self.as_baseline_solvable()
+            .get_amounts_out(out_token, input)
+    }
+
+    fn gas_cost(&self) -> usize {
+        self.as_baseline_solvable().gas_cost()
+    }
+
+    fn max_amount_in(&self, in_token: H160, out_token: H160) -> Option<U256> {
+        self.as_baseline_solvable()
+            .max_amount_in(in_token, out_token)
+    }
+}
+
+/// Trading with a limit order buys its sell token for its buy token at the
+/// limit price of the order, which is filled like an RFQ quote.
+impl BaselineSolvable for LimitOrder {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        if in_token != self.buy_token || out_token != self.sell_token {
+            return None;
+        }
+        rfq::fill_amount_out(
+            in_amount,
+            (self.buy_amount, self.sell_amount),
+            self.partially_fillable,
+        )
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        if in_token != self.buy_token || out_token != self.sell_token {
+            return None;
+        }
+        rfq::fill_amount_in(
+            out_amount,
+            (self.buy_amount, self.sell_amount),
+            self.partially_fillable,
+        )
+    }
+
+    fn gas_cost(&self) -> usize {
+        LIMIT_ORDER_GAS_COST
+    }
+
+    fn max_amount_in(&self, in_token: H160, out_token: H160) -> Option<U256> {
+        if in_token != self.buy_token || out_token != self.sell_token {
+            return None;
+        }
+        Some(self.buy_amount)
+    }
+}
+
+#[async_trait::async_trait]
+pub trait LimitOrderFetching: Send + Sync {
+    async fn fetch(
+        &self,
+        token_pairs: HashSet<TokenPair>,
+        at_block: Block,
+    ) -> Result<Vec<LimitOrder>>;
+}
+
+/// Fetching liquidity that is already in its unified representation, used for
+/// sources that are implemented outside of this crate.
+#[async_trait::async_trait]
+pub trait LiquidityFetching: Send + Sync {
+    async fn fetch(
+        &self,
+        token_pairs: HashSet<TokenPair>,
+        at_block: Block,
+    ) -> Result<Vec<Liquidity>>;
+}
+
+/// Fetches liquidity from a set of source specific fetchers.
+#[derive(Default)]
+pub struct LiquidityCollector {
+    pub constant_product: Vec<Arc<dyn uniswap_v2::pool_fetching::PoolFetching>>,
+    pub balancer: Vec<Arc<dyn BalancerPoolFetching>>,
+    pub curve: Vec<Arc<dyn curve::pool_fetching::PoolFetching>>,
+    pub concentrated_liquidity: Vec<Arc<dyn uniswap_v3::pool_fetching::PoolFetching>>,
+    pub limit_orders: Vec<Arc<dyn LimitOrderFetching>>,
+    pub algebra: Vec<Arc<dyn uniswap_v3::pool_fetching::PoolFetching>>,
+    pub ambient: Vec<Arc<dyn ambient::pool_fetching::PoolFetching>>,
+    pub erc4626: Vec<Arc<dyn erc4626::PoolFetching>>,
+    pub integral: Vec<Arc<dyn integral::pool_fetching::PoolFetching>>,
+    pub iziswap: Vec<Arc<dyn iziswap::pool_fetching::PoolFetching>>,
+    pub maker_psm: Vec<Arc<dyn maker_psm::PoolFetching>>,
+    pub mstable: Vec<Arc<dyn mstable::pool_fetching::PoolFetching>>,
+    pub native: Vec<Arc<dyn native::PoolFetching>>,
+    pub saddle: Vec<Arc<dyn curve::pool_fetching::PoolFetching>>,
+    pub solidly: Vec<Arc<dyn solidly::pool_fetching::PoolFetching>>,
+    pub synthetix: Vec<Arc<dyn synthetix::pool_fetching::PoolFetching>>,
+    pub uniswap_v4: Vec<Arc<dyn uniswap_v4::pool_fetching::PoolFetching>>,
+    pub wombat: Vec<Arc<dyn wombat::pool_fetching::PoolFetching>>,
+    pub wsteth: Vec<Arc<dyn wsteth::PoolFetching>>,
+    /// Fetchers for sources that are registered externally, see
+    /// [`registry::SourceRegistry`].
+    pub external: Vec<Arc<dyn LiquidityFetching>>,
+    /// The filter of the tokens whose liquidity is used, applied to all
+    /// sources, see [`crate::token_filter`].
+    pub token_filter: Option<Arc<TokenFilter>>,
+}
+
+impl LiquidityCollector {
+    /// Fetches the liquidity for the specified token pairs from all sources.
+    ///
+    /// Pairs with denied or unlisted tokens are not fetched. Liquidity that
+    /// can trade a denied token, like a Balancer pool that was
+    /// fetched for another pair of its tokens, is dropped entirely, while
+    /// liquidity of unlisted tokens is kept for trading the listed ones.
+    ///
+    /// Fails if any of the sources fail to fetch their liquidity.
+    pub async fn fetch(
+        &self,
+        token_pairs: HashSet<TokenPair>,
+        at_block: Block,
+    ) -> Result<Vec<Liquidity>> {
+        let token_pairs = match &self.token_filter {
+            Some(filter) => filter.filter_pairs(token_pairs),
+            None => token_pairs,
+        };
+
+        let mut fetches: Vec<BoxFuture<Result<Vec<Liquidity>>>> = Vec::new();
+        for fetcher in &self.constant_product {
+            let pools = fetcher.fetch(token_pairs.clone(), at_block);
+            fetches.push(collect(pools, Liquidity::ConstantProduct));
+        }
+        for fetcher in &self.balancer {
+            let pools = fetcher.fetch(token_pairs.clone(), at_block);
+            fetches.push(
+                async move {
+                    let pools = pools.await?;
+                    Ok(pools
+                        .weighted_pools
+                        .into_iter()
+                        .map(Liquidity::WeightedProduct)
+                        .chain(
+                            pools
+                                .stable_pools
+                                .into_iter()
+                                .map(|pool| Liquidity::Stable(StableLiquidity::Balancer(pool))),
+                        )
+                        .chain(pools.composable_stable_pools.into_iter().map(|pool| {
+                            Liquidity::Stable(StableLiquidity::BalancerComposable(pool))
+                        }))
+                        .chain(pools.linear_pools.into_iter().map(Liquidity::Linear))
+                        .chain(
+                            pools
+                                .gyro_eclp_pools
+                                .into_iter()
+                                .map(Liquidity::EllipticConcentratedLiquidity),
+                        )
+                        .collect())
+                }
+                .boxed(),
+            );
+        }
+        for fetcher in &self.curve {
+            let pools = fetcher.fetch(token_pairs.clone(), at_block);
+            fetches.push(collect(pools, |pool| {
+                Liquidity::Stable(StableLiquidity::Curve(pool))
+            }));
+        }
+        for fetcher in &self.concentrated_liquidity {
+            // Concentrated liquidity pools are fetched from an indexed cache
+            // that is not block specific.
+            let pools = fetcher.fetch(&token_pairs);
+            fetches.push(collect(pools, Liquidity::ConcentratedLiquidity));
+        }
+        for fetcher in &self.algebra {
+            let pools = fetcher.fetch(&token_pairs);
+            fetches.push(collect(pools, |pool| {
+                Liquidity::QuoteOnly(QuoteOnlyLiquidity::Algebra(pool))
+            }));
+        }
+        for fetcher in &self.limit_orders {
+            let orders = fetcher.fetch(token_pairs.clone(), at_block);
+            fetches.push(collect(orders, Liquidity::LimitOrder));
+        }
+        macro_rules! collect_quote_only {
+            ($fetchers:expr, $variant:ident) => {
+                for fetcher in $fetchers {
+                    let pools = fetcher.fetch(token_pairs.clone(), at_block);
+                    fetches.push(collect(pools, |pool| {
+                        Liquidity::QuoteOnly(QuoteOnlyLiquidity::$variant(pool))
+                    }));
+                }
+            };
+        }
+        collect_quote_only!(&self.ambient, Ambient);
+        collect_quote_only!(&self.erc4626, Erc4626);
+        collect_quote_only!(&self.integral, Integral);
+        collect_quote_only!(&self.iziswap, IZiSwap);
+        collect_quote_only!(&self.maker_psm, MakerPsm);
+        collect_quote_only!(&self.mstable, MStable);
+        collect_quote_only!(&self.native, Native);
+        collect_quote_only!(&self.saddle, Saddle);
+        collect_quote_only!(&self.solidly, Solidly);
+        collect_quote_only!(&self.synthetix, Synthetix);
+        collect_quote_only!(&self.uniswap_v4, UniswapV4);
+        collect_quote_only!(&self.wombat, Wombat);
+        collect_quote_only!(&self.wsteth, WstEth);
+        for fetcher in &self.external {
+            fetches.push(fetcher.fetch(token_pairs.clone(), at_block));
+        }
+
+        let results = future::try_join_all(fetches).await?;
+        let liquidity = results.into_iter().flatten();
+        Ok(match &self.token_filter {
+            Some(filter) => liquidity
+                .filter(|liquidity| filter.allows_liquidity(liquidity))
+                .collect(),
+            None => liquidity.collect(),
+        })
+    }
+}
+
+fn collect<'a, T>(
+    fetch: impl std::future::Future<Output = Result<Vec<T>>> + Send + 'a,
+    into_liquidity: impl Fn(T) -> Liquidity + Send + 'a,
+) -> BoxFuture<'a, Result<Vec<Liquidity>>> {
+    async move { Ok(fetch.await?.into_iter().map(into_liquidity).collect()) }.boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::balancer_v2::pool_fetching::{
+        FetchedBalancerPools, MockBalancerPoolFetching,
+    };
+    use anyhow::anyhow;
+    use maplit::hashset;
+    use num::rational::Ratio;
+
+    struct FakeConstantProductFetcher(Result<Vec<uniswap_v2::pool_fetching::Pool>, String>);
+
+    #[async_trait::async_trait]
+    impl uniswap_v2::pool_fetching::PoolFetching for FakeConstantProductFetcher {
+        async fn fetch(
+            &self,
+            _: HashSet<TokenPair>,
+            _: Block,
+        ) -> Result<Vec<uniswap_v2::pool_fetching::Pool>> {
+            self.0.clone().map_err(|err| anyhow!(err))
+        }
+    }
+
+    struct FakeLimitOrderFetcher(Vec<LimitOrder>);
+
+    #[async_trait::async_trait]
+    impl LimitOrderFetching for FakeLimitOrderFetcher {
+        async fn fetch(&self, _: HashSet<TokenPair>, _: Block) -> Result<Vec<LimitOrder>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    struct FakeNativeFetcher(native::Pool);
+
+    #[async_trait::async_trait]
+    impl native::PoolFetching for FakeNativeFetcher {
+        async fn fetch(&self, _: HashSet<TokenPair>, _: Block) -> Result<Vec<native::Pool>> {
+            Ok(vec![self.0])
+        }
+    }
+
+    fn token_pairs() -> HashSet<TokenPair> {
+        hashset! { TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap() }
+    }
+
+    #[tokio::test]
+    async fn collects_liquidity_from_all_sources() {
+        let pool = uniswap_v2::pool_fetching::Pool {
+            tokens: TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap(),
+            reserves: (13, 37),
+            fee: Ratio::new(3, 1000),
+        };
+        let order = LimitOrder {
+            id: "order".to_owned(),
+            sell_token: H160([1; 20]),
+            buy_token: H160([2; 20]),
+            sell_amount: 1_000.into(),
+            buy_amount: 2_000.into(),
+            partially_fillable: false,
+        };
+        let mut balancer = MockBalancerPoolFetching::new();
+        balancer
+            .expect_fetch()
+            .returning(|_, _| Ok(FetchedBalancerPools::default()));
+
+        let collector = LiquidityCollector {
+            constant_product: vec![Arc::new(FakeConstantProductFetcher(Ok(vec![pool])))],
+            balancer: vec![Arc::new(balancer)],
+            limit_orders: vec![Arc::new(FakeLimitOrderFetcher(vec![order.clone()]))],
+            ..Default::default()
+        };
+
+        let liquidity = collector.fetch(token_pairs(), Block::Recent).await.unwrap();
+        assert_eq!(liquidity.len(), 2);
+        assert!(matches!(&liquidity[0], Liquidity::ConstantProduct(p) if *p == pool));
+        assert!(matches!(&liquidity[1], Liquidity::LimitOrder(o) if *o == order));
+    }
+
+    #[tokio::test]
+    async fn collects_quote_only_liquidity() {
+        let pool = native::Pool {
+            native: H160([1; 20]),
+            wrapped: H160([2; 20]),
+        };
+        let collector = LiquidityCollector {
+            native: vec![Arc::new(FakeNativeFetcher(pool))],
+            ..Default::default()
+        };
+
+        let liquidity = collector.fetch(token_pairs(), Block::Recent).await.unwrap();
+        assert!(matches!(
+            &liquidity[..],
+            [Liquidity::QuoteOnly(QuoteOnlyLiquidity::Native(p))] if *p == pool
+        ));
+        assert_eq!(liquidity[0].token_pairs(), Vec::from_iter(token_pairs()));
+        assert_eq!(
+            liquidity[0].get_amount_out(pool.wrapped, (1_000.into(), pool.native)),
+            Some(1_000.into())
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_liquidity_of_denied_tokens() {
+        let pool = uniswap_v2::pool_fetching::Pool {
+            tokens: TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap(),
+            reserves: (13, 37),
+            fee: Ratio::new(3, 1000),
+        };
+        let collector = LiquidityCollector {
+            constant_product: vec![Arc::new(FakeConstantProductFetcher(Ok(vec![pool])))],
+            token_filter: Some(Arc::new(TokenFilter::new([H160([2; 20])]))),
+            ..Default::default()
+        };
+
+        let liquidity = collector.fetch(token_pairs(), Block::Recent).await.unwrap();
+        assert!(liquidity.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forwards_source_errors() {
+        let collector = LiquidityCollector {
+            constant_product: vec![
+                Arc::new(FakeConstantProductFetcher(Ok(Vec::new()))),
+                Arc::new(FakeConstantProductFetcher(Err("error".to_owned()))),
+            ],
+            ..Default::default()
+        };
+
+        assert!(collector.fetch(token_pairs(), Block::Recent).await.is_err());
+    }
+}
@@ -0,0 +1,544 @@
+//! A graph of the tokens connected by fetched liquidity.
+//!
+//! Tokens are the nodes of the graph and every token pair that can be traded
+//! with some piece of liquidity is an edge. This is used for finding the paths
+//! to route trades along and for analysing which pairs are covered by the
+//! fetched liquidity.
+//!
+//! For liquidity that can be quoted, every edge also has a marginal exchange
+//! rate in both directions. Cycles whose rates multiply to more than one are
+//! profitable loops, which are either arbitrage opportunities or a sign that
+//! some source reports inconsistent data.
+
+use super::{Liquidity, QuoteOnlyLiquidity, StableLiquidity};
+use crate::{
+    baseline_solver::{marginal_price, BaselineSolvable},
+    token_pair::{TokenPair, TokenPath},
+};
+use ethcontract::{H160, U256};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+};
+
+/// Liquidity indexed by the token pairs it can trade.
+#[derive(Clone, Debug)]
+pub struct LiquidityGraph<L = Liquidity> {
+    liquidity: Vec<L>,
+    pairs: HashMap<TokenPair, Vec<usize>>,
+    adjacency: HashMap<H160, BTreeSet<H160>>,
+}
+
+impl<L> Default for LiquidityGraph<L> {
+    fn default() -> Self {
+        Self {
+            liquidity: Vec::new(),
+            pairs: HashMap::new(),
+            adjacency: HashMap::new(),
+        }
+    }
+}
+
+impl LiquidityGraph {
+    /// Creates a graph of the liquidity of any of the supported sources.
+    pub fn from_liquidity(liquidity: impl IntoIterator<Item = Liquidity>) -> Self {
+        Self::new(liquidity, Liquidity::token_pairs)
+    }
+}
+
+impl<L> LiquidityGraph<L> {
+    /// Creates a graph of the liquidity, where `token_pairs` returns the pairs
+    /// that a piece of liquidity can trade.
+    pub fn new(
+        liquidity: impl IntoIterator<Item = L>,
+        token_pairs: impl Fn(&L) -> Vec<TokenPair>,
+    ) -> Self {
+        let mut graph = Self::default();
+        for liquidity in liquidity {
+            let pairs = token_pairs(&liquidity);
+            graph.insert(liquidity, &pairs);
+        }
+        graph
+    }
+
+    /// Adds liquidity that can trade the specified token pairs.
+    pub fn insert(&mut self, liquidity: L, token_pairs: &[TokenPair]) {
+        let index = self.liquidity.len();
+        self.liquidity.push(liquidity);
+        for pair in token_pairs {
+            let indices = self.pairs.entry(*pair).or_default();
+            if indices.last() == Some(&index) {
+                continue;
+            }
+            indices.push(index);
+            let (a, b) = pair.get();
+            self.adjacency.entry(a).or_default().insert(b);
+            self.adjacency.entry(b).or_default().insert(a);
+        }
+    }
+
+    /// Returns the number of pieces of liquidity in the graph.
+    pub fn len(&self) -> usize {
+        self.liquidity.len()
+    }
+
+    /// Returns `true` if the graph has no liquidity.
+    pub fn is_empty(&self) -> bool {
+        self.liquidity.is_empty()
+    }
+
+    /// Returns the token pairs that can be traded with some liquidity.
+    pub fn pairs(&self) -> impl Iterator<Item = TokenPair> + '_ {
+        self.pairs.keys().copied()
+    }
+
+    /// Returns the liquidity that can trade the token pair.
+    pub fn liquidity(&self, pair: TokenPair) -> impl Iterator<Item = &L> + '_ {
+        self.pairs
+            .get(&pair)
+            .into_iter()
+            .flatten()
+            .map(move |index| &self.liquidity[*index])
+    }
+
+    /// Returns the tokens that can be traded directly with the token, in
+    /// ascending order.
+    pub fn neighbours(&self, token: H160) -> impl Iterator<Item = H160> + '_ {
+        self.adjacency.get(&token).into_iter().flatten().copied()
+    }
+
+    /// Returns all paths from the sell token to the buy token with up to
+    /// `max_hops` intermediate tokens and without visiting any token twice.
+    ///
+    /// Paths start with the sell token and end with the buy token, like the
+    /// path candidates of the baseline solver. Shorter paths come first.
+    pub fn paths(&self, sell_token: H160, buy_token: H160, max_hops: usize) -> Vec<TokenPath> {
+        let mut paths = Vec::new();
+        if sell_token != buy_token {
+            self.extend_paths(&mut vec![sell_token], buy_token, max_hops + 1, &mut paths);
+        }
+        paths.sort_by_key(|path| path.len());
+        paths
+            .into_iter()
+            .map(|path| TokenPath::acyclic(path).expect("paths visit every token once"))
+            .collect()
+    }
+
+    fn extend_paths(
+        &self,
+        path: &mut Vec<H160>,
+        buy_token: H160,
+        max_edges: usize,
+        paths: &mut Vec<Vec<H160>>,
+    ) {
+        let last = *path.last().expect("paths start with the sell token");
+        for token in self.neighbours(last) {
+            if token == buy_token {
+                let mut complete = path.clone();
+                complete.push(token);
+                paths.push(complete);
+            } else if path.len() < max_edges && !path.contains(&token) {
+                path.push(token);
+                self.extend_paths(path, buy_token, max_edges, paths);
+                path.pop();
+            }
+        }
+    }
+
+    /// Returns the minimum number of trades needed to get from one token to
+    /// the other, or `None` if they are not connected.
+    pub fn distance(&self, from: H160, to: H160) -> Option<usize> {
+        let mut distances = HashMap::new();
+        distances.insert(from, 0);
+        let mut queue = VecDeque::from(vec![from]);
+        while let Some(token) = queue.pop_front() {
+            let distance = distances[&token];
+            if token == to {
+                return Some(distance);
+            }
+            for neighbour in self.neighbours(token) {
+                if !distances.contains_key(&neighbour) {
+                    distances.insert(neighbour, distance + 1);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns `true` if the tokens can be traded for each other along some
+    /// path.
+    pub fn is_connected(&self, a: H160, b: H160) -> bool {
+        self.distance(a, b).is_some()
+    }
+
+    /// Returns all tokens that can be traded for the token along some path,
+    /// including the token itself.
+    pub fn connected_tokens(&self, token: H160) -> HashSet<H160> {
+        let mut tokens = HashSet::new();
+        tokens.insert(token);
+        let mut stack = vec![token];
+        while let Some(token) = stack.pop() {
+            for neighbour in self.neighbours(token) {
+                if tokens.insert(neighbour) {
+                    stack.push(neighbour);
+                }
+            }
+        }
+        tokens
+    }
+}
+
+/// A loop of trades that ends with more of its first token than it started
+/// with, at marginal prices.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cycle {
+    /// The traded tokens, starting and ending with the smallest token of the
+    /// cycle.
+    pub tokens: Vec<H160>,
+    /// The product of the best marginal exchange rates along the cycle, so
+    /// `1.01` is a profit of 1%.
+    pub rate: f64,
+}
+
+impl<L: BaselineSolvable> LiquidityGraph<L> {
+    /// Returns profitable cycles whose rate exceeds `1 + threshold`, most
+    /// profitable first.
+    ///
+    /// Marginal rates are approximated by quoting `probe_amount(token)` of
+    /// the sold token, which should be small compared to the liquidity but
+    /// large enough to not be dominated by rounding. Cycles are found with
+    /// Bellman-Ford over the negative logarithms of the rates, so every
+    /// token with a profitable cycle is covered, but when profitable cycles
+    /// overlap only some of them are returned.
+    pub fn profitable_cycles(
+        &self,
+        probe_amount: impl Fn(H160) -> U256,
+        threshold: f64,
+    ) -> Vec<Cycle> {
+        let rates = self.marginal_rates(probe_amount);
+        let mut tokens = self.adjacency.keys().copied().collect::<Vec<_>>();
+        tokens.sort();
+        let indices = tokens
+            .iter()
+            .enumerate()
+            .map(|(index, token)| (*token, index))
+            .collect::<HashMap<_, _>>();
+        let edges = rates
+            .iter()
+            .map(|((from, to), rate)| (indices[from], indices[to], -rate.ln()))
+            .collect::<Vec<_>>();
+
+        // Starting with all distances at zero is like relaxing from a virtual
+        // source connected to every token, which reaches all cycles.
+        let mut distances = vec![0.; tokens.len()];
+        let mut predecessors = vec![None; tokens.len()];
+        let mut relaxed = Vec::new();
+        for _ in 0..tokens.len() {
+            relaxed.clear();
+            for (from, to, weight) in &edges {
+                let distance = distances[*from] + weight;
+                if distance < distances[*to] - RELAXATION_TOLERANCE {
+                    distances[*to] = distance;
+                    predecessors[*to] = Some(*from);
+                    relaxed.push(*to);
+                }
+            }
+            if relaxed.is_empty() {
+                return Vec::new();
+            }
+        }
+
+        // Tokens that could still be relaxed after as many rounds as there
+        // are tokens lead back to a negative cycle along their predecessors.
+        let mut seen = HashSet::new();
+        let mut cycles = Vec::new();
+        for token in relaxed {
+            let mut start = token;
+            for _ in 0..tokens.len() {
+                start = predecessors[start].expect("relaxed tokens have predecessors");
+            }
+            let mut cycle = vec![start];
+            let mut current = predecessors[start].expect("cycle tokens have predecessors");
+            while current != start {
+                cycle.push(current);
+                current = predecessors[current].expect("cycle tokens have predecessors");
+            }
+            // Predecessors lead backwards, so reverse to get the trading order
+            // and start with the smallest token to deduplicate rotations.
+            cycle.reverse();
+            let first = (0..cycle.len()).min_by_key(|i| cycle[*i]).unwrap();
+            cycle.rotate_left(first);
+            cycle.push(cycle[0]);
+            if !seen.insert(cycle.clone()) {
+                continue;
+            }
+            let cycle = cycle.iter().map(|index| tokens[*index]).collect::<Vec<_>>();
+            let rate = cycle
+                .windows(2)
+                .map(|pair| rates[&(pair[0], pair[1])])
+                .product::<f64>();
+            if rate > 1. + threshold {
+                cycles.push(Cycle {
+                    tokens: cycle,
+                    rate,
+                });
+            }
+        }
+        cycles.sort_by(|a, b| b.rate.partial_cmp(&a.rate).unwrap_or(Ordering::Equal));
+        cycles
+    }
+
+    /// Returns the best marginal exchange rate of every traded token pair in
+    /// both directions.
+    fn marginal_rates(&self, probe_amount: impl Fn(H160) -> U256) -> HashMap<(H160, H160), f64> {
+        let mut rates = HashMap::new();
+        for (pair, indices) in &self.pairs {
+            let (a, b) = pair.get();
+            for (sell_token, buy_token) in [(a, b), (b, a)] {
+                let amount = probe_amount(sell_token);
+                let rate = indices
+                    .iter()
+                    .filter_map(|index| {
+                        marginal_price(&self.liquidity[*index], sell_token, buy_token, amount)
+                    })
+                    .fold(None, |best: Option<f64>, rate| {
+                        Some(best.map_or(rate, |best| best.max(rate)))
+                    });
+                if let Some(rate) = rate {
+                    rates.insert((sell_token, buy_token), rate);
+                }
+            }
+        }
+        rates
+    }
+}
+
+/// Relaxations that improve a distance by less than this are ignored, so that
+/// rounding errors in the logarithms of rates don't look like cycles.
+const RELAXATION_TOLERANCE: f64 = 1e-12;
+
+impl Liquidity {
+    /// Returns all token pairs that can be traded with the liquidity.
+    pub fn token_pairs(&self) -> Vec<TokenPair> {
+        match self {
+            Liquidity::ConstantProduct(pool) => vec![pool.tokens],
+            Liquidity::WeightedProduct(pool) => all_pairs(pool.reserves.keys()),
+            Liquidity::Stable(StableLiquidity::Balancer(pool)) => all_pairs(pool.reserves.keys()),
+            Liquidity::Stable(StableLiquidity::BalancerComposable(pool)) => {
+                all_pairs(pool.reserves.keys())
+            }
+            Liquidity::Stable(StableLiquidity::Curve(pool)) => {
+                match &pool.base_pool {
+                    // Metapools trade their own coins with each other and with
+                    // the coins of the base pool, but not their base pool LP
+                    // token or the base pool coins with each other.
+                    Some(base_pool) => {
+                        let coins = &pool.tokens[..pool.tokens.len().saturating_sub(1)];
+                        let mut pairs = all_pairs(coins.iter().map(|token| &token.address));
+                        pairs.extend(coins.iter().flat_map(|coin| {
+                            base_pool.pool.tokens.iter().filter_map(move |token| {
+                                TokenPair::new(coin.address, token.address)
+                            })
+                        }));
+                        pairs
+                    }
+                    None => all_pairs(pool.tokens.iter().map(|token| &token.address)),
+                }
+            }
+            // Linear pools also trade their pool token with both tokens.
+            Liquidity::Linear(pool) => {
+                all_pairs([pool.main_token, pool.wrapped_token, pool.common.address].iter())
+            }
+            Liquidity::ConcentratedLiquidity(pool) => {
+                all_pairs(pool.tokens.iter().map(|token| &token.id))
+            }
+            Liquidity::EllipticConcentratedLiquidity(pool) => all_pairs(pool.reserves.keys()),
+            Liquidity::LimitOrder(order) => pair(order.sell_token, order.buy_token),
+            Liquidity::QuoteOnly(liquidity) => match liquidity {
+                QuoteOnlyLiquidity::Algebra(pool) => {
+                    all_pairs(pool.tokens.iter().map(|token| &token.id))
+                }
+                QuoteOnlyLiquidity::Ambient(pool) => pair(pool.key.base, pool.key.quote),
+                QuoteOnlyLiquidity::Erc4626(pool) => pair(pool.vault, pool.asset),
+                QuoteOnlyLiquidity::Integral(pool) => {
+                    pair(pool.tokens.0.address, pool.tokens.1.address)
+                }
+                QuoteOnlyLiquidity::IZiSwap(pool) => pair(pool.tokens.0, pool.tokens.1),
+                QuoteOnlyLiquidity::MakerPsm(pool) => pair(pool.dai, pool.gem),
+                QuoteOnlyLiquidity::MStable(pool) => {
+                    all_pairs(pool.tokens.iter().map(|token| &token.address))
+                }
+                QuoteOnlyLiquidity::Native(pool) => pair(pool.native, pool.wrapped),
+                QuoteOnlyLiquidity::Saddle(pool) => {
+                    all_pairs(pool.tokens.iter().map(|token| &token.address))
+                }
+                QuoteOnlyLiquidity::Solidly(pool) => pair(pool.tokens.0, pool.tokens.1),
+                QuoteOnlyLiquidity::Synthetix(pool) => pair(pool.tokens.0, pool.tokens.1),
+                QuoteOnlyLiquidity::UniswapV4(pool) => pair(pool.key.currency0, pool.key.currency1),
+                QuoteOnlyLiquidity::Wombat(pool) => {
+                    all_pairs(pool.assets.iter().map(|asset| &asset.token))
+                }
+                QuoteOnlyLiquidity::WstEth(pool) => pair(pool.wsteth, pool.steth),
+            },
+        }
+    }
+}
+
+/// Returns the pair of two tokens, or no pairs if they are the same.
+fn pair(a: H160, b: H160) -> Vec<TokenPair> {
+    TokenPair::new(a, b).into_iter().collect()
+}
+
+/// Returns the pairs of all the tokens with each other.
+fn all_pairs<'a>(tokens: impl Iterator<Item = &'a H160>) -> Vec<TokenPair> {
+    let tokens = tokens.copied().collect::<Vec<_>>();
+    tokens
+        .iter()
+        .enumerate()
+        .flat_map(|(index, a)| {
+            tokens[index + 1..]
+                .iter()
+                .filter_map(move |b| TokenPair::new(*a, *b))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn pair(a: u64, b: u64) -> TokenPair {
+        TokenPair::new(token(a), token(b)).unwrap()
+    }
+
+    /// Graph of `1 - 2 - 3 - 4` with a shortcut `1 - 3` and a separate `5 - 6`.
+    fn graph() -> LiquidityGraph<&'static str> {
+        LiquidityGraph::new(
+            vec!["a", "b", "c", "d", "e"],
+            |liquidity| match *liquidity {
+                "a" => vec![pair(1, 2)],
+                "b" => vec![pair(2, 3), pair(1, 3)],
+                "c" => vec![pair(3, 4)],
+                "d" => vec![pair(5, 6)],
+                _ => vec![pair(1, 2)],
+            },
+        )
+    }
+
+    #[test]
+    fn indexes_liquidity_by_token_pair() {
+        let graph = graph();
+        assert_eq!(graph.len(), 5);
+        assert_eq!(graph.pairs().count(), 5);
+        assert_eq!(
+            graph.liquidity(pair(1, 2)).collect::<Vec<_>>(),
+            [&"a", &"e"]
+        );
+        assert_eq!(graph.liquidity(pair(1, 3)).collect::<Vec<_>>(), [&"b"]);
+        assert_eq!(graph.liquidity(pair(1, 4)).count(), 0);
+        assert_eq!(
+            graph.neighbours(token(3)).collect::<Vec<_>>(),
+            [token(1), token(2), token(4)]
+        );
+    }
+
+    #[test]
+    fn enumerates_bounded_paths() {
+        let graph = graph();
+        let paths = |sell_token, buy_token, max_hops| {
+            graph
+                .paths(token(sell_token), token(buy_token), max_hops)
+                .into_iter()
+                .map(Vec::from)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(1, 3, 0), [vec![token(1), token(3)]]);
+        assert_eq!(paths(1, 4, 1), [vec![token(1), token(3), token(4)]]);
+        assert_eq!(
+            paths(1, 4, 2),
+            [
+                vec![token(1), token(3), token(4)],
+                vec![token(1), token(2), token(3), token(4)],
+            ]
+        );
+        assert!(graph.paths(token(1), token(5), 3).is_empty());
+        assert!(graph.paths(token(1), token(1), 3).is_empty());
+    }
+
+    #[test]
+    fn answers_connectivity_queries() {
+        let graph = graph();
+        assert_eq!(graph.distance(token(1), token(1)), Some(0));
+        assert_eq!(graph.distance(token(2), token(4)), Some(2));
+        assert!(graph.is_connected(token(4), token(1)));
+        assert!(!graph.is_connected(token(1), token(6)));
+        assert_eq!(
+            graph.connected_tokens(token(5)),
+            [token(5), token(6)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn finds_profitable_cycles() {
+        use crate::sources::uniswap_v2::pool_fetching::Pool;
+
+        let pool = |a: u64, b: u64, reserves: (u128, u128)| Pool::uniswap(pair(a, b), reserves);
+        let probe_amount = |_| U256::exp10(15);
+        let consistent = vec![
+            pool(1, 2, (10u128.pow(24), 10u128.pow(24))),
+            pool(2, 3, (10u128.pow(24), 10u128.pow(24))),
+            pool(1, 3, (10u128.pow(24), 10u128.pow(24))),
+            pool(3, 4, (10u128.pow(24), 2 * 10u128.pow(24))),
+        ];
+        let graph = LiquidityGraph::new(consistent.clone(), |pool| vec![pool.tokens]);
+        assert!(graph.profitable_cycles(probe_amount, 0.).is_empty());
+
+        // Token 3 is twice as valuable in terms of token 1 in this pool.
+        let mut inconsistent = consistent;
+        inconsistent[2] = pool(1, 3, (2 * 10u128.pow(24), 10u128.pow(24)));
+        let graph = LiquidityGraph::new(inconsistent, |pool| vec![pool.tokens]);
+        let cycles = graph.profitable_cycles(probe_amount, 0.);
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].tokens, [token(1), token(2), token(3), token(1)]);
+        // Two without three times the fee.
+        assert!((cycles[0].rate - 2. * 0.997f64.powi(3)).abs() < 1e-3);
+        assert!(graph.profitable_cycles(probe_amount, 1.).is_empty());
+    }
+
+    #[test]
+    fn metapools_trade_with_base_pool_coins() {
+        use crate::sources::curve::pool_fetching::{BasePool, Pool, TokenState};
+
+        let pool = |tokens: &[u64], base_pool: Option<Pool>| Pool {
+            address: H160::zero(),
+            tokens: tokens
+                .iter()
+                .map(|n| TokenState {
+                    address: token(*n),
+                    balance: 0.into(),
+                    rate: 0.into(),
+                })
+                .collect(),
+            amplification_parameter: 0.into(),
+            fee: 0.into(),
+            underlying: false,
+            base_pool: base_pool.map(|pool| {
+                Box::new(BasePool {
+                    pool,
+                    lp_supply: 0.into(),
+                })
+            }),
+        };
+        // Coin 1 and the LP token 2 of a base pool with coins 3 and 4.
+        let metapool = pool(&[1, 2], Some(pool(&[3, 4], None)));
+
+        let mut pairs = Liquidity::Stable(StableLiquidity::Curve(metapool)).token_pairs();
+        pairs.sort();
+        assert_eq!(pairs, [pair(1, 3), pair(1, 4)]);
+    }
+}
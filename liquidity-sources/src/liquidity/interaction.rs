@@ -0,0 +1,641 @@
+//! Encoding quoted routes as settlement interactions.
+//!
+//! Every hop of a route is encoded as an approval of the sell token followed
+//! by a swap with the contract of its liquidity. Hops are executed with limits
+//! that allow for the configured slippage, and every hop only relies on the
+//! amount that the hops before it guarantee. So intermediate hops of sell
+//! routes sell the minimum the previous hop receives, and intermediate hops of
+//! buy routes buy the maximum the next hop sends.
+
+use super::{
+    quote::{Hop, Quote, Side},
+    slippage::Slippage,
+    Liquidity, StableLiquidity,
+};
+use crate::baseline_solver::BaselineSolvable;
+use anyhow::{bail, Context as _, Result};
+use ethcontract::{H160, H256, U256};
+use web3::{
+    ethabi::{self, Token},
+    signing::keccak256,
+};
+
+/// Balancer `SwapKind` values.
+pub(super) const GIVEN_IN: u8 = 0;
+pub(super) const GIVEN_OUT: u8 = 1;
+
+/// A call executed by the settlement contract.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Interaction {
+    pub target: H160,
+    pub value: U256,
+    pub call_data: Vec<u8>,
+}
+
+/// Encodes routes as interactions for a settlement contract.
+///
+/// Interactions are only encoded for liquidity whose contracts are
+/// configured. Limit orders are settled by their sources and can't be encoded,
+/// and neither can quote only liquidity.
+#[derive(Clone, Debug, Default)]
+pub struct InteractionEncoder {
+    /// The contract executing the interactions, which receives the bought
+    /// tokens of every hop.
+    pub settlement: H160,
+    /// The slippage that is tolerated for each hop.
+    pub slippage: Slippage,
+    /// A Uniswap V2 compatible router for the factory of all constant product
+    /// pools on encoded routes.
+    pub constant_product_router: Option<H160>,
+    pub balancer_vault: Option<H160>,
+    /// A Uniswap `SwapRouter02` compatible router for concentrated liquidity
+    /// pools.
+    pub concentrated_liquidity_router: Option<H160>,
+}
+
+/// The amounts of a single swap.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Amounts {
+    ExactIn {
+        amount_in: U256,
+        min_amount_out: U256,
+    },
+    ExactOut {
+        amount_out: U256,
+        max_amount_in: U256,
+    },
+}
+
+impl Amounts {
+    fn max_amount_in(&self) -> U256 {
+        match self {
+            Amounts::ExactIn { amount_in, .. } => *amount_in,
+            Amounts::ExactOut { max_amount_in, .. } => *max_amount_in,
+        }
+    }
+
+    fn min_amount_out(&self) -> U256 {
+        match self {
+            Amounts::ExactIn { min_amount_out, .. } => *min_amount_out,
+            Amounts::ExactOut { amount_out, .. } => *amount_out,
+        }
+    }
+}
+
+impl InteractionEncoder {
+    /// Encodes the interactions for executing all hops of the quote.
+    ///
+    /// Hops of buy quotes are executed for their exact buy amounts with
+    /// liquidity that supports it. Curve pools only support exact sell amounts,
+    /// so they sell their maximum amount and require at least the exact buy
+    /// amount in return.
+    pub fn encode(&self, quote: &Quote, side: Side) -> Result<Vec<Interaction>> {
+        let mut interactions = Vec::new();
+        for (hop, amounts) in quote.hops.iter().zip(self.amounts(quote, side)?) {
+            let (spender, amounts, swap) = self.encode_swap(hop, amounts)?;
+            interactions.push(Interaction {
+                target: hop.sell_token,
+                value: U256::zero(),
+                call_data: encode_call(
+                    "approve(address,uint256)",
+                    &[
+                        Token::Address(spender),
+                        Token::Uint(amounts.max_amount_in()),
+                    ],
+                ),
+            });
+            interactions.push(swap);
+        }
+        Ok(interactions)
+    }
+
+    /// Returns the contract that needs to be approved for the swap, the swap
+    /// amounts and the swap interaction.
+    fn encode_swap(&self, hop: &Hop, amounts: Amounts) -> Result<(H160, Amounts, Interaction)> {
+        let (target, amounts, call_data) = match &hop.liquidity {
+            Liquidity::ConstantProduct(_) => {
+                let router = self
+                    .constant_product_router
+                    .context("no constant product router configured")?;
+                let path = Token::Array(vec![
+                    Token::Address(hop.sell_token),
+                    Token::Address(hop.buy_token),
+                ]);
+                let call_data = match amounts {
+                    Amounts::ExactIn {
+                        amount_in,
+                        min_amount_out,
+                    } => encode_call(
+                        "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)",
+                        &[
+                            Token::Uint(amount_in),
+                            Token::Uint(min_amount_out),
+                            path,
+                            Token::Address(self.settlement),
+                            Token::Uint(U256::max_value()),
+                        ],
+                    ),
+                    Amounts::ExactOut {
+                        amount_out,
+                        max_amount_in,
+                    } => encode_call(
+                        "swapTokensForExactTokens(uint256,uint256,address[],address,uint256)",
+                        &[
+                            Token::Uint(amount_out),
+                            Token::Uint(max_amount_in),
+                            path,
+                            Token::Address(self.settlement),
+                            Token::Uint(U256::max_value()),
+                        ],
+                    ),
+                };
+                (router, amounts, call_data)
+            }
+            Liquidity::WeightedProduct(pool) => {
+                self.encode_balancer_swap(hop, amounts, pool.common.id)?
+            }
+            Liquidity::Stable(StableLiquidity::Balancer(pool)) => {
+                self.encode_balancer_swap(hop, amounts, pool.common.id)?
+            }
+            Liquidity::Stable(StableLiquidity::BalancerComposable(pool)) => {
+                self.encode_balancer_swap(hop, amounts, pool.common.id)?
+            }
+            Liquidity::Linear(pool) => self.encode_balancer_swap(hop, amounts, pool.common.id)?,
+            Liquidity::EllipticConcentratedLiquidity(pool) => {
+                self.encode_balancer_swap(hop, amounts, pool.common.id)?
+            }
+            Liquidity::Stable(StableLiquidity::Curve(pool)) => {
+                let exchange = pool
+                    .exchange(hop.sell_token, hop.buy_token)
+                    .context("Curve pool can't swap the tokens of the hop")?;
+                let (amount_in, min_amount_out) =
+                    (amounts.max_amount_in(), amounts.min_amount_out());
+                let signature = if exchange.underlying {
+                    "exchange_underlying(int128,int128,uint256,uint256)"
+                } else {
+                    "exchange(int128,int128,uint256,uint256)"
+                };
+                let call_data = encode_call(
+                    signature,
+                    &[
+                        Token::Int(exchange.i.into()),
+                        Token::Int(exchange.j.into()),
+                        Token::Uint(amount_in),
+                        Token::Uint(min_amount_out),
+                    ],
+                );
+                let amounts = Amounts::ExactIn {
+                    amount_in,
+                    min_amount_out,
+                };
+                (pool.address, amounts, call_data)
+            }
+            Liquidity::ConcentratedLiquidity(pool) => {
+                let router = self
+                    .concentrated_liquidity_router
+                    .context("no concentrated liquidity router configured")?;
+                let fee = pool.state.fee;
+                let fee = U256::from(*fee.numer()) * 1_000_000 / U256::from(*fee.denom());
+                let (signature, amount, limit) = match amounts {
+                    Amounts::ExactIn {
+                        amount_in,
+                        min_amount_out,
+                    } => (
+                        "exactInputSingle((address,address,uint24,address,uint256,uint256,uint160))",
+                        amount_in,
+                        min_amount_out,
+                    ),
+                    Amounts::ExactOut {
+                        amount_out,
+                        max_amount_in,
+                    } => (
+                        "exactOutputSingle((address,address,uint24,address,uint256,uint256,uint160))",
+                        amount_out,
+                        max_amount_in,
+                    ),
+                };
+                let call_data = encode_call(
+                    signature,
+                    &[Token::Tuple(vec![
+                        Token::Address(hop.sell_token),
+                        Token::Address(hop.buy_token),
+                        Token::Uint(fee),
+                        Token::Address(self.settlement),
+                        Token::Uint(amount),
+                        Token::Uint(limit),
+                        // No price limit.
+                        Token::Uint(U256::zero()),
+                    ])],
+                );
+                (router, amounts, call_data)
+            }
+            Liquidity::LimitOrder(order) => {
+                bail!(
+                    "limit order {} can't be encoded as an interaction",
+                    order.id
+                )
+            }
+            Liquidity::QuoteOnly(liquidity) => {
+                bail!(
+                    "{:?} liquidity can't be encoded as an interaction",
+                    liquidity.source()
+                )
+            }
+        };
+        let swap = Interaction {
+            target,
+            value: U256::zero(),
+            call_data,
+        };
+        Ok((target, amounts, swap))
+    }
+
+    fn encode_balancer_swap(
+        &self,
+        hop: &Hop,
+        amounts: Amounts,
+        pool_id: H256,
+    ) -> Result<(H160, Amounts, Vec<u8>)> {
+        let vault = self
+            .balancer_vault
+            .context("no Balancer vault configured")?;
+        let (kind, amount, limit) = match amounts {
+            Amounts::ExactIn {
+                amount_in,
+                min_amount_out,
+            } => (GIVEN_IN, amount_in, min_amount_out),
+            Amounts::ExactOut {
+                amount_out,
+                max_amount_in,
+            } => (GIVEN_OUT, amount_out, max_amount_in),
+        };
+        let call_data = encode_call(
+            "swap((bytes32,uint8,address,address,uint256,bytes),(address,bool,address,bool),uint256,uint256)",
+            &[
+                Token::Tuple(vec![
+                    Token::FixedBytes(pool_id.as_bytes().to_vec()),
+                    Token::Uint(kind.into()),
+                    Token::Address(hop.sell_token),
+                    Token::Address(hop.buy_token),
+                    Token::Uint(amount),
+                    Token::Bytes(Vec::new()),
+                ]),
+                Token::Tuple(vec![
+                    Token::Address(self.settlement),
+                    Token::Bool(false),
+                    Token::Address(self.settlement),
+                    Token::Bool(false),
+                ]),
+                Token::Uint(limit),
+                Token::Uint(U256::max_value()),
+            ],
+        );
+        Ok((vault, amounts, call_data))
+    }
+
+    /// Returns the amounts of every hop of the quote.
+    ///
+    /// Sell routes are walked forwards, and every hop sells what the previous
+    /// hop receives at least. Buy routes are walked backwards, and every hop
+    /// buys what the next hop sends at most. Hops that swap different amounts
+    /// than were quoted for them are quoted again with their liquidity.
+    fn amounts(&self, quote: &Quote, side: Side) -> Result<Vec<Amounts>> {
+        match side {
+            Side::Sell => {
+                let mut amount_in = quote.sell_amount;
+                quote
+                    .hops
+                    .iter()
+                    .map(|hop| {
+                        let amount_out = if amount_in == hop.sell_amount {
+                            hop.buy_amount
+                        } else {
+                            hop.liquidity
+                                .get_amount_out(hop.buy_token, (amount_in, hop.sell_token))
+                                .context("hop can't be quoted for its guaranteed sell amount")?
+                        };
+                        let amounts = Amounts::ExactIn {
+                            amount_in,
+                            min_amount_out: self.slippage.min_received(amount_out),
+                        };
+                        amount_in = amounts.min_amount_out();
+                        Ok(amounts)
+                    })
+                    .collect()
+            }
+            Side::Buy => {
+                let mut amount_out = quote.buy_amount;
+                let mut amounts = quote
+                    .hops
+                    .iter()
+                    .rev()
+                    .map(|hop| {
+                        let amount_in = if amount_out == hop.buy_amount {
+                            hop.sell_amount
+                        } else {
+                            hop.liquidity
+                                .get_amount_in(hop.sell_token, (amount_out, hop.buy_token))
+                                .context("hop can't be quoted for its required buy amount")?
+                        };
+                        let amounts = Amounts::ExactOut {
+                            amount_out,
+                            max_amount_in: self
+                                .slippage
+                                .max_sent(amount_in)
+                                .context("amount overflow")?,
+                        };
+                        amount_out = amounts.max_amount_in();
+                        Ok(amounts)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                amounts.reverse();
+                Ok(amounts)
+            }
+        }
+    }
+}
+
+/// Encodes a call of the function with the specified signature.
+pub(crate) fn encode_call(signature: &str, args: &[Token]) -> Vec<u8> {
+    let mut call_data = keccak256(signature.as_bytes())[..4].to_vec();
+    call_data.extend(ethabi::encode(args));
+    call_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        liquidity::LimitOrder,
+        sources::{
+            balancer_v2::{
+                pool_fetching::{CommonPoolState, WeightedPool},
+                swap::fixed_point::Bfp,
+            },
+            uniswap_v2::pool_fetching::Pool,
+        },
+        token_pair::TokenPair,
+    };
+    use ethabi::ParamType;
+    use hex_literal::hex;
+
+    fn token(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn encoder() -> InteractionEncoder {
+        InteractionEncoder {
+            settlement: H160([0x90; 20]),
+            slippage: Slippage::from_bps(100).unwrap(),
+            constant_product_router: Some(H160([0x01; 20])),
+            balancer_vault: Some(H160([0xba; 20])),
+            concentrated_liquidity_router: None,
+        }
+    }
+
+    fn hop(sell: u64, buy: u64, sell_amount: u64, buy_amount: u64, liquidity: Liquidity) -> Hop {
+        Hop {
+            sell_token: token(sell),
+            buy_token: token(buy),
+            sell_amount: sell_amount.into(),
+            buy_amount: buy_amount.into(),
+            liquidity,
+        }
+    }
+
+    fn quote(hops: Vec<Hop>) -> Quote {
+        Quote {
+            sell_token: hops[0].sell_token,
+            buy_token: hops[hops.len() - 1].buy_token,
+            sell_amount: hops[0].sell_amount,
+            buy_amount: hops[hops.len() - 1].buy_amount,
+            gas_cost: 0,
+            hops,
+        }
+    }
+
+    fn constant_product(a: u64, b: u64) -> Liquidity {
+        Liquidity::ConstantProduct(Pool::uniswap(
+            TokenPair::new(token(a), token(b)).unwrap(),
+            (1_000_000, 1_000_000),
+        ))
+    }
+
+    fn weighted() -> Liquidity {
+        // Only the pool ID is needed for encoding swaps.
+        Liquidity::WeightedProduct(WeightedPool {
+            common: CommonPoolState {
+                id: H256([0xee; 32]),
+                address: H160([0xee; 20]),
+                swap_fee: Bfp::zero(),
+                paused: false,
+            },
+            reserves: Default::default(),
+        })
+    }
+
+    fn decode(interaction: &Interaction, selector: [u8; 4], types: &[ParamType]) -> Vec<Token> {
+        assert_eq!(interaction.call_data[..4], selector);
+        ethabi::decode(types, &interaction.call_data[4..]).unwrap()
+    }
+
+    fn approval(interaction: &Interaction) -> (H160, Vec<Token>) {
+        let args = decode(
+            interaction,
+            hex!("095ea7b3"),
+            &[ParamType::Address, ParamType::Uint(256)],
+        );
+        (interaction.target, args)
+    }
+
+    fn swap_args(interaction: &Interaction, selector: [u8; 4]) -> Vec<Token> {
+        decode(
+            interaction,
+            selector,
+            &[
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Array(Box::new(ParamType::Address)),
+                ParamType::Address,
+                ParamType::Uint(256),
+            ],
+        )
+    }
+
+    #[test]
+    fn encodes_sell_route() {
+        let encoder = encoder();
+        // The second hop is quoted for the 900 tokens the first hop is
+        // expected to buy, but only 891 of them are guaranteed.
+        let quote = quote(vec![
+            hop(1, 2, 1_000, 900, weighted()),
+            hop(2, 3, 900, 896, constant_product(2, 3)),
+        ]);
+
+        let interactions = encoder.encode(&quote, Side::Sell).unwrap();
+        assert_eq!(interactions.len(), 4);
+
+        assert_eq!(
+            approval(&interactions[0]),
+            (
+                token(1),
+                vec![Token::Address(H160([0xba; 20])), Token::Uint(1_000.into())]
+            )
+        );
+        assert_eq!(interactions[1].target, H160([0xba; 20]));
+        let args = decode(
+            &interactions[1],
+            hex!("52bbbe29"),
+            &[
+                ParamType::Tuple(vec![
+                    ParamType::FixedBytes(32),
+                    ParamType::Uint(8),
+                    ParamType::Address,
+                    ParamType::Address,
+                    ParamType::Uint(256),
+                    ParamType::Bytes,
+                ]),
+                ParamType::Tuple(vec![
+                    ParamType::Address,
+                    ParamType::Bool,
+                    ParamType::Address,
+                    ParamType::Bool,
+                ]),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+            ],
+        );
+        assert_eq!(
+            args[0],
+            Token::Tuple(vec![
+                Token::FixedBytes(vec![0xee; 32]),
+                Token::Uint(GIVEN_IN.into()),
+                Token::Address(token(1)),
+                Token::Address(token(2)),
+                Token::Uint(1_000.into()),
+                Token::Bytes(Vec::new()),
+            ])
+        );
+        assert_eq!(args[2], Token::Uint(891.into()));
+
+        assert_eq!(
+            approval(&interactions[2]),
+            (
+                token(2),
+                vec![Token::Address(H160([0x01; 20])), Token::Uint(891.into())]
+            )
+        );
+        assert_eq!(interactions[3].target, H160([0x01; 20]));
+        // 891 tokens buy 887 tokens from the pool.
+        assert_eq!(
+            swap_args(&interactions[3], hex!("38ed1739")),
+            [
+                Token::Uint(891.into()),
+                Token::Uint(878.into()),
+                Token::Array(vec![Token::Address(token(2)), Token::Address(token(3))]),
+                Token::Address(H160([0x90; 20])),
+                Token::Uint(U256::max_value()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sell_route_succeeds_when_hops_receive_their_minimum() {
+        let encoder = encoder();
+        let quote = quote(vec![
+            hop(1, 2, 1_000, 900, weighted()),
+            hop(2, 3, 900, 896, constant_product(2, 3)),
+            hop(3, 4, 896, 892, constant_product(3, 4)),
+        ]);
+
+        let interactions = encoder.encode(&quote, Side::Sell).unwrap();
+        let swaps = interactions.chunks(2).skip(1).map(|interactions| {
+            let args = swap_args(&interactions[1], hex!("38ed1739"));
+            (
+                args[0].clone().into_uint().unwrap(),
+                args[1].clone().into_uint().unwrap(),
+            )
+        });
+        // Every hop sells at most what the previous hop receives at least.
+        let mut min_received = U256::from(891);
+        for (amount_in, min_amount_out) in swaps {
+            assert!(amount_in <= min_received);
+            min_received = min_amount_out;
+        }
+    }
+
+    #[test]
+    fn encodes_buy_route_with_required_amounts() {
+        let encoder = encoder();
+        // The second hop may sell up to 909 tokens, so the first hop needs to
+        // buy that many instead of the 900 tokens that were quoted.
+        let quote = quote(vec![
+            hop(1, 2, 904, 900, constant_product(1, 2)),
+            hop(2, 3, 900, 896, constant_product(2, 3)),
+        ]);
+
+        let interactions = encoder.encode(&quote, Side::Buy).unwrap();
+        assert_eq!(interactions.len(), 4);
+
+        assert_eq!(
+            approval(&interactions[0]).1,
+            [Token::Address(H160([0x01; 20])), Token::Uint(923.into())]
+        );
+        let args = swap_args(&interactions[1], hex!("8803dbee"));
+        assert_eq!(args[0], Token::Uint(909.into()));
+        assert_eq!(args[1], Token::Uint(923.into()));
+
+        assert_eq!(
+            approval(&interactions[2]).1,
+            [Token::Address(H160([0x01; 20])), Token::Uint(909.into())]
+        );
+        let args = swap_args(&interactions[3], hex!("8803dbee"));
+        assert_eq!(args[0], Token::Uint(896.into()));
+        assert_eq!(args[1], Token::Uint(909.into()));
+    }
+
+    #[test]
+    fn encodes_buy_route_with_exact_out_swaps() {
+        let encoder = encoder();
+        let quote = quote(vec![hop(1, 2, 1_000, 900, constant_product(1, 2))]);
+
+        let interactions = encoder.encode(&quote, Side::Buy).unwrap();
+        assert_eq!(
+            approval(&interactions[0]).1,
+            [Token::Address(H160([0x01; 20])), Token::Uint(1_010.into())]
+        );
+        let args = swap_args(&interactions[1], hex!("8803dbee"));
+        assert_eq!(args[0], Token::Uint(900.into()));
+        assert_eq!(args[1], Token::Uint(1_010.into()));
+    }
+
+    #[test]
+    fn rejects_unsupported_liquidity() {
+        let order = LimitOrder {
+            id: "order".to_owned(),
+            sell_token: token(2),
+            buy_token: token(1),
+            sell_amount: 900.into(),
+            buy_amount: 1_000.into(),
+            partially_fillable: false,
+        };
+        let encoder = encoder();
+        assert!(encoder
+            .encode(
+                &quote(vec![hop(1, 2, 1_000, 900, Liquidity::LimitOrder(order))]),
+                Side::Sell
+            )
+            .is_err());
+
+        let encoder = InteractionEncoder {
+            constant_product_router: None,
+            ..encoder
+        };
+        assert!(encoder
+            .encode(
+                &quote(vec![hop(1, 2, 1_000, 900, constant_product(1, 2))]),
+                Side::Sell
+            )
+            .is_err());
+    }
+}
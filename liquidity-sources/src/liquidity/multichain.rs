@@ -0,0 +1,226 @@
+//! Serving the liquidity of several chains from a single instance.
+//!
+//! Operators running solver infrastructure for multiple chains can keep one
+//! `LiquidityCollector` per chain in a `MultichainLiquidity`, which routes
+//! fetches to the collector of the requested chain. All chains share the same
+//! HTTP client, and the maintenance of every chain is scheduled on the new
+//! blocks of that chain from a single future.
+
+use super::{Liquidity, LiquidityCollector};
+use crate::{
+    current_block::{self, CurrentBlockStream},
+    maintenance::{Maintaining, ServiceMaintenance},
+    recent_block_cache::Block,
+    sources::{self, BaselineSource, SharedClients},
+    token_pair::TokenPair,
+};
+use anyhow::{bail, ensure, Result};
+use reqwest::Client;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+
+struct ChainLiquidity {
+    collector: LiquidityCollector,
+    maintainers: Vec<Arc<dyn Maintaining>>,
+    block_stream: CurrentBlockStream,
+}
+
+/// Liquidity collectors for several chains.
+pub struct MultichainLiquidity {
+    client: Client,
+    chains: BTreeMap<u64, ChainLiquidity>,
+}
+
+impl MultichainLiquidity {
+    /// Creates a new instance without any chains, where the fetchers of all
+    /// chains share the specified HTTP client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            chains: Default::default(),
+        }
+    }
+
+    /// Returns the HTTP client shared by all chains, which should be used for
+    /// building the fetchers of chains added with [`Self::add_chain`].
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Adds the collector of a chain, along with the maintainers that need to
+    /// run on the new blocks of the chain.
+    ///
+    /// Returns an error if the chain was already added.
+    pub fn add_chain(
+        &mut self,
+        chain_id: u64,
+        collector: LiquidityCollector,
+        maintainers: Vec<Arc<dyn Maintaining>>,
+        block_stream: CurrentBlockStream,
+    ) -> Result<()> {
+        ensure!(
+            !self.chains.contains_key(&chain_id),
+            "chain {} was already added",
+            chain_id,
+        );
+        self.chains.insert(
+            chain_id,
+            ChainLiquidity {
+                collector,
+                maintainers,
+                block_stream,
+            },
+        );
+        Ok(())
+    }
+
+    /// Adds a chain with the specified baseline sources, see
+    /// [`sources::liquidity_collector`].
+    ///
+    /// The HTTP client of `clients` is replaced with the shared client.
+    pub async fn add_baseline_chain(
+        &mut self,
+        clients: SharedClients,
+        sources: &[BaselineSource],
+    ) -> Result<()> {
+        let clients = SharedClients {
+            client: self.client.clone(),
+            ..clients
+        };
+        let (collector, maintainers) = sources::liquidity_collector(&clients, sources).await?;
+        self.add_chain(
+            clients.chain_id,
+            collector,
+            maintainers,
+            clients.block_stream,
+        )
+    }
+
+    /// Returns the IDs of all added chains.
+    pub fn chain_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.chains.keys().copied()
+    }
+
+    /// Fetches the liquidity for the specified token pairs on a chain.
+    pub async fn fetch(
+        &self,
+        chain_id: u64,
+        token_pairs: HashSet<TokenPair>,
+        at_block: Block,
+    ) -> Result<Vec<Liquidity>> {
+        match self.chains.get(&chain_id) {
+            Some(chain) => chain.collector.fetch(token_pairs, at_block).await,
+            None => bail!("unsupported chain {}", chain_id),
+        }
+    }
+
+    /// Runs the maintenance of every chain on each new block of that chain.
+    ///
+    /// This only completes once the block streams of all chains end.
+    pub async fn run_maintenance_on_new_blocks(&self) {
+        futures::future::join_all(self.chains.iter().map(|(chain_id, chain)| {
+            let maintenance = ServiceMaintenance {
+                maintainers: chain.maintainers.clone(),
+            };
+            let block_stream = current_block::into_stream(chain.block_stream.clone());
+            async move {
+                maintenance
+                    .run_maintenance_for_block_stream(block_stream)
+                    .await;
+                tracing::warn!(%chain_id, "block stream ended");
+            }
+        }))
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        liquidity::{LimitOrder, LimitOrderFetching},
+        maintenance::MockMaintaining,
+    };
+    use ethcontract::H160;
+    use tokio::sync::watch;
+
+    struct FakeLimitOrderFetcher(LimitOrder);
+
+    #[async_trait::async_trait]
+    impl LimitOrderFetching for FakeLimitOrderFetcher {
+        async fn fetch(&self, _: HashSet<TokenPair>, _: Block) -> Result<Vec<LimitOrder>> {
+            Ok(vec![self.0.clone()])
+        }
+    }
+
+    fn collector(id: &str) -> LiquidityCollector {
+        LiquidityCollector {
+            limit_orders: vec![Arc::new(FakeLimitOrderFetcher(LimitOrder {
+                id: id.to_owned(),
+                sell_token: H160([1; 20]),
+                buy_token: H160([2; 20]),
+                sell_amount: 1_000.into(),
+                buy_amount: 2_000.into(),
+                partially_fillable: false,
+            }))],
+            ..Default::default()
+        }
+    }
+
+    fn block_stream() -> CurrentBlockStream {
+        // The sender is dropped right away, so the stream ends after the
+        // current block.
+        watch::channel(Default::default()).1
+    }
+
+    #[tokio::test]
+    async fn routes_fetches_to_chain() {
+        let mut multichain = MultichainLiquidity::new(Client::new());
+        multichain
+            .add_chain(1, collector("mainnet"), Vec::new(), block_stream())
+            .unwrap();
+        multichain
+            .add_chain(100, collector("gnosis"), Vec::new(), block_stream())
+            .unwrap();
+        assert!(multichain
+            .add_chain(1, collector("mainnet"), Vec::new(), block_stream())
+            .is_err());
+        assert_eq!(multichain.chain_ids().collect::<Vec<_>>(), vec![1, 100]);
+
+        for (chain_id, id) in [(1, "mainnet"), (100, "gnosis")] {
+            let liquidity = multichain
+                .fetch(chain_id, Default::default(), Block::Recent)
+                .await
+                .unwrap();
+            assert!(matches!(&liquidity[..], [Liquidity::LimitOrder(o)] if o.id == id));
+        }
+        assert!(multichain
+            .fetch(56, Default::default(), Block::Recent)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn runs_maintenance_for_every_chain() {
+        let mut multichain = MultichainLiquidity::new(Client::new());
+        for chain_id in [1, 100] {
+            let mut maintainer = MockMaintaining::new();
+            maintainer
+                .expect_run_maintenance()
+                .times(1)
+                .returning(|| Ok(()));
+            multichain
+                .add_chain(
+                    chain_id,
+                    Default::default(),
+                    vec![Arc::new(maintainer)],
+                    block_stream(),
+                )
+                .unwrap();
+        }
+
+        multichain.run_maintenance_on_new_blocks().await;
+    }
+}
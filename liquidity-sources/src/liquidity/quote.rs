@@ -0,0 +1,597 @@
+//! Quoting trades with the best route over the liquidity of all sources.
+
+pub mod cache;
+
+use super::{graph::LiquidityGraph, Liquidity, LiquidityCollector};
+use crate::{
+    baseline_solver::{
+        estimate_buy_amount, estimate_sell_amount, price_curve, spot_price, BaseTokens, Estimate,
+        GasCostModel, PathConfig, PricePoint, SpotPrice,
+    },
+    gas_price::GasPriceStream,
+    recent_block_cache::Block,
+    token_pair::{TokenPair, TokenPath},
+};
+use anyhow::Result;
+use ethcontract::{H160, U256};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+/// The side of a trade that has a fixed amount.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Side {
+    /// The sell amount is fixed and the quote is for the buy amount.
+    Sell,
+    /// The buy amount is fixed and the quote is for the sell amount.
+    Buy,
+}
+
+/// The best route found for a trade.
+#[derive(Clone, Debug)]
+pub struct Quote {
+    pub sell_token: H160,
+    pub buy_token: H160,
+    pub sell_amount: U256,
+    pub buy_amount: U256,
+    /// The trades along the route, starting with the one selling the sell
+    /// token.
+    pub hops: Vec<Hop>,
+    /// The approximate gas needed for executing the route.
+    pub gas_cost: usize,
+}
+
+/// A trade with a single piece of liquidity along a route.
+#[derive(Clone, Debug)]
+pub struct Hop {
+    pub sell_token: H160,
+    pub buy_token: H160,
+    pub sell_amount: U256,
+    pub buy_amount: U256,
+    pub liquidity: Liquidity,
+}
+
+/// Quotes trades with the liquidity of all sources of a collector, routed
+/// along the path candidates over the configured base tokens.
+pub struct QuoteEstimator {
+    collector: Arc<LiquidityCollector>,
+    base_tokens: Arc<BaseTokens>,
+    path_config: PathConfig,
+    gas_pricing: Option<GasPricing>,
+}
+
+/// How the gas for executing routes is priced, so that routes are ranked by
+/// their amounts net of gas costs, see [`GasCostModel`].
+#[derive(Clone, Debug)]
+pub struct GasPricing {
+    pub gas_prices: GasPriceStream,
+    /// The priority fee percentile of the gas price estimates to pay, which
+    /// needs to be one of the configured percentiles of the stream.
+    pub percentile: f64,
+    /// The wrapped native token, whose price in the estimated tokens is
+    /// quoted with the same liquidity as the routes.
+    pub native_token: H160,
+}
+
+impl QuoteEstimator {
+    pub fn new(collector: Arc<LiquidityCollector>, base_tokens: Arc<BaseTokens>) -> Self {
+        Self {
+            collector,
+            base_tokens,
+            path_config: PathConfig::default(),
+            gas_pricing: None,
+        }
+    }
+
+    /// Configures the maximum number of hops and path candidates of routes.
+    pub fn with_path_config(mut self, path_config: PathConfig) -> Self {
+        self.path_config = path_config;
+        self
+    }
+
+    /// Ranks routes by their amounts net of the gas costs of executing them.
+    ///
+    /// Without gas pricing, routes are ranked by their amounts alone.
+    pub fn with_gas_pricing(mut self, gas_pricing: GasPricing) -> Self {
+        self.gas_pricing = Some(gas_pricing);
+        self
+    }
+
+    /// Returns the route with the most buy tokens for selling the amount, or
+    /// with the fewest sell tokens for buying the amount, depending on the
+    /// side. With gas pricing, the gas costs of routes are subtracted from
+    /// their buy amounts or added to their sell amounts for ranking them.
+    ///
+    /// Routes are only ranked by their amounts when the native token can't be
+    /// priced in the estimated token, or the gas price estimate doesn't have
+    /// the configured percentile.
+    ///
+    /// Fetches the relevant liquidity for the recent block from all sources.
+    /// Returns `None` if no route can trade the amount.
+    pub async fn best_quote(
+        &self,
+        sell_token: H160,
+        buy_token: H160,
+        amount: U256,
+        side: Side,
+    ) -> Result<Option<Quote>> {
+        let query = Query {
+            sell_token,
+            buy_token,
+            amount,
+            side,
+        };
+        Ok(self.estimate_all(vec![query]).await?.pop().flatten())
+    }
+
+    /// Returns the marginal prices of the token pair over the liquidity of
+    /// all sources that trade it directly, see [`spot_price`].
+    ///
+    /// The sell amount is the trade size that marginal prices are probed with
+    /// a tiny fraction of, and that the depth of the liquidity is measured at.
+    pub async fn spot_price(
+        &self,
+        sell_token: H160,
+        buy_token: H160,
+        sell_amount: U256,
+    ) -> Result<Option<SpotPrice>> {
+        let pair = match TokenPair::new(sell_token, buy_token) {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+        let liquidity = self
+            .collector
+            .fetch(HashSet::from([pair]), Block::Recent)
+            .await?;
+        Ok(spot_price(&liquidity, sell_token, buy_token, sell_amount))
+    }
+
+    /// Returns the effective price curve of selling each of the sell amounts
+    /// with the liquidity of all sources that trade the pair directly, see
+    /// [`price_curve`].
+    ///
+    /// All amounts are quoted with one fetch of the liquidity and one pass
+    /// over each pool, for example to measure the depth of a market or to
+    /// decide how much of an order to fill.
+    pub async fn price_curve(
+        &self,
+        sell_token: H160,
+        buy_token: H160,
+        sell_amounts: &[U256],
+    ) -> Result<Vec<Option<PricePoint>>> {
+        let pair = match TokenPair::new(sell_token, buy_token) {
+            Some(pair) => pair,
+            None => return Ok(vec![None; sell_amounts.len()]),
+        };
+        let liquidity = self
+            .collector
+            .fetch(HashSet::from([pair]), Block::Recent)
+            .await?;
+        Ok(price_curve(&liquidity, sell_token, buy_token, sell_amounts))
+    }
+
+    /// Returns the best quote for each of the queries, in the same order.
+    ///
+    /// The liquidity for all queries is fetched at once, so pools that are
+    /// relevant for several queries are only fetched once, and the paths of
+    /// each token pair are only enumerated once for all of its amounts.
+    pub async fn estimate_all(&self, queries: Vec<Query>) -> Result<Vec<Option<Quote>>> {
+        let pairs = queries
+            .iter()
+            .filter_map(|query| TokenPair::new(query.sell_token, query.buy_token))
+            .collect::<HashSet<_>>();
+        if pairs.is_empty() {
+            return Ok(vec![None; queries.len()]);
+        }
+        let mut pairs = pairs;
+        if let Some(gas_pricing) = &self.gas_pricing {
+            pairs.extend(queries.iter().filter_map(|query| {
+                TokenPair::new(gas_pricing.native_token, query.estimated_token())
+            }));
+        }
+        let pairs = self.base_tokens.relevant_pairs(pairs.into_iter());
+        let liquidity = self.collector.fetch(pairs, Block::Recent).await?;
+        let liquidity = RoutingLiquidity::new(liquidity);
+
+        let gas_price_estimate = self
+            .gas_pricing
+            .as_ref()
+            .map(|gas_pricing| (gas_pricing, gas_pricing.gas_prices.borrow().clone()));
+        let mut native_prices = HashMap::new();
+        let mut paths = HashMap::new();
+        Ok(queries
+            .iter()
+            .map(|query| {
+                let gas = gas_price_estimate
+                    .as_ref()
+                    .and_then(|(gas_pricing, estimate)| {
+                        let token = query.estimated_token();
+                        let native_price = *native_prices.entry(token).or_insert_with(|| {
+                            liquidity.native_price(
+                                gas_pricing.native_token,
+                                token,
+                                &self.path_config,
+                            )
+                        });
+                        GasCostModel::new(estimate, gas_pricing.percentile, native_price?)
+                    })
+                    .unwrap_or(GasCostModel {
+                        gas_price: U256::zero(),
+                        native_token_price: U256::zero(),
+                    });
+                let paths = paths
+                    .entry((query.sell_token, query.buy_token))
+                    .or_insert_with(|| {
+                        liquidity.paths(query.sell_token, query.buy_token, &self.path_config)
+                    });
+                route(query, paths, &liquidity, &gas)
+            })
+            .collect())
+    }
+}
+
+/// A request for a quote.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Query {
+    pub sell_token: H160,
+    pub buy_token: H160,
+    /// The sell amount for sell queries, or the buy amount for buy queries.
+    pub amount: U256,
+    pub side: Side,
+}
+
+impl Query {
+    /// Returns the token that the estimated amount is denominated in.
+    fn estimated_token(&self) -> H160 {
+        match self.side {
+            Side::Sell => self.buy_token,
+            Side::Buy => self.sell_token,
+        }
+    }
+}
+
+/// Fetched liquidity indexed for routing.
+struct RoutingLiquidity {
+    by_pair: HashMap<TokenPair, Vec<Liquidity>>,
+    graph: LiquidityGraph<TokenPair>,
+}
+
+impl RoutingLiquidity {
+    fn new(liquidity: Vec<Liquidity>) -> Self {
+        let mut by_pair = HashMap::<_, Vec<_>>::new();
+        for liquidity in liquidity {
+            for pair in liquidity.token_pairs() {
+                by_pair.entry(pair).or_default().push(liquidity.clone());
+            }
+        }
+        let graph = LiquidityGraph::new(by_pair.keys().copied(), |pair| vec![*pair]);
+        Self { by_pair, graph }
+    }
+
+    fn paths(&self, sell_token: H160, buy_token: H160, config: &PathConfig) -> Vec<TokenPath> {
+        let mut paths = self.graph.paths(sell_token, buy_token, config.max_hops);
+        paths.truncate(config.max_candidates);
+        paths
+    }
+
+    /// Returns the amount of the token that one native token (1e18 atoms)
+    /// can be sold for along the best route.
+    fn native_price(&self, native_token: H160, token: H160, config: &PathConfig) -> Option<U256> {
+        let amount = U256::exp10(18);
+        if token == native_token {
+            return Some(amount);
+        }
+        self.paths(native_token, token, config)
+            .iter()
+            .filter_map(|path| estimate_buy_amount(amount, path.tokens(), &self.by_pair))
+            .map(|estimate| estimate.value)
+            .max()
+    }
+}
+
+/// Returns the best quote for the query along one of the paths, ranking the
+/// routes by their amounts net of gas costs.
+fn route(
+    query: &Query,
+    paths: &[TokenPath],
+    liquidity: &RoutingLiquidity,
+    gas: &GasCostModel,
+) -> Option<Quote> {
+    let amount = query.amount;
+    let paths = paths.iter();
+    let (path, estimate) = match query.side {
+        Side::Sell => paths
+            .filter_map(|path| {
+                let estimate = estimate_buy_amount(amount, path.tokens(), &liquidity.by_pair)?;
+                Some((path, estimate))
+            })
+            .max_by_key(|(_, estimate)| gas.net_buy_amount(estimate))?,
+        Side::Buy => paths
+            .filter_map(|path| {
+                let estimate = estimate_sell_amount(amount, path.tokens(), &liquidity.by_pair)?;
+                Some((path, estimate))
+            })
+            .min_by_key(|(_, estimate)| gas.gross_sell_amount(estimate))?,
+    };
+    let hops = route_hops(path, &estimate)?;
+    Some(Quote {
+        sell_token: query.sell_token,
+        buy_token: query.buy_token,
+        sell_amount: hops.first()?.sell_amount,
+        buy_amount: hops.last()?.buy_amount,
+        gas_cost: estimate.gas_cost(),
+        hops,
+    })
+}
+
+/// Returns the trades with each piece of liquidity along the path of an
+/// estimate.
+fn route_hops(path: &TokenPath, estimate: &Estimate<U256, Liquidity>) -> Option<Vec<Hop>> {
+    if estimate.amounts.len() != path.tokens().len() {
+        return None;
+    }
+    Some(
+        path.tokens()
+            .windows(2)
+            .zip(estimate.amounts.windows(2))
+            .zip(&estimate.path)
+            .map(|((tokens, amounts), liquidity)| Hop {
+                sell_token: tokens[0],
+                buy_token: tokens[1],
+                sell_amount: amounts[0],
+                buy_amount: amounts[1],
+                liquidity: (*liquidity).clone(),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gas_price::GasPriceEstimate,
+        sources::uniswap_v2::pool_fetching::{Pool, PoolFetching},
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::watch;
+
+    struct FakeConstantProductFetcher(Vec<Pool>, AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl PoolFetching for FakeConstantProductFetcher {
+        async fn fetch(&self, token_pairs: HashSet<TokenPair>, _: Block) -> Result<Vec<Pool>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Ok(self
+                .0
+                .iter()
+                .filter(|pool| token_pairs.contains(&pool.tokens))
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn token(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn pool(a: u64, b: u64, reserves: (u128, u128)) -> Pool {
+        Pool::uniswap(TokenPair::new(token(a), token(b)).unwrap(), reserves)
+    }
+
+    fn fetcher() -> Arc<FakeConstantProductFetcher> {
+        // The direct pool between tokens 1 and 2 is much shallower than the
+        // pools with the base token 3.
+        let pools = vec![
+            pool(1, 2, (10u128.pow(20), 10u128.pow(20))),
+            pool(1, 3, (10u128.pow(24), 10u128.pow(24))),
+            pool(3, 2, (10u128.pow(24), 10u128.pow(24))),
+            pool(1, 4, (10u128.pow(24), 10u128.pow(24))),
+        ];
+        Arc::new(FakeConstantProductFetcher(pools, Default::default()))
+    }
+
+    fn estimator(fetcher: Arc<FakeConstantProductFetcher>) -> QuoteEstimator {
+        let collector = LiquidityCollector {
+            constant_product: vec![fetcher],
+            ..Default::default()
+        };
+        QuoteEstimator::new(
+            Arc::new(collector),
+            Arc::new(BaseTokens::new(token(3), &[])),
+        )
+    }
+
+    #[tokio::test]
+    async fn quotes_best_route_with_hops() {
+        let estimator = estimator(fetcher());
+        let amount = U256::exp10(19);
+
+        let quote = estimator
+            .best_quote(token(1), token(2), amount, Side::Sell)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.sell_amount, amount);
+        assert_eq!(quote.hops.len(), 2);
+        assert_eq!(quote.hops[0].sell_token, token(1));
+        assert_eq!(quote.hops[0].buy_token, token(3));
+        assert_eq!(quote.hops[1].sell_amount, quote.hops[0].buy_amount);
+        assert_eq!(quote.hops[1].buy_token, token(2));
+        assert_eq!(quote.buy_amount, quote.hops[1].buy_amount);
+        assert_eq!(quote.gas_cost, 170_000);
+
+        let buy_quote = estimator
+            .best_quote(token(1), token(2), quote.buy_amount, Side::Buy)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buy_quote.buy_amount, quote.buy_amount);
+        assert_eq!(buy_quote.hops.len(), 2);
+        assert_eq!(buy_quote.hops[0].buy_amount, buy_quote.hops[1].sell_amount);
+        assert_eq!(buy_quote.sell_amount, buy_quote.hops[0].sell_amount);
+        assert!(buy_quote.sell_amount >= amount);
+
+        // Small trades are best executed with the direct pool.
+        let quote = estimator
+            .best_quote(token(1), token(2), 1000.into(), Side::Sell)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.hops.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ranks_routes_net_of_gas_costs() {
+        let amount = U256::exp10(19);
+        let quote = estimator(fetcher())
+            .best_quote(token(1), token(2), amount, Side::Sell)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.hops.len(), 2);
+
+        // At this gas price, the gas of the additional hop costs more than the
+        // better price of the deeper pools is worth.
+        let (_sender, gas_prices) = watch::channel(GasPriceEstimate {
+            next_base_fee: U256::from(3) * U256::exp10(13),
+            priority_fees: vec![(50., U256::zero())],
+            ..Default::default()
+        });
+        let gas_pricing = GasPricing {
+            gas_prices,
+            percentile: 50.,
+            native_token: token(3),
+        };
+        let priced = estimator(fetcher()).with_gas_pricing(gas_pricing.clone());
+        let quote = priced
+            .best_quote(token(1), token(2), amount, Side::Sell)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.hops.len(), 1);
+        let buy_quote = priced
+            .best_quote(token(1), token(2), quote.buy_amount, Side::Buy)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buy_quote.hops.len(), 1);
+
+        // Routes are ranked by their amounts alone if gas can't be priced in
+        // the estimated token.
+        let unpriced = estimator(fetcher()).with_gas_pricing(GasPricing {
+            native_token: token(5),
+            ..gas_pricing
+        });
+        let quote = unpriced
+            .best_quote(token(1), token(2), amount, Side::Sell)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.hops.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn no_quote_without_route() {
+        let estimator = estimator(fetcher());
+        assert!(estimator
+            .best_quote(token(1), token(5), 1000.into(), Side::Sell)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(estimator
+            .best_quote(token(1), token(1), 1000.into(), Side::Sell)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn estimates_all_queries_with_one_fetch() {
+        let fetcher = fetcher();
+        let estimator = estimator(fetcher.clone());
+        let query = |sell: u64, buy: u64, amount: u128, side: Side| Query {
+            sell_token: token(sell),
+            buy_token: token(buy),
+            amount: amount.into(),
+            side,
+        };
+        let queries = vec![
+            query(1, 2, 10u128.pow(19), Side::Sell),
+            query(1, 4, 1000, Side::Sell),
+            query(1, 5, 1000, Side::Sell),
+            query(1, 2, 10u128.pow(18), Side::Buy),
+            query(1, 1, 1000, Side::Buy),
+        ];
+
+        let quotes = estimator.estimate_all(queries.clone()).await.unwrap();
+        assert_eq!(fetcher.1.load(Ordering::SeqCst), 1);
+        assert_eq!(quotes.len(), queries.len());
+        for (query, quote) in queries.iter().zip(&quotes) {
+            let expected = estimator
+                .best_quote(query.sell_token, query.buy_token, query.amount, query.side)
+                .await
+                .unwrap();
+            assert_eq!(
+                quote
+                    .as_ref()
+                    .map(|quote| (quote.sell_amount, quote.buy_amount)),
+                expected.map(|quote| (quote.sell_amount, quote.buy_amount)),
+            );
+        }
+        assert!(quotes[0].is_some() && quotes[1].is_some() && quotes[3].is_some());
+        assert!(quotes[2].is_none() && quotes[4].is_none());
+    }
+
+    #[tokio::test]
+    async fn spot_price_of_directly_traded_pair() {
+        let estimator = estimator(fetcher());
+
+        let price = estimator
+            .spot_price(token(1), token(3), U256::exp10(18))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!((price.best - 0.997).abs() < 1e-3, "{:?}", price);
+        assert!((price.mid.unwrap() - 1.).abs() < 1e-3, "{:?}", price);
+
+        // Tokens 2 and 4 are only connected through other tokens.
+        assert!(estimator
+            .spot_price(token(2), token(4), U256::exp10(18))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn price_curve_of_directly_traded_pair() {
+        let fetcher = fetcher();
+        let estimator = estimator(fetcher.clone());
+        let amounts = [U256::exp10(15), U256::exp10(18), U256::exp10(21)];
+
+        let curve = estimator
+            .price_curve(token(1), token(3), &amounts)
+            .await
+            .unwrap();
+        assert_eq!(fetcher.1.load(Ordering::SeqCst), 1);
+        let prices = curve
+            .iter()
+            .map(|point| point.unwrap().price())
+            .collect::<Vec<_>>();
+        assert!((prices[0] - 0.997).abs() < 1e-3, "{:?}", prices);
+        assert!(
+            prices[0] > prices[1] && prices[1] > prices[2],
+            "{:?}",
+            prices
+        );
+
+        assert_eq!(
+            estimator
+                .price_curve(token(1), token(1), &amounts)
+                .await
+                .unwrap(),
+            vec![None; 3]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Caching quotes for the current block.
+//!
+//! Estimating prices for an auction requests quotes for the same token pairs
+//! many times within a block. Quotes are cached per token pair, side and
+//! amount bucket, where a bucket covers all amounts with the same most
+//! significant bits, and the whole cache is discarded when a new block arrives.
+//!
+//! The quote of a bucket is computed for its largest amount. Because of price
+//! impact, smaller amounts trade at a price that is at least as good, so
+//! scaling the quote down to them is conservative, and it doesn't depend on
+//! which amount of the bucket was requested first.
+
+use super::{Hop, Quote, QuoteEstimator, Side};
+use crate::{
+    current_block::CurrentBlockStream,
+    fixed_point::{mul_div, Rounding},
+};
+use anyhow::Result;
+use ethcontract::{H160, U256};
+use std::{collections::HashMap, sync::Mutex};
+
+/// Amounts within about a millionth of each other share a bucket by default.
+const DEFAULT_AMOUNT_PRECISION_BITS: usize = 20;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct QuoteKey {
+    sell_token: H160,
+    buy_token: H160,
+    side: Side,
+    /// The largest amount of the bucket, which the quote is computed for.
+    amount_bucket: U256,
+}
+
+#[derive(Default)]
+struct Cache {
+    block: u64,
+    quotes: HashMap<QuoteKey, Option<Quote>>,
+}
+
+/// A quote estimator that caches quotes for the current block.
+///
+/// Requests for amounts in the bucket of a cached quote are answered by
+/// scaling the cached quote linearly to the requested amount, which
+/// underestimates the price of the smaller amount by at most the price impact
+/// of the rest of the bucket.
+pub struct CachingQuoteEstimator {
+    inner: QuoteEstimator,
+    block_stream: CurrentBlockStream,
+    amount_precision_bits: usize,
+    cache: Mutex<Cache>,
+}
+
+impl CachingQuoteEstimator {
+    pub fn new(inner: QuoteEstimator, block_stream: CurrentBlockStream) -> Self {
+        Self {
+            inner,
+            block_stream,
+            amount_precision_bits: DEFAULT_AMOUNT_PRECISION_BITS,
+            cache: Default::default(),
+        }
+    }
+
+    /// Configures the number of most significant bits of amounts that are
+    /// distinguished by the cache.
+    pub fn with_amount_precision_bits(mut self, bits: usize) -> Self {
+        self.amount_precision_bits = bits;
+        self
+    }
+
+    /// Returns the best quote like [`QuoteEstimator::best_quote`], using the
+    /// cached quote of the current block if there is one.
+    pub async fn best_quote(
+        &self,
+        sell_token: H160,
+        buy_token: H160,
+        amount: U256,
+        side: Side,
+    ) -> Result<Option<Quote>> {
+        let block = self.block_stream.borrow().number;
+        let key = QuoteKey {
+            sell_token,
+            buy_token,
+            side,
+            amount_bucket: self.amount_bucket(amount),
+        };
+        let quote = match self.cached(block, &key) {
+            Some(quote) => quote,
+            None => {
+                // Don't hold the lock while computing the quote, so concurrent
+                // requests for the same key may both compute it.
+                let quote = self
+                    .inner
+                    .best_quote(sell_token, buy_token, key.amount_bucket, side)
+                    .await?;
+                self.insert(block, key, quote.clone());
+                quote
+            }
+        };
+        Ok(quote.and_then(|quote| scale(&quote, amount, side)))
+    }
+
+    /// Returns the largest amount with the same most significant bits.
+    fn amount_bucket(&self, amount: U256) -> U256 {
+        let shift = amount.bits().saturating_sub(self.amount_precision_bits);
+        amount | ((U256::one() << shift) - 1)
+    }
+
+    fn cached(&self, block: u64, key: &QuoteKey) -> Option<Option<Quote>> {
+        let cache = self.cache.lock().unwrap();
+        if cache.block != block {
+            return None;
+        }
+        cache.quotes.get(key).cloned()
+    }
+
+    fn insert(&self, block: u64, key: QuoteKey, quote: Option<Quote>) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.block < block {
+            cache.block = block;
+            cache.quotes.clear();
+        }
+        // Quotes computed for an older block are not cached after a new block
+        // arrived.
+        if cache.block == block {
+            cache.quotes.insert(key, quote);
+        }
+    }
+}
+
+/// Scales all amounts of the quote linearly so that its fixed amount on the
+/// specified side matches `amount`.
+///
+/// Sell amounts are rounded up and buy amounts down, so that the scaled quote
+/// never promises a better price than the cached one.
+fn scale(quote: &Quote, amount: U256, side: Side) -> Option<Quote> {
+    let quoted = match side {
+        Side::Sell => quote.sell_amount,
+        Side::Buy => quote.buy_amount,
+    };
+    if quoted == amount {
+        return Some(quote.clone());
+    }
+    let scale = |value: U256, rounding| mul_div(value, amount, quoted, rounding);
+    Some(Quote {
+        sell_amount: scale(quote.sell_amount, Rounding::Up)?,
+        buy_amount: scale(quote.buy_amount, Rounding::Down)?,
+        hops: quote
+            .hops
+            .iter()
+            .map(|hop| {
+                Some(Hop {
+                    sell_amount: scale(hop.sell_amount, Rounding::Up)?,
+                    buy_amount: scale(hop.buy_amount, Rounding::Down)?,
+                    ..hop.clone()
+                })
+            })
+            .collect::<Option<_>>()?,
+        ..quote.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        baseline_solver::BaseTokens,
+        current_block::BlockInfo,
+        liquidity::LiquidityCollector,
+        recent_block_cache::Block,
+        sources::uniswap_v2::pool_fetching::{Pool, PoolFetching},
+        token_pair::TokenPair,
+    };
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::sync::watch;
+
+    #[derive(Default)]
+    struct CountingFetcher(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl PoolFetching for CountingFetcher {
+        async fn fetch(&self, _: HashSet<TokenPair>, _: Block) -> Result<Vec<Pool>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            let pair = TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap();
+            Ok(vec![Pool::uniswap(pair, (10u128.pow(24), 10u128.pow(24)))])
+        }
+    }
+
+    fn quote_estimator(fetcher: Arc<CountingFetcher>) -> QuoteEstimator {
+        let collector = LiquidityCollector {
+            constant_product: vec![fetcher],
+            ..Default::default()
+        };
+        QuoteEstimator::new(
+            Arc::new(collector),
+            Arc::new(BaseTokens::new(H160([3; 20]), &[])),
+        )
+    }
+
+    #[tokio::test]
+    async fn caches_quotes_of_current_block() {
+        let fetcher = Arc::new(CountingFetcher::default());
+        let inner = quote_estimator(fetcher.clone());
+        let (sender, receiver) = watch::channel(BlockInfo {
+            number: 1,
+            ..Default::default()
+        });
+        let estimator = CachingQuoteEstimator::new(inner, receiver);
+        let fetches = || fetcher.0.load(Ordering::SeqCst);
+        let quote =
+            |amount: U256| estimator.best_quote(H160([1; 20]), H160([2; 20]), amount, Side::Sell);
+
+        let amount = U256::exp10(18);
+        let first = quote(amount).await.unwrap().unwrap();
+        let second = quote(amount).await.unwrap().unwrap();
+        assert_eq!(fetches(), 1);
+        assert_eq!(second.buy_amount, first.buy_amount);
+
+        // Amounts in the same bucket are scaled from the cached quote.
+        let scaled = quote(amount + 1).await.unwrap().unwrap();
+        assert_eq!(fetches(), 1);
+        assert_eq!(scaled.sell_amount, amount + 1);
+        assert_eq!(scaled.hops[0].sell_amount, amount + 1);
+        assert!(scaled.buy_amount >= first.buy_amount);
+
+        quote(amount * 2).await.unwrap().unwrap();
+        assert_eq!(fetches(), 2);
+
+        // A new block invalidates all cached quotes.
+        sender
+            .send(BlockInfo {
+                number: 2,
+                ..Default::default()
+            })
+            .unwrap();
+        quote(amount).await.unwrap().unwrap();
+        assert_eq!(fetches(), 3);
+    }
+
+    #[tokio::test]
+    async fn scaled_quotes_are_conservative() {
+        let (sell_token, buy_token) = (H160([1; 20]), H160([2; 20]));
+        let inner = quote_estimator(Default::default());
+        let estimator = || {
+            CachingQuoteEstimator::new(
+                quote_estimator(Default::default()),
+                watch::channel(Default::default()).1,
+            )
+            // Large buckets make the price impact within them noticeable.
+            .with_amount_precision_bits(4)
+        };
+        // Both amounts are in the bucket from 13 * 2^66 to 14 * 2^66 - 1.
+        let small = U256::exp10(21);
+        let large = small + small / 50;
+
+        for side in [Side::Sell, Side::Buy] {
+            let exact = inner
+                .best_quote(sell_token, buy_token, small, side)
+                .await
+                .unwrap()
+                .unwrap();
+
+            let small_first = estimator();
+            let cached = small_first
+                .best_quote(sell_token, buy_token, small, side)
+                .await
+                .unwrap()
+                .unwrap();
+            let scaled = small_first
+                .best_quote(sell_token, buy_token, large, side)
+                .await
+                .unwrap()
+                .unwrap();
+            match side {
+                Side::Sell => assert!(cached.buy_amount < exact.buy_amount),
+                Side::Buy => assert!(cached.sell_amount > exact.sell_amount),
+            }
+
+            // The cached quote doesn't depend on the order of the requests.
+            let large_first = estimator();
+            let large_first_scaled = large_first
+                .best_quote(sell_token, buy_token, large, side)
+                .await
+                .unwrap()
+                .unwrap();
+            let large_first_cached = large_first
+                .best_quote(sell_token, buy_token, small, side)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(large_first_cached.sell_amount, cached.sell_amount);
+            assert_eq!(large_first_cached.buy_amount, cached.buy_amount);
+            assert_eq!(large_first_scaled.sell_amount, scaled.sell_amount);
+            assert_eq!(large_first_scaled.buy_amount, scaled.buy_amount);
+        }
+    }
+
+    #[test]
+    fn buckets_amounts_by_most_significant_bits() {
+        let amount_bucket = |bits, amount: u64| {
+            let estimator = CachingQuoteEstimator {
+                inner: QuoteEstimator::new(
+                    Default::default(),
+                    Arc::new(BaseTokens::new(H160::zero(), &[])),
+                ),
+                block_stream: watch::channel(Default::default()).1,
+                amount_precision_bits: bits,
+                cache: Default::default(),
+            };
+            estimator.amount_bucket(amount.into())
+        };
+        assert_eq!(amount_bucket(4, 0b1011_0111), 0b1011_1111.into());
+        assert_eq!(amount_bucket(4, 0b1011_0000), 0b1011_1111.into());
+        assert_eq!(amount_bucket(4, 0b101), 0b101.into());
+        assert_eq!(amount_bucket(0, 0b101), 0b111.into());
+    }
+}
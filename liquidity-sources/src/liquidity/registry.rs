@@ -0,0 +1,219 @@
+//! Registration of liquidity sources implemented outside of this crate.
+//!
+//! Downstream crates can register their own liquidity sources along with
+//! metadata describing them, and install them into a `LiquidityCollector` and
+//! the service maintenance without any changes to this crate.
+
+use super::{LiquidityCollector, LiquidityFetching};
+use crate::maintenance::Maintaining;
+use anyhow::{ensure, Result};
+use std::sync::Arc;
+
+/// Metadata describing a registered liquidity source.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceMetadata {
+    /// The unique name of the source, used for identifying it in logs.
+    pub name: String,
+    /// The chains the source supports, or `None` if it supports all chains.
+    pub chain_ids: Option<Vec<u64>>,
+}
+
+impl SourceMetadata {
+    /// Returns `true` if the source supports the specified chain.
+    pub fn supports_chain(&self, chain_id: u64) -> bool {
+        self.chain_ids
+            .as_ref()
+            .map(|chain_ids| chain_ids.contains(&chain_id))
+            .unwrap_or(true)
+    }
+}
+
+struct RegisteredSource {
+    metadata: SourceMetadata,
+    fetcher: Arc<dyn LiquidityFetching>,
+    maintainer: Option<Arc<dyn Maintaining>>,
+}
+
+/// A registry of externally implemented liquidity sources.
+#[derive(Default)]
+pub struct SourceRegistry {
+    sources: Vec<RegisteredSource>,
+}
+
+impl SourceRegistry {
+    /// Registers a liquidity source.
+    ///
+    /// Returns an error if a source with the same name is already registered.
+    pub fn register(
+        &mut self,
+        metadata: SourceMetadata,
+        fetcher: Arc<dyn LiquidityFetching>,
+    ) -> Result<()> {
+        self.insert(metadata, fetcher, None)
+    }
+
+    /// Registers a liquidity source that needs to run maintenance on new
+    /// blocks, for example to keep an index of its pools up to date.
+    ///
+    /// Returns an error if a source with the same name is already registered.
+    pub fn register_maintained<T>(&mut self, metadata: SourceMetadata, source: Arc<T>) -> Result<()>
+    where
+        T: LiquidityFetching + Maintaining + 'static,
+    {
+        self.insert(metadata, source.clone(), Some(source))
+    }
+
+    fn insert(
+        &mut self,
+        metadata: SourceMetadata,
+        fetcher: Arc<dyn LiquidityFetching>,
+        maintainer: Option<Arc<dyn Maintaining>>,
+    ) -> Result<()> {
+        ensure!(
+            self.sources
+                .iter()
+                .all(|source| source.metadata.name != metadata.name),
+            "liquidity source {} is already registered",
+            metadata.name,
+        );
+        self.sources.push(RegisteredSource {
+            metadata,
+            fetcher,
+            maintainer,
+        });
+        Ok(())
+    }
+
+    /// Returns the metadata of all registered sources.
+    pub fn sources(&self) -> impl Iterator<Item = &SourceMetadata> + '_ {
+        self.sources.iter().map(|source| &source.metadata)
+    }
+
+    /// Adds the fetchers of all registered sources that support the specified
+    /// chain to the collector, and returns their maintainers.
+    pub fn install(
+        &self,
+        chain_id: u64,
+        collector: &mut LiquidityCollector,
+    ) -> Vec<Arc<dyn Maintaining>> {
+        let mut maintainers = Vec::new();
+        for source in &self.sources {
+            if !source.metadata.supports_chain(chain_id) {
+                tracing::debug!(
+                    source = %source.metadata.name, %chain_id,
+                    "skipping liquidity source for unsupported chain",
+                );
+                continue;
+            }
+            collector.external.push(source.fetcher.clone());
+            maintainers.extend(source.maintainer.clone());
+        }
+        maintainers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        liquidity::{LimitOrder, Liquidity},
+        recent_block_cache::Block,
+        token_pair::TokenPair,
+    };
+    use ethcontract::H160;
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[derive(Default)]
+    struct FakeSource {
+        maintenance_runs: AtomicUsize,
+    }
+
+    fn order() -> LimitOrder {
+        LimitOrder {
+            id: "order".to_owned(),
+            sell_token: H160([1; 20]),
+            buy_token: H160([2; 20]),
+            sell_amount: 1_000.into(),
+            buy_amount: 2_000.into(),
+            partially_fillable: false,
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LiquidityFetching for FakeSource {
+        async fn fetch(&self, _: HashSet<TokenPair>, _: Block) -> Result<Vec<Liquidity>> {
+            Ok(vec![Liquidity::LimitOrder(order())])
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Maintaining for FakeSource {
+        async fn run_maintenance(&self) -> Result<()> {
+            self.maintenance_runs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn metadata(name: &str, chain_ids: Option<Vec<u64>>) -> SourceMetadata {
+        SourceMetadata {
+            name: name.to_owned(),
+            chain_ids,
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_source_names() {
+        let mut registry = SourceRegistry::default();
+        registry
+            .register(metadata("fake", None), Arc::new(FakeSource::default()))
+            .unwrap();
+        assert!(registry
+            .register(
+                metadata("fake", Some(vec![1])),
+                Arc::new(FakeSource::default())
+            )
+            .is_err());
+        assert_eq!(registry.sources().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn installs_sources_for_supported_chains() {
+        let maintained = Arc::new(FakeSource::default());
+
+        let mut registry = SourceRegistry::default();
+        registry
+            .register(metadata("any", None), Arc::new(FakeSource::default()))
+            .unwrap();
+        registry
+            .register_maintained(metadata("mainnet", Some(vec![1])), maintained.clone())
+            .unwrap();
+        registry
+            .register(
+                metadata("gnosis", Some(vec![100])),
+                Arc::new(FakeSource::default()),
+            )
+            .unwrap();
+
+        let mut collector = LiquidityCollector::default();
+        let maintainers = registry.install(1, &mut collector);
+        assert_eq!(collector.external.len(), 2);
+        assert_eq!(maintainers.len(), 1);
+
+        for maintainer in &maintainers {
+            maintainer.run_maintenance().await.unwrap();
+        }
+        assert_eq!(maintained.maintenance_runs.load(Ordering::SeqCst), 1);
+
+        let liquidity = collector
+            .fetch(Default::default(), Block::Recent)
+            .await
+            .unwrap();
+        assert!(liquidity
+            .iter()
+            .all(|liquidity| matches!(liquidity, Liquidity::LimitOrder(o) if *o == order())));
+        assert_eq!(liquidity.len(), 2);
+    }
+}
@@ -0,0 +1,158 @@
+//! Limits on the amounts of trades for a slippage tolerance.
+//!
+//! Amounts are in token atoms, so rounding the minimum received amount down
+//! and the maximum sent amount up to the nearest atom never tolerates more
+//! slippage than configured, whatever the decimals of the token.
+
+use super::quote::{Quote, Side};
+use ethcontract::U256;
+use primitive_types::U512;
+use std::convert::TryFrom;
+
+/// Slippage is specified in basis points.
+const BPS_BASE: u32 = 10_000;
+
+/// A relative slippage tolerance in basis points, between 0 and 100%.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Slippage(u32);
+
+impl Slippage {
+    /// Creates a slippage tolerance, or returns `None` if it exceeds 100%.
+    pub fn from_bps(bps: u32) -> Option<Self> {
+        if bps > BPS_BASE {
+            return None;
+        }
+        Some(Self(bps))
+    }
+
+    pub fn bps(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns the amount reduced by the slippage, rounded down.
+    pub fn min_received(&self, amount: U256) -> U256 {
+        let (quotient, _) = scale(amount, BPS_BASE - self.0);
+        // Scaling by at most 100% can't overflow.
+        U256::try_from(quotient).unwrap_or_else(|_| U256::max_value())
+    }
+
+    /// Returns the amount increased by the slippage, rounded up, or `None` if
+    /// it overflows.
+    pub fn max_sent(&self, amount: U256) -> Option<U256> {
+        let (quotient, remainder) = scale(amount, BPS_BASE + self.0);
+        let quotient = U256::try_from(quotient).ok()?;
+        if remainder.is_zero() {
+            Some(quotient)
+        } else {
+            quotient.checked_add(U256::one())
+        }
+    }
+}
+
+/// Returns `amount * bps / BPS_BASE` with full precision, along with the
+/// remainder of the division.
+fn scale(amount: U256, bps: u32) -> (U512, U512) {
+    amount
+        .full_mul(U256::from(bps))
+        .div_mod(U512::from(BPS_BASE))
+}
+
+/// The limits of a quoted trade for a slippage tolerance.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// The maximum amount of sell tokens that is sent.
+    pub max_sell_amount: U256,
+    /// The minimum amount of buy tokens that is received.
+    pub min_buy_amount: U256,
+}
+
+impl Quote {
+    /// Returns the limits for executing the quote with the slippage.
+    ///
+    /// The fixed amount of the side of the quote is kept exactly, and only the
+    /// other amount is adjusted for the slippage. Returns `None` if the
+    /// maximum sell amount overflows.
+    pub fn limits(&self, side: Side, slippage: Slippage) -> Option<Limits> {
+        Some(match side {
+            Side::Sell => Limits {
+                max_sell_amount: self.sell_amount,
+                min_buy_amount: slippage.min_received(self.buy_amount),
+            },
+            Side::Buy => Limits {
+                max_sell_amount: slippage.max_sent(self.sell_amount)?,
+                min_buy_amount: self.buy_amount,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethcontract::H160;
+
+    #[test]
+    fn rounds_in_favour_of_the_trader() {
+        let slippage = Slippage::from_bps(50).unwrap();
+        assert_eq!(slippage.min_received(1_000.into()), 995.into());
+        assert_eq!(slippage.min_received(999.into()), 994.into());
+        assert_eq!(slippage.max_sent(1_000.into()), Some(1_005.into()));
+        assert_eq!(slippage.max_sent(999.into()), Some(1_004.into()));
+        // Even the smallest amounts are rounded conservatively.
+        assert_eq!(slippage.min_received(1.into()), 0.into());
+        assert_eq!(slippage.max_sent(1.into()), Some(2.into()));
+
+        assert_eq!(
+            slippage.min_received(U256::max_value()),
+            U256::max_value() - U256::max_value() / 200 - 1
+        );
+        assert_eq!(slippage.max_sent(U256::max_value()), None);
+        assert_eq!(
+            Slippage::default().max_sent(U256::max_value()),
+            Some(U256::max_value())
+        );
+    }
+
+    #[test]
+    fn bounds_slippage() {
+        assert_eq!(
+            Slippage::from_bps(10_000)
+                .unwrap()
+                .min_received(1_000.into()),
+            0.into()
+        );
+        assert_eq!(
+            Slippage::from_bps(10_000).unwrap().max_sent(1_000.into()),
+            Some(2_000.into())
+        );
+        assert_eq!(Slippage::from_bps(10_001), None);
+    }
+
+    #[test]
+    fn limits_of_quotes() {
+        let quote = Quote {
+            sell_token: H160([1; 20]),
+            buy_token: H160([2; 20]),
+            sell_amount: 1_000.into(),
+            buy_amount: 2_000.into(),
+            hops: Vec::new(),
+            gas_cost: 0,
+        };
+        let slippage = Slippage::from_bps(100).unwrap();
+
+        assert_eq!(
+            quote.limits(Side::Sell, slippage),
+            Some(Limits {
+                max_sell_amount: 1_000.into(),
+                min_buy_amount: 1_980.into(),
+            })
+        );
+        assert_eq!(
+            quote.limits(Side::Buy, slippage),
+            Some(Limits {
+                max_sell_amount: 1_010.into(),
+                min_buy_amount: 2_000.into(),
+            })
+        );
+    }
+}
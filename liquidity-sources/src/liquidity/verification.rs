@@ -0,0 +1,572 @@
+//! Verifying quoted routes by simulating them against a node.
+//!
+//! Routes are quoted with local ports of the pools' math and with pool state
+//! that may be slightly outdated, so bugs in either misprice trades. Verifying
+//! a route simulates each of its hops with the quoting functions of the pools'
+//! contracts in an `eth_call` at the block the route was quoted at, and rejects
+//! the route if a simulated amount deviates from its quoted amount by more than
+//! a tolerance.
+//!
+//! Concentrated liquidity is simulated with a Uniswap `QuoterV2`, Balancer
+//! pools with the vault's `queryBatchSwap` and Curve pools with `get_dy`. Other
+//! liquidity has no quoting function and its hops are not simulated.
+//!
+//! The tokens of rejected hops are reported to the bad token quarantine, as
+//! tokens that charge fees or block transfers make pools deviate from their
+//! quoted amounts, see [`crate::bad_token`].
+
+use super::{
+    interaction::{encode_call, GIVEN_IN, GIVEN_OUT},
+    quote::{Hop, Quote, Side},
+    Liquidity, StableLiquidity,
+};
+use crate::{bad_token::BadTokenQuarantine, Web3};
+use anyhow::{bail, ensure, Context as _, Result};
+use ethcontract::{BlockId, H160, H256, U256};
+use std::sync::Arc;
+use web3::{
+    ethabi::{self, ParamType, Token},
+    types::CallRequest,
+};
+
+/// Simulates the hops of quoted routes with the contracts of their liquidity.
+///
+/// Hops are only simulated with liquidity whose quoting contracts are
+/// configured.
+#[derive(Clone)]
+pub struct RouteVerifier {
+    pub web3: Web3,
+    /// A Uniswap `QuoterV2` compatible quoter for concentrated liquidity
+    /// pools.
+    pub quoter: Option<H160>,
+    pub balancer_vault: Option<H160>,
+    /// The largest relative deviation of a simulated amount from its quoted
+    /// amount, in either direction.
+    pub tolerance: f64,
+    /// The quarantine that the tokens of rejected hops are reported to as
+    /// suspicious.
+    pub bad_tokens: Option<Arc<BadTokenQuarantine>>,
+}
+
+/// The outcome of verifying a route.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verification {
+    /// The simulated amounts of all hops that could be simulated are within
+    /// the tolerance.
+    Verified { simulated_hops: usize },
+    /// The simulated amount of a hop deviates from its quoted amount.
+    Rejected {
+        /// The index of the hop in the route.
+        hop: usize,
+        quoted: U256,
+        simulated: U256,
+    },
+}
+
+/// A call simulating a hop, along with the quoted amount that its result is
+/// compared to.
+struct Simulation {
+    target: H160,
+    call_data: Vec<u8>,
+    output: Output,
+    quoted: U256,
+    /// The contract holding the tokens of the hop's liquidity.
+    holder: H160,
+}
+
+/// The encoding of the simulated amount in the output of a call.
+enum Output {
+    /// The amount is the first return value.
+    Amount,
+    /// The return value are the Balancer vault's asset deltas, and the amount
+    /// is the delta of the asset with the index, which is negative for assets
+    /// that leave the vault.
+    AssetDelta(usize),
+}
+
+impl RouteVerifier {
+    /// Simulates the hops of the quote at the block it was quoted at.
+    ///
+    /// Hops of sell quotes are simulated for their sell amounts and compared by
+    /// their buy amounts, and hops of buy quotes the other way around. Curve
+    /// pools can only be simulated for sell amounts, like they are executed.
+    /// Returns an error if a simulation fails, for example because the swap
+    /// reverts.
+    pub async fn verify(&self, quote: &Quote, side: Side, block: BlockId) -> Result<Verification> {
+        let mut simulated_hops = 0;
+        for (index, hop) in quote.hops.iter().enumerate() {
+            let simulation = match self.simulation(hop, side) {
+                Some(simulation) => simulation,
+                None => continue,
+            };
+            let request = CallRequest {
+                to: Some(simulation.target),
+                data: Some(simulation.call_data.into()),
+                ..Default::default()
+            };
+            let output = self
+                .web3
+                .eth()
+                .call(request, Some(block))
+                .await
+                .with_context(|| format!("failed to simulate hop {}", index))?;
+            let simulated = simulation
+                .output
+                .decode(&output.0)
+                .with_context(|| format!("invalid simulation output of hop {}", index))?;
+            if deviation(simulation.quoted, simulated) > self.tolerance {
+                if let Some(bad_tokens) = &self.bad_tokens {
+                    bad_tokens.suspect(hop.sell_token, simulation.holder);
+                    bad_tokens.suspect(hop.buy_token, simulation.holder);
+                }
+                return Ok(Verification::Rejected {
+                    hop: index,
+                    quoted: simulation.quoted,
+                    simulated,
+                });
+            }
+            simulated_hops += 1;
+        }
+        Ok(Verification::Verified { simulated_hops })
+    }
+
+    /// Returns the call simulating the hop, or `None` if its liquidity can't be
+    /// simulated.
+    fn simulation(&self, hop: &Hop, side: Side) -> Option<Simulation> {
+        match &hop.liquidity {
+            Liquidity::ConcentratedLiquidity(pool) => {
+                let fee = pool.state.fee;
+                let fee = U256::from(*fee.numer()) * 1_000_000 / U256::from(*fee.denom());
+                let (signature, amount, quoted) = match side {
+                    Side::Sell => (
+                        "quoteExactInputSingle((address,address,uint256,uint24,uint160))",
+                        hop.sell_amount,
+                        hop.buy_amount,
+                    ),
+                    Side::Buy => (
+                        "quoteExactOutputSingle((address,address,uint256,uint24,uint160))",
+                        hop.buy_amount,
+                        hop.sell_amount,
+                    ),
+                };
+                let call_data = encode_call(
+                    signature,
+                    &[Token::Tuple(vec![
+                        Token::Address(hop.sell_token),
+                        Token::Address(hop.buy_token),
+                        Token::Uint(amount),
+                        Token::Uint(fee),
+                        // No price limit.
+                        Token::Uint(U256::zero()),
+                    ])],
+                );
+                Some(Simulation {
+                    target: self.quoter?,
+                    call_data,
+                    output: Output::Amount,
+                    quoted,
+                    holder: pool.address,
+                })
+            }
+            Liquidity::WeightedProduct(pool) => self.balancer_simulation(hop, side, pool.common.id),
+            Liquidity::Stable(StableLiquidity::Balancer(pool)) => {
+                self.balancer_simulation(hop, side, pool.common.id)
+            }
+            Liquidity::Stable(StableLiquidity::BalancerComposable(pool)) => {
+                self.balancer_simulation(hop, side, pool.common.id)
+            }
+            Liquidity::Linear(pool) => self.balancer_simulation(hop, side, pool.common.id),
+            Liquidity::EllipticConcentratedLiquidity(pool) => {
+                self.balancer_simulation(hop, side, pool.common.id)
+            }
+            Liquidity::Stable(StableLiquidity::Curve(pool)) => {
+                let exchange = pool.exchange(hop.sell_token, hop.buy_token)?;
+                let signature = if exchange.underlying {
+                    "get_dy_underlying(int128,int128,uint256)"
+                } else {
+                    "get_dy(int128,int128,uint256)"
+                };
+                let call_data = encode_call(
+                    signature,
+                    &[
+                        Token::Int(exchange.i.into()),
+                        Token::Int(exchange.j.into()),
+                        Token::Uint(hop.sell_amount),
+                    ],
+                );
+                Some(Simulation {
+                    target: pool.address,
+                    call_data,
+                    output: Output::Amount,
+                    quoted: hop.buy_amount,
+                    holder: pool.address,
+                })
+            }
+            Liquidity::ConstantProduct(_) | Liquidity::LimitOrder(_) | Liquidity::QuoteOnly(_) => {
+                None
+            }
+        }
+    }
+
+    fn balancer_simulation(&self, hop: &Hop, side: Side, pool_id: H256) -> Option<Simulation> {
+        // The assets are the sell token followed by the buy token.
+        let (kind, amount, quoted, index) = match side {
+            Side::Sell => (GIVEN_IN, hop.sell_amount, hop.buy_amount, 1),
+            Side::Buy => (GIVEN_OUT, hop.buy_amount, hop.sell_amount, 0),
+        };
+        let call_data = encode_call(
+            "queryBatchSwap(uint8,(bytes32,uint256,uint256,uint256,bytes)[],address[],\
+             (address,bool,address,bool))",
+            &[
+                Token::Uint(kind.into()),
+                Token::Array(vec![Token::Tuple(vec![
+                    Token::FixedBytes(pool_id.as_bytes().to_vec()),
+                    Token::Uint(0.into()),
+                    Token::Uint(1.into()),
+                    Token::Uint(amount),
+                    Token::Bytes(Vec::new()),
+                ])]),
+                Token::Array(vec![
+                    Token::Address(hop.sell_token),
+                    Token::Address(hop.buy_token),
+                ]),
+                // Queries don't transfer any tokens.
+                Token::Tuple(vec![
+                    Token::Address(H160::zero()),
+                    Token::Bool(false),
+                    Token::Address(H160::zero()),
+                    Token::Bool(false),
+                ]),
+            ],
+        );
+        // The vault holds the tokens of all Balancer pools.
+        let vault = self.balancer_vault?;
+        Some(Simulation {
+            target: vault,
+            call_data,
+            output: Output::AssetDelta(index),
+            quoted,
+            holder: vault,
+        })
+    }
+}
+
+impl Output {
+    fn decode(&self, output: &[u8]) -> Result<U256> {
+        match self {
+            Output::Amount => {
+                ensure!(output.len() >= 32, "missing amount");
+                Ok(U256::from_big_endian(&output[..32]))
+            }
+            Output::AssetDelta(index) => {
+                let deltas =
+                    ethabi::decode(&[ParamType::Array(Box::new(ParamType::Int(256)))], output)?;
+                let delta = match deltas.as_slice() {
+                    [Token::Array(deltas)] => deltas.get(*index).cloned(),
+                    _ => None,
+                };
+                match delta {
+                    Some(Token::Int(delta)) if delta.bit(255) => {
+                        Ok((!delta).saturating_add(1.into()))
+                    }
+                    Some(Token::Int(delta)) => Ok(delta),
+                    _ => bail!("missing asset delta"),
+                }
+            }
+        }
+    }
+}
+
+/// Returns the relative deviation of the simulated amount from the quoted
+/// amount.
+fn deviation(quoted: U256, simulated: U256) -> f64 {
+    let difference = if quoted > simulated {
+        quoted - simulated
+    } else {
+        simulated - quoted
+    };
+    if difference.is_zero() {
+        return 0.;
+    }
+    difference.to_f64_lossy() / quoted.to_f64_lossy()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bad_token::{MockBadTokenDetecting, TokenQuality},
+        maintenance::Maintaining,
+        sources::{
+            balancer_v2::{
+                pool_fetching::{CommonPoolState, WeightedPool},
+                swap::fixed_point::Bfp,
+            },
+            uniswap_v2::pool_fetching::Pool,
+            uniswap_v3::{
+                graph_api::Token as PoolToken,
+                pool_fetching::{PoolInfo, PoolState},
+            },
+        },
+        test::test_transport::TestTransport,
+        token_filter::TokenFilter,
+        token_pair::TokenPair,
+    };
+    use ethcontract::{dyns::DynTransport, BlockNumber};
+    use hex_literal::hex;
+    use maplit::hashset;
+    use num::rational::Ratio;
+    use serde_json::{json, Value};
+    use web3::types::Bytes;
+
+    fn token(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn hop(sell: u64, buy: u64, sell_amount: u64, buy_amount: u64, liquidity: Liquidity) -> Hop {
+        Hop {
+            sell_token: token(sell),
+            buy_token: token(buy),
+            sell_amount: sell_amount.into(),
+            buy_amount: buy_amount.into(),
+            liquidity,
+        }
+    }
+
+    fn quote(hops: Vec<Hop>) -> Quote {
+        Quote {
+            sell_token: hops[0].sell_token,
+            buy_token: hops[hops.len() - 1].buy_token,
+            sell_amount: hops[0].sell_amount,
+            buy_amount: hops[hops.len() - 1].buy_amount,
+            gas_cost: 0,
+            hops,
+        }
+    }
+
+    fn concentrated(a: u64, b: u64) -> Liquidity {
+        let pool_token = |n| PoolToken {
+            id: token(n),
+            symbol: String::new(),
+            decimals: 18,
+        };
+        Liquidity::ConcentratedLiquidity(PoolInfo {
+            address: H160([0xcc; 20]),
+            tokens: vec![pool_token(a), pool_token(b)],
+            state: PoolState {
+                fee: Ratio::new(3_000, 1_000_000),
+                ..Default::default()
+            },
+            gas_stats: Default::default(),
+        })
+    }
+
+    fn weighted() -> Liquidity {
+        Liquidity::WeightedProduct(WeightedPool {
+            common: CommonPoolState {
+                id: H256([0xee; 32]),
+                address: H160([0xee; 20]),
+                swap_fee: Bfp::zero(),
+                paused: false,
+            },
+            reserves: Default::default(),
+        })
+    }
+
+    fn constant_product(a: u64, b: u64) -> Liquidity {
+        Liquidity::ConstantProduct(Pool::uniswap(
+            TokenPair::new(token(a), token(b)).unwrap(),
+            (1_000_000, 1_000_000),
+        ))
+    }
+
+    fn verifier(transport: &TestTransport) -> RouteVerifier {
+        RouteVerifier {
+            web3: Web3::new(DynTransport::new(transport.clone())),
+            quoter: Some(H160([0x03; 20])),
+            balancer_vault: Some(H160([0xba; 20])),
+            tolerance: 0.001,
+            bad_tokens: None,
+        }
+    }
+
+    fn output(tokens: &[Token]) -> Value {
+        serde_json::to_value(Bytes(ethabi::encode(tokens))).unwrap()
+    }
+
+    fn quoter_output(amount: u64) -> Value {
+        output(&[
+            Token::Uint(amount.into()),
+            Token::Uint(U256::one() << 96),
+            Token::Uint(1.into()),
+            Token::Uint(100_000.into()),
+        ])
+    }
+
+    fn asset_deltas(amount_in: u64, amount_out: u64) -> Value {
+        output(&[Token::Array(vec![
+            Token::Int(amount_in.into()),
+            Token::Int(U256::zero().overflowing_sub(amount_out.into()).0),
+        ])])
+    }
+
+    fn decode_call(request: &Value, selector: [u8; 4], types: &[ParamType]) -> Vec<Token> {
+        let call_data = serde_json::from_value::<Bytes>(request["data"].clone())
+            .unwrap()
+            .0;
+        assert_eq!(call_data[..4], selector);
+        ethabi::decode(types, &call_data[4..]).unwrap()
+    }
+
+    fn block() -> BlockId {
+        BlockId::Number(BlockNumber::Number(42.into()))
+    }
+
+    #[tokio::test]
+    async fn verifies_routes_within_tolerance() {
+        let mut transport = TestTransport::new();
+        let verifier = verifier(&transport);
+        let route = quote(vec![
+            hop(1, 2, 1_000_000, 900_000, concentrated(1, 2)),
+            hop(2, 3, 900_000, 800_000, weighted()),
+            hop(3, 4, 800_000, 700_000, constant_product(3, 4)),
+        ]);
+
+        transport.add_response(quoter_output(900_100));
+        transport.add_response(asset_deltas(900_000, 799_500));
+        assert_eq!(
+            verifier.verify(&route, Side::Sell, block()).await.unwrap(),
+            Verification::Verified { simulated_hops: 2 }
+        );
+
+        let params = transport.assert_request_params("eth_call");
+        assert_eq!(params[0]["to"], json!(H160([0x03; 20])));
+        assert_eq!(params[1], json!("0x2a"));
+        let args = decode_call(
+            &params[0],
+            hex!("c6a5026a"),
+            &[ParamType::Tuple(vec![
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::Uint(24),
+                ParamType::Uint(160),
+            ])],
+        );
+        assert_eq!(
+            args,
+            vec![Token::Tuple(vec![
+                Token::Address(token(1)),
+                Token::Address(token(2)),
+                Token::Uint(1_000_000.into()),
+                Token::Uint(3_000.into()),
+                Token::Uint(0.into()),
+            ])]
+        );
+
+        let params = transport.assert_request_params("eth_call");
+        assert_eq!(params[0]["to"], json!(H160([0xba; 20])));
+        let args = decode_call(
+            &params[0],
+            hex!("f84d066e"),
+            &[
+                ParamType::Uint(8),
+                ParamType::Array(Box::new(ParamType::Tuple(vec![
+                    ParamType::FixedBytes(32),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Bytes,
+                ]))),
+                ParamType::Array(Box::new(ParamType::Address)),
+                ParamType::Tuple(vec![
+                    ParamType::Address,
+                    ParamType::Bool,
+                    ParamType::Address,
+                    ParamType::Bool,
+                ]),
+            ],
+        );
+        assert_eq!(args[0], Token::Uint(GIVEN_IN.into()));
+        assert_eq!(
+            args[1],
+            Token::Array(vec![Token::Tuple(vec![
+                Token::FixedBytes(vec![0xee; 32]),
+                Token::Uint(0.into()),
+                Token::Uint(1.into()),
+                Token::Uint(900_000.into()),
+                Token::Bytes(Vec::new()),
+            ])])
+        );
+        assert_eq!(
+            args[2],
+            Token::Array(vec![Token::Address(token(2)), Token::Address(token(3))])
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[tokio::test]
+    async fn rejects_deviating_hops() {
+        let mut transport = TestTransport::new();
+        let mut detector = MockBadTokenDetecting::new();
+        detector
+            .expect_detect()
+            .times(2)
+            .returning(|probed, holder, _| {
+                // Balancer pools are probed with the vault holding their tokens.
+                assert_eq!(holder, H160([0xba; 20]));
+                Ok(if probed == token(3) {
+                    TokenQuality::Bad {
+                        reason: "fee on transfer".to_string(),
+                    }
+                } else {
+                    TokenQuality::Good
+                })
+            });
+        let filter = Arc::new(TokenFilter::default());
+        let bad_tokens = Arc::new(BadTokenQuarantine::new(Arc::new(detector), filter.clone()));
+        let verifier = RouteVerifier {
+            bad_tokens: Some(bad_tokens.clone()),
+            ..verifier(&transport)
+        };
+        let route = quote(vec![
+            hop(1, 2, 1_000_000, 900_000, concentrated(1, 2)),
+            hop(2, 3, 900_000, 800_000, weighted()),
+        ]);
+
+        // Buy quotes are compared by the sell amounts of their hops.
+        transport.add_response(quoter_output(1_000_500));
+        transport.add_response(asset_deltas(901_000, 800_000));
+        assert_eq!(
+            verifier.verify(&route, Side::Buy, block()).await.unwrap(),
+            Verification::Rejected {
+                hop: 1,
+                quoted: 900_000.into(),
+                simulated: 901_000.into(),
+            }
+        );
+
+        // The tokens of the rejected hop are probed on the next maintenance
+        // run.
+        bad_tokens.run_maintenance().await.unwrap();
+        assert_eq!(filter.denied(), hashset! { token(3) });
+    }
+
+    #[tokio::test]
+    async fn skips_liquidity_without_quoting_contracts() {
+        let transport = TestTransport::new();
+        let verifier = RouteVerifier {
+            quoter: None,
+            ..verifier(&transport)
+        };
+        let route = quote(vec![
+            hop(1, 2, 1_000_000, 900_000, concentrated(1, 2)),
+            hop(2, 3, 900_000, 800_000, constant_product(2, 3)),
+        ]);
+        assert_eq!(
+            verifier.verify(&route, Side::Sell, block()).await.unwrap(),
+            Verification::Verified { simulated_hops: 0 }
+        );
+        transport.assert_no_more_requests();
+    }
+}
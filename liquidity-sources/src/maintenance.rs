@@ -1,4 +1,4 @@
-use crate::current_block::{self, Block, CurrentBlockStream};
+use crate::current_block::{self, BlockInfo, CurrentBlockStream};
 use anyhow::Result;
 use futures::{future::join_all, Stream, StreamExt};
 use std::sync::Arc;
@@ -27,11 +27,14 @@ impl Maintaining for ServiceMaintenance {
 }
 
 impl ServiceMaintenance {
-    async fn run_maintenance_for_block_stream(self, block_stream: impl Stream<Item = Block>) {
+    pub(crate) async fn run_maintenance_for_block_stream(
+        self,
+        block_stream: impl Stream<Item = BlockInfo>,
+    ) {
         futures::pin_mut!(block_stream);
         while let Some(block) = block_stream.next().await {
             tracing::debug!(
-                "running maintenance on block number {:?} hash {:?}",
+                "running maintenance on block number {} hash {:?}",
                 block.number,
                 block.hash
             );
@@ -90,7 +93,7 @@ mod tests {
             maintainers: vec![Arc::new(mock_maintenance)],
         };
 
-        let block_stream = futures::stream::repeat(Block::default()).take(block_count);
+        let block_stream = futures::stream::repeat(BlockInfo::default()).take(block_count);
         service_maintenance
             .run_maintenance_for_block_stream(block_stream)
             .await;
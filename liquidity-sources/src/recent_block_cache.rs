@@ -12,25 +12,38 @@
 //! When entries are requested we mark all those entries as recently used which potentially evicts
 //! other entries from the lru cache. Cache misses are fetched and inserted into the cache.
 //! Then when the automatic update runs the next time, we request and cache all recently used
-//! entries. For some consumers we only care about the "recent" state of the entries. So we can
+//! entries, along with the most frequently requested ones if configured. The automatic update
+//! is either driven by the service maintenance or by a background task following the current block
+//! stream. For some consumers we only care about the "recent" state of the entries. So we can
 //! return any result from the cache even if it comes from previous blocks.
 //!
 //! On the other hand for others we need to fetch on-chain data at exact blocks which is why we keep
 //! a cache of previous blocks in the first place as we could simplify this module if it was only
 //! used by by the former.
 
-use crate::current_block::{self, CurrentBlockStream};
+use crate::{
+    current_block::CurrentBlockStream, event_handling::RollbackHandling,
+    metrics::get_metric_storage_registry,
+};
 use anyhow::Result;
 use ethcontract::BlockNumber;
+use futures::{
+    channel::oneshot,
+    future::{self, Shared},
+    FutureExt as _,
+};
 use lru::LruCache;
 use std::{
     collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     hash::Hash,
     num::NonZeroU64,
-    sync::Mutex,
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
+#[cfg(feature = "redis")]
+pub mod redis;
+
 /// A trait used to define `RecentBlockCache` updating behaviour.
 #[async_trait::async_trait]
 pub trait CacheFetching<K, V> {
@@ -45,6 +58,14 @@ pub trait CacheKey<V>: Clone + Eq + Hash + Ord {
 
     /// Returns the key for the specified value.
     fn for_value(value: &V) -> Self;
+
+    /// Returns the approximate number of bytes that the value uses in the cache, which is used for
+    /// keeping the cache within its memory budget.
+    ///
+    /// This should be overridden for values owning large heap allocations.
+    fn approximate_size(_value: &V) -> usize {
+        std::mem::size_of::<V>()
+    }
 }
 
 /// The state of the chain at which information should be retrieved.
@@ -79,6 +100,8 @@ where
     fetcher: F,
     block_stream: CurrentBlockStream,
     metrics: M,
+    name: &'static str,
+    cache_metrics: &'static Metrics,
     maximum_retries: u32,
     delay_between_retries: Duration,
 }
@@ -87,9 +110,15 @@ where
 pub struct CacheConfig {
     pub number_of_blocks_to_cache: NonZeroU64,
     pub number_of_entries_to_auto_update: usize,
+    /// The number of most frequently requested entries that are automatically updated in addition
+    /// to the recently used ones, so that hot entries are always answered from the cache.
+    pub number_of_hot_entries_to_auto_update: usize,
     pub maximum_recent_block_age: u64,
     pub max_retries: u32,
     pub delay_between_retries: Duration,
+    /// The approximate number of bytes that cached entries may use, or `None` for no limit. Least
+    /// recently used entries are evicted once the budget is exceeded.
+    pub memory_budget: Option<usize>,
 }
 
 impl Default for CacheConfig {
@@ -97,9 +126,11 @@ impl Default for CacheConfig {
         Self {
             number_of_blocks_to_cache: NonZeroU64::new(1).unwrap(),
             number_of_entries_to_auto_update: Default::default(),
+            number_of_hot_entries_to_auto_update: Default::default(),
             maximum_recent_block_age: Default::default(),
             max_retries: Default::default(),
             delay_between_retries: Default::default(),
+            memory_budget: Default::default(),
         }
     }
 }
@@ -113,6 +144,27 @@ impl CacheMetrics for NoopCacheMetrics {
     fn entries_fetched(&self, _: usize, _: usize) {}
 }
 
+#[derive(prometheus_metric_storage::MetricStorage, Clone, Debug)]
+#[metric(subsystem = "recent_block_cache")]
+struct Metrics {
+    /// Number of requested entries that were cached.
+    #[metric(labels("cache"))]
+    hits: prometheus::IntCounterVec,
+
+    /// Number of requested entries that were not cached.
+    #[metric(labels("cache"))]
+    misses: prometheus::IntCounterVec,
+
+    /// Number of cache misses for entries that were already being fetched for another request,
+    /// whose fetch is shared instead of fetching the entries again.
+    #[metric(labels("cache"))]
+    duplicate_fetches: prometheus::IntCounterVec,
+
+    /// Number of entries evicted to keep the cache within its memory budget.
+    #[metric(labels("cache"))]
+    evictions: prometheus::IntCounterVec,
+}
+
 impl<K, V, F, M> RecentBlockCache<K, V, F, M>
 where
     K: CacheKey<V>,
@@ -120,6 +172,8 @@ where
     F: CacheFetching<K, V>,
     M: CacheMetrics,
 {
+    /// name: The name of the cache, used for labelling its metrics.
+    ///
     /// number_of_blocks_to_cache: Previous blocks stay cached until the block is this much older
     /// than the current block. If there is a request for a block that is already too old then the
     /// result stays cached until the automatic updating runs the next time.
@@ -129,47 +183,84 @@ where
     ///
     /// maximum_recent_block_age: When a recent block is requested, this is the maximum a cached
     /// block can have to be considered.
+    ///
+    /// memory_budget: The approximate number of bytes that cached entries may use before the least
+    /// recently used entries are evicted.
     pub fn new(
+        name: &'static str,
         config: CacheConfig,
         fetcher: F,
         block_stream: CurrentBlockStream,
         metrics: M,
     ) -> Result<Self> {
-        let block = current_block::block_number(&block_stream.borrow())?;
+        let block = block_stream.borrow().number;
         Ok(Self {
             mutexed: Mutex::new(Mutexed::new(
                 config.number_of_entries_to_auto_update,
+                config.number_of_hot_entries_to_auto_update,
                 block,
                 config.maximum_recent_block_age,
+                config.memory_budget,
             )),
             number_of_blocks_to_cache: config.number_of_blocks_to_cache,
             fetcher,
             block_stream,
             metrics,
+            name,
+            cache_metrics: Metrics::instance(get_metric_storage_registry()).unwrap(),
             maximum_retries: config.max_retries,
             delay_between_retries: config.delay_between_retries,
         })
     }
 
     pub async fn update_cache(&self) -> Result<()> {
-        let new_block = current_block::block_number(&self.block_stream.borrow())?;
+        let new_block = self.block_stream.borrow().number;
         self.update_cache_at_block(new_block).await
     }
 
+    /// Spawns a task that updates the cache whenever the current block stream yields a new block,
+    /// for caches that are not updated as part of the regular maintenance.
+    ///
+    /// The task stops once the cache is dropped.
+    pub fn spawn_background_updates(self: &Arc<Self>)
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        F: Send + Sync + 'static,
+        M: 'static,
+    {
+        let cache = Arc::downgrade(self);
+        let mut block_stream = self.block_stream.clone();
+        tokio::task::spawn(async move {
+            while block_stream.changed().await.is_ok() {
+                let cache = match cache.upgrade() {
+                    Some(cache) => cache,
+                    None => break,
+                };
+                if let Err(err) = cache.update_cache().await {
+                    tracing::warn!("failed to update {} cache: {:?}", cache.name, err);
+                }
+            }
+        });
+    }
+
     async fn update_cache_at_block(&self, new_block: u64) -> Result<()> {
-        let keys = self
-            .mutexed
-            .lock()
-            .unwrap()
-            .keys_of_recently_used_entries()
-            .collect::<HashSet<_>>();
+        let keys = {
+            let mut mutexed = self.mutexed.lock().unwrap();
+            let mut keys = mutexed
+                .keys_of_recently_used_entries()
+                .collect::<HashSet<_>>();
+            keys.extend(mutexed.take_hot_keys());
+            keys
+        };
         tracing::debug!("automatically updating {} entries", keys.len());
         let entries = self
             .fetch_inner(keys.clone(), Block::Number(new_block))
             .await?;
         {
             let mut mutexed = self.mutexed.lock().unwrap();
-            mutexed.insert(new_block, keys.into_iter(), entries);
+            let evicted = mutexed.insert(new_block, keys.into_iter(), entries);
+            self.record_evictions(evicted);
             let oldest_to_keep = new_block.saturating_sub(self.number_of_blocks_to_cache.get() - 1);
             mutexed.remove_cached_blocks_older_than(oldest_to_keep);
             mutexed.last_update_block = new_block;
@@ -197,7 +288,30 @@ where
             Block::Recent => None,
             Block::Number(number) => Some(number),
         };
+        self.fetch_entries(keys, block, true).await
+    }
+
+    /// Returns the values at the specified block, which can be a historical block, fetching and
+    /// caching them under that block if they are not cached yet.
+    ///
+    /// Unlike with `fetch`, the keys are not marked as recently used, so that requests for past
+    /// blocks, for example for re-quoting past auctions, do not cause the keys to be automatically
+    /// updated on new blocks. Values for blocks older than the cached blocks stay cached until the
+    /// next automatic update.
+    pub async fn fetch_at_block(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        block: u64,
+    ) -> Result<Vec<V>> {
+        self.fetch_entries(keys, Some(block), false).await
+    }
 
+    async fn fetch_entries(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        block: Option<u64>,
+        mark_recently_used: bool,
+    ) -> Result<Vec<V>> {
         let mut cache_hit_count = 0usize;
         let mut cache_hits = Vec::new();
         let mut cache_misses = HashSet::new();
@@ -205,7 +319,7 @@ where
         {
             let mut mutexed = self.mutexed.lock().unwrap();
             for key in keys {
-                match mutexed.get(key.clone(), block) {
+                match mutexed.get(key.clone(), block, mark_recently_used) {
                     Some(values) => {
                         cache_hit_count += 1;
                         cache_hits.extend_from_slice(values);
@@ -220,25 +334,116 @@ where
 
         self.metrics
             .entries_fetched(cache_hit_count, cache_misses.len());
+        self.cache_metrics
+            .hits
+            .with_label_values(&[self.name])
+            .inc_by(cache_hit_count as u64);
+        self.cache_metrics
+            .misses
+            .with_label_values(&[self.name])
+            .inc_by(cache_misses.len() as u64);
 
         if cache_misses.is_empty() {
             return Ok(cache_hits);
         }
 
+        // Concurrent requests missing the same entries share a single fetch, so only the entries
+        // that are not already being fetched are fetched here.
         let cache_miss_block = block.unwrap_or(last_update_block);
-        let uncached_values = self
-            .fetch_inner(cache_misses.clone(), Block::Number(cache_miss_block))
-            .await?;
+        let (to_fetch, ongoing, done) = self
+            .mutexed
+            .lock()
+            .unwrap()
+            .start_fetching(cache_miss_block, cache_misses);
+        self.cache_metrics
+            .duplicate_fetches
+            .with_label_values(&[self.name])
+            .inc_by(ongoing.len() as u64);
 
-        cache_hits.extend_from_slice(&uncached_values);
+        let fetch = async {
+            // Completes the shared fetch for other requests once the result is cached.
+            let _done = done;
+            if to_fetch.is_empty() {
+                return Ok(Vec::new());
+            }
+            let values = self
+                .fetch_inner(to_fetch.clone(), Block::Number(cache_miss_block))
+                .await;
+            let mut mutexed = self.mutexed.lock().unwrap();
+            mutexed.finish_fetching(cache_miss_block, &to_fetch);
+            let values = values?;
+            let evicted = mutexed.insert(cache_miss_block, to_fetch, values.clone());
+            self.record_evictions(evicted);
+            Ok::<_, anyhow::Error>(values)
+        };
+        let wait = future::join_all(ongoing.iter().map(|(_, done)| done.0.clone()));
+        let (fetched, _) = futures::join!(fetch, wait);
+        cache_hits.extend(fetched?);
 
+        // The entries fetched for other requests are now cached, unless their fetch failed in
+        // which case they are fetched again.
+        let mut refetch = HashSet::new();
         {
             let mut mutexed = self.mutexed.lock().unwrap();
-            mutexed.insert(cache_miss_block, cache_misses.into_iter(), uncached_values);
+            for (key, _) in ongoing {
+                match mutexed.get(key.clone(), Some(cache_miss_block), false) {
+                    Some(values) => cache_hits.extend_from_slice(values),
+                    None => {
+                        refetch.insert(key);
+                    }
+                }
+            }
+        }
+        if !refetch.is_empty() {
+            let values = self
+                .fetch_inner(refetch.clone(), Block::Number(cache_miss_block))
+                .await?;
+            cache_hits.extend_from_slice(&values);
+            let evicted = self
+                .mutexed
+                .lock()
+                .unwrap()
+                .insert(cache_miss_block, refetch, values);
+            self.record_evictions(evicted);
         }
 
         Ok(cache_hits)
     }
+
+    fn record_evictions(&self, evicted: usize) {
+        self.cache_metrics
+            .evictions
+            .with_label_values(&[self.name])
+            .inc_by(evicted as u64);
+    }
+}
+
+/// Drops the entries cached for reorged blocks, so that they are fetched again from the new chain.
+#[async_trait::async_trait]
+impl<K, V, F, M> RollbackHandling for RecentBlockCache<K, V, F, M>
+where
+    K: CacheKey<V> + Send + Sync,
+    V: Send + Sync,
+    F: CacheFetching<K, V> + Send + Sync,
+    M: CacheMetrics,
+{
+    async fn rollback(&self, from_block: u64) -> Result<()> {
+        self.mutexed
+            .lock()
+            .unwrap()
+            .remove_cached_blocks_from(from_block);
+        Ok(())
+    }
+}
+
+/// Completes when an ongoing fetch of uncached entries completes.
+#[derive(Clone)]
+struct FetchDone(Shared<oneshot::Receiver<()>>);
+
+impl std::fmt::Debug for FetchDone {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("FetchDone").finish()
+    }
 }
 
 #[derive(Debug)]
@@ -247,6 +452,10 @@ where
     K: CacheKey<V>,
 {
     recently_used: LruCache<K, ()>,
+    // How often each key was requested, decaying with every automatic cache update.
+    request_counts: HashMap<K, u64>,
+    // The number of most frequently requested keys to automatically update.
+    number_of_hot_entries: usize,
     // For quickly finding at which block an entry is cached.
     cached_most_recently_at_block: HashMap<K, u64>,
     // Tuple ordering allows us to efficiently construct range queries by block.
@@ -255,24 +464,49 @@ where
     last_update_block: u64,
     // Maximum age a cached block can have to count as recent.
     maximum_recent_block_age: u64,
+    // The approximate size of each entry in bytes, ordered by when the entry was last used.
+    entry_sizes: LruCache<(u64, K), usize>,
+    // The approximate total size of all entries in bytes.
+    memory_usage: usize,
+    // The approximate total size that entries may have before the least recently used are evicted.
+    memory_budget: Option<usize>,
+    // The ongoing fetches of uncached entries by block and key.
+    fetching: HashMap<(u64, K), FetchDone>,
 }
 
 impl<K, V> Mutexed<K, V>
 where
     K: CacheKey<V>,
 {
-    fn new(entries_lru_size: usize, current_block: u64, maximum_recent_block_age: u64) -> Self {
+    fn new(
+        entries_lru_size: usize,
+        number_of_hot_entries: usize,
+        current_block: u64,
+        maximum_recent_block_age: u64,
+        memory_budget: Option<usize>,
+    ) -> Self {
         Self {
             recently_used: LruCache::new(entries_lru_size),
+            request_counts: HashMap::new(),
+            number_of_hot_entries,
             cached_most_recently_at_block: HashMap::new(),
             entries: BTreeMap::new(),
             last_update_block: current_block,
             maximum_recent_block_age,
+            entry_sizes: LruCache::unbounded(),
+            memory_usage: 0,
+            memory_budget,
+            fetching: HashMap::new(),
         }
     }
 
-    fn get(&mut self, key: K, block: Option<u64>) -> Option<&[V]> {
-        self.recently_used.put(key.clone(), ());
+    fn get(&mut self, key: K, block: Option<u64>, mark_recently_used: bool) -> Option<&[V]> {
+        if mark_recently_used {
+            self.recently_used.put(key.clone(), ());
+            if self.number_of_hot_entries > 0 {
+                *self.request_counts.entry(key.clone()).or_default() += 1;
+            }
+        }
         let block = block.or_else(|| {
             self.cached_most_recently_at_block
                 .get(&key)
@@ -281,7 +515,9 @@ where
                     self.last_update_block.saturating_sub(block) <= self.maximum_recent_block_age
                 })
         })?;
-        self.entries.get(&(block, key)).map(Vec::as_slice)
+        let entry = (block, key);
+        self.entry_sizes.get(&entry);
+        self.entries.get(&entry).map(Vec::as_slice)
     }
 
     fn insert(
@@ -289,8 +525,9 @@ where
         block: u64,
         keys: impl IntoIterator<Item = K>,
         values: impl IntoIterator<Item = V>,
-    ) {
-        for key in keys {
+    ) -> usize {
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        for key in keys.iter().cloned() {
             match self.cached_most_recently_at_block.entry(key.clone()) {
                 Entry::Occupied(mut entry) => {
                     let value = entry.get_mut();
@@ -310,11 +547,98 @@ where
                 .unwrap()
                 .push(value);
         }
+        for key in keys {
+            let size = std::mem::size_of::<(u64, K)>()
+                + self.entries[&(block, key.clone())]
+                    .iter()
+                    .map(K::approximate_size)
+                    .sum::<usize>();
+            self.memory_usage += size;
+            if let Some(replaced) = self.entry_sizes.put((block, key), size) {
+                self.memory_usage -= replaced;
+            }
+        }
+        self.evict_over_memory_budget()
+    }
+
+    /// Evicts the least recently used entries until the cache is within its memory budget,
+    /// returning the number of evicted entries.
+    fn evict_over_memory_budget(&mut self) -> usize {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return 0,
+        };
+        let mut evicted = 0;
+        while self.memory_usage > budget {
+            let ((block, key), size) = match self.entry_sizes.pop_lru() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.memory_usage -= size;
+            self.entries.remove(&(block, key.clone()));
+            if self.cached_most_recently_at_block.get(&key) == Some(&block) {
+                self.cached_most_recently_at_block.remove(&key);
+            }
+            evicted += 1;
+        }
+        if evicted > 0 {
+            tracing::debug!(
+                "evicted {} entries to keep the cache within {} bytes",
+                evicted,
+                budget
+            );
+        }
+        evicted
+    }
+
+    /// Registers fetches for the uncached entries at the block.
+    ///
+    /// Returns the keys that need to be fetched, the keys that are already being fetched along with
+    /// futures that complete when those fetches do, and a sender that completes the registered
+    /// fetches when dropped.
+    #[allow(clippy::type_complexity)]
+    fn start_fetching(
+        &mut self,
+        block: u64,
+        keys: HashSet<K>,
+    ) -> (HashSet<K>, Vec<(K, FetchDone)>, oneshot::Sender<()>) {
+        let (sender, receiver) = oneshot::channel();
+        let done = FetchDone(receiver.shared());
+        let mut to_fetch = HashSet::new();
+        let mut ongoing = Vec::new();
+        for key in keys {
+            match self.fetching.entry((block, key.clone())) {
+                // Fetches of requests that were cancelled complete without being removed.
+                Entry::Occupied(entry) if entry.get().0.peek().is_none() => {
+                    ongoing.push((key, entry.get().clone()));
+                }
+                Entry::Occupied(mut entry) => {
+                    entry.insert(done.clone());
+                    to_fetch.insert(key);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(done.clone());
+                    to_fetch.insert(key);
+                }
+            }
+        }
+        (to_fetch, ongoing, sender)
+    }
+
+    fn finish_fetching(&mut self, block: u64, keys: &HashSet<K>) {
+        for key in keys {
+            self.fetching.remove(&(block, key.clone()));
+        }
     }
 
     fn remove_cached_blocks_older_than(&mut self, oldest_to_keep: u64) {
         tracing::debug!("dropping blocks older than {} from cache", oldest_to_keep);
-        self.entries = self.entries.split_off(&(oldest_to_keep, K::first_ord()));
+        let newer = self.entries.split_off(&(oldest_to_keep, K::first_ord()));
+        for entry in std::mem::replace(&mut self.entries, newer).into_keys() {
+            if let Some(size) = self.entry_sizes.pop(&entry) {
+                self.memory_usage -= size;
+            }
+        }
         self.cached_most_recently_at_block
             .retain(|_, block| *block >= oldest_to_keep);
         tracing::debug!(
@@ -323,15 +647,48 @@ where
         );
     }
 
+    fn remove_cached_blocks_from(&mut self, first_to_remove: u64) {
+        tracing::debug!("dropping blocks from {} from cache", first_to_remove);
+        let removed = self.entries.split_off(&(first_to_remove, K::first_ord()));
+        for entry in removed.into_keys() {
+            if let Some(size) = self.entry_sizes.pop(&entry) {
+                self.memory_usage -= size;
+            }
+        }
+        self.cached_most_recently_at_block
+            .retain(|_, block| *block < first_to_remove);
+    }
+
     fn keys_of_recently_used_entries(&self) -> impl Iterator<Item = K> + '_ {
         self.recently_used.iter().map(|(key, _)| key.clone())
     }
+
+    /// Returns the most frequently requested keys and halves all request counts, so that keys
+    /// which are no longer requested stop being considered hot after a few updates.
+    fn take_hot_keys(&mut self) -> Vec<K> {
+        let mut counts = self
+            .request_counts
+            .iter()
+            .map(|(key, count)| (*count, key.clone()))
+            .collect::<Vec<_>>();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        let hot_keys = counts
+            .into_iter()
+            .take(self.number_of_hot_entries)
+            .map(|(_, key)| key)
+            .collect();
+        self.request_counts.retain(|_, count| {
+            *count /= 2;
+            *count > 0
+        });
+        hot_keys
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::current_block::Block as Web3Block;
+    use crate::current_block::BlockInfo;
     use futures::FutureExt;
     use std::sync::Arc;
     use tokio::sync::watch;
@@ -381,12 +738,13 @@ mod tests {
     fn marks_recently_used() {
         let fetcher = FakeCacheFetcher::default();
         let block_number = 10u64;
-        let block = Web3Block {
-            number: Some(block_number.into()),
+        let block = BlockInfo {
+            number: block_number,
             ..Default::default()
         };
         let (_sender, receiver) = watch::channel(block);
         let cache = RecentBlockCache::new(
+            "test",
             CacheConfig {
                 number_of_entries_to_auto_update: 2,
                 ..Default::default()
@@ -435,12 +793,13 @@ mod tests {
         let fetcher = FakeCacheFetcher::default();
         let values = fetcher.0.clone();
         let block_number = 10u64;
-        let block = Web3Block {
-            number: Some(block_number.into()),
+        let block = BlockInfo {
+            number: block_number,
             ..Default::default()
         };
         let (_sender, receiver) = watch::channel(block);
         let cache = RecentBlockCache::new(
+            "test",
             CacheConfig {
                 number_of_entries_to_auto_update: 2,
                 ..Default::default()
@@ -483,12 +842,13 @@ mod tests {
         let fetcher = FakeCacheFetcher::default();
         let values = fetcher.0.clone();
         let block_number = 10u64;
-        let block = Web3Block {
-            number: Some(block_number.into()),
+        let block = BlockInfo {
+            number: block_number,
             ..Default::default()
         };
         let (_sender, receiver) = watch::channel(block);
         let cache = RecentBlockCache::new(
+            "test",
             CacheConfig {
                 number_of_entries_to_auto_update: 2,
                 ..Default::default()
@@ -540,12 +900,13 @@ mod tests {
         let fetcher = FakeCacheFetcher::default();
         let values = fetcher.0.clone();
         let block_number = 10u64;
-        let block = Web3Block {
-            number: Some(block_number.into()),
+        let block = BlockInfo {
+            number: block_number,
             ..Default::default()
         };
         let (_sender, receiver) = watch::channel(block);
         let cache = RecentBlockCache::new(
+            "test",
             CacheConfig {
                 number_of_entries_to_auto_update: 2,
                 maximum_recent_block_age: 10,
@@ -606,12 +967,13 @@ mod tests {
     fn evicts_old_blocks_from_cache() {
         let fetcher = FakeCacheFetcher::default();
         let block_number = 10u64;
-        let block = Web3Block {
-            number: Some(block_number.into()),
+        let block = BlockInfo {
+            number: block_number,
             ..Default::default()
         };
         let (_sender, receiver) = watch::channel(block);
         let cache = RecentBlockCache::new(
+            "test",
             CacheConfig {
                 number_of_blocks_to_cache: NonZeroU64::new(5).unwrap(),
                 ..Default::default()
@@ -643,16 +1005,214 @@ mod tests {
         assert!(cache.mutexed.lock().unwrap().entries.is_empty());
     }
 
+    #[test]
+    fn evicts_least_recently_used_entries_over_memory_budget() {
+        let entry_size = std::mem::size_of::<(u64, TestKey)>() + std::mem::size_of::<TestValue>();
+        let mut mutexed = Mutexed::new(0, 0, 10, 0, Some(2 * entry_size));
+
+        mutexed.insert(
+            10,
+            test_keys(0..2),
+            [TestValue::new(0, "a"), TestValue::new(1, "b")],
+        );
+        assert_eq!(mutexed.memory_usage, 2 * entry_size);
+        // Using the first entry makes the second one the least recently used.
+        assert!(mutexed.get(TestKey(0), Some(10), true).is_some());
+
+        mutexed.insert(10, test_keys(2..3), [TestValue::new(2, "c")]);
+        assert_eq!(mutexed.memory_usage, 2 * entry_size);
+        assert!(mutexed.get(TestKey(1), None, true).is_none());
+        assert!(mutexed.get(TestKey(0), None, true).is_some());
+        assert!(mutexed.get(TestKey(2), None, true).is_some());
+
+        mutexed.remove_cached_blocks_older_than(11);
+        assert_eq!(mutexed.memory_usage, 0);
+    }
+
+    #[test]
+    fn rolls_back_reorged_blocks() {
+        let mut mutexed = Mutexed::<TestKey, TestValue>::new(0, 0, 12, 5, None);
+        mutexed.insert(10, test_keys(0..1), [TestValue::new(0, "a")]);
+        mutexed.insert(11, test_keys(0..2), [TestValue::new(0, "b")]);
+        mutexed.insert(12, test_keys(1..2), [TestValue::new(1, "c")]);
+
+        mutexed.remove_cached_blocks_from(11);
+        assert_eq!(mutexed.entries.len(), 1);
+        assert!(mutexed.get(TestKey(0), Some(10), false).is_some());
+        // Recent entries are only returned from the newest cached block of each key.
+        assert!(mutexed.get(TestKey(0), None, false).is_none());
+        assert!(mutexed.get(TestKey(1), None, false).is_none());
+        assert_eq!(
+            mutexed.memory_usage,
+            mutexed
+                .entry_sizes
+                .iter()
+                .map(|(_, size)| size)
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn takes_most_frequently_requested_keys() {
+        let mut mutexed = Mutexed::<TestKey, TestValue>::new(0, 1, 10, 0, None);
+        for key in [0, 1, 1, 1, 2, 2] {
+            mutexed.get(TestKey(key), None, true);
+        }
+        // Lookups that do not mark entries as used, like automatic updates, are not counted.
+        mutexed.get(TestKey(0), None, false);
+        mutexed.get(TestKey(0), None, false);
+
+        assert_eq!(mutexed.take_hot_keys(), vec![TestKey(1)]);
+        assert_eq!(mutexed.take_hot_keys(), vec![TestKey(2)]);
+        assert!(mutexed.take_hot_keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn updates_hot_entries_on_new_blocks() {
+        let fetcher = FakeCacheFetcher::default();
+        let values = fetcher.0.clone();
+        let (sender, receiver) = watch::channel(BlockInfo {
+            number: 10,
+            ..Default::default()
+        });
+        let cache = Arc::new(
+            RecentBlockCache::new(
+                "test",
+                CacheConfig {
+                    number_of_hot_entries_to_auto_update: 1,
+                    maximum_recent_block_age: 1,
+                    ..Default::default()
+                },
+                fetcher,
+                receiver,
+                NoopCacheMetrics,
+            )
+            .unwrap(),
+        );
+        cache.spawn_background_updates();
+
+        cache.fetch(test_keys(0..1), Block::Recent).await.unwrap();
+        *values.lock().unwrap() = vec![TestValue::new(0, "hot")];
+        sender
+            .send(BlockInfo {
+                number: 11,
+                ..Default::default()
+            })
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while cache.mutexed.lock().unwrap().last_update_block != 11 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        values.lock().unwrap().clear();
+
+        let result = cache.fetch(test_keys(0..1), Block::Recent).await.unwrap();
+        assert_eq!(result, vec![TestValue::new(0, "hot")]);
+    }
+
+    #[test]
+    fn fetches_at_historical_block() {
+        let fetcher = FakeCacheFetcher::default();
+        let values = fetcher.0.clone();
+        let (_sender, receiver) = watch::channel(BlockInfo {
+            number: 10,
+            ..Default::default()
+        });
+        let cache = RecentBlockCache::new(
+            "test",
+            CacheConfig {
+                number_of_entries_to_auto_update: 2,
+                ..Default::default()
+            },
+            fetcher,
+            receiver,
+            NoopCacheMetrics,
+        )
+        .unwrap();
+
+        *values.lock().unwrap() = vec![TestValue::new(0, "5")];
+        let result = cache
+            .fetch_at_block(test_keys(0..1), 5)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(result, vec![TestValue::new(0, "5")]);
+
+        // The value is cached under the historical block.
+        *values.lock().unwrap() = vec![TestValue::new(0, "10")];
+        let result = cache
+            .fetch_at_block(test_keys(0..1), 5)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(result, vec![TestValue::new(0, "5")]);
+
+        // Historical requests are not automatically updated.
+        let keys = cache
+            .mutexed
+            .lock()
+            .unwrap()
+            .keys_of_recently_used_entries()
+            .count();
+        assert_eq!(keys, 0);
+    }
+
+    #[derive(Default)]
+    struct SlowCacheFetcher(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl CacheFetching<TestKey, TestValue> for SlowCacheFetcher {
+        async fn fetch_values(&self, keys: HashSet<TestKey>, _: Block) -> Result<Vec<TestValue>> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(keys
+                .into_iter()
+                .map(|key| TestValue::new(key.0, ""))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_fetches() {
+        let (_sender, receiver) = watch::channel(BlockInfo {
+            number: 10,
+            ..Default::default()
+        });
+        let cache = RecentBlockCache::new(
+            "test",
+            CacheConfig {
+                number_of_entries_to_auto_update: 2,
+                ..Default::default()
+            },
+            SlowCacheFetcher::default(),
+            receiver,
+            NoopCacheMetrics,
+        )
+        .unwrap();
+
+        let (first, second) = futures::join!(
+            cache.fetch(test_keys(0..2), Block::Recent),
+            cache.fetch(test_keys(1..2), Block::Recent),
+        );
+        assert_eq!(first.unwrap().len(), 2);
+        assert_eq!(second.unwrap(), vec![TestValue::new(1, "")]);
+        assert_eq!(cache.fetcher.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(cache.mutexed.lock().unwrap().fetching.is_empty());
+    }
+
     #[test]
     fn respects_max_age_limit_for_recent() {
         let fetcher = FakeCacheFetcher::default();
         let block_number = 10u64;
-        let block = Web3Block {
-            number: Some(block_number.into()),
+        let block = BlockInfo {
+            number: block_number,
             ..Default::default()
         };
         let (_sender, receiver) = watch::channel(block);
         let cache = RecentBlockCache::new(
+            "test",
             CacheConfig {
                 number_of_blocks_to_cache: NonZeroU64::new(5).unwrap(),
                 maximum_recent_block_age: 2,
@@ -671,8 +1231,13 @@ mod tests {
             .now_or_never()
             .unwrap()
             .unwrap();
-        assert!(cache.mutexed.lock().unwrap().get(key, Some(7)).is_some());
-        assert!(cache.mutexed.lock().unwrap().get(key, None).is_none());
+        assert!(cache
+            .mutexed
+            .lock()
+            .unwrap()
+            .get(key, Some(7), true)
+            .is_some());
+        assert!(cache.mutexed.lock().unwrap().get(key, None, true).is_none());
 
         // cache at block 8
         cache
@@ -680,8 +1245,18 @@ mod tests {
             .now_or_never()
             .unwrap()
             .unwrap();
-        assert!(cache.mutexed.lock().unwrap().get(key, Some(7)).is_some());
-        assert!(cache.mutexed.lock().unwrap().get(key, Some(8)).is_some());
-        assert!(cache.mutexed.lock().unwrap().get(key, None).is_some());
+        assert!(cache
+            .mutexed
+            .lock()
+            .unwrap()
+            .get(key, Some(7), true)
+            .is_some());
+        assert!(cache
+            .mutexed
+            .lock()
+            .unwrap()
+            .get(key, Some(8), true)
+            .is_some());
+        assert!(cache.mutexed.lock().unwrap().get(key, None, true).is_some());
     }
 }
@@ -0,0 +1,292 @@
+//! A shared Redis tier for recent block caches.
+//!
+//! Horizontally scaled instances each keep their own in-memory cache, so without a shared tier
+//! every instance fetches the same entries from the node or subgraph for every block. Wrapping the
+//! fetcher of a [`RecentBlockCache`](super::RecentBlockCache) in a [`RedisCacheFetcher`] makes
+//! misses of the in-memory cache consult Redis first, and stores the entries that still had to be
+//! fetched in Redis for the other instances.
+
+use super::{Block, CacheFetching, CacheKey};
+use crate::metrics::get_metric_storage_registry;
+use ::redis::{aio::MultiplexedConnection, Client};
+use anyhow::{Context as _, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    time::Duration,
+};
+
+#[derive(prometheus_metric_storage::MetricStorage, Clone, Debug)]
+#[metric(subsystem = "redis_cache")]
+struct Metrics {
+    /// Number of requested entries that were found in Redis.
+    #[metric(labels("cache"))]
+    hits: prometheus::IntCounterVec,
+
+    /// Number of requested entries that were not found in Redis.
+    #[metric(labels("cache"))]
+    misses: prometheus::IntCounterVec,
+
+    /// Number of failed Redis requests.
+    #[metric(labels("cache"))]
+    errors: prometheus::IntCounterVec,
+}
+
+/// Cache fetcher that looks up entries at specific blocks in Redis before fetching them with the
+/// inner fetcher.
+///
+/// Entries are stored as JSON under the namespace of the cache, the block and the key, and expire
+/// after the configured time. Failing Redis requests are logged and the entries are fetched with
+/// the inner fetcher instead, so that Redis being unavailable only costs performance.
+pub struct RedisCacheFetcher<K, V, F> {
+    inner: F,
+    connection: MultiplexedConnection,
+    namespace: String,
+    expiry: Duration,
+    metrics: &'static Metrics,
+    _entries: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, F> RedisCacheFetcher<K, V, F>
+where
+    K: CacheKey<V> + Serialize,
+    V: Serialize + DeserializeOwned,
+{
+    /// Connects to the Redis server at the specified URL.
+    ///
+    /// The namespace needs to be unique for each cache and chain since all instances sharing the
+    /// namespace share the entries.
+    pub async fn new(
+        inner: F,
+        url: &str,
+        namespace: impl Into<String>,
+        expiry: Duration,
+    ) -> Result<Self> {
+        let connection = Client::open(url)?
+            .get_multiplexed_tokio_connection()
+            .await
+            .context("failed to connect to redis")?;
+        Ok(Self::with_connection(inner, connection, namespace, expiry))
+    }
+
+    /// Creates a new fetcher using an existing Redis connection.
+    pub fn with_connection(
+        inner: F,
+        connection: MultiplexedConnection,
+        namespace: impl Into<String>,
+        expiry: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            connection,
+            namespace: namespace.into(),
+            expiry,
+            metrics: Metrics::instance(get_metric_storage_registry()).unwrap(),
+            _entries: PhantomData,
+        }
+    }
+
+    fn redis_key(&self, block: u64, key: &K) -> Result<String> {
+        Ok(format!(
+            "{}:{}:{}",
+            self.namespace,
+            block,
+            serde_json::to_string(key)?
+        ))
+    }
+
+    /// Returns the cached values for each of the keys, or `None` for keys that are not cached.
+    async fn get(&self, keys: &[String]) -> Result<Vec<Option<Vec<V>>>> {
+        let mut connection = self.connection.clone();
+        let entries: Vec<Option<Vec<u8>>> = ::redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut connection)
+            .await?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                entry
+                    .map(|entry| serde_json::from_slice(&entry))
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()
+            .context("invalid cached entry")
+    }
+
+    async fn set(&self, block: u64, entries: &HashMap<K, Vec<V>>) -> Result<()> {
+        let expiry = self.expiry.as_secs().max(1) as usize;
+        let mut pipeline = ::redis::pipe();
+        for (key, values) in entries {
+            pipeline
+                .set_ex(
+                    self.redis_key(block, key)?,
+                    serde_json::to_vec(values)?,
+                    expiry,
+                )
+                .ignore();
+        }
+        let mut connection = self.connection.clone();
+        pipeline.query_async::<_, ()>(&mut connection).await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl<K, V, F> CacheFetching<K, V> for RedisCacheFetcher<K, V, F>
+where
+    K: CacheKey<V> + Serialize + Send + Sync + 'static,
+    V: Serialize + DeserializeOwned + Send + Sync + 'static,
+    F: CacheFetching<K, V> + Send + Sync,
+{
+    async fn fetch_values(&self, keys: HashSet<K>, block: Block) -> Result<Vec<V>> {
+        let number = match block {
+            Block::Number(number) => number,
+            // The recent state differs between instances, so it can not be shared.
+            Block::Recent => return self.inner.fetch_values(keys, block).await,
+        };
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        let redis_keys = keys
+            .iter()
+            .map(|key| self.redis_key(number, key))
+            .collect::<Result<Vec<_>>>()?;
+        let mut values = Vec::new();
+        let mut misses = HashSet::new();
+        match self.get(&redis_keys).await {
+            Ok(cached) => {
+                for (key, cached) in keys.into_iter().zip(cached) {
+                    match cached {
+                        Some(cached) => values.extend(cached),
+                        None => {
+                            misses.insert(key);
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    namespace = %self.namespace,
+                    "failed to get cached entries: {:?}",
+                    err
+                );
+                self.metrics
+                    .errors
+                    .with_label_values(&[self.namespace.as_str()])
+                    .inc();
+                misses.extend(keys);
+            }
+        }
+        self.metrics
+            .hits
+            .with_label_values(&[self.namespace.as_str()])
+            .inc_by((redis_keys.len() - misses.len()) as u64);
+        self.metrics
+            .misses
+            .with_label_values(&[self.namespace.as_str()])
+            .inc_by(misses.len() as u64);
+        if misses.is_empty() {
+            return Ok(values);
+        }
+
+        // Keys without values are stored too so that the other instances don't fetch them again.
+        let fetched = self.inner.fetch_values(misses.clone(), block).await?;
+        let mut entries = misses
+            .into_iter()
+            .map(|key| (key, Vec::new()))
+            .collect::<HashMap<_, _>>();
+        for value in fetched {
+            entries.entry(K::for_value(&value)).or_default().push(value);
+        }
+        if let Err(err) = self.set(number, &entries).await {
+            tracing::warn!(
+                namespace = %self.namespace,
+                "failed to cache entries: {:?}",
+                err
+            );
+            self.metrics
+                .errors
+                .with_label_values(&[self.namespace.as_str()])
+                .inc();
+        }
+        values.extend(entries.into_values().flatten());
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+    struct TestKey(u64);
+
+    impl CacheKey<u64> for TestKey {
+        fn first_ord() -> Self {
+            Self(0)
+        }
+
+        fn for_value(value: &u64) -> Self {
+            Self(value / 10)
+        }
+    }
+
+    /// Returns the key times ten as the value of odd keys, counting the fetched keys.
+    struct CountingFetcher(Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl CacheFetching<TestKey, u64> for CountingFetcher {
+        async fn fetch_values(&self, keys: HashSet<TestKey>, _: Block) -> Result<Vec<u64>> {
+            self.0.fetch_add(keys.len(), Ordering::SeqCst);
+            Ok(keys
+                .into_iter()
+                .filter(|key| key.0 % 2 == 1)
+                .map(|key| key.0 * 10)
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn shares_fetched_entries_between_instances() {
+        let namespace = format!("test-{}", std::process::id());
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let mut instances = Vec::new();
+        for _ in 0..2 {
+            instances.push(
+                RedisCacheFetcher::new(
+                    CountingFetcher(fetched.clone()),
+                    "redis://127.0.0.1/",
+                    namespace.clone(),
+                    Duration::from_secs(10),
+                )
+                .await
+                .unwrap(),
+            );
+        }
+
+        let keys = || (1..=4).map(TestKey).collect::<HashSet<_>>();
+        for instance in &instances {
+            let mut values = instance
+                .fetch_values(keys(), Block::Number(1))
+                .await
+                .unwrap();
+            values.sort_unstable();
+            assert_eq!(values, [10, 30]);
+        }
+        assert_eq!(fetched.load(Ordering::SeqCst), 4);
+
+        instances[1]
+            .fetch_values(keys(), Block::Number(2))
+            .await
+            .unwrap();
+        assert_eq!(fetched.load(Ordering::SeqCst), 8);
+    }
+}
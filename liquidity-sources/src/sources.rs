@@ -1,29 +1,89 @@
 //! Top-level module organizing all baseline liquidity sources.
 
+pub mod algebra;
+pub mod ambient;
 pub mod balancer_v2;
 pub mod baoswap;
+pub mod clipper;
+pub mod cow_orderbook;
+pub mod curve;
+pub mod erc4626;
+pub mod fraxswap;
+pub mod hashflow;
 pub mod honeyswap;
+pub mod integral;
+pub mod iziswap;
+pub mod maker_psm;
+pub mod market_maker;
+pub mod mstable;
+pub mod native;
+pub mod pancakeswap;
+pub mod rfq;
+pub mod saddle;
+pub mod solidly;
+pub mod stableswap;
 pub mod sushiswap;
 pub mod swapr;
+pub mod synthetix;
+pub mod traderjoe;
 pub mod uniswap_v2;
 pub mod uniswap_v3;
 pub mod uniswap_v3_pair_provider;
+pub mod uniswap_v4;
+pub mod wombat;
+pub mod wsteth;
 
 use self::uniswap_v2::{
     pair_provider::PairProvider,
     pool_fetching::{Pool, PoolFetching},
 };
+use self::{
+    algebra::pool_fetching::AlgebraPoolFetcher,
+    ambient::pool_fetching::AmbientPoolFetcher,
+    balancer_v2::pool_fetching::{
+        BalancerContracts, BalancerFactoryKind, BalancerPoolFetcher, NoopBalancerPoolCacheMetrics,
+    },
+    curve::pool_fetching::CurvePoolFetcher,
+    erc4626::Erc4626PoolFetcher,
+    integral::pool_fetching::IntegralPoolFetcher,
+    iziswap::pool_fetching::IZiSwapPoolFetcher,
+    maker_psm::PsmPoolFetcher,
+    mstable::pool_fetching::MStablePoolFetcher,
+    native::NativePoolFetcher,
+    saddle::pool_fetching::SaddlePoolFetcher,
+    solidly::pool_fetching::SolidlyPoolFetcher,
+    uniswap_v3::graph_api::DEFAULT_MAX_AGE_BLOCK_COUNT,
+    uniswap_v4::{
+        pool_fetching::UniswapV4PoolFetcher,
+        registry::{HookFilter, PoolRegistry},
+    },
+    wsteth::WstEthPoolFetcher,
+};
 use crate::token_pair::TokenPair;
-use crate::{recent_block_cache::Block, Web3};
+use crate::{
+    bad_token::{BadTokenQuarantine, TraceCallDetector},
+    chain_config::{self, ChainConfig},
+    current_block::CurrentBlockStream,
+    liquidity::LiquidityCollector,
+    maintenance::Maintaining,
+    recent_block_cache::{Block, CacheConfig},
+    token_filter::TokenFilter,
+    token_info::TokenInfoFetching,
+    Web3,
+};
 use anyhow::{bail, Result};
+use clap::ArgEnum as _;
+use reqwest::Client;
+use serde::Deserialize;
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
+    time::Duration,
 };
 
 pub const MAX_BATCH_SIZE: usize = 100;
 
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, clap::ArgEnum)]
+#[derive(Debug, Clone, Copy, Deserialize, Eq, Hash, PartialEq, clap::ArgEnum)]
 #[clap(rename_all = "verbatim")]
 pub enum BaselineSource {
     UniswapV2,
@@ -33,29 +93,263 @@ pub enum BaselineSource {
     Baoswap,
     Swapr,
     ZeroEx,
+    Curve,
+    MakerPsm,
+    Erc4626,
+    WstEth,
+    UniswapV4,
+    Algebra,
+    Solidly,
+    MStable,
+    IZiSwap,
+    Ambient,
+    Integral,
+    Saddle,
+    Synthetix,
+    Wombat,
+    Fraxswap,
+    Native,
+    PancakeSwap,
+    TraderJoe,
 }
 
+/// Returns the default baseline sources of a chain from the current chain
+/// configuration.
 pub fn defaults_for_chain(chain_id: u64) -> Result<Vec<BaselineSource>> {
-    Ok(match chain_id {
-        1 => vec![
-            BaselineSource::UniswapV2,
-            BaselineSource::SushiSwap,
-            BaselineSource::BalancerV2,
-            BaselineSource::ZeroEx,
-        ],
-        4 => vec![
-            BaselineSource::UniswapV2,
-            BaselineSource::SushiSwap,
-            BaselineSource::BalancerV2,
-        ],
-        100 => vec![
-            BaselineSource::Honeyswap,
-            BaselineSource::SushiSwap,
-            BaselineSource::Baoswap,
-            BaselineSource::Swapr,
-        ],
-        _ => bail!("unsupported chain {:#x}", chain_id),
-    })
+    ChainConfig::for_chain(chain_id)?.default_baseline_sources()
+}
+
+/// Returns the baseline sources to enable for a chain.
+///
+/// The configured sources take precedence over the chain defaults when
+/// specified.
+pub fn enabled_sources(
+    chain_id: u64,
+    configured: Option<Vec<BaselineSource>>,
+) -> Result<Vec<BaselineSource>> {
+    match configured {
+        Some(sources) => Ok(sources),
+        None => defaults_for_chain(chain_id),
+    }
+}
+
+/// Clients and configuration shared by all liquidity sources built with
+/// [`liquidity_collector`].
+pub struct SharedClients {
+    pub web3: Web3,
+    pub client: Client,
+    pub chain_id: u64,
+    pub token_infos: Arc<dyn TokenInfoFetching>,
+    pub block_stream: CurrentBlockStream,
+    pub cache_config: CacheConfig,
+    /// The filter of the tokens whose liquidity is used by all sources, see
+    /// [`crate::token_filter`].
+    pub token_filter: Option<Arc<TokenFilter>>,
+    /// The quarantine of bad tokens, which denies them in the token filter,
+    /// see [`crate::bad_token`].
+    pub bad_tokens: Option<Arc<BadTokenQuarantine>>,
+}
+
+impl SharedClients {
+    /// Creates new shared clients for the chain that the node is connected to.
+    pub async fn new(
+        web3: Web3,
+        client: Client,
+        token_infos: Arc<dyn TokenInfoFetching>,
+        block_stream: CurrentBlockStream,
+        cache_config: CacheConfig,
+    ) -> Result<Self> {
+        let chain_id = chain_config::chain_id(&web3).await?;
+        Ok(Self {
+            web3,
+            client,
+            chain_id,
+            token_infos,
+            block_stream,
+            cache_config,
+            token_filter: None,
+            bad_tokens: None,
+        })
+    }
+
+    /// Filters the tokens of the liquidity of all sources.
+    pub fn with_token_filter(mut self, filter: Arc<TokenFilter>) -> Self {
+        self.token_filter = Some(filter);
+        self
+    }
+
+    /// Quarantines bad tokens reported as suspicious, for example by the
+    /// route verifier, in the token filter. The node needs to support
+    /// `trace_callMany` to probe the tokens.
+    ///
+    /// Creates a token filter if none was configured, so the token filter
+    /// needs to be configured first.
+    pub fn with_bad_token_detection(mut self) -> Self {
+        let filter = self.token_filter.get_or_insert_with(Default::default);
+        let detector = TraceCallDetector {
+            web3: self.web3.clone(),
+        };
+        self.bad_tokens = Some(Arc::new(BadTokenQuarantine::new(
+            Arc::new(detector),
+            filter.clone(),
+        )));
+        self
+    }
+}
+
+/// Builds a liquidity collector with the fetchers for all enabled baseline
+/// sources, along with the maintainers that need to run in order to keep the
+/// indexed pools of these fetchers up to date and to probe suspicious tokens.
+///
+/// Sources that need contract addresses besides the deployments known to the
+/// crate read them from the factories of the chain configuration, see
+/// [`ChainConfig::factory`].
+///
+/// Fails if the node is not connected to the chain of the shared clients, or
+/// if any of the sources can't be created for the chain. This includes the
+/// sources that need more configuration than a single contract address,
+/// whose fetchers need to be added to the collector explicitly.
+pub async fn liquidity_collector(
+    clients: &SharedClients,
+    sources: &[BaselineSource],
+) -> Result<(LiquidityCollector, Vec<Arc<dyn Maintaining>>)> {
+    chain_config::ensure_chain_id(&clients.web3, clients.chain_id).await?;
+    let config = ChainConfig::for_chain(clients.chain_id)?;
+
+    let mut maintainers: Vec<Arc<dyn Maintaining>> = Vec::new();
+    let mut collector = LiquidityCollector {
+        constant_product: uniswap_like_liquidity_sources(&clients.web3, sources)
+            .await?
+            .into_values()
+            .map(|(_, fetcher)| fetcher)
+            .collect(),
+        token_filter: clients.token_filter.clone(),
+        ..Default::default()
+    };
+    if let Some(bad_tokens) = &clients.bad_tokens {
+        maintainers.push(bad_tokens.clone());
+    }
+
+    for source in sources {
+        match source {
+            BaselineSource::BalancerV2 => {
+                let contracts = BalancerContracts::new(&clients.web3).await?;
+                let fetcher = BalancerPoolFetcher::new(
+                    clients.chain_id,
+                    clients.token_infos.clone(),
+                    BalancerFactoryKind::value_variants(),
+                    clients.cache_config,
+                    clients.block_stream.clone(),
+                    Arc::new(NoopBalancerPoolCacheMetrics),
+                    clients.client.clone(),
+                    &contracts,
+                )
+                .await?;
+                let fetcher = Arc::new(fetcher);
+                collector.balancer.push(fetcher.clone());
+                maintainers.push(fetcher);
+            }
+            BaselineSource::Curve => {
+                let fetcher = Arc::new(CurvePoolFetcher::new(&clients.web3).await?);
+                collector.curve.push(fetcher.clone());
+                maintainers.push(fetcher);
+            }
+            BaselineSource::MakerPsm => {
+                let fetcher = PsmPoolFetcher::new(&clients.web3).await?;
+                collector.maker_psm.push(Arc::new(fetcher));
+            }
+            BaselineSource::Erc4626 => {
+                let fetcher = Erc4626PoolFetcher::new(
+                    &clients.web3,
+                    &erc4626::default_vaults(clients.chain_id),
+                    clients.cache_config,
+                    clients.block_stream.clone(),
+                )
+                .await?;
+                let fetcher = Arc::new(fetcher);
+                collector.erc4626.push(fetcher.clone());
+                maintainers.push(fetcher);
+            }
+            BaselineSource::WstEth => {
+                let fetcher = WstEthPoolFetcher::new(&clients.web3).await?;
+                collector.wsteth.push(Arc::new(fetcher));
+            }
+            BaselineSource::Solidly => {
+                let fetcher = SolidlyPoolFetcher::new(&clients.web3).await?;
+                collector.solidly.push(Arc::new(fetcher));
+            }
+            BaselineSource::MStable => {
+                let pools = mstable::pool_fetching::default_pools(clients.chain_id);
+                let fetcher = MStablePoolFetcher::new(&clients.web3, pools);
+                collector.mstable.push(Arc::new(fetcher));
+            }
+            BaselineSource::Native => {
+                let fetcher = NativePoolFetcher::from_config(config)?;
+                collector.native.push(Arc::new(fetcher));
+            }
+            BaselineSource::UniswapV4 => {
+                let registry =
+                    Arc::new(PoolRegistry::new(&clients.web3, config.block_finality()).await?);
+                let fetcher =
+                    UniswapV4PoolFetcher::new(&clients.web3, registry, HookFilter::default())
+                        .await?;
+                let fetcher = Arc::new(fetcher);
+                collector.uniswap_v4.push(fetcher.clone());
+                maintainers.push(fetcher);
+            }
+            BaselineSource::Algebra => {
+                let max_age =
+                    Duration::from_millis(config.block_time_ms) * DEFAULT_MAX_AGE_BLOCK_COUNT;
+                let fetcher =
+                    AlgebraPoolFetcher::new(clients.chain_id, max_age, clients.client.clone())
+                        .await?;
+                collector.algebra.push(Arc::new(fetcher));
+            }
+            BaselineSource::Ambient => {
+                let fetcher = AmbientPoolFetcher::new(
+                    &clients.web3,
+                    vec![ambient::pool_fetching::DEFAULT_POOL_INDEX],
+                )
+                .await?;
+                collector.ambient.push(Arc::new(fetcher));
+            }
+            BaselineSource::Integral => {
+                let relayer = config.factory(chain_config::INTEGRAL)?;
+                let fetcher = IntegralPoolFetcher::new(&clients.web3, relayer).await?;
+                collector.integral.push(Arc::new(fetcher));
+            }
+            BaselineSource::IZiSwap => {
+                let factory = config.factory(chain_config::IZISWAP)?;
+                let fetcher = IZiSwapPoolFetcher::new(&clients.web3, factory);
+                collector.iziswap.push(Arc::new(fetcher));
+            }
+            BaselineSource::Saddle => {
+                let registry = config.factory(chain_config::SADDLE)?;
+                let fetcher = Arc::new(SaddlePoolFetcher::new(&clients.web3, registry).await?);
+                collector.saddle.push(fetcher.clone());
+                maintainers.push(fetcher);
+            }
+            BaselineSource::UniswapV2
+            | BaselineSource::Honeyswap
+            | BaselineSource::SushiSwap
+            | BaselineSource::Baoswap
+            | BaselineSource::Swapr
+            | BaselineSource::PancakeSwap
+            | BaselineSource::TraderJoe => (),
+            // Synthetix and Wombat need the synths and pools to index, and
+            // Fraxswap pairs need the init code digest of the factory.
+            BaselineSource::Synthetix | BaselineSource::Wombat | BaselineSource::Fraxswap => {
+                bail!(
+                    "{:?} liquidity needs to be configured explicitly instead of being \
+                     enabled as a baseline source",
+                    source,
+                )
+            }
+            BaselineSource::ZeroEx => bail!("ZeroEx liquidity is not supported"),
+        }
+    }
+
+    Ok((collector, maintainers))
 }
 
 /// Returns a mapping of UniswapV2-like baseline sources to their respective
@@ -72,8 +366,26 @@ pub async fn uniswap_like_liquidity_sources(
             BaselineSource::Honeyswap => honeyswap::get_liquidity_source(web3).await?,
             BaselineSource::Baoswap => baoswap::get_liquidity_source(web3).await?,
             BaselineSource::Swapr => swapr::get_liquidity_source(web3).await?,
+            BaselineSource::PancakeSwap => pancakeswap::get_liquidity_source(web3).await?,
+            BaselineSource::TraderJoe => traderjoe::get_liquidity_source(web3).await?,
             BaselineSource::BalancerV2 => continue,
             BaselineSource::ZeroEx => continue,
+            BaselineSource::Curve => continue,
+            BaselineSource::MakerPsm => continue,
+            BaselineSource::Erc4626 => continue,
+            BaselineSource::WstEth => continue,
+            BaselineSource::UniswapV4 => continue,
+            BaselineSource::Algebra => continue,
+            BaselineSource::Solidly => continue,
+            BaselineSource::MStable => continue,
+            BaselineSource::IZiSwap => continue,
+            BaselineSource::Ambient => continue,
+            BaselineSource::Integral => continue,
+            BaselineSource::Saddle => continue,
+            BaselineSource::Synthetix => continue,
+            BaselineSource::Wombat => continue,
+            BaselineSource::Fraxswap => continue,
+            BaselineSource::Native => continue,
         };
 
         liquidity_sources.insert(*source, liquidity_source);
@@ -99,3 +411,72 @@ impl PoolFetching for PoolAggregator {
         Ok(results.into_iter().flatten().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_config::ChainConfigs;
+
+    #[test]
+    fn configured_sources_override_chain_defaults() {
+        assert_eq!(
+            enabled_sources(1, None).unwrap(),
+            defaults_for_chain(1).unwrap()
+        );
+        assert_eq!(
+            enabled_sources(1, Some(vec![BaselineSource::Curve])).unwrap(),
+            vec![BaselineSource::Curve]
+        );
+        assert_eq!(
+            enabled_sources(1337, Some(vec![BaselineSource::UniswapV2])).unwrap(),
+            vec![BaselineSource::UniswapV2]
+        );
+        assert!(enabled_sources(1337, None).is_err());
+        assert_eq!(
+            enabled_sources(56, None).unwrap(),
+            vec![BaselineSource::PancakeSwap]
+        );
+        assert_eq!(
+            enabled_sources(8453, None).unwrap(),
+            vec![BaselineSource::Solidly]
+        );
+        assert_eq!(
+            enabled_sources(43114, None).unwrap(),
+            vec![BaselineSource::TraderJoe]
+        );
+        assert_eq!(
+            enabled_sources(11155111, None).unwrap(),
+            vec![BaselineSource::UniswapV2]
+        );
+    }
+
+    #[test]
+    fn all_chains_have_default_sources() {
+        for config in ChainConfigs::embedded().chains() {
+            assert!(
+                defaults_for_chain(config.chain_id).is_ok(),
+                "no default sources for chain {}",
+                config.chain_id,
+            );
+        }
+    }
+
+    #[test]
+    fn default_sources_have_subgraphs() {
+        for config in ChainConfigs::embedded().chains() {
+            for source in config.default_baseline_sources().unwrap() {
+                let subgraph = match source {
+                    BaselineSource::BalancerV2 => chain_config::BALANCER_V2,
+                    BaselineSource::Algebra => chain_config::ALGEBRA,
+                    _ => continue,
+                };
+                assert!(
+                    config.subgraphs.contains_key(subgraph),
+                    "no {} subgraph for chain {}",
+                    subgraph,
+                    config.chain_id,
+                );
+            }
+        }
+    }
+}
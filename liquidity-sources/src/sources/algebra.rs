@@ -0,0 +1,8 @@
+//! Algebra baseline liquidity source implementation.
+//!
+//! Algebra is a concentrated liquidity engine used by DEXs such as QuickSwap
+//! V3 and Camelot V3. Unlike Uniswap V3, there is a single pool per token pair
+//! instead of one pool per fee tier, and the pool fee is adjusted dynamically.
+//! Pools are provided in the Uniswap V3 solver format with their current fee.
+pub mod graph_api;
+pub mod pool_fetching;
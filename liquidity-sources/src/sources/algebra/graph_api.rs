@@ -0,0 +1,285 @@
+//! Module containing The Graph API client used for retrieving Algebra pools
+//! from the subgraphs of Algebra based DEXs.
+
+use crate::{
+    chain_config::{self, ChainConfig},
+    event_handling::MAX_REORG_BLOCK_COUNT,
+    sources::uniswap_v3::graph_api::{TickData, Token},
+    subgraph::{ContainsId, Data, SubgraphClient},
+    u256_decimal,
+};
+use anyhow::Result;
+use ethcontract::{H160, U256};
+use num::BigInt;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+const ALL_POOLS_QUERY: &str = r#"
+    query Pools($block: Int, $pageSize: Int, $lastId: ID) {
+        pools(
+            block: { number: $block }
+            first: $pageSize
+            where: {
+                id_gt: $lastId
+                tick_not: null
+            }
+        ) {
+            id
+            token0 {
+                symbol
+                id
+                decimals
+            }
+            token1 {
+                symbol
+                id
+                decimals
+            }
+            fee
+            liquidity
+            sqrtPrice
+            tick
+        }
+    }
+"#;
+
+const POOLS_WITH_TICKS_BY_IDS_QUERY: &str = r#"
+    query Poolsbyidswithticks($block: Int, $ids: [ID]) {
+        pools(
+            block: { number: $block }
+            where: {
+                id_in: $ids
+                tick_not: null
+            }
+        ) {
+            id
+            token0 {
+                symbol
+                id
+                decimals
+            }
+            token1 {
+                symbol
+                id
+                decimals
+            }
+            fee
+            liquidity
+            sqrtPrice
+            tick
+            ticks(where: { liquidityNet_not: "0" }) {
+                id
+                tickIdx
+                liquidityNet
+                poolAddress
+            }
+        }
+    }
+"#;
+
+/// A client to the subgraph of an Algebra based DEX.
+///
+/// This client is not implemented to allow general GraphQL queries, but instead
+/// implements high-level methods that perform GraphQL queries under the hood.
+pub struct AlgebraSubgraphClient(SubgraphClient);
+
+impl AlgebraSubgraphClient {
+    /// Creates a new Algebra subgraph client for the specified chain ID.
+    pub fn for_chain(chain_id: u64, client: Client) -> Result<Self> {
+        Self::from_config(ChainConfig::for_chain(chain_id)?, client)
+    }
+
+    /// Creates a new Algebra subgraph client for the specified chain
+    /// configuration.
+    pub fn from_config(config: &ChainConfig, client: Client) -> Result<Self> {
+        Ok(Self(config.subgraph_client(chain_config::ALGEBRA, client)?))
+    }
+
+    /// Retrieves the list of registered pools from the subgraph.
+    pub async fn get_registered_pools(&self) -> Result<RegisteredPools> {
+        let block_number = self.get_safe_block().await?;
+        let pools = self
+            .0
+            .paginated_query(block_number, ALL_POOLS_QUERY)
+            .await?;
+
+        Ok(RegisteredPools {
+            fetched_block_number: block_number,
+            pools,
+        })
+    }
+
+    /// Retrieves the pools (including ticks) by ids from the subgraph.
+    pub async fn get_pools_with_ticks_by_ids(&self, ids: &[H160]) -> Result<Vec<PoolData>> {
+        let block_number = self.get_safe_block().await?;
+        Ok(self
+            .0
+            .query::<Data<PoolData>>(
+                POOLS_WITH_TICKS_BY_IDS_QUERY,
+                Some(json_map! {
+                    "block" => block_number,
+                    "ids" => json!(ids)
+                }),
+            )
+            .await?
+            .inner)
+    }
+
+    /// Retrieves a recent block number for which it is safe to assume no
+    /// reorgs will happen.
+    async fn get_safe_block(&self) -> Result<u64> {
+        Ok(self
+            .0
+            .query::<block_number_query::Data>(block_number_query::QUERY, None)
+            .await?
+            .meta
+            .block
+            .number
+            .saturating_sub(MAX_REORG_BLOCK_COUNT))
+    }
+}
+
+/// Result of the registered pool query.
+#[derive(Debug, Default, PartialEq)]
+pub struct RegisteredPools {
+    /// The block number that the data was fetched
+    pub fetched_block_number: u64,
+    /// The registered Pools
+    pub pools: Vec<PoolData>,
+}
+
+/// Pool data from an Algebra subgraph.
+#[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolData {
+    pub id: H160,
+    pub token0: Option<Token>,
+    pub token1: Option<Token>,
+    /// The current dynamic fee of the pool in hundredths of a basis point.
+    #[serde(with = "serde_with::rust::display_fromstr")]
+    pub fee: u32,
+    #[serde(with = "u256_decimal")]
+    pub liquidity: U256,
+    #[serde(with = "u256_decimal")]
+    pub sqrt_price: U256,
+    #[serde(with = "serde_with::rust::display_fromstr")]
+    pub tick: BigInt,
+    pub ticks: Option<Vec<TickData>>,
+}
+
+impl ContainsId for PoolData {
+    fn get_id(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+mod block_number_query {
+    use serde::Deserialize;
+
+    pub const QUERY: &str = r#"{
+        _meta {
+            block { number }
+        }
+    }"#;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Data {
+        #[serde(rename = "_meta")]
+        pub meta: Meta,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Meta {
+        pub block: Block,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Block {
+        pub number: u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decode_pools_data() {
+        assert_eq!(
+            serde_json::from_value::<Data<PoolData>>(json!({
+                "pools": [
+                    {
+                        "id": "0x55caabb0d2b704fd0ef8192a7e35d8837e678207",
+                        "token0": {
+                            "decimals": "6",
+                            "id": "0x2791bca1f2de4661ed88a30c99a7a9449aa84174",
+                            "symbol": "USDC"
+                        },
+                        "token1": {
+                            "decimals": "18",
+                            "id": "0x7ceb23fd6bc0add59e62ac25578270cff1b9f619",
+                            "symbol": "WETH"
+                        },
+                        "fee": "500",
+                        "liquidity": "1234567890123456789",
+                        "sqrtPrice": "1771595571142957166518320255467520",
+                        "tick": "200000",
+                        "ticks": [
+                            {
+                                "id": "0x55caabb0d2b704fd0ef8192a7e35d8837e678207#199980",
+                                "tickIdx": "199980",
+                                "liquidityNet": "1000",
+                                "poolAddress": "0x55caabb0d2b704fd0ef8192a7e35d8837e678207"
+                            }
+                        ]
+                    }
+                ],
+            }))
+            .unwrap(),
+            Data {
+                inner: vec![PoolData {
+                    id: addr!("55caabb0d2b704fd0ef8192a7e35d8837e678207"),
+                    token0: Some(Token {
+                        id: addr!("2791bca1f2de4661ed88a30c99a7a9449aa84174"),
+                        symbol: "USDC".to_string(),
+                        decimals: 6,
+                    }),
+                    token1: Some(Token {
+                        id: addr!("7ceb23fd6bc0add59e62ac25578270cff1b9f619"),
+                        symbol: "WETH".to_string(),
+                        decimals: 18,
+                    }),
+                    fee: 500,
+                    liquidity: U256::from_dec_str("1234567890123456789").unwrap(),
+                    sqrt_price: U256::from_dec_str("1771595571142957166518320255467520").unwrap(),
+                    tick: BigInt::from(200000),
+                    ticks: Some(vec![TickData {
+                        id: "0x55caabb0d2b704fd0ef8192a7e35d8837e678207#199980".to_string(),
+                        tick_idx: BigInt::from(199980),
+                        liquidity_net: BigInt::from(1000),
+                        pool_address: addr!("55caabb0d2b704fd0ef8192a7e35d8837e678207"),
+                    }]),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn unsupported_chain() {
+        assert!(AlgebraSubgraphClient::for_chain(1, Client::new()).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn quickswap_v3_subgraph_query_get_pools() {
+        let client = AlgebraSubgraphClient::for_chain(137, Client::new()).unwrap();
+        let result = client.get_registered_pools().await.unwrap();
+        println!(
+            "Retrieved {} total pools at block {}",
+            result.pools.len(),
+            result.fetched_block_number,
+        );
+    }
+}
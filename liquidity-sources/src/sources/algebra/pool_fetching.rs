@@ -0,0 +1,264 @@
+//! Module for fetching Algebra pools in the Uniswap V3 solver format.
+
+use super::graph_api::{AlgebraSubgraphClient, PoolData};
+use crate::{
+    sources::uniswap_v3::pool_fetching::{PoolFetching, PoolInfo, PoolState, PoolStats},
+    token_pair::TokenPair,
+};
+use anyhow::{Context, Result};
+use ethcontract::{H160, U256};
+use num::{rational::Ratio, Zero};
+use reqwest::Client;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The mean gas cost of swapping with an Algebra pool. This is higher than for
+/// Uniswap V3 pools because of the dynamic fee and oracle updates.
+const POOL_SWAP_GAS_COST: u64 = 350_000;
+
+impl TryFrom<PoolData> for PoolInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(pool: PoolData) -> Result<Self> {
+        Ok(Self {
+            address: pool.id,
+            tokens: vec![
+                pool.token0.context("no token0")?,
+                pool.token1.context("no token1")?,
+            ],
+            state: PoolState {
+                sqrt_price: pool.sqrt_price,
+                liquidity: pool.liquidity,
+                tick: pool.tick,
+                liquidity_net: pool
+                    .ticks
+                    .context("no ticks")?
+                    .into_iter()
+                    .filter_map(|tick| {
+                        if tick.liquidity_net.is_zero() {
+                            None
+                        } else {
+                            Some((tick.tick_idx, tick.liquidity_net))
+                        }
+                    })
+                    .collect(),
+                // Algebra pools have a single dynamic fee instead of a fee
+                // tier, so use the fee at the time the pool was fetched.
+                fee: Ratio::new(pool.fee, 1_000_000u32),
+            },
+            gas_stats: PoolStats {
+                mean_gas: U256::from(POOL_SWAP_GAS_COST),
+            },
+        })
+    }
+}
+
+struct CachedPool {
+    pool: PoolData,
+    updated_at: Instant,
+}
+
+/// Pool fetcher for an Algebra based DEX.
+///
+/// Since pool fees change with market volatility, cached pools should have a
+/// short maximum age.
+pub struct AlgebraPoolFetcher {
+    graph_api: AlgebraSubgraphClient,
+    /// There is at most one Algebra pool per token pair.
+    pools_by_token_pair: HashMap<TokenPair, H160>,
+    cache: Mutex<HashMap<H160, CachedPool>>,
+    max_age: Duration,
+}
+
+impl AlgebraPoolFetcher {
+    /// Retrieves all registered pools from the subgraph for the specified
+    /// chain.
+    pub async fn new(chain_id: u64, max_age: Duration, client: Client) -> Result<Self> {
+        let graph_api = AlgebraSubgraphClient::for_chain(chain_id, client)?;
+        let registered_pools = graph_api.get_registered_pools().await?;
+        tracing::debug!(
+            block = %registered_pools.fetched_block_number, pools = %registered_pools.pools.len(),
+            "initialized registered Algebra pools",
+        );
+
+        Ok(Self {
+            graph_api,
+            pools_by_token_pair: index_pools(&registered_pools.pools)?,
+            cache: Default::default(),
+            max_age,
+        })
+    }
+
+    /// Returns cached pools and ids of outdated pools.
+    fn get_cached_pools(&self, token_pairs: &HashSet<TokenPair>) -> (Vec<PoolData>, Vec<H160>) {
+        let now = Instant::now();
+        let cache = self.cache.lock().unwrap();
+
+        let mut cached = Vec::new();
+        let mut outdated = Vec::new();
+        for pool_id in token_pairs
+            .iter()
+            .filter_map(|pair| self.pools_by_token_pair.get(pair))
+        {
+            match cache.get(pool_id) {
+                Some(entry) if now.saturating_duration_since(entry.updated_at) < self.max_age => {
+                    cached.push(entry.pool.clone())
+                }
+                _ => outdated.push(*pool_id),
+            }
+        }
+
+        (cached, outdated)
+    }
+
+    async fn get_pools_and_update_cache(&self, pool_ids: &[H160]) -> Result<Vec<PoolData>> {
+        let pools = self.graph_api.get_pools_with_ticks_by_ids(pool_ids).await?;
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        for pool in &pools {
+            cache.insert(
+                pool.id,
+                CachedPool {
+                    pool: pool.clone(),
+                    updated_at: now,
+                },
+            );
+        }
+        Ok(pools)
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for AlgebraPoolFetcher {
+    async fn fetch(&self, token_pairs: &HashSet<TokenPair>) -> Result<Vec<PoolInfo>> {
+        let (mut pools, outdated_pools) = self.get_cached_pools(token_pairs);
+
+        if !outdated_pools.is_empty() {
+            let updated_pools = self.get_pools_and_update_cache(&outdated_pools).await?;
+            pools.extend(updated_pools);
+        }
+
+        Ok(pools.into_iter().flat_map(TryInto::try_into).collect())
+    }
+}
+
+/// Indexes pools by their token pair.
+fn index_pools(pools: &[PoolData]) -> Result<HashMap<TokenPair, H160>> {
+    let mut pools_by_token_pair = HashMap::new();
+    for pool in pools {
+        let token0 = pool.token0.as_ref().context("token0 does not exist")?.id;
+        let token1 = pool.token1.as_ref().context("token1 does not exist")?.id;
+        let pair = TokenPair::new(token0, token1).context("cant create pair")?;
+
+        if let Some(existing) = pools_by_token_pair.insert(pair, pool.id) {
+            tracing::warn!(?pair, ?existing, pool = ?pool.id, "multiple Algebra pools for pair");
+        }
+    }
+    Ok(pools_by_token_pair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::uniswap_v3::graph_api::{TickData, Token};
+    use num::BigInt;
+
+    fn token(byte: u8) -> Token {
+        Token {
+            id: H160([byte; 20]),
+            symbol: String::new(),
+            decimals: 18,
+        }
+    }
+
+    fn pool(id: u8, token0: u8, token1: u8) -> PoolData {
+        PoolData {
+            id: H160([id; 20]),
+            token0: Some(token(token0)),
+            token1: Some(token(token1)),
+            fee: 3_000,
+            liquidity: 1_000.into(),
+            sqrt_price: U256::one() << 96,
+            tick: 0.into(),
+            ticks: None,
+        }
+    }
+
+    #[test]
+    fn converts_pool_with_dynamic_fee() {
+        let pool = PoolData {
+            fee: 1_234,
+            ticks: Some(vec![
+                TickData {
+                    id: String::new(),
+                    tick_idx: (-60).into(),
+                    liquidity_net: 1_000.into(),
+                    pool_address: H160([1; 20]),
+                },
+                TickData {
+                    id: String::new(),
+                    tick_idx: 0.into(),
+                    liquidity_net: 0.into(),
+                    pool_address: H160([1; 20]),
+                },
+            ]),
+            ..pool(1, 2, 3)
+        };
+
+        let info = PoolInfo::try_from(pool).unwrap();
+        assert_eq!(info.address, H160([1; 20]));
+        assert_eq!(info.state.fee, Ratio::new(1_234, 1_000_000));
+        assert_eq!(
+            info.state.liquidity_net,
+            vec![(BigInt::from(-60), BigInt::from(1_000))],
+        );
+        assert_eq!(info.gas_stats.mean_gas, POOL_SWAP_GAS_COST.into());
+    }
+
+    #[test]
+    fn pool_without_ticks_is_invalid() {
+        assert!(PoolInfo::try_from(pool(1, 2, 3)).is_err());
+    }
+
+    #[test]
+    fn indexes_single_pool_per_pair() {
+        let pools = index_pools(&[pool(1, 2, 3), pool(4, 3, 5)]).unwrap();
+        assert_eq!(
+            pools,
+            HashMap::from([
+                (
+                    TokenPair::new(H160([2; 20]), H160([3; 20])).unwrap(),
+                    H160([1; 20])
+                ),
+                (
+                    TokenPair::new(H160([3; 20]), H160([5; 20])).unwrap(),
+                    H160([4; 20])
+                ),
+            ]),
+        );
+
+        let invalid = PoolData {
+            token1: None,
+            ..pool(1, 2, 3)
+        };
+        assert!(index_pools(&[invalid]).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn algebra_pool_fetcher_test() {
+        let fetcher = AlgebraPoolFetcher::new(137, Duration::from_secs(10), Client::new())
+            .await
+            .unwrap();
+        let token_pairs = HashSet::from([TokenPair::new(
+            addr!("2791bca1f2de4661ed88a30c99a7a9449aa84174"),
+            addr!("7ceb23fd6bc0add59e62ac25578270cff1b9f619"),
+        )
+        .unwrap()]);
+        let pools = fetcher.fetch(&token_pairs).await.unwrap();
+        assert!(!pools.is_empty());
+    }
+}
@@ -0,0 +1,6 @@
+//! Ambient (formerly CrocSwap) baseline liquidity source implementation.
+//!
+//! Ambient pools combine full range (ambient) and concentrated liquidity.
+//! Pools are provided in the Uniswap V3 solver format, where the full range
+//! liquidity is part of the active liquidity at every tick.
+pub mod pool_fetching;
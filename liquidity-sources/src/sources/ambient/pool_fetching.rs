@@ -0,0 +1,446 @@
+//! Module for fetching the current state of Ambient pools.
+//!
+//! All Ambient pools live in the single `CrocSwapDex` contract and are
+//! identified by their base and quote tokens and a pool type index. Their
+//! state is read through the `CrocQuery` lens contract.
+
+use crate::{
+    baseline_solver::BaselineSolvable,
+    recent_block_cache::Block,
+    sources::{
+        uniswap_v2::pool_fetching::handle_contract_error,
+        uniswap_v3::{pool_fetching::PoolState, swap_math},
+        MAX_BATCH_SIZE,
+    },
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::AmbientCrocQuery;
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use num::{rational::Ratio, BigInt, Zero};
+use std::collections::HashSet;
+
+/// The pool type index of the standard Ambient pools.
+pub const DEFAULT_POOL_INDEX: u64 = 420;
+
+/// The number of multiples of the pool tick size on each side of the current
+/// tick for which liquidity levels are fetched. The query lens does not expose
+/// the tick bitmap, so every level in this range is queried.
+const LEVEL_WINDOW: i32 = 64;
+
+/// All pools live in a single contract, so swaps don't need any token
+/// transfers between pools.
+const POOL_SWAP_GAS_COST: usize = 100_000;
+
+/// Concentrated liquidity is tracked in lots of `2^LOT_SIZE_BITS` units.
+const LOT_SIZE_BITS: u32 = 10;
+
+/// The lowest bit of the lots of a level flags knockout liquidity, and does
+/// not count towards the liquidity.
+const KNOCKOUT_FLAG_MASK: u128 = 1;
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The key identifying an Ambient pool.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PoolKey {
+    /// The base token, which always has the smaller address. Native Ether is
+    /// represented by the zero address.
+    pub base: H160,
+    pub quote: H160,
+    pub pool_index: U256,
+}
+
+/// The state of an Ambient pool.
+///
+/// Concentrated liquidity is only included for the ticks within
+/// `LEVEL_WINDOW` multiples of the tick size of the current tick.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pool {
+    pub key: PoolKey,
+    /// The pool state with the base token as token 0.
+    ///
+    /// Ambient prices are denominated in base token per quote token, which is
+    /// the inverse of the Uniswap V3 convention. The price and ticks are
+    /// converted so that the state can be used like any other Uniswap V3
+    /// pool.
+    pub state: PoolState,
+}
+
+impl Pool {
+    /// Returns whether the base token is sold for the quote token, or `None`
+    /// if the tokens are not the tokens of the pool.
+    fn base_for_quote(&self, in_token: H160, out_token: H160) -> Option<bool> {
+        let tokens = (self.key.base, self.key.quote);
+        if tokens == (in_token, out_token) {
+            Some(true)
+        } else if tokens == (out_token, in_token) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let base_for_quote = self.base_for_quote(in_token, out_token)?;
+        swap_math::get_amount_out(&self.state, base_for_quote, in_amount)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let base_for_quote = self.base_for_quote(in_token, out_token)?;
+        swap_math::get_amount_in(&self.state, base_for_quote, out_amount)
+    }
+
+    fn gas_cost(&self) -> usize {
+        POOL_SWAP_GAS_COST
+    }
+}
+
+/// Ambient pool fetcher.
+pub struct AmbientPoolFetcher {
+    query: AmbientCrocQuery,
+    pool_indices: Vec<U256>,
+}
+
+impl AmbientPoolFetcher {
+    /// Creates a new pool fetcher for the pools with the specified pool type
+    /// indices.
+    pub async fn new(web3: &Web3, pool_indices: Vec<u64>) -> Result<Self> {
+        Ok(Self {
+            query: AmbientCrocQuery::deployed(web3).await?,
+            pool_indices: pool_indices.into_iter().map(U256::from).collect(),
+        })
+    }
+
+    async fn fetch_curves(&self, keys: Vec<PoolKey>, block: BlockId) -> Result<Vec<FetchedCurve>> {
+        let web3 = self.query.raw_instance().web3();
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let futures = keys
+            .into_iter()
+            .map(|key| {
+                let (base, quote, index) = (key.base, key.quote, key.pool_index);
+                let params = self
+                    .query
+                    .query_pool_params(base, quote, index)
+                    .block(block)
+                    .batch_call(&mut batch);
+                let price = self
+                    .query
+                    .query_price(base, quote, index)
+                    .block(block)
+                    .batch_call(&mut batch);
+                let tick = self
+                    .query
+                    .query_curve_tick(base, quote, index)
+                    .block(block)
+                    .batch_call(&mut batch);
+                let liquidity = self
+                    .query
+                    .query_liquidity(base, quote, index)
+                    .block(block)
+                    .batch_call(&mut batch);
+                async move {
+                    let params = handle_contract_error(params.await)?;
+                    let price = handle_contract_error(price.await)?;
+                    let tick = handle_contract_error(tick.await)?;
+                    let liquidity = handle_contract_error(liquidity.await)?;
+                    Result::<_>::Ok(match (params, price, tick, liquidity) {
+                        // Pools without a schema or price are not initialized.
+                        (
+                            Some((schema, fee_rate, _, tick_size, _, _, _)),
+                            Some(price_root),
+                            Some(tick),
+                            Some(liquidity),
+                        ) if schema != 0 && price_root != 0 && tick_size != 0 => {
+                            Some(FetchedCurve {
+                                key,
+                                price_root,
+                                tick,
+                                liquidity,
+                                fee_rate,
+                                tick_size,
+                            })
+                        }
+                        _ => None,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(futures)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    async fn fetch_pools(&self, curves: Vec<FetchedCurve>, block: BlockId) -> Result<Vec<Pool>> {
+        let web3 = self.query.raw_instance().web3();
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let futures = curves
+            .into_iter()
+            .map(|curve| {
+                let key = curve.key;
+                let levels = level_ticks(curve.tick, curve.tick_size)
+                    .map(|tick| {
+                        let level = self
+                            .query
+                            .query_level(key.base, key.quote, key.pool_index, tick)
+                            .block(block)
+                            .batch_call(&mut batch);
+                        (tick, level)
+                    })
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut fetched = Vec::new();
+                    for (tick, level) in levels {
+                        fetched.push((tick, level.await));
+                    }
+                    handle_results(curve, fetched)
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(futures)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for AmbientPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let keys = token_pairs
+            .into_iter()
+            .flat_map(|pair| {
+                let (base, quote) = pair.get();
+                self.pool_indices.iter().map(move |pool_index| PoolKey {
+                    base,
+                    quote,
+                    pool_index: *pool_index,
+                })
+            })
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let block = BlockId::Number(at_block.into());
+        let curves = self.fetch_curves(keys, block).await?;
+        self.fetch_pools(curves, block).await
+    }
+}
+
+/// The current price, liquidity and parameters of a pool.
+struct FetchedCurve {
+    key: PoolKey,
+    /// The square root of the price as a 64.64 fixed point number.
+    price_root: u128,
+    tick: i32,
+    liquidity: u128,
+    /// The swap fee in hundredths of a basis point.
+    fee_rate: u16,
+    tick_size: u16,
+}
+
+fn handle_results(
+    curve: FetchedCurve,
+    levels: Vec<(i32, Result<(u128, u128), MethodError>)>,
+) -> Result<Option<Pool>> {
+    let mut ticks = Vec::new();
+    // Ticks are negated for the inverted price, so iterate in reverse to keep
+    // them sorted.
+    for (tick, level) in levels.into_iter().rev() {
+        let (bid_lots, ask_lots) = match handle_contract_error(level)? {
+            Some(level) => level,
+            None => return Ok(None),
+        };
+        // With the inverted price, the upper (ask) tick of a range becomes its
+        // lower tick and the lower (bid) tick its upper tick.
+        let net =
+            BigInt::from(lots_to_liquidity(ask_lots)) - BigInt::from(lots_to_liquidity(bid_lots));
+        if !net.is_zero() {
+            ticks.push((BigInt::from(-tick), net));
+        }
+    }
+
+    Ok(Some(Pool {
+        key: curve.key,
+        state: PoolState {
+            sqrt_price: (U256::one() << 160) / U256::from(curve.price_root),
+            liquidity: curve.liquidity.into(),
+            // The inverted price lies in `(1.0001^(-tick-1), 1.0001^-tick]`.
+            // At the upper bound, Ambient includes the liquidity starting at
+            // the tick, which corresponds to a Uniswap V3 pool at that price
+            // that has not crossed the tick yet.
+            tick: BigInt::from(-curve.tick - 1),
+            liquidity_net: ticks,
+            fee: Ratio::new(curve.fee_rate.into(), 1_000_000u32),
+        },
+    }))
+}
+
+/// Returns the ticks around the current tick for which levels are fetched.
+fn level_ticks(tick: i32, tick_size: u16) -> impl Iterator<Item = i32> {
+    let tick_size = tick_size as i32;
+    let grid = tick.div_euclid(tick_size);
+    (grid - LEVEL_WINDOW..=grid + LEVEL_WINDOW).map(move |grid| grid * tick_size)
+}
+
+/// Converts the lots of a level to liquidity.
+fn lots_to_liquidity(lots: u128) -> u128 {
+    (lots & !KNOCKOUT_FLAG_MASK) << LOT_SIZE_BITS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ethcontract_error, transport::create_env_test_transport};
+    use maplit::hashset;
+
+    fn curve() -> FetchedCurve {
+        FetchedCurve {
+            key: PoolKey {
+                base: H160::zero(),
+                quote: H160([2; 20]),
+                pool_index: DEFAULT_POOL_INDEX.into(),
+            },
+            // A price of 4 base tokens per quote token.
+            price_root: 2 << 64,
+            tick: 13_863,
+            liquidity: 1 << 20,
+            fee_rate: 500,
+            tick_size: 16,
+        }
+    }
+
+    #[test]
+    fn computes_level_ticks() {
+        let ticks = level_ticks(13_863, 16).collect::<Vec<_>>();
+        assert_eq!(ticks.len(), 129);
+        assert_eq!(ticks[0], 12_832);
+        assert_eq!(ticks[64], 13_856);
+        assert_eq!(ticks[128], 14_880);
+
+        let ticks = level_ticks(-1, 16).collect::<Vec<_>>();
+        assert_eq!(ticks[64], -16);
+    }
+
+    #[test]
+    fn converts_lots_to_liquidity() {
+        assert_eq!(lots_to_liquidity(0), 0);
+        assert_eq!(lots_to_liquidity(2), 2048);
+        // Knockout liquidity flag.
+        assert_eq!(lots_to_liquidity(3), 2048);
+    }
+
+    #[test]
+    fn converts_pool_state() {
+        let pool = handle_results(
+            curve(),
+            vec![
+                (13_840, Ok((4, 0))),
+                (13_856, Ok((0, 0))),
+                (13_872, Ok((0, 4))),
+            ],
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(pool.key, curve().key);
+        assert_eq!(
+            pool.state,
+            PoolState {
+                // A price of 1/4 quote tokens per base token.
+                sqrt_price: U256::one() << 95,
+                liquidity: (1 << 20).into(),
+                tick: BigInt::from(-13_864),
+                liquidity_net: vec![
+                    (BigInt::from(-13_872), 4096.into()),
+                    (BigInt::from(-13_840), (-4096).into()),
+                ],
+                fee: Ratio::new(5, 10_000),
+            }
+        );
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let results = vec![(0, Err(ethcontract_error::testing_node_error()))];
+        assert!(handle_results(curve(), results).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let results = vec![(0, Err(ethcontract_error::testing_contract_error()))];
+        assert!(handle_results(curve(), results).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn fetch_ambient_pool() {
+        let web3 = Web3::new(create_env_test_transport());
+        let fetcher = AmbientPoolFetcher::new(&web3, vec![DEFAULT_POOL_INDEX])
+            .await
+            .unwrap();
+
+        // Native Ether and USDC.
+        let pools = fetcher
+            .fetch(
+                hashset! {
+                    TokenPair::new(
+                        H160::zero(),
+                        addr!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+                    )
+                    .unwrap(),
+                },
+                Block::Recent,
+            )
+            .await
+            .unwrap();
+        println!("ETH <> USDC pool: {:#?}", pools);
+        assert_eq!(pools.len(), 1);
+    }
+
+    #[test]
+    fn quotes_swaps_in_both_directions() {
+        // A full range position at a price of 1.
+        let pool = Pool {
+            key: curve().key,
+            state: PoolState {
+                sqrt_price: U256::one() << 96,
+                liquidity: 10u128.pow(18).into(),
+                tick: 0.into(),
+                liquidity_net: vec![
+                    ((-887_220).into(), 10u128.pow(18).into()),
+                    (887_220.into(), (-10i128.pow(18)).into()),
+                ],
+                fee: Ratio::new(3_000, 1_000_000),
+            },
+        };
+        let (base, quote) = (pool.key.base, pool.key.quote);
+        let amount = U256::exp10(15);
+
+        assert_eq!(
+            pool.get_amount_out(quote, (amount, base)),
+            Some(996_006_981_039_903u128.into())
+        );
+        assert_eq!(
+            pool.get_amount_in(quote, (amount, base)),
+            Some(1_004_013_040_121_367u128.into())
+        );
+        assert_eq!(pool.get_amount_out(quote, (amount, H160([3; 20]))), None);
+        assert_eq!(pool.get_amount_in(base, (amount, base)), None);
+    }
+}
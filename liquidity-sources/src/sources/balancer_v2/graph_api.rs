@@ -10,10 +10,11 @@
 
 use super::swap::fixed_point::Bfp;
 use crate::{
+    chain_config::{self, ChainConfig},
     event_handling::MAX_REORG_BLOCK_COUNT,
     subgraph::{ContainsId, SubgraphClient},
 };
-use anyhow::{bail, Result};
+use anyhow::Result;
 use ethcontract::{H160, H256};
 use reqwest::Client;
 use serde::Deserialize;
@@ -29,16 +30,15 @@ pub struct BalancerSubgraphClient(SubgraphClient);
 impl BalancerSubgraphClient {
     /// Creates a new Balancer subgraph client for the specified chain ID.
     pub fn for_chain(chain_id: u64, client: Client) -> Result<Self> {
-        let subgraph_name = match chain_id {
-            1 => "balancer-v2",
-            4 => "balancer-rinkeby-v2",
-            _ => bail!("unsupported chain {}", chain_id),
-        };
-        Ok(Self(SubgraphClient::new(
-            "balancer-labs",
-            subgraph_name,
-            client,
-        )?))
+        Self::from_config(ChainConfig::for_chain(chain_id)?, client)
+    }
+
+    /// Creates a new Balancer subgraph client for the specified chain
+    /// configuration.
+    pub fn from_config(config: &ChainConfig, client: Client) -> Result<Self> {
+        Ok(Self(
+            config.subgraph_client(chain_config::BALANCER_V2, client)?,
+        ))
     }
 
     /// Retrieves the list of registered pools from the subgraph.
@@ -135,6 +135,10 @@ pub enum PoolType {
     Stable,
     Weighted,
     LiquidityBootstrapping,
+    ComposableStable,
+    AaveLinear,
+    Managed,
+    GyroE,
 }
 
 /// Token data for pools.
@@ -163,6 +167,10 @@ mod pools_query {
                         "Stable",
                         "Weighted",
                         "LiquidityBootstrapping",
+                        "ComposableStable",
+                        "AaveLinear",
+                        "Managed",
+                        "GyroE",
                     ]
                 }
             ) {
@@ -1,7 +1,8 @@
 //! Pool Fetching is primarily concerned with retrieving relevant pools from the `BalancerPoolRegistry`
 //! when given a collection of `TokenPair`. Each of these pools are then queried for
-//! their `token_balances` and the `PoolFetcher` returns all up-to-date `Weighted` and `Stable`
-//! pools to be consumed by external users (e.g. Price Estimators and Solvers).
+//! their `token_balances` and the `PoolFetcher` returns all up-to-date `Weighted`, `Stable`,
+//! `ComposableStable`, `Linear` and Gyroscope E-CLP pools to be consumed by external users (e.g.
+//! Price Estimators and Solvers).
 
 mod aggregate;
 mod cache;
@@ -18,13 +19,16 @@ use super::{
     pool_init::PoolInitializing,
     pools::{
         common::{self, PoolInfoFetcher},
-        stable, weighted, FactoryIndexing, Pool, PoolIndexing, PoolKind,
+        composable_stable, gyro_eclp, linear, stable, weighted, FactoryIndexing, Pool,
+        PoolIndexing, PoolKind,
     },
     swap::fixed_point::Bfp,
 };
 use crate::token_pair::TokenPair;
 use crate::{
-    current_block::CurrentBlockStream,
+    chain_config::ChainConfig,
+    current_block::{BlockFinality, CurrentBlockStream},
+    event_handling::{DeferredRollbackHandler, RollbackHandling},
     maintenance::Maintaining,
     recent_block_cache::{Block, CacheConfig},
     token_info::TokenInfoFetching,
@@ -33,11 +37,13 @@ use crate::{
 use anyhow::Result;
 use clap::ArgEnum;
 use contracts::{
-    BalancerV2LiquidityBootstrappingPoolFactory,
-    BalancerV2NoProtocolFeeLiquidityBootstrappingPoolFactory, BalancerV2StablePoolFactory,
-    BalancerV2Vault, BalancerV2WeightedPool2TokensFactory, BalancerV2WeightedPoolFactory,
+    BalancerV2AaveLinearPoolFactory, BalancerV2ComposableStablePoolFactory,
+    BalancerV2GyroEclpPoolFactory, BalancerV2LiquidityBootstrappingPoolFactory,
+    BalancerV2ManagedPoolFactory, BalancerV2NoProtocolFeeLiquidityBootstrappingPoolFactory,
+    BalancerV2StablePoolFactory, BalancerV2Vault, BalancerV2WeightedPool2TokensFactory,
+    BalancerV2WeightedPoolFactory,
 };
-use ethcontract::{Instance, H160, H256};
+use ethcontract::{errors::DeployError, Instance, H160, H256, U256};
 use reqwest::Client;
 use std::{
     collections::{HashMap, HashSet},
@@ -45,6 +51,7 @@ use std::{
 };
 
 pub use common::TokenState;
+pub use composable_stable::TokenState as RatedTokenState;
 pub use stable::AmplificationParameter;
 pub use weighted::TokenState as WeightedTokenState;
 pub trait BalancerPoolEvaluating {
@@ -101,10 +108,94 @@ impl StablePool {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct ComposableStablePool {
+    pub common: CommonPoolState,
+    /// The pool reserves, excluding the pool's own BPT.
+    pub reserves: HashMap<H160, RatedTokenState>,
+    pub amplification_parameter: AmplificationParameter,
+}
+
+impl ComposableStablePool {
+    pub fn new_unpaused(pool_id: H256, state: composable_stable::PoolState) -> Self {
+        ComposableStablePool {
+            common: CommonPoolState {
+                id: pool_id,
+                address: pool_address_from_id(pool_id),
+                swap_fee: state.swap_fee,
+                paused: false,
+            },
+            reserves: state.tokens.into_iter().collect(),
+            amplification_parameter: state.amplification_parameter,
+        }
+    }
+}
+
+/// A linear pool. Note that the pool's BPT, which is also tradable, is the
+/// pool address.
+#[derive(Clone, Debug)]
+pub struct LinearPool {
+    pub common: CommonPoolState,
+    /// The main and wrapped token reserves.
+    pub reserves: HashMap<H160, RatedTokenState>,
+    pub main_token: H160,
+    pub wrapped_token: H160,
+    pub virtual_supply: U256,
+    pub lower_target: Bfp,
+    pub upper_target: Bfp,
+}
+
+impl LinearPool {
+    pub fn new_unpaused(pool_id: H256, state: linear::PoolState) -> Self {
+        LinearPool {
+            common: CommonPoolState {
+                id: pool_id,
+                address: pool_address_from_id(pool_id),
+                swap_fee: state.swap_fee,
+                paused: false,
+            },
+            reserves: state.tokens.into_iter().collect(),
+            main_token: state.main_token,
+            wrapped_token: state.wrapped_token,
+            virtual_supply: state.virtual_supply,
+            lower_target: state.lower_target,
+            upper_target: state.upper_target,
+        }
+    }
+}
+
+/// A Gyroscope E-CLP pool.
+#[derive(Clone, Debug)]
+pub struct GyroEclpPool {
+    pub common: CommonPoolState,
+    pub reserves: HashMap<H160, RatedTokenState>,
+    pub params: gyro_eclp::Params,
+    pub derived: gyro_eclp::DerivedParams,
+}
+
+impl GyroEclpPool {
+    pub fn new_unpaused(pool_id: H256, state: gyro_eclp::PoolState) -> Self {
+        GyroEclpPool {
+            common: CommonPoolState {
+                id: pool_id,
+                address: pool_address_from_id(pool_id),
+                swap_fee: state.swap_fee,
+                paused: false,
+            },
+            reserves: state.tokens.into_iter().collect(),
+            params: state.params,
+            derived: state.derived,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct FetchedBalancerPools {
     pub stable_pools: Vec<StablePool>,
     pub weighted_pools: Vec<WeightedPool>,
+    pub composable_stable_pools: Vec<ComposableStablePool>,
+    pub linear_pools: Vec<LinearPool>,
+    pub gyro_eclp_pools: Vec<GyroEclpPool>,
 }
 
 impl FetchedBalancerPools {
@@ -120,6 +211,22 @@ impl FetchedBalancerPools {
                 .iter()
                 .flat_map(|pool| pool.reserves.keys().copied()),
         );
+        tokens.extend(
+            self.composable_stable_pools
+                .iter()
+                .flat_map(|pool| pool.reserves.keys().copied()),
+        );
+        tokens.extend(self.linear_pools.iter().flat_map(|pool| {
+            pool.reserves
+                .keys()
+                .copied()
+                .chain(std::iter::once(pool.common.address))
+        }));
+        tokens.extend(
+            self.gyro_eclp_pools
+                .iter()
+                .flat_map(|pool| pool.reserves.keys().copied()),
+        );
         tokens
     }
 }
@@ -147,6 +254,10 @@ pub enum BalancerFactoryKind {
     Stable,
     LiquidityBootstrapping,
     NoProtocolFeeLiquidityBootstrapping,
+    ComposableStable,
+    AaveLinear,
+    Managed,
+    GyroEclp,
 }
 
 /// All balancer related contracts that we expect to exist.
@@ -157,10 +268,25 @@ pub struct BalancerContracts {
     pub stable: BalancerV2StablePoolFactory,
     pub liquidity_bootstrapping: BalancerV2LiquidityBootstrappingPoolFactory,
     pub no_fee_liquidity_bootstrapping: BalancerV2NoProtocolFeeLiquidityBootstrappingPoolFactory,
+    /// Newer pool factories that are not deployed on all supported networks.
+    pub composable_stable: Option<BalancerV2ComposableStablePoolFactory>,
+    pub aave_linear: Option<BalancerV2AaveLinearPoolFactory>,
+    pub managed: Option<BalancerV2ManagedPoolFactory>,
+    pub gyro_eclp: Option<BalancerV2GyroEclpPoolFactory>,
 }
 
 impl BalancerContracts {
     pub async fn new(web3: &Web3) -> Result<Self> {
+        macro_rules! deployed_if_available {
+            ($contract:ty) => {
+                match <$contract>::deployed(web3).await {
+                    Ok(contract) => Some(contract),
+                    Err(DeployError::NotFound(_)) => None,
+                    Err(err) => return Err(err.into()),
+                }
+            };
+        }
+
         Ok(Self {
             vault: BalancerV2Vault::deployed(web3).await?,
             weighted: BalancerV2WeightedPoolFactory::deployed(web3).await?,
@@ -170,6 +296,10 @@ impl BalancerContracts {
                 .await?,
             no_fee_liquidity_bootstrapping:
                 BalancerV2NoProtocolFeeLiquidityBootstrappingPoolFactory::deployed(web3).await?,
+            composable_stable: deployed_if_available!(BalancerV2ComposableStablePoolFactory),
+            aave_linear: deployed_if_available!(BalancerV2AaveLinearPoolFactory),
+            managed: deployed_if_available!(BalancerV2ManagedPoolFactory),
+            gyro_eclp: deployed_if_available!(BalancerV2GyroEclpPoolFactory),
         })
     }
 }
@@ -187,15 +317,30 @@ impl BalancerPoolFetcher {
         contracts: &BalancerContracts,
     ) -> Result<Self> {
         let pool_initializer = BalancerSubgraphClient::for_chain(chain_id, client)?;
-        let fetcher = Arc::new(Cache::new(
-            create_aggregate_pool_fetcher(pool_initializer, token_infos, factories, contracts)
-                .await?,
+        let finality = ChainConfig::for_chain(chain_id)?.block_finality();
+        // The cached pool states of reorged blocks need to be dropped after
+        // deep reorgs of the factory events, but the cache can only be created
+        // after the registries that index them.
+        let rollback_handler = Arc::new(DeferredRollbackHandler::default());
+        let fetcher = Cache::new(
+            create_aggregate_pool_fetcher(
+                pool_initializer,
+                token_infos,
+                factories,
+                contracts,
+                finality,
+                rollback_handler.clone(),
+            )
+            .await?,
             config,
             block_stream,
             metrics,
-        )?);
+        )?;
+        rollback_handler.set(fetcher.rollback_handler())?;
 
-        Ok(Self { fetcher })
+        Ok(Self {
+            fetcher: Arc::new(fetcher),
+        })
     }
 
     async fn fetch_pools(
@@ -233,6 +378,15 @@ impl BalancerPoolFetching for BalancerPoolFetcher {
                     PoolKind::Stable(state) => fetched_pools
                         .stable_pools
                         .push(StablePool::new_unpaused(pool.id, state)),
+                    PoolKind::ComposableStable(state) => fetched_pools
+                        .composable_stable_pools
+                        .push(ComposableStablePool::new_unpaused(pool.id, state)),
+                    PoolKind::Linear(state) => fetched_pools
+                        .linear_pools
+                        .push(LinearPool::new_unpaused(pool.id, state)),
+                    PoolKind::GyroEclp(state) => fetched_pools
+                        .gyro_eclp_pools
+                        .push(GyroEclpPool::new_unpaused(pool.id, state)),
                 }
                 fetched_pools
             },
@@ -249,12 +403,18 @@ impl Maintaining for BalancerPoolFetcher {
     }
 }
 
-/// Creates an aggregate fetcher for all supported pool factories.
+/// Creates an aggregate fetcher for all supported pool factories, skipping
+/// optional factories that are not deployed on the network.
+///
+/// The factory events are indexed with the specified block finality, and the
+/// rollback handler is called after deep reorgs of these events.
 async fn create_aggregate_pool_fetcher(
     pool_initializer: impl PoolInitializing,
     token_infos: Arc<dyn TokenInfoFetching>,
     factories: &[BalancerFactoryKind],
     contracts: &BalancerContracts,
+    finality: BlockFinality,
+    rollback_handler: Arc<dyn RollbackHandling>,
 ) -> Result<Aggregate> {
     let registered_pools = pool_initializer.initialize_pools().await?;
     let fetched_block_number = registered_pools.fetched_block_number;
@@ -270,10 +430,29 @@ async fn create_aggregate_pool_fetcher(
                 registered_pools_by_factory
                     .remove(&$factory.address())
                     .unwrap_or_else(|| RegisteredPools::empty(fetched_block_number)),
+                finality,
+                rollback_handler.clone(),
             )?
         }};
     }
 
+    // Optional factories are only deployed on some networks, so missing ones
+    // are skipped instead of failing to create the fetcher for all pools.
+    macro_rules! optional_registry {
+        ($kind:expr, $factory:expr) => {
+            match $factory {
+                Some(factory) => registry!(factory),
+                None => {
+                    tracing::warn!(
+                        kind = ?$kind,
+                        "skipping Balancer pool factory not deployed on this network"
+                    );
+                    continue;
+                }
+            }
+        };
+    }
+
     let mut fetchers = Vec::new();
     for factory in factories {
         let registry = match factory {
@@ -288,6 +467,12 @@ async fn create_aggregate_pool_fetcher(
             BalancerFactoryKind::NoProtocolFeeLiquidityBootstrapping => {
                 registry!(&contracts.no_fee_liquidity_bootstrapping)
             }
+            BalancerFactoryKind::ComposableStable => {
+                optional_registry!(factory, &contracts.composable_stable)
+            }
+            BalancerFactoryKind::AaveLinear => optional_registry!(factory, &contracts.aave_linear),
+            BalancerFactoryKind::Managed => optional_registry!(factory, &contracts.managed),
+            BalancerFactoryKind::GyroEclp => optional_registry!(factory, &contracts.gyro_eclp),
         };
         fetchers.push(registry);
     }
@@ -316,6 +501,8 @@ fn create_internal_pool_fetcher<Factory>(
     token_infos: Arc<dyn TokenInfoFetching>,
     factory_instance: &Instance<Web3Transport>,
     registered_pools: RegisteredPools,
+    finality: BlockFinality,
+    rollback_handler: Arc<dyn RollbackHandling>,
 ) -> Result<Box<dyn InternalPoolFetching>>
 where
     Factory: FactoryIndexing,
@@ -332,6 +519,8 @@ where
         factory_instance,
         initial_pools,
         start_sync_at_block,
+        finality,
+        rollback_handler,
     )))
 }
 
@@ -380,7 +569,10 @@ mod tests {
         println!("Indexing events for chain {}", chain_id);
 
         let pool_initializer = EmptyPoolInitializer::for_chain(chain_id);
-        let token_infos = TokenInfoFetcher { web3: web3.clone() };
+        let token_infos = TokenInfoFetcher {
+            web3: web3.clone(),
+            overrides: Default::default(),
+        };
         let contracts = BalancerContracts::new(&web3).await.unwrap();
         let pool_fetcher = BalancerPoolFetcher {
             fetcher: Arc::new(
@@ -389,6 +581,8 @@ mod tests {
                     Arc::new(token_infos),
                     BalancerFactoryKind::value_variants(),
                     &contracts,
+                    ChainConfig::for_chain(chain_id).unwrap().block_finality(),
+                    Arc::new(DeferredRollbackHandler::default()),
                 )
                 .await
                 .unwrap(),
@@ -431,13 +625,21 @@ mod tests {
             match &fetched_pool.kind {
                 PoolKind::Weighted(state) => {
                     for token in &subgraph_pool.tokens {
+                        // Managed pools register their own BPT, which is
+                        // excluded from the state.
+                        if token.address == subgraph_pool.address {
+                            continue;
+                        }
                         let token_state = &state.tokens[&token.address];
                         assert_eq!(token_state.common.scaling_exponent, 18 - token.decimals);
 
-                        // Don't check weights for LBPs because they may be out
-                        // of date in the subgraph. See:
+                        // Don't check weights for LBPs and managed pools
+                        // because they may be out of date in the subgraph. See:
                         // <https://github.com/balancer-labs/balancer-subgraph-v2/issues/173>
-                        if subgraph_pool.pool_type != PoolType::LiquidityBootstrapping {
+                        if !matches!(
+                            subgraph_pool.pool_type,
+                            PoolType::LiquidityBootstrapping | PoolType::Managed
+                        ) {
                             assert_eq!(token_state.weight, token.weight.unwrap());
                         }
                     }
@@ -448,6 +650,31 @@ mod tests {
                         assert_eq!(token_state.scaling_exponent, 18 - token.decimals);
                     }
                 }
+                PoolKind::ComposableStable(state) => {
+                    for token in &subgraph_pool.tokens {
+                        // The pool's own BPT is excluded from the state.
+                        if token.address == subgraph_pool.address {
+                            continue;
+                        }
+                        let token_state = &state.tokens[&token.address];
+                        assert_eq!(token_state.common.scaling_exponent, 18 - token.decimals);
+                    }
+                }
+                PoolKind::Linear(state) => {
+                    for token in &subgraph_pool.tokens {
+                        if token.address == subgraph_pool.address {
+                            continue;
+                        }
+                        let token_state = &state.tokens[&token.address];
+                        assert_eq!(token_state.common.scaling_exponent, 18 - token.decimals);
+                    }
+                }
+                PoolKind::GyroEclp(state) => {
+                    for token in &subgraph_pool.tokens {
+                        let token_state = &state.tokens[&token.address];
+                        assert_eq!(token_state.common.scaling_exponent, 18 - token.decimals);
+                    }
+                }
             };
         }
         tracing::warn!(?unknown_pools);
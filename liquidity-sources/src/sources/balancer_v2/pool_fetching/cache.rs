@@ -6,16 +6,21 @@
 use super::internal::InternalPoolFetching;
 use crate::{
     current_block::CurrentBlockStream,
+    event_handling::RollbackHandling,
     maintenance::Maintaining,
     recent_block_cache::{
         Block, CacheConfig, CacheFetching, CacheKey, CacheMetrics, RecentBlockCache,
     },
-    sources::balancer_v2::pools::Pool,
+    sources::balancer_v2::pools::{Pool, PoolKind},
     token_pair::TokenPair,
 };
 use anyhow::Result;
 use ethcontract::H256;
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    mem,
+    sync::{Arc, Weak},
+};
 
 /// Trait used for Balancer pool cache metrics.
 pub trait BalancerPoolCacheMetrics: Send + Sync {
@@ -38,7 +43,7 @@ where
     Inner: InternalPoolFetching,
 {
     inner: Arc<Inner>,
-    cache: PoolCache<Inner>,
+    cache: Arc<PoolCache<Inner>>,
 }
 
 impl<Inner> Cache<Inner>
@@ -53,8 +58,18 @@ where
     ) -> Result<Self> {
         let inner = Arc::new(inner);
         let fetcher = CacheFetcher(inner.clone());
-        let cache = RecentBlockCache::new(config, fetcher, block_stream, metrics)?;
-        Ok(Self { inner, cache })
+        let cache = RecentBlockCache::new("balancer_v2", config, fetcher, block_stream, metrics)?;
+        Ok(Self {
+            inner,
+            cache: Arc::new(cache),
+        })
+    }
+
+    /// Returns the handler that drops the cached pools of reorged blocks, to
+    /// be registered with the event handlers of the inner pool fetcher.
+    pub fn rollback_handler(&self) -> Weak<dyn RollbackHandling> {
+        let cache: Arc<dyn RollbackHandling> = self.cache.clone();
+        Arc::downgrade(&cache)
     }
 }
 
@@ -91,6 +106,22 @@ impl CacheKey<Pool> for H256 {
     fn for_value(pool: &Pool) -> Self {
         pool.id
     }
+
+    fn approximate_size(pool: &Pool) -> usize {
+        // Pool states are mostly made up of their token states, which are allocated separately.
+        fn tokens_size<K, V>(tokens: &BTreeMap<K, V>) -> usize {
+            tokens.len() * mem::size_of::<(K, V)>()
+        }
+
+        mem::size_of::<Pool>()
+            + match &pool.kind {
+                PoolKind::Weighted(state) => tokens_size(&state.tokens),
+                PoolKind::Stable(state) => tokens_size(&state.tokens),
+                PoolKind::ComposableStable(state) => tokens_size(&state.tokens),
+                PoolKind::Linear(state) => tokens_size(&state.tokens),
+                PoolKind::GyroEclp(state) => tokens_size(&state.tokens),
+            }
+    }
 }
 
 /// Internal struct for implementing `CacheFetching` for `InnerPoolFetching`
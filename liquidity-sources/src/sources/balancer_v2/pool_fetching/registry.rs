@@ -4,8 +4,9 @@
 use super::{internal::InternalPoolFetching, pool_storage::PoolStorage};
 use crate::token_pair::TokenPair;
 use crate::{
+    current_block::BlockFinality,
     ethcontract_error::EthcontractErrorType,
-    event_handling::EventHandler,
+    event_handling::{EventHandler, RollbackHandling},
     impl_event_retrieving,
     maintenance::Maintaining,
     recent_block_cache::Block,
@@ -48,20 +49,28 @@ impl<Factory> Registry<Factory>
 where
     Factory: FactoryIndexing,
 {
-    /// Returns a new pool registry for the specified factory.
+    /// Returns a new pool registry for the specified factory, indexing its
+    /// events with the specified block finality and rolling back the state
+    /// of the rollback handler after deep reorgs.
     pub fn new(
         fetcher: Arc<dyn PoolInfoFetching<Factory>>,
         factory_instance: &Instance<Web3Transport>,
         initial_pools: Vec<Factory::PoolInfo>,
         start_sync_at_block: Option<u64>,
+        finality: BlockFinality,
+        rollback_handler: Arc<dyn RollbackHandling>,
     ) -> Self {
         let web3 = factory_instance.web3();
-        let updater = Mutex::new(EventHandler::new(
-            web3.clone(),
-            BasePoolFactoryContract(base_pool_factory(factory_instance)),
-            PoolStorage::new(initial_pools, fetcher.clone()),
-            start_sync_at_block,
-        ));
+        let updater = Mutex::new(
+            EventHandler::new(
+                web3.clone(),
+                BasePoolFactoryContract(base_pool_factory(factory_instance)),
+                PoolStorage::new(initial_pools, fetcher.clone()),
+                start_sync_at_block,
+            )
+            .with_block_finality(finality)
+            .with_rollback_handler(rollback_handler),
+        );
         Self {
             web3,
             fetcher,
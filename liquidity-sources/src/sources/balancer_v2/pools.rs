@@ -8,7 +8,11 @@
 //! types by just implementing the required `BalancerFactory` trait.
 
 pub mod common;
+pub mod composable_stable;
+pub mod gyro_eclp;
+pub mod linear;
 pub mod liquidity_bootstrapping;
+pub mod managed;
 pub mod no_protocol_fee_liquidity_bootstrapping;
 pub mod stable;
 pub mod weighted;
@@ -34,6 +38,9 @@ pub struct Pool {
 pub enum PoolKind {
     Weighted(weighted::PoolState),
     Stable(stable::PoolState),
+    ComposableStable(composable_stable::PoolState),
+    Linear(linear::PoolState),
+    GyroEclp(gyro_eclp::PoolState),
 }
 
 macro_rules! impl_from_state {
@@ -48,6 +55,9 @@ macro_rules! impl_from_state {
 
 impl_from_state!(weighted::PoolState, Weighted);
 impl_from_state!(stable::PoolState, Stable);
+impl_from_state!(composable_stable::PoolState, ComposableStable);
+impl_from_state!(linear::PoolState, Linear);
+impl_from_state!(gyro_eclp::PoolState, GyroEclp);
 
 #[derive(Clone, Debug, PartialEq)]
 /// Balancer pool status.
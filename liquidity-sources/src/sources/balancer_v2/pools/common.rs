@@ -381,9 +381,9 @@ mod tests {
             .withf(move |t| t == tokens)
             .returning(move |_| {
                 hashmap! {
-                    tokens[0] => TokenInfo { decimals: Some(18), symbol: None },
-                    tokens[1] => TokenInfo { decimals: Some(18), symbol: None },
-                    tokens[2] => TokenInfo { decimals: Some(6), symbol: None },
+                    tokens[0] => TokenInfo { decimals: Some(18), symbol: None, name: None },
+                    tokens[1] => TokenInfo { decimals: Some(18), symbol: None, name: None },
+                    tokens[2] => TokenInfo { decimals: Some(6), symbol: None, name: None },
                 }
             });
 
@@ -781,7 +781,7 @@ mod tests {
         let mut token_infos = MockTokenInfoFetching::new();
         token_infos.expect_get_token_infos().returning(move |_| {
             hashmap! {
-                token => TokenInfo { decimals: None, symbol: None },
+                token => TokenInfo { decimals: None, symbol: None, name: None },
             }
         });
 
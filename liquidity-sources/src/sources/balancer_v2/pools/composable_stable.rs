@@ -0,0 +1,291 @@
+//! Module implementing composable stable pool specific indexing logic.
+//!
+//! Composable stable pools register their own BPT as one of the pool tokens.
+//! Swapping to or from the BPT corresponds to joining or exiting the pool,
+//! which we don't support, so the BPT is not included in the pool state.
+//! Additionally, tokens may have rate providers (for example, when the pool
+//! token is itself a linear pool BPT in "boosted" pools), so we keep track of
+//! the current token rates as part of the pool state.
+
+pub use super::stable::AmplificationParameter;
+use super::{common, FactoryIndexing, PoolIndexing};
+use crate::{
+    sources::balancer_v2::{
+        graph_api::{PoolData, PoolType},
+        swap::fixed_point::Bfp,
+    },
+    Web3CallBatch,
+};
+use anyhow::{anyhow, ensure, Result};
+use contracts::{BalancerV2ComposableStablePool, BalancerV2ComposableStablePoolFactory};
+use ethcontract::{BlockId, H160, U256};
+use futures::{future::BoxFuture, FutureExt as _};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PoolInfo {
+    pub common: common::PoolInfo,
+}
+
+impl PoolIndexing for PoolInfo {
+    fn from_graph_data(pool: &PoolData, block_created: u64) -> Result<Self> {
+        Ok(PoolInfo {
+            common: common::PoolInfo::for_type(PoolType::ComposableStable, pool, block_created)?,
+        })
+    }
+
+    fn common(&self) -> &common::PoolInfo {
+        &self.common
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolState {
+    pub tokens: BTreeMap<H160, TokenState>,
+    pub swap_fee: Bfp,
+    pub amplification_parameter: AmplificationParameter,
+}
+
+/// Token state for pools whose tokens can have a rate provider.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenState {
+    pub common: common::TokenState,
+    pub rate: Bfp,
+}
+
+impl TokenState {
+    /// Creates a new token state from its Balancer scaling factor.
+    ///
+    /// Balancer scaling factors are defined as `10 ** (18 - decimals)`
+    /// multiplied by the token rate, so we can recover the rate by dividing by
+    /// the decimals scaling factor (which is always exact).
+    pub fn from_scaling_factor(common: common::TokenState, scaling_factor: U256) -> Result<Self> {
+        let decimals_factor = U256::exp10(common.scaling_exponent as usize);
+        ensure!(
+            (scaling_factor % decimals_factor).is_zero(),
+            "scaling factor {} is not a multiple of the token decimals scaling factor",
+            scaling_factor,
+        );
+        Ok(Self {
+            common,
+            rate: Bfp::from_wei(scaling_factor / decimals_factor),
+        })
+    }
+}
+
+/// Combines common pool token states with their corresponding scaling factors
+/// as returned by the `getScalingFactors` pool method, skipping the BPT.
+pub fn tokens_with_rates(
+    pool_info: &common::PoolInfo,
+    mut common_tokens: BTreeMap<H160, common::TokenState>,
+    scaling_factors: Vec<U256>,
+) -> Result<BTreeMap<H160, TokenState>> {
+    ensure!(
+        pool_info.tokens.len() == scaling_factors.len(),
+        "pool token and scaling factor count mismatch",
+    );
+    pool_info
+        .tokens
+        .iter()
+        .zip(scaling_factors)
+        .filter(|(token, _)| **token != pool_info.address)
+        .map(|(&token, scaling_factor)| {
+            let common = common_tokens
+                .remove(&token)
+                .ok_or_else(|| anyhow!("missing token state for {:?}", token))?;
+            Ok((
+                token,
+                TokenState::from_scaling_factor(common, scaling_factor)?,
+            ))
+        })
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl FactoryIndexing for BalancerV2ComposableStablePoolFactory {
+    type PoolInfo = PoolInfo;
+    type PoolState = PoolState;
+
+    async fn specialize_pool_info(&self, pool: common::PoolInfo) -> Result<Self::PoolInfo> {
+        Ok(PoolInfo { common: pool })
+    }
+
+    fn fetch_pool_state(
+        &self,
+        pool_info: &Self::PoolInfo,
+        common_pool_state: BoxFuture<'static, common::PoolState>,
+        batch: &mut Web3CallBatch,
+        block: BlockId,
+    ) -> BoxFuture<'static, Result<Option<Self::PoolState>>> {
+        let pool_contract = BalancerV2ComposableStablePool::at(
+            &self.raw_instance().web3(),
+            pool_info.common.address,
+        );
+
+        let amplification_parameter = pool_contract
+            .get_amplification_parameter()
+            .block(block)
+            .batch_call(batch);
+        // Scaling factors include token rates, so they need to be fetched
+        // every time.
+        let scaling_factors = pool_contract
+            .get_scaling_factors()
+            .block(block)
+            .batch_call(batch);
+
+        let pool_info = pool_info.common.clone();
+        async move {
+            let common = common_pool_state.await;
+            let amplification_parameter = {
+                let (factor, _, precision) = amplification_parameter.await?;
+                AmplificationParameter::new(factor, precision)?
+            };
+            let tokens = tokens_with_rates(&pool_info, common.tokens, scaling_factors.await?)?;
+
+            Ok(Some(PoolState {
+                tokens,
+                swap_fee: common.swap_fee,
+                amplification_parameter,
+            }))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::balancer_v2::graph_api::Token;
+    use ethcontract::{H160, H256};
+    use ethcontract_mock::Mock;
+    use futures::future;
+    use maplit::btreemap;
+
+    #[tokio::test]
+    async fn fetch_pool_state() {
+        let swap_fee = bfp!("0.0001");
+        let amplification_parameter =
+            AmplificationParameter::new(2000.into(), 1000.into()).unwrap();
+
+        let mock = Mock::new(42);
+        let web3 = mock.web3();
+
+        let pool = mock.deploy(BalancerV2ComposableStablePool::raw_contract().abi.clone());
+        pool.expect_call(
+            BalancerV2ComposableStablePool::signatures().get_amplification_parameter(),
+        )
+        .returns((2000.into(), false, 1000.into()));
+        pool.expect_call(BalancerV2ComposableStablePool::signatures().get_scaling_factors())
+            .returns(vec![
+                bfp!("1.01").as_uint256(),
+                bfp!("1002000000000.0").as_uint256(),
+                bfp!("1.0").as_uint256(),
+            ]);
+
+        // The last token is the pool's own BPT.
+        let tokens = vec![H160([1; 20]), H160([2; 20]), pool.address()];
+        let common_tokens = btreemap! {
+            tokens[0] => common::TokenState {
+                balance: bfp!("1000.0").as_uint256(),
+                scaling_exponent: 0,
+            },
+            tokens[1] => common::TokenState {
+                balance: 15_000_000.into(),
+                scaling_exponent: 12,
+            },
+            tokens[2] => common::TokenState {
+                balance: bfp!("1000000.0").as_uint256(),
+                scaling_exponent: 0,
+            },
+        };
+
+        let factory = dummy_contract!(BalancerV2ComposableStablePoolFactory, H160::default());
+        let pool_info = PoolInfo {
+            common: common::PoolInfo {
+                id: H256([0x90; 32]),
+                address: pool.address(),
+                tokens: tokens.clone(),
+                scaling_exponents: vec![0, 12, 0],
+                block_created: 1337,
+            },
+        };
+        let common_pool_state = common::PoolState {
+            paused: false,
+            swap_fee,
+            tokens: common_tokens.clone(),
+        };
+
+        let pool_state = {
+            let mut batch = Web3CallBatch::new(web3.transport().clone());
+            let block = web3.eth().block_number().await.unwrap();
+
+            let pool_state = factory.fetch_pool_state(
+                &pool_info,
+                future::ready(common_pool_state).boxed(),
+                &mut batch,
+                block.into(),
+            );
+
+            batch.execute_all(100).await;
+            pool_state.await.unwrap()
+        };
+
+        assert_eq!(
+            pool_state,
+            Some(PoolState {
+                tokens: btreemap! {
+                    tokens[0] => TokenState {
+                        common: common_tokens[&tokens[0]].clone(),
+                        rate: bfp!("1.01"),
+                    },
+                    tokens[1] => TokenState {
+                        common: common_tokens[&tokens[1]].clone(),
+                        rate: bfp!("1.002"),
+                    },
+                },
+                swap_fee,
+                amplification_parameter,
+            })
+        );
+    }
+
+    #[test]
+    fn token_rate_from_scaling_factor() {
+        let common = common::TokenState {
+            balance: 0.into(),
+            scaling_exponent: 12,
+        };
+        assert_eq!(
+            TokenState::from_scaling_factor(common.clone(), bfp!("1000000000000.0").as_uint256())
+                .unwrap()
+                .rate,
+            Bfp::one(),
+        );
+        assert!(TokenState::from_scaling_factor(common, 1.into()).is_err());
+    }
+
+    #[test]
+    fn errors_when_converting_wrong_pool_type() {
+        let pool = PoolData {
+            pool_type: PoolType::Stable,
+            id: H256([2; 32]),
+            address: H160([1; 20]),
+            factory: H160([0xfa; 20]),
+            swap_enabled: true,
+            tokens: vec![
+                Token {
+                    address: H160([0x11; 20]),
+                    decimals: 1,
+                    weight: None,
+                },
+                Token {
+                    address: H160([0x22; 20]),
+                    decimals: 2,
+                    weight: None,
+                },
+            ],
+        };
+
+        assert!(PoolInfo::from_graph_data(&pool, 42).is_err());
+    }
+}
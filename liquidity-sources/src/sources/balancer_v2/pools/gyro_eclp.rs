@@ -0,0 +1,358 @@
+//! Module implementing Gyroscope E-CLP pool specific indexing logic.
+//!
+//! Gyroscope elliptic concentrated liquidity pools (E-CLPs) are two-token
+//! pools registered with the Balancer Vault whose liquidity is concentrated
+//! on a rotated and stretched ellipse within a price range. The curve is
+//! described by its parameters as well as derived parameters that are computed
+//! off-chain in higher precision when creating the pool. Tokens can have rate
+//! providers, so the current token rates are part of the pool state.
+
+pub use super::composable_stable::TokenState;
+use super::{common, FactoryIndexing, PoolIndexing};
+use crate::{
+    sources::balancer_v2::{
+        graph_api::{PoolData, PoolType},
+        swap::fixed_point::Bfp,
+    },
+    Web3CallBatch,
+};
+use anyhow::{ensure, Result};
+use contracts::{BalancerV2GyroEclpPool, BalancerV2GyroEclpPoolFactory};
+use ethcontract::{BlockId, H160, I256};
+use futures::{future::BoxFuture, FutureExt as _};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PoolInfo {
+    pub common: common::PoolInfo,
+}
+
+impl PoolIndexing for PoolInfo {
+    fn from_graph_data(pool: &PoolData, block_created: u64) -> Result<Self> {
+        ensure!(
+            pool.tokens.len() == 2,
+            "E-CLP pools have exactly two tokens"
+        );
+        Ok(PoolInfo {
+            common: common::PoolInfo::for_type(PoolType::GyroE, pool, block_created)?,
+        })
+    }
+
+    fn common(&self) -> &common::PoolInfo {
+        &self.common
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolState {
+    pub tokens: BTreeMap<H160, TokenState>,
+    pub swap_fee: Bfp,
+    pub params: Params,
+    pub derived: DerivedParams,
+}
+
+/// A two-dimensional vector of signed fixed point values.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Vector2 {
+    pub x: I256,
+    pub y: I256,
+}
+
+/// E-CLP curve parameters with 18 decimals.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Params {
+    /// The lower bound of the price range.
+    pub alpha: I256,
+    /// The upper bound of the price range.
+    pub beta: I256,
+    /// The cosine of the ellipse rotation angle.
+    pub c: I256,
+    /// The sine of the ellipse rotation angle.
+    pub s: I256,
+    /// The stretching factor of the ellipse.
+    pub lambda: I256,
+}
+
+/// Derived E-CLP parameters with 38 decimals.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DerivedParams {
+    pub tau_alpha: Vector2,
+    pub tau_beta: Vector2,
+    pub u: I256,
+    pub v: I256,
+    pub w: I256,
+    pub z: I256,
+    /// The squared norm of the rotation vector `(c, s)`, used for correcting
+    /// its rounding errors.
+    pub d_sq: I256,
+}
+
+type RawParams = (I256, I256, I256, I256, I256);
+type RawDerivedParams = ((I256, I256), (I256, I256), I256, I256, I256, I256, I256);
+
+impl Params {
+    fn from_raw((alpha, beta, c, s, lambda): RawParams) -> Result<Self> {
+        ensure!(
+            !alpha.is_negative() && alpha < beta,
+            "invalid E-CLP price bounds"
+        );
+        ensure!(
+            !c.is_negative() && !s.is_negative(),
+            "invalid E-CLP rotation vector"
+        );
+        ensure!(lambda >= I256::exp10(18), "invalid E-CLP stretching factor");
+        Ok(Self {
+            alpha,
+            beta,
+            c,
+            s,
+            lambda,
+        })
+    }
+}
+
+impl DerivedParams {
+    fn from_raw(
+        ((tau_alpha_x, tau_alpha_y), (tau_beta_x, tau_beta_y), u, v, w, z, d_sq): RawDerivedParams,
+    ) -> Result<Self> {
+        // The swap math divides by these values, so make sure they are valid
+        // so we never divide by zero.
+        ensure!(
+            d_sq.is_positive() && tau_alpha_y.is_positive() && tau_beta_y.is_positive(),
+            "invalid E-CLP derived parameters"
+        );
+        Ok(Self {
+            tau_alpha: Vector2 {
+                x: tau_alpha_x,
+                y: tau_alpha_y,
+            },
+            tau_beta: Vector2 {
+                x: tau_beta_x,
+                y: tau_beta_y,
+            },
+            u,
+            v,
+            w,
+            z,
+            d_sq,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl FactoryIndexing for BalancerV2GyroEclpPoolFactory {
+    type PoolInfo = PoolInfo;
+    type PoolState = PoolState;
+
+    async fn specialize_pool_info(&self, pool: common::PoolInfo) -> Result<Self::PoolInfo> {
+        ensure!(
+            pool.tokens.len() == 2,
+            "E-CLP pools have exactly two tokens"
+        );
+        Ok(PoolInfo { common: pool })
+    }
+
+    fn fetch_pool_state(
+        &self,
+        pool_info: &Self::PoolInfo,
+        common_pool_state: BoxFuture<'static, common::PoolState>,
+        batch: &mut Web3CallBatch,
+        block: BlockId,
+    ) -> BoxFuture<'static, Result<Option<Self::PoolState>>> {
+        let pool_contract =
+            BalancerV2GyroEclpPool::at(&self.raw_instance().web3(), pool_info.common.address);
+
+        let eclp_params = pool_contract
+            .get_eclp_params()
+            .block(block)
+            .batch_call(batch);
+        let token_rates = pool_contract
+            .get_token_rates()
+            .block(block)
+            .batch_call(batch);
+
+        async move {
+            let common = common_pool_state.await;
+            let (params, derived) = eclp_params.await?;
+            let (rate0, rate1) = token_rates.await?;
+
+            // Pool tokens are sorted by address, so they are in the same order
+            // as the token rates.
+            let tokens = common
+                .tokens
+                .into_iter()
+                .zip([rate0, rate1])
+                .map(|((address, common), rate)| {
+                    (
+                        address,
+                        TokenState {
+                            common,
+                            rate: Bfp::from_wei(rate),
+                        },
+                    )
+                })
+                .collect();
+
+            Ok(Some(PoolState {
+                tokens,
+                swap_fee: common.swap_fee,
+                params: Params::from_raw(params)?,
+                derived: DerivedParams::from_raw(derived)?,
+            }))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::balancer_v2::graph_api::Token;
+    use ethcontract::{H160, H256};
+    use ethcontract_mock::Mock;
+    use futures::future;
+    use maplit::btreemap;
+
+    fn int(value: &str) -> I256 {
+        I256::from_dec_str(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn fetch_pool_state() {
+        let swap_fee = bfp!("0.0002");
+        let raw_params = (
+            int("1200000000000000000"),
+            int("1500000000000000000"),
+            int("600000000000000000"),
+            int("800000000000000000"),
+            int("50000000000000000000"),
+        );
+        let raw_derived = (
+            (int("-99999999999999999999999999999999999999"), int("1")),
+            (int("1"), int("99999999999999999999999999999999999999")),
+            int("1"),
+            int("2"),
+            int("3"),
+            int("4"),
+            I256::exp10(38),
+        );
+
+        let mock = Mock::new(42);
+        let web3 = mock.web3();
+
+        let pool = mock.deploy(BalancerV2GyroEclpPool::raw_contract().abi.clone());
+        pool.expect_call(BalancerV2GyroEclpPool::signatures().get_eclp_params())
+            .returns((raw_params, raw_derived));
+        pool.expect_call(BalancerV2GyroEclpPool::signatures().get_token_rates())
+            .returns((bfp!("1.0").as_uint256(), bfp!("1.15").as_uint256()));
+
+        let tokens = btreemap! {
+            H160([1; 20]) => common::TokenState {
+                balance: bfp!("1000.0").as_uint256(),
+                scaling_exponent: 0,
+            },
+            H160([2; 20]) => common::TokenState {
+                balance: 1_000_000_000.into(),
+                scaling_exponent: 12,
+            },
+        };
+
+        let factory = dummy_contract!(BalancerV2GyroEclpPoolFactory, H160::default());
+        let pool_info = PoolInfo {
+            common: common::PoolInfo {
+                id: H256([0x90; 32]),
+                address: pool.address(),
+                tokens: tokens.keys().copied().collect(),
+                scaling_exponents: vec![0, 12],
+                block_created: 1337,
+            },
+        };
+        let common_pool_state = common::PoolState {
+            paused: false,
+            swap_fee,
+            tokens: tokens.clone(),
+        };
+
+        let pool_state = {
+            let mut batch = Web3CallBatch::new(web3.transport().clone());
+            let block = web3.eth().block_number().await.unwrap();
+
+            let pool_state = factory.fetch_pool_state(
+                &pool_info,
+                future::ready(common_pool_state).boxed(),
+                &mut batch,
+                block.into(),
+            );
+
+            batch.execute_all(100).await;
+            pool_state.await.unwrap()
+        };
+
+        assert_eq!(
+            pool_state,
+            Some(PoolState {
+                tokens: btreemap! {
+                    H160([1; 20]) => TokenState {
+                        common: tokens[&H160([1; 20])].clone(),
+                        rate: Bfp::one(),
+                    },
+                    H160([2; 20]) => TokenState {
+                        common: tokens[&H160([2; 20])].clone(),
+                        rate: bfp!("1.15"),
+                    },
+                },
+                swap_fee,
+                params: Params::from_raw(raw_params).unwrap(),
+                derived: DerivedParams::from_raw(raw_derived).unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        let params = (
+            I256::exp10(18),
+            I256::exp10(17),
+            I256::zero(),
+            I256::exp10(18),
+            I256::exp10(18),
+        );
+        assert!(Params::from_raw(params).is_err());
+
+        let derived = (
+            (I256::zero(), I256::one()),
+            (I256::zero(), I256::one()),
+            I256::zero(),
+            I256::zero(),
+            I256::zero(),
+            I256::zero(),
+            I256::zero(),
+        );
+        assert!(DerivedParams::from_raw(derived).is_err());
+    }
+
+    #[test]
+    fn errors_when_converting_wrong_pool_type() {
+        let pool = PoolData {
+            pool_type: PoolType::Weighted,
+            id: H256([2; 32]),
+            address: H160([1; 20]),
+            factory: H160([0xfa; 20]),
+            swap_enabled: true,
+            tokens: vec![
+                Token {
+                    address: H160([0x11; 20]),
+                    decimals: 1,
+                    weight: None,
+                },
+                Token {
+                    address: H160([0x22; 20]),
+                    decimals: 2,
+                    weight: None,
+                },
+            ],
+        };
+
+        assert!(PoolInfo::from_graph_data(&pool, 42).is_err());
+    }
+}
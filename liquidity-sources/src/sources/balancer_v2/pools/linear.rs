@@ -0,0 +1,256 @@
+//! Module implementing linear pool specific indexing logic.
+//!
+//! Linear pools hold a "main" token and its yield-bearing "wrapped" token (for
+//! example DAI and aDAI). They also register their own BPT as a pool token,
+//! and allow swapping to and from it. Linear pool BPTs are what make up the
+//! tokens of "boosted" composable stable pools like `bb-a-USD`.
+
+pub use super::composable_stable::TokenState;
+use super::{common, composable_stable, FactoryIndexing, PoolIndexing};
+use crate::{
+    sources::balancer_v2::{
+        graph_api::{PoolData, PoolType},
+        swap::fixed_point::Bfp,
+    },
+    Web3CallBatch,
+};
+use anyhow::{anyhow, ensure, Result};
+use contracts::{BalancerV2AaveLinearPool, BalancerV2AaveLinearPoolFactory};
+use ethcontract::{BlockId, H160, U256};
+use futures::{future::BoxFuture, FutureExt as _};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PoolInfo {
+    pub common: common::PoolInfo,
+}
+
+impl PoolIndexing for PoolInfo {
+    fn from_graph_data(pool: &PoolData, block_created: u64) -> Result<Self> {
+        Ok(PoolInfo {
+            common: common::PoolInfo::for_type(PoolType::AaveLinear, pool, block_created)?,
+        })
+    }
+
+    fn common(&self) -> &common::PoolInfo {
+        &self.common
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolState {
+    /// The main and wrapped token states. The BPT is not included since its
+    /// balance in the Vault does not correspond to swappable reserves. Note
+    /// that the BPT address is the pool address.
+    pub tokens: BTreeMap<H160, TokenState>,
+    pub main_token: H160,
+    pub wrapped_token: H160,
+    /// The amount of BPT in circulation, excluding the pre-minted BPT held by
+    /// the Vault.
+    pub virtual_supply: U256,
+    pub swap_fee: Bfp,
+    pub lower_target: Bfp,
+    pub upper_target: Bfp,
+}
+
+#[async_trait::async_trait]
+impl FactoryIndexing for BalancerV2AaveLinearPoolFactory {
+    type PoolInfo = PoolInfo;
+    type PoolState = PoolState;
+
+    async fn specialize_pool_info(&self, pool: common::PoolInfo) -> Result<Self::PoolInfo> {
+        Ok(PoolInfo { common: pool })
+    }
+
+    fn fetch_pool_state(
+        &self,
+        pool_info: &Self::PoolInfo,
+        common_pool_state: BoxFuture<'static, common::PoolState>,
+        batch: &mut Web3CallBatch,
+        block: BlockId,
+    ) -> BoxFuture<'static, Result<Option<Self::PoolState>>> {
+        let pool_contract =
+            BalancerV2AaveLinearPool::at(&self.raw_instance().web3(), pool_info.common.address);
+
+        let main_token = pool_contract
+            .get_main_token()
+            .block(block)
+            .batch_call(batch);
+        let targets = pool_contract.get_targets().block(block).batch_call(batch);
+        let virtual_supply = pool_contract
+            .get_virtual_supply()
+            .block(block)
+            .batch_call(batch);
+        // The wrapped token scaling factor includes the wrapped token rate,
+        // so they need to be fetched every time.
+        let scaling_factors = pool_contract
+            .get_scaling_factors()
+            .block(block)
+            .batch_call(batch);
+
+        let pool_info = pool_info.common.clone();
+        async move {
+            let common = common_pool_state.await;
+            let tokens = composable_stable::tokens_with_rates(
+                &pool_info,
+                common.tokens,
+                scaling_factors.await?,
+            )?;
+            ensure!(tokens.len() == 2, "linear pool with unexpected token count");
+
+            let main_token = main_token.await?;
+            ensure!(
+                tokens.contains_key(&main_token),
+                "linear pool main token is not a pool token",
+            );
+            let wrapped_token = tokens
+                .keys()
+                .copied()
+                .find(|&token| token != main_token)
+                .ok_or_else(|| anyhow!("linear pool is missing its wrapped token"))?;
+
+            let (lower_target, upper_target) = targets.await?;
+
+            Ok(Some(PoolState {
+                tokens,
+                main_token,
+                wrapped_token,
+                virtual_supply: virtual_supply.await?,
+                swap_fee: common.swap_fee,
+                lower_target: Bfp::from_wei(lower_target),
+                upper_target: Bfp::from_wei(upper_target),
+            }))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::balancer_v2::graph_api::Token;
+    use ethcontract::{H160, H256};
+    use ethcontract_mock::Mock;
+    use futures::future;
+    use maplit::btreemap;
+
+    #[tokio::test]
+    async fn fetch_pool_state() {
+        let swap_fee = bfp!("0.0002");
+
+        let mock = Mock::new(42);
+        let web3 = mock.web3();
+
+        let pool = mock.deploy(BalancerV2AaveLinearPool::raw_contract().abi.clone());
+        let (main_token, wrapped_token) = (H160([1; 20]), H160([2; 20]));
+        pool.expect_call(BalancerV2AaveLinearPool::signatures().get_main_token())
+            .returns(main_token);
+        pool.expect_call(BalancerV2AaveLinearPool::signatures().get_targets())
+            .returns((
+                bfp!("2000000.0").as_uint256(),
+                bfp!("3000000.0").as_uint256(),
+            ));
+        pool.expect_call(BalancerV2AaveLinearPool::signatures().get_virtual_supply())
+            .returns(bfp!("5000000.0").as_uint256());
+        pool.expect_call(BalancerV2AaveLinearPool::signatures().get_scaling_factors())
+            .returns(vec![
+                bfp!("1000000000000.0").as_uint256(),
+                bfp!("1075000000000.0").as_uint256(),
+                bfp!("1.0").as_uint256(),
+            ]);
+
+        let tokens = vec![main_token, wrapped_token, pool.address()];
+        let common_tokens = btreemap! {
+            main_token => common::TokenState {
+                balance: 2_500_000_000_000_u64.into(),
+                scaling_exponent: 12,
+            },
+            wrapped_token => common::TokenState {
+                balance: 2_000_000_000_000_u64.into(),
+                scaling_exponent: 12,
+            },
+            pool.address() => common::TokenState {
+                balance: bfp!("5192296858534827.628530496329220095").as_uint256(),
+                scaling_exponent: 0,
+            },
+        };
+
+        let factory = dummy_contract!(BalancerV2AaveLinearPoolFactory, H160::default());
+        let pool_info = PoolInfo {
+            common: common::PoolInfo {
+                id: H256([0x90; 32]),
+                address: pool.address(),
+                tokens: tokens.clone(),
+                scaling_exponents: vec![12, 12, 0],
+                block_created: 1337,
+            },
+        };
+        let common_pool_state = common::PoolState {
+            paused: false,
+            swap_fee,
+            tokens: common_tokens.clone(),
+        };
+
+        let pool_state = {
+            let mut batch = Web3CallBatch::new(web3.transport().clone());
+            let block = web3.eth().block_number().await.unwrap();
+
+            let pool_state = factory.fetch_pool_state(
+                &pool_info,
+                future::ready(common_pool_state).boxed(),
+                &mut batch,
+                block.into(),
+            );
+
+            batch.execute_all(100).await;
+            pool_state.await.unwrap()
+        };
+
+        assert_eq!(
+            pool_state,
+            Some(PoolState {
+                tokens: btreemap! {
+                    main_token => TokenState {
+                        common: common_tokens[&main_token].clone(),
+                        rate: Bfp::one(),
+                    },
+                    wrapped_token => TokenState {
+                        common: common_tokens[&wrapped_token].clone(),
+                        rate: bfp!("1.075"),
+                    },
+                },
+                main_token,
+                wrapped_token,
+                virtual_supply: bfp!("5000000.0").as_uint256(),
+                swap_fee,
+                lower_target: bfp!("2000000.0"),
+                upper_target: bfp!("3000000.0"),
+            })
+        );
+    }
+
+    #[test]
+    fn errors_when_converting_wrong_pool_type() {
+        let pool = PoolData {
+            pool_type: PoolType::ComposableStable,
+            id: H256([2; 32]),
+            address: H160([1; 20]),
+            factory: H160([0xfa; 20]),
+            swap_enabled: true,
+            tokens: vec![
+                Token {
+                    address: H160([0x11; 20]),
+                    decimals: 1,
+                    weight: None,
+                },
+                Token {
+                    address: H160([0x22; 20]),
+                    decimals: 2,
+                    weight: None,
+                },
+            ],
+        };
+
+        assert!(PoolInfo::from_graph_data(&pool, 42).is_err());
+    }
+}
@@ -1,24 +1,36 @@
 use crate::{
     baseline_solver::BaselineSolvable,
     sources::balancer_v2::{
-        pool_fetching::{StablePool, TokenState, WeightedPool, WeightedTokenState},
+        pool_fetching::{
+            ComposableStablePool, GyroEclpPool, LinearPool, RatedTokenState, StablePool,
+            TokenState, WeightedPool, WeightedTokenState,
+        },
+        pools::gyro_eclp::Vector2,
         swap::math::BalU256,
     },
 };
 use error::Error;
-use ethcontract::{H160, U256};
+use ethcontract::{H160, I256, U256};
 use fixed_point::Bfp;
 use std::collections::HashMap;
 
-mod error;
+pub mod error;
 pub mod fixed_point;
+mod gyro_eclp_math;
+mod linear_math;
 mod math;
 mod stable_math;
-mod weighted_math;
+pub mod weighted_math;
 
 const WEIGHTED_SWAP_GAS_COST: usize = 100_000;
 // See https://dune.xyz/queries/219641 for cost of pure stable swaps
 const STABLE_SWAP_GAS_COST: usize = 183_520;
+// Linear pool math is piecewise linear and doesn't require any iterative
+// computations, making swaps significantly cheaper than stable swaps.
+const LINEAR_SWAP_GAS_COST: usize = 90_000;
+// E-CLP swaps compute the invariant and solve a quadratic equation with a
+// couple of square roots, but don't need any iterative solvers.
+const GYRO_ECLP_SWAP_GAS_COST: usize = 150_000;
 
 fn add_swap_fee_amount(amount: U256, swap_fee: Bfp) -> Result<U256, Error> {
     // https://github.com/balancer-labs/balancer-v2-monorepo/blob/6c9e24e22d0c46cca6dd15861d3d33da61a60b98/pkg/core/contracts/pools/BasePool.sol#L454-L457
@@ -34,27 +46,43 @@ fn subtract_swap_fee_amount(amount: U256, swap_fee: Bfp) -> Result<U256, Error>
     Ok(amount_without_fees.as_uint256())
 }
 
-impl TokenState {
+/// Token state that can be converted to and from the internal fixed point
+/// representation used by the Balancer pool math.
+pub trait ScaledTokenState {
     /// Converts the stored balance into its internal representation as a
     /// Balancer fixed point number.
-    fn upscaled_balance(&self) -> Option<Bfp> {
-        self.upscale(self.balance)
-    }
+    fn upscaled_balance(&self) -> Option<Bfp>;
+
+    /// Scales the input token amount to the value that is used by the Balancer
+    /// contract to execute math operations.
+    fn upscale(&self, amount: U256) -> Option<Bfp>;
+
+    /// Returns the token amount corresponding to the internal Balancer
+    /// representation for the same amount, rounded up.
+    fn downscale_up(&self, amount: Bfp) -> Result<U256, Error>;
+
+    /// Returns the token amount corresponding to the internal Balancer
+    /// representation for the same amount, rounded down.
+    fn downscale_down(&self, amount: Bfp) -> Option<U256>;
+}
 
+impl TokenState {
     fn scaling_exponent_as_factor(&self) -> Option<U256> {
         U256::from(10).checked_pow(self.scaling_exponent.into())
     }
+}
+
+impl ScaledTokenState for TokenState {
+    fn upscaled_balance(&self) -> Option<Bfp> {
+        self.upscale(self.balance)
+    }
 
-    /// Scales the input token amount to the value that is used by the Balancer
-    /// contract to execute math operations.
     fn upscale(&self, amount: U256) -> Option<Bfp> {
         amount
             .checked_mul(self.scaling_exponent_as_factor()?)
             .map(Bfp::from_wei)
     }
 
-    /// Returns the token amount corresponding to the internal Balancer
-    /// representation for the same amount.
     /// Based on contract code here:
     /// https://github.com/balancer-labs/balancer-v2-monorepo/blob/c18ff2686c61a8cbad72cdcfc65e9b11476fdbc3/pkg/pool-utils/contracts/BasePool.sol#L560-L562
     fn downscale_up(&self, amount: Bfp) -> Result<U256, Error> {
@@ -73,6 +101,27 @@ impl TokenState {
     }
 }
 
+/// Tokens with rates have a scaling factor of `10 ** (18 - decimals) * rate`.
+/// Scaling in two steps is exactly equivalent to scaling with the combined
+/// factor, as the decimal scaling is exact integer arithmetic.
+impl ScaledTokenState for RatedTokenState {
+    fn upscaled_balance(&self) -> Option<Bfp> {
+        self.upscale(self.common.balance)
+    }
+
+    fn upscale(&self, amount: U256) -> Option<Bfp> {
+        self.common.upscale(amount)?.mul_down(self.rate).ok()
+    }
+
+    fn downscale_up(&self, amount: Bfp) -> Result<U256, Error> {
+        self.common.downscale_up(amount.div_up(self.rate)?)
+    }
+
+    fn downscale_down(&self, amount: Bfp) -> Option<U256> {
+        self.common.downscale_down(amount.div_down(self.rate).ok()?)
+    }
+}
+
 /// Weighted pool data as a reference used for computing input and output amounts.
 pub struct WeightedPoolRef<'a> {
     pub reserves: &'a HashMap<H160, WeightedTokenState>,
@@ -125,8 +174,8 @@ impl BaselineSolvable for WeightedPoolRef<'_> {
 }
 
 /// Stable pool data as a reference used for computing input and output amounts.
-pub struct StablePoolRef<'a> {
-    pub reserves: &'a HashMap<H160, TokenState>,
+pub struct StablePoolRef<'a, T = TokenState> {
+    pub reserves: &'a HashMap<H160, T>,
     pub swap_fee: Bfp,
     pub amplification_parameter: U256,
 }
@@ -138,7 +187,10 @@ struct BalancesWithIndices {
     balances: Vec<Bfp>,
 }
 
-impl StablePoolRef<'_> {
+impl<T> StablePoolRef<'_, T>
+where
+    T: ScaledTokenState,
+{
     // TODO - https://github.com/gnosis/gp-v2-services/pull/1225#discussion_r739033527
     // Based on this discussion, it remains to verify that the non-deterministic ordering
     // of the Balance array returned by this method cannot give rise to any undesired
@@ -172,7 +224,10 @@ impl StablePoolRef<'_> {
     }
 }
 
-impl BaselineSolvable for StablePoolRef<'_> {
+impl<T> BaselineSolvable for StablePoolRef<'_, T>
+where
+    T: ScaledTokenState,
+{
     /// Stable pools use the BaseGeneralPool.sol for these methods, called from within `onSwap`
     /// https://github.com/balancer-labs/balancer-v2-monorepo/blob/589542001aeca5bdc120404874fe0137f6a4c749/pkg/pool-utils/contracts/BaseGeneralPool.sol#L31-L44
 
@@ -235,6 +290,16 @@ impl StablePool {
     }
 }
 
+impl ComposableStablePool {
+    fn as_pool_ref(&self) -> StablePoolRef<RatedTokenState> {
+        StablePoolRef {
+            reserves: &self.reserves,
+            swap_fee: self.common.swap_fee,
+            amplification_parameter: self.amplification_parameter.as_u256(),
+        }
+    }
+}
+
 impl WeightedPool {
     fn as_pool_ref(&self) -> WeightedPoolRef {
         WeightedPoolRef {
@@ -272,10 +337,334 @@ impl BaselineSolvable for StablePool {
     }
 }
 
+impl BaselineSolvable for ComposableStablePool {
+    fn get_amount_out(&self, out_token: H160, input: (U256, H160)) -> Option<U256> {
+        self.as_pool_ref().get_amount_out(out_token, input)
+    }
+
+    fn get_amount_in(&self, in_token: H160, output: (U256, H160)) -> Option<U256> {
+        self.as_pool_ref().get_amount_in(in_token, output)
+    }
+
+    fn gas_cost(&self) -> usize {
+        self.as_pool_ref().gas_cost()
+    }
+}
+
+/// The role of a token in a linear pool.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum LinearToken {
+    Main,
+    Wrapped,
+    Bpt,
+}
+
+/// Upscaled linear pool balances used for computing input and output amounts.
+struct LinearBalances<'a> {
+    main: &'a RatedTokenState,
+    wrapped: &'a RatedTokenState,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: linear_math::Params,
+}
+
+impl LinearPool {
+    fn token_role(&self, token: H160) -> Option<LinearToken> {
+        if token == self.main_token {
+            Some(LinearToken::Main)
+        } else if token == self.wrapped_token {
+            Some(LinearToken::Wrapped)
+        } else if token == self.common.address {
+            Some(LinearToken::Bpt)
+        } else {
+            None
+        }
+    }
+
+    fn upscaled_balances(&self) -> Option<LinearBalances> {
+        let main = self.reserves.get(&self.main_token)?;
+        let wrapped = self.reserves.get(&self.wrapped_token)?;
+        Some(LinearBalances {
+            main,
+            wrapped,
+            main_balance: main.upscaled_balance()?,
+            wrapped_balance: wrapped.upscaled_balance()?,
+            // The BPT always has 18 decimals and a rate of 1, meaning that it
+            // does not need any scaling.
+            bpt_supply: Bfp::from_wei(self.virtual_supply),
+            params: linear_math::Params {
+                fee: self.common.swap_fee,
+                lower_target: self.lower_target,
+                upper_target: self.upper_target,
+            },
+        })
+    }
+}
+
+impl BaselineSolvable for LinearPool {
+    /// Linear pools do not charge swap fees on the amounts, instead the fees
+    /// are part of the nominal balance computations.
+    /// https://github.com/balancer-labs/balancer-v2-monorepo/blob/master/pkg/pool-linear/contracts/LinearPool.sol
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let LinearBalances {
+            main,
+            wrapped,
+            main_balance,
+            wrapped_balance,
+            bpt_supply,
+            params,
+        } = self.upscaled_balances()?;
+        let bpt_in = || Bfp::from_wei(in_amount);
+        let bpt_out = |amount: Bfp| Some(amount.as_uint256());
+
+        match (self.token_role(in_token)?, self.token_role(out_token)?) {
+            (LinearToken::Main, LinearToken::Wrapped) => wrapped.downscale_down(
+                linear_math::calc_wrapped_out_per_main_in(
+                    main.upscale(in_amount)?,
+                    main_balance,
+                    &params,
+                )
+                .ok()?,
+            ),
+            (LinearToken::Wrapped, LinearToken::Main) => main.downscale_down(
+                linear_math::calc_main_out_per_wrapped_in(
+                    wrapped.upscale(in_amount)?,
+                    main_balance,
+                    &params,
+                )
+                .ok()?,
+            ),
+            (LinearToken::Main, LinearToken::Bpt) => bpt_out(
+                linear_math::calc_bpt_out_per_main_in(
+                    main.upscale(in_amount)?,
+                    main_balance,
+                    wrapped_balance,
+                    bpt_supply,
+                    &params,
+                )
+                .ok()?,
+            ),
+            (LinearToken::Bpt, LinearToken::Main) => main.downscale_down(
+                linear_math::calc_main_out_per_bpt_in(
+                    bpt_in(),
+                    main_balance,
+                    wrapped_balance,
+                    bpt_supply,
+                    &params,
+                )
+                .ok()?,
+            ),
+            (LinearToken::Wrapped, LinearToken::Bpt) => bpt_out(
+                linear_math::calc_bpt_out_per_wrapped_in(
+                    wrapped.upscale(in_amount)?,
+                    main_balance,
+                    wrapped_balance,
+                    bpt_supply,
+                    &params,
+                )
+                .ok()?,
+            ),
+            (LinearToken::Bpt, LinearToken::Wrapped) => wrapped.downscale_down(
+                linear_math::calc_wrapped_out_per_bpt_in(
+                    bpt_in(),
+                    main_balance,
+                    wrapped_balance,
+                    bpt_supply,
+                    &params,
+                )
+                .ok()?,
+            ),
+            _ => None,
+        }
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let LinearBalances {
+            main,
+            wrapped,
+            main_balance,
+            wrapped_balance,
+            bpt_supply,
+            params,
+        } = self.upscaled_balances()?;
+        let bpt_out = || Bfp::from_wei(out_amount);
+        let bpt_in = |amount: Bfp| Some(amount.as_uint256());
+
+        match (self.token_role(in_token)?, self.token_role(out_token)?) {
+            (LinearToken::Main, LinearToken::Wrapped) => main
+                .downscale_up(
+                    linear_math::calc_main_in_per_wrapped_out(
+                        wrapped.upscale(out_amount)?,
+                        main_balance,
+                        &params,
+                    )
+                    .ok()?,
+                )
+                .ok(),
+            (LinearToken::Wrapped, LinearToken::Main) => wrapped
+                .downscale_up(
+                    linear_math::calc_wrapped_in_per_main_out(
+                        main.upscale(out_amount)?,
+                        main_balance,
+                        &params,
+                    )
+                    .ok()?,
+                )
+                .ok(),
+            (LinearToken::Main, LinearToken::Bpt) => main
+                .downscale_up(
+                    linear_math::calc_main_in_per_bpt_out(
+                        bpt_out(),
+                        main_balance,
+                        wrapped_balance,
+                        bpt_supply,
+                        &params,
+                    )
+                    .ok()?,
+                )
+                .ok(),
+            (LinearToken::Bpt, LinearToken::Main) => bpt_in(
+                linear_math::calc_bpt_in_per_main_out(
+                    main.upscale(out_amount)?,
+                    main_balance,
+                    wrapped_balance,
+                    bpt_supply,
+                    &params,
+                )
+                .ok()?,
+            ),
+            (LinearToken::Wrapped, LinearToken::Bpt) => wrapped
+                .downscale_up(
+                    linear_math::calc_wrapped_in_per_bpt_out(
+                        bpt_out(),
+                        main_balance,
+                        wrapped_balance,
+                        bpt_supply,
+                        &params,
+                    )
+                    .ok()?,
+                )
+                .ok(),
+            (LinearToken::Bpt, LinearToken::Wrapped) => bpt_in(
+                linear_math::calc_bpt_in_per_wrapped_out(
+                    wrapped.upscale(out_amount)?,
+                    main_balance,
+                    wrapped_balance,
+                    bpt_supply,
+                    &params,
+                )
+                .ok()?,
+            ),
+            _ => None,
+        }
+    }
+
+    fn gas_cost(&self) -> usize {
+        LINEAR_SWAP_GAS_COST
+    }
+}
+
+/// Upscaled E-CLP pool balances used for computing input and output amounts.
+struct GyroEclpBalances<'a> {
+    token_in: &'a RatedTokenState,
+    token_out: &'a RatedTokenState,
+    token_in_is_token0: bool,
+    /// The upscaled `[balance0, balance1]`.
+    balances: [U256; 2],
+    invariant: Vector2,
+}
+
+impl GyroEclpPool {
+    /// Based on the `onSwap` implementation of the E-CLP pool contract:
+    /// https://github.com/gyrostable/concentrated-lps/blob/main/contracts/eclp/GyroECLPPool.sol
+    fn upscaled_balances(&self, in_token: H160, out_token: H160) -> Option<GyroEclpBalances> {
+        let token_in = self.reserves.get(&in_token)?;
+        let token_out = self.reserves.get(&out_token)?;
+        if in_token == out_token {
+            return None;
+        }
+
+        // Pool tokens are sorted by address.
+        let token_in_is_token0 = in_token < out_token;
+        let (balance_in, balance_out) = (
+            token_in.upscaled_balance()?.as_uint256(),
+            token_out.upscaled_balance()?.as_uint256(),
+        );
+        let balances = if token_in_is_token0 {
+            [balance_in, balance_out]
+        } else {
+            [balance_out, balance_in]
+        };
+
+        // The invariant over-estimates in its `x` component and under-estimates
+        // in its `y` component.
+        let (invariant, error) =
+            gyro_eclp_math::calculate_invariant_with_error(balances, &self.params, &self.derived)
+                .ok()?;
+        let invariant = Vector2 {
+            x: invariant + error * I256::from(2_i128),
+            y: invariant,
+        };
+
+        Some(GyroEclpBalances {
+            token_in,
+            token_out,
+            token_in_is_token0,
+            balances,
+            invariant,
+        })
+    }
+}
+
+impl BaselineSolvable for GyroEclpPool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let balances = self.upscaled_balances(in_token, out_token)?;
+        let in_amount_minus_fees =
+            subtract_swap_fee_amount(in_amount, self.common.swap_fee).ok()?;
+        let out_amount = gyro_eclp_math::calc_out_given_in(
+            balances.balances,
+            balances
+                .token_in
+                .upscale(in_amount_minus_fees)?
+                .as_uint256(),
+            balances.token_in_is_token0,
+            &self.params,
+            &self.derived,
+            &balances.invariant,
+        )
+        .ok()?;
+        balances.token_out.downscale_down(Bfp::from_wei(out_amount))
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let balances = self.upscaled_balances(in_token, out_token)?;
+        let in_amount = gyro_eclp_math::calc_in_given_out(
+            balances.balances,
+            balances.token_out.upscale(out_amount)?.as_uint256(),
+            balances.token_in_is_token0,
+            &self.params,
+            &self.derived,
+            &balances.invariant,
+        )
+        .ok()?;
+        let amount_in_before_fee = balances
+            .token_in
+            .downscale_up(Bfp::from_wei(in_amount))
+            .ok()?;
+        add_swap_fee_amount(amount_in_before_fee, self.common.swap_fee).ok()
+    }
+
+    fn gas_cost(&self) -> usize {
+        GYRO_ECLP_SWAP_GAS_COST
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::sources::balancer_v2::pool_fetching::{AmplificationParameter, CommonPoolState};
+    use maplit::hashmap;
     use std::collections::HashMap;
 
     fn create_weighted_pool_with(
@@ -504,4 +893,186 @@ mod tests {
         let res_out = pool.get_amount_in(usdc, (amount_out, dai));
         assert_eq!(res_out.unwrap(), amount_in.into());
     }
+
+    #[test]
+    fn composable_stable_with_unit_rates_matches_stable() {
+        let tokens: Vec<_> = (1..=3).map(H160::from_low_u64_be).collect();
+        let balances = vec![
+            bfp!("1000.0").as_uint256(),
+            1_200_000_000.into(),
+            bfp!("900.0").as_uint256(),
+        ];
+        let scaling_exps = vec![0, 12, 0];
+        let amplification_parameter = AmplificationParameter::new(200.into(), 1.into()).unwrap();
+        let swap_fee = bfp!("0.0004").as_uint256();
+
+        let stable = create_stable_pool_with(
+            tokens.clone(),
+            balances,
+            amplification_parameter.clone(),
+            scaling_exps,
+            swap_fee,
+        );
+        let composable_stable = ComposableStablePool {
+            common: stable.common.clone(),
+            reserves: stable
+                .reserves
+                .iter()
+                .map(|(&token, common)| {
+                    (
+                        token,
+                        RatedTokenState {
+                            common: common.clone(),
+                            rate: Bfp::one(),
+                        },
+                    )
+                })
+                .collect(),
+            amplification_parameter,
+        };
+
+        let amount = U256::from(10_000_000);
+        assert_eq!(
+            composable_stable.get_amount_out(tokens[0], (amount, tokens[1])),
+            stable.get_amount_out(tokens[0], (amount, tokens[1])),
+        );
+        assert_eq!(
+            composable_stable.get_amount_in(tokens[1], (amount, tokens[2])),
+            stable.get_amount_in(tokens[1], (amount, tokens[2])),
+        );
+    }
+
+    #[test]
+    fn rated_token_scaling() {
+        let token_state = RatedTokenState {
+            common: TokenState {
+                balance: 1_000_000.into(),
+                scaling_exponent: 12,
+            },
+            rate: bfp!("1.5"),
+        };
+
+        assert_eq!(token_state.upscaled_balance().unwrap(), bfp!("1.5"));
+        assert_eq!(
+            token_state.downscale_down(bfp!("1.0")).unwrap(),
+            666_666.into()
+        );
+        assert_eq!(
+            token_state.downscale_up(bfp!("1.0")).unwrap(),
+            666_667.into()
+        );
+    }
+
+    fn create_linear_pool() -> LinearPool {
+        let (main_token, wrapped_token) = (H160([1; 20]), H160([2; 20]));
+        LinearPool {
+            common: CommonPoolState {
+                id: Default::default(),
+                address: H160([3; 20]),
+                swap_fee: bfp!("0.01"),
+                paused: false,
+            },
+            reserves: hashmap! {
+                main_token => RatedTokenState {
+                    common: TokenState {
+                        balance: bfp!("1500.0").as_uint256(),
+                        scaling_exponent: 0,
+                    },
+                    rate: Bfp::one(),
+                },
+                wrapped_token => RatedTokenState {
+                    common: TokenState {
+                        balance: bfp!("1000.0").as_uint256(),
+                        scaling_exponent: 0,
+                    },
+                    rate: bfp!("1.5"),
+                },
+            },
+            main_token,
+            wrapped_token,
+            virtual_supply: bfp!("1000.0").as_uint256(),
+            lower_target: bfp!("1000.0"),
+            upper_target: bfp!("2000.0"),
+        }
+    }
+
+    #[test]
+    fn linear_main_wrapped_swaps() {
+        let pool = create_linear_pool();
+        let (main, wrapped) = (pool.main_token, pool.wrapped_token);
+
+        // Within targets, 100 main tokens are worth 100 nominal wrapped tokens,
+        // which are `100 / 1.5` wrapped tokens.
+        assert_eq!(
+            pool.get_amount_out(wrapped, (bfp!("100.0").as_uint256(), main)),
+            Some(66_666_666_666_666_666_666_u128.into())
+        );
+        assert_eq!(
+            pool.get_amount_in(wrapped, (bfp!("100.0").as_uint256(), main)),
+            Some(66_666_666_666_666_666_667_u128.into())
+        );
+    }
+
+    #[test]
+    fn linear_bpt_swaps() {
+        let pool = create_linear_pool();
+        let (main, bpt) = (pool.main_token, pool.common.address);
+
+        // The invariant is 3000 and the virtual supply 1000.
+        assert_eq!(
+            pool.get_amount_out(bpt, (bfp!("300.0").as_uint256(), main)),
+            Some(bfp!("100.0").as_uint256())
+        );
+        assert_eq!(
+            pool.get_amount_out(main, (bfp!("100.0").as_uint256(), bpt)),
+            Some(bfp!("300.0").as_uint256())
+        );
+        assert_eq!(pool.get_amount_out(main, (1.into(), H160([4; 20]))), None);
+    }
+
+    #[test]
+    fn gyro_eclp_swaps() {
+        let (params, derived) = gyro_eclp_math::example_params();
+        let (token0, token1) = (H160([1; 20]), H160([2; 20]));
+        let pool = GyroEclpPool {
+            common: CommonPoolState {
+                id: Default::default(),
+                address: H160([3; 20]),
+                swap_fee: bfp!("0.0002"),
+                paused: false,
+            },
+            reserves: hashmap! {
+                token0 => RatedTokenState {
+                    common: TokenState {
+                        balance: bfp!("1000.0").as_uint256(),
+                        scaling_exponent: 0,
+                    },
+                    rate: Bfp::one(),
+                },
+                token1 => RatedTokenState {
+                    common: TokenState {
+                        balance: bfp!("1500.0").as_uint256(),
+                        scaling_exponent: 0,
+                    },
+                    rate: Bfp::one(),
+                },
+            },
+            params,
+            derived,
+        };
+
+        let amount_out = pool
+            .get_amount_out(token1, (bfp!("10.0").as_uint256(), token0))
+            .unwrap();
+        assert_eq!(amount_out, 13_358_797_886_942_895_954_u128.into());
+        assert_eq!(
+            pool.get_amount_in(token0, (amount_out, token1)),
+            Some(9_999_999_999_999_999_998_u128.into())
+        );
+        assert_eq!(
+            pool.get_amount_out(token0, (bfp!("10.0").as_uint256(), token1)),
+            Some(7_480_328_270_577_966_139_u128.into())
+        );
+        assert_eq!(pool.get_amount_out(token0, (1.into(), token0)), None);
+    }
 }
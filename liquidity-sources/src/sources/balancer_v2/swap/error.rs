@@ -51,6 +51,9 @@ errors_from_codes!(
     (MaxOutRatio, 305),
     (InvalidToken, 309),
     (StableInvariantDidntConverge, 321),
+    (AssetBoundsExceeded, 357),
+    (MaxAssetsExceeded, 363),
+    (MaxInvariantExceeded, 364),
 );
 
 #[cfg(test)]
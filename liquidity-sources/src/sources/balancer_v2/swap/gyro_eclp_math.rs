@@ -0,0 +1,762 @@
+//! Module emulating the functions in the Gyroscope GyroECLPMath.sol smart
+//! contract, including the signed fixed point helpers from
+//! SignedFixedPoint.sol and the square root from GyroPoolMath.sol. The
+//! original contract code can be found at:
+//! https://github.com/gyrostable/concentrated-lps/blob/main/contracts/eclp/GyroECLPMath.sol
+//!
+//! All balances and amounts are expected to already be upscaled. Values in
+//! "extra precision" (named `*_xp`) use 38 decimals instead of 18.
+
+use super::error::Error;
+use crate::sources::balancer_v2::pools::gyro_eclp::{DerivedParams, Params, Vector2};
+use ethcontract::{I256, U256};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref ONE: I256 = I256::exp10(18);
+    static ref ONE_XP: I256 = I256::exp10(38);
+    static ref ONE_U256: U256 = U256::exp10(18);
+    static ref XP_TO_NP: I256 = I256::exp10(19);
+    static ref MAX_BALANCES: I256 = I256::exp10(34);
+    static ref MAX_INVARIANT: I256 = int(3) * I256::exp10(37);
+    static ref MAX_INV_INVARIANT_DENOMINATOR_XP: I256 = I256::exp10(43);
+}
+
+/// Tolerance used by the pool contract when computing square roots.
+const SQRT_TOLERANCE: u64 = 5;
+
+fn int(value: i128) -> I256 {
+    I256::from(value)
+}
+
+fn to_int(value: U256) -> Result<I256, Error> {
+    I256::try_from(value).map_err(|_| Error::AddOverflow)
+}
+
+fn to_uint(value: I256) -> Result<U256, Error> {
+    if value.is_negative() {
+        return Err(Error::SubOverflow);
+    }
+    Ok(value.into_raw())
+}
+
+// Signed fixed point operations from `SignedFixedPoint.sol`. Division in
+// Solidity rounds towards zero, as does `I256` division.
+
+fn mul_down_mag(a: I256, b: I256) -> I256 {
+    (a * b) / *ONE
+}
+
+fn mul_up_mag(a: I256, b: I256) -> I256 {
+    let product = a * b;
+    if product.is_positive() {
+        (product - int(1)) / *ONE + int(1)
+    } else if product.is_negative() {
+        (product + int(1)) / *ONE - int(1)
+    } else {
+        I256::zero()
+    }
+}
+
+fn div_down_mag(a: I256, b: I256) -> I256 {
+    if a.is_zero() {
+        return I256::zero();
+    }
+    (a * *ONE) / b
+}
+
+fn div_up_mag(mut a: I256, mut b: I256) -> I256 {
+    if a.is_zero() {
+        return I256::zero();
+    }
+    if b.is_negative() {
+        a = -a;
+        b = -b;
+    }
+    let a_inflated = a * *ONE;
+    if a_inflated.is_positive() {
+        (a_inflated - int(1)) / b + int(1)
+    } else {
+        (a_inflated + int(1)) / b - int(1)
+    }
+}
+
+fn mul_xp(a: I256, b: I256) -> I256 {
+    (a * b) / *ONE_XP
+}
+
+fn div_xp(a: I256, b: I256) -> I256 {
+    if a.is_zero() {
+        return I256::zero();
+    }
+    (a * *ONE_XP) / b
+}
+
+/// Multiplies a normal precision value by an extra precision value, rounding
+/// the normal precision result down.
+fn mul_down_xp_to_np(a: I256, b: I256) -> I256 {
+    let b1 = b / *XP_TO_NP;
+    let product1 = a * b1;
+    let b2 = b % *XP_TO_NP;
+    let product2 = a * b2;
+    if !product1.is_negative() && !product2.is_negative() {
+        (product1 + product2 / *XP_TO_NP) / *XP_TO_NP
+    } else {
+        (product1 + product2 / *XP_TO_NP + int(1)) / *XP_TO_NP - int(1)
+    }
+}
+
+/// Multiplies a normal precision value by an extra precision value, rounding
+/// the normal precision result up.
+fn mul_up_xp_to_np(a: I256, b: I256) -> I256 {
+    let b1 = b / *XP_TO_NP;
+    let product1 = a * b1;
+    let b2 = b % *XP_TO_NP;
+    let product2 = a * b2;
+    if !product1.is_positive() && !product2.is_positive() {
+        (product1 + product2 / *XP_TO_NP) / *XP_TO_NP
+    } else {
+        (product1 + product2 / *XP_TO_NP - int(1)) / *XP_TO_NP + int(1)
+    }
+}
+
+/// Square root in 18 decimal fixed point, using a fixed number of Newton
+/// iterations from `GyroPoolMath._sqrt`.
+fn sqrt(input: U256, tolerance: u64) -> Result<U256, Error> {
+    if input.is_zero() {
+        return Ok(input);
+    }
+    let mut guess = make_initial_guess(input);
+    let inflated = input.checked_mul(*ONE_U256).ok_or(Error::MulOverflow)?;
+    for _ in 0..7 {
+        guess = (guess + inflated / guess) / 2;
+    }
+
+    // The pool contract reverts if the result is not within tolerance.
+    let guess_squared = guess.checked_mul(guess).ok_or(Error::MulOverflow)? / *ONE_U256;
+    let margin = guess
+        .checked_mul(tolerance.into())
+        .ok_or(Error::MulOverflow)?
+        .checked_add(*ONE_U256 - 1)
+        .ok_or(Error::AddOverflow)?
+        / *ONE_U256;
+    if guess_squared > input.saturating_add(margin) || guess_squared < input.saturating_sub(margin)
+    {
+        return Err(Error::XOutOfBounds);
+    }
+    Ok(guess)
+}
+
+fn make_initial_guess(input: U256) -> U256 {
+    if input >= *ONE_U256 {
+        return (U256::one() << int_log2_halved(input / *ONE_U256)) * *ONE_U256;
+    }
+    // Approximate square roots of powers of ten between 1e-17 and 1e-1.
+    let guesses: [(u64, u64); 17] = [
+        (10, 3_162_277_660),
+        (100, 10_000_000_000),
+        (1_000, 31_622_776_601),
+        (10_000, 100_000_000_000),
+        (100_000, 316_227_766_016),
+        (1_000_000, 1_000_000_000_000),
+        (10_000_000, 3_162_277_660_168),
+        (100_000_000, 10_000_000_000_000),
+        (1_000_000_000, 31_622_776_601_683),
+        (10_000_000_000, 100_000_000_000_000),
+        (100_000_000_000, 316_227_766_016_837),
+        (1_000_000_000_000, 1_000_000_000_000_000),
+        (10_000_000_000_000, 3_162_277_660_168_379),
+        (100_000_000_000_000, 10_000_000_000_000_000),
+        (1_000_000_000_000_000, 31_622_776_601_683_793),
+        (10_000_000_000_000_000, 100_000_000_000_000_000),
+        (100_000_000_000_000_000, 316_227_766_016_837_933),
+    ];
+    guesses
+        .iter()
+        .find(|(bound, _)| input <= U256::from(*bound))
+        .map(|(_, guess)| U256::from(*guess))
+        .unwrap_or(input)
+}
+
+fn int_log2_halved(mut x: U256) -> usize {
+    let mut n = 0;
+    for shift in [128, 64, 32, 16, 8, 4, 2] {
+        if x >= U256::one() << shift {
+            x >>= shift;
+            n += shift / 2;
+        }
+    }
+    n
+}
+
+fn sqrt_int(value: I256) -> Result<I256, Error> {
+    to_int(sqrt(to_uint(value)?, SQRT_TOLERANCE)?)
+}
+
+fn virtual_offset0(p: &Params, d: &DerivedParams, r: &Vector2) -> I256 {
+    let term_xp = div_xp(d.tau_beta.x, d.d_sq);
+    let a = if d.tau_beta.x.is_positive() {
+        mul_up_xp_to_np(mul_up_mag(mul_up_mag(r.x, p.lambda), p.c), term_xp)
+    } else {
+        mul_up_xp_to_np(mul_down_mag(mul_down_mag(r.y, p.lambda), p.c), term_xp)
+    };
+    a + mul_up_xp_to_np(mul_up_mag(r.x, p.s), div_xp(d.tau_beta.y, d.d_sq))
+}
+
+fn virtual_offset1(p: &Params, d: &DerivedParams, r: &Vector2) -> I256 {
+    let term_xp = div_xp(d.tau_alpha.x, d.d_sq);
+    let b = if d.tau_alpha.x.is_negative() {
+        mul_up_xp_to_np(mul_up_mag(mul_up_mag(r.x, p.lambda), p.s), -term_xp)
+    } else {
+        mul_up_xp_to_np(mul_down_mag(mul_down_mag(-r.y, p.lambda), p.s), term_xp)
+    };
+    b + mul_up_xp_to_np(mul_up_mag(r.x, p.c), div_xp(d.tau_alpha.y, d.d_sq))
+}
+
+fn max_balances0(p: &Params, d: &DerivedParams, r: &Vector2) -> I256 {
+    let term_xp1 = div_xp(d.tau_beta.x - d.tau_alpha.x, d.d_sq);
+    let term_xp2 = div_xp(d.tau_beta.y - d.tau_alpha.y, d.d_sq);
+    let xp = mul_down_xp_to_np(mul_down_mag(mul_down_mag(r.y, p.lambda), p.c), term_xp1);
+    let term = if term_xp2.is_positive() {
+        mul_down_mag(r.y, p.s)
+    } else {
+        mul_up_mag(r.x, p.s)
+    };
+    xp + mul_down_xp_to_np(term, term_xp2)
+}
+
+fn max_balances1(p: &Params, d: &DerivedParams, r: &Vector2) -> I256 {
+    let term_xp1 = div_xp(d.tau_beta.x - d.tau_alpha.x, d.d_sq);
+    let term_xp2 = div_xp(d.tau_alpha.y - d.tau_beta.y, d.d_sq);
+    let yp = mul_down_xp_to_np(mul_down_mag(mul_down_mag(r.y, p.lambda), p.s), term_xp1);
+    let term = if term_xp2.is_positive() {
+        mul_down_mag(r.y, p.c)
+    } else {
+        mul_up_mag(r.x, p.c)
+    };
+    yp + mul_down_xp_to_np(term, term_xp2)
+}
+
+/// Computes the pool invariant and an upper bound of its error for the
+/// specified `[balance0, balance1]`.
+pub fn calculate_invariant_with_error(
+    balances: [U256; 2],
+    p: &Params,
+    d: &DerivedParams,
+) -> Result<(I256, I256), Error> {
+    let (x, y) = (to_int(balances[0])?, to_int(balances[1])?);
+    if x + y > *MAX_BALANCES {
+        return Err(Error::MaxAssetsExceeded);
+    }
+    let at_a_chi = calc_at_a_chi(x, y, p, d);
+    let (sqrt, mut err) = calc_invariant_sqrt(x, y, p, d)?;
+    // Calculate the error in the square root term, separating cases based on
+    // `sqrt >= 1/2`.
+    err = if sqrt.is_positive() {
+        div_up_mag(err + int(1), int(2) * sqrt)
+    } else if err.is_positive() {
+        sqrt_int(err)?
+    } else {
+        I256::exp10(9)
+    };
+    // Calculate the error in the numerator, scaled by 20 to be sure all
+    // possible terms are accounted for.
+    err = (mul_up_mag(p.lambda, x + y) / *ONE_XP + err + int(1)) * int(20);
+
+    let mul_denominator = div_xp(*ONE_XP, calc_a_chi_a_chi_in_xp(p, d) - *ONE_XP);
+    if mul_denominator > *MAX_INV_INVARIANT_DENOMINATOR_XP {
+        return Err(Error::MaxInvariantExceeded);
+    }
+    let invariant = mul_down_xp_to_np(at_a_chi + sqrt - err, mul_denominator);
+    // The error scales if the denominator is small, and needs to account for
+    // the relative error in the denominator itself.
+    err = mul_up_xp_to_np(err, mul_denominator);
+    err = err
+        + (mul_up_xp_to_np(invariant, mul_denominator)
+            * ((p.lambda * p.lambda) / I256::exp10(36))
+            * int(40))
+            / *ONE_XP
+        + int(1);
+
+    if invariant + err > *MAX_INVARIANT {
+        return Err(Error::MaxInvariantExceeded);
+    }
+    Ok((invariant, err))
+}
+
+fn calc_at_a_chi(x: I256, y: I256, p: &Params, d: &DerivedParams) -> I256 {
+    let d_sq2 = mul_xp(d.d_sq, d.d_sq);
+
+    // (cx - sy) * (w/lambda + z) / lambda
+    let term_xp = div_xp(
+        div_down_mag(div_down_mag(d.w, p.lambda) + d.z, p.lambda),
+        d_sq2,
+    );
+    let mut val = mul_down_xp_to_np(mul_down_mag(x, p.c) - mul_down_mag(y, p.s), term_xp);
+
+    // (x lambda s + y lambda c) * u
+    let term_np =
+        mul_down_mag(mul_down_mag(x, p.lambda), p.s) + mul_down_mag(mul_down_mag(y, p.lambda), p.c);
+    val = val + mul_down_xp_to_np(term_np, div_xp(d.u, d_sq2));
+
+    // (sx + cy) * v
+    let term_np = mul_down_mag(x, p.s) + mul_down_mag(y, p.c);
+    val + mul_down_xp_to_np(term_np, div_xp(d.v, d_sq2))
+}
+
+fn calc_a_chi_a_chi_in_xp(p: &Params, d: &DerivedParams) -> I256 {
+    let d_sq3 = mul_xp(mul_xp(d.d_sq, d.d_sq), d.d_sq);
+
+    // (A chi)_y^2 = lambda^2 u^2 + lambda 2 u v + v^2
+    let mut val = mul_up_mag(p.lambda, div_xp(mul_xp(int(2) * d.u, d.v), d_sq3));
+    val = val
+        + mul_up_mag(
+            mul_up_mag(div_xp(mul_xp(d.u + int(1), d.u + int(1)), d_sq3), p.lambda),
+            p.lambda,
+        );
+    val = val + div_xp(mul_xp(d.v, d.v), d_sq3);
+
+    // (A chi)_x^2 = (w/lambda + z)^2
+    let term_xp = div_up_mag(d.w, p.lambda) + d.z;
+    val + div_xp(mul_xp(term_xp, term_xp), d_sq3)
+}
+
+fn calc_invariant_sqrt(
+    x: I256,
+    y: I256,
+    p: &Params,
+    d: &DerivedParams,
+) -> Result<(I256, I256), Error> {
+    let val = calc_min_atx_a_chiy_sq_plus_atx_sq(x, y, p, d)
+        + calc_2_atx_aty_a_chix_a_chiy(x, y, p, d)
+        + calc_min_aty_a_chix_sq_plus_aty_sq(x, y, p, d);
+    // The error inside the square root is `O((x^2 + y^2) * eps_xp)`.
+    let err = (mul_up_mag(x, x) + mul_up_mag(y, y)) / *ONE_XP;
+    // Mathematically, the terms in the square root are positive, so treat
+    // negative values as 0 as they are caused by rounding errors.
+    let val = if val.is_positive() {
+        sqrt_int(val)?
+    } else {
+        I256::zero()
+    };
+    Ok((val, err))
+}
+
+fn d_sq4(d: &DerivedParams) -> I256 {
+    mul_xp(mul_xp(mul_xp(d.d_sq, d.d_sq), d.d_sq), d.d_sq)
+}
+
+fn calc_min_atx_a_chiy_sq_plus_atx_sq(x: I256, y: I256, p: &Params, d: &DerivedParams) -> I256 {
+    // (At)_x^2 (A chi)_y^2 = (x^2 c^2 - xy2sc + y^2 s^2) (u^2 + 2uv/lambda + v^2/lambda^2)
+    let mut term_np = mul_up_mag(mul_up_mag(mul_up_mag(x, x), p.c), p.c)
+        + mul_up_mag(mul_up_mag(mul_up_mag(y, y), p.s), p.s);
+    term_np = term_np - mul_down_mag(mul_down_mag(mul_down_mag(x, y), p.c * int(2)), p.s);
+
+    let mut term_xp = mul_xp(d.u, d.u)
+        + div_down_mag(mul_xp(int(2) * d.u, d.v), p.lambda)
+        + div_down_mag(div_down_mag(mul_xp(d.v, d.v), p.lambda), p.lambda);
+    term_xp = div_xp(term_xp, d_sq4(d));
+    let val = mul_down_xp_to_np(-term_np, term_xp);
+
+    // (At)_x^2 = (x^2 c^2 - xy2sc + y^2 s^2) / lambda^2, rounded down.
+    val + mul_down_xp_to_np(
+        div_down_mag(div_down_mag(term_np - int(9), p.lambda), p.lambda),
+        div_xp(*ONE_XP, d.d_sq),
+    )
+}
+
+fn calc_2_atx_aty_a_chix_a_chiy(x: I256, y: I256, p: &Params, d: &DerivedParams) -> I256 {
+    // ((x^2 - y^2)sc + yx(c^2-s^2)) * 2 * (zu + (wu + zv)/lambda + wv/lambda^2)
+    let mut term_np = mul_down_mag(
+        mul_down_mag(mul_down_mag(x, x) - mul_up_mag(y, y), int(2) * p.c),
+        p.s,
+    );
+    let xy = mul_down_mag(y, int(2) * x);
+    term_np = term_np + mul_down_mag(mul_down_mag(xy, p.c), p.c)
+        - mul_down_mag(mul_down_mag(xy, p.s), p.s);
+
+    let mut term_xp =
+        mul_xp(d.z, d.u) + div_down_mag(div_down_mag(mul_xp(d.w, d.v), p.lambda), p.lambda);
+    term_xp = term_xp + div_down_mag(mul_xp(d.w, d.u) + mul_xp(d.z, d.v), p.lambda);
+    term_xp = div_xp(term_xp, d_sq4(d));
+
+    mul_down_xp_to_np(term_np, term_xp)
+}
+
+fn calc_min_aty_a_chix_sq_plus_aty_sq(x: I256, y: I256, p: &Params, d: &DerivedParams) -> I256 {
+    // (At)_y^2 (A chi)_x^2 = (x^2 s^2 + xy2sc + y^2 c^2) * (z^2 + 2zw/lambda + w^2/lambda^2)
+    let mut term_np = mul_up_mag(mul_up_mag(mul_up_mag(x, x), p.s), p.s)
+        + mul_up_mag(mul_up_mag(mul_up_mag(y, y), p.c), p.c);
+    term_np = term_np + mul_up_mag(mul_up_mag(mul_up_mag(x, y), p.s * int(2)), p.c);
+
+    let mut term_xp =
+        mul_xp(d.z, d.z) + div_down_mag(div_down_mag(mul_xp(d.w, d.w), p.lambda), p.lambda);
+    term_xp = term_xp + div_down_mag(mul_xp(int(2) * d.z, d.w), p.lambda);
+    term_xp = div_xp(term_xp, d_sq4(d));
+    let val = mul_down_xp_to_np(-term_np, term_xp);
+
+    // (At)_y^2 = (x^2 s^2 + xy2sc + y^2 c^2), rounded down.
+    val + mul_down_xp_to_np(term_np - int(9), div_xp(*ONE_XP, d.d_sq))
+}
+
+fn check_asset_bounds(
+    p: &Params,
+    d: &DerivedParams,
+    invariant: &Vector2,
+    new_balance: I256,
+    asset_index: usize,
+) -> Result<(), Error> {
+    let max_balance = if asset_index == 0 {
+        max_balances0(p, d, invariant)
+    } else {
+        max_balances1(p, d, invariant)
+    };
+    if new_balance > *MAX_BALANCES || new_balance > max_balance {
+        return Err(Error::AssetBoundsExceeded);
+    }
+    Ok(())
+}
+
+type CalcGiven = fn(I256, &Params, &DerivedParams, &Vector2) -> Result<I256, Error>;
+
+/// Computes the upscaled output amount for an upscaled input amount, where
+/// `invariant` over-estimates the invariant in its `x` component and
+/// under-estimates it in its `y` component.
+pub fn calc_out_given_in(
+    balances: [U256; 2],
+    amount_in: U256,
+    token_in_is_token0: bool,
+    p: &Params,
+    d: &DerivedParams,
+    invariant: &Vector2,
+) -> Result<U256, Error> {
+    let (ix_in, ix_out, calc_given): (_, _, CalcGiven) = if token_in_is_token0 {
+        (0, 1, calc_y_given_x)
+    } else {
+        (1, 0, calc_x_given_y)
+    };
+
+    let bal_in_new = to_int(
+        balances[ix_in]
+            .checked_add(amount_in)
+            .ok_or(Error::AddOverflow)?,
+    )?;
+    check_asset_bounds(p, d, invariant, bal_in_new, ix_in)?;
+    let bal_out_new = calc_given(bal_in_new, p, d, invariant)?;
+    balances[ix_out]
+        .checked_sub(to_uint(bal_out_new)?)
+        .ok_or(Error::SubOverflow)
+}
+
+/// Computes the upscaled input amount for an upscaled output amount.
+pub fn calc_in_given_out(
+    balances: [U256; 2],
+    amount_out: U256,
+    token_in_is_token0: bool,
+    p: &Params,
+    d: &DerivedParams,
+    invariant: &Vector2,
+) -> Result<U256, Error> {
+    let (ix_in, ix_out, calc_given): (_, _, CalcGiven) = if token_in_is_token0 {
+        (0, 1, calc_x_given_y)
+    } else {
+        (1, 0, calc_y_given_x)
+    };
+
+    if amount_out > balances[ix_out] {
+        return Err(Error::AssetBoundsExceeded);
+    }
+    let bal_out_new = to_int(balances[ix_out] - amount_out)?;
+    let bal_in_new = calc_given(bal_out_new, p, d, invariant)?;
+    check_asset_bounds(p, d, invariant, bal_in_new, ix_in)?;
+    to_uint(bal_in_new)?
+        .checked_sub(balances[ix_in])
+        .ok_or(Error::SubOverflow)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn solve_quadratic_swap(
+    lambda: I256,
+    x: I256,
+    s: I256,
+    c: I256,
+    r: &Vector2,
+    ab: &Vector2,
+    tau_beta: &Vector2,
+    d_sq: I256,
+) -> Result<I256, Error> {
+    // The x component rounds up and the y component rounds down.
+    let lam_bar = Vector2 {
+        x: *ONE_XP - div_down_mag(div_down_mag(*ONE_XP, lambda), lambda),
+        y: *ONE_XP - div_up_mag(div_up_mag(*ONE_XP, lambda), lambda),
+    };
+
+    // Shift by the virtual offsets.
+    let xp = x - ab.x;
+    let qb = if xp.is_positive() {
+        mul_up_xp_to_np(
+            mul_down_mag(mul_down_mag(-xp, s), c),
+            div_xp(lam_bar.y, d_sq),
+        )
+    } else {
+        mul_up_xp_to_np(
+            mul_up_mag(mul_up_mag(-xp, s), c),
+            div_xp(lam_bar.x, d_sq) + int(1),
+        )
+    };
+
+    let s_term = Vector2 {
+        x: *ONE_XP - div_xp(mul_down_mag(mul_down_mag(lam_bar.y, s), s), d_sq),
+        y: *ONE_XP - (div_xp(mul_up_mag(mul_up_mag(lam_bar.x, s), s), d_sq + int(1)) + int(1)),
+    };
+
+    // The square root is always subtracted, so round it down.
+    let mut qc = -calc_xp_xp_div_lambda_lambda(x, r, lambda, s, c, tau_beta, d_sq);
+    qc = qc + mul_down_xp_to_np(mul_down_mag(r.y, r.y), s_term.y);
+    let qc = if qc.is_positive() {
+        sqrt_int(qc)?
+    } else {
+        I256::zero()
+    };
+
+    let qa = if (qb - qc).is_positive() {
+        mul_up_xp_to_np(qb - qc, div_xp(*ONE_XP, s_term.y) + int(1))
+    } else {
+        mul_up_xp_to_np(qb - qc, div_xp(*ONE_XP, s_term.x))
+    };
+
+    Ok(qa + ab.y)
+}
+
+fn calc_xp_xp_div_lambda_lambda(
+    x: I256,
+    r: &Vector2,
+    lambda: I256,
+    s: I256,
+    c: I256,
+    tau_beta: &Vector2,
+    d_sq: I256,
+) -> I256 {
+    // x'x'/lambda^2 = r^2 c^2 tau(beta)_x^2
+    //      + ( r^2 2s c tau(beta)_x tau(beta)_y - rx 2c tau(beta)_x ) / lambda
+    //      + ( r^2 s^2 tau(beta)_y^2 - rx 2s tau(beta)_y + x^2 ) / lambda^2
+    let d_sq2 = mul_xp(d_sq, d_sq);
+    let r_x_sq = mul_up_mag(r.x, r.x);
+
+    // r^2 s 2c tau(beta)_x tau(beta)_y
+    let term_xp = div_xp(mul_xp(tau_beta.x, tau_beta.y), d_sq2);
+    let mut qa = if term_xp.is_positive() {
+        mul_up_xp_to_np(
+            mul_up_mag(mul_up_mag(r_x_sq, int(2) * s), c),
+            term_xp + int(7),
+        )
+    } else {
+        mul_up_xp_to_np(
+            mul_down_mag(mul_down_mag(mul_down_mag(r.y, r.y), int(2) * s), c),
+            term_xp,
+        )
+    };
+
+    // -rx 2c tau(beta)_x
+    let qb = if tau_beta.x.is_negative() {
+        mul_up_xp_to_np(
+            mul_up_mag(mul_up_mag(r.x, x), int(2) * c),
+            -div_xp(tau_beta.x, d_sq) + int(3),
+        )
+    } else {
+        mul_up_xp_to_np(
+            mul_down_mag(mul_down_mag(-r.y, x), int(2) * c),
+            div_xp(tau_beta.x, d_sq),
+        )
+    };
+    qa = qa + qb;
+
+    // r^2 s^2 tau(beta)_y^2
+    let term_xp = div_xp(mul_xp(tau_beta.y, tau_beta.y), d_sq2) + int(7);
+    let mut qb = mul_up_xp_to_np(mul_up_mag(mul_up_mag(r_x_sq, s), s), term_xp);
+
+    // -rx 2s tau(beta)_y
+    let qc = mul_up_xp_to_np(
+        mul_down_mag(mul_down_mag(-r.y, x), int(2) * s),
+        div_xp(tau_beta.y, d_sq),
+    );
+
+    // (qb + qc + x^2) / lambda
+    qb = qb + qc + mul_up_mag(x, x);
+    qb = if qb.is_positive() {
+        div_up_mag(qb, lambda)
+    } else {
+        div_down_mag(qb, lambda)
+    };
+
+    // (qa + qb) / lambda
+    qa = qa + qb;
+    qa = if qa.is_positive() {
+        div_up_mag(qa, lambda)
+    } else {
+        div_down_mag(qa, lambda)
+    };
+
+    // + r^2 c^2 tau(beta)_x^2
+    let term_xp = div_xp(mul_xp(tau_beta.x, tau_beta.x), d_sq2) + int(7);
+    let val = mul_up_mag(mul_up_mag(r_x_sq, c), c);
+    mul_up_xp_to_np(val, term_xp) + qa
+}
+
+fn calc_y_given_x(x: I256, p: &Params, d: &DerivedParams, r: &Vector2) -> Result<I256, Error> {
+    let ab = Vector2 {
+        x: virtual_offset0(p, d, r),
+        y: virtual_offset1(p, d, r),
+    };
+    solve_quadratic_swap(p.lambda, x, p.s, p.c, r, &ab, &d.tau_beta, d.d_sq)
+}
+
+fn calc_x_given_y(y: I256, p: &Params, d: &DerivedParams, r: &Vector2) -> Result<I256, Error> {
+    let ba = Vector2 {
+        x: virtual_offset1(p, d, r),
+        y: virtual_offset0(p, d, r),
+    };
+    // Change x->y, s->c, c->s, b->a, a->b, tau(beta)_x -> -tau(alpha)_x and
+    // tau(beta)_y -> tau(alpha)_y compared to `calc_y_given_x`.
+    let tau = Vector2 {
+        x: -d.tau_alpha.x,
+        y: d.tau_alpha.y,
+    };
+    solve_quadratic_swap(p.lambda, y, p.c, p.s, r, &ba, &tau, d.d_sq)
+}
+
+/// E-CLP parameters for a pool with a price range of `[1.2, 1.5]`, a rotation
+/// angle of `atan(4/3)` and a stretching factor of 50, with the derived
+/// parameters computed in high precision.
+#[cfg(test)]
+pub fn example_params() -> (Params, DerivedParams) {
+    let int = |value: &str| I256::from_dec_str(value).unwrap();
+    (
+        Params {
+            alpha: int("1200000000000000000"),
+            beta: int("1500000000000000000"),
+            c: int("600000000000000000"),
+            s: int("800000000000000000"),
+            lambda: int("50000000000000000000"),
+        },
+        DerivedParams {
+            tau_alpha: Vector2 {
+                x: int("-93165455510677928117982508238899133481"),
+                y: int("36334527649164391966013178213170662058"),
+            },
+            tau_beta: Vector2 {
+                x: int("94088741186872681077800154929077170101"),
+                y: int("33871946827274165188008055774467781236"),
+            },
+            u: int("89882014414824292413975678320628625719"),
+            v: int("34758475923154646828089899852400818332"),
+            w: int("-1182038794507308853442458770577382794"),
+            z: int("-25753944699559708807500749498427664192"),
+            d_sq: I256::exp10(38),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances() -> [U256; 2] {
+        [
+            U256::from(1_000) * U256::exp10(18),
+            U256::from(1_500) * U256::exp10(18),
+        ]
+    }
+
+    fn invariant_vector(p: &Params, d: &DerivedParams) -> Vector2 {
+        let (invariant, err) = calculate_invariant_with_error(balances(), p, d).unwrap();
+        Vector2 {
+            x: invariant + err * int(2),
+            y: invariant,
+        }
+    }
+
+    #[test]
+    fn square_root() {
+        assert_eq!(sqrt(U256::zero(), 5).unwrap(), U256::zero());
+        assert_eq!(
+            sqrt(U256::from(4) * *ONE_U256, 5).unwrap(),
+            U256::from(2) * *ONE_U256
+        );
+        assert_eq!(sqrt(U256::exp10(16), 5).unwrap(), U256::exp10(17));
+    }
+
+    #[test]
+    fn invariant() {
+        let (p, d) = example_params();
+        // The exact invariant is 38.383297394481303771579...
+        assert_eq!(
+            calculate_invariant_with_error(balances(), &p, &d).unwrap(),
+            (int(38_383_297_394_481_303_771), int(2))
+        );
+    }
+
+    #[test]
+    fn out_given_in() {
+        let (p, d) = example_params();
+        let r = invariant_vector(&p, &d);
+        let amount_in = U256::from(10) * U256::exp10(18);
+
+        // The exact amounts are 13.361469694857829627... and
+        // 7.481824431435710634... respectively, the pool rounds in its favour.
+        assert_eq!(
+            calc_out_given_in(balances(), amount_in, true, &p, &d, &r).unwrap(),
+            U256::from(13_361_469_694_857_829_352_u128)
+        );
+        assert_eq!(
+            calc_out_given_in(balances(), amount_in, false, &p, &d, &r).unwrap(),
+            U256::from(7_481_824_431_435_710_430_u128)
+        );
+    }
+
+    #[test]
+    fn in_given_out() {
+        let (p, d) = example_params();
+        let r = invariant_vector(&p, &d);
+
+        assert_eq!(
+            calc_in_given_out(
+                balances(),
+                U256::from(13_361_469_694_857_829_352_u128),
+                true,
+                &p,
+                &d,
+                &r
+            )
+            .unwrap(),
+            U256::from(9_999_999_999_999_999_998_u128)
+        );
+        assert_eq!(
+            calc_in_given_out(
+                balances(),
+                U256::from(7_481_824_431_435_710_430_u128),
+                false,
+                &p,
+                &d,
+                &r
+            )
+            .unwrap(),
+            U256::from(9_999_999_999_999_999_997_u128)
+        );
+    }
+
+    #[test]
+    fn rejects_swaps_out_of_bounds() {
+        let (p, d) = example_params();
+        let r = invariant_vector(&p, &d);
+
+        assert_eq!(
+            calc_in_given_out(balances(), balances()[1] + 1, true, &p, &d, &r),
+            Err(Error::AssetBoundsExceeded)
+        );
+        assert_eq!(
+            calc_out_given_in(balances(), U256::exp10(36), true, &p, &d, &r),
+            Err(Error::AssetBoundsExceeded)
+        );
+    }
+}
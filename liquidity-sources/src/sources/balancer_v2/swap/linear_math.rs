@@ -0,0 +1,406 @@
+//! Module emulating the functions in the Balancer LinearMath implementation.
+//! The original contract code can be found at:
+//! https://github.com/balancer-labs/balancer-v2-monorepo/blob/master/pkg/pool-linear/contracts/LinearMath.sol
+//!
+//! Note that all balances and amounts are expected to already be upscaled,
+//! and that the wrapped token rate is included in its scaling factor.
+
+use super::{error::Error, fixed_point::Bfp, math::BalU256};
+
+/// Linear pool parameters used for computing nominal main token balances.
+#[derive(Clone, Copy, Debug)]
+pub struct Params {
+    pub fee: Bfp,
+    pub lower_target: Bfp,
+    pub upper_target: Bfp,
+}
+
+pub fn calc_bpt_out_per_main_in(
+    main_in: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount out, so we round down overall.
+
+    if bpt_supply.is_zero() {
+        return to_nominal(main_in, params);
+    }
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let after_nominal_main = to_nominal(main_balance.add(main_in)?, params)?;
+    let delta_nominal_main = after_nominal_main.sub(previous_nominal_main)?;
+    let invariant = calc_invariant(previous_nominal_main, wrapped_balance)?;
+    mul_div_down(bpt_supply, delta_nominal_main, invariant)
+}
+
+pub fn calc_bpt_in_per_main_out(
+    main_out: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount in, so we round up overall.
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let after_nominal_main = to_nominal(main_balance.sub(main_out)?, params)?;
+    let delta_nominal_main = previous_nominal_main.sub(after_nominal_main)?;
+    let invariant = calc_invariant(previous_nominal_main, wrapped_balance)?;
+    mul_div_up(bpt_supply, delta_nominal_main, invariant)
+}
+
+pub fn calc_wrapped_out_per_main_in(
+    main_in: Bfp,
+    main_balance: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount out, so we round down overall.
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let after_nominal_main = to_nominal(main_balance.add(main_in)?, params)?;
+    after_nominal_main.sub(previous_nominal_main)
+}
+
+pub fn calc_wrapped_in_per_main_out(
+    main_out: Bfp,
+    main_balance: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount in, so we round up overall.
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let after_nominal_main = to_nominal(main_balance.sub(main_out)?, params)?;
+    previous_nominal_main.sub(after_nominal_main)
+}
+
+pub fn calc_main_in_per_bpt_out(
+    bpt_out: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount in, so we round up overall.
+
+    if bpt_supply.is_zero() {
+        return from_nominal(bpt_out, params);
+    }
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let invariant = calc_invariant(previous_nominal_main, wrapped_balance)?;
+    let delta_nominal_main = mul_div_up(invariant, bpt_out, bpt_supply)?;
+    let after_nominal_main = previous_nominal_main.add(delta_nominal_main)?;
+    let new_main_balance = from_nominal(after_nominal_main, params)?;
+    new_main_balance.sub(main_balance)
+}
+
+pub fn calc_main_out_per_bpt_in(
+    bpt_in: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount out, so we round down overall.
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let invariant = calc_invariant(previous_nominal_main, wrapped_balance)?;
+    let delta_nominal_main = mul_div_down(invariant, bpt_in, bpt_supply)?;
+    let after_nominal_main = previous_nominal_main.sub(delta_nominal_main)?;
+    let new_main_balance = from_nominal(after_nominal_main, params)?;
+    main_balance.sub(new_main_balance)
+}
+
+pub fn calc_main_out_per_wrapped_in(
+    wrapped_in: Bfp,
+    main_balance: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount out, so we round down overall.
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let after_nominal_main = previous_nominal_main.sub(wrapped_in)?;
+    let new_main_balance = from_nominal(after_nominal_main, params)?;
+    main_balance.sub(new_main_balance)
+}
+
+pub fn calc_main_in_per_wrapped_out(
+    wrapped_out: Bfp,
+    main_balance: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount in, so we round up overall.
+
+    let previous_nominal_main = to_nominal(main_balance, params)?;
+    let after_nominal_main = previous_nominal_main.add(wrapped_out)?;
+    let new_main_balance = from_nominal(after_nominal_main, params)?;
+    new_main_balance.sub(main_balance)
+}
+
+pub fn calc_bpt_out_per_wrapped_in(
+    wrapped_in: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount out, so we round down overall.
+
+    if bpt_supply.is_zero() {
+        return Ok(wrapped_in);
+    }
+
+    let nominal_main = to_nominal(main_balance, params)?;
+    let previous_invariant = calc_invariant(nominal_main, wrapped_balance)?;
+    let new_wrapped_balance = wrapped_balance.add(wrapped_in)?;
+    let new_invariant = calc_invariant(nominal_main, new_wrapped_balance)?;
+    let new_bpt_balance = mul_div_down(bpt_supply, new_invariant, previous_invariant)?;
+    new_bpt_balance.sub(bpt_supply)
+}
+
+pub fn calc_bpt_in_per_wrapped_out(
+    wrapped_out: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount in, so we round up overall.
+
+    let nominal_main = to_nominal(main_balance, params)?;
+    let previous_invariant = calc_invariant(nominal_main, wrapped_balance)?;
+    let new_wrapped_balance = wrapped_balance.sub(wrapped_out)?;
+    let new_invariant = calc_invariant(nominal_main, new_wrapped_balance)?;
+    let new_bpt_balance = mul_div_down(bpt_supply, new_invariant, previous_invariant)?;
+    bpt_supply.sub(new_bpt_balance)
+}
+
+pub fn calc_wrapped_in_per_bpt_out(
+    bpt_out: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount in, so we round up overall.
+
+    if bpt_supply.is_zero() {
+        return Ok(bpt_out);
+    }
+
+    let nominal_main = to_nominal(main_balance, params)?;
+    let previous_invariant = calc_invariant(nominal_main, wrapped_balance)?;
+    let new_bpt_balance = bpt_supply.add(bpt_out)?;
+    let new_wrapped_balance =
+        mul_div_up(new_bpt_balance, previous_invariant, bpt_supply)?.sub(nominal_main)?;
+    new_wrapped_balance.sub(wrapped_balance)
+}
+
+pub fn calc_wrapped_out_per_bpt_in(
+    bpt_in: Bfp,
+    main_balance: Bfp,
+    wrapped_balance: Bfp,
+    bpt_supply: Bfp,
+    params: &Params,
+) -> Result<Bfp, Error> {
+    // Amount out, so we round down overall.
+
+    let nominal_main = to_nominal(main_balance, params)?;
+    let previous_invariant = calc_invariant(nominal_main, wrapped_balance)?;
+    let new_bpt_balance = bpt_supply.sub(bpt_in)?;
+    let new_wrapped_balance =
+        mul_div_up(new_bpt_balance, previous_invariant, bpt_supply)?.sub(nominal_main)?;
+    wrapped_balance.sub(new_wrapped_balance)
+}
+
+fn calc_invariant(nominal_main_balance: Bfp, wrapped_balance: Bfp) -> Result<Bfp, Error> {
+    nominal_main_balance.add(wrapped_balance)
+}
+
+fn to_nominal(real: Bfp, params: &Params) -> Result<Bfp, Error> {
+    // Fees are always rounded down: either direction would work but we need to
+    // be consistent, and rounding down uses less gas.
+
+    if real < params.lower_target {
+        let fees = params.lower_target.sub(real)?.mul_down(params.fee)?;
+        real.sub(fees)
+    } else if real <= params.upper_target {
+        Ok(real)
+    } else {
+        let fees = real.sub(params.upper_target)?.mul_down(params.fee)?;
+        real.sub(fees)
+    }
+}
+
+fn from_nominal(nominal: Bfp, params: &Params) -> Result<Bfp, Error> {
+    // Since real = nominal + fees, rounding down fees is equivalent to rounding
+    // down real.
+
+    if nominal < params.lower_target {
+        nominal
+            .add(params.fee.mul_down(params.lower_target)?)?
+            .div_down(Bfp::one().add(params.fee)?)
+    } else if nominal <= params.upper_target {
+        Ok(nominal)
+    } else {
+        nominal
+            .sub(params.fee.mul_down(params.upper_target)?)?
+            .div_down(Bfp::one().sub(params.fee)?)
+    }
+}
+
+/// Computes `a * b / c` with regular (not fixed point) integer arithmetic,
+/// rounding down.
+fn mul_div_down(a: Bfp, b: Bfp, c: Bfp) -> Result<Bfp, Error> {
+    Ok(Bfp::from_wei(
+        a.as_uint256()
+            .bmul(b.as_uint256())?
+            .bdiv_down(c.as_uint256())?,
+    ))
+}
+
+/// Computes `a * b / c` with regular (not fixed point) integer arithmetic,
+/// rounding up.
+fn mul_div_up(a: Bfp, b: Bfp, c: Bfp) -> Result<Bfp, Error> {
+    Ok(Bfp::from_wei(
+        a.as_uint256()
+            .bmul(b.as_uint256())?
+            .bdiv_up(c.as_uint256())?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Params {
+        Params {
+            fee: bfp!("0.01"),
+            lower_target: bfp!("1000.0"),
+            upper_target: bfp!("2000.0"),
+        }
+    }
+
+    #[test]
+    fn nominal_balances_within_targets_have_no_fees() {
+        let params = params();
+        assert_eq!(to_nominal(bfp!("1500.0"), &params).unwrap(), bfp!("1500.0"));
+        assert_eq!(
+            from_nominal(bfp!("1500.0"), &params).unwrap(),
+            bfp!("1500.0")
+        );
+    }
+
+    #[test]
+    fn nominal_balances_outside_targets_charge_fees() {
+        let params = params();
+
+        // 500 below the lower target charges 1% of 500 in fees.
+        assert_eq!(to_nominal(bfp!("500.0"), &params).unwrap(), bfp!("495.0"));
+        assert_eq!(from_nominal(bfp!("495.0"), &params).unwrap(), bfp!("500.0"));
+
+        // 1000 above the upper target charges 1% of 1000 in fees.
+        assert_eq!(to_nominal(bfp!("3000.0"), &params).unwrap(), bfp!("2990.0"));
+        assert_eq!(
+            from_nominal(bfp!("2990.0"), &params).unwrap(),
+            bfp!("3000.0")
+        );
+    }
+
+    #[test]
+    fn main_wrapped_swaps_within_targets() {
+        let params = params();
+        let main_balance = bfp!("1500.0");
+
+        assert_eq!(
+            calc_wrapped_out_per_main_in(bfp!("100.0"), main_balance, &params).unwrap(),
+            bfp!("100.0")
+        );
+        assert_eq!(
+            calc_wrapped_in_per_main_out(bfp!("100.0"), main_balance, &params).unwrap(),
+            bfp!("100.0")
+        );
+        assert_eq!(
+            calc_main_out_per_wrapped_in(bfp!("100.0"), main_balance, &params).unwrap(),
+            bfp!("100.0")
+        );
+        assert_eq!(
+            calc_main_in_per_wrapped_out(bfp!("100.0"), main_balance, &params).unwrap(),
+            bfp!("100.0")
+        );
+    }
+
+    #[test]
+    fn main_wrapped_swaps_outside_targets() {
+        let params = params();
+
+        // Bringing the main balance from 2000 to 2100 moves it 100 above the
+        // upper target, so we get 1% less wrapped tokens out.
+        assert_eq!(
+            calc_wrapped_out_per_main_in(bfp!("100.0"), bfp!("2000.0"), &params).unwrap(),
+            bfp!("99.0")
+        );
+        // Conversely, bringing the main balance back from 2100 to 2000 only
+        // requires 99 wrapped tokens.
+        assert_eq!(
+            calc_wrapped_in_per_main_out(bfp!("100.0"), bfp!("2100.0"), &params).unwrap(),
+            bfp!("99.0")
+        );
+    }
+
+    #[test]
+    fn bpt_swaps_are_proportional_to_invariant() {
+        let params = params();
+        let (main_balance, wrapped_balance, bpt_supply) =
+            (bfp!("1500.0"), bfp!("1500.0"), bfp!("1000.0"));
+
+        // The invariant is 3000, and the supply is 1000, so each BPT is worth
+        // 3 nominal main or wrapped tokens.
+        assert_eq!(
+            calc_bpt_out_per_main_in(
+                bfp!("300.0"),
+                main_balance,
+                wrapped_balance,
+                bpt_supply,
+                &params
+            )
+            .unwrap(),
+            bfp!("100.0")
+        );
+        assert_eq!(
+            calc_main_out_per_bpt_in(
+                bfp!("100.0"),
+                main_balance,
+                wrapped_balance,
+                bpt_supply,
+                &params
+            )
+            .unwrap(),
+            bfp!("300.0")
+        );
+        assert_eq!(
+            calc_bpt_out_per_wrapped_in(
+                bfp!("300.0"),
+                main_balance,
+                wrapped_balance,
+                bpt_supply,
+                &params
+            )
+            .unwrap(),
+            bfp!("100.0")
+        );
+        assert_eq!(
+            calc_wrapped_in_per_bpt_out(
+                bfp!("100.0"),
+                main_balance,
+                wrapped_balance,
+                bpt_supply,
+                &params
+            )
+            .unwrap(),
+            bfp!("300.0")
+        );
+    }
+}
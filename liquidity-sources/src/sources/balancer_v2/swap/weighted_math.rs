@@ -74,9 +74,30 @@ pub fn calc_in_given_out(
     balance_in.mul_up(ratio)
 }
 
+/// Returns the spot price of the out token in units of the in token including the swap fee, that
+/// is the amount of the in token to pay per unit of the out token for an infinitesimally small
+/// trade.
+///
+/// There is no equivalent in WeightedMath.sol, the price follows from differentiating the
+/// invariant: `(balance_in / weight_in) / (balance_out / weight_out) / (1 - swap_fee)`. It is
+/// rounded up like the in amounts computed by the contract.
+pub fn calc_spot_price(
+    balance_in: Bfp,
+    weight_in: Bfp,
+    balance_out: Bfp,
+    weight_out: Bfp,
+    swap_fee: Bfp,
+) -> Result<Bfp, Error> {
+    let numerator = balance_in.div_up(weight_in)?;
+    let denominator = balance_out.div_down(weight_out)?;
+    numerator.div_up(denominator)?.div_up(swap_fee.complement())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::amm::{self, Decimal};
+    use serde::Deserialize;
 
     // The expected output for the tested functions was generated by running the
     // following instructions after cloning and installing the repo at
@@ -91,7 +112,38 @@ mod tests {
     // > const weightedMath = await (await ethers.getContractFactory("WeightedMathTest")).deploy()
     // ```
     // Every test specifies a command that should be pasted into the console to
-    // obtain the expected output.
+    // obtain the expected output. The fixtures start with the same vectors,
+    // followed by vectors of the reference port in `test/amm/balancer_math.py`
+    // that reproduces these outputs.
+
+    #[derive(Deserialize)]
+    struct Input {
+        balance_in: Decimal,
+        weight_in: Decimal,
+        balance_out: Decimal,
+        weight_out: Decimal,
+        amount: Decimal,
+    }
+
+    type Calc = fn(Bfp, Bfp, Bfp, Bfp, Bfp) -> Result<Bfp, Error>;
+
+    fn check_vectors(calc: Calc, fixture: &str) {
+        for vector in amm::vectors::<Input, Decimal>(fixture) {
+            let input = vector.input;
+            assert_eq!(
+                calc(
+                    Bfp::from_wei(input.balance_in.0),
+                    Bfp::from_wei(input.weight_in.0),
+                    Bfp::from_wei(input.balance_out.0),
+                    Bfp::from_wei(input.weight_out.0),
+                    Bfp::from_wei(input.amount.0),
+                ),
+                Ok(Bfp::from_wei(vector.output.0)),
+                "{}",
+                vector.source
+            );
+        }
+    }
 
     #[test]
     fn calc_out_given_in_ok() {
@@ -125,6 +177,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn calc_out_given_in_vectors() {
+        check_vectors(calc_out_given_in, "balancer_weighted_out_given_in.json");
+    }
+
+    #[test]
+    fn calc_in_given_out_vectors() {
+        check_vectors(calc_in_given_out, "balancer_weighted_in_given_out.json");
+    }
+
     #[test]
     fn calc_out_given_in_err() {
         let zero = Bfp::from_wei(0.into());
@@ -284,4 +346,73 @@ mod tests {
             "305".into()
         );
     }
+
+    #[test]
+    fn calc_spot_price_ok() {
+        let bfp = |value: &str| value.parse::<Bfp>().unwrap();
+        assert_eq!(
+            calc_spot_price(bfp("100"), bfp("0.8"), bfp("25"), bfp("0.2"), bfp("0")).unwrap(),
+            Bfp::one(),
+        );
+        assert_eq!(
+            calc_spot_price(bfp("100"), bfp("0.5"), bfp("300"), bfp("0.5"), bfp("0")).unwrap(),
+            Bfp::from_wei(333_333_333_333_333_334_u128.into()),
+        );
+        assert_eq!(
+            calc_spot_price(bfp("100"), bfp("0.8"), bfp("25"), bfp("0.2"), bfp("0.01")).unwrap(),
+            Bfp::from_wei(1_010_101_010_101_010_102_u128.into()),
+        );
+
+        // Small trades are executed at about the spot price.
+        let amount_in =
+            calc_in_given_out(bfp("100"), bfp("0.5"), bfp("300"), bfp("0.5"), bfp("0.001"))
+                .unwrap();
+        assert!((amount_in.to_f64_lossy() / 0.001 - 1. / 3.).abs() < 1e-5);
+
+        assert_eq!(
+            calc_spot_price(bfp("100"), bfp("0.5"), bfp("0"), bfp("0.5"), bfp("0"))
+                .unwrap_err()
+                .to_string(),
+            "BAL#004: ZeroDivision",
+        );
+    }
+
+    #[test]
+    fn swap_properties() {
+        let bfp = |value: &str| value.parse::<Bfp>().unwrap();
+        // Amounts up to half the balance, to cover trades exceeding the
+        // maximum in ratio. The weights are chosen such that buying the
+        // amounts bought with the largest trades stays within the maximum out
+        // ratio.
+        let (balance_in, balance_out) = (bfp("20000"), bfp("10000"));
+        let amounts = amm::amounts(U256::exp10(19), U256::exp10(22), 31);
+        for (weight_in, weight_out) in [(bfp("0.5"), bfp("0.5")), (bfp("0.2"), bfp("0.8"))] {
+            amm::check_swap_properties(
+                |amount_in| {
+                    calc_out_given_in(
+                        balance_in,
+                        weight_in,
+                        balance_out,
+                        weight_out,
+                        Bfp::from_wei(amount_in),
+                    )
+                    .ok()
+                    .map(Bfp::as_uint256)
+                },
+                |amount_out| {
+                    calc_in_given_out(
+                        balance_in,
+                        weight_in,
+                        balance_out,
+                        weight_out,
+                        Bfp::from_wei(amount_out),
+                    )
+                    .ok()
+                    .map(Bfp::as_uint256)
+                },
+                &amounts,
+                1e-6,
+            );
+        }
+    }
 }
@@ -0,0 +1,421 @@
+//! Clipper RFQ liquidity source implementation.
+//!
+//! Clipper is a pooled RFQ exchange: a single exchange contract per chain
+//! holds the liquidity for a small set of short-tail assets, and swaps are
+//! priced off-chain from oracle prices and the pool's inventory. Quotes are
+//! requested from the Clipper API and then signed for settlement with the
+//! exchange contract.
+
+use super::rfq::{QuoteAmount, QuoteRequest, RfqQuote, RfqQuoting};
+use crate::u256_decimal::{self, DecimalU256};
+use anyhow::{ensure, Context as _, Result};
+use ethcontract::{H160, H256, U256};
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::serde_as;
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    pub static ref DEFAULT_CLIPPER_API_URL: Url =
+        Url::parse("https://api.clipper.exchange/")
+            .expect("invalid default Clipper API URL");
+}
+
+/// Configuration for the Clipper RFQ source.
+#[derive(Clone, Debug)]
+pub struct ClipperConfig {
+    pub api_url: Url,
+    pub api_key: String,
+    pub chain_id: u64,
+    /// The amount of time requested quotes should remain valid for.
+    pub quote_validity: Duration,
+    /// The minimum amount of time a quote needs to remain valid for in order
+    /// to be used. This should cover the time it takes to settle the quote.
+    pub min_validity: Duration,
+}
+
+/// Clipper RFQ quoter.
+pub struct ClipperRfq {
+    client: Client,
+    config: ClipperConfig,
+}
+
+impl ClipperRfq {
+    pub fn new(client: Client, config: ClipperConfig) -> Self {
+        Self { client, config }
+    }
+
+    async fn get<T>(&self, path: &str, query: &[(&str, String)]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.client
+            .get(self.config.api_url.join(path)?)
+            .header("x-api-key", &self.config.api_key)
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("invalid Clipper {} response", path))
+    }
+
+    async fn post<T>(&self, path: &str, body: &impl Serialize) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.client
+            .post(self.config.api_url.join(path)?)
+            .header("x-api-key", &self.config.api_key)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("invalid Clipper {} response", path))
+    }
+
+    /// Returns the symbols of the sell and buy tokens, or `None` if swaps are
+    /// disabled or the pool does not hold both tokens.
+    async fn symbols(&self, request: &QuoteRequest) -> Result<Option<(String, String)>> {
+        let pool = self
+            .get::<PoolResponse>(
+                "rfq/pool",
+                &[("chain_id", self.config.chain_id.to_string())],
+            )
+            .await?;
+        ensure!(
+            pool.pool.chain_id == self.config.chain_id,
+            "Clipper pool information for a different chain"
+        );
+        Ok(pool.symbols(request.sell_token, request.buy_token))
+    }
+
+    /// Converts a signed Clipper quote into an RFQ quote, returning `None`
+    /// for quotes that do not match the request or that are about to expire.
+    fn convert_quote(&self, request: &QuoteRequest, signed: Value) -> Result<Option<RfqQuote>> {
+        let quote = serde_json::from_value::<SignedQuote>(signed.clone())
+            .context("invalid Clipper signed quote")?;
+        if let QuoteAmount::Sell(amount) = request.amount {
+            if quote.input_amount != amount {
+                return Ok(None);
+            }
+        }
+
+        let quote = RfqQuote {
+            maker: quote.clipper_exchange_address,
+            sell_token: request.sell_token,
+            buy_token: request.buy_token,
+            sell_amount: quote.input_amount,
+            buy_amount: quote.output_amount,
+            partially_fillable: false,
+            valid_to: quote.good_until,
+            data: serde_json::to_vec(&signed)?,
+            signature: quote.signature.to_bytes(),
+        };
+        if !quote.is_valid_for(self.config.min_validity) {
+            tracing::debug!("discarding expiring Clipper quote");
+            return Ok(None);
+        }
+
+        Ok(Some(quote))
+    }
+}
+
+#[async_trait::async_trait]
+impl RfqQuoting for ClipperRfq {
+    async fn quote(&self, request: &QuoteRequest) -> Result<Option<RfqQuote>> {
+        // Clipper only quotes exact input amounts.
+        let amount = match request.amount {
+            QuoteAmount::Sell(amount) => amount,
+            QuoteAmount::Buy(_) => return Ok(None),
+        };
+        let (input_asset_symbol, output_asset_symbol) = match self.symbols(request).await? {
+            Some(symbols) => symbols,
+            None => return Ok(None),
+        };
+
+        let quote = self
+            .post::<QuoteResponse>(
+                "rfq/quote",
+                &QuoteBody {
+                    input_amount: amount,
+                    input_asset_symbol,
+                    output_asset_symbol,
+                    chain_id: self.config.chain_id,
+                    time_in_seconds: self.config.quote_validity.as_secs(),
+                    destination_address: request.trader,
+                },
+            )
+            .await?;
+        let signed = self
+            .post::<Value>(
+                "rfq/sign",
+                &SignBody {
+                    quote_id: quote.id,
+                    destination_address: request.trader,
+                    sender_address: request.trader,
+                    native_input: false,
+                    native_output: false,
+                },
+            )
+            .await?;
+
+        self.convert_quote(request, signed)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PoolResponse {
+    pool: PoolInfo,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PoolInfo {
+    chain_id: u64,
+    swaps_enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    address: H160,
+}
+
+impl PoolResponse {
+    fn symbols(&self, sell_token: H160, buy_token: H160) -> Option<(String, String)> {
+        if !self.pool.swaps_enabled {
+            return None;
+        }
+        let symbol = |token: H160| {
+            self.assets
+                .iter()
+                .find(|asset| asset.address == token)
+                .map(|asset| asset.name.clone())
+        };
+        Some((symbol(sell_token)?, symbol(buy_token)?))
+    }
+}
+
+#[serde_as]
+#[derive(Serialize)]
+struct QuoteBody {
+    #[serde_as(as = "DecimalU256")]
+    input_amount: U256,
+    input_asset_symbol: String,
+    output_asset_symbol: String,
+    chain_id: u64,
+    time_in_seconds: u64,
+    destination_address: H160,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuoteResponse {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct SignBody {
+    quote_id: String,
+    destination_address: H160,
+    sender_address: H160,
+    native_input: bool,
+    native_output: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedQuote {
+    clipper_exchange_address: H160,
+    #[serde(with = "u256_decimal")]
+    input_amount: U256,
+    #[serde(with = "u256_decimal")]
+    output_amount: U256,
+    good_until: u64,
+    signature: Signature,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signature {
+    v: u8,
+    r: H256,
+    s: H256,
+}
+
+impl Signature {
+    /// Encodes the signature as `r || s || v`.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(65);
+        bytes.extend_from_slice(self.r.as_bytes());
+        bytes.extend_from_slice(self.s.as_bytes());
+        bytes.push(self.v);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn clipper() -> ClipperRfq {
+        ClipperRfq::new(
+            Client::new(),
+            ClipperConfig {
+                api_url: DEFAULT_CLIPPER_API_URL.clone(),
+                api_key: String::new(),
+                chain_id: 1,
+                quote_validity: Duration::from_secs(60),
+                min_validity: Duration::from_secs(10),
+            },
+        )
+    }
+
+    fn request() -> QuoteRequest {
+        QuoteRequest {
+            sell_token: addr!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+            buy_token: addr!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+            amount: QuoteAmount::Sell(1_000_000_000.into()),
+            trader: H160([0x42; 20]),
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn signed_quote(input_amount: &str, good_until: u64) -> Value {
+        json!({
+            "clipper_exchange_address": "0x1111111111111111111111111111111111111111",
+            "input_amount": input_amount,
+            "output_amount": "500000000000000000",
+            "good_until": good_until,
+            "destination_address": "0x4242424242424242424242424242424242424242",
+            "signature": {
+                "v": 27,
+                "r": "0x0101010101010101010101010101010101010101010101010101010101010101",
+                "s": "0x0202020202020202020202020202020202020202020202020202020202020202",
+            },
+        })
+    }
+
+    #[test]
+    fn finds_pool_symbols() {
+        let pool = serde_json::from_value::<PoolResponse>(json!({
+            "pool": {
+                "chain_id": 1,
+                "address": "0x1111111111111111111111111111111111111111",
+                "num_assets": 2,
+                "swaps_enabled": true,
+            },
+            "assets": [
+                {
+                    "name": "USDC",
+                    "address": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+                    "decimals": 6,
+                },
+                {
+                    "name": "WETH",
+                    "address": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+                    "decimals": 18,
+                },
+            ],
+        }))
+        .unwrap();
+
+        let request = request();
+        assert_eq!(
+            pool.symbols(request.sell_token, request.buy_token),
+            Some(("USDC".to_owned(), "WETH".to_owned())),
+        );
+        assert_eq!(pool.symbols(request.sell_token, H160([1; 20])), None);
+
+        let disabled = PoolResponse {
+            pool: PoolInfo {
+                chain_id: 1,
+                swaps_enabled: false,
+            },
+            ..pool
+        };
+        assert_eq!(
+            disabled.symbols(request.sell_token, request.buy_token),
+            None
+        );
+    }
+
+    #[test]
+    fn serialize_quote_body() {
+        let body = QuoteBody {
+            input_amount: 42.into(),
+            input_asset_symbol: "USDC".to_owned(),
+            output_asset_symbol: "WETH".to_owned(),
+            chain_id: 1,
+            time_in_seconds: 60,
+            destination_address: H160([3; 20]),
+        };
+        assert_eq!(
+            serde_json::to_value(&body).unwrap(),
+            json!({
+                "input_amount": "42",
+                "input_asset_symbol": "USDC",
+                "output_asset_symbol": "WETH",
+                "chain_id": 1,
+                "time_in_seconds": 60,
+                "destination_address": "0x0303030303030303030303030303030303030303",
+            }),
+        );
+    }
+
+    #[test]
+    fn converts_quotes() {
+        let quote = clipper()
+            .convert_quote(&request(), signed_quote("1000000000", now() + 60))
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.maker, H160([0x11; 20]));
+        assert_eq!(quote.sell_amount, 1_000_000_000.into());
+        assert_eq!(quote.buy_amount, U256::from(5) * U256::exp10(17));
+        assert!(!quote.partially_fillable);
+        assert_eq!(quote.signature.len(), 65);
+        assert_eq!(quote.signature[0], 1);
+        assert_eq!(quote.signature[32], 2);
+        assert_eq!(quote.signature[64], 27);
+    }
+
+    #[test]
+    fn discards_expiring_and_mismatched_quotes() {
+        let clipper = clipper();
+        assert!(clipper
+            .convert_quote(&request(), signed_quote("1000000000", now() + 5))
+            .unwrap()
+            .is_none());
+        assert!(clipper
+            .convert_quote(&request(), signed_quote("1", now() + 60))
+            .unwrap()
+            .is_none());
+        assert!(clipper
+            .convert_quote(&request(), json!({ "error": "bad quote" }))
+            .is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn clipper_quote() {
+        let clipper = ClipperRfq::new(
+            Client::new(),
+            ClipperConfig {
+                api_key: std::env::var("CLIPPER_API_KEY").unwrap(),
+                ..clipper().config
+            },
+        );
+        let quote = clipper.quote(&request()).await.unwrap();
+        println!("{:#?}", quote);
+    }
+}
@@ -0,0 +1,312 @@
+//! CoW Protocol orderbook liquidity source implementation.
+//!
+//! Open orders of other CoW Protocol traders can be matched against each
+//! other directly in a coincidence of wants. This source fetches the orders
+//! that are currently solvable from the orderbook API, meaning that they are
+//! open and that their owners have sufficient balance and allowance to fill
+//! them, and exposes the orders for the requested token pairs as limit order
+//! liquidity.
+
+use crate::{
+    conversions::U256Ext as _,
+    liquidity::{LimitOrder, LimitOrderFetching},
+    recent_block_cache::Block,
+    token_pair::TokenPair,
+    u256_decimal,
+};
+use anyhow::{Context as _, Result};
+use ethcontract::{H160, U256};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+lazy_static::lazy_static! {
+    pub static ref DEFAULT_ORDERBOOK_API_URL: Url =
+        Url::parse("https://api.cow.fi/mainnet/")
+            .expect("invalid default CoW Protocol orderbook API URL");
+}
+
+/// Fetches solvable orders from the CoW Protocol orderbook API.
+pub struct CowOrderbookFetcher {
+    client: Client,
+    api_url: Url,
+}
+
+impl CowOrderbookFetcher {
+    /// Creates a new fetcher for the orderbook API at the specified URL, for
+    /// example `https://api.cow.fi/mainnet/` for Ethereum mainnet.
+    pub fn new(client: Client, api_url: Url) -> Self {
+        Self { client, api_url }
+    }
+
+    async fn solvable_orders(&self) -> Result<Vec<Order>> {
+        let auction = self
+            .client
+            .get(self.api_url.join("api/v1/auction")?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Auction>()
+            .await
+            .context("invalid CoW Protocol auction response")?;
+        Ok(auction.orders)
+    }
+}
+
+#[async_trait::async_trait]
+impl LimitOrderFetching for CowOrderbookFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, _: Block) -> Result<Vec<LimitOrder>> {
+        // The orderbook only knows about the latest state of the orders, so
+        // the block is ignored.
+        let orders = self.solvable_orders().await?;
+        Ok(limit_orders(orders, &token_pairs, now()))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Converts the orders for the specified token pairs that are still valid at
+/// the specified time into limit orders.
+fn limit_orders(orders: Vec<Order>, token_pairs: &HashSet<TokenPair>, now: u64) -> Vec<LimitOrder> {
+    orders
+        .into_iter()
+        .filter(|order| {
+            TokenPair::new(order.sell_token, order.buy_token)
+                .map(|pair| token_pairs.contains(&pair))
+                .unwrap_or(false)
+        })
+        .filter(|order| !order.invalidated && order.valid_to > now)
+        .filter_map(|order| order.remaining())
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct Auction {
+    orders: Vec<Order>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum OrderKind {
+    Sell,
+    Buy,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Order {
+    uid: String,
+    sell_token: H160,
+    buy_token: H160,
+    #[serde(with = "u256_decimal")]
+    sell_amount: U256,
+    #[serde(with = "u256_decimal")]
+    buy_amount: U256,
+    valid_to: u64,
+    kind: OrderKind,
+    partially_fillable: bool,
+    #[serde(default, with = "u256_decimal")]
+    executed_sell_amount_before_fees: U256,
+    #[serde(default, with = "u256_decimal")]
+    executed_buy_amount: U256,
+    #[serde(default)]
+    invalidated: bool,
+}
+
+impl Order {
+    /// Returns the limit order for the remaining amounts of the order, or
+    /// `None` if the order can no longer be filled.
+    fn remaining(self) -> Option<LimitOrder> {
+        let (sell_amount, buy_amount) = match (self.kind, self.partially_fillable) {
+            (_, false) => {
+                if !self.executed_sell_amount_before_fees.is_zero()
+                    || !self.executed_buy_amount.is_zero()
+                {
+                    return None;
+                }
+                (self.sell_amount, self.buy_amount)
+            }
+            // The remaining amounts of partially filled orders are scaled
+            // such that the limit price is never improved for the trader.
+            (OrderKind::Sell, true) => {
+                let sell_amount = self
+                    .sell_amount
+                    .checked_sub(self.executed_sell_amount_before_fees)?;
+                let buy_amount = self
+                    .buy_amount
+                    .checked_mul(sell_amount)?
+                    .checked_ceil_div(&self.sell_amount)?;
+                (sell_amount, buy_amount)
+            }
+            (OrderKind::Buy, true) => {
+                let buy_amount = self.buy_amount.checked_sub(self.executed_buy_amount)?;
+                let sell_amount = self
+                    .sell_amount
+                    .checked_mul(buy_amount)?
+                    .checked_div(self.buy_amount)?;
+                (sell_amount, buy_amount)
+            }
+        };
+        if sell_amount.is_zero() || buy_amount.is_zero() {
+            return None;
+        }
+
+        Some(LimitOrder {
+            id: self.uid,
+            sell_token: self.sell_token,
+            buy_token: self.buy_token,
+            sell_amount,
+            buy_amount,
+            partially_fillable: self.partially_fillable,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashset;
+    use serde_json::json;
+
+    fn order(kind: &str, partially_fillable: bool, executed: (u64, u64)) -> Order {
+        serde_json::from_value(json!({
+            "uid": "0x0101010101010101010101010101010101010101010101010101010101010101\
+                    0202020202020202020202020202020202020202ffffffff",
+            "owner": "0x0202020202020202020202020202020202020202",
+            "sellToken": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+            "buyToken": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            "receiver": null,
+            "sellAmount": "1000000000",
+            "buyAmount": "300000000000000000",
+            "validTo": 4294967295_u64,
+            "appData": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "feeAmount": "1000000",
+            "kind": kind,
+            "partiallyFillable": partially_fillable,
+            "sellTokenBalance": "erc20",
+            "buyTokenBalance": "erc20",
+            "signingScheme": "eip712",
+            "signature": "0x",
+            "creationDate": "2022-08-01T00:00:00.000000Z",
+            "executedSellAmount": executed.0.to_string(),
+            "executedSellAmountBeforeFees": executed.0.to_string(),
+            "executedBuyAmount": executed.1.to_string(),
+            "executedFeeAmount": "0",
+            "invalidated": false,
+            "status": "open",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn deserialize_auction() {
+        let auction = serde_json::from_value::<Auction>(json!({
+            "id": 42,
+            "block": 15000000,
+            "latestSettlementBlock": 14999990,
+            "orders": [],
+            "prices": {},
+        }))
+        .unwrap();
+        assert!(auction.orders.is_empty());
+    }
+
+    #[test]
+    fn converts_unfilled_orders() {
+        let order = order("sell", false, (0, 0)).remaining().unwrap();
+        assert_eq!(
+            order,
+            LimitOrder {
+                id: "0x0101010101010101010101010101010101010101010101010101010101010101\
+                     0202020202020202020202020202020202020202ffffffff"
+                    .to_owned(),
+                sell_token: addr!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+                buy_token: addr!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+                sell_amount: 1_000_000_000.into(),
+                buy_amount: U256::from(3) * U256::exp10(17),
+                partially_fillable: false,
+            }
+        );
+    }
+
+    #[test]
+    fn skips_filled_fill_or_kill_orders() {
+        assert!(
+            order("sell", false, (1_000_000_000, 300_000_000_000_000_000))
+                .remaining()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn scales_partially_filled_orders() {
+        // Sell orders round the remaining buy amount up.
+        let remaining = order("sell", true, (333_333_333, 100_000_000_000_000_000))
+            .remaining()
+            .unwrap();
+        assert_eq!(remaining.sell_amount, 666_666_667.into());
+        assert_eq!(remaining.buy_amount, 200_000_000_100_000_000_u64.into());
+
+        // Buy orders round the remaining sell amount down.
+        let remaining = order("buy", true, (0, 100_000_000_000_000_001))
+            .remaining()
+            .unwrap();
+        assert_eq!(remaining.buy_amount, 199_999_999_999_999_999_u64.into());
+        assert_eq!(remaining.sell_amount, 666_666_666.into());
+
+        assert!(order("buy", true, (0, 300_000_000_000_000_000))
+            .remaining()
+            .is_none());
+    }
+
+    #[test]
+    fn filters_orders_by_pair_and_validity() {
+        let pair = TokenPair::new(
+            addr!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+            addr!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+        )
+        .unwrap();
+        let orders = || {
+            vec![
+                order("sell", false, (0, 0)),
+                Order {
+                    valid_to: 100,
+                    ..order("sell", false, (0, 0))
+                },
+                Order {
+                    invalidated: true,
+                    ..order("sell", false, (0, 0))
+                },
+            ]
+        };
+
+        assert_eq!(limit_orders(orders(), &hashset! { pair }, 100).len(), 1);
+        assert!(limit_orders(
+            orders(),
+            &hashset! { TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap() },
+            100,
+        )
+        .is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn fetch_orderbook_limit_orders() {
+        let fetcher = CowOrderbookFetcher::new(Client::new(), DEFAULT_ORDERBOOK_API_URL.clone());
+        let orders = fetcher.solvable_orders().await.unwrap();
+        let token_pairs = orders
+            .iter()
+            .filter_map(|order| TokenPair::new(order.sell_token, order.buy_token))
+            .collect();
+        let limit_orders = fetcher.fetch(token_pairs, Block::Recent).await.unwrap();
+        println!("{:#?}", limit_orders);
+    }
+}
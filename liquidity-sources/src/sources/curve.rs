@@ -0,0 +1,9 @@
+//! Curve StableSwap baseline liquidity source implementation.
+//!
+//! Pools are discovered from the on-chain Curve registry, so pools that are
+//! not part of the registry (such as factory pools) are not indexed.
+//! Metapools are composed with their base pool (for example 3pool), so that
+//! their coins can be traded with the coins of the base pool.
+
+pub mod pool_fetching;
+pub mod registry;
@@ -0,0 +1,1024 @@
+//! Module for fetching current Curve pool state for token pairs.
+
+use super::registry::{BasePoolInfo, PoolInfo, PoolRegistry};
+use crate::{
+    baseline_solver::BaselineSolvable,
+    maintenance::Maintaining,
+    recent_block_cache::Block,
+    sources::{
+        stableswap::{self, Amplification, PRECISION},
+        uniswap_v2::pool_fetching::handle_contract_error,
+        MAX_BATCH_SIZE,
+    },
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::{Context as _, Result};
+use contracts::ERC20;
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt as _,
+};
+use std::collections::HashSet;
+
+const POOL_SWAP_GAS_COST: usize = 130_000;
+const UNDERLYING_POOL_SWAP_GAS_COST: usize = 350_000;
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The current state of a Curve pool for a set of coins that can be swapped
+/// with each other.
+///
+/// Lending pools are represented by two distinct pools: one for swapping the
+/// coins held by the pool with `exchange` and one for swapping the underlying
+/// coins with `exchange_underlying`.
+///
+/// Metapools are similarly represented by two pools: one for swapping the
+/// metapool coins with the LP token of the base pool, and one for swapping the
+/// metapool coins with the base pool coins with `exchange_underlying`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub address: H160,
+    pub tokens: Vec<TokenState>,
+    pub amplification_parameter: U256,
+    /// The swap fee with 10 decimals of precision.
+    pub fee: U256,
+    /// Whether this pool swaps the underlying coins of a lending pool or a
+    /// metapool.
+    pub underlying: bool,
+    /// The base pool of a metapool for swapping with the base pool coins. In
+    /// this case the last token of the pool is the base pool LP token, which
+    /// is minted from or redeemed for the base pool coins as part of a swap.
+    pub base_pool: Option<Box<BasePool>>,
+}
+
+/// The current state of the base pool of a metapool.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasePool {
+    pub pool: Pool,
+    /// The total supply of the base pool LP token.
+    pub lp_supply: U256,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenState {
+    pub address: H160,
+    pub balance: U256,
+    /// The rate for normalizing the balance to 18 decimals, with 18 decimals
+    /// of precision.
+    pub rate: U256,
+}
+
+/// The coin indices of a swap with a pool contract.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Exchange {
+    pub i: usize,
+    pub j: usize,
+    /// Whether the swap uses `exchange_underlying` instead of `exchange`.
+    pub underlying: bool,
+}
+
+/// The route of a swap through a pool.
+enum Swap<'a> {
+    /// Swap coin `i` for coin `j` of the pool.
+    Direct(usize, usize),
+    /// Swap metapool coin `i` for the base pool LP token, and redeem it for
+    /// base pool coin `j`.
+    ToBaseCoin(usize, &'a BasePool, usize),
+    /// Deposit base pool coin `i` for the base pool LP token, and swap it for
+    /// metapool coin `j`.
+    FromBaseCoin(&'a BasePool, usize, usize),
+}
+
+impl Pool {
+    /// Returns true if the pool can swap between both tokens of a pair.
+    pub fn contains_pair(&self, pair: TokenPair) -> bool {
+        let (a, b) = pair.get();
+        self.swap(a, b).is_some()
+    }
+
+    /// Returns the arguments for swapping the tokens with the pool contract,
+    /// or `None` if the pool can't swap them.
+    pub fn exchange(&self, in_token: H160, out_token: H160) -> Option<Exchange> {
+        // The underlying coins of a metapool are its own coins followed by
+        // the coins of its base pool.
+        let (i, j, underlying) = match self.swap(in_token, out_token)? {
+            Swap::Direct(i, j) => (i, j, self.underlying),
+            Swap::ToBaseCoin(i, _, j) => (i, self.lp_index()? + j, true),
+            Swap::FromBaseCoin(_, i, j) => (self.lp_index()? + i, j, true),
+        };
+        Some(Exchange { i, j, underlying })
+    }
+
+    fn token_index(&self, token: H160) -> Option<usize> {
+        self.tokens.iter().position(|state| state.address == token)
+    }
+
+    fn swap(&self, in_token: H160, out_token: H160) -> Option<Swap<'_>> {
+        let base_pool = match &self.base_pool {
+            Some(base_pool) => base_pool,
+            None => {
+                return Some(Swap::Direct(
+                    self.token_index(in_token)?,
+                    self.token_index(out_token)?,
+                ))
+            }
+        };
+
+        let lp_index = self.lp_index()?;
+        let meta_index = |token| {
+            self.token_index(token)
+                .filter(|index: &usize| *index != lp_index)
+        };
+        match (meta_index(in_token), meta_index(out_token)) {
+            (Some(i), Some(j)) => Some(Swap::Direct(i, j)),
+            (Some(i), None) => Some(Swap::ToBaseCoin(
+                i,
+                base_pool,
+                base_pool.pool.token_index(out_token)?,
+            )),
+            (None, Some(j)) => Some(Swap::FromBaseCoin(
+                base_pool,
+                base_pool.pool.token_index(in_token)?,
+                j,
+            )),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the index of the base pool LP token of a metapool.
+    fn lp_index(&self) -> Option<usize> {
+        self.tokens.len().checked_sub(1)
+    }
+
+    fn balances_and_rates(&self) -> (Vec<U256>, Vec<U256>) {
+        self.tokens
+            .iter()
+            .map(|state| (state.balance, state.rate))
+            .unzip()
+    }
+
+    fn get_dy(&self, i: usize, j: usize, dx: U256) -> Option<U256> {
+        let (balances, rates) = self.balances_and_rates();
+        stableswap::get_dy(
+            i,
+            j,
+            dx,
+            &balances,
+            &rates,
+            Amplification::new(self.amplification_parameter),
+            self.fee,
+        )
+    }
+
+    fn get_dx(&self, i: usize, j: usize, dy: U256) -> Option<U256> {
+        let (balances, rates) = self.balances_and_rates();
+        stableswap::get_dx(
+            i,
+            j,
+            dy,
+            &balances,
+            &rates,
+            Amplification::new(self.amplification_parameter),
+            self.fee,
+        )
+    }
+}
+
+impl BasePool {
+    /// Computes the amount of LP tokens minted for depositing `amount` of
+    /// coin `i`.
+    fn add_liquidity(&self, i: usize, amount: U256) -> Option<U256> {
+        let (balances, rates) = self.pool.balances_and_rates();
+        let mut amounts = vec![U256::zero(); balances.len()];
+        *amounts.get_mut(i)? = amount;
+        stableswap::add_liquidity(
+            &amounts,
+            &balances,
+            &rates,
+            Amplification::new(self.pool.amplification_parameter),
+            self.pool.fee,
+            self.lp_supply,
+        )
+    }
+
+    /// Computes the amount of coin `i` received for redeeming `lp_amount` of
+    /// LP tokens.
+    fn remove_liquidity_one_coin(&self, i: usize, lp_amount: U256) -> Option<U256> {
+        let (balances, rates) = self.pool.balances_and_rates();
+        stableswap::remove_liquidity_one_coin(
+            i,
+            lp_amount,
+            &balances,
+            &rates,
+            Amplification::new(self.pool.amplification_parameter),
+            self.pool.fee,
+            self.lp_supply,
+        )
+    }
+
+    /// Computes the LP token virtual price of the base pool.
+    fn virtual_price(&self) -> Option<U256> {
+        let (balances, rates) = self.pool.balances_and_rates();
+        stableswap::get_virtual_price(
+            &balances,
+            &rates,
+            Amplification::new(self.pool.amplification_parameter),
+            self.lp_supply,
+        )
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        match self.swap(in_token, out_token)? {
+            Swap::Direct(i, j) => self.get_dy(i, j, in_amount),
+            Swap::ToBaseCoin(i, base_pool, j) => {
+                let lp_amount = self.get_dy(i, self.lp_index()?, in_amount)?;
+                base_pool.remove_liquidity_one_coin(j, lp_amount)
+            }
+            Swap::FromBaseCoin(base_pool, i, j) => {
+                let lp_amount = base_pool.add_liquidity(i, in_amount)?;
+                self.get_dy(self.lp_index()?, j, lp_amount)
+            }
+        }
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        // Depositing into and redeeming from the base pool can't be inverted
+        // in closed form, so the input amounts for these legs are searched.
+        match self.swap(in_token, out_token)? {
+            Swap::Direct(i, j) => self.get_dx(i, j, out_amount),
+            Swap::ToBaseCoin(i, base_pool, j) => {
+                let lp_amount = min_input(out_amount, |lp_amount| {
+                    base_pool.remove_liquidity_one_coin(j, lp_amount)
+                })?;
+                self.get_dx(i, self.lp_index()?, lp_amount)
+            }
+            Swap::FromBaseCoin(base_pool, i, j) => {
+                let lp_amount = self.get_dx(self.lp_index()?, j, out_amount)?;
+                min_input(lp_amount, |amount| base_pool.add_liquidity(i, amount))
+            }
+        }
+    }
+
+    fn gas_cost(&self) -> usize {
+        if self.underlying {
+            UNDERLYING_POOL_SWAP_GAS_COST
+        } else {
+            POOL_SWAP_GAS_COST
+        }
+    }
+}
+
+/// Curve pool fetcher for pools indexed from the on-chain registry.
+pub struct CurvePoolFetcher {
+    registry: PoolRegistry,
+    web3: Web3,
+}
+
+impl CurvePoolFetcher {
+    pub async fn new(web3: &Web3) -> Result<Self> {
+        Ok(Self {
+            registry: PoolRegistry::new(web3).await?,
+            web3: web3.clone(),
+        })
+    }
+
+    fn read_state(
+        &self,
+        info: PoolInfo,
+        batch: &mut Web3CallBatch,
+        block: BlockId,
+    ) -> BoxFuture<'static, Result<Vec<Pool>>> {
+        let registry = self.registry.contract();
+        let balances = registry
+            .get_balances(info.address)
+            .block(block)
+            .batch_call(batch);
+        let rates = registry
+            .get_rates(info.address)
+            .block(block)
+            .batch_call(batch);
+        let amplification_parameter = registry.get_a(info.address).block(block).batch_call(batch);
+        let fees = registry
+            .get_fees(info.address)
+            .block(block)
+            .batch_call(batch);
+        let base_pool = info
+            .base_pool
+            .clone()
+            .map(|base_pool| self.read_base_pool_state(&info, base_pool, batch, block))
+            .transpose();
+
+        async move {
+            let base_pool = match base_pool? {
+                Some(base_pool) => Some(base_pool.await),
+                None => None,
+            };
+            handle_results(FetchedPool {
+                info,
+                balances: balances.await,
+                rates: rates.await,
+                amplification_parameter: amplification_parameter.await,
+                fees: fees.await,
+                base_pool,
+            })
+        }
+        .boxed()
+    }
+
+    fn read_base_pool_state(
+        &self,
+        info: &PoolInfo,
+        base_pool: BasePoolInfo,
+        batch: &mut Web3CallBatch,
+        block: BlockId,
+    ) -> Result<BoxFuture<'static, FetchedBasePool>> {
+        // The base pool LP token is the last coin of the metapool.
+        let lp_token = info
+            .coins
+            .last()
+            .context("base pool without coins")?
+            .address;
+        let registry = self.registry.contract();
+        let balances = registry
+            .get_balances(base_pool.address)
+            .block(block)
+            .batch_call(batch);
+        let rates = registry
+            .get_rates(base_pool.address)
+            .block(block)
+            .batch_call(batch);
+        let amplification_parameter = registry
+            .get_a(base_pool.address)
+            .block(block)
+            .batch_call(batch);
+        let fees = registry
+            .get_fees(base_pool.address)
+            .block(block)
+            .batch_call(batch);
+        let lp_supply = ERC20::at(&self.web3, lp_token)
+            .total_supply()
+            .block(block)
+            .batch_call(batch);
+
+        Ok(async move {
+            FetchedBasePool {
+                info: base_pool,
+                balances: balances.await,
+                rates: rates.await,
+                amplification_parameter: amplification_parameter.await,
+                fees: fees.await,
+                lp_supply: lp_supply.await,
+            }
+        }
+        .boxed())
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for CurvePoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let futures = self
+            .registry
+            .pools_for_token_pairs(&token_pairs)
+            .into_iter()
+            .map(|info| self.read_state(info, &mut batch, block))
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        let pools = future::try_join_all(futures).await?;
+        Ok(pools
+            .into_iter()
+            .flatten()
+            .filter(|pool| token_pairs.iter().any(|pair| pool.contains_pair(*pair)))
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for CurvePoolFetcher {
+    async fn run_maintenance(&self) -> Result<()> {
+        self.registry.run_maintenance().await
+    }
+}
+
+struct FetchedPool {
+    info: PoolInfo,
+    balances: Result<[U256; 8], MethodError>,
+    rates: Result<[U256; 8], MethodError>,
+    amplification_parameter: Result<U256, MethodError>,
+    fees: Result<[U256; 2], MethodError>,
+    base_pool: Option<FetchedBasePool>,
+}
+
+struct FetchedBasePool {
+    info: BasePoolInfo,
+    balances: Result<[U256; 8], MethodError>,
+    rates: Result<[U256; 8], MethodError>,
+    amplification_parameter: Result<U256, MethodError>,
+    fees: Result<[U256; 2], MethodError>,
+    lp_supply: Result<U256, MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Vec<Pool>> {
+    let balances = handle_contract_error(fetched_pool.balances)?;
+    let rates = handle_contract_error(fetched_pool.rates)?;
+    let amplification_parameter = handle_contract_error(fetched_pool.amplification_parameter)?;
+    let fees = handle_contract_error(fetched_pool.fees)?;
+    let base_pool = match fetched_pool.base_pool {
+        Some(fetched_base_pool) => match handle_base_pool_results(fetched_base_pool)? {
+            Some(base_pool) => Some(base_pool),
+            None => return Ok(Vec::new()),
+        },
+        None => None,
+    };
+
+    let pools = match (balances, rates, amplification_parameter, fees) {
+        (Some(balances), Some(rates), Some(amplification_parameter), Some([fee, _])) => {
+            let pools = match base_pool {
+                Some(base_pool) => metapools_from_state(
+                    &fetched_pool.info,
+                    &balances,
+                    &rates,
+                    amplification_parameter,
+                    fee,
+                    base_pool,
+                ),
+                None => pools_from_state(
+                    &fetched_pool.info,
+                    &balances,
+                    &rates,
+                    amplification_parameter,
+                    fee,
+                ),
+            };
+            pools.unwrap_or_default()
+        }
+        _ => Vec::new(),
+    };
+    Ok(pools)
+}
+
+fn handle_base_pool_results(fetched_base_pool: FetchedBasePool) -> Result<Option<BasePool>> {
+    let balances = handle_contract_error(fetched_base_pool.balances)?;
+    let rates = handle_contract_error(fetched_base_pool.rates)?;
+    let amplification_parameter = handle_contract_error(fetched_base_pool.amplification_parameter)?;
+    let fees = handle_contract_error(fetched_base_pool.fees)?;
+    let lp_supply = handle_contract_error(fetched_base_pool.lp_supply)?;
+
+    let base_pool = match (balances, rates, amplification_parameter, fees, lp_supply) {
+        (
+            Some(balances),
+            Some(rates),
+            Some(amplification_parameter),
+            Some([fee, _]),
+            Some(lp_supply),
+        ) => {
+            let info = PoolInfo {
+                address: fetched_base_pool.info.address,
+                coins: fetched_base_pool.info.coins,
+                underlying_coins: None,
+                base_pool: None,
+            };
+            pools_from_state(&info, &balances, &rates, amplification_parameter, fee)
+                .and_then(|pools| pools.into_iter().next())
+                .map(|pool| BasePool { pool, lp_supply })
+        }
+        _ => None,
+    };
+    Ok(base_pool)
+}
+
+/// Computes the pools for the coins and underlying coins of a Curve pool from
+/// its current state, returning `None` on arithmetic errors and for coins with
+/// more than 18 decimals.
+fn pools_from_state(
+    info: &PoolInfo,
+    balances: &[U256],
+    rates: &[U256],
+    amplification_parameter: U256,
+    fee: U256,
+) -> Option<Vec<Pool>> {
+    // For lending pools, the rates returned by the registry are the exchange
+    // rates of the coins to their underlying coins. Balances are then
+    // normalized with the decimals of the underlying coins.
+    let normalizing_coins = info.underlying_coins.as_ref().unwrap_or(&info.coins);
+
+    let tokens = info
+        .coins
+        .iter()
+        .zip(normalizing_coins)
+        .zip(balances.iter().zip(rates))
+        .map(|((coin, normalizing_coin), (balance, rate))| {
+            Some(TokenState {
+                address: coin.address,
+                balance: *balance,
+                rate: rate.checked_mul(decimals_factor(normalizing_coin.decimals)?)?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut pools = vec![Pool {
+        address: info.address,
+        tokens,
+        amplification_parameter,
+        fee,
+        underlying: false,
+        base_pool: None,
+    }];
+
+    if let Some(underlying_coins) = &info.underlying_coins {
+        let tokens = underlying_coins
+            .iter()
+            .zip(balances.iter().zip(rates))
+            .map(|(coin, (balance, rate))| {
+                Some(TokenState {
+                    address: coin.address,
+                    balance: balance.checked_mul(*rate)? / *PRECISION,
+                    rate: decimals_factor(coin.decimals)?.checked_mul(*PRECISION)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        pools.push(Pool {
+            address: info.address,
+            tokens,
+            amplification_parameter,
+            fee,
+            underlying: true,
+            base_pool: None,
+        });
+    }
+
+    Some(pools)
+}
+
+/// Computes the pools for the coins of a Curve metapool and for swapping them
+/// with the coins of its base pool, returning `None` on arithmetic errors.
+fn metapools_from_state(
+    info: &PoolInfo,
+    balances: &[U256],
+    rates: &[U256],
+    amplification_parameter: U256,
+    fee: U256,
+    base_pool: BasePool,
+) -> Option<Vec<Pool>> {
+    let mut pool = pools_from_state(info, balances, rates, amplification_parameter, fee)?
+        .into_iter()
+        .next()?;
+
+    // Metapools value the base pool LP token at the base pool virtual price,
+    // which they cache for a few minutes. The current virtual price is used
+    // here, so amounts can be off by a tiny fraction.
+    pool.tokens.last_mut()?.rate = base_pool.virtual_price()?;
+
+    let underlying = Pool {
+        underlying: true,
+        base_pool: Some(Box::new(base_pool)),
+        ..pool.clone()
+    };
+    Some(vec![pool, underlying])
+}
+
+/// Computes the smallest input amount for which the monotonically increasing
+/// function `f` returns at least `target`.
+fn min_input(target: U256, f: impl Fn(U256) -> Option<U256>) -> Option<U256> {
+    // Amounts that are too small to be swapped make `f` fail, so failures are
+    // treated like outputs below the target.
+    let reaches_target = |amount| matches!(f(amount), Some(output) if output >= target);
+
+    // Find an upper bound by doubling the input and then bisect the range.
+    let (mut low, mut high) = (U256::zero(), U256::one());
+    while !reaches_target(high) {
+        low = high;
+        high = high.checked_mul(2.into())?;
+    }
+    while high - low > U256::one() {
+        let middle = low + (high - low) / 2;
+        if reaches_target(middle) {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+
+    Some(high)
+}
+
+/// Returns the factor for normalizing amounts with the specified decimals to
+/// 18 decimals, or `None` for more than 18 decimals, which Curve doesn't
+/// support.
+fn decimals_factor(decimals: u8) -> Option<U256> {
+    Some(U256::exp10(18u8.checked_sub(decimals)? as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ethcontract_error, sources::curve::registry::Coin};
+
+    fn coin(byte: u8, decimals: u8) -> Coin {
+        Coin {
+            address: H160([byte; 20]),
+            decimals,
+        }
+    }
+
+    fn array<const N: usize>(values: &[u64]) -> [U256; N] {
+        let mut array = [U256::zero(); N];
+        for (item, value) in array.iter_mut().zip(values) {
+            *item = (*value).into();
+        }
+        array
+    }
+
+    #[test]
+    fn plain_pool_from_state() {
+        let info = PoolInfo {
+            address: H160([0xff; 20]),
+            coins: vec![coin(1, 18), coin(2, 6)],
+            underlying_coins: None,
+            base_pool: None,
+        };
+
+        assert_eq!(
+            pools_from_state(
+                &info,
+                &[1_000.into(), 2_000.into()],
+                &[*PRECISION, *PRECISION],
+                100.into(),
+                4_000_000.into(),
+            )
+            .unwrap(),
+            vec![Pool {
+                address: info.address,
+                tokens: vec![
+                    TokenState {
+                        address: H160([1; 20]),
+                        balance: 1_000.into(),
+                        rate: U256::exp10(18),
+                    },
+                    TokenState {
+                        address: H160([2; 20]),
+                        balance: 2_000.into(),
+                        rate: U256::exp10(30),
+                    },
+                ],
+                amplification_parameter: 100.into(),
+                fee: 4_000_000.into(),
+                underlying: false,
+                base_pool: None,
+            }],
+        );
+    }
+
+    #[test]
+    fn skips_pools_with_more_than_18_decimals() {
+        let info = PoolInfo {
+            address: H160([0xff; 20]),
+            coins: vec![coin(1, 18), coin(2, 24)],
+            underlying_coins: None,
+            base_pool: None,
+        };
+
+        assert_eq!(
+            pools_from_state(
+                &info,
+                &[1_000.into(), 2_000.into()],
+                &[*PRECISION, *PRECISION],
+                100.into(),
+                4_000_000.into(),
+            ),
+            None,
+        );
+    }
+
+    #[test]
+    fn lending_pool_from_state() {
+        // A Compound-like pool with cDAI (8 decimals, underlying DAI with 18
+        // decimals) and cUSDC (8 decimals, underlying USDC with 6 decimals).
+        let info = PoolInfo {
+            address: H160([0xff; 20]),
+            coins: vec![coin(1, 8), coin(2, 8)],
+            underlying_coins: Some(vec![coin(3, 18), coin(4, 6)]),
+            base_pool: None,
+        };
+        let balances = [
+            U256::from(50_000_000) * U256::exp10(8),
+            U256::from(50_000_000) * U256::exp10(8),
+        ];
+        let rates = [
+            U256::from(2) * U256::exp10(26),
+            U256::from(2) * U256::exp10(14),
+        ];
+
+        let pools =
+            pools_from_state(&info, &balances, &rates, 100.into(), 4_000_000.into()).unwrap();
+        assert_eq!(pools.len(), 2);
+
+        let (wrapped, underlying) = (&pools[0], &pools[1]);
+        assert!(!wrapped.underlying);
+        assert_eq!(wrapped.tokens[0].rate, U256::from(2) * U256::exp10(26));
+        assert_eq!(wrapped.tokens[1].rate, U256::from(2) * U256::exp10(26));
+
+        assert!(underlying.underlying);
+        assert_eq!(
+            underlying
+                .tokens
+                .iter()
+                .map(|token| (token.address, token.balance, token.rate))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    H160([3; 20]),
+                    U256::from(1_000_000) * U256::exp10(18),
+                    U256::exp10(18),
+                ),
+                (
+                    H160([4; 20]),
+                    U256::from(1_000_000) * U256::exp10(6),
+                    U256::exp10(30),
+                ),
+            ],
+        );
+
+        // Both pools describe the same balances, so swapping 1000 cDAI (worth
+        // 20 DAI) should result in about the same output as swapping 20 DAI.
+        assert_eq!(
+            wrapped.get_amount_out(
+                H160([2; 20]),
+                (U256::from(1000) * U256::exp10(8), H160([1; 20]))
+            ),
+            Some(99_959_980_206_u64.into()),
+        );
+        assert_eq!(
+            underlying.get_amount_out(
+                H160([4; 20]),
+                (U256::from(20) * U256::exp10(18), H160([3; 20]))
+            ),
+            Some(19_991_997.into()),
+        );
+    }
+
+    fn three_pool() -> BasePool {
+        let token = |byte: u8, balance: U256, rate: U256| TokenState {
+            address: H160([byte; 20]),
+            balance,
+            rate,
+        };
+        BasePool {
+            pool: Pool {
+                address: H160([0xee; 20]),
+                tokens: vec![
+                    token(
+                        3,
+                        U256::from(100_000_000) * U256::exp10(18),
+                        U256::exp10(18),
+                    ),
+                    token(4, U256::from(120_000_000) * U256::exp10(6), U256::exp10(30)),
+                    token(5, U256::from(80_000_000) * U256::exp10(6), U256::exp10(30)),
+                ],
+                amplification_parameter: 2000.into(),
+                fee: 1_000_000.into(),
+                underlying: false,
+                base_pool: None,
+            },
+            lp_supply: U256::from(290_000_000) * U256::exp10(18),
+        }
+    }
+
+    fn metapools() -> (Pool, Pool) {
+        // A metapool pairing a coin with the 3pool LP token.
+        let info = PoolInfo {
+            address: H160([0xff; 20]),
+            coins: vec![coin(1, 18), coin(2, 18)],
+            underlying_coins: None,
+            base_pool: Some(BasePoolInfo {
+                address: H160([0xee; 20]),
+                coins: vec![coin(3, 18), coin(4, 6), coin(5, 6)],
+            }),
+        };
+        let pools = metapools_from_state(
+            &info,
+            &[
+                U256::from(50_000_000) * U256::exp10(18),
+                U256::from(45_000_000) * U256::exp10(18),
+            ],
+            &[*PRECISION, *PRECISION],
+            100.into(),
+            4_000_000.into(),
+            three_pool(),
+        )
+        .unwrap();
+        assert_eq!(pools.len(), 2);
+        (pools[0].clone(), pools[1].clone())
+    }
+
+    #[test]
+    fn metapool_from_state() {
+        let (meta, underlying) = metapools();
+
+        assert!(!meta.underlying);
+        assert_eq!(meta.base_pool, None);
+        // The LP token is valued at the base pool virtual price.
+        assert_eq!(meta.tokens[1].rate, 1_034_475_578_502_098_800_u64.into());
+
+        assert!(underlying.underlying);
+        assert_eq!(underlying.tokens, meta.tokens);
+        assert_eq!(underlying.base_pool, Some(Box::new(three_pool())));
+    }
+
+    #[test]
+    fn metapool_contains_pair() {
+        let (meta, underlying) = metapools();
+        let pair = |a: u8, b: u8| TokenPair::new(H160([a; 20]), H160([b; 20])).unwrap();
+
+        assert!(meta.contains_pair(pair(1, 2)));
+        assert!(!meta.contains_pair(pair(1, 4)));
+
+        assert!(underlying.contains_pair(pair(1, 3)));
+        assert!(underlying.contains_pair(pair(1, 4)));
+        assert!(underlying.contains_pair(pair(1, 5)));
+        assert!(!underlying.contains_pair(pair(1, 2)));
+        assert!(!underlying.contains_pair(pair(3, 4)));
+    }
+
+    #[test]
+    fn metapool_exchange_indices() {
+        let (meta, underlying) = metapools();
+        let token = |byte: u8| H160([byte; 20]);
+
+        assert_eq!(
+            meta.exchange(token(1), token(2)),
+            Some(Exchange {
+                i: 0,
+                j: 1,
+                underlying: false
+            })
+        );
+        assert_eq!(
+            underlying.exchange(token(1), token(4)),
+            Some(Exchange {
+                i: 0,
+                j: 2,
+                underlying: true
+            })
+        );
+        assert_eq!(
+            underlying.exchange(token(5), token(1)),
+            Some(Exchange {
+                i: 3,
+                j: 0,
+                underlying: true
+            })
+        );
+        assert_eq!(underlying.exchange(token(3), token(4)), None);
+    }
+
+    #[test]
+    fn metapool_swaps_through_base_pool() {
+        let (meta, underlying) = metapools();
+        let (coin, usdc, usdt) = (H160([1; 20]), H160([4; 20]), H160([5; 20]));
+
+        // Swapping the metapool coin for USDC first swaps it for the LP token
+        // and then redeems the LP token for USDC.
+        let coin_amount = U256::from(1000) * U256::exp10(18);
+        let lp_amount = meta
+            .get_amount_out(H160([2; 20]), (coin_amount, coin))
+            .unwrap();
+        assert_eq!(
+            lp_amount,
+            U256::from_dec_str("965601595814853201531").unwrap()
+        );
+        assert_eq!(
+            underlying.get_amount_out(usdc, (coin_amount, coin)),
+            Some(998_939_914.into()),
+        );
+        assert_eq!(
+            underlying.get_amount_in(coin, (1_000_000_000.into(), usdc)),
+            Some(U256::from_dec_str("1001061210956070206346").unwrap()),
+        );
+
+        // Swapping USDT for the metapool coin first deposits USDT for the LP
+        // token and then swaps the LP token.
+        assert_eq!(
+            underlying.get_amount_out(coin, (1_000_000_000.into(), usdt)),
+            Some(U256::from_dec_str("1000377030871028163446").unwrap()),
+        );
+        let usdt_amount = underlying.get_amount_in(usdt, (coin_amount, coin)).unwrap();
+        assert_eq!(usdt_amount, 999_623_113.into());
+        assert!(
+            underlying
+                .get_amount_out(coin, (usdt_amount, usdt))
+                .unwrap()
+                >= coin_amount
+        );
+        assert!(
+            underlying
+                .get_amount_out(coin, (usdt_amount - 1, usdt))
+                .unwrap()
+                < coin_amount
+        );
+    }
+
+    #[test]
+    fn pool_contains_pair() {
+        let pool = Pool {
+            address: H160([0xff; 20]),
+            tokens: vec![
+                TokenState {
+                    address: H160([1; 20]),
+                    balance: 1.into(),
+                    rate: 1.into(),
+                },
+                TokenState {
+                    address: H160([2; 20]),
+                    balance: 1.into(),
+                    rate: 1.into(),
+                },
+            ],
+            amplification_parameter: 100.into(),
+            fee: 0.into(),
+            underlying: false,
+            base_pool: None,
+        };
+
+        assert!(pool.contains_pair(TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap()));
+        assert!(!pool.contains_pair(TokenPair::new(H160([1; 20]), H160([3; 20])).unwrap()));
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched_pool = FetchedPool {
+            info: PoolInfo {
+                address: H160([0xff; 20]),
+                coins: vec![coin(1, 18), coin(2, 18)],
+                underlying_coins: None,
+                base_pool: None,
+            },
+            balances: Err(ethcontract_error::testing_node_error()),
+            rates: Ok(array(&[1, 1])),
+            amplification_parameter: Ok(100.into()),
+            fees: Ok(array(&[0, 0])),
+            base_pool: None,
+        };
+        assert!(handle_results(fetched_pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched_pool = FetchedPool {
+            info: PoolInfo {
+                address: H160([0xff; 20]),
+                coins: vec![coin(1, 18), coin(2, 18)],
+                underlying_coins: None,
+                base_pool: None,
+            },
+            balances: Ok(array(&[1, 1])),
+            rates: Ok(array(&[1, 1])),
+            amplification_parameter: Err(ethcontract_error::testing_contract_error()),
+            fees: Ok(array(&[0, 0])),
+            base_pool: None,
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_base_pool_contract_error() {
+        let fetched_pool = FetchedPool {
+            info: PoolInfo {
+                address: H160([0xff; 20]),
+                coins: vec![coin(1, 18), coin(2, 18)],
+                underlying_coins: None,
+                base_pool: Some(BasePoolInfo {
+                    address: H160([0xee; 20]),
+                    coins: vec![coin(3, 18), coin(4, 18)],
+                }),
+            },
+            balances: Ok(array(&[1, 1])),
+            rates: Ok(array(&[1, 1])),
+            amplification_parameter: Ok(100.into()),
+            fees: Ok(array(&[0, 0])),
+            base_pool: Some(FetchedBasePool {
+                info: BasePoolInfo {
+                    address: H160([0xee; 20]),
+                    coins: vec![coin(3, 18), coin(4, 18)],
+                },
+                balances: Ok(array(&[1, 1])),
+                rates: Ok(array(&[1, 1])),
+                amplification_parameter: Ok(100.into()),
+                fees: Ok(array(&[0, 0])),
+                lp_supply: Err(ethcontract_error::testing_contract_error()),
+            }),
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,383 @@
+//! Module for indexing Curve pools from the on-chain registry.
+//!
+//! Pools are enumerated from the main registry, whose address is read from the
+//! Curve address provider. Only static pool information (coins and their
+//! decimals) is indexed here, current pool state is fetched on demand by the
+//! pool fetcher.
+//!
+//! Metapools, which pair one or more coins with the LP token of a base pool,
+//! are indexed along with the coins of their base pool, so that the metapool
+//! coins can be traded against the base pool coins by composing both pools.
+
+use crate::{
+    maintenance::Maintaining, sources::MAX_BATCH_SIZE, token_pair::TokenPair, Web3, Web3CallBatch,
+};
+use anyhow::{ensure, Result};
+use contracts::{CurveAddressProvider, CurveRegistry};
+use ethcontract::{H160, U256};
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt as _,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::RwLock,
+};
+
+/// Static information of a Curve pool.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolInfo {
+    pub address: H160,
+    /// The coins held by the pool, traded with `exchange`.
+    pub coins: Vec<Coin>,
+    /// The underlying coins of lending pools, traded with
+    /// `exchange_underlying` (for example DAI for cDAI in the Compound pool).
+    /// This is `None` for plain pools.
+    pub underlying_coins: Option<Vec<Coin>>,
+    /// The base pool of metapools, whose LP token is the last of the pool's
+    /// coins. This is `None` for pools that are not metapools.
+    pub base_pool: Option<BasePoolInfo>,
+}
+
+/// Static information of the base pool of a metapool.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasePoolInfo {
+    pub address: H160,
+    /// The coins held by the base pool.
+    pub coins: Vec<Coin>,
+}
+
+/// A coin of a Curve pool.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Coin {
+    pub address: H160,
+    pub decimals: u8,
+}
+
+impl PoolInfo {
+    /// Returns all token pairs that can be traded with this pool, including
+    /// pairs of underlying coins and, for metapools, pairs of the metapool
+    /// coins with the base pool coins.
+    pub fn token_pairs(&self) -> HashSet<TokenPair> {
+        let pairs = std::iter::once(&self.coins)
+            .chain(&self.underlying_coins)
+            .flat_map(|coins| {
+                coins.iter().enumerate().flat_map(move |(i, a)| {
+                    coins[i + 1..]
+                        .iter()
+                        .filter_map(move |b| TokenPair::new(a.address, b.address))
+                })
+            });
+        let base_pairs = self.base_pool.iter().flat_map(|base_pool| {
+            self.coins[..self.coins.len() - 1]
+                .iter()
+                .flat_map(move |a| {
+                    base_pool
+                        .coins
+                        .iter()
+                        .filter_map(move |b| TokenPair::new(a.address, b.address))
+                })
+        });
+        pairs.chain(base_pairs).collect()
+    }
+}
+
+/// Registry of indexed Curve pools.
+pub struct PoolRegistry {
+    registry: CurveRegistry,
+    pools: RwLock<IndexedPools>,
+}
+
+#[derive(Default)]
+struct IndexedPools {
+    /// The number of registry pool list entries that were indexed so far.
+    indexed: usize,
+    by_address: HashMap<H160, PoolInfo>,
+    by_pair: HashMap<TokenPair, HashSet<H160>>,
+}
+
+impl PoolRegistry {
+    /// Creates a new pool registry, indexing all pools from the main Curve
+    /// registry.
+    pub async fn new(web3: &Web3) -> Result<Self> {
+        let address_provider = CurveAddressProvider::deployed(web3).await?;
+        let registry = CurveRegistry::at(web3, address_provider.get_registry().call().await?);
+
+        let pool_registry = Self {
+            registry,
+            pools: Default::default(),
+        };
+        pool_registry.update().await?;
+
+        Ok(pool_registry)
+    }
+
+    /// Returns the registry contract.
+    pub fn contract(&self) -> &CurveRegistry {
+        &self.registry
+    }
+
+    /// Returns the static information of all pools that can trade any of the
+    /// specified token pairs.
+    pub fn pools_for_token_pairs(&self, token_pairs: &HashSet<TokenPair>) -> Vec<PoolInfo> {
+        let pools = self.pools.read().unwrap();
+        token_pairs
+            .iter()
+            .filter_map(|pair| pools.by_pair.get(pair))
+            .flatten()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|address| pools.by_address[address].clone())
+            .collect()
+    }
+
+    /// Indexes pools that were added to the registry since the last update.
+    ///
+    /// Note that pools removed from the registry are not removed from the
+    /// index.
+    async fn update(&self) -> Result<()> {
+        let pool_count = self.registry.pool_count().call().await?.as_usize();
+        let indexed = self.pools.read().unwrap().indexed;
+        if pool_count <= indexed {
+            return Ok(());
+        }
+
+        let new_pools = self.fetch_pool_infos(indexed..pool_count).await?;
+        tracing::debug!(count = %new_pools.len(), "indexed new Curve pools");
+
+        let mut pools = self.pools.write().unwrap();
+        if pools.indexed != indexed {
+            // A concurrent update already indexed these pools.
+            return Ok(());
+        }
+        for pool in new_pools {
+            for pair in pool.token_pairs() {
+                pools.by_pair.entry(pair).or_default().insert(pool.address);
+            }
+            pools.by_address.insert(pool.address, pool);
+        }
+        pools.indexed = pool_count;
+
+        Ok(())
+    }
+
+    async fn fetch_pool_infos(&self, indices: Range<usize>) -> Result<Vec<PoolInfo>> {
+        let web3 = self.registry.raw_instance().web3();
+
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let addresses = indices
+            .map(|index| self.registry.pool_list(index.into()).batch_call(&mut batch))
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let addresses = future::try_join_all(addresses).await?;
+
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let pool_infos = addresses
+            .into_iter()
+            .map(|address| self.fetch_pool_info(address, &mut batch))
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(pool_infos)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    /// Fetches the static pool information for a pool, returning `None` for
+    /// unsupported pools.
+    fn fetch_pool_info(
+        &self,
+        address: H160,
+        batch: &mut Web3CallBatch,
+    ) -> BoxFuture<'static, Result<Option<PoolInfo>>> {
+        let registry = self.registry.clone();
+        let is_meta = self.registry.is_meta(address).batch_call(batch);
+        let n_coins = self.registry.get_n_coins(address).batch_call(batch);
+        let coins = self.registry.get_coins(address).batch_call(batch);
+        let decimals = self.registry.get_decimals(address).batch_call(batch);
+        let underlying_coins = self
+            .registry
+            .get_underlying_coins(address)
+            .batch_call(batch);
+        let underlying_decimals = self
+            .registry
+            .get_underlying_decimals(address)
+            .batch_call(batch);
+
+        async move {
+            let is_meta = is_meta.await?;
+            let [n_coins, n_underlying_coins] = n_coins.await?;
+            let coins = match coin_list(&coins.await?, &decimals.await?, n_coins) {
+                Ok(coins) => coins,
+                Err(err) => {
+                    tracing::warn!(pool = ?address, ?err, "skipping unsupported Curve pool");
+                    return Ok(None);
+                }
+            };
+            // The registry reports the underlying coins of metapools as the
+            // metapool coins followed by the base pool coins.
+            let underlying_coins = match coin_list(
+                &underlying_coins.await?,
+                &underlying_decimals.await?,
+                if is_meta { n_underlying_coins } else { n_coins },
+            ) {
+                Ok(underlying_coins) => underlying_coins,
+                Err(err) => {
+                    tracing::warn!(pool = ?address, ?err, "skipping unsupported Curve pool");
+                    return Ok(None);
+                }
+            };
+
+            if is_meta {
+                let lp_token = coins[coins.len() - 1].address;
+                let base_pool = registry.get_pool_from_lp_token(lp_token).call().await?;
+                let base_coins = &underlying_coins[coins.len() - 1..];
+                if base_pool.is_zero()
+                    || base_coins.len() < 2
+                    || underlying_coins[..coins.len() - 1] != coins[..coins.len() - 1]
+                {
+                    tracing::warn!(pool = ?address, "skipping unsupported Curve metapool");
+                    return Ok(None);
+                }
+
+                return Ok(Some(PoolInfo {
+                    address,
+                    base_pool: Some(BasePoolInfo {
+                        address: base_pool,
+                        coins: base_coins.to_vec(),
+                    }),
+                    coins,
+                    underlying_coins: None,
+                }));
+            }
+
+            let underlying_coins = if underlying_coins != coins {
+                Some(underlying_coins)
+            } else {
+                None
+            };
+
+            Ok(Some(PoolInfo {
+                address,
+                coins,
+                underlying_coins,
+                base_pool: None,
+            }))
+        }
+        .boxed()
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for PoolRegistry {
+    async fn run_maintenance(&self) -> Result<()> {
+        self.update().await
+    }
+}
+
+/// Converts the fixed size coin and decimal arrays returned by the registry
+/// into a list of coins.
+fn coin_list(addresses: &[H160], decimals: &[U256], count: U256) -> Result<Vec<Coin>> {
+    ensure!(
+        count >= 2.into() && count <= addresses.len().into(),
+        "invalid coin count {}",
+        count,
+    );
+    addresses
+        .iter()
+        .zip(decimals)
+        .take(count.as_usize())
+        .map(|(&address, &decimals)| {
+            ensure!(!address.is_zero(), "missing coin address");
+            ensure!(
+                decimals <= 18.into(),
+                "unsupported coin decimals {}",
+                decimals,
+            );
+            Ok(Coin {
+                address,
+                decimals: decimals.as_u32() as u8,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashset;
+
+    #[test]
+    fn converts_coin_lists() {
+        let addresses = [H160([1; 20]), H160([2; 20]), H160::zero(), H160::zero()];
+        let decimals = [18.into(), 6.into(), 0.into(), 0.into()];
+
+        assert_eq!(
+            coin_list(&addresses, &decimals, 2.into()).unwrap(),
+            vec![
+                Coin {
+                    address: H160([1; 20]),
+                    decimals: 18,
+                },
+                Coin {
+                    address: H160([2; 20]),
+                    decimals: 6,
+                },
+            ],
+        );
+        assert!(coin_list(&addresses, &decimals, 1.into()).is_err());
+        assert!(coin_list(&addresses, &decimals, 3.into()).is_err());
+        assert!(coin_list(&addresses, &decimals, 5.into()).is_err());
+        assert!(coin_list(&addresses, &[18.into(), 24.into()], 2.into()).is_err());
+    }
+
+    #[test]
+    fn token_pairs_include_underlying_coins() {
+        let coin = |byte: u8| Coin {
+            address: H160([byte; 20]),
+            decimals: 18,
+        };
+        let pool = PoolInfo {
+            address: H160([0xff; 20]),
+            coins: vec![coin(1), coin(2), coin(3)],
+            underlying_coins: Some(vec![coin(4), coin(5), coin(6)]),
+            base_pool: None,
+        };
+
+        let pair = |a: u8, b: u8| TokenPair::new(H160([a; 20]), H160([b; 20])).unwrap();
+        assert_eq!(
+            pool.token_pairs(),
+            hashset! {
+                pair(1, 2), pair(1, 3), pair(2, 3),
+                pair(4, 5), pair(4, 6), pair(5, 6),
+            },
+        );
+    }
+
+    #[test]
+    fn token_pairs_include_base_pool_coins() {
+        let coin = |byte: u8| Coin {
+            address: H160([byte; 20]),
+            decimals: 18,
+        };
+        // A metapool pairing a coin with the LP token of a base pool.
+        let pool = PoolInfo {
+            address: H160([0xff; 20]),
+            coins: vec![coin(1), coin(2)],
+            underlying_coins: None,
+            base_pool: Some(BasePoolInfo {
+                address: H160([0xee; 20]),
+                coins: vec![coin(3), coin(4), coin(5)],
+            }),
+        };
+
+        let pair = |a: u8, b: u8| TokenPair::new(H160([a; 20]), H160([b; 20])).unwrap();
+        assert_eq!(
+            pool.token_pairs(),
+            hashset! { pair(1, 2), pair(1, 3), pair(1, 4), pair(1, 5) },
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! ERC-4626 tokenized vault baseline liquidity source implementation.
+//!
+//! Configured vaults (such as sDAI) are treated as liquidity between their
+//! underlying asset and their share token: depositing or minting swaps assets
+//! for shares, while redeeming or withdrawing swaps shares for assets. Vault
+//! exchange rates are read on-chain with `convertToShares` and
+//! `convertToAssets` and cached per block.
+
+use crate::{
+    baseline_solver::BaselineSolvable,
+    current_block::CurrentBlockStream,
+    maintenance::Maintaining,
+    recent_block_cache::{
+        Block, CacheConfig, CacheFetching, CacheKey, NoopCacheMetrics, RecentBlockCache,
+    },
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::IERC4626;
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use std::collections::{HashMap, HashSet};
+
+const VAULT_GAS_COST: usize = 120_000;
+
+lazy_static::lazy_static! {
+    /// The amount of assets and shares used for reading vault exchange rates.
+    static ref RATE_PRECISION: U256 = U256::exp10(27);
+}
+
+/// Returns the default vaults to use for a chain.
+pub fn default_vaults(chain_id: u64) -> Vec<H160> {
+    match chain_id {
+        // sDAI
+        1 => vec![addr!("83F20F44975D03b1b09e64809B757c47f942BEeA")],
+        _ => Vec::new(),
+    }
+}
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The current exchange rates of an ERC-4626 vault.
+///
+/// Amounts are computed from the exchange rates rounding in favour of the
+/// vault, so they may differ from the vault's `preview*` methods by a few wei.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    /// The vault address, which is also its share token.
+    pub vault: H160,
+    pub asset: H160,
+    /// The amount of assets for `RATE_PRECISION` shares.
+    pub assets_per_share: U256,
+    /// The amount of shares for `RATE_PRECISION` assets.
+    pub shares_per_asset: U256,
+}
+
+impl Pool {
+    /// Converts an amount with the specified rate, rounding down.
+    fn convert_down(amount: U256, rate: U256) -> Option<U256> {
+        Some(amount.checked_mul(rate)? / *RATE_PRECISION)
+    }
+
+    /// Converts an amount with the specified rate, rounding up.
+    ///
+    /// Since the rate itself was rounded down when it was read, this
+    /// additionally rounds the rate up.
+    fn convert_up(amount: U256, rate: U256) -> Option<U256> {
+        let (quotient, remainder) = amount
+            .checked_mul(rate.checked_add(U256::one())?)?
+            .div_mod(*RATE_PRECISION);
+        if remainder.is_zero() {
+            Some(quotient)
+        } else {
+            quotient.checked_add(U256::one())
+        }
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        if in_token == self.asset && out_token == self.vault {
+            Self::convert_down(in_amount, self.shares_per_asset)
+        } else if in_token == self.vault && out_token == self.asset {
+            Self::convert_down(in_amount, self.assets_per_share)
+        } else {
+            None
+        }
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        if in_token == self.asset && out_token == self.vault {
+            Self::convert_up(out_amount, self.assets_per_share)
+        } else if in_token == self.vault && out_token == self.asset {
+            Self::convert_up(out_amount, self.shares_per_asset)
+        } else {
+            None
+        }
+    }
+
+    fn gas_cost(&self) -> usize {
+        VAULT_GAS_COST
+    }
+}
+
+/// Cached pool fetcher for a set of ERC-4626 vaults.
+pub struct Erc4626PoolFetcher {
+    vaults_by_pair: HashMap<TokenPair, H160>,
+    cache: RecentBlockCache<H160, Pool, VaultReader, NoopCacheMetrics>,
+}
+
+impl Erc4626PoolFetcher {
+    /// Creates a new pool fetcher for the specified vaults, reading their
+    /// underlying assets.
+    pub async fn new(
+        web3: &Web3,
+        vaults: &[H160],
+        config: CacheConfig,
+        block_stream: CurrentBlockStream,
+    ) -> Result<Self> {
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let assets = vaults
+            .iter()
+            .map(|vault| IERC4626::at(web3, *vault).asset().batch_call(&mut batch))
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let assets = vaults
+            .iter()
+            .copied()
+            .zip(future::try_join_all(assets).await?)
+            .collect::<HashMap<_, _>>();
+
+        let vaults_by_pair = assets
+            .iter()
+            .filter_map(|(&vault, &asset)| Some((TokenPair::new(vault, asset)?, vault)))
+            .collect();
+        let reader = VaultReader {
+            web3: web3.clone(),
+            assets,
+        };
+
+        Ok(Self {
+            vaults_by_pair,
+            cache: RecentBlockCache::new(
+                "erc4626",
+                config,
+                reader,
+                block_stream,
+                NoopCacheMetrics,
+            )?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for Erc4626PoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let vaults = token_pairs
+            .iter()
+            .filter_map(|pair| self.vaults_by_pair.get(pair))
+            .copied()
+            .collect::<Vec<_>>();
+        if vaults.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.cache.fetch(vaults, at_block).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for Erc4626PoolFetcher {
+    async fn run_maintenance(&self) -> Result<()> {
+        self.cache.update_cache().await
+    }
+}
+
+impl CacheKey<Pool> for H160 {
+    fn first_ord() -> Self {
+        H160::zero()
+    }
+
+    fn for_value(pool: &Pool) -> Self {
+        pool.vault
+    }
+}
+
+/// On-chain vault exchange rate reader used for filling the cache.
+struct VaultReader {
+    web3: Web3,
+    /// Underlying asset by vault address.
+    assets: HashMap<H160, H160>,
+}
+
+#[async_trait::async_trait]
+impl CacheFetching<H160, Pool> for VaultReader {
+    async fn fetch_values(&self, vaults: HashSet<H160>, at_block: Block) -> Result<Vec<Pool>> {
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let futures = vaults
+            .into_iter()
+            .filter_map(|vault| Some((vault, *self.assets.get(&vault)?)))
+            .map(|(vault, asset)| {
+                let contract = IERC4626::at(&self.web3, vault);
+                let assets_per_share = contract
+                    .convert_to_assets(*RATE_PRECISION)
+                    .block(block)
+                    .batch_call(&mut batch);
+                let shares_per_asset = contract
+                    .convert_to_shares(*RATE_PRECISION)
+                    .block(block)
+                    .batch_call(&mut batch);
+                async move {
+                    handle_results(FetchedPool {
+                        vault,
+                        asset,
+                        assets_per_share: assets_per_share.await,
+                        shares_per_asset: shares_per_asset.await,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        future::join_all(futures)
+            .await
+            .into_iter()
+            .filter_map(|pool| pool.transpose())
+            .collect()
+    }
+}
+
+struct FetchedPool {
+    vault: H160,
+    asset: H160,
+    assets_per_share: Result<U256, MethodError>,
+    shares_per_asset: Result<U256, MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let assets_per_share = handle_contract_error(fetched_pool.assets_per_share)?;
+    let shares_per_asset = handle_contract_error(fetched_pool.shares_per_asset)?;
+
+    Ok(match (assets_per_share, shares_per_asset) {
+        (Some(assets_per_share), Some(shares_per_asset)) => Some(Pool {
+            vault: fetched_pool.vault,
+            asset: fetched_pool.asset,
+            assets_per_share,
+            shares_per_asset,
+        }),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn sdai() -> Pool {
+        // 1 sDAI is worth 1.05 DAI.
+        Pool {
+            vault: H160([1; 20]),
+            asset: H160([2; 20]),
+            assets_per_share: U256::from(105) * U256::exp10(25),
+            shares_per_asset: U256::from_dec_str("952380952380952380952380952").unwrap(),
+        }
+    }
+
+    #[test]
+    fn deposits_and_redeems() {
+        let pool = sdai();
+        let (sdai, dai) = (pool.vault, pool.asset);
+
+        // Deposit
+        assert_eq!(
+            pool.get_amount_out(sdai, (U256::from(105) * U256::exp10(18), dai)),
+            Some(U256::from_dec_str("99999999999999999999").unwrap()),
+        );
+        // Redeem
+        assert_eq!(
+            pool.get_amount_out(dai, (U256::from(100) * U256::exp10(18), sdai)),
+            Some(U256::from(105) * U256::exp10(18)),
+        );
+    }
+
+    #[test]
+    fn mints_and_withdraws_rounding_up() {
+        let pool = sdai();
+        let (sdai, dai) = (pool.vault, pool.asset);
+
+        // Mint
+        assert_eq!(
+            pool.get_amount_in(dai, (U256::from(100) * U256::exp10(18), sdai)),
+            Some(U256::from(105) * U256::exp10(18) + 1),
+        );
+        // Withdraw
+        let shares = pool
+            .get_amount_in(sdai, (U256::from(105) * U256::exp10(18), dai))
+            .unwrap();
+        assert!(shares >= U256::from(100) * U256::exp10(18));
+        assert!(
+            pool.get_amount_out(dai, (shares, sdai)).unwrap() >= U256::from(105) * U256::exp10(18)
+        );
+    }
+
+    #[test]
+    fn unknown_tokens() {
+        let pool = sdai();
+        assert_eq!(
+            pool.get_amount_out(H160([3; 20]), (1.into(), pool.asset)),
+            None
+        );
+        assert_eq!(pool.get_amount_in(pool.vault, (1.into(), pool.vault)), None);
+    }
+
+    #[test]
+    fn default_vaults_for_chains() {
+        assert_eq!(default_vaults(1).len(), 1);
+        assert!(default_vaults(100).is_empty());
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched_pool = FetchedPool {
+            vault: H160([1; 20]),
+            asset: H160([2; 20]),
+            assets_per_share: Err(ethcontract_error::testing_node_error()),
+            shares_per_asset: Ok(1.into()),
+        };
+        assert!(handle_results(fetched_pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched_pool = FetchedPool {
+            vault: H160([1; 20]),
+            asset: H160([2; 20]),
+            assets_per_share: Ok(1.into()),
+            shares_per_asset: Err(ethcontract_error::testing_contract_error()),
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_none());
+    }
+}
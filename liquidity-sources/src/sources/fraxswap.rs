@@ -0,0 +1,39 @@
+//! Fraxswap baseline liquidity source implementation.
+//!
+//! Fraxswap is a Uniswap V2 fork with an embedded TWAMM (time-weighted
+//! average market maker). Long-term orders are executed against the pair
+//! reserves in virtual orders on the next interaction with the pair, so swap
+//! quotes need to be based on the reserves after executing these orders. Each
+//! pair also has its own swap fee.
+
+mod reader;
+
+use crate::{
+    sources::uniswap_v2::{
+        pair_provider::PairProvider,
+        pool_fetching::{PoolFetcher, PoolFetching, PoolReading as _},
+    },
+    Web3,
+};
+use anyhow::Result;
+use ethcontract::H160;
+use std::sync::Arc;
+
+/// Creates the pair provider and pool fetcher for the Fraxswap factory at the
+/// specified address, whose pairs are deployed with the specified init code
+/// digest.
+pub fn get_liquidity_source(
+    web3: &Web3,
+    factory: H160,
+    init_code_digest: [u8; 32],
+) -> (PairProvider, Arc<dyn PoolFetching>) {
+    let provider = PairProvider {
+        factory,
+        init_code_digest,
+    };
+    let fetcher = PoolFetcher {
+        pool_reader: reader::FraxswapPoolReader::for_pair_provider(provider.clone(), web3.clone()),
+        web3: web3.clone(),
+    };
+    (provider, Arc::new(fetcher))
+}
@@ -0,0 +1,178 @@
+//! A pool state reading implementation specific to Fraxswap.
+
+use crate::token_pair::TokenPair;
+use crate::{
+    sources::uniswap_v2::{
+        pair_provider::PairProvider,
+        pool_fetching::{self, Pool, PoolReading},
+    },
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::IFraxswapPair;
+use ethcontract::{errors::MethodError, BlockId, U256};
+use futures::{future::BoxFuture, FutureExt as _};
+use num::rational::Ratio;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A specialized Uniswap-like pool reader for Fraxswap pairs.
+///
+/// Instead of the stored reserves, this reads the reserves after executing
+/// the pending long-term orders up to the current time, along with the
+/// dynamic fee of the pair.
+pub struct FraxswapPoolReader {
+    pair_provider: PairProvider,
+    web3: Web3,
+}
+
+/// The base amount for fees representing 100%.
+const FEE_BASE: u32 = 10_000;
+
+/// The result of the pair `getReserveAfterTwamm` method: the reserves, the
+/// timestamp of the last executed virtual order, the reserves of long-term
+/// orders, and the fee multiplier in `FEE_BASE` units.
+type ReservesAfterTwamm = (u128, u128, U256, u128, u128, U256);
+
+impl PoolReading for FraxswapPoolReader {
+    fn for_pair_provider(pair_provider: PairProvider, web3: Web3) -> Self {
+        Self {
+            pair_provider,
+            web3,
+        }
+    }
+
+    fn read_state(
+        &self,
+        pair: TokenPair,
+        batch: &mut Web3CallBatch,
+        block: BlockId,
+    ) -> BoxFuture<'_, Result<Option<Pool>>> {
+        let pair_address = self.pair_provider.pair_address(&pair);
+        let pair_contract = IFraxswapPair::at(&self.web3, pair_address);
+
+        // Virtual orders are executed up to the timestamp of the block that
+        // interacts with the pair, which is approximated with the current
+        // time.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let reserves = pair_contract
+            .get_reserve_after_twamm(now.into())
+            .block(block)
+            .batch_call(batch);
+
+        async move { handle_results(pair, reserves.await) }.boxed()
+    }
+}
+
+fn handle_results(
+    pair: TokenPair,
+    reserves: Result<ReservesAfterTwamm, MethodError>,
+) -> Result<Option<Pool>> {
+    let reserves = pool_fetching::handle_contract_error(reserves)?;
+    Ok(reserves.and_then(|(reserve0, reserve1, _, _, _, fee)| {
+        // The pair stores the share of the input amount that is not taken as
+        // fee.
+        let fee = FEE_BASE.checked_sub(u32::try_from(fee).ok()?)?;
+        Some(Pool {
+            tokens: pair,
+            reserves: (reserve0, reserve1),
+            fee: Ratio::new(fee, FEE_BASE),
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ethcontract_error,
+        recent_block_cache::Block,
+        sources::uniswap_v2::pool_fetching::{PoolFetcher, PoolFetching as _},
+        transport::create_env_test_transport,
+    };
+    use ethcontract::{H160, H256};
+    use maplit::hashset;
+
+    #[test]
+    fn reads_reserves_and_fee() {
+        let tokens = TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap();
+        assert_eq!(
+            handle_results(tokens, Ok((13, 37, 42.into(), 1, 2, 9970.into())))
+                .unwrap()
+                .unwrap(),
+            Pool {
+                tokens,
+                reserves: (13, 37),
+                fee: Ratio::new(3, 1000),
+            }
+        );
+    }
+
+    #[test]
+    fn skips_invalid_fees() {
+        let tokens = TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap();
+        assert!(
+            handle_results(tokens, Ok((13, 37, 42.into(), 1, 2, 10_001.into())))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let tokens = TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap();
+        assert!(handle_results(tokens, Err(ethcontract_error::testing_node_error())).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let tokens = TokenPair::new(H160([1; 20]), H160([2; 20])).unwrap();
+        assert!(
+            handle_results(tokens, Err(ethcontract_error::testing_contract_error()))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn fetch_fraxswap_pool() {
+        let transport = create_env_test_transport();
+        let web3 = Web3::new(transport);
+
+        // Set `FRAXSWAP_FACTORY` and `FRAXSWAP_INIT_CODE_DIGEST` to the
+        // deployment to test.
+        let factory = std::env::var("FRAXSWAP_FACTORY").unwrap().parse().unwrap();
+        let init_code_digest = std::env::var("FRAXSWAP_INIT_CODE_DIGEST")
+            .unwrap()
+            .parse::<H256>()
+            .unwrap()
+            .0;
+        let pair_provider = PairProvider {
+            factory,
+            init_code_digest,
+        };
+        let pool_fetcher = PoolFetcher {
+            pool_reader: FraxswapPoolReader::for_pair_provider(pair_provider, web3.clone()),
+            web3,
+        };
+
+        // FRAX and FXS.
+        let pools = pool_fetcher
+            .fetch(
+                hashset! {
+                    TokenPair::new(
+                        addr!("853d955acef822db058eb8505911ed77f175b99e"),
+                        addr!("3432b6a60d23ca0dfca7761b7ab56459d9c964d0"),
+                    )
+                    .unwrap(),
+                },
+                Block::Recent,
+            )
+            .await
+            .unwrap();
+        println!("FRAX <> FXS pool: {:#?}", pools);
+    }
+}
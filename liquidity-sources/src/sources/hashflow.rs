@@ -0,0 +1,464 @@
+//! Hashflow RFQ liquidity source implementation.
+//!
+//! Firm quotes are requested from the Hashflow taker API. Each quote is issued
+//! by a market maker for one of its Hashflow pools, which are deployed per
+//! chain, so quotes are only accepted if they were issued for the configured
+//! chain.
+
+use super::rfq::{QuoteAmount, QuoteRequest, RfqQuote, RfqQuoting};
+use crate::u256_decimal::{self, DecimalU256};
+use anyhow::{anyhow, Context as _, Result};
+use ethcontract::{H160, U256};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::serde_as;
+use std::time::Duration;
+use web3::types::Bytes;
+
+lazy_static::lazy_static! {
+    pub static ref DEFAULT_HASHFLOW_API_URL: Url =
+        Url::parse("https://api.hashflow.com/taker/v3/rfq")
+            .expect("invalid default Hashflow API URL");
+}
+
+/// Configuration for the Hashflow RFQ source.
+#[derive(Clone, Debug)]
+pub struct HashflowConfig {
+    pub api_url: Url,
+    pub api_key: String,
+    /// The source name identifying us to the Hashflow API.
+    pub source: String,
+    pub chain_id: u64,
+    /// The minimum amount of time a quote needs to remain valid for in order
+    /// to be used. This should cover the time it takes to settle the quote.
+    pub min_validity: Duration,
+}
+
+/// Hashflow RFQ quoter.
+pub struct HashflowRfq {
+    client: Client,
+    config: HashflowConfig,
+}
+
+impl HashflowRfq {
+    pub fn new(client: Client, config: HashflowConfig) -> Self {
+        Self { client, config }
+    }
+
+    async fn request_quotes(&self, request: &QuoteRequest) -> Result<Vec<Quote>> {
+        let chain = Chain::evm(self.config.chain_id);
+        let (base_token_amount, quote_token_amount) = match request.amount {
+            QuoteAmount::Sell(amount) => (Some(amount), None),
+            QuoteAmount::Buy(amount) => (None, Some(amount)),
+        };
+        let body = RfqRequest {
+            base_chain: chain,
+            quote_chain: chain,
+            source: &self.config.source,
+            rfqs: vec![Rfq {
+                base_token: request.sell_token,
+                quote_token: request.buy_token,
+                base_token_amount,
+                quote_token_amount,
+                trader: request.trader,
+            }],
+        };
+
+        let response = self
+            .client
+            .post(self.config.api_url.clone())
+            .header("Authorization", &self.config.api_key)
+            .json(&body)
+            .send()
+            .await?
+            .json::<RfqResponse>()
+            .await
+            .context("invalid Hashflow RFQ response")?;
+        match response {
+            RfqResponse::Success { quotes } => Ok(quotes),
+            RfqResponse::Fail { error } => Err(anyhow!(
+                "Hashflow RFQ failed with code {}: {}",
+                error.code,
+                error.message
+            )),
+        }
+    }
+
+    /// Converts a Hashflow quote into an RFQ quote, returning `None` for quotes
+    /// that are malformed, that do not match the request, that were issued for
+    /// another chain or that are about to expire.
+    fn convert_quote(&self, request: &QuoteRequest, quote: Quote) -> Result<Option<RfqQuote>> {
+        let (raw_data, signature) = match (quote.quote_data, quote.signature) {
+            (Some(raw_data), Some(signature)) => (raw_data, signature),
+            _ => return Ok(None),
+        };
+        let data = match serde_json::from_value::<QuoteData>(raw_data.clone()) {
+            Ok(data) => data,
+            Err(err) => {
+                tracing::warn!(?err, "discarding invalid Hashflow quote data");
+                return Ok(None);
+            }
+        };
+
+        let chain = Chain::evm(self.config.chain_id);
+        if data.base_chain != chain || data.quote_chain != chain {
+            tracing::warn!(pool = ?data.pool, "discarding Hashflow quote for a different chain");
+            return Ok(None);
+        }
+        if data.base_token != request.sell_token || data.quote_token != request.buy_token {
+            return Ok(None);
+        }
+        let matches_amount = match request.amount {
+            QuoteAmount::Sell(amount) => data.base_token_amount == amount,
+            QuoteAmount::Buy(amount) => data.quote_token_amount == amount,
+        };
+        if !matches_amount {
+            return Ok(None);
+        }
+
+        let quote = RfqQuote {
+            maker: data.pool,
+            sell_token: data.base_token,
+            buy_token: data.quote_token,
+            sell_amount: data.base_token_amount,
+            buy_amount: data.quote_token_amount,
+            partially_fillable: true,
+            valid_to: data.quote_expiry,
+            data: serde_json::to_vec(&raw_data)?,
+            signature: signature.0,
+        };
+        if !quote.is_valid_for(self.config.min_validity) {
+            tracing::debug!(pool = ?quote.maker, "discarding expiring Hashflow quote");
+            return Ok(None);
+        }
+
+        Ok(Some(quote))
+    }
+
+    /// Returns the best of the quotes that can be converted into RFQ quotes.
+    fn best_quote(&self, request: &QuoteRequest, quotes: Vec<Quote>) -> Result<Option<RfqQuote>> {
+        let mut best = None;
+        for quote in quotes {
+            let quote = match self.convert_quote(request, quote)? {
+                Some(quote) => quote,
+                None => continue,
+            };
+            best = match best {
+                Some(best) if !is_better(&quote, &best, request.amount) => Some(best),
+                _ => Some(quote),
+            };
+        }
+
+        Ok(best)
+    }
+}
+
+#[async_trait::async_trait]
+impl RfqQuoting for HashflowRfq {
+    async fn quote(&self, request: &QuoteRequest) -> Result<Option<RfqQuote>> {
+        let quotes = self.request_quotes(request).await?;
+        self.best_quote(request, quotes)
+    }
+}
+
+/// Returns `true` if quote `a` is better than quote `b`.
+fn is_better(a: &RfqQuote, b: &RfqQuote, amount: QuoteAmount) -> bool {
+    match amount {
+        QuoteAmount::Sell(_) => a.buy_amount > b.buy_amount,
+        QuoteAmount::Buy(_) => a.sell_amount < b.sell_amount,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Chain {
+    chain_type: ChainType,
+    chain_id: u64,
+}
+
+impl Chain {
+    fn evm(chain_id: u64) -> Self {
+        Self {
+            chain_type: ChainType::Evm,
+            chain_id,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ChainType {
+    Evm,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RfqRequest<'a> {
+    base_chain: Chain,
+    quote_chain: Chain,
+    source: &'a str,
+    rfqs: Vec<Rfq>,
+}
+
+#[serde_as]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Rfq {
+    base_token: H160,
+    quote_token: H160,
+    #[serde_as(as = "Option<DecimalU256>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base_token_amount: Option<U256>,
+    #[serde_as(as = "Option<DecimalU256>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quote_token_amount: Option<U256>,
+    trader: H160,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum RfqResponse {
+    Success { quotes: Vec<Quote> },
+    Fail { error: ApiError },
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Quote {
+    quote_data: Option<Value>,
+    signature: Option<Bytes>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuoteData {
+    base_chain: Chain,
+    quote_chain: Chain,
+    base_token: H160,
+    quote_token: H160,
+    #[serde(with = "u256_decimal")]
+    base_token_amount: U256,
+    #[serde(with = "u256_decimal")]
+    quote_token_amount: U256,
+    pool: H160,
+    quote_expiry: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn hashflow() -> HashflowRfq {
+        HashflowRfq::new(
+            Client::new(),
+            HashflowConfig {
+                api_url: DEFAULT_HASHFLOW_API_URL.clone(),
+                api_key: String::new(),
+                source: "cowswap".to_owned(),
+                chain_id: 1,
+                min_validity: Duration::from_secs(10),
+            },
+        )
+    }
+
+    fn request() -> QuoteRequest {
+        QuoteRequest {
+            sell_token: addr!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+            buy_token: addr!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+            amount: QuoteAmount::Sell(1_000_000_000.into()),
+            trader: H160([0x42; 20]),
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn quote(chain_id: u64, quote_token_amount: &str, quote_expiry: u64) -> Quote {
+        serde_json::from_value(json!({
+            "quoteData": {
+                "baseChain": { "chainType": "evm", "chainId": chain_id },
+                "quoteChain": { "chainType": "evm", "chainId": chain_id },
+                "baseToken": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+                "quoteToken": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+                "baseTokenAmount": "1000000000",
+                "quoteTokenAmount": quote_token_amount,
+                "pool": "0x1111111111111111111111111111111111111111",
+                "quoteExpiry": quote_expiry,
+                "nonce": 1,
+                "txid": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            },
+            "signature": "0x0102",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn deserialize_responses() {
+        let success = serde_json::from_value::<RfqResponse>(json!({
+            "status": "success",
+            "rfqId": "rfq",
+            "quotes": [
+                { "quoteData": null, "signature": null },
+            ],
+        }))
+        .unwrap();
+        assert!(matches!(success, RfqResponse::Success { quotes } if quotes.len() == 1));
+
+        let fail = serde_json::from_value::<RfqResponse>(json!({
+            "status": "fail",
+            "error": { "code": "InvalidRequest", "message": "bad token" },
+        }))
+        .unwrap();
+        assert!(matches!(fail, RfqResponse::Fail { error } if error.code == "InvalidRequest"));
+    }
+
+    #[test]
+    fn serialize_request() {
+        let rfq = Rfq {
+            base_token: H160([1; 20]),
+            quote_token: H160([2; 20]),
+            base_token_amount: None,
+            quote_token_amount: Some(42.into()),
+            trader: H160([3; 20]),
+        };
+        assert_eq!(
+            serde_json::to_value(&rfq).unwrap(),
+            json!({
+                "baseToken": "0x0101010101010101010101010101010101010101",
+                "quoteToken": "0x0202020202020202020202020202020202020202",
+                "quoteTokenAmount": "42",
+                "trader": "0x0303030303030303030303030303030303030303",
+            }),
+        );
+    }
+
+    #[test]
+    fn converts_quotes() {
+        let hashflow = hashflow();
+        let request = request();
+
+        let quote = hashflow
+            .convert_quote(&request, quote(1, "500000000000000000", now() + 60))
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.maker, H160([0x11; 20]));
+        assert_eq!(quote.sell_amount, 1_000_000_000.into());
+        assert_eq!(quote.buy_amount, U256::from(5) * U256::exp10(17));
+        assert_eq!(quote.signature, vec![1, 2]);
+    }
+
+    #[test]
+    fn discards_expiring_quotes() {
+        let hashflow = hashflow();
+        assert!(hashflow
+            .convert_quote(&request(), quote(1, "1", now() + 5))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn discards_quotes_for_other_chains() {
+        let hashflow = hashflow();
+        assert!(hashflow
+            .convert_quote(&request(), quote(137, "1", now() + 60))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn discards_invalid_quotes() {
+        let hashflow = hashflow();
+        let invalid = serde_json::from_value::<Quote>(json!({
+            "quoteData": { "pool": "not an address" },
+            "signature": "0x0102",
+        }))
+        .unwrap();
+        let valid = quote(1, "500000000000000000", now() + 60);
+
+        let best = hashflow
+            .best_quote(&request(), vec![invalid, valid])
+            .unwrap()
+            .unwrap();
+        assert_eq!(best.buy_amount, U256::from(5) * U256::exp10(17));
+    }
+
+    #[test]
+    fn discards_mismatched_quotes() {
+        let hashflow = hashflow();
+        let request = QuoteRequest {
+            amount: QuoteAmount::Sell(1.into()),
+            ..request()
+        };
+        assert!(hashflow
+            .convert_quote(&request, quote(1, "1", now() + 60))
+            .unwrap()
+            .is_none());
+        assert!(hashflow
+            .convert_quote(
+                &request,
+                Quote {
+                    quote_data: None,
+                    signature: None,
+                }
+            )
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn compares_quotes() {
+        let quote = |sell_amount: u64, buy_amount: u64| RfqQuote {
+            maker: H160::zero(),
+            sell_token: H160([1; 20]),
+            buy_token: H160([2; 20]),
+            sell_amount: sell_amount.into(),
+            buy_amount: buy_amount.into(),
+            partially_fillable: true,
+            valid_to: 0,
+            data: Vec::new(),
+            signature: Vec::new(),
+        };
+        assert!(is_better(
+            &quote(10, 11),
+            &quote(10, 10),
+            QuoteAmount::Sell(10.into())
+        ));
+        assert!(!is_better(
+            &quote(10, 10),
+            &quote(10, 10),
+            QuoteAmount::Sell(10.into())
+        ));
+        assert!(is_better(
+            &quote(9, 10),
+            &quote(10, 10),
+            QuoteAmount::Buy(10.into())
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn hashflow_quote() {
+        let hashflow = HashflowRfq::new(
+            Client::new(),
+            HashflowConfig {
+                api_key: std::env::var("HASHFLOW_API_KEY").unwrap(),
+                ..hashflow().config
+            },
+        );
+        let quote = hashflow.quote(&request()).await.unwrap();
+        println!("{:#?}", quote);
+    }
+}
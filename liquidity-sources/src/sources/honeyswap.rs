@@ -1,4 +1,8 @@
 //! Honeyswap baseline liquidity source implementation.
+//!
+//! Honeyswap is a Uniswap V2 fork deployed on Gnosis Chain. Its pairs charge
+//! the same 0.3% fee as Uniswap V2 (of which a part goes to the protocol
+//! instead of liquidity providers), so the default pool reader is used.
 
 use super::uniswap_v2::macros::impl_uniswap_like_liquidity;
 
@@ -10,8 +14,12 @@ impl_uniswap_like_liquidity! {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::token_pair::TokenPair;
+    use crate::{
+        recent_block_cache::Block, token_pair::TokenPair, transport::create_env_test_transport,
+        Web3,
+    };
     use ethcontract_mock::Mock;
+    use maplit::hashset;
 
     #[tokio::test]
     async fn test_create2_xdai() {
@@ -27,4 +35,31 @@ mod tests {
             addr!("4505b262dc053998c10685dc5f9098af8ae5c8ad")
         );
     }
+
+    #[tokio::test]
+    #[ignore]
+    async fn fetch_honeyswap_pool() {
+        let transport = create_env_test_transport();
+        let web3 = Web3::new(transport);
+
+        let (_, pool_fetcher) = get_liquidity_source(&web3).await.unwrap();
+        let pool = pool_fetcher
+            .fetch(
+                hashset! {
+                    TokenPair::new(
+                        addr!("71850b7e9ee3f13ab46d67167341e4bdc905eef9"),
+                        addr!("e91d153e0b41518a2ce8dd3d7944fa863463a97d"),
+                    )
+                    .unwrap(),
+                },
+                Block::Recent,
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+
+        println!("HNY <> wxDAI pool: {:#?}", pool);
+    }
 }
@@ -0,0 +1,6 @@
+//! Integral SIZE baseline liquidity source implementation.
+//!
+//! Integral pairs are not traded against a bonding curve. Instead, the Integral
+//! relayer executes swaps at the TWAP oracle price of the pair, minus a fee,
+//! for amounts within token specific limits.
+pub mod pool_fetching;
@@ -0,0 +1,412 @@
+//! Module for fetching Integral pairs and their relayer execution parameters.
+
+use crate::{
+    baseline_solver::BaselineSolvable,
+    fixed_point::{div, Rounding},
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::{IntegralTwapFactory, IntegralTwapRelayer};
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+const INTEGRAL_SWAP_GAS_COST: usize = 250_000;
+
+lazy_static::lazy_static! {
+    /// The precision of relayer prices and fees.
+    static ref ONE: U256 = U256::exp10(18);
+}
+
+/// The result of the relayer `getPoolState` method: the price, the swap fee
+/// and the minimum and maximum amounts of each token.
+type PoolState = (U256, U256, U256, U256, U256, U256);
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The state of an Integral pair as executed by the relayer.
+///
+/// There is no bonding curve, so the price does not depend on the swapped
+/// amount. Instead, the relayer only executes swaps for which the bought
+/// amount is within the limits of the bought token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub address: H160,
+    /// The pair tokens X and Y, where X has the smaller address.
+    pub tokens: (TokenState, TokenState),
+    /// The oracle price of token X in token Y with 18 decimals, independent of
+    /// the token decimals.
+    pub price: U256,
+    /// The swap fee with 18 decimals.
+    pub fee: U256,
+}
+
+/// The parameters of a single token of a pair.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenState {
+    pub address: H160,
+    pub decimals: u8,
+    /// The minimum amount of the token that can be bought in a swap.
+    pub limit_min: U256,
+    /// The maximum amount of the token that can be bought in a swap. This
+    /// depends on the relayer balance of the token.
+    pub limit_max: U256,
+}
+
+impl TokenState {
+    fn can_buy(&self, amount: U256) -> bool {
+        self.limit_min <= amount && amount <= self.limit_max
+    }
+}
+
+impl Pool {
+    /// Returns the output token state and whether or not the swap is from
+    /// token X to token Y.
+    fn relative(&self, in_token: H160, out_token: H160) -> Option<(&TokenState, bool)> {
+        if (in_token, out_token) == (self.tokens.0.address, self.tokens.1.address) {
+            Some((&self.tokens.1, true))
+        } else if (out_token, in_token) == (self.tokens.0.address, self.tokens.1.address) {
+            Some((&self.tokens.0, false))
+        } else {
+            None
+        }
+    }
+
+    /// Converts an amount at the oracle price, the same way as the relayer.
+    fn convert(&self, amount: U256, x_to_y: bool, round_up: bool) -> Option<U256> {
+        let x_scale = U256::exp10(self.tokens.0.decimals.into());
+        let y_scale = U256::exp10(self.tokens.1.decimals.into());
+        let (numerator, denominator) = if x_to_y {
+            (self.price.checked_mul(y_scale)?, ONE.checked_mul(x_scale)?)
+        } else {
+            (ONE.checked_mul(x_scale)?, self.price.checked_mul(y_scale)?)
+        };
+        let amount = amount.checked_mul(numerator)?;
+        if round_up {
+            div(amount, denominator, Rounding::Up)
+        } else {
+            amount.checked_div(denominator)
+        }
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let (out_state, x_to_y) = self.relative(in_token, out_token)?;
+        let fee = in_amount.checked_mul(self.fee)? / *ONE;
+        let out_amount = self.convert(in_amount.checked_sub(fee)?, x_to_y, false)?;
+        if !out_state.can_buy(out_amount) {
+            return None;
+        }
+        Some(out_amount)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let (out_state, x_to_y) = self.relative(in_token, out_token)?;
+        if !out_state.can_buy(out_amount) {
+            return None;
+        }
+        let in_amount = self.convert(out_amount, !x_to_y, true)?;
+        div(
+            in_amount.checked_mul(*ONE)?,
+            ONE.checked_sub(self.fee)?,
+            Rounding::Up,
+        )
+    }
+
+    fn gas_cost(&self) -> usize {
+        INTEGRAL_SWAP_GAS_COST
+    }
+}
+
+/// Pool fetcher for the pairs executed by an Integral relayer.
+pub struct IntegralPoolFetcher {
+    web3: Web3,
+    relayer: IntegralTwapRelayer,
+    factory: IntegralTwapFactory,
+    /// Cache of existing pair addresses. Pairs never change address once
+    /// created, so entries never expire.
+    pairs: Mutex<HashMap<TokenPair, H160>>,
+}
+
+impl IntegralPoolFetcher {
+    /// Creates a new pool fetcher for the relayer at the specified address.
+    pub async fn new(web3: &Web3, relayer: H160) -> Result<Self> {
+        let relayer = IntegralTwapRelayer::at(web3, relayer);
+        let factory = IntegralTwapFactory::at(web3, relayer.factory().call().await?);
+        Ok(Self {
+            web3: web3.clone(),
+            relayer,
+            factory,
+            pairs: Default::default(),
+        })
+    }
+
+    /// Returns the addresses of the existing pairs for the specified token
+    /// pairs.
+    async fn pair_addresses(
+        &self,
+        token_pairs: HashSet<TokenPair>,
+    ) -> Result<Vec<(H160, TokenPair)>> {
+        let mut addresses = Vec::new();
+        let mut missing = Vec::new();
+        {
+            let pairs = self.pairs.lock().unwrap();
+            for pair in token_pairs {
+                match pairs.get(&pair) {
+                    Some(address) => addresses.push((*address, pair)),
+                    None => missing.push(pair),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(addresses);
+        }
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let futures = missing
+            .iter()
+            .map(|pair| {
+                let (token_x, token_y) = pair.get();
+                self.factory
+                    .get_pair(token_x, token_y)
+                    .batch_call(&mut batch)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let results = future::try_join_all(futures).await?;
+
+        // Only cache pairs that exist, as they may be created later.
+        let mut pairs = self.pairs.lock().unwrap();
+        for (pair, address) in missing.into_iter().zip(results) {
+            if !address.is_zero() {
+                pairs.insert(pair, address);
+                addresses.push((address, pair));
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for IntegralPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let pairs = self.pair_addresses(token_pairs).await?;
+        if pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let futures = pairs
+            .into_iter()
+            .map(|(address, pair)| {
+                let tokens = pair.get();
+                let enabled = self
+                    .relayer
+                    .is_pair_enabled(address)
+                    .block(block)
+                    .batch_call(&mut batch);
+                let price = self
+                    .relayer
+                    .get_price_by_pair_address(address, false)
+                    .block(block)
+                    .batch_call(&mut batch);
+                let state = self
+                    .relayer
+                    .get_pool_state(tokens.0, tokens.1)
+                    .block(block)
+                    .batch_call(&mut batch);
+                async move {
+                    handle_results(FetchedPool {
+                        address,
+                        tokens,
+                        enabled: enabled.await,
+                        price: price.await,
+                        state: state.await,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        future::join_all(futures)
+            .await
+            .into_iter()
+            .filter_map(|pool| pool.transpose())
+            .collect()
+    }
+}
+
+struct FetchedPool {
+    address: H160,
+    tokens: (H160, H160),
+    enabled: Result<bool, MethodError>,
+    /// The token decimals and the oracle price of the pair.
+    price: Result<(u8, u8, U256), MethodError>,
+    state: Result<PoolState, MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let enabled = handle_contract_error(fetched_pool.enabled)?;
+    let price = handle_contract_error(fetched_pool.price)?;
+    let state = handle_contract_error(fetched_pool.state)?;
+
+    let ((x_decimals, y_decimals, price), (_, fee, min_x, max_x, min_y, max_y)) =
+        match (enabled, price, state) {
+            (Some(true), Some(price), Some(state)) => (price, state),
+            _ => return Ok(None),
+        };
+
+    let (x, y) = fetched_pool.tokens;
+    Ok(Some(Pool {
+        address: fetched_pool.address,
+        tokens: (
+            TokenState {
+                address: x,
+                decimals: x_decimals,
+                limit_min: min_x,
+                limit_max: max_x,
+            },
+            TokenState {
+                address: y,
+                decimals: y_decimals,
+                limit_min: min_y,
+                limit_max: max_y,
+            },
+        ),
+        price,
+        fee,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn usdc_weth() -> Pool {
+        Pool {
+            address: H160([0xff; 20]),
+            tokens: (
+                TokenState {
+                    address: H160([1; 20]),
+                    decimals: 6,
+                    limit_min: U256::from(10) * U256::exp10(6),
+                    limit_max: U256::from(50_000) * U256::exp10(6),
+                },
+                TokenState {
+                    address: H160([2; 20]),
+                    decimals: 18,
+                    limit_min: U256::exp10(16),
+                    limit_max: U256::from(10) * U256::exp10(18),
+                },
+            ),
+            // 1 USDC is worth 0.0005 WETH.
+            price: U256::from(5) * U256::exp10(14),
+            // 0.1%
+            fee: U256::exp10(15),
+        }
+    }
+
+    #[test]
+    fn amounts() {
+        let pool = usdc_weth();
+        let (usdc, weth) = (H160([1; 20]), H160([2; 20]));
+
+        assert_eq!(
+            pool.get_amount_out(weth, (U256::from(2_000) * U256::exp10(6), usdc)),
+            Some(U256::from(999) * U256::exp10(15)),
+        );
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::exp10(18), weth)),
+            Some(1_998_000_000.into()),
+        );
+
+        let in_amount = pool.get_amount_in(usdc, (U256::exp10(18), weth)).unwrap();
+        assert_eq!(in_amount, 2_002_002_003.into());
+        assert!(pool.get_amount_out(weth, (in_amount, usdc)).unwrap() >= U256::exp10(18));
+    }
+
+    #[test]
+    fn respects_limits() {
+        let pool = usdc_weth();
+        let (usdc, weth) = (H160([1; 20]), H160([2; 20]));
+
+        // Bought amounts above the maximum.
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::from(30) * U256::exp10(18), weth)),
+            None
+        );
+        assert_eq!(
+            pool.get_amount_in(usdc, (U256::from(11) * U256::exp10(18), weth)),
+            None
+        );
+        // Bought amounts below the minimum.
+        assert_eq!(pool.get_amount_out(weth, (U256::exp10(6), usdc)), None);
+        assert_eq!(pool.get_amount_in(weth, (U256::exp10(6), usdc)), None);
+
+        assert_eq!(
+            pool.get_amount_out(H160([3; 20]), (U256::exp10(6), usdc)),
+            None
+        );
+    }
+
+    fn fetched_pool() -> FetchedPool {
+        FetchedPool {
+            address: H160([0xff; 20]),
+            tokens: (H160([1; 20]), H160([2; 20])),
+            enabled: Ok(true),
+            price: Ok((6, 18, U256::from(5) * U256::exp10(14))),
+            state: Ok((
+                U256::zero(),
+                U256::exp10(15),
+                U256::from(10) * U256::exp10(6),
+                U256::from(50_000) * U256::exp10(6),
+                U256::exp10(16),
+                U256::from(10) * U256::exp10(18),
+            )),
+        }
+    }
+
+    #[test]
+    fn converts_pool_state() {
+        assert_eq!(handle_results(fetched_pool()).unwrap(), Some(usdc_weth()));
+    }
+
+    #[test]
+    fn skips_disabled_pairs() {
+        let fetched_pool = FetchedPool {
+            enabled: Ok(false),
+            ..fetched_pool()
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_none());
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched_pool = FetchedPool {
+            price: Err(ethcontract_error::testing_node_error()),
+            ..fetched_pool()
+        };
+        assert!(handle_results(fetched_pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched_pool = FetchedPool {
+            state: Err(ethcontract_error::testing_contract_error()),
+            ..fetched_pool()
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_none());
+    }
+}
@@ -0,0 +1,7 @@
+//! iZiSwap baseline liquidity source implementation.
+//!
+//! iZiSwap pools discretize liquidity on points, and additionally allow
+//! placing limit orders on single points. Pools are discovered through the
+//! factory and their points are read from the pool contracts.
+pub mod pool_fetching;
+mod swap_math;
@@ -0,0 +1,836 @@
+//! Module for fetching iZiSwap pools and quoting swaps against them.
+
+use super::swap_math::{self, Direction, Step, LEFT_MOST_POINT, RIGHT_MOST_POINT};
+use crate::{
+    baseline_solver::BaselineSolvable,
+    fixed_point::{div, Rounding},
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::{IZiSwapFactory, IZiSwapPool};
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::{Bound, RangeInclusive},
+    sync::Mutex,
+};
+
+const SWAP_GAS_COST: usize = 200_000;
+
+/// Pool fees are specified in units of `1e-6`.
+const FEE_DENOMINATOR: u32 = 1_000_000;
+
+/// The fee tiers for which iZiSwap factories create pools.
+const FEE_TIERS: [u32; 6] = [100, 400, 500, 2_000, 3_000, 10_000];
+
+/// The number of `orderOrEndpoint` bitmap words around the current point for
+/// which points are fetched. Each word covers 256 multiples of the pool's
+/// point delta.
+const POINT_BITMAP_WORDS: i16 = 2;
+
+/// The result of the pool `points` method: the liquidity sum, the liquidity
+/// delta, fee accumulators and whether or not the point is an endpoint.
+type PointData = (u128, i128, U256, U256, bool);
+
+/// The result of the pool `limitOrderData` method. Only the amounts of token X
+/// and Y being sold (the first and sixth fields) are relevant for swaps.
+type LimitOrderData = (u128, u128, U256, U256, u128, u128, u128, u128, U256, U256);
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The state of an iZiSwap pool.
+///
+/// Points are only included within `POINT_BITMAP_WORDS` bitmap words of the
+/// current point, and swaps moving the price out of this range are not
+/// supported.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub address: H160,
+    /// The pool tokens X and Y, where X has the smaller address.
+    pub tokens: (H160, H160),
+    /// The pool fee in units of `1e-6`.
+    pub fee: u32,
+    pub current_point: i32,
+    /// The liquidity at the current point.
+    pub liquidity: u128,
+    /// The part of the liquidity at the current point that is held in token
+    /// X. The rest is held in token Y.
+    pub liquidity_x: u128,
+    /// The points with liquidity changes or limit orders.
+    pub points: BTreeMap<i32, PointState>,
+    /// The range of points for which all points with liquidity changes or
+    /// limit orders are known.
+    pub known_points: RangeInclusive<i32>,
+}
+
+/// The state of a single point of an iZiSwap pool.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PointState {
+    /// The change in liquidity when crossing the point from left to right.
+    pub liquidity_delta: i128,
+    /// The amount of token X being sold by limit orders at the point.
+    pub selling_x: u128,
+    /// The amount of token Y being sold by limit orders at the point.
+    pub selling_y: u128,
+}
+
+impl Pool {
+    fn direction(&self, in_token: H160, out_token: H160) -> Option<Direction> {
+        if (in_token, out_token) == self.tokens {
+            Some(Direction::XToY)
+        } else if (out_token, in_token) == self.tokens {
+            Some(Direction::YToX)
+        } else {
+            None
+        }
+    }
+
+    /// Simulates a swap, returning `None` if it can not be completed with the
+    /// known points of the pool.
+    fn swap(&self, direction: Direction, amount: U256, exact_in: bool) -> Option<Swap> {
+        if !self.known_points.contains(&self.current_point) {
+            return None;
+        }
+        let swap = Swap {
+            remaining: amount,
+            exact_in,
+            cost: U256::zero(),
+            acquire: U256::zero(),
+        };
+        match direction {
+            Direction::XToY => self.swap_x_to_y(swap),
+            Direction::YToX => self.swap_y_to_x(swap),
+        }
+    }
+
+    /// Swaps token X for token Y, moving the price to the left.
+    fn swap_x_to_y(&self, mut swap: Swap) -> Option<Swap> {
+        let direction = Direction::XToY;
+        let mut point = self.current_point;
+        let mut liquidity = self.liquidity;
+        // Only the liquidity held in token Y can be bought at the current
+        // point.
+        let mut available = self.liquidity.checked_sub(self.liquidity_x)?;
+        loop {
+            let state = self.points.get(&point).copied().unwrap_or_default();
+            let sqrt_price = swap_math::sqrt_price(point)?;
+            if state.selling_y > 0
+                && swap.apply(swap.limit_order(direction, sqrt_price, state.selling_y)?)?
+            {
+                return Some(swap);
+            }
+            if available > 0 && swap.apply(swap.point(direction, sqrt_price, available)?)? {
+                return Some(swap);
+            }
+
+            liquidity = add_delta(liquidity, state.liquidity_delta.checked_neg()?)?;
+            let next = self
+                .points
+                .range(*self.known_points.start()..point)
+                .next_back()
+                .map(|(point, _)| *point);
+            let left = next.map_or(*self.known_points.start(), |next| next + 1);
+            if left < point
+                && liquidity > 0
+                && swap.apply(swap.range(direction, liquidity, left, point)?)?
+            {
+                return Some(swap);
+            }
+
+            point = next?;
+            available = liquidity;
+        }
+    }
+
+    /// Swaps token Y for token X, moving the price to the right.
+    fn swap_y_to_x(&self, mut swap: Swap) -> Option<Swap> {
+        let direction = Direction::YToX;
+        let mut point = self.current_point;
+        let mut liquidity = self.liquidity;
+        // Only the liquidity held in token X can be bought at the current
+        // point.
+        let mut available = self.liquidity_x;
+        loop {
+            let state = self.points.get(&point).copied().unwrap_or_default();
+            let sqrt_price = swap_math::sqrt_price(point)?;
+            if state.selling_x > 0
+                && swap.apply(swap.limit_order(direction, sqrt_price, state.selling_x)?)?
+            {
+                return Some(swap);
+            }
+            if available > 0 && swap.apply(swap.point(direction, sqrt_price, available)?)? {
+                return Some(swap);
+            }
+
+            let next = self
+                .points
+                .range((
+                    Bound::Excluded(point),
+                    Bound::Included(*self.known_points.end()),
+                ))
+                .next()
+                .map(|(point, _)| *point);
+            let right = next.unwrap_or(*self.known_points.end() + 1);
+            if point + 1 < right
+                && liquidity > 0
+                && swap.apply(swap.range(direction, liquidity, point + 1, right)?)?
+            {
+                return Some(swap);
+            }
+
+            point = next?;
+            liquidity = add_delta(liquidity, self.points[&point].liquidity_delta)?;
+            available = liquidity;
+        }
+    }
+}
+
+/// The accumulated amounts of a swap.
+struct Swap {
+    /// The remaining input amount for exact input swaps and the remaining
+    /// output amount for exact output swaps.
+    remaining: U256,
+    exact_in: bool,
+    cost: U256,
+    acquire: U256,
+}
+
+impl Swap {
+    fn limit_order(&self, direction: Direction, sqrt_price: U256, selling: u128) -> Option<Step> {
+        swap_math::limit_order(
+            direction,
+            sqrt_price,
+            selling.into(),
+            self.remaining,
+            self.exact_in,
+        )
+    }
+
+    fn point(&self, direction: Direction, sqrt_price: U256, liquidity: u128) -> Option<Step> {
+        swap_math::point(
+            direction,
+            sqrt_price,
+            liquidity.into(),
+            self.remaining,
+            self.exact_in,
+        )
+    }
+
+    fn range(&self, direction: Direction, liquidity: u128, left: i32, right: i32) -> Option<Step> {
+        swap_math::range(
+            direction,
+            liquidity.into(),
+            left,
+            right,
+            self.remaining,
+            self.exact_in,
+        )
+    }
+
+    /// Applies a swap step, returning whether or not the swap is complete.
+    fn apply(&mut self, step: Step) -> Option<bool> {
+        self.cost = self.cost.checked_add(step.cost)?;
+        self.acquire = self.acquire.checked_add(step.acquire)?;
+        self.remaining = if self.exact_in {
+            self.remaining.checked_sub(step.cost)?
+        } else {
+            self.remaining.saturating_sub(step.acquire)
+        };
+        Some(step.done || self.remaining.is_zero())
+    }
+}
+
+fn add_delta(liquidity: u128, delta: i128) -> Option<u128> {
+    if delta >= 0 {
+        liquidity.checked_add(delta as u128)
+    } else {
+        liquidity.checked_sub(delta.unsigned_abs())
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let direction = self.direction(in_token, out_token)?;
+        let in_amount = in_amount.checked_mul(FEE_DENOMINATOR.checked_sub(self.fee)?.into())?
+            / U256::from(FEE_DENOMINATOR);
+        Some(self.swap(direction, in_amount, true)?.acquire)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let direction = self.direction(in_token, out_token)?;
+        let cost = self.swap(direction, out_amount, false)?.cost;
+        let fee = div(
+            cost.checked_mul(self.fee.into())?,
+            FEE_DENOMINATOR.checked_sub(self.fee)?.into(),
+            Rounding::Up,
+        )?;
+        cost.checked_add(fee)
+    }
+
+    fn gas_cost(&self) -> usize {
+        SWAP_GAS_COST
+    }
+}
+
+/// Pool fetcher for an iZiSwap factory.
+pub struct IZiSwapPoolFetcher {
+    web3: Web3,
+    factory: IZiSwapFactory,
+    /// Cache of existing pool addresses by token pair and fee tier. Pools
+    /// never change address once created, so entries never expire.
+    pools: Mutex<HashMap<(TokenPair, u32), H160>>,
+}
+
+impl IZiSwapPoolFetcher {
+    /// Creates a new pool fetcher for the iZiSwap factory at the specified
+    /// address.
+    pub fn new(web3: &Web3, factory: H160) -> Self {
+        Self {
+            web3: web3.clone(),
+            factory: IZiSwapFactory::at(web3, factory),
+            pools: Default::default(),
+        }
+    }
+
+    /// Returns the addresses and tokens of the existing pools for the
+    /// specified token pairs.
+    async fn pool_addresses(
+        &self,
+        token_pairs: HashSet<TokenPair>,
+    ) -> Result<Vec<(H160, TokenPair)>> {
+        let mut addresses = Vec::new();
+        let mut missing = Vec::new();
+        {
+            let pools = self.pools.lock().unwrap();
+            for key in token_pairs
+                .into_iter()
+                .flat_map(|pair| FEE_TIERS.iter().map(move |fee| (pair, *fee)))
+            {
+                match pools.get(&key) {
+                    Some(address) => addresses.push((*address, key.0)),
+                    None => missing.push(key),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(addresses);
+        }
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let futures = missing
+            .iter()
+            .map(|(pair, fee)| {
+                let (token_x, token_y) = pair.get();
+                self.factory
+                    .pool(token_x, token_y, *fee)
+                    .batch_call(&mut batch)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let results = future::try_join_all(futures).await?;
+
+        // Only cache pools that exist, as they may be created later.
+        let mut pools = self.pools.lock().unwrap();
+        for (key, address) in missing.into_iter().zip(results) {
+            if !address.is_zero() {
+                pools.insert(key, address);
+                addresses.push((address, key.0));
+            }
+        }
+        Ok(addresses)
+    }
+
+    async fn fetch_slots(
+        &self,
+        pools: Vec<(H160, TokenPair)>,
+        block: BlockId,
+    ) -> Result<Vec<FetchedSlot>> {
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let futures = pools
+            .into_iter()
+            .map(|(address, tokens)| {
+                let pool = IZiSwapPool::at(&self.web3, address);
+                let state = pool.state().block(block).batch_call(&mut batch);
+                let fee = pool.fee().block(block).batch_call(&mut batch);
+                let point_delta = pool.point_delta().block(block).batch_call(&mut batch);
+                async move {
+                    let state = handle_contract_error(state.await)?;
+                    let fee = handle_contract_error(fee.await)?;
+                    let point_delta = handle_contract_error(point_delta.await)?;
+                    Result::<_>::Ok(match (state, fee, point_delta) {
+                        (
+                            Some((_, current_point, _, _, _, _, liquidity, liquidity_x)),
+                            Some(fee),
+                            Some(point_delta),
+                        ) if point_delta > 0 && fee < FEE_DENOMINATOR => Some(FetchedSlot {
+                            address,
+                            tokens: tokens.get(),
+                            fee,
+                            point_delta,
+                            current_point,
+                            liquidity,
+                            liquidity_x,
+                        }),
+                        _ => None,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(futures)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    async fn fetch_marked_points(
+        &self,
+        slots: &[FetchedSlot],
+        block: BlockId,
+    ) -> Result<Vec<Vec<i32>>> {
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let futures = slots
+            .iter()
+            .map(|slot| {
+                let pool = IZiSwapPool::at(&self.web3, slot.address);
+                let point_delta = slot.point_delta;
+                let words = bitmap_words(slot.current_point, point_delta)
+                    .map(|word| {
+                        let bitmap = pool
+                            .order_or_endpoint(word)
+                            .block(block)
+                            .batch_call(&mut batch);
+                        (word, bitmap)
+                    })
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut points = Vec::new();
+                    for (word, bitmap) in words {
+                        if let Some(bitmap) = handle_contract_error(bitmap.await)? {
+                            points.extend(marked_points(word, bitmap, point_delta));
+                        }
+                    }
+                    Result::<_>::Ok(points)
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        future::try_join_all(futures).await
+    }
+
+    async fn fetch_pools(&self, slots: Vec<FetchedSlot>, block: BlockId) -> Result<Vec<Pool>> {
+        let points = self.fetch_marked_points(&slots, block).await?;
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let futures = slots
+            .into_iter()
+            .zip(points)
+            .map(|(slot, points)| {
+                let pool = IZiSwapPool::at(&self.web3, slot.address);
+                let points = points
+                    .into_iter()
+                    .map(|point| {
+                        let data = pool.points(point).block(block).batch_call(&mut batch);
+                        let orders = pool
+                            .limit_order_data(point)
+                            .block(block)
+                            .batch_call(&mut batch);
+                        (point, data, orders)
+                    })
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut fetched = Vec::new();
+                    for (point, data, orders) in points {
+                        fetched.push(FetchedPoint {
+                            point,
+                            data: data.await,
+                            orders: orders.await,
+                        });
+                    }
+                    handle_results(slot, fetched)
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(futures)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for IZiSwapPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let pools = self.pool_addresses(token_pairs).await?;
+        if pools.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let block = BlockId::Number(at_block.into());
+        let slots = self.fetch_slots(pools, block).await?;
+        self.fetch_pools(slots, block).await
+    }
+}
+
+/// The current point, liquidity and parameters of a pool.
+struct FetchedSlot {
+    address: H160,
+    tokens: (H160, H160),
+    fee: u32,
+    point_delta: i32,
+    current_point: i32,
+    liquidity: u128,
+    liquidity_x: u128,
+}
+
+struct FetchedPoint {
+    point: i32,
+    data: Result<PointData, MethodError>,
+    orders: Result<LimitOrderData, MethodError>,
+}
+
+fn handle_results(slot: FetchedSlot, fetched: Vec<FetchedPoint>) -> Result<Option<Pool>> {
+    let mut points = BTreeMap::new();
+    for fetched_point in fetched {
+        let data = handle_contract_error(fetched_point.data)?;
+        let orders = handle_contract_error(fetched_point.orders)?;
+        let (state, orders) = match (data, orders) {
+            (Some(state), Some(orders)) => (state, orders),
+            _ => return Ok(None),
+        };
+
+        let (_, liquidity_delta, _, _, _) = state;
+        let (selling_x, _, _, _, _, selling_y, _, _, _, _) = orders;
+        if liquidity_delta != 0 || selling_x != 0 || selling_y != 0 {
+            points.insert(
+                fetched_point.point,
+                PointState {
+                    liquidity_delta,
+                    selling_x,
+                    selling_y,
+                },
+            );
+        }
+    }
+
+    Ok(Some(Pool {
+        address: slot.address,
+        tokens: slot.tokens,
+        fee: slot.fee,
+        current_point: slot.current_point,
+        liquidity: slot.liquidity,
+        liquidity_x: slot.liquidity_x,
+        points,
+        known_points: known_points(
+            bitmap_words(slot.current_point, slot.point_delta),
+            slot.point_delta,
+        ),
+    }))
+}
+
+/// Returns the `orderOrEndpoint` bitmap word positions around the specified
+/// point.
+fn bitmap_words(point: i32, point_delta: i32) -> RangeInclusive<i16> {
+    let word = (point.div_euclid(point_delta) >> 8) as i16;
+    word.saturating_sub(POINT_BITMAP_WORDS)..=word.saturating_add(POINT_BITMAP_WORDS)
+}
+
+/// Returns the points marked as having limit orders or being liquidity
+/// endpoints in an `orderOrEndpoint` bitmap word.
+fn marked_points(word: i16, bitmap: U256, point_delta: i32) -> impl Iterator<Item = i32> {
+    (0..256)
+        .filter(move |bit| bitmap.bit(*bit))
+        .map(move |bit| ((word as i32) * 256 + bit as i32) * point_delta)
+}
+
+/// Returns the range of points covered by the specified bitmap words.
+fn known_points(words: RangeInclusive<i16>, point_delta: i32) -> RangeInclusive<i32> {
+    let word_points = 256 * point_delta as i64;
+    let start = *words.start() as i64 * word_points;
+    let end = (*words.end() as i64 + 1) * word_points - 1;
+    let clamp = |point: i64| point.clamp(LEFT_MOST_POINT as i64, RIGHT_MOST_POINT as i64) as i32;
+    clamp(start)..=clamp(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+    use maplit::btreemap;
+
+    fn e18(value: u64) -> U256 {
+        U256::from(value) * U256::exp10(18)
+    }
+
+    fn int(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    fn pool(fee: u32) -> Pool {
+        const E18: u128 = 1_000_000_000_000_000_000;
+        let point = |liquidity_delta: i128, selling_x: u128, selling_y: u128| PointState {
+            liquidity_delta,
+            selling_x,
+            selling_y,
+        };
+        Pool {
+            address: H160([0xff; 20]),
+            tokens: (H160([1; 20]), H160([2; 20])),
+            fee,
+            current_point: 10,
+            liquidity: 5 * E18,
+            liquidity_x: 2 * E18,
+            points: btreemap! {
+                -120 => point(3 * E18 as i128, 0, 0),
+                -40 => point(2 * E18 as i128, 0, 5 * E18),
+                40 => point(-2 * E18 as i128, 7 * E18 / 10, 0),
+                80 => point(-3 * E18 as i128, 0, 0),
+                200 => point(0, 2 * E18, 0),
+            },
+            known_points: -160..=240,
+        }
+    }
+
+    #[test]
+    fn swaps_without_fees() {
+        // Values computed with a Python model of the pool contract swap math.
+        let pool = pool(0);
+        let swap = |direction, amount| {
+            let swap = pool.swap(direction, amount, true)?;
+            Some((swap.cost, swap.acquire))
+        };
+
+        assert_eq!(
+            swap(Direction::XToY, e18(1)),
+            Some((e18(1), int("1001000450120021002")))
+        );
+        assert_eq!(
+            swap(Direction::XToY, e18(20)),
+            Some((e18(20), int("20016204050492533373")))
+        );
+        assert_eq!(
+            swap(Direction::XToY, e18(400)),
+            Some((e18(400), int("398690182811984711112")))
+        );
+        assert!(swap(Direction::XToY, e18(1_000)).is_none());
+
+        assert_eq!(
+            swap(Direction::YToX, e18(1)),
+            Some((e18(1), int("999000549780071479")))
+        );
+        assert_eq!(
+            swap(Direction::YToX, e18(20)),
+            Some((e18(20), int("19975818140026865263")))
+        );
+        assert_eq!(
+            swap(Direction::YToX, e18(100)),
+            Some((e18(100), int("99799311189064178933")))
+        );
+        assert!(swap(Direction::YToX, e18(400)).is_none());
+    }
+
+    #[test]
+    fn amounts_with_fees() {
+        let pool = pool(3_000);
+        let (x, y) = pool.tokens;
+
+        assert_eq!(
+            pool.get_amount_out(y, (e18(10), x)),
+            Some(int("9979079177207267386"))
+        );
+        assert_eq!(
+            pool.get_amount_out(x, (e18(10), y)),
+            Some(int("9958943188192092951"))
+        );
+        assert_eq!(
+            pool.get_amount_out(y, (e18(60), x)),
+            Some(int("59844632306154892849"))
+        );
+        assert_eq!(
+            pool.get_amount_out(x, (e18(60), y)),
+            Some(int("59723906540067712374"))
+        );
+
+        let in_amount = pool.get_amount_in(x, (e18(10), y)).unwrap();
+        assert_eq!(in_amount, int("10020966994647427106"));
+        assert_eq!(pool.get_amount_out(y, (in_amount, x)), Some(e18(10)));
+
+        let in_amount = pool.get_amount_in(y, (e18(10), x)).unwrap();
+        assert_eq!(in_amount, int("10041229796478040986"));
+        assert_eq!(pool.get_amount_out(x, (in_amount, y)), Some(e18(10)));
+    }
+
+    #[test]
+    fn exact_output_amounts_are_sufficient() {
+        let pool = pool(3_000);
+        let (x, y) = pool.tokens;
+        for amount in [
+            U256::one(),
+            U256::from(1_000),
+            U256::from(1_000_000_000),
+            e18(1),
+            e18(50),
+        ] {
+            for (in_token, out_token) in [(x, y), (y, x)] {
+                let in_amount = pool.get_amount_in(in_token, (amount, out_token)).unwrap();
+                assert!(
+                    pool.get_amount_out(out_token, (in_amount, in_token))
+                        .unwrap()
+                        >= amount
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_swaps() {
+        let pool = pool(3_000);
+        let (x, y) = pool.tokens;
+
+        assert_eq!(pool.get_amount_out(y, (e18(1_000), x)), None);
+        assert_eq!(pool.get_amount_in(x, (e18(1_000), y)), None);
+        assert_eq!(pool.get_amount_in(y, (e18(1_000), x)), None);
+        assert_eq!(pool.get_amount_out(H160([3; 20]), (e18(1), x)), None);
+
+        let pool = Pool {
+            current_point: 300,
+            ..pool
+        };
+        assert_eq!(pool.get_amount_out(y, (e18(1), x)), None);
+    }
+
+    #[test]
+    fn computes_bitmap_words() {
+        assert_eq!(bitmap_words(0, 1), -2..=2);
+        assert_eq!(bitmap_words(-1, 1), -3..=1);
+        assert_eq!(bitmap_words(-599, 40), -3..=1);
+        assert_eq!(bitmap_words(10_240, 40), -1..=3);
+    }
+
+    #[test]
+    fn computes_marked_points() {
+        let bitmap = U256::one() | (U256::one() << 255);
+        assert_eq!(
+            marked_points(0, bitmap, 40).collect::<Vec<_>>(),
+            vec![0, 10_200],
+        );
+        assert_eq!(
+            marked_points(-1, bitmap, 40).collect::<Vec<_>>(),
+            vec![-10_240, -40],
+        );
+        assert_eq!(marked_points(3, U256::zero(), 40).count(), 0);
+    }
+
+    #[test]
+    fn computes_known_points() {
+        assert_eq!(known_points(-2..=2, 1), -512..=767);
+        assert_eq!(known_points(-2..=2, 40), -20_480..=30_719);
+        assert_eq!(known_points(-3_127..=-3_123, 1), LEFT_MOST_POINT..=-799_233);
+        assert_eq!(known_points(3_123..=3_127, 1), 799_488..=RIGHT_MOST_POINT);
+    }
+
+    fn slot() -> FetchedSlot {
+        FetchedSlot {
+            address: H160([0xff; 20]),
+            tokens: (H160([1; 20]), H160([2; 20])),
+            fee: 2_000,
+            point_delta: 40,
+            current_point: 5,
+            liquidity: 1_000,
+            liquidity_x: 400,
+        }
+    }
+
+    fn point_data(liquidity_delta: i128) -> PointData {
+        (0, liquidity_delta, U256::zero(), U256::zero(), true)
+    }
+
+    fn limit_order_data(selling_x: u128, selling_y: u128) -> LimitOrderData {
+        let zero = U256::zero();
+        (selling_x, 0, zero, zero, 0, selling_y, 0, 0, zero, zero)
+    }
+
+    #[test]
+    fn converts_pool_state() {
+        let pool = handle_results(
+            slot(),
+            vec![
+                FetchedPoint {
+                    point: -40,
+                    data: Ok(point_data(1_000)),
+                    orders: Ok(limit_order_data(0, 0)),
+                },
+                FetchedPoint {
+                    point: 0,
+                    data: Ok(point_data(0)),
+                    orders: Ok(limit_order_data(0, 0)),
+                },
+                FetchedPoint {
+                    point: 40,
+                    data: Ok(point_data(-1_000)),
+                    orders: Ok(limit_order_data(7, 0)),
+                },
+            ],
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            pool,
+            Pool {
+                address: H160([0xff; 20]),
+                tokens: (H160([1; 20]), H160([2; 20])),
+                fee: 2_000,
+                current_point: 5,
+                liquidity: 1_000,
+                liquidity_x: 400,
+                points: btreemap! {
+                    -40 => PointState {
+                        liquidity_delta: 1_000,
+                        selling_x: 0,
+                        selling_y: 0,
+                    },
+                    40 => PointState {
+                        liquidity_delta: -1_000,
+                        selling_x: 7,
+                        selling_y: 0,
+                    },
+                },
+                known_points: -20_480..=30_719,
+            }
+        );
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched = vec![FetchedPoint {
+            point: 0,
+            data: Err(ethcontract_error::testing_node_error()),
+            orders: Ok(limit_order_data(0, 0)),
+        }];
+        assert!(handle_results(slot(), fetched).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched = vec![FetchedPoint {
+            point: 0,
+            data: Ok(point_data(0)),
+            orders: Err(ethcontract_error::testing_contract_error()),
+        }];
+        assert!(handle_results(slot(), fetched).unwrap().is_none());
+    }
+}
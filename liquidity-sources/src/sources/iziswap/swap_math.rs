@@ -0,0 +1,362 @@
+//! Module implementing the iZiSwap swap math.
+//!
+//! Liquidity in iZiSwap pools lives on discrete points, where point `i`
+//! prices token X at `1.0001^i` token Y. Swaps consume limit orders and
+//! liquidity point by point, and the amounts of ranges of points with constant
+//! liquidity are computed with geometric sums. This is a port of the pool's
+//! `SwapMathX2Y`, `SwapMathY2X` and `AmountMath` libraries, rounding
+//! identically to the contracts.
+
+use crate::fixed_point::{mul_div, Rounding};
+use ethcontract::U256;
+
+/// The left-most point supported by iZiSwap pools.
+pub const LEFT_MOST_POINT: i32 = -800_000;
+/// The right-most point supported by iZiSwap pools.
+pub const RIGHT_MOST_POINT: i32 = 800_000;
+
+lazy_static::lazy_static! {
+    static ref Q96: U256 = U256::one() << 96;
+    /// The square root of the price ratio between adjacent points.
+    static ref SQRT_RATE: U256 = sqrt_price(1).unwrap();
+}
+
+/// Factors for computing `sqrt(1.0001^-(2^i))` as 128-bit fixed point
+/// numbers. These are the same constants used by the Uniswap V3 `TickMath`
+/// library, which iZiSwap's `LogPowMath` is based on.
+const SQRT_PRICE_FACTORS: [u128; 20] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e213a,
+    0xfff2e50f5f656932ef12357cf3c7fdcc,
+    0xffe5caca7e10e4e61c3624eaa0941cd0,
+    0xffcb9843d60f6159c9db58835c926644,
+    0xff973b41fa98c081472e6896dfb254c0,
+    0xff2ea16466c96a3843ec78b326b52861,
+    0xfe5dee046a99a2a811c461f1969c3053,
+    0xfcbe86c7900a88aedcffc83b479aa3a4,
+    0xf987a7253ac413176f2b074cf7815e54,
+    0xf3392b0822b70005940c7a398e4b70f3,
+    0xe7159475a2c29b7443b29c7fa6e889d9,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e5,
+    0x70d869a156d2a1b890bb3df62baf32f7,
+    0x31be135f97d08fd981231505542fcfa6,
+    0x9aa508b5b7a84e1c677de54f3e99bc9,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe98,
+    0x48a170391f7dc42444e8fa2,
+];
+
+/// Returns the square root of the price at the specified point as a 96-bit
+/// fixed point number.
+pub fn sqrt_price(point: i32) -> Option<U256> {
+    if !(LEFT_MOST_POINT..=RIGHT_MOST_POINT).contains(&point) {
+        return None;
+    }
+
+    let abs = point.unsigned_abs();
+    let mut ratio = if abs & 1 != 0 {
+        U256::from(SQRT_PRICE_FACTORS[0])
+    } else {
+        U256::one() << 128
+    };
+    for (i, factor) in SQRT_PRICE_FACTORS.iter().enumerate().skip(1) {
+        if abs & (1 << i) != 0 {
+            ratio = (ratio * U256::from(*factor)) >> 128;
+        }
+    }
+    if point > 0 {
+        ratio = U256::max_value() / ratio;
+    }
+
+    // Round up when converting from 128-bit to 96-bit fixed point.
+    let rounding = !(ratio & U256::from(u32::MAX)).is_zero();
+    Some((ratio >> 32) + U256::from(rounding as u8))
+}
+
+/// Returns the amount of token Y for the liquidity on the points
+/// `[left, right)`.
+fn amount_y(liquidity: U256, left: i32, right: i32, round_up: bool) -> Option<U256> {
+    mul_div(
+        liquidity,
+        sqrt_price(right)?.checked_sub(sqrt_price(left)?)?,
+        SQRT_RATE.checked_sub(*Q96)?,
+        Rounding::up_if(round_up),
+    )
+}
+
+/// Returns the amount of token X for the liquidity on the points
+/// `[left, right)`.
+fn amount_x(liquidity: U256, left: i32, right: i32, round_up: bool) -> Option<U256> {
+    let sqrt_price_right = sqrt_price(right)?;
+    mul_div(
+        liquidity,
+        sqrt_price(right.checked_sub(left)?)?.checked_sub(*Q96)?,
+        sqrt_price_right.checked_sub(mul_div(
+            sqrt_price_right,
+            *Q96,
+            *SQRT_RATE,
+            Rounding::Down,
+        )?)?,
+        Rounding::up_if(round_up),
+    )
+}
+
+/// The result of a swap step.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Step {
+    /// The amount of the input token paid to the pool.
+    pub cost: U256,
+    /// The amount of the output token acquired from the pool.
+    pub acquire: U256,
+    /// Whether or not the swap completes with this step.
+    pub done: bool,
+}
+
+/// Swap direction dependent amount computations.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    XToY,
+    YToX,
+}
+
+impl Direction {
+    /// Returns the amount of output token for an amount of input token at the
+    /// specified price. Each step of the computation rounds down.
+    fn output(self, amount: U256, sqrt_price: U256) -> Option<U256> {
+        let (numerator, denominator) = self.price(sqrt_price);
+        let half = mul_div(amount, numerator, denominator, Rounding::Down)?;
+        mul_div(half, numerator, denominator, Rounding::Down)
+    }
+
+    /// Returns the amount of input token for an amount of output token at the
+    /// specified price. Each step of the computation rounds up.
+    fn input(self, amount: U256, sqrt_price: U256) -> Option<U256> {
+        let (numerator, denominator) = self.price(sqrt_price);
+        let half = mul_div(amount, denominator, numerator, Rounding::Up)?;
+        mul_div(half, denominator, numerator, Rounding::Up)
+    }
+
+    /// Returns the square root of the price of the input token in output
+    /// token as a fraction.
+    fn price(self, sqrt_price: U256) -> (U256, U256) {
+        match self {
+            Direction::XToY => (sqrt_price, *Q96),
+            Direction::YToX => (*Q96, sqrt_price),
+        }
+    }
+}
+
+/// Swaps against the limit orders selling `selling` of the output token at a
+/// point.
+///
+/// `remaining` is the remaining input amount for exact input swaps, and the
+/// remaining output amount for exact output swaps.
+pub fn limit_order(
+    direction: Direction,
+    sqrt_price: U256,
+    selling: U256,
+    remaining: U256,
+    exact_in: bool,
+) -> Option<Step> {
+    let (acquire, done) = if exact_in {
+        let acquire = direction.output(remaining, sqrt_price)?;
+        (acquire.min(selling), acquire < selling)
+    } else {
+        (remaining.min(selling), remaining <= selling)
+    };
+    Some(Step {
+        cost: direction.input(acquire, sqrt_price)?,
+        acquire,
+        done,
+    })
+}
+
+/// Swaps against the `liquidity` available at a single point.
+pub fn point(
+    direction: Direction,
+    sqrt_price: U256,
+    liquidity: U256,
+    remaining: U256,
+    exact_in: bool,
+) -> Option<Step> {
+    // Liquidity at a point is the amount of Y divided by the square root of
+    // the price, or the amount of X multiplied by it.
+    let (numerator, denominator) = direction.price(sqrt_price);
+    let required = if exact_in {
+        mul_div(remaining, numerator, denominator, Rounding::Down)?
+    } else {
+        mul_div(remaining, denominator, numerator, Rounding::Up)?
+    };
+    let used = required.min(liquidity);
+    Some(Step {
+        cost: mul_div(used, denominator, numerator, Rounding::Up)?,
+        acquire: mul_div(used, numerator, denominator, Rounding::Down)?,
+        done: required < liquidity,
+    })
+}
+
+/// Swaps against constant `liquidity` on the points `[left, right)`.
+///
+/// Swaps from X to Y move the price down and consume points from the right,
+/// swaps from Y to X move it up and consume points from the left.
+pub fn range(
+    direction: Direction,
+    liquidity: U256,
+    left: i32,
+    right: i32,
+    remaining: U256,
+    exact_in: bool,
+) -> Option<Step> {
+    // Returns the step for consuming the `count` first points of the range.
+    let consume = |count: i32| -> Option<Step> {
+        let (cost, acquire) = match direction {
+            Direction::XToY => (
+                amount_x(liquidity, right - count, right, true)?,
+                amount_y(liquidity, right - count, right, false)?,
+            ),
+            Direction::YToX => (
+                amount_y(liquidity, left, left + count, true)?,
+                amount_x(liquidity, left, left + count, false)?,
+            ),
+        };
+        Some(Step {
+            cost,
+            acquire,
+            done: false,
+        })
+    };
+    let measure = |step: &Step| if exact_in { step.cost } else { step.acquire };
+
+    let points = right.checked_sub(left)?;
+    let full = consume(points)?;
+    if measure(&full) <= remaining {
+        return Some(Step {
+            done: measure(&full) == remaining,
+            ..full
+        });
+    }
+
+    // Find the largest number of points that can be fully consumed and
+    // partially consume the following point with what is left.
+    let (mut low, mut high) = (0, points - 1);
+    while low < high {
+        let mid = (low + high + 1) / 2;
+        if measure(&consume(mid)?) <= remaining {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    let mut step = consume(low)?;
+    let remaining = if exact_in {
+        remaining - step.cost
+    } else {
+        remaining - step.acquire
+    };
+    if !remaining.is_zero() {
+        let next = match direction {
+            Direction::XToY => right - low - 1,
+            Direction::YToX => left + low,
+        };
+        let partial = point(direction, sqrt_price(next)?, liquidity, remaining, exact_in)?;
+        step.cost = step.cost.checked_add(partial.cost)?;
+        step.acquire = step.acquire.checked_add(partial.acquire)?;
+    }
+    Some(Step { done: true, ..step })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn e18(value: u64) -> U256 {
+        U256::from(value) * U256::exp10(18)
+    }
+
+    #[test]
+    fn sqrt_prices() {
+        assert_eq!(sqrt_price(0), Some(*Q96));
+        assert_eq!(
+            sqrt_price(1),
+            Some(U256::from_dec_str("79232123823359799118286999568").unwrap())
+        );
+        assert_eq!(
+            sqrt_price(-1),
+            Some(U256::from_dec_str("79224201403219477170569942574").unwrap())
+        );
+        assert_eq!(
+            sqrt_price(RIGHT_MOST_POINT),
+            Some(U256::from_dec_str("18611883644907511909590774894315720731532604461").unwrap())
+        );
+        assert_eq!(sqrt_price(LEFT_MOST_POINT), Some(337_263_108_622u64.into()));
+        assert_eq!(sqrt_price(RIGHT_MOST_POINT + 1), None);
+        assert_eq!(sqrt_price(LEFT_MOST_POINT - 1), None);
+    }
+
+    #[test]
+    fn range_amounts() {
+        assert_eq!(
+            amount_y(U256::exp10(18), 0, 10, false),
+            Some(10_002_250_243_764_062_867u64.into())
+        );
+        assert_eq!(
+            amount_y(U256::exp10(18), 0, 10, true),
+            Some(10_002_250_243_764_062_868u64.into())
+        );
+        assert_eq!(
+            amount_x(U256::exp10(18), 0, 10, false),
+            Some(9_997_750_468_664_701_647u64.into())
+        );
+        assert_eq!(
+            amount_x(U256::exp10(18), 0, 10, true),
+            Some(9_997_750_468_664_701_648u64.into())
+        );
+    }
+
+    #[test]
+    fn limit_order_steps() {
+        // Orders are filled at the point price, so at point 0 one unit of
+        // input buys one unit of output.
+        let step = limit_order(Direction::XToY, *Q96, e18(5), e18(2), true).unwrap();
+        assert_eq!(
+            step,
+            Step {
+                cost: e18(2),
+                acquire: e18(2),
+                done: true,
+            }
+        );
+
+        let step = limit_order(Direction::YToX, *Q96, e18(5), e18(7), false).unwrap();
+        assert_eq!(
+            step,
+            Step {
+                cost: e18(5),
+                acquire: e18(5),
+                done: false,
+            }
+        );
+    }
+
+    #[test]
+    fn range_steps_consume_partial_ranges() {
+        for direction in [Direction::XToY, Direction::YToX] {
+            for exact_in in [true, false] {
+                let full = range(direction, e18(1), -10, 10, e18(1_000), exact_in).unwrap();
+                assert!(!full.done);
+
+                let remaining = e18(5);
+                let partial = range(direction, e18(1), -10, 10, remaining, exact_in).unwrap();
+                assert!(partial.done);
+                if exact_in {
+                    assert!(partial.cost <= remaining);
+                } else {
+                    assert!(partial.acquire >= remaining);
+                }
+                assert!(partial.cost < full.cost);
+                assert!(partial.acquire < full.acquire);
+            }
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Maker Peg Stability Module (PSM) baseline liquidity source implementation.
+//!
+//! The PSM allows swapping a collateral "gem" token (for example USDC) for DAI
+//! at a fixed 1:1 price, minus the `tin` fee when selling the gem and plus the
+//! `tout` fee when buying it. Selling gems mints DAI, which is limited by the
+//! debt ceiling of the PSM's collateral type, while buying gems is limited by
+//! the gems held by the PSM's join adapter.
+
+use crate::{
+    baseline_solver::BaselineSolvable,
+    fixed_point::{div, Rounding},
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::{ensure, Result};
+use contracts::{MakerGemJoin, MakerPsm, MakerVat, ERC20};
+use ethcontract::{errors::MethodError, tokens::Bytes, BlockId, H160, U256};
+use std::collections::HashSet;
+
+const PSM_SWAP_GAS_COST: usize = 170_000;
+
+lazy_static::lazy_static! {
+    static ref WAD: U256 = U256::exp10(18);
+    static ref RAY: U256 = U256::exp10(27);
+}
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The current state of a PSM.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub address: H160,
+    pub dai: H160,
+    pub gem: H160,
+    /// The factor for converting gem amounts to 18 decimals.
+    pub to_18_conversion_factor: U256,
+    /// The fee for selling gems with 18 decimals of precision.
+    pub tin: U256,
+    /// The fee for buying gems with 18 decimals of precision.
+    pub tout: U256,
+    /// The amount of gems that can be bought from the PSM.
+    pub gem_reserve: U256,
+    /// The amount of DAI that can still be minted before reaching the debt
+    /// ceiling, limiting the amount of gems that can be sold to the PSM.
+    pub dai_capacity: U256,
+}
+
+impl Pool {
+    /// Returns the amount of DAI received for selling gems.
+    fn sell_gem(&self, gem_amount: U256) -> Option<U256> {
+        let gem_amount_18 = gem_amount.checked_mul(self.to_18_conversion_factor)?;
+        if gem_amount_18 > self.dai_capacity {
+            return None;
+        }
+        let fee = gem_amount_18.checked_mul(self.tin)? / *WAD;
+        gem_amount_18.checked_sub(fee)
+    }
+
+    /// Returns the amount of gems that need to be sold to receive DAI.
+    fn gem_in_for_dai_out(&self, dai_amount: U256) -> Option<U256> {
+        let gem_amount_18 = div(
+            dai_amount.checked_mul(*WAD)?,
+            WAD.checked_sub(self.tin)?,
+            Rounding::Up,
+        )?;
+        let gem_amount = div(gem_amount_18, self.to_18_conversion_factor, Rounding::Up)?;
+        // Rounding may make the computed DAI amount slightly larger than
+        // requested, so make sure the debt ceiling is not exceeded.
+        self.sell_gem(gem_amount)?;
+        Some(gem_amount)
+    }
+
+    /// Returns the amount of DAI that needs to be paid for buying gems.
+    fn buy_gem(&self, gem_amount: U256) -> Option<U256> {
+        if gem_amount > self.gem_reserve {
+            return None;
+        }
+        let gem_amount_18 = gem_amount.checked_mul(self.to_18_conversion_factor)?;
+        let fee = gem_amount_18.checked_mul(self.tout)? / *WAD;
+        gem_amount_18.checked_add(fee)
+    }
+
+    /// Returns the amount of gems that can be bought with DAI.
+    fn gem_out_for_dai_in(&self, dai_amount: U256) -> Option<U256> {
+        let gem_amount_18 = dai_amount
+            .checked_mul(*WAD)?
+            .checked_div(WAD.checked_add(self.tout)?)?;
+        let gem_amount = gem_amount_18.checked_div(self.to_18_conversion_factor)?;
+        if gem_amount > self.gem_reserve {
+            return None;
+        }
+        Some(gem_amount)
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        if in_token == self.gem && out_token == self.dai {
+            self.sell_gem(in_amount)
+        } else if in_token == self.dai && out_token == self.gem {
+            self.gem_out_for_dai_in(in_amount)
+        } else {
+            None
+        }
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        if in_token == self.gem && out_token == self.dai {
+            self.gem_in_for_dai_out(out_amount)
+        } else if in_token == self.dai && out_token == self.gem {
+            self.buy_gem(out_amount)
+        } else {
+            None
+        }
+    }
+
+    fn gas_cost(&self) -> usize {
+        PSM_SWAP_GAS_COST
+    }
+}
+
+/// Pool fetcher for a single Maker PSM.
+pub struct PsmPoolFetcher {
+    psm: MakerPsm,
+    vat: MakerVat,
+    gem: ERC20,
+    gem_join: H160,
+    dai: H160,
+    ilk: [u8; 32],
+    to_18_conversion_factor: U256,
+    web3: Web3,
+}
+
+impl PsmPoolFetcher {
+    /// Creates a new pool fetcher for the deployed USDC PSM.
+    pub async fn new(web3: &Web3) -> Result<Self> {
+        Self::for_psm(MakerPsm::deployed(web3).await?).await
+    }
+
+    /// Creates a new pool fetcher for the specified PSM, reading all the
+    /// information about the PSM that never changes.
+    pub async fn for_psm(psm: MakerPsm) -> Result<Self> {
+        let web3 = psm.raw_instance().web3();
+        let gem_join = MakerGemJoin::at(&web3, psm.gem_join().call().await?);
+        let decimals = gem_join.dec().call().await?;
+        ensure!(
+            decimals <= 18.into(),
+            "unsupported PSM gem decimals {}",
+            decimals
+        );
+
+        Ok(Self {
+            vat: MakerVat::at(&web3, psm.vat().call().await?),
+            gem: ERC20::at(&web3, gem_join.gem().call().await?),
+            gem_join: gem_join.address(),
+            dai: psm.dai().call().await?,
+            ilk: psm.ilk().call().await?.0,
+            to_18_conversion_factor: U256::exp10(18 - decimals.as_usize()),
+            psm,
+            web3,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for PsmPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let pair = match TokenPair::new(self.dai, self.gem.address()) {
+            Some(pair) if token_pairs.contains(&pair) => pair,
+            _ => return Ok(Vec::new()),
+        };
+        tracing::trace!(?pair, "fetching PSM state");
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let tin = self.psm.tin().block(block).batch_call(&mut batch);
+        let tout = self.psm.tout().block(block).batch_call(&mut batch);
+        let ilk = self
+            .vat
+            .ilks(Bytes(self.ilk))
+            .block(block)
+            .batch_call(&mut batch);
+        let gem_reserve = self
+            .gem
+            .balance_of(self.gem_join)
+            .block(block)
+            .batch_call(&mut batch);
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        let pool = handle_results(FetchedPool {
+            address: self.psm.address(),
+            dai: self.dai,
+            gem: self.gem.address(),
+            to_18_conversion_factor: self.to_18_conversion_factor,
+            tin: tin.await,
+            tout: tout.await,
+            ilk: ilk.await,
+            gem_reserve: gem_reserve.await,
+        })?;
+        Ok(pool.into_iter().collect())
+    }
+}
+
+type Ilk = (U256, U256, U256, U256, U256);
+
+struct FetchedPool {
+    address: H160,
+    dai: H160,
+    gem: H160,
+    to_18_conversion_factor: U256,
+    tin: Result<U256, MethodError>,
+    tout: Result<U256, MethodError>,
+    ilk: Result<Ilk, MethodError>,
+    gem_reserve: Result<U256, MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let tin = handle_contract_error(fetched_pool.tin)?;
+    let tout = handle_contract_error(fetched_pool.tout)?;
+    let ilk = handle_contract_error(fetched_pool.ilk)?;
+    let gem_reserve = handle_contract_error(fetched_pool.gem_reserve)?;
+
+    let pool = match (tin, tout, ilk, gem_reserve) {
+        (Some(tin), Some(tout), Some((art, rate, _, line, _)), Some(gem_reserve)) => Some(Pool {
+            address: fetched_pool.address,
+            dai: fetched_pool.dai,
+            gem: fetched_pool.gem,
+            to_18_conversion_factor: fetched_pool.to_18_conversion_factor,
+            tin,
+            tout,
+            gem_reserve,
+            dai_capacity: dai_capacity(art, rate, line),
+        }),
+        _ => None,
+    };
+    Ok(pool)
+}
+
+/// Computes the amount of DAI that can be minted before reaching the debt
+/// ceiling `line` (with 45 decimals) given the normalized debt `art` and its
+/// accumulated `rate` (with 27 decimals).
+fn dai_capacity(art: U256, rate: U256, line: U256) -> U256 {
+    art.checked_mul(rate)
+        .and_then(|debt| line.checked_sub(debt))
+        .map(|capacity| capacity / *RAY)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn usdc_psm() -> Pool {
+        Pool {
+            address: H160([0xff; 20]),
+            dai: H160([1; 20]),
+            gem: H160([2; 20]),
+            to_18_conversion_factor: U256::exp10(12),
+            // 0.1% fee for selling USDC and no fee for buying it.
+            tin: U256::exp10(15),
+            tout: U256::zero(),
+            gem_reserve: U256::from(1_000_000) * U256::exp10(6),
+            dai_capacity: U256::from(500_000) * U256::exp10(18),
+        }
+    }
+
+    #[test]
+    fn sells_gem_for_dai() {
+        let pool = usdc_psm();
+        let (dai, usdc) = (pool.dai, pool.gem);
+
+        assert_eq!(
+            pool.get_amount_out(dai, (U256::from(1_000) * U256::exp10(6), usdc)),
+            Some(U256::from(999) * U256::exp10(18)),
+        );
+        assert_eq!(
+            pool.get_amount_in(usdc, (U256::from(999) * U256::exp10(18), dai)),
+            Some(U256::from(1_000) * U256::exp10(6)),
+        );
+
+        // Rounds up the required USDC amount.
+        let usdc_in = pool.get_amount_in(usdc, (1.into(), dai)).unwrap();
+        assert_eq!(usdc_in, 1.into());
+        assert!(pool.get_amount_out(dai, (usdc_in, usdc)).unwrap() >= 1.into());
+    }
+
+    #[test]
+    fn buys_gem_with_dai() {
+        let pool = Pool {
+            // 0.2% fee for buying USDC.
+            tout: U256::from(2) * U256::exp10(15),
+            ..usdc_psm()
+        };
+        let (dai, usdc) = (pool.dai, pool.gem);
+
+        assert_eq!(
+            pool.get_amount_in(dai, (U256::from(1_000) * U256::exp10(6), usdc)),
+            Some(U256::from(1_002) * U256::exp10(18)),
+        );
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::from(1_002) * U256::exp10(18), dai)),
+            Some(U256::from(1_000) * U256::exp10(6)),
+        );
+    }
+
+    #[test]
+    fn respects_capacity() {
+        let pool = usdc_psm();
+        let (dai, usdc) = (pool.dai, pool.gem);
+
+        // Selling more USDC than the debt ceiling allows.
+        assert_eq!(
+            pool.get_amount_out(dai, (U256::from(500_001) * U256::exp10(6), usdc)),
+            None,
+        );
+        assert_eq!(
+            pool.get_amount_in(usdc, (U256::from(500_000) * U256::exp10(18), dai)),
+            None,
+        );
+
+        // Buying more USDC than the PSM holds.
+        assert_eq!(
+            pool.get_amount_in(dai, (U256::from(1_000_001) * U256::exp10(6), usdc)),
+            None,
+        );
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::from(1_000_001) * U256::exp10(18), dai)),
+            None,
+        );
+    }
+
+    #[test]
+    fn unknown_tokens() {
+        let pool = usdc_psm();
+        assert_eq!(
+            pool.get_amount_out(H160([3; 20]), (1.into(), pool.gem)),
+            None
+        );
+        assert_eq!(pool.get_amount_in(pool.dai, (1.into(), pool.dai)), None);
+    }
+
+    #[test]
+    fn computes_dai_capacity() {
+        let rad = U256::exp10(45);
+        assert_eq!(
+            dai_capacity(
+                U256::from(100) * U256::exp10(18),
+                *RAY,
+                U256::from(1_000) * rad,
+            ),
+            U256::from(900) * U256::exp10(18),
+        );
+        // Debt ceiling was lowered below the current debt.
+        assert_eq!(
+            dai_capacity(U256::from(100) * U256::exp10(18), *RAY, rad),
+            U256::zero(),
+        );
+    }
+
+    fn fetched_pool() -> FetchedPool {
+        FetchedPool {
+            address: H160([0xff; 20]),
+            dai: H160([1; 20]),
+            gem: H160([2; 20]),
+            to_18_conversion_factor: U256::exp10(12),
+            tin: Ok(0.into()),
+            tout: Ok(0.into()),
+            ilk: Ok(Default::default()),
+            gem_reserve: Ok(0.into()),
+        }
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched_pool = FetchedPool {
+            tin: Err(ethcontract_error::testing_node_error()),
+            ..fetched_pool()
+        };
+        assert!(handle_results(fetched_pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched_pool = FetchedPool {
+            ilk: Err(ethcontract_error::testing_contract_error()),
+            ..fetched_pool()
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_none());
+    }
+}
@@ -0,0 +1,445 @@
+//! Generic signed-quote market maker RFQ liquidity source implementation.
+//!
+//! Firm quotes are requested from an operator supplied market maker endpoint.
+//! Market makers sign their quotes with EIP-712 for the domain of the contract
+//! that settles them, and quotes are only accepted if they are signed by the
+//! configured signer. This allows integrating professional market makers
+//! without a dedicated source implementation for each of them.
+
+use super::rfq::{QuoteAmount, QuoteRequest, RfqQuote, RfqQuoting};
+use crate::u256_decimal::{self, DecimalU256};
+use anyhow::{bail, ensure, Context as _, Result};
+use ethcontract::{H160, U256};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use std::time::Duration;
+use web3::{
+    ethabi::{self, Token},
+    signing::{self, keccak256},
+    types::Bytes,
+};
+
+const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+const QUOTE_TYPE: &str = "Quote(address maker,address taker,address sellToken,address buyToken,uint256 sellAmount,uint256 buyAmount,uint256 expiry,uint256 nonce)";
+
+/// The EIP-712 domain that market maker quotes are signed for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Eip712Domain {
+    pub name: String,
+    pub version: String,
+    pub chain_id: u64,
+    /// The contract that verifies the quote signatures when settling them.
+    pub verifying_contract: H160,
+}
+
+impl Eip712Domain {
+    /// Computes the EIP-712 domain separator.
+    pub fn separator(&self) -> [u8; 32] {
+        keccak256(&ethabi::encode(&[
+            Token::FixedBytes(keccak256(DOMAIN_TYPE.as_bytes()).to_vec()),
+            Token::FixedBytes(keccak256(self.name.as_bytes()).to_vec()),
+            Token::FixedBytes(keccak256(self.version.as_bytes()).to_vec()),
+            Token::Uint(self.chain_id.into()),
+            Token::Address(self.verifying_contract),
+        ]))
+    }
+}
+
+/// Configuration for a market maker RFQ source.
+#[derive(Clone, Debug)]
+pub struct MarketMakerConfig {
+    /// The name of the market maker, used for identifying it in logs.
+    pub name: String,
+    pub api_url: Url,
+    /// The API key sent in the `Authorization` header, if any.
+    pub api_key: Option<String>,
+    pub domain: Eip712Domain,
+    /// The address that signs the quotes for the market maker.
+    pub signer: H160,
+    /// The minimum amount of time a quote needs to remain valid for in order
+    /// to be used. This should cover the time it takes to settle the quote.
+    pub min_validity: Duration,
+}
+
+/// RFQ quoter for a market maker issuing EIP-712 signed quotes.
+pub struct MarketMakerRfq {
+    client: Client,
+    config: MarketMakerConfig,
+    domain_separator: [u8; 32],
+}
+
+impl MarketMakerRfq {
+    pub fn new(client: Client, config: MarketMakerConfig) -> Self {
+        let domain_separator = config.domain.separator();
+        Self {
+            client,
+            config,
+            domain_separator,
+        }
+    }
+
+    async fn request_quote(&self, request: &QuoteRequest) -> Result<QuoteResponse> {
+        let (sell_amount, buy_amount) = match request.amount {
+            QuoteAmount::Sell(amount) => (Some(amount), None),
+            QuoteAmount::Buy(amount) => (None, Some(amount)),
+        };
+        let body = RfqRequest {
+            chain_id: self.config.domain.chain_id,
+            sell_token: request.sell_token,
+            buy_token: request.buy_token,
+            sell_amount,
+            buy_amount,
+            taker: request.trader,
+        };
+
+        let mut builder = self.client.post(self.config.api_url.clone()).json(&body);
+        if let Some(api_key) = &self.config.api_key {
+            builder = builder.header("Authorization", api_key);
+        }
+        builder
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("invalid {} RFQ response", self.config.name))
+    }
+
+    /// Converts a signed market maker quote into an RFQ quote, returning
+    /// `None` for quotes that do not match the request or that are about to
+    /// expire.
+    ///
+    /// Returns an error if the quote is not signed by the configured signer.
+    fn convert_quote(
+        &self,
+        request: &QuoteRequest,
+        response: QuoteResponse,
+    ) -> Result<Option<RfqQuote>> {
+        let (quote, signature) = match (response.quote, response.signature) {
+            (Some(quote), Some(signature)) => (quote, signature),
+            _ => return Ok(None),
+        };
+
+        if quote.sell_token != request.sell_token
+            || quote.buy_token != request.buy_token
+            || quote.taker != request.trader
+        {
+            return Ok(None);
+        }
+        let matches_amount = match request.amount {
+            QuoteAmount::Sell(amount) => quote.sell_amount == amount,
+            QuoteAmount::Buy(amount) => quote.buy_amount == amount,
+        };
+        if !matches_amount {
+            return Ok(None);
+        }
+
+        let signer = recover_signer(&quote.signing_digest(&self.domain_separator), &signature.0)?;
+        ensure!(
+            signer == self.config.signer,
+            "{} quote signed by unexpected signer {:?}",
+            self.config.name,
+            signer,
+        );
+
+        let rfq_quote = RfqQuote {
+            maker: quote.maker,
+            sell_token: quote.sell_token,
+            buy_token: quote.buy_token,
+            sell_amount: quote.sell_amount,
+            buy_amount: quote.buy_amount,
+            partially_fillable: false,
+            valid_to: quote.expiry,
+            data: ethabi::encode(&quote.tokens()),
+            signature: signature.0,
+        };
+        if !rfq_quote.is_valid_for(self.config.min_validity) {
+            tracing::debug!(market_maker = %self.config.name, "discarding expiring quote");
+            return Ok(None);
+        }
+
+        Ok(Some(rfq_quote))
+    }
+}
+
+#[async_trait::async_trait]
+impl RfqQuoting for MarketMakerRfq {
+    async fn quote(&self, request: &QuoteRequest) -> Result<Option<RfqQuote>> {
+        let response = self.request_quote(request).await?;
+        self.convert_quote(request, response)
+    }
+}
+
+/// Recovers the signer of an ECDSA signature packed as `r || s || v`.
+fn recover_signer(digest: &[u8; 32], signature: &[u8]) -> Result<H160> {
+    ensure!(
+        signature.len() == 65,
+        "invalid signature length {}",
+        signature.len()
+    );
+    let recovery_id = match signature[64] {
+        v @ 0..=1 => v,
+        v @ 27..=28 => v - 27,
+        v => bail!("invalid signature recovery ID {}", v),
+    };
+    Ok(signing::recover(
+        digest,
+        &signature[..64],
+        recovery_id.into(),
+    )?)
+}
+
+#[serde_as]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RfqRequest {
+    chain_id: u64,
+    sell_token: H160,
+    buy_token: H160,
+    #[serde_as(as = "Option<DecimalU256>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sell_amount: Option<U256>,
+    #[serde_as(as = "Option<DecimalU256>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buy_amount: Option<U256>,
+    taker: H160,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuoteResponse {
+    quote: Option<Quote>,
+    signature: Option<Bytes>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Quote {
+    maker: H160,
+    taker: H160,
+    sell_token: H160,
+    buy_token: H160,
+    #[serde(with = "u256_decimal")]
+    sell_amount: U256,
+    #[serde(with = "u256_decimal")]
+    buy_amount: U256,
+    expiry: u64,
+    #[serde(with = "u256_decimal")]
+    nonce: U256,
+}
+
+impl Quote {
+    fn tokens(&self) -> Vec<Token> {
+        vec![
+            Token::Address(self.maker),
+            Token::Address(self.taker),
+            Token::Address(self.sell_token),
+            Token::Address(self.buy_token),
+            Token::Uint(self.sell_amount),
+            Token::Uint(self.buy_amount),
+            Token::Uint(self.expiry.into()),
+            Token::Uint(self.nonce),
+        ]
+    }
+
+    /// Computes the EIP-712 signing digest of the quote.
+    fn signing_digest(&self, domain_separator: &[u8; 32]) -> [u8; 32] {
+        let mut tokens = vec![Token::FixedBytes(keccak256(QUOTE_TYPE.as_bytes()).to_vec())];
+        tokens.extend(self.tokens());
+        let struct_hash = keccak256(&ethabi::encode(&tokens));
+
+        let mut message = [0u8; 66];
+        message[0..2].copy_from_slice(&[0x19, 0x01]);
+        message[2..34].copy_from_slice(domain_separator);
+        message[34..66].copy_from_slice(&struct_hash);
+        keccak256(&message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+    use serde_json::json;
+
+    /// The address for the private key `0x00..01`, which signed the test
+    /// quote.
+    fn signer() -> H160 {
+        addr!("7e5f4552091a69125d5dfcb7b8c2659029395bdf")
+    }
+
+    fn market_maker() -> MarketMakerRfq {
+        MarketMakerRfq::new(
+            Client::new(),
+            MarketMakerConfig {
+                name: "test".to_owned(),
+                api_url: "http://localhost:8080/quote".parse().unwrap(),
+                api_key: None,
+                domain: Eip712Domain {
+                    name: "MarketMaker".to_owned(),
+                    version: "1".to_owned(),
+                    chain_id: 1,
+                    verifying_contract: H160([0x11; 20]),
+                },
+                signer: signer(),
+                min_validity: Duration::from_secs(10),
+            },
+        )
+    }
+
+    fn request() -> QuoteRequest {
+        QuoteRequest {
+            sell_token: addr!("a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+            buy_token: addr!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+            amount: QuoteAmount::Sell(1_000_000_000.into()),
+            trader: H160([0x42; 20]),
+        }
+    }
+
+    fn response() -> QuoteResponse {
+        serde_json::from_value(json!({
+            "quote": {
+                "maker": "0x2222222222222222222222222222222222222222",
+                "taker": "0x4242424242424242424242424242424242424242",
+                "sellToken": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+                "buyToken": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+                "sellAmount": "1000000000",
+                "buyAmount": "500000000000000000",
+                "expiry": 4102444800_u64,
+                "nonce": "1",
+            },
+            "signature": "0x24d07d4892f9b695f83a310e82b01d9ca636e746487582ae0ba7b7064d3a90825554ab602bc0b65733597fefdfa5a45483c7619cafe0059d79b67c8934516e0a1b",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn computes_signing_digest() {
+        let domain_separator = market_maker().domain_separator;
+        assert_eq!(
+            domain_separator,
+            hex!("83df171127426a8e021742ca2a27e050b148efd9550481f28e6b212474c826c0")
+        );
+        assert_eq!(
+            response().quote.unwrap().signing_digest(&domain_separator),
+            hex!("aef173930433732a20e2e1790f9a2348477ee99213814671ec5621bc08348320")
+        );
+    }
+
+    #[test]
+    fn recovers_signers() {
+        let digest = hex!("aef173930433732a20e2e1790f9a2348477ee99213814671ec5621bc08348320");
+        let mut signature = response().signature.unwrap().0;
+        assert_eq!(recover_signer(&digest, &signature).unwrap(), signer());
+
+        signature[64] -= 27;
+        assert_eq!(recover_signer(&digest, &signature).unwrap(), signer());
+
+        signature[64] = 2;
+        assert!(recover_signer(&digest, &signature).is_err());
+        assert!(recover_signer(&digest, &signature[..64]).is_err());
+    }
+
+    #[test]
+    fn converts_quotes() {
+        let quote = market_maker()
+            .convert_quote(&request(), response())
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.maker, H160([0x22; 20]));
+        assert_eq!(quote.sell_amount, 1_000_000_000.into());
+        assert_eq!(quote.buy_amount, U256::from(5) * U256::exp10(17));
+        assert_eq!(quote.valid_to, 4102444800);
+        assert!(!quote.partially_fillable);
+        assert_eq!(quote.data.len(), 8 * 32);
+        assert_eq!(quote.signature.len(), 65);
+    }
+
+    #[test]
+    fn rejects_quotes_from_other_signers() {
+        let mut market_maker = market_maker();
+        market_maker.config.signer = H160([0x33; 20]);
+        assert!(market_maker.convert_quote(&request(), response()).is_err());
+
+        // Modifying the signed quote changes the recovered signer.
+        let mut response = response();
+        response.quote.as_mut().unwrap().nonce = 2.into();
+        assert!(market_maker().convert_quote(&request(), response).is_err());
+    }
+
+    #[test]
+    fn discards_mismatched_quotes() {
+        let market_maker = market_maker();
+        assert!(market_maker
+            .convert_quote(
+                &QuoteRequest {
+                    amount: QuoteAmount::Sell(1.into()),
+                    ..request()
+                },
+                response()
+            )
+            .unwrap()
+            .is_none());
+        assert!(market_maker
+            .convert_quote(
+                &QuoteRequest {
+                    trader: H160([0x43; 20]),
+                    ..request()
+                },
+                response()
+            )
+            .unwrap()
+            .is_none());
+        assert!(market_maker
+            .convert_quote(
+                &request(),
+                QuoteResponse {
+                    quote: None,
+                    signature: None,
+                }
+            )
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn discards_expiring_quotes() {
+        let mut response = response();
+        response.quote.as_mut().unwrap().expiry = 1;
+        response.signature = Some(Bytes(
+            hex!(
+                "d15bbfee906e2d606303b0d389c12beb30dfe6c58c0601b6c9fde4b11ad04e26"
+                "29bfcb2bfa23505aedea5c9a5a746465a9cdaf1f2550527b195a0d4bf6ef33fa"
+                "1c"
+            )
+            .to_vec(),
+        ));
+        assert!(market_maker()
+            .convert_quote(&request(), response)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn serialize_request() {
+        let request = RfqRequest {
+            chain_id: 1,
+            sell_token: H160([1; 20]),
+            buy_token: H160([2; 20]),
+            sell_amount: None,
+            buy_amount: Some(42.into()),
+            taker: H160([3; 20]),
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({
+                "chainId": 1,
+                "sellToken": "0x0101010101010101010101010101010101010101",
+                "buyToken": "0x0202020202020202020202020202020202020202",
+                "buyAmount": "42",
+                "taker": "0x0303030303030303030303030303030303030303",
+            }),
+        );
+    }
+}
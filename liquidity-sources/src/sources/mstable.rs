@@ -0,0 +1,8 @@
+//! mStable baseline liquidity source implementation.
+//!
+//! mStable mAssets (such as mUSD) and feeder pools (such as the mUSD/GUSD
+//! pool) hold a basket of stablecoins ("bAssets") that can be swapped with
+//! each other using a StableSwap invariant. Swaps are only possible as long as
+//! the bAsset weights in the basket stay within the configured limits.
+mod invariant;
+pub mod pool_fetching;
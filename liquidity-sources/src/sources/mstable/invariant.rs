@@ -0,0 +1,129 @@
+//! Module implementing the mStable StableSwap invariant math.
+//!
+//! This is a port of the `_invariant` and `_solveInvariant` methods of the
+//! `MassetLogic` library, which is shared by mAssets and feeder pools:
+//! <https://github.com/mstable/mStable-contracts/blob/master/contracts/masset/MassetLogic.sol>
+//!
+//! Balances are scaled to 18 decimals with the bAsset ratios. The
+//! amplification parameter `a` has `A_PRECISION` decimals of precision.
+
+use ethcontract::U256;
+
+/// The maximum number of Newton's method iterations for the invariant.
+const MAX_ITERATIONS: usize = 256;
+
+lazy_static::lazy_static! {
+    static ref A_PRECISION: U256 = U256::from(100);
+}
+
+/// Computes the invariant `k` for the specified scaled balances.
+pub fn invariant(x: &[U256], a: U256) -> Option<U256> {
+    let n = U256::from(x.len());
+    let sum = x
+        .iter()
+        .try_fold(U256::zero(), |sum, x| sum.checked_add(*x))?;
+    if sum.is_zero() {
+        return Some(U256::zero());
+    }
+
+    let mut k = sum;
+    for _ in 0..MAX_ITERATIONS {
+        let mut k_p = k;
+        for x in x {
+            k_p = k_p.checked_mul(k)?.checked_div(x.checked_mul(n)?)?;
+        }
+        let k_prev = k;
+        let numerator = (a.checked_mul(sum)? / *A_PRECISION)
+            .checked_add(k_p.checked_mul(n)?)?
+            .checked_mul(k)?;
+        let denominator = (a.checked_sub(*A_PRECISION)?.checked_mul(k)? / *A_PRECISION)
+            .checked_add(n.checked_add(U256::one())?.checked_mul(k_p)?)?;
+        k = numerator.checked_div(denominator)?;
+        if abs_diff(k, k_prev) <= U256::one() {
+            return Some(k);
+        }
+    }
+
+    None
+}
+
+/// Computes the scaled balance of the bAsset at `index` for which the
+/// invariant equals `k`, ignoring its current balance in `x`.
+///
+/// The invariant is quadratic in a single balance, so this is solved
+/// directly instead of with Newton's method. The result is rounded up.
+pub fn solve_invariant(x: &[U256], a: U256, index: usize, k: U256) -> Option<U256> {
+    if index >= x.len() {
+        return None;
+    }
+
+    let n = U256::from(x.len());
+    let mut sum = U256::zero();
+    let mut k_p = k;
+    for (i, x) in x.iter().enumerate() {
+        if i == index {
+            continue;
+        }
+        sum = sum.checked_add(*x)?;
+        k_p = k_p.checked_mul(k)?.checked_div(x.checked_mul(n)?)?;
+    }
+
+    // Solves `y² + b·y - c = 0` with `b = sum + k / Ann - k` and
+    // `c = k^(n+1) / (n^n · prod · Ann)`, where `Ann = a / A_PRECISION`.
+    let c = k_p
+        .checked_mul(k)?
+        .checked_mul(*A_PRECISION)?
+        .checked_div(a.checked_mul(n)?)?;
+    let g = k.checked_mul(*A_PRECISION)?.checked_div(a)?;
+    let sum_g = sum.checked_add(g)?;
+    let b = abs_diff(sum_g, k);
+    let root = b
+        .checked_mul(b)?
+        .checked_add(c.checked_mul(4.into())?)?
+        .integer_sqrt();
+    let y = if sum_g >= k {
+        root.checked_sub(b)?
+    } else {
+        root.checked_add(b)?
+    };
+    (y / 2).checked_add(U256::one())
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced_invariant() {
+        let x = [U256::exp10(21), U256::exp10(21)];
+        let k = invariant(&x, 20_000.into()).unwrap();
+        assert_eq!(k, U256::from(2) * U256::exp10(21));
+        assert_eq!(
+            solve_invariant(&x, 20_000.into(), 1, k).unwrap(),
+            U256::exp10(21) + 1
+        );
+    }
+
+    #[test]
+    fn solves_for_imbalanced_balances() {
+        let a = U256::from(10_000);
+        let x = [
+            U256::from(3_000_000) * U256::exp10(18),
+            U256::from(2_000_000) * U256::exp10(18),
+        ];
+        let k = invariant(&x, a).unwrap();
+
+        // Solving for the current balances returns them rounded up.
+        let y = solve_invariant(&x, a, 0, k).unwrap();
+        assert!(y >= x[0] && y <= x[0] + 2);
+        assert_eq!(solve_invariant(&x, a, 2, k), None);
+    }
+}
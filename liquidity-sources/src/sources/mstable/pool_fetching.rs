@@ -0,0 +1,391 @@
+//! Module for fetching current mStable mAsset and feeder pool state.
+
+use super::invariant;
+use crate::{
+    baseline_solver::BaselineSolvable,
+    fixed_point::{div, Rounding},
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::MStablePool;
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use std::collections::HashSet;
+
+const MSTABLE_SWAP_GAS_COST: usize = 200_000;
+
+/// bAsset statuses that allow swapping, `Default` and `Normal`.
+const MAX_SWAPPABLE_STATUS: u8 = 1;
+
+lazy_static::lazy_static! {
+    static ref ONE: U256 = U256::exp10(18);
+    /// The precision of bAsset ratios.
+    static ref RATIO_SCALE: U256 = U256::exp10(8);
+}
+
+/// Returns the default mAssets and feeder pools to use for a chain.
+pub fn default_pools(chain_id: u64) -> Vec<H160> {
+    match chain_id {
+        1 => vec![
+            // mUSD
+            addr!("e2f2a5C287993345a840Db3B0845fbC70f5935a5"),
+            // mUSD/GUSD feeder pool
+            addr!("4fB30C5A3aC8e85bC32785518633303C4590752d"),
+            // mUSD/BUSD feeder pool
+            addr!("fE842e95f8911dcc21c943a1dAA4bd641a1381c6"),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The current state of an mAsset or feeder pool basket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub address: H160,
+    pub tokens: Vec<TokenState>,
+    /// The amplification parameter with 2 decimals of precision.
+    pub amplification: U256,
+    /// The minimum bAsset weight with 18 decimals of precision.
+    pub min_weight: U256,
+    /// The maximum bAsset weight with 18 decimals of precision.
+    pub max_weight: U256,
+    /// The swap fee with 18 decimals of precision.
+    pub swap_fee: U256,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenState {
+    pub address: H160,
+    pub vault_balance: U256,
+    /// The ratio for scaling balances to 18 decimals, with 8 decimals of
+    /// precision.
+    pub ratio: U256,
+}
+
+impl Pool {
+    /// Returns true if the pool can swap between both tokens of a pair.
+    pub fn contains_pair(&self, pair: TokenPair) -> bool {
+        pair.into_iter()
+            .all(|token| self.token_index(token).is_some())
+    }
+
+    fn token_index(&self, token: H160) -> Option<usize> {
+        self.tokens.iter().position(|state| state.address == token)
+    }
+
+    fn indices(&self, in_token: H160, out_token: H160) -> Option<(usize, usize)> {
+        let (in_index, out_index) = (self.token_index(in_token)?, self.token_index(out_token)?);
+        if in_index == out_index {
+            return None;
+        }
+        Some((in_index, out_index))
+    }
+
+    fn scaled_balances(&self) -> Option<Vec<U256>> {
+        self.tokens
+            .iter()
+            .map(|token| Some(token.vault_balance.checked_mul(token.ratio)? / *RATIO_SCALE))
+            .collect()
+    }
+
+    /// Checks that the weight of the input bAsset does not exceed the maximum
+    /// weight and that the weight of the output bAsset does not fall below
+    /// the minimum weight after a swap.
+    fn check_weights(&self, x: &[U256], in_index: usize, out_index: usize) -> Option<()> {
+        let sum = x
+            .iter()
+            .try_fold(U256::zero(), |sum, x| sum.checked_add(*x))?;
+        let weight = |index: usize| x[index].checked_mul(*ONE)?.checked_div(sum);
+        if weight(in_index)? > self.max_weight || weight(out_index)? < self.min_weight {
+            return None;
+        }
+        Some(())
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let (i, o) = self.indices(in_token, out_token)?;
+        let mut x = self.scaled_balances()?;
+        let k = invariant::invariant(&x, self.amplification)?;
+
+        x[i] = x[i].checked_add(in_amount.checked_mul(self.tokens[i].ratio)? / *RATIO_SCALE)?;
+        let y = invariant::solve_invariant(&x, self.amplification, o, k)?;
+        let gross_out = x[o].checked_sub(y)?;
+        let net_out = gross_out.checked_sub(gross_out.checked_mul(self.swap_fee)? / *ONE)?;
+        x[o] -= net_out;
+        self.check_weights(&x, i, o)?;
+
+        Some(net_out.checked_mul(*RATIO_SCALE)? / self.tokens[o].ratio)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let (i, o) = self.indices(in_token, out_token)?;
+        let mut x = self.scaled_balances()?;
+        let k = invariant::invariant(&x, self.amplification)?;
+
+        let net_out = div(
+            out_amount.checked_mul(self.tokens[o].ratio)?,
+            *RATIO_SCALE,
+            Rounding::Up,
+        )?;
+        let gross_out = div(
+            net_out.checked_mul(*ONE)?,
+            ONE.checked_sub(self.swap_fee)?,
+            Rounding::Up,
+        )?;
+        x[o] = x[o].checked_sub(gross_out)?;
+        if x[o].is_zero() {
+            return None;
+        }
+        let y = invariant::solve_invariant(&x, self.amplification, i, k)?;
+        let scaled_in = y.checked_sub(x[i])?;
+        x[i] = y;
+        self.check_weights(&x, i, o)?;
+
+        div(
+            scaled_in.checked_mul(*RATIO_SCALE)?,
+            self.tokens[i].ratio,
+            Rounding::Up,
+        )
+    }
+
+    fn gas_cost(&self) -> usize {
+        MSTABLE_SWAP_GAS_COST
+    }
+}
+
+/// Pool fetcher for a set of mAssets and feeder pools.
+pub struct MStablePoolFetcher {
+    web3: Web3,
+    pools: Vec<H160>,
+}
+
+impl MStablePoolFetcher {
+    pub fn new(web3: &Web3, pools: Vec<H160>) -> Self {
+        Self {
+            web3: web3.clone(),
+            pools,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for MStablePoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        if token_pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let futures = self
+            .pools
+            .iter()
+            .map(|&address| {
+                let contract = MStablePool::at(&self.web3, address);
+                let bassets = contract.get_bassets().block(block).batch_call(&mut batch);
+                let config = contract.get_config().block(block).batch_call(&mut batch);
+                let data = contract.data().block(block).batch_call(&mut batch);
+                async move {
+                    handle_results(FetchedPool {
+                        address,
+                        bassets: bassets.await,
+                        config: config.await,
+                        data: data.await,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        let pools = future::try_join_all(futures).await?;
+        Ok(pools
+            .into_iter()
+            .flatten()
+            .filter(|pool| token_pairs.iter().any(|pair| pool.contains_pair(*pair)))
+            .collect())
+    }
+}
+
+type BassetPersonal = (H160, H160, bool, u8);
+type BassetData = (u128, u128);
+
+struct FetchedPool {
+    address: H160,
+    bassets: Result<(Vec<BassetPersonal>, Vec<BassetData>), MethodError>,
+    config: Result<(U256, U256, (u128, u128)), MethodError>,
+    /// The leading swap and redemption fees of the pool data. The rest of the
+    /// data layout differs between mAssets and feeder pools and is not needed.
+    data: Result<(U256, U256), MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let bassets = handle_contract_error(fetched_pool.bassets)?;
+    let config = handle_contract_error(fetched_pool.config)?;
+    let data = handle_contract_error(fetched_pool.data)?;
+
+    let ((personal, data), (_, amplification, (min_weight, max_weight)), (swap_fee, _)) =
+        match (bassets, config, data) {
+            (Some(bassets), Some(config), Some(data)) => (bassets, config, data),
+            _ => return Ok(None),
+        };
+
+    // bAssets with transfer fees or that are not in a normal state are not
+    // swappable with the invariant math, so exclude them from the pool.
+    let tokens = personal
+        .into_iter()
+        .zip(data)
+        .filter(|((_, _, has_tx_fee, status), _)| !has_tx_fee && *status <= MAX_SWAPPABLE_STATUS)
+        .map(|((address, ..), (ratio, vault_balance))| TokenState {
+            address,
+            vault_balance: vault_balance.into(),
+            ratio: ratio.into(),
+        })
+        .collect();
+
+    Ok(Some(Pool {
+        address: fetched_pool.address,
+        tokens,
+        amplification,
+        min_weight: min_weight.into(),
+        max_weight: max_weight.into(),
+        swap_fee,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn musd_gusd() -> Pool {
+        // 3M mUSD and 2M GUSD (2 decimals).
+        Pool {
+            address: H160([0xff; 20]),
+            tokens: vec![
+                TokenState {
+                    address: H160([1; 20]),
+                    vault_balance: U256::from(3_000_000) * U256::exp10(18),
+                    ratio: U256::exp10(8),
+                },
+                TokenState {
+                    address: H160([2; 20]),
+                    vault_balance: U256::from(200_000_000),
+                    ratio: U256::exp10(24),
+                },
+            ],
+            amplification: 10_000.into(),
+            min_weight: U256::from(20) * U256::exp10(16),
+            max_weight: U256::from(80) * U256::exp10(16),
+            swap_fee: U256::from(3) * U256::exp10(14),
+        }
+    }
+
+    #[test]
+    fn swaps() {
+        let pool = musd_gusd();
+        let (musd, gusd) = (H160([1; 20]), H160([2; 20]));
+
+        assert_eq!(
+            pool.get_amount_out(gusd, (U256::from(10_000) * U256::exp10(18), musd)),
+            Some(991_154.into())
+        );
+        assert_eq!(
+            pool.get_amount_in(musd, (991_154.into(), gusd)),
+            Some(U256::from_dec_str("9999990207659509991294").unwrap())
+        );
+        assert_eq!(
+            pool.get_amount_out(musd, (1_000_000.into(), gusd)),
+            Some(U256::from_dec_str("10081202682834639077771").unwrap())
+        );
+        assert_eq!(
+            pool.get_amount_in(
+                gusd,
+                (U256::from_dec_str("10081202682834639077771").unwrap(), musd)
+            ),
+            Some(1_000_001.into())
+        );
+    }
+
+    #[test]
+    fn respects_weight_limits() {
+        let pool = musd_gusd();
+        let (musd, gusd) = (H160([1; 20]), H160([2; 20]));
+
+        assert_eq!(
+            pool.get_amount_out(gusd, (U256::from(2_000_000) * U256::exp10(18), musd)),
+            None
+        );
+        assert_eq!(pool.get_amount_out(musd, (1.into(), musd)), None);
+    }
+
+    #[test]
+    fn excludes_unswappable_bassets() {
+        let fetched_pool = FetchedPool {
+            address: H160([0xff; 20]),
+            bassets: Ok((
+                vec![
+                    (H160([1; 20]), H160::zero(), false, 1),
+                    (H160([2; 20]), H160::zero(), true, 1),
+                    (H160([3; 20]), H160::zero(), false, 2),
+                ],
+                vec![(100_000_000, 1), (100_000_000, 2), (100_000_000, 3)],
+            )),
+            config: Ok((0.into(), 10_000.into(), (1, 2))),
+            data: Ok((3.into(), 4.into())),
+        };
+
+        assert_eq!(
+            handle_results(fetched_pool).unwrap().unwrap(),
+            Pool {
+                address: H160([0xff; 20]),
+                tokens: vec![TokenState {
+                    address: H160([1; 20]),
+                    vault_balance: 1.into(),
+                    ratio: 100_000_000.into(),
+                }],
+                amplification: 10_000.into(),
+                min_weight: 1.into(),
+                max_weight: 2.into(),
+                swap_fee: 3.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn default_pools_for_chains() {
+        assert_eq!(default_pools(1).len(), 3);
+        assert!(default_pools(100).is_empty());
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched_pool = FetchedPool {
+            address: H160([0xff; 20]),
+            bassets: Err(ethcontract_error::testing_node_error()),
+            config: Ok(Default::default()),
+            data: Ok(Default::default()),
+        };
+        assert!(handle_results(fetched_pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched_pool = FetchedPool {
+            address: H160([0xff; 20]),
+            bassets: Ok(Default::default()),
+            config: Err(ethcontract_error::testing_contract_error()),
+            data: Ok(Default::default()),
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_none());
+    }
+}
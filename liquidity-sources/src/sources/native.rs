@@ -0,0 +1,184 @@
+//! Native token wrapping baseline liquidity source implementation.
+//!
+//! The native token of a chain (Ether, or xDAI on Gnosis Chain) can always be
+//! wrapped into and unwrapped from its wrapped token (WETH or wxDAI) at 1:1.
+//! This source exposes wrapping as liquidity between the native token sentinel
+//! address and the wrapped token, so that routes can include native token
+//! legs. Wrapping has no state, so pools are returned without any RPC
+//! requests.
+
+use crate::{
+    baseline_solver::BaselineSolvable, chain_config::ChainConfig, recent_block_cache::Block,
+    token_pair::TokenPair, Web3,
+};
+use anyhow::{bail, Result};
+use contracts::WETH9;
+use ethcontract::{H160, U256};
+use std::collections::HashSet;
+
+const WRAPPER_GAS_COST: usize = 40_000;
+
+/// The sentinel address representing the native token of a chain.
+pub const NATIVE_TOKEN: H160 = H160([0xee; 20]);
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// Wrapping between the native token and its wrapped token.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub native: H160,
+    pub wrapped: H160,
+}
+
+impl Pool {
+    fn converts(&self, in_token: H160, out_token: H160) -> bool {
+        (in_token, out_token) == (self.native, self.wrapped)
+            || (in_token, out_token) == (self.wrapped, self.native)
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        self.converts(in_token, out_token).then(|| in_amount)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        self.converts(in_token, out_token).then(|| out_amount)
+    }
+
+    fn gas_cost(&self) -> usize {
+        WRAPPER_GAS_COST
+    }
+}
+
+/// Pool fetcher for wrapping the native token.
+pub struct NativePoolFetcher {
+    pool: Pool,
+}
+
+impl NativePoolFetcher {
+    /// Creates a new pool fetcher for the wrapped native token of the chain.
+    pub async fn new(web3: &Web3) -> Result<Self> {
+        let wrapped = WETH9::deployed(web3).await?;
+        Ok(Self::with_wrapped_token(wrapped.address()))
+    }
+
+    /// Creates a new pool fetcher for the wrapped native token of the specified
+    /// chain configuration.
+    pub fn from_config(config: &ChainConfig) -> Result<Self> {
+        match config.wrapped_native_token {
+            Some(wrapped) => Ok(Self::with_wrapped_token(wrapped)),
+            None => bail!("no wrapped native token on chain {}", config.chain_id),
+        }
+    }
+
+    /// Creates a new pool fetcher for the specified wrapped native token.
+    pub fn with_wrapped_token(wrapped: H160) -> Self {
+        Self {
+            pool: Pool {
+                native: NATIVE_TOKEN,
+                wrapped,
+            },
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for NativePoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, _: Block) -> Result<Vec<Pool>> {
+        match TokenPair::new(self.pool.native, self.pool.wrapped) {
+            Some(pair) if token_pairs.contains(&pair) => Ok(vec![self.pool]),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashset;
+
+    fn weth() -> H160 {
+        addr!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")
+    }
+
+    #[test]
+    fn native_token_sentinel() {
+        assert_eq!(
+            NATIVE_TOKEN,
+            addr!("eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee")
+        );
+    }
+
+    #[test]
+    fn wraps_at_par() {
+        let pool = Pool {
+            native: NATIVE_TOKEN,
+            wrapped: weth(),
+        };
+
+        assert_eq!(
+            pool.get_amount_out(weth(), (42.into(), NATIVE_TOKEN)),
+            Some(42.into())
+        );
+        assert_eq!(
+            pool.get_amount_out(NATIVE_TOKEN, (42.into(), weth())),
+            Some(42.into())
+        );
+        assert_eq!(
+            pool.get_amount_in(NATIVE_TOKEN, (42.into(), weth())),
+            Some(42.into())
+        );
+        assert_eq!(
+            pool.get_amount_in(weth(), (42.into(), NATIVE_TOKEN)),
+            Some(42.into())
+        );
+        assert_eq!(
+            pool.get_amount_out(H160([1; 20]), (42.into(), weth())),
+            None
+        );
+        assert_eq!(pool.get_amount_out(weth(), (42.into(), weth())), None);
+    }
+
+    #[test]
+    fn wrapped_token_from_config() {
+        let fetcher = NativePoolFetcher::from_config(ChainConfig::for_chain(1).unwrap()).unwrap();
+        assert_eq!(fetcher.pool.wrapped, weth());
+        assert!(NativePoolFetcher::from_config(ChainConfig::for_chain(42220).unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn fetches_pool_for_requested_pair() {
+        let fetcher = NativePoolFetcher::with_wrapped_token(weth());
+
+        let pools = fetcher
+            .fetch(
+                hashset! {
+                    TokenPair::new(NATIVE_TOKEN, weth()).unwrap(),
+                    TokenPair::new(weth(), H160([1; 20])).unwrap(),
+                },
+                Block::Recent,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            pools,
+            vec![Pool {
+                native: NATIVE_TOKEN,
+                wrapped: weth(),
+            }]
+        );
+
+        let pools = fetcher
+            .fetch(
+                hashset! { TokenPair::new(weth(), H160([1; 20])).unwrap() },
+                Block::Recent,
+            )
+            .await
+            .unwrap();
+        assert!(pools.is_empty());
+    }
+}
@@ -0,0 +1,30 @@
+//! PancakeSwap baseline liquidity source implementation.
+
+use super::uniswap_v2::macros::impl_uniswap_like_liquidity;
+
+impl_uniswap_like_liquidity! {
+    factory: contracts::PancakeSwapFactory,
+    init_code_digest: "00fb7f630766e6a796048ea87d01acd3068e8ff67d078148a3fa3f4a84f69bd5",
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token_pair::TokenPair;
+    use ethcontract_mock::Mock;
+
+    #[tokio::test]
+    async fn test_create2_pancakeswap() {
+        // BNB Chain
+        let (bnb_pair_provider, _) = get_liquidity_source(&Mock::new(56).web3()).await.unwrap();
+        let bnb_pair = TokenPair::new(
+            addr!("bb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c"),
+            addr!("e9e7CEA3DedcA5984780Bafc599bD69ADd087D56"),
+        )
+        .unwrap();
+        assert_eq!(
+            bnb_pair_provider.pair_address(&bnb_pair),
+            addr!("58F876857a02D6762E0101bb5C46A8c1ED44Dc16")
+        );
+    }
+}
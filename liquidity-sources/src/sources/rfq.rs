@@ -0,0 +1,222 @@
+//! Common types for request-for-quote (RFQ) liquidity sources.
+//!
+//! Unlike on-chain pools, RFQ liquidity is provided by off-chain market makers
+//! that return signed firm quotes for a specific trade. These quotes are only
+//! valid for a short amount of time and can only be settled with the maker
+//! that issued them.
+
+use crate::baseline_solver::BaselineSolvable;
+use anyhow::Result;
+use ethcontract::{H160, U256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const RFQ_GAS_COST: usize = 110_000;
+
+#[async_trait::async_trait]
+pub trait RfqQuoting: Send + Sync {
+    /// Requests a firm quote for the specified trade, returning `None` if no
+    /// maker is willing to quote it.
+    async fn quote(&self, request: &QuoteRequest) -> Result<Option<RfqQuote>>;
+}
+
+/// A request for a firm quote.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QuoteRequest {
+    pub sell_token: H160,
+    pub buy_token: H160,
+    pub amount: QuoteAmount,
+    /// The address that will settle the quote.
+    pub trader: H160,
+}
+
+/// The fixed side of a quote request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuoteAmount {
+    Sell(U256),
+    Buy(U256),
+}
+
+/// A signed firm quote from an RFQ market maker.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RfqQuote {
+    /// The maker contract that the quote needs to be settled with.
+    pub maker: H160,
+    pub sell_token: H160,
+    pub buy_token: H160,
+    pub sell_amount: U256,
+    pub buy_amount: U256,
+    /// Whether or not the quote can be settled for a fraction of its amounts,
+    /// in which case the amounts are scaled pro-rata.
+    pub partially_fillable: bool,
+    /// The Unix timestamp in seconds after which the quote can no longer be
+    /// settled.
+    pub valid_to: u64,
+    /// Opaque maker specific quote data required for settling the quote.
+    pub data: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl RfqQuote {
+    /// Returns `true` if the quote is still valid for at least the specified
+    /// duration.
+    pub fn is_valid_for(&self, duration: Duration) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.is_valid_at(now + duration)
+    }
+
+    fn is_valid_at(&self, time: Duration) -> bool {
+        time.as_secs() < self.valid_to
+    }
+}
+
+/// Returns the amount out for filling an order or quote that trades
+/// `total_in` for `total_out` with the specified amount in.
+///
+/// Partially fillable amounts are scaled pro-rata, rounding down. Otherwise
+/// only the full amount in can be filled.
+pub(crate) fn fill_amount_out(
+    in_amount: U256,
+    (total_in, total_out): (U256, U256),
+    partially_fillable: bool,
+) -> Option<U256> {
+    if in_amount == total_in {
+        Some(total_out)
+    } else if partially_fillable && in_amount < total_in {
+        scale(in_amount, total_out, total_in)
+    } else {
+        None
+    }
+}
+
+/// Returns the amount in for filling an order or quote that trades
+/// `total_in` for `total_out` with the specified amount out.
+///
+/// Partially fillable amounts are scaled pro-rata, rounding up so that a fill
+/// is never at a better price than the one of the order or quote. Otherwise
+/// only the full amount out can be filled.
+pub(crate) fn fill_amount_in(
+    out_amount: U256,
+    (total_in, total_out): (U256, U256),
+    partially_fillable: bool,
+) -> Option<U256> {
+    if out_amount == total_out {
+        Some(total_in)
+    } else if partially_fillable && out_amount < total_out {
+        scale_up(out_amount, total_in, total_out)
+    } else {
+        None
+    }
+}
+
+fn scale(amount: U256, numerator: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    Some(amount.checked_mul(numerator)? / denominator)
+}
+
+fn scale_up(amount: U256, numerator: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let (quotient, remainder) = amount.checked_mul(numerator)?.div_mod(denominator);
+    if remainder.is_zero() {
+        Some(quotient)
+    } else {
+        quotient.checked_add(U256::one())
+    }
+}
+
+impl BaselineSolvable for RfqQuote {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        if in_token != self.sell_token || out_token != self.buy_token {
+            return None;
+        }
+        fill_amount_out(
+            in_amount,
+            (self.sell_amount, self.buy_amount),
+            self.partially_fillable,
+        )
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        if in_token != self.sell_token || out_token != self.buy_token {
+            return None;
+        }
+        fill_amount_in(
+            out_amount,
+            (self.sell_amount, self.buy_amount),
+            self.partially_fillable,
+        )
+    }
+
+    fn gas_cost(&self) -> usize {
+        RFQ_GAS_COST
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(partially_fillable: bool) -> RfqQuote {
+        RfqQuote {
+            maker: H160([0xff; 20]),
+            sell_token: H160([1; 20]),
+            buy_token: H160([2; 20]),
+            sell_amount: 300.into(),
+            buy_amount: 200.into(),
+            partially_fillable,
+            valid_to: 1_000,
+            data: Vec::new(),
+            signature: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn full_fill() {
+        let quote = quote(false);
+        let (sell, buy) = (quote.sell_token, quote.buy_token);
+
+        assert_eq!(
+            quote.get_amount_out(buy, (300.into(), sell)),
+            Some(200.into())
+        );
+        assert_eq!(
+            quote.get_amount_in(sell, (200.into(), buy)),
+            Some(300.into())
+        );
+        assert_eq!(quote.get_amount_out(buy, (100.into(), sell)), None);
+        assert_eq!(quote.get_amount_out(sell, (200.into(), buy)), None);
+    }
+
+    #[test]
+    fn partial_fill() {
+        let quote = quote(true);
+        let (sell, buy) = (quote.sell_token, quote.buy_token);
+
+        assert_eq!(
+            quote.get_amount_out(buy, (100.into(), sell)),
+            Some(66.into())
+        );
+        assert_eq!(
+            quote.get_amount_in(sell, (100.into(), buy)),
+            Some(150.into())
+        );
+        assert_eq!(
+            quote.get_amount_in(sell, (101.into(), buy)),
+            Some(152.into())
+        );
+        assert_eq!(quote.get_amount_out(buy, (301.into(), sell)), None);
+    }
+
+    #[test]
+    fn validity() {
+        let quote = quote(false);
+        assert!(quote.is_valid_at(Duration::from_secs(999)));
+        assert!(!quote.is_valid_at(Duration::from_secs(1_000)));
+        assert!(!quote.is_valid_for(Duration::ZERO));
+    }
+}
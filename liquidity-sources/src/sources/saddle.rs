@@ -0,0 +1,7 @@
+//! Saddle stable pool baseline liquidity source implementation.
+//!
+//! Pools are discovered from the Saddle pool registry of the chain. Saddle
+//! pools use the Curve StableSwap invariant, so fetched pools are represented
+//! as Curve pools. Metapools are not supported.
+pub mod pool_fetching;
+pub mod registry;
@@ -0,0 +1,274 @@
+//! Module for fetching current Saddle pool state for token pairs.
+//!
+//! Saddle pools implement the Curve StableSwap invariant with the same
+//! precision for the amplification parameter and swap fee, so their state is
+//! converted to Curve pools and reuses the shared StableSwap math.
+
+use super::registry::PoolRegistry;
+use crate::{
+    maintenance::Maintaining,
+    recent_block_cache::Block,
+    sources::{
+        curve::{
+            pool_fetching::{Pool, PoolFetching, TokenState},
+            registry::PoolInfo,
+        },
+        uniswap_v2::pool_fetching::handle_contract_error,
+        MAX_BATCH_SIZE,
+    },
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::SaddleSwap;
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use std::collections::HashSet;
+
+/// The result of the pool `swapStorage` method.
+type SwapStorage = (U256, U256, U256, U256, U256, U256, H160);
+
+/// Saddle pool fetcher for pools indexed from the on-chain pool registry.
+pub struct SaddlePoolFetcher {
+    registry: PoolRegistry,
+    web3: Web3,
+}
+
+impl SaddlePoolFetcher {
+    /// Creates a new pool fetcher for the pools of the Saddle pool registry at
+    /// the specified address.
+    pub async fn new(web3: &Web3, registry: H160) -> Result<Self> {
+        Ok(Self {
+            registry: PoolRegistry::new(web3, registry).await?,
+            web3: web3.clone(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for SaddlePoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let futures = self
+            .registry
+            .pools_for_token_pairs(&token_pairs)
+            .into_iter()
+            .map(|info| {
+                let pool = SaddleSwap::at(&self.web3, info.address);
+                let paused = pool.paused().block(block).batch_call(&mut batch);
+                let amplification_parameter = pool.get_a().block(block).batch_call(&mut batch);
+                let swap_storage = pool.swap_storage().block(block).batch_call(&mut batch);
+                let balances = (0..info.coins.len())
+                    .map(|index| {
+                        pool.get_token_balance(index as u8)
+                            .block(block)
+                            .batch_call(&mut batch)
+                    })
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut fetched_balances = Vec::new();
+                    for balance in balances {
+                        fetched_balances.push(balance.await);
+                    }
+                    handle_results(FetchedPool {
+                        info,
+                        paused: paused.await,
+                        amplification_parameter: amplification_parameter.await,
+                        swap_storage: swap_storage.await,
+                        balances: fetched_balances,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        let pools = future::try_join_all(futures).await?;
+        Ok(pools
+            .into_iter()
+            .flatten()
+            .filter(|pool| token_pairs.iter().any(|pair| pool.contains_pair(*pair)))
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for SaddlePoolFetcher {
+    async fn run_maintenance(&self) -> Result<()> {
+        self.registry.run_maintenance().await
+    }
+}
+
+struct FetchedPool {
+    info: PoolInfo,
+    paused: Result<bool, MethodError>,
+    amplification_parameter: Result<U256, MethodError>,
+    swap_storage: Result<SwapStorage, MethodError>,
+    balances: Vec<Result<U256, MethodError>>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let paused = handle_contract_error(fetched_pool.paused)?;
+    let amplification_parameter = handle_contract_error(fetched_pool.amplification_parameter)?;
+    let swap_storage = handle_contract_error(fetched_pool.swap_storage)?;
+    let mut balances = Vec::new();
+    for balance in fetched_pool.balances {
+        balances.push(handle_contract_error(balance)?);
+    }
+
+    // Swaps revert while a pool is paused.
+    let (amplification_parameter, (_, _, _, _, fee, _, _)) =
+        match (paused, amplification_parameter, swap_storage) {
+            (Some(false), Some(amplification_parameter), Some(swap_storage)) => {
+                (amplification_parameter, swap_storage)
+            }
+            _ => return Ok(None),
+        };
+    let balances = match balances.into_iter().collect::<Option<Vec<_>>>() {
+        Some(balances) => balances,
+        None => return Ok(None),
+    };
+
+    let tokens = fetched_pool
+        .info
+        .coins
+        .iter()
+        .zip(balances)
+        .map(|(coin, balance)| TokenState {
+            address: coin.address,
+            balance,
+            // Saddle normalizes balances to 18 decimals, which corresponds to
+            // a Curve rate of `10^(18 - decimals)` with 18 decimals of
+            // precision.
+            rate: U256::exp10(36 - coin.decimals as usize),
+        })
+        .collect();
+
+    Ok(Some(Pool {
+        address: fetched_pool.info.address,
+        tokens,
+        amplification_parameter,
+        fee,
+        underlying: false,
+        base_pool: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        baseline_solver::BaselineSolvable, ethcontract_error, sources::curve::registry::Coin,
+    };
+
+    fn fetched_pool() -> FetchedPool {
+        // A DAI (18 decimals) and USDC (6 decimals) pool.
+        FetchedPool {
+            info: PoolInfo {
+                address: H160([0xff; 20]),
+                coins: vec![
+                    Coin {
+                        address: H160([1; 20]),
+                        decimals: 18,
+                    },
+                    Coin {
+                        address: H160([2; 20]),
+                        decimals: 6,
+                    },
+                ],
+                underlying_coins: None,
+                base_pool: None,
+            },
+            paused: Ok(false),
+            amplification_parameter: Ok(200.into()),
+            swap_storage: Ok((
+                20_000.into(),
+                20_000.into(),
+                0.into(),
+                0.into(),
+                4_000_000.into(),
+                5_000_000_000u64.into(),
+                H160([0xee; 20]),
+            )),
+            balances: vec![
+                Ok(U256::from(1_000_000) * U256::exp10(18)),
+                Ok(U256::from(1_200_000) * U256::exp10(6)),
+            ],
+        }
+    }
+
+    #[test]
+    fn converts_pool_state() {
+        let pool = handle_results(fetched_pool()).unwrap().unwrap();
+        assert_eq!(
+            pool,
+            Pool {
+                address: H160([0xff; 20]),
+                tokens: vec![
+                    TokenState {
+                        address: H160([1; 20]),
+                        balance: U256::from(1_000_000) * U256::exp10(18),
+                        rate: U256::exp10(18),
+                    },
+                    TokenState {
+                        address: H160([2; 20]),
+                        balance: U256::from(1_200_000) * U256::exp10(6),
+                        rate: U256::exp10(30),
+                    },
+                ],
+                amplification_parameter: 200.into(),
+                fee: 4_000_000.into(),
+                underlying: false,
+                base_pool: None,
+            }
+        );
+    }
+
+    #[test]
+    fn swaps_match_saddle_calculate_swap() {
+        // Expected amounts were computed with the `SwapUtils.calculateSwap`
+        // implementation of the Saddle contracts.
+        let pool = handle_results(fetched_pool()).unwrap().unwrap();
+        let (dai, usdc) = (H160([1; 20]), H160([2; 20]));
+
+        assert_eq!(
+            pool.get_amount_out(dai, (U256::from(1_000) * U256::exp10(6), usdc)),
+            Some(U256::from_dec_str("998676474062344666072").unwrap()),
+        );
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::from(1_000) * U256::exp10(18), dai)),
+            Some(1_000_514_875.into()),
+        );
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::from(123_456_789) * U256::exp10(12), dai)),
+            Some(123_520_868.into()),
+        );
+    }
+
+    #[test]
+    fn skips_paused_pools() {
+        let pool = FetchedPool {
+            paused: Ok(true),
+            ..fetched_pool()
+        };
+        assert_eq!(handle_results(pool).unwrap(), None);
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let pool = FetchedPool {
+            balances: vec![Ok(1.into()), Err(ethcontract_error::testing_node_error())],
+            ..fetched_pool()
+        };
+        assert!(handle_results(pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let pool = FetchedPool {
+            amplification_parameter: Err(ethcontract_error::testing_contract_error()),
+            ..fetched_pool()
+        };
+        assert_eq!(handle_results(pool).unwrap(), None);
+    }
+}
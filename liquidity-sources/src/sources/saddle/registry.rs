@@ -0,0 +1,227 @@
+//! Module for indexing Saddle pools from the on-chain pool registry.
+//!
+//! Only static pool information (tokens and their decimals) is indexed here,
+//! current pool state is fetched on demand by the pool fetcher. Pools are
+//! indexed with the Curve pool information type, as Saddle pools are plain
+//! StableSwap pools.
+
+use crate::{
+    maintenance::Maintaining,
+    sources::{
+        curve::registry::{Coin, PoolInfo},
+        uniswap_v2::pool_fetching::handle_contract_error,
+        MAX_BATCH_SIZE,
+    },
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::{SaddlePoolRegistry, ERC20};
+use ethcontract::{Bytes, H160};
+use futures::future;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::RwLock,
+};
+
+/// The result of the registry `getPoolDataAtIndex` method.
+type PoolData = (
+    H160,
+    H160,
+    u8,
+    Bytes<[u8; 32]>,
+    H160,
+    Vec<H160>,
+    Vec<H160>,
+    H160,
+    H160,
+    bool,
+    bool,
+    bool,
+);
+
+/// Registry of indexed Saddle pools.
+pub struct PoolRegistry {
+    registry: SaddlePoolRegistry,
+    pools: RwLock<IndexedPools>,
+}
+
+#[derive(Default)]
+struct IndexedPools {
+    /// The number of registry pools that were indexed so far.
+    indexed: usize,
+    by_address: HashMap<H160, PoolInfo>,
+    by_pair: HashMap<TokenPair, HashSet<H160>>,
+}
+
+impl PoolRegistry {
+    /// Creates a new pool registry, indexing all pools from the Saddle pool
+    /// registry at the specified address.
+    pub async fn new(web3: &Web3, registry: H160) -> Result<Self> {
+        let pool_registry = Self {
+            registry: SaddlePoolRegistry::at(web3, registry),
+            pools: Default::default(),
+        };
+        pool_registry.update().await?;
+
+        Ok(pool_registry)
+    }
+
+    /// Returns the static information of all pools that can trade any of the
+    /// specified token pairs.
+    pub fn pools_for_token_pairs(&self, token_pairs: &HashSet<TokenPair>) -> Vec<PoolInfo> {
+        let pools = self.pools.read().unwrap();
+        token_pairs
+            .iter()
+            .filter_map(|pair| pools.by_pair.get(pair))
+            .flatten()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|address| pools.by_address[address].clone())
+            .collect()
+    }
+
+    /// Indexes pools that were added to the registry since the last update.
+    ///
+    /// Note that pools removed from the registry after they were indexed are
+    /// not removed from the index.
+    async fn update(&self) -> Result<()> {
+        let pool_count = self.registry.get_pools_length().call().await?.as_usize();
+        let indexed = self.pools.read().unwrap().indexed;
+        if pool_count <= indexed {
+            return Ok(());
+        }
+
+        let new_pools = self.fetch_pool_infos(indexed..pool_count).await?;
+        tracing::debug!(count = %new_pools.len(), "indexed new Saddle pools");
+
+        let mut pools = self.pools.write().unwrap();
+        if pools.indexed != indexed {
+            // A concurrent update already indexed these pools.
+            return Ok(());
+        }
+        for pool in new_pools {
+            for pair in pool.token_pairs() {
+                pools.by_pair.entry(pair).or_default().insert(pool.address);
+            }
+            pools.by_address.insert(pool.address, pool);
+        }
+        pools.indexed = pool_count;
+
+        Ok(())
+    }
+
+    async fn fetch_pool_infos(&self, indices: Range<usize>) -> Result<Vec<PoolInfo>> {
+        let web3 = self.registry.raw_instance().web3();
+
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let pool_data = indices
+            .map(|index| {
+                self.registry
+                    .get_pool_data_at_index(index.into())
+                    .batch_call(&mut batch)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let pools = future::try_join_all(pool_data)
+            .await?
+            .into_iter()
+            .filter_map(supported_pool)
+            .collect::<Vec<_>>();
+
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let pool_infos = pools
+            .into_iter()
+            .map(|(address, tokens)| {
+                let decimals = tokens
+                    .iter()
+                    .map(|token| ERC20::at(&web3, *token).decimals().batch_call(&mut batch))
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut coins = Vec::new();
+                    for (token, decimals) in tokens.into_iter().zip(decimals) {
+                        match handle_contract_error(decimals.await)? {
+                            Some(decimals) if decimals <= 18 => coins.push(Coin {
+                                address: token,
+                                decimals,
+                            }),
+                            _ => {
+                                tracing::warn!(pool = ?address, ?token, "skipping unsupported Saddle pool");
+                                return Ok(None);
+                            }
+                        }
+                    }
+                    Result::<_>::Ok(Some(PoolInfo {
+                        address,
+                        coins,
+                        underlying_coins: None,
+                        base_pool: None,
+                    }))
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(pool_infos)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for PoolRegistry {
+    async fn run_maintenance(&self) -> Result<()> {
+        self.update().await
+    }
+}
+
+/// Returns the address and tokens of a registry pool, or `None` if the pool is
+/// not supported.
+fn supported_pool(pool_data: PoolData) -> Option<(H160, Vec<H160>)> {
+    let (address, _, _, _, _, tokens, _, base_pool, _, _, removed, _) = pool_data;
+    // Metapools trade their tokens against the LP token of a base pool, which
+    // requires different math.
+    if removed || !base_pool.is_zero() || tokens.len() < 2 {
+        return None;
+    }
+    Some((address, tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool_data(tokens: usize, base_pool: H160, removed: bool) -> PoolData {
+        (
+            H160([0xff; 20]),
+            H160([0xee; 20]),
+            1,
+            Bytes([0; 32]),
+            H160::zero(),
+            (1..=tokens as u8).map(|byte| H160([byte; 20])).collect(),
+            Vec::new(),
+            base_pool,
+            H160::zero(),
+            true,
+            removed,
+            false,
+        )
+    }
+
+    #[test]
+    fn filters_supported_pools() {
+        assert_eq!(
+            supported_pool(pool_data(3, H160::zero(), false)),
+            Some((
+                H160([0xff; 20]),
+                vec![H160([1; 20]), H160([2; 20]), H160([3; 20])]
+            )),
+        );
+        assert_eq!(supported_pool(pool_data(3, H160::zero(), true)), None);
+        assert_eq!(supported_pool(pool_data(2, H160([0xbb; 20]), false)), None);
+        assert_eq!(supported_pool(pool_data(1, H160::zero(), false)), None);
+    }
+}
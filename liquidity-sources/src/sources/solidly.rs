@@ -0,0 +1,8 @@
+//! Solidly baseline liquidity source implementation.
+//!
+//! Solidly forks such as Velodrome on Optimism and Aerodrome on Base have two
+//! kinds of pools: volatile pools using the constant product `x * y = k`
+//! invariant and stable pools using the `x³y + xy³ = k` invariant. Each pool
+//! has its own fee, which is read from the pool factory.
+pub mod pool_fetching;
+mod stable_math;
@@ -0,0 +1,427 @@
+//! Module for fetching Solidly pools from the pool factory.
+
+use super::stable_math;
+use crate::{
+    baseline_solver::BaselineSolvable,
+    fixed_point::{div, Rounding},
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::{SolidlyPool, SolidlyPoolFactory};
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+const VOLATILE_POOL_SWAP_GAS_COST: usize = 90_000;
+const STABLE_POOL_SWAP_GAS_COST: usize = 130_000;
+
+lazy_static::lazy_static! {
+    static ref ONE: U256 = U256::exp10(18);
+    /// Pool fees are specified in basis points.
+    static ref FEE_DENOMINATOR: U256 = U256::from(10_000);
+}
+
+/// The result of the pool `metadata` method: token scaling factors, reserves,
+/// whether or not the pool is stable and the pool tokens.
+type Metadata = (U256, U256, U256, U256, bool, H160, H160);
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The state of a Solidly pool.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub address: H160,
+    pub tokens: (H160, H160),
+    pub reserves: (U256, U256),
+    /// The scaling factors (`10^decimals`) of the pool tokens.
+    pub decimals: (U256, U256),
+    pub stable: bool,
+    /// The pool fee in basis points.
+    pub fee: U256,
+}
+
+impl Pool {
+    /// Returns the reserves and scaling factors ordered as input and output.
+    fn relative(&self, in_token: H160, out_token: H160) -> Option<Relative> {
+        if (in_token, out_token) == self.tokens {
+            Some(Relative {
+                reserves: self.reserves,
+                decimals: self.decimals,
+            })
+        } else if (out_token, in_token) == self.tokens {
+            Some(Relative {
+                reserves: (self.reserves.1, self.reserves.0),
+                decimals: (self.decimals.1, self.decimals.0),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn k(&self) -> Option<U256> {
+        stable_math::k(self.reserves.0, self.reserves.1, self.decimals)
+    }
+}
+
+/// Pool reserves and token scaling factors ordered as `(in, out)`.
+struct Relative {
+    reserves: (U256, U256),
+    decimals: (U256, U256),
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let relative = self.relative(in_token, out_token)?;
+        let (reserve_in, reserve_out) = relative.reserves;
+        if in_amount.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+            return None;
+        }
+
+        let fee = in_amount.checked_mul(self.fee)? / *FEE_DENOMINATOR;
+        let in_amount = in_amount.checked_sub(fee)?;
+
+        let out_amount = if self.stable {
+            let (decimals_in, decimals_out) = relative.decimals;
+            let xy = self.k()?;
+            let reserve_in = scale_to_18_decimals(reserve_in, decimals_in)?;
+            let reserve_out = scale_to_18_decimals(reserve_out, decimals_out)?;
+            let in_amount = scale_to_18_decimals(in_amount, decimals_in)?;
+
+            let y = stable_math::get_y(
+                in_amount.checked_add(reserve_in)?,
+                xy,
+                reserve_out,
+                self.decimals,
+            )?;
+            reserve_out.checked_sub(y)?.checked_mul(decimals_out)? / *ONE
+        } else {
+            in_amount.checked_mul(reserve_out)? / reserve_in.checked_add(in_amount)?
+        };
+
+        if out_amount.is_zero() || out_amount >= reserve_out {
+            return None;
+        }
+        Some(out_amount)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let relative = self.relative(in_token, out_token)?;
+        let (reserve_in, reserve_out) = relative.reserves;
+        if out_amount.is_zero() || out_amount >= reserve_out || reserve_in.is_zero() {
+            return None;
+        }
+
+        let in_amount = if self.stable {
+            let (decimals_in, decimals_out) = relative.decimals;
+            let xy = self.k()?;
+            let reserve_in = scale_to_18_decimals(reserve_in, decimals_in)?;
+            let reserve_out = scale_to_18_decimals(reserve_out, decimals_out)?;
+            let out_amount = div(out_amount.checked_mul(*ONE)?, decimals_out, Rounding::Up)?;
+
+            // The invariant is symmetric, so the same method can be used to
+            // solve for the new input reserve.
+            let x = stable_math::get_y(
+                reserve_out.checked_sub(out_amount)?,
+                xy,
+                reserve_in,
+                self.decimals,
+            )?;
+            // Account for rounding in the stable math.
+            div(
+                x.checked_sub(reserve_in)?.checked_mul(decimals_in)?,
+                *ONE,
+                Rounding::Up,
+            )?
+            .checked_add(U256::one())?
+        } else {
+            div(
+                out_amount.checked_mul(reserve_in)?,
+                reserve_out.checked_sub(out_amount)?,
+                Rounding::Up,
+            )?
+        };
+        let in_amount = div(
+            in_amount.checked_mul(*FEE_DENOMINATOR)?,
+            FEE_DENOMINATOR.checked_sub(self.fee)?,
+            Rounding::Up,
+        )?;
+
+        // Rounding in the stable math is not always in favour of the pool, so
+        // make sure that the computed amount is sufficient.
+        let computed_out = self.get_amount_out(out_token, (in_amount, in_token))?;
+        if computed_out < out_amount {
+            tracing::debug!(pool = ?self.address, "insufficient computed Solidly input amount");
+            return None;
+        }
+        Some(in_amount)
+    }
+
+    fn gas_cost(&self) -> usize {
+        if self.stable {
+            STABLE_POOL_SWAP_GAS_COST
+        } else {
+            VOLATILE_POOL_SWAP_GAS_COST
+        }
+    }
+}
+
+/// Pool fetcher for a Solidly pool factory.
+pub struct SolidlyPoolFetcher {
+    web3: Web3,
+    factory: SolidlyPoolFactory,
+    /// Cache of existing pool addresses by token pair and stability. Pools
+    /// never change address once created, so entries never expire.
+    pools: Mutex<HashMap<(TokenPair, bool), H160>>,
+}
+
+impl SolidlyPoolFetcher {
+    /// Creates a new pool fetcher for the Solidly deployment on the current
+    /// chain.
+    pub async fn new(web3: &Web3) -> Result<Self> {
+        Ok(Self {
+            web3: web3.clone(),
+            factory: SolidlyPoolFactory::deployed(web3).await?,
+            pools: Default::default(),
+        })
+    }
+
+    /// Returns the addresses and stability of the existing pools for the
+    /// specified token pairs.
+    async fn pool_addresses(&self, token_pairs: HashSet<TokenPair>) -> Result<Vec<(H160, bool)>> {
+        let mut addresses = Vec::new();
+        let mut missing = Vec::new();
+        {
+            let pools = self.pools.lock().unwrap();
+            for key in token_pairs
+                .into_iter()
+                .flat_map(|pair| [(pair, false), (pair, true)])
+            {
+                match pools.get(&key) {
+                    Some(address) => addresses.push((*address, key.1)),
+                    None => missing.push(key),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(addresses);
+        }
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let futures = missing
+            .iter()
+            .map(|(pair, stable)| {
+                let (token_a, token_b) = pair.get();
+                self.factory
+                    .get_pool(token_a, token_b, *stable)
+                    .batch_call(&mut batch)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let results = future::try_join_all(futures).await?;
+
+        // Only cache pools that exist, as they may be created later.
+        let mut pools = self.pools.lock().unwrap();
+        for (key, address) in missing.into_iter().zip(results) {
+            if !address.is_zero() {
+                pools.insert(key, address);
+                addresses.push((address, key.1));
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for SolidlyPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let addresses = self.pool_addresses(token_pairs).await?;
+        if addresses.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let futures = addresses
+            .into_iter()
+            .map(|(address, stable)| {
+                let metadata = SolidlyPool::at(&self.web3, address)
+                    .metadata()
+                    .block(block)
+                    .batch_call(&mut batch);
+                let fee = self
+                    .factory
+                    .get_fee(address, stable)
+                    .block(block)
+                    .batch_call(&mut batch);
+                async move {
+                    handle_results(FetchedPool {
+                        address,
+                        metadata: metadata.await,
+                        fee: fee.await,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        future::join_all(futures)
+            .await
+            .into_iter()
+            .filter_map(|pool| pool.transpose())
+            .collect()
+    }
+}
+
+struct FetchedPool {
+    address: H160,
+    metadata: Result<Metadata, MethodError>,
+    fee: Result<U256, MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let metadata = handle_contract_error(fetched_pool.metadata)?;
+    let fee = handle_contract_error(fetched_pool.fee)?;
+
+    Ok(match (metadata, fee) {
+        (Some((decimals0, decimals1, reserve0, reserve1, stable, token0, token1)), Some(fee)) => {
+            Some(Pool {
+                address: fetched_pool.address,
+                tokens: (token0, token1),
+                reserves: (reserve0, reserve1),
+                decimals: (decimals0, decimals1),
+                stable,
+                fee,
+            })
+        }
+        _ => None,
+    })
+}
+
+/// Scales an amount in token units to 18 decimals.
+fn scale_to_18_decimals(amount: U256, decimals: U256) -> Option<U256> {
+    amount.checked_mul(*ONE)?.checked_div(decimals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn usdc_dai() -> Pool {
+        Pool {
+            address: H160([0xff; 20]),
+            tokens: (H160([1; 20]), H160([2; 20])),
+            reserves: (
+                U256::from(10_000_000) * U256::exp10(6),
+                U256::from(10_000_000) * U256::exp10(18),
+            ),
+            decimals: (U256::exp10(6), U256::exp10(18)),
+            stable: true,
+            fee: 5.into(),
+        }
+    }
+
+    fn weth_usdc() -> Pool {
+        Pool {
+            address: H160([0xff; 20]),
+            tokens: (H160([1; 20]), H160([2; 20])),
+            reserves: (
+                U256::from(100) * U256::exp10(18),
+                U256::from(200_000) * U256::exp10(6),
+            ),
+            decimals: (U256::exp10(18), U256::exp10(6)),
+            stable: false,
+            fee: 30.into(),
+        }
+    }
+
+    #[test]
+    fn stable_pool_amounts() {
+        let pool = usdc_dai();
+        let (usdc, dai) = pool.tokens;
+
+        // Values computed with a port of the Velodrome V2 pool contract.
+        assert_eq!(
+            pool.get_amount_out(dai, (U256::from(1_000) * U256::exp10(6), usdc)),
+            Some(U256::from_dec_str("999499999999500999250").unwrap()),
+        );
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::from(1_000) * U256::exp10(18), dai)),
+            Some(999_499_999.into()),
+        );
+        assert_eq!(
+            pool.get_amount_out(dai, (U256::from(5_000_000) * U256::exp10(6), usdc)),
+            Some(U256::from_dec_str("4724040219290249594806083").unwrap()),
+        );
+
+        let out_amount = U256::from(1_000) * U256::exp10(18);
+        let in_amount = pool.get_amount_in(usdc, (out_amount, dai)).unwrap();
+        assert_eq!(in_amount, 1_000_500_253.into());
+        assert!(pool.get_amount_out(dai, (in_amount, usdc)).unwrap() >= out_amount);
+    }
+
+    #[test]
+    fn volatile_pool_amounts() {
+        let pool = weth_usdc();
+        let (weth, usdc) = pool.tokens;
+
+        assert_eq!(
+            pool.get_amount_out(usdc, (U256::exp10(18), weth)),
+            Some(1_974_316_068.into()),
+        );
+
+        let in_amount = pool
+            .get_amount_in(weth, (1_974_316_068.into(), usdc))
+            .unwrap();
+        assert_eq!(in_amount, 999_999_999_593_763_121u64.into());
+        assert_eq!(
+            pool.get_amount_out(usdc, (in_amount, weth)),
+            Some(1_974_316_068.into()),
+        );
+    }
+
+    #[test]
+    fn invalid_amounts() {
+        let pool = weth_usdc();
+        let (weth, usdc) = pool.tokens;
+
+        assert_eq!(pool.get_amount_out(usdc, (0.into(), weth)), None);
+        assert_eq!(pool.get_amount_in(weth, (pool.reserves.1, usdc)), None);
+        assert_eq!(pool.get_amount_out(H160([3; 20]), (1.into(), weth)), None);
+    }
+
+    #[test]
+    fn gas_costs() {
+        assert_eq!(usdc_dai().gas_cost(), STABLE_POOL_SWAP_GAS_COST);
+        assert_eq!(weth_usdc().gas_cost(), VOLATILE_POOL_SWAP_GAS_COST);
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched_pool = FetchedPool {
+            address: H160([1; 20]),
+            metadata: Err(ethcontract_error::testing_node_error()),
+            fee: Ok(5.into()),
+        };
+        assert!(handle_results(fetched_pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched_pool = FetchedPool {
+            address: H160([1; 20]),
+            metadata: Err(ethcontract_error::testing_contract_error()),
+            fee: Ok(5.into()),
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_none());
+    }
+}
@@ -0,0 +1,490 @@
+//! Module implementing the StableSwap invariant math shared by the Curve,
+//! Saddle and Balancer stable pool sources.
+//!
+//! This is a port of the `get_D`, `get_y`, `get_y_D`, `get_dy`,
+//! `calc_token_amount` and `calc_withdraw_one_coin` methods of the Curve
+//! StableSwap Vyper contracts, see for example the 3pool implementation:
+//! <https://github.com/curvefi/curve-contract/blob/master/contracts/pools/3pool/StableSwap3Pool.vy>
+//!
+//! Balances are normalized to 18 decimals ("xp" values in the Curve
+//! contracts) by multiplying them with a per-coin rate with 18 decimals of
+//! precision. All methods return `None` on arithmetic errors or if the
+//! Newton's method approximations fail to converge.
+//!
+//! Newer Curve pools and Balancer stable pools store the amplification
+//! parameter with extra precision, which is supported with [`Amplification`].
+//! Note that Balancer rounds some intermediate values differently, so amounts
+//! computed here can differ from the Balancer contracts by a few wei.
+
+use crate::fixed_point::{div, Rounding};
+use ethcontract::U256;
+
+/// The maximum number of iterations for the Newton's method approximations.
+const MAX_ITERATIONS: usize = 255;
+
+lazy_static::lazy_static! {
+    /// The precision of coin rates.
+    pub static ref PRECISION: U256 = U256::exp10(18);
+    /// The denominator of pool swap fees.
+    pub static ref FEE_DENOMINATOR: U256 = U256::exp10(10);
+}
+
+/// The amplification parameter `A` of a pool, stored as an integer with the
+/// specified precision.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Amplification {
+    value: U256,
+    precision: U256,
+}
+
+impl Amplification {
+    /// Creates an amplification parameter without extra precision, as used by
+    /// the original Curve and Saddle pools.
+    pub fn new(value: U256) -> Self {
+        Self {
+            value,
+            precision: U256::one(),
+        }
+    }
+
+    /// Creates an amplification parameter of `value / precision`, for example
+    /// with a precision of 100 for newer Curve pools or 1000 for Balancer
+    /// stable pools. Returns `None` if the precision is zero.
+    pub fn with_precision(value: U256, precision: U256) -> Option<Self> {
+        if precision.is_zero() {
+            return None;
+        }
+        Some(Self { value, precision })
+    }
+}
+
+/// Computes the StableSwap invariant `D` for the specified normalized
+/// balances and amplification parameter.
+pub fn get_d(xp: &[U256], amp: Amplification) -> Option<U256> {
+    let n = U256::from(xp.len());
+    let s = xp
+        .iter()
+        .try_fold(U256::zero(), |sum, x| sum.checked_add(*x))?;
+    if s.is_zero() {
+        return Some(U256::zero());
+    }
+
+    let ann = amp.value.checked_mul(n)?;
+    let mut d = s;
+    for _ in 0..MAX_ITERATIONS {
+        let mut d_p = d;
+        for x in xp {
+            d_p = d_p.checked_mul(d)?.checked_div(x.checked_mul(n)?)?;
+        }
+        let d_prev = d;
+        let numerator = (ann.checked_mul(s)? / amp.precision)
+            .checked_add(d_p.checked_mul(n)?)?
+            .checked_mul(d)?;
+        let denominator = (ann.checked_sub(amp.precision)?.checked_mul(d)? / amp.precision)
+            .checked_add(n.checked_add(U256::one())?.checked_mul(d_p)?)?;
+        d = numerator.checked_div(denominator)?;
+        if abs_diff(d, d_prev) <= U256::one() {
+            return Some(d);
+        }
+    }
+
+    None
+}
+
+/// Computes the new normalized balance of coin `j` such that the invariant is
+/// preserved when the normalized balance of coin `i` is set to `x`.
+pub fn get_y(i: usize, j: usize, x: U256, xp: &[U256], amp: Amplification) -> Option<U256> {
+    if i == j || i >= xp.len() || j >= xp.len() {
+        return None;
+    }
+
+    let d = get_d(xp, amp)?;
+    let mut xp = xp.to_vec();
+    xp[i] = x;
+    get_y_d(j, &xp, amp, d)
+}
+
+/// Computes the normalized balance of coin `i` such that the pool has the
+/// invariant `d` with the other normalized balances.
+pub fn get_y_d(i: usize, xp: &[U256], amp: Amplification, d: U256) -> Option<U256> {
+    if i >= xp.len() {
+        return None;
+    }
+
+    let n = U256::from(xp.len());
+    let ann = amp.value.checked_mul(n)?;
+
+    let mut c = d;
+    let mut s = U256::zero();
+    for (k, x_k) in xp.iter().enumerate() {
+        if k == i {
+            continue;
+        }
+        s = s.checked_add(*x_k)?;
+        c = c.checked_mul(d)?.checked_div(x_k.checked_mul(n)?)?;
+    }
+    c = c
+        .checked_mul(d)?
+        .checked_mul(amp.precision)?
+        .checked_div(ann.checked_mul(n)?)?;
+    let b = s.checked_add(d.checked_mul(amp.precision)?.checked_div(ann)?)?;
+
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        y = y
+            .checked_mul(y)?
+            .checked_add(c)?
+            .checked_div(y.checked_mul(2.into())?.checked_add(b)?.checked_sub(d)?)?;
+        if abs_diff(y, y_prev) <= U256::one() {
+            return Some(y);
+        }
+    }
+
+    None
+}
+
+/// Computes the amount of coin `j` received for selling `dx` of coin `i`.
+pub fn get_dy(
+    i: usize,
+    j: usize,
+    dx: U256,
+    balances: &[U256],
+    rates: &[U256],
+    amp: Amplification,
+    fee: U256,
+) -> Option<U256> {
+    let xp = normalize(balances, rates)?;
+    let x = xp
+        .get(i)?
+        .checked_add(dx.checked_mul(rates[i])? / *PRECISION)?;
+    let y = get_y(i, j, x, &xp, amp)?;
+    let dy = xp[j]
+        .checked_sub(y)?
+        .checked_sub(U256::one())?
+        .checked_mul(*PRECISION)?
+        .checked_div(rates[j])?;
+    let fee = fee.checked_mul(dy)? / *FEE_DENOMINATOR;
+    dy.checked_sub(fee)
+}
+
+/// Computes the amount of coin `i` needed to buy `dy` of coin `j`.
+///
+/// Curve pools don't provide a `get_dx` method, so this is computed by
+/// inverting `get_dy`. The result is rounded up, such that selling the
+/// returned amount will always buy at least `dy`.
+pub fn get_dx(
+    i: usize,
+    j: usize,
+    dy: U256,
+    balances: &[U256],
+    rates: &[U256],
+    amp: Amplification,
+    fee: U256,
+) -> Option<U256> {
+    let xp = normalize(balances, rates)?;
+    let dy_before_fee = div(
+        dy.checked_mul(*FEE_DENOMINATOR)?,
+        FEE_DENOMINATOR.checked_sub(fee)?,
+        Rounding::Up,
+    )?;
+    let y = xp
+        .get(j)?
+        .checked_sub(div(
+            dy_before_fee.checked_mul(rates[j])?,
+            *PRECISION,
+            Rounding::Up,
+        )?)?
+        .checked_sub(U256::one())?;
+    let x = get_y(j, i, y, &xp, amp)?;
+    div(
+        x.checked_sub(xp[i])?.checked_mul(*PRECISION)?,
+        rates[i],
+        Rounding::Up,
+    )
+}
+
+/// Computes the virtual price of the pool's LP token, that is the value of one
+/// LP token in normalized balances with 18 decimals.
+pub fn get_virtual_price(
+    balances: &[U256],
+    rates: &[U256],
+    amp: Amplification,
+    supply: U256,
+) -> Option<U256> {
+    let d = get_d(&normalize(balances, rates)?, amp)?;
+    d.checked_mul(*PRECISION)?.checked_div(supply)
+}
+
+/// Computes the amount of LP tokens minted for depositing the specified
+/// amounts of each coin into a pool with the specified LP token supply,
+/// including fees charged for imbalanced deposits.
+pub fn add_liquidity(
+    amounts: &[U256],
+    balances: &[U256],
+    rates: &[U256],
+    amp: Amplification,
+    fee: U256,
+    supply: U256,
+) -> Option<U256> {
+    let n = balances.len();
+    if amounts.len() != n || n < 2 || supply.is_zero() {
+        return None;
+    }
+
+    let d0 = get_d(&normalize(balances, rates)?, amp)?;
+    let mut new_balances = balances
+        .iter()
+        .zip(amounts)
+        .map(|(balance, amount)| balance.checked_add(*amount))
+        .collect::<Option<Vec<_>>>()?;
+    let d1 = get_d(&normalize(&new_balances, rates)?, amp)?;
+    if d0.is_zero() || d1 <= d0 {
+        return None;
+    }
+
+    // Deposits that change the ratio of the pool balances are charged a
+    // fee on the difference to the ideal balanced deposit.
+    let fee = fee.checked_mul(n.into())? / U256::from(4 * (n - 1));
+    for (balance, new_balance) in balances.iter().zip(&mut new_balances) {
+        let ideal_balance = d1.checked_mul(*balance)? / d0;
+        let difference = abs_diff(ideal_balance, *new_balance);
+        *new_balance = new_balance.checked_sub(fee.checked_mul(difference)? / *FEE_DENOMINATOR)?;
+    }
+    let d2 = get_d(&normalize(&new_balances, rates)?, amp)?;
+
+    supply.checked_mul(d2.checked_sub(d0)?)?.checked_div(d0)
+}
+
+/// Computes the amount of coin `i` received for withdrawing the specified
+/// amount of LP tokens from a pool with the specified LP token supply.
+pub fn remove_liquidity_one_coin(
+    i: usize,
+    token_amount: U256,
+    balances: &[U256],
+    rates: &[U256],
+    amp: Amplification,
+    fee: U256,
+    supply: U256,
+) -> Option<U256> {
+    let n = balances.len();
+    if i >= n || n < 2 || token_amount > supply {
+        return None;
+    }
+
+    let xp = normalize(balances, rates)?;
+    let d0 = get_d(&xp, amp)?;
+    let d1 = d0.checked_sub(token_amount.checked_mul(d0)?.checked_div(supply)?)?;
+    let new_y = get_y_d(i, &xp, amp, d1)?;
+
+    let fee = fee.checked_mul(n.into())? / U256::from(4 * (n - 1));
+    let mut xp_reduced = xp.clone();
+    for (j, (x, x_reduced)) in xp.iter().zip(&mut xp_reduced).enumerate() {
+        let dx_expected = if j == i {
+            x.checked_mul(d1)?.checked_div(d0)?.checked_sub(new_y)?
+        } else {
+            x.checked_sub(x.checked_mul(d1)?.checked_div(d0)?)?
+        };
+        *x_reduced = x_reduced.checked_sub(fee.checked_mul(dx_expected)? / *FEE_DENOMINATOR)?;
+    }
+
+    let dy = xp_reduced[i].checked_sub(get_y_d(i, &xp_reduced, amp, d1)?)?;
+    dy.checked_sub(U256::one())?
+        .checked_mul(*PRECISION)?
+        .checked_div(rates[i])
+}
+
+/// Normalizes balances to 18 decimals with the specified rates.
+fn normalize(balances: &[U256], rates: &[U256]) -> Option<Vec<U256>> {
+    if balances.len() != rates.len() {
+        return None;
+    }
+    balances
+        .iter()
+        .zip(rates)
+        .map(|(balance, rate)| Some(balance.checked_mul(*rate)? / *PRECISION))
+        .collect()
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn three_pool() -> (Vec<U256>, Vec<U256>) {
+        // DAI, USDC and USDT balances with their respective rates.
+        (
+            vec![
+                U256::from(100_000_000) * U256::exp10(18),
+                U256::from(120_000_000) * U256::exp10(6),
+                U256::from(80_000_000) * U256::exp10(6),
+            ],
+            vec![U256::exp10(18), U256::exp10(30), U256::exp10(30)],
+        )
+    }
+
+    #[test]
+    fn computes_invariant() {
+        let (balances, rates) = three_pool();
+        let xp = normalize(&balances, &rates).unwrap();
+        assert_eq!(
+            get_d(&xp, Amplification::new(2000.into())).unwrap(),
+            U256::from_dec_str("299997917765608652100285788").unwrap(),
+        );
+        assert_eq!(
+            get_d(&[0.into(), 0.into()], Amplification::new(2000.into())),
+            Some(0.into())
+        );
+    }
+
+    #[test]
+    fn amplification_precision() {
+        let (balances, rates) = three_pool();
+        let xp = normalize(&balances, &rates).unwrap();
+        assert_eq!(
+            get_d(
+                &xp,
+                Amplification::with_precision(200_000.into(), 100.into()).unwrap()
+            ),
+            get_d(&xp, Amplification::new(2000.into())),
+        );
+        // Fractional amplification parameters can only be represented with
+        // extra precision.
+        let d = get_d(
+            &xp,
+            Amplification::with_precision(200_050.into(), 100.into()).unwrap(),
+        )
+        .unwrap();
+        assert!(d > get_d(&xp, Amplification::new(2000.into())).unwrap());
+        assert!(d < get_d(&xp, Amplification::new(2001.into())).unwrap());
+
+        assert_eq!(Amplification::with_precision(1.into(), 0.into()), None);
+    }
+
+    #[test]
+    fn computes_swap_amounts() {
+        let (balances, rates) = three_pool();
+        let (amp, fee) = (Amplification::new(2000.into()), U256::from(1_000_000));
+
+        // DAI -> USDC
+        let dy = get_dy(
+            0,
+            1,
+            U256::from(1_000_000) * U256::exp10(18),
+            &balances,
+            &rates,
+            amp,
+            fee,
+        )
+        .unwrap();
+        assert_eq!(dy, 999_982_288_438_u64.into());
+        assert_eq!(
+            get_dx(0, 1, dy, &balances, &rates, amp, fee).unwrap(),
+            U256::from_dec_str("1000000000000972687996312").unwrap(),
+        );
+
+        // USDT -> DAI
+        let dy = get_dy(
+            2,
+            0,
+            U256::from(5_000_000) * U256::exp10(6),
+            &balances,
+            &rates,
+            amp,
+            fee,
+        )
+        .unwrap();
+        assert_eq!(dy, U256::from_dec_str("4999983421760304905294023").unwrap());
+        assert_eq!(
+            get_dx(2, 0, dy, &balances, &rates, amp, fee).unwrap(),
+            5_000_000_000_001_u64.into(),
+        );
+    }
+
+    #[test]
+    fn computes_liquidity_amounts() {
+        let (balances, rates) = three_pool();
+        let (amp, fee) = (Amplification::new(2000.into()), U256::from(1_000_000));
+        let supply = U256::from(290_000_000) * U256::exp10(18);
+
+        assert_eq!(
+            get_virtual_price(&balances, &rates, amp, supply).unwrap(),
+            1_034_475_578_502_098_800_u64.into(),
+        );
+
+        // Imbalanced USDC deposit.
+        assert_eq!(
+            add_liquidity(
+                &[0.into(), U256::from(1_000_000) * U256::exp10(6), 0.into()],
+                &balances,
+                &rates,
+                amp,
+                fee,
+                supply,
+            )
+            .unwrap(),
+            U256::from_dec_str("966538047032249821817360").unwrap(),
+        );
+        // Balanced deposits are not charged any fees.
+        assert_eq!(
+            add_liquidity(
+                &balances
+                    .iter()
+                    .map(|balance| *balance / 100)
+                    .collect::<Vec<_>>(),
+                &balances,
+                &rates,
+                amp,
+                fee,
+                supply,
+            )
+            .unwrap(),
+            supply / 100,
+        );
+
+        // USDT withdrawal.
+        assert_eq!(
+            remove_liquidity_one_coin(
+                2,
+                U256::from(1_000_000) * U256::exp10(18),
+                &balances,
+                &rates,
+                amp,
+                fee,
+                supply,
+            )
+            .unwrap(),
+            1_034_288_101_222_u64.into(),
+        );
+        // Withdrawing more than the supply.
+        assert_eq!(
+            remove_liquidity_one_coin(0, supply + 1, &balances, &rates, amp, fee, supply),
+            None,
+        );
+    }
+
+    #[test]
+    fn invalid_swaps() {
+        let (balances, rates) = three_pool();
+        let (amp, fee) = (Amplification::new(2000.into()), U256::from(1_000_000));
+
+        // Same coin in and out.
+        assert_eq!(get_dy(0, 0, 1.into(), &balances, &rates, amp, fee), None);
+        // Unknown coin index.
+        assert_eq!(get_dy(0, 3, 1.into(), &balances, &rates, amp, fee), None);
+        // Buying more than the pool's balance.
+        assert_eq!(get_dx(0, 1, balances[1], &balances, &rates, amp, fee), None);
+        // Overflow.
+        assert_eq!(
+            get_dy(0, 1, U256::max_value(), &balances, &rates, amp, fee),
+            None
+        );
+    }
+}
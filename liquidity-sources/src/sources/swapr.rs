@@ -1,4 +1,8 @@
 //! Swapr baseline liquidity source implementation.
+//!
+//! Swapr (formerly DXswap) is a Uniswap V2 fork deployed on Gnosis Chain.
+//! Unlike Uniswap V2, each pair has its own swap fee that can be changed by
+//! the fee setter, so fees are read along with the reserves of each pair.
 
 mod reader;
 
@@ -0,0 +1,6 @@
+//! Synthetix atomic exchange baseline liquidity source implementation.
+//!
+//! Synths can be exchanged atomically at the worse of the Chainlink and the
+//! DEX TWAP price of the exchanged synths, minus the atomic exchange fee. The
+//! sUSD value of atomic exchanges is limited per block.
+pub mod pool_fetching;
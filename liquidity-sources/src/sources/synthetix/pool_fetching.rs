@@ -0,0 +1,491 @@
+//! Module for fetching Synthetix atomic exchange rates between synths.
+
+use crate::{
+    baseline_solver::BaselineSolvable,
+    fixed_point::{div, Rounding},
+    maintenance::Maintaining,
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::{ensure, Result};
+use contracts::{
+    SynthetixAddressResolver, SynthetixExchangeRates, SynthetixExchanger, SynthetixSynth,
+    SynthetixSystemSettings,
+};
+use ethcontract::{errors::MethodError, tokens::Bytes, BlockId, H160, U256};
+use futures::future;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+const ATOMIC_EXCHANGE_GAS_COST: usize = 500_000;
+
+lazy_static::lazy_static! {
+    /// The precision of Synthetix rates and fees, which is also the amount of
+    /// one synth unit as all synths have 18 decimals.
+    static ref ONE: U256 = U256::exp10(18);
+}
+
+/// The result of the exchanger `getAmountsForAtomicExchange` method: the
+/// received amount, the fee and the fee rate.
+type AtomicAmounts = (U256, U256, U256);
+
+/// The result of the exchange rates `effectiveAtomicValueAndRates` method: the
+/// atomic value, the system value and the system source and destination rates.
+type AtomicValueAndRates = (U256, U256, U256, U256);
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The atomic exchange rates between two synths.
+///
+/// Atomic exchange rates are computed from oracle prices and do not depend on
+/// the exchanged amount. Rates are quoted for one unit of the source synth,
+/// so computed amounts may be slightly worse than the amounts of the actual
+/// exchange.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    /// The pair synths, where the first has the smaller address.
+    pub tokens: (H160, H160),
+    /// The rates for exchanging the first synth for the second synth and back,
+    /// or `None` if atomic exchanges are not available in that direction.
+    pub rates: (Option<ExchangeRate>, Option<ExchangeRate>),
+    /// The maximum sUSD value of atomic exchanges per block.
+    ///
+    /// The volume of earlier exchanges is not accounted for, as it only limits
+    /// exchanges in the same block.
+    pub max_volume: U256,
+}
+
+/// The rate for atomically exchanging a source synth for a destination synth.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExchangeRate {
+    /// The amount of destination synth for one source synth, before fees.
+    pub rate: U256,
+    /// The exchange fee rate with 18 decimals.
+    pub fee_rate: U256,
+    /// The sUSD value of one source synth, for computing the exchange volume.
+    pub susd_rate: U256,
+}
+
+impl Pool {
+    fn exchange_rate(&self, in_token: H160, out_token: H160) -> Option<&ExchangeRate> {
+        if (in_token, out_token) == self.tokens {
+            self.rates.0.as_ref()
+        } else if (out_token, in_token) == self.tokens {
+            self.rates.1.as_ref()
+        } else {
+            None
+        }
+    }
+
+    fn within_volume(&self, rate: &ExchangeRate, in_amount: U256) -> Option<()> {
+        let volume = in_amount.checked_mul(rate.susd_rate)? / *ONE;
+        if volume > self.max_volume {
+            return None;
+        }
+        Some(())
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let rate = self.exchange_rate(in_token, out_token)?;
+        self.within_volume(rate, in_amount)?;
+        let amount = in_amount.checked_mul(rate.rate)? / *ONE;
+        Some(amount.checked_mul(ONE.checked_sub(rate.fee_rate)?)? / *ONE)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let rate = self.exchange_rate(in_token, out_token)?;
+        let amount = div(
+            out_amount.checked_mul(*ONE)?,
+            ONE.checked_sub(rate.fee_rate)?,
+            Rounding::Up,
+        )?;
+        let in_amount = div(amount.checked_mul(*ONE)?, rate.rate, Rounding::Up)?;
+        self.within_volume(rate, in_amount)?;
+        Some(in_amount)
+    }
+
+    fn gas_cost(&self) -> usize {
+        ATOMIC_EXCHANGE_GAS_COST
+    }
+}
+
+/// Pool fetcher for atomic exchanges between a set of synths.
+pub struct SynthetixPoolFetcher {
+    web3: Web3,
+    resolver: SynthetixAddressResolver,
+    contracts: RwLock<SystemContracts>,
+    /// The currency keys of the supported synths by synth proxy address.
+    synths: HashMap<H160, [u8; 32]>,
+}
+
+/// The Synthetix system contracts used for quoting atomic exchanges. These
+/// get replaced on Synthetix releases, so they are periodically resolved.
+#[derive(Clone)]
+struct SystemContracts {
+    exchanger: SynthetixExchanger,
+    exchange_rates: SynthetixExchangeRates,
+    system_settings: SynthetixSystemSettings,
+}
+
+impl SynthetixPoolFetcher {
+    /// Creates a new pool fetcher for exchanges between the synths with the
+    /// specified proxy addresses, using the system contracts of the address
+    /// resolver at the specified address.
+    pub async fn new(web3: &Web3, resolver: H160, synths: Vec<H160>) -> Result<Self> {
+        let resolver = SynthetixAddressResolver::at(web3, resolver);
+        let contracts = resolve_contracts(web3, &resolver).await?;
+
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let currency_keys = synths
+            .iter()
+            .map(|synth| {
+                SynthetixSynth::at(web3, *synth)
+                    .currency_key()
+                    .batch_call(&mut batch)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let currency_keys = future::try_join_all(currency_keys).await?;
+
+        Ok(Self {
+            web3: web3.clone(),
+            resolver,
+            contracts: RwLock::new(contracts),
+            synths: synths
+                .into_iter()
+                .zip(currency_keys)
+                .map(|(synth, key)| (synth, key.0))
+                .collect(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for SynthetixPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let pairs = token_pairs
+            .into_iter()
+            .filter_map(|pair| {
+                let (a, b) = pair.get();
+                Some(((a, *self.synths.get(&a)?), (b, *self.synths.get(&b)?)))
+            })
+            .collect::<Vec<_>>();
+        if pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let contracts = self.contracts.read().unwrap().clone();
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let max_volume = contracts
+            .system_settings
+            .atomic_max_volume_per_block()
+            .block(block)
+            .batch_call(&mut batch);
+        let mut fetch_rate = |source: [u8; 32], destination: [u8; 32]| {
+            let amounts = contracts
+                .exchanger
+                .get_amounts_for_atomic_exchange(*ONE, Bytes(source), Bytes(destination))
+                .block(block)
+                .batch_call(&mut batch);
+            let value = contracts
+                .exchange_rates
+                .effective_atomic_value_and_rates(Bytes(source), *ONE, Bytes(destination))
+                .block(block)
+                .batch_call(&mut batch);
+            async move {
+                FetchedRate {
+                    amounts: amounts.await,
+                    value: value.await,
+                }
+            }
+        };
+        let futures = pairs
+            .into_iter()
+            .map(|((a, a_key), (b, b_key))| {
+                let forward = fetch_rate(a_key, b_key);
+                let backward = fetch_rate(b_key, a_key);
+                async move {
+                    FetchedPool {
+                        tokens: (a, b),
+                        forward: forward.await,
+                        backward: backward.await,
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        let max_volume = match handle_contract_error(max_volume.await)? {
+            Some(max_volume) if !max_volume.is_zero() => max_volume,
+            // Atomic exchanges are disabled.
+            _ => return Ok(Vec::new()),
+        };
+        future::join_all(futures)
+            .await
+            .into_iter()
+            .filter_map(|pool| handle_results(pool, max_volume).transpose())
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for SynthetixPoolFetcher {
+    async fn run_maintenance(&self) -> Result<()> {
+        let contracts = resolve_contracts(&self.web3, &self.resolver).await?;
+        *self.contracts.write().unwrap() = contracts;
+        Ok(())
+    }
+}
+
+async fn resolve_contracts(
+    web3: &Web3,
+    resolver: &SynthetixAddressResolver,
+) -> Result<SystemContracts> {
+    let mut batch = Web3CallBatch::new(web3.transport().clone());
+    let addresses = ["Exchanger", "ExchangeRates", "SystemSettings"]
+        .iter()
+        .map(|name| {
+            resolver
+                .get_address(Bytes(contract_name(name)))
+                .batch_call(&mut batch)
+        })
+        .collect::<Vec<_>>();
+    batch.execute_all(MAX_BATCH_SIZE).await;
+    let addresses = future::try_join_all(addresses).await?;
+    ensure!(
+        addresses.iter().all(|address| !address.is_zero()),
+        "missing Synthetix system contracts in address resolver"
+    );
+
+    Ok(SystemContracts {
+        exchanger: SynthetixExchanger::at(web3, addresses[0]),
+        exchange_rates: SynthetixExchangeRates::at(web3, addresses[1]),
+        system_settings: SynthetixSystemSettings::at(web3, addresses[2]),
+    })
+}
+
+/// Encodes a contract name for the address resolver as right padded bytes.
+fn contract_name(name: &str) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    bytes
+}
+
+struct FetchedPool {
+    tokens: (H160, H160),
+    forward: FetchedRate,
+    backward: FetchedRate,
+}
+
+struct FetchedRate {
+    amounts: Result<AtomicAmounts, MethodError>,
+    value: Result<AtomicValueAndRates, MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool, max_volume: U256) -> Result<Option<Pool>> {
+    let rates = (
+        handle_rate(fetched_pool.forward)?,
+        handle_rate(fetched_pool.backward)?,
+    );
+    if rates.0.is_none() && rates.1.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(Pool {
+        tokens: fetched_pool.tokens,
+        rates,
+        max_volume,
+    }))
+}
+
+fn handle_rate(fetched_rate: FetchedRate) -> Result<Option<ExchangeRate>> {
+    // Quoting reverts for synths without atomic pricing.
+    let amounts = handle_contract_error(fetched_rate.amounts)?;
+    let value = handle_contract_error(fetched_rate.value)?;
+
+    Ok(match (amounts, value) {
+        (Some((amount_received, fee, fee_rate)), Some((_, _, susd_rate, _))) => {
+            let rate = amount_received.checked_add(fee);
+            match rate {
+                Some(rate) if !rate.is_zero() && fee_rate < *ONE => Some(ExchangeRate {
+                    rate,
+                    fee_rate,
+                    susd_rate,
+                }),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn e18(amount: u64) -> U256 {
+        U256::from(amount) * *ONE
+    }
+
+    /// A pool between sETH and sUSD at 2000 sUSD per sETH with a 0.3% fee.
+    fn seth_susd() -> Pool {
+        Pool {
+            tokens: (H160([1; 20]), H160([2; 20])),
+            rates: (
+                Some(ExchangeRate {
+                    rate: e18(2000),
+                    fee_rate: U256::exp10(15) * 3,
+                    susd_rate: e18(2000),
+                }),
+                Some(ExchangeRate {
+                    rate: U256::exp10(14) * 5,
+                    fee_rate: U256::exp10(15) * 3,
+                    susd_rate: *ONE,
+                }),
+            ),
+            max_volume: e18(2_000_000),
+        }
+    }
+
+    fn fetched_rate(amounts: AtomicAmounts, susd_rate: U256) -> FetchedRate {
+        FetchedRate {
+            amounts: Ok(amounts),
+            value: Ok((amounts.0 + amounts.1, 0.into(), susd_rate, 0.into())),
+        }
+    }
+
+    #[test]
+    fn computes_amounts() {
+        let pool = seth_susd();
+        let (seth, susd) = pool.tokens;
+
+        assert_eq!(pool.get_amount_out(susd, (*ONE, seth)), Some(e18(1994)));
+        assert_eq!(pool.get_amount_in(seth, (e18(1994), susd)), Some(*ONE));
+        assert_eq!(
+            pool.get_amount_out(seth, (e18(2000), susd)),
+            Some(U256::exp10(15) * 997),
+        );
+        assert_eq!(
+            pool.get_amount_in(susd, (U256::exp10(15) * 997, seth)),
+            Some(e18(2000)),
+        );
+    }
+
+    #[test]
+    fn rounds_in_favour_of_the_exchange() {
+        let pool = seth_susd();
+        let (seth, susd) = pool.tokens;
+
+        let out_amount = e18(1994) + 1;
+        let in_amount = pool.get_amount_in(seth, (out_amount, susd)).unwrap();
+        assert_eq!(in_amount, *ONE + 1);
+        assert!(pool.get_amount_out(susd, (in_amount, seth)).unwrap() >= out_amount);
+        assert!(pool.get_amount_out(susd, (in_amount - 1, seth)).unwrap() < out_amount);
+    }
+
+    #[test]
+    fn respects_volume_limit() {
+        let pool = seth_susd();
+        let (seth, susd) = pool.tokens;
+
+        assert!(pool.get_amount_out(susd, (e18(1000), seth)).is_some());
+        assert_eq!(pool.get_amount_out(susd, (e18(1001), seth)), None);
+        assert_eq!(pool.get_amount_in(seth, (e18(2_000_000), susd)), None);
+        assert!(pool.get_amount_out(seth, (e18(2_000_000), susd)).is_some());
+    }
+
+    #[test]
+    fn unsupported_direction() {
+        let pool = Pool {
+            rates: (seth_susd().rates.0, None),
+            ..seth_susd()
+        };
+        let (seth, susd) = pool.tokens;
+
+        assert!(pool.get_amount_out(susd, (*ONE, seth)).is_some());
+        assert_eq!(pool.get_amount_out(seth, (*ONE, susd)), None);
+        assert_eq!(pool.get_amount_in(susd, (*ONE, seth)), None);
+        assert_eq!(pool.get_amount_out(H160([3; 20]), (*ONE, seth)), None);
+    }
+
+    #[test]
+    fn encodes_contract_names() {
+        let name = contract_name("Exchanger");
+        assert_eq!(&name[..9], b"Exchanger");
+        assert!(name[9..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn converts_fetched_rates() {
+        let pool = handle_results(
+            FetchedPool {
+                tokens: seth_susd().tokens,
+                forward: fetched_rate((e18(1994), e18(6), U256::exp10(15) * 3), e18(2000)),
+                backward: fetched_rate(
+                    (
+                        U256::exp10(11) * 4985,
+                        U256::exp10(11) * 15,
+                        U256::exp10(15) * 3,
+                    ),
+                    *ONE,
+                ),
+            },
+            e18(2_000_000),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(pool, seth_susd());
+    }
+
+    #[test]
+    fn skips_pairs_without_atomic_rates() {
+        let unsupported = || FetchedRate {
+            amounts: Err(ethcontract_error::testing_contract_error()),
+            value: Err(ethcontract_error::testing_contract_error()),
+        };
+        let pool = FetchedPool {
+            tokens: seth_susd().tokens,
+            forward: unsupported(),
+            backward: unsupported(),
+        };
+        assert_eq!(handle_results(pool, e18(2_000_000)).unwrap(), None);
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let pool = FetchedPool {
+            tokens: seth_susd().tokens,
+            forward: fetched_rate((e18(1994), e18(6), U256::exp10(15) * 3), e18(2000)),
+            backward: FetchedRate {
+                amounts: Err(ethcontract_error::testing_node_error()),
+                value: Ok(Default::default()),
+            },
+        };
+        assert!(handle_results(pool, e18(2_000_000)).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let pool = FetchedPool {
+            tokens: seth_susd().tokens,
+            forward: fetched_rate((e18(1994), e18(6), U256::exp10(15) * 3), e18(2000)),
+            backward: FetchedRate {
+                amounts: Ok(Default::default()),
+                value: Err(ethcontract_error::testing_contract_error()),
+            },
+        };
+        let pool = handle_results(pool, e18(2_000_000)).unwrap().unwrap();
+        assert_eq!(pool.rates.1, None);
+    }
+}
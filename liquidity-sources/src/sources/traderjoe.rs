@@ -0,0 +1,32 @@
+//! Trader Joe baseline liquidity source implementation.
+
+use super::uniswap_v2::macros::impl_uniswap_like_liquidity;
+
+impl_uniswap_like_liquidity! {
+    factory: contracts::TraderJoeFactory,
+    init_code_digest: "0bbca9af0511ad1a1da383135cf3a8d2ac620e549ef9f6ae3a4c33c2fed0af91",
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token_pair::TokenPair;
+    use ethcontract_mock::Mock;
+
+    #[tokio::test]
+    async fn test_create2_traderjoe() {
+        // Avalanche C-Chain
+        let (avalanche_pair_provider, _) = get_liquidity_source(&Mock::new(43114).web3())
+            .await
+            .unwrap();
+        let avalanche_pair = TokenPair::new(
+            addr!("B31f66AA3C1e785363F0875A1B74E27b85FD66c7"),
+            addr!("A7D7079b0FEaD91F3e65f86E8915Cb59c1a4C664"),
+        )
+        .unwrap();
+        assert_eq!(
+            avalanche_pair_provider.pair_address(&avalanche_pair),
+            addr!("A389f9430876455C36478DeEa9769B7Ca4E3DDB1")
+        );
+    }
+}
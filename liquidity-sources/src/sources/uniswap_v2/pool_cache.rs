@@ -47,14 +47,16 @@ impl CacheMetrics for Arc<dyn PoolCacheMetrics> {
 }
 
 impl PoolCache {
-    /// Creates a new pool cache.
+    /// Creates a new pool cache, labelling its metrics with the name.
     pub fn new(
+        name: &'static str,
         config: CacheConfig,
         fetcher: Arc<dyn PoolFetching>,
         block_stream: CurrentBlockStream,
         metrics: Arc<dyn PoolCacheMetrics>,
     ) -> Result<Self> {
         Ok(Self(RecentBlockCache::new(
+            name,
             config,
             fetcher,
             block_stream,
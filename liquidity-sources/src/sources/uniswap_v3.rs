@@ -1,3 +1,4 @@
 //! Uniswap V3 baseline liquidity source implementation.
 pub mod graph_api;
 pub mod pool_fetching;
+pub mod swap_math;
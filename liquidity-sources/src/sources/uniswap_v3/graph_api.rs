@@ -2,15 +2,18 @@
 //! data from the Uniswap V3 subgraph.
 
 use crate::{
-    event_handling::MAX_REORG_BLOCK_COUNT,
+    chain_config::{self, ChainConfig, Subgraph},
+    current_block::{safe_block_number, BlockFinality},
     subgraph::{ContainsId, Data, SubgraphClient},
+    Web3,
 };
 use anyhow::{bail, Result};
 use ethcontract::{H160, U256};
 use num::BigInt;
-use reqwest::Client;
+use reqwest::{Client, IntoUrl};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::time::Duration;
 
 const ALL_POOLS_QUERY: &str = r#"
     query Pools($block: Int, $pageSize: Int, $lastId: ID) {
@@ -83,27 +86,125 @@ const TICKS_QUERY: &str = r#"
     }
 "#;
 
+/// A Uniswap V3 deployment.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Deployment {
+    pub chain_id: u64,
+    pub factory: H160,
+    /// The deployment's subgraph, or `None` if it needs to be specified
+    /// explicitly, for example for subgraphs that are only available on the
+    /// decentralized network.
+    pub subgraph: Option<Subgraph>,
+    /// The number of blocks behind the subgraph head that are assumed to be
+    /// safe from reorgs.
+    pub reorg_block_count: u64,
+    /// How the blocks of the chain become final, used instead of the reorg
+    /// block count when the subgraph client is connected to a node.
+    pub finality: BlockFinality,
+    /// The average block time of the chain.
+    pub block_time: Duration,
+}
+
+/// The number of blocks that cached pool state is considered recent for by
+/// default.
+pub const DEFAULT_MAX_AGE_BLOCK_COUNT: u32 = 5;
+
+impl Deployment {
+    /// Returns the deployment for the specified chain ID from the current
+    /// chain configuration.
+    pub fn for_chain(chain_id: u64) -> Result<Self> {
+        Self::from_config(ChainConfig::for_chain(chain_id)?)
+    }
+
+    /// Returns the deployment described by the specified chain configuration.
+    pub fn from_config(config: &ChainConfig) -> Result<Self> {
+        let factory = match config.factories.get(chain_config::UNISWAP_V3) {
+            Some(factory) => *factory,
+            None => bail!("no Uniswap V3 deployment on chain {}", config.chain_id),
+        };
+        Ok(Self {
+            chain_id: config.chain_id,
+            factory,
+            subgraph: config.subgraphs.get(chain_config::UNISWAP_V3).cloned(),
+            reorg_block_count: config.reorg_block_count,
+            finality: config.block_finality(),
+            block_time: config.block_time(),
+        })
+    }
+
+    /// Returns the default maximum age of cached pool state, which spans the
+    /// same number of blocks on all chains.
+    pub fn default_max_age(&self) -> Duration {
+        self.block_time * DEFAULT_MAX_AGE_BLOCK_COUNT
+    }
+}
+
 /// A client to the Uniswap V3 subgraph.
 ///
 /// This client is not implemented to allow general GraphQL queries, but instead
 /// implements high-level methods that perform GraphQL queries under the hood.
-pub struct UniV3SubgraphClient(SubgraphClient);
+pub struct UniV3SubgraphClient {
+    client: SubgraphClient,
+    reorg_block_count: u64,
+    finality: BlockFinality,
+    node: Option<Web3>,
+}
 
 impl UniV3SubgraphClient {
     /// Creates a new Uniswap V3 subgraph client for the specified chain ID.
+    ///
+    /// Fails for chains whose subgraph is not configured, use
+    /// [`Self::with_subgraph_url`] for these instead.
     pub fn for_chain(chain_id: u64, client: Client) -> Result<Self> {
-        let subgraph_name = match chain_id {
-            1 => "uniswap-v3",
-            _ => bail!("unsupported chain {}", chain_id),
+        Self::for_deployment(&Deployment::for_chain(chain_id)?, client)
+    }
+
+    /// Creates a new Uniswap V3 subgraph client for the specified deployment.
+    pub fn for_deployment(deployment: &Deployment, client: Client) -> Result<Self> {
+        let subgraph = match &deployment.subgraph {
+            Some(subgraph) => subgraph,
+            None => bail!(
+                "no Uniswap V3 subgraph configured for chain {}",
+                deployment.chain_id
+            ),
         };
-        Ok(Self(SubgraphClient::new("uniswap", subgraph_name, client)?))
+        Ok(Self {
+            client: subgraph.client(client)?,
+            reorg_block_count: deployment.reorg_block_count,
+            finality: deployment.finality,
+            node: None,
+        })
+    }
+
+    /// Creates a new Uniswap V3 subgraph client for the deployment on the
+    /// specified chain ID, querying the subgraph at the specified URL.
+    pub fn with_subgraph_url(
+        chain_id: u64,
+        subgraph_url: impl IntoUrl,
+        client: Client,
+    ) -> Result<Self> {
+        let deployment = Deployment::for_chain(chain_id)?;
+        Ok(Self {
+            client: SubgraphClient::with_url(subgraph_url, client)?,
+            reorg_block_count: deployment.reorg_block_count,
+            finality: deployment.finality,
+            node: None,
+        })
+    }
+
+    /// Determines the reorg-safe block with the node instead of subtracting
+    /// the reorg block count from the subgraph head, so that the `safe` or
+    /// `finalized` block tags are used on chains configured with them.
+    pub fn with_node(mut self, web3: Web3) -> Self {
+        self.node = Some(web3);
+        self
     }
 
     /// Retrieves the list of registered pools from the subgraph.
     pub async fn get_registered_pools(&self) -> Result<RegisteredPools> {
         let block_number = self.get_safe_block().await?;
         let pools = self
-            .0
+            .client
             .paginated_query(block_number, ALL_POOLS_QUERY)
             .await?;
 
@@ -117,7 +218,7 @@ impl UniV3SubgraphClient {
     pub async fn get_pools_with_ticks_by_ids(&self, ids: &[H160]) -> Result<Vec<PoolData>> {
         let block_number = self.get_safe_block().await?;
         Ok(self
-            .0
+            .client
             .query::<Data<PoolData>>(
                 POOLS_WITH_TICKS_BY_IDS_QUERY,
                 Some(json_map! {
@@ -132,7 +233,7 @@ impl UniV3SubgraphClient {
     /// Retrieves the list of ticks from the subgraph.
     pub async fn get_ticks(&self) -> Result<Vec<TickData>> {
         let block_number = self.get_safe_block().await?;
-        self.0.paginated_query(block_number, TICKS_QUERY).await
+        self.client.paginated_query(block_number, TICKS_QUERY).await
     }
 
     /// Retrieves a recent block number for which it is safe to assume no
@@ -142,14 +243,20 @@ impl UniV3SubgraphClient {
         // that there indeed is no reorg. However, it does not seem possible to
         // retrieve historic block hashes just from the subgraph (it always
         // returns `null`).
-        Ok(self
-            .0
+        let subgraph_block = self
+            .client
             .query::<block_number_query::Data>(block_number_query::QUERY, None)
             .await?
             .meta
             .block
-            .number
-            .saturating_sub(MAX_REORG_BLOCK_COUNT))
+            .number;
+        Ok(match &self.node {
+            // The subgraph can only be queried at blocks it has indexed.
+            Some(node) => safe_block_number(node, self.finality)
+                .await?
+                .min(subgraph_block),
+            None => subgraph_block.saturating_sub(self.reorg_block_count),
+        })
     }
 }
 
@@ -239,7 +346,11 @@ mod block_number_query {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::subgraph::Data;
+    use crate::{
+        chain_config::ChainConfigs, current_block::SafeBlockTag,
+        event_handling::MAX_REORG_BLOCK_COUNT, subgraph::Data,
+    };
+    use num::Zero as _;
     use serde_json::json;
     use std::str::FromStr;
 
@@ -373,6 +484,70 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_arbitrum_pools_with_dense_ticks() {
+        // Arbitrum has many 0.01% fee tier pools with a tick spacing of 1, so
+        // initialized ticks can be adjacent to each other.
+        let pools = serde_json::from_value::<Data<PoolData>>(json!({
+            "pools": [
+                {
+                    "id": "0x8c9d230d45d6cfee39a6680fb7cb7e8de7ea8e71",
+                    "liquidity": "23011208385862041",
+                    "sqrtPrice": "79228453812810151785218043543",
+                    "tick": "0",
+                    "ticks": [
+                        {
+                            "id": "0x8c9d230d45d6cfee39a6680fb7cb7e8de7ea8e71#-1",
+                            "tickIdx": "-1",
+                            "liquidityNet": "23011208385862041",
+                            "poolAddress": "0x8c9d230d45d6cfee39a6680fb7cb7e8de7ea8e71"
+                        },
+                        {
+                            "id": "0x8c9d230d45d6cfee39a6680fb7cb7e8de7ea8e71#0",
+                            "tickIdx": "0",
+                            "liquidityNet": "-3011208385862041",
+                            "poolAddress": "0x8c9d230d45d6cfee39a6680fb7cb7e8de7ea8e71"
+                        },
+                        {
+                            "id": "0x8c9d230d45d6cfee39a6680fb7cb7e8de7ea8e71#1",
+                            "tickIdx": "1",
+                            "liquidityNet": "-20000000000000000",
+                            "poolAddress": "0x8c9d230d45d6cfee39a6680fb7cb7e8de7ea8e71"
+                        }
+                    ]
+                }
+            ],
+        }))
+        .unwrap()
+        .inner;
+
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].tick, BigInt::from(0));
+        assert_eq!(
+            pools[0]
+                .ticks
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|tick| (tick.tick_idx.clone(), tick.liquidity_net.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (BigInt::from(-1), BigInt::from(23011208385862041_i64)),
+                (BigInt::from(0), BigInt::from(-3011208385862041_i64)),
+                (BigInt::from(1), BigInt::from(-20000000000000000_i64)),
+            ],
+        );
+        // The net liquidity of all ticks of a pool sums up to zero.
+        assert!(pools[0]
+            .ticks
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|tick| &tick.liquidity_net)
+            .sum::<BigInt>()
+            .is_zero());
+    }
+
     #[test]
     fn decode_block_number_data() {
         use block_number_query::*;
@@ -394,6 +569,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn default_max_age_depends_on_block_time() {
+        assert_eq!(
+            Deployment::for_chain(1).unwrap().default_max_age(),
+            Duration::from_secs(60),
+        );
+        assert_eq!(
+            Deployment::for_chain(10).unwrap().default_max_age(),
+            Duration::from_secs(10),
+        );
+    }
+
+    #[test]
+    fn deployments_are_unique_per_chain() {
+        let configs = ChainConfigs::embedded();
+        for config in configs.chains() {
+            match Deployment::from_config(config) {
+                Ok(deployment) => {
+                    assert_eq!(Deployment::for_chain(config.chain_id).unwrap(), deployment)
+                }
+                Err(_) => assert!(!config.factories.contains_key(chain_config::UNISWAP_V3)),
+            }
+        }
+        assert!(UniV3SubgraphClient::for_chain(42161, Client::new()).is_ok());
+        assert_eq!(
+            Deployment::for_chain(42161).unwrap().reorg_block_count,
+            1200,
+        );
+        assert!(UniV3SubgraphClient::for_chain(137, Client::new()).is_ok());
+        assert!(UniV3SubgraphClient::for_chain(10, Client::new()).is_ok());
+        assert!(UniV3SubgraphClient::for_chain(43114, Client::new()).is_ok());
+        assert_eq!(Deployment::for_chain(43114).unwrap().reorg_block_count, 5);
+        assert_eq!(Deployment::for_chain(137).unwrap().reorg_block_count, 256);
+        assert_eq!(
+            Deployment::for_chain(1).unwrap().reorg_block_count,
+            MAX_REORG_BLOCK_COUNT,
+        );
+        assert_eq!(
+            Deployment::for_chain(1).unwrap().finality,
+            BlockFinality::Depth(MAX_REORG_BLOCK_COUNT),
+        );
+        assert_eq!(
+            Deployment::for_chain(42161).unwrap().finality,
+            BlockFinality::Tagged {
+                tag: SafeBlockTag::Safe,
+                fallback_depth: 1200,
+            },
+        );
+        assert!(UniV3SubgraphClient::for_chain(100, Client::new()).is_err());
+
+        // The Base subgraph needs to be specified explicitly.
+        assert_eq!(
+            Deployment::for_chain(8453).unwrap().factory,
+            addr!("33128a8fC17869897dcE68Ed026d694621f6FDfD"),
+        );
+        assert!(UniV3SubgraphClient::for_chain(8453, Client::new()).is_err());
+        assert!(UniV3SubgraphClient::for_chain(11155111, Client::new()).is_err());
+        assert!(UniV3SubgraphClient::with_subgraph_url(
+            8453,
+            "https://gateway.thegraph.com/api/key/subgraphs/id/subgraph",
+            Client::new(),
+        )
+        .is_ok());
+        assert!(UniV3SubgraphClient::with_subgraph_url(
+            100,
+            "https://gateway.thegraph.com/api/key/subgraphs/id/subgraph",
+            Client::new(),
+        )
+        .is_err());
+    }
+
     #[tokio::test]
     #[ignore]
     async fn uniswap_v3_subgraph_query_get_pools() {
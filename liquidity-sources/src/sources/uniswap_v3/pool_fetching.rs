@@ -1,5 +1,11 @@
-use super::graph_api::{PoolData, Token, UniV3SubgraphClient};
-use crate::{token_pair::TokenPair, u256_decimal};
+use super::{
+    graph_api::{Deployment, PoolData, Token, UniV3SubgraphClient},
+    swap_math,
+};
+use crate::{
+    baseline_solver::BaselineSolvable, current_block::CurrentBlockStream, token_pair::TokenPair,
+    u256_decimal,
+};
 use anyhow::{Context, Result};
 use ethcontract::{H160, U256};
 use itertools::{Either, Itertools};
@@ -87,9 +93,55 @@ impl TryFrom<PoolData> for PoolInfo {
     }
 }
 
+impl PoolInfo {
+    /// Returns whether token 0 is sold for token 1, or `None` if the tokens are
+    /// not the tokens of the pool.
+    fn zero_for_one(&self, in_token: H160, out_token: H160) -> Option<bool> {
+        match self.tokens.as_slice() {
+            [token0, token1] if (token0.id, token1.id) == (in_token, out_token) => Some(true),
+            [token0, token1] if (token1.id, token0.id) == (in_token, out_token) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl BaselineSolvable for PoolInfo {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let zero_for_one = self.zero_for_one(in_token, out_token)?;
+        swap_math::get_amount_out(&self.state, zero_for_one, in_amount)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let zero_for_one = self.zero_for_one(in_token, out_token)?;
+        swap_math::get_amount_in(&self.state, zero_for_one, out_amount)
+    }
+
+    fn get_amounts_out(
+        &self,
+        out_token: H160,
+        (in_amounts, in_token): (&[U256], H160),
+    ) -> Vec<Option<U256>> {
+        match self.zero_for_one(in_token, out_token) {
+            Some(zero_for_one) => swap_math::get_amounts_out(&self.state, zero_for_one, in_amounts),
+            None => vec![None; in_amounts.len()],
+        }
+    }
+
+    fn gas_cost(&self) -> usize {
+        self.gas_stats.mean_gas.low_u64() as usize
+    }
+
+    fn max_amount_in(&self, in_token: H160, out_token: H160) -> Option<U256> {
+        let zero_for_one = self.zero_for_one(in_token, out_token)?;
+        swap_math::max_amount_in(&self.state, zero_for_one)
+    }
+}
+
 pub struct CachedPool {
     pub pool: PoolData,
     pub updated_at: Instant,
+    /// The current block when the pool was fetched.
+    pub updated_at_block: u64,
     pub requested_at: Instant,
 }
 
@@ -99,14 +151,33 @@ pub struct UniswapV3PoolFetcher {
     pools_by_token_pair: HashMap<TokenPair, HashSet<H160>>,
     cache: Mutex<HashMap<H160, CachedPool>>,
     max_age: Duration,
+    block_stream: CurrentBlockStream,
 }
 
 impl UniswapV3PoolFetcher {
     /// Retrieves all registered pools on Uniswap V3 subgraph, but without `ticks`,
     /// making the cache values outdated immediately. Cache values are supposed to be updated
     /// either on fetch or on periodic maintenance update.
-    pub async fn new(chain_id: u64, max_age: Duration, client: Client) -> Result<Self> {
+    ///
+    /// Cached pools are outdated once a new block arrives on the block stream or
+    /// when they are older than `max_age`, whichever happens first.
+    pub async fn new(
+        chain_id: u64,
+        max_age: Duration,
+        block_stream: CurrentBlockStream,
+        client: Client,
+    ) -> Result<Self> {
         let graph_api = UniV3SubgraphClient::for_chain(chain_id, client)?;
+        Self::with_graph_api(graph_api, max_age, block_stream).await
+    }
+
+    /// Creates a new pool fetcher using the specified subgraph client, for
+    /// example for deployments without a hosted subgraph.
+    pub async fn with_graph_api(
+        graph_api: UniV3SubgraphClient,
+        max_age: Duration,
+        block_stream: CurrentBlockStream,
+    ) -> Result<Self> {
         let registered_pools = graph_api.get_registered_pools().await?;
         tracing::debug!(
             block = %registered_pools.fetched_block_number, pools = %registered_pools.pools.len(),
@@ -127,10 +198,25 @@ impl UniswapV3PoolFetcher {
             graph_api,
             cache: Default::default(),
             max_age,
+            block_stream,
         })
     }
 
+    /// Creates a new pool fetcher with the default maximum cache age for the
+    /// block time of the chain.
+    pub async fn with_default_max_age(
+        chain_id: u64,
+        block_stream: CurrentBlockStream,
+        client: Client,
+    ) -> Result<Self> {
+        let max_age = Deployment::for_chain(chain_id)?.default_max_age();
+        Self::new(chain_id, max_age, block_stream, client).await
+    }
+
     async fn get_pools_and_update_cache(&self, pool_ids: &[H160]) -> Result<Vec<PoolData>> {
+        // Use the block from before the query since the pools might have been
+        // fetched before a new block arrived during the query.
+        let block = self.current_block();
         let pools = self.graph_api.get_pools_with_ticks_by_ids(pool_ids).await?;
         let now = Instant::now();
         let mut cache = self.cache.lock().unwrap();
@@ -140,6 +226,7 @@ impl UniswapV3PoolFetcher {
                 CachedPool {
                     pool: pool.clone(),
                     updated_at: now,
+                    updated_at_block: block,
                     requested_at: now,
                 },
             );
@@ -147,6 +234,17 @@ impl UniswapV3PoolFetcher {
         Ok(pools)
     }
 
+    fn current_block(&self) -> u64 {
+        self.block_stream.borrow().number
+    }
+
+    /// Returns whether a cached pool needs to be fetched again because a new
+    /// block arrived or because it is older than the maximum age.
+    fn is_outdated(&self, cached: &CachedPool, now: Instant, block: u64) -> bool {
+        cached.updated_at_block < block
+            || now.saturating_duration_since(cached.updated_at) >= self.max_age
+    }
+
     /// Returns cached pools and ids of outdated pools.
     fn get_cached_pools(&self, token_pairs: &HashSet<TokenPair>) -> (Vec<PoolData>, Vec<H160>) {
         let mut pool_ids = token_pairs
@@ -158,11 +256,10 @@ impl UniswapV3PoolFetcher {
         match pool_ids.peek() {
             Some(_) => {
                 let now = Instant::now();
+                let block = self.current_block();
                 let mut cache = self.cache.lock().unwrap();
                 pool_ids.partition_map(|pool_id| match cache.get_mut(pool_id) {
-                    Some(entry)
-                        if now.saturating_duration_since(entry.updated_at) < self.max_age =>
-                    {
+                    Some(entry) if !self.is_outdated(entry, now, block) => {
                         entry.requested_at = now;
                         Either::Left(entry.pool.clone())
                     }
@@ -196,9 +293,38 @@ pub struct AutoUpdatingUniswapV3PoolFetcher(Arc<UniswapV3PoolFetcher>);
 impl AutoUpdatingUniswapV3PoolFetcher {
     /// Creates new CachingUniswapV3PoolFetcher with the purpose of spawning an additional
     /// background task for periodic update of cache
-    pub async fn new(chain_id: u64, max_age: Duration, client: Client) -> Result<Self> {
+    pub async fn new(
+        chain_id: u64,
+        max_age: Duration,
+        block_stream: CurrentBlockStream,
+        client: Client,
+    ) -> Result<Self> {
         Ok(Self(Arc::new(
-            UniswapV3PoolFetcher::new(chain_id, max_age, client).await?,
+            UniswapV3PoolFetcher::new(chain_id, max_age, block_stream, client).await?,
+        )))
+    }
+
+    /// Creates new CachingUniswapV3PoolFetcher using the specified subgraph
+    /// client, for example for deployments without a hosted subgraph.
+    pub async fn with_graph_api(
+        graph_api: UniV3SubgraphClient,
+        max_age: Duration,
+        block_stream: CurrentBlockStream,
+    ) -> Result<Self> {
+        Ok(Self(Arc::new(
+            UniswapV3PoolFetcher::with_graph_api(graph_api, max_age, block_stream).await?,
+        )))
+    }
+
+    /// Creates a new pool fetcher with the default maximum cache age for the
+    /// block time of the chain.
+    pub async fn with_default_max_age(
+        chain_id: u64,
+        block_stream: CurrentBlockStream,
+        client: Client,
+    ) -> Result<Self> {
+        Ok(Self(Arc::new(
+            UniswapV3PoolFetcher::with_default_max_age(chain_id, block_stream, client).await?,
         )))
     }
 
@@ -213,6 +339,26 @@ impl AutoUpdatingUniswapV3PoolFetcher {
             update_size,
         ));
     }
+
+    /// Spawns a background task maintaining the cache on every `block_interval`-th new block of
+    /// the block stream instead of once per fixed interval, so that updates follow the activity of
+    /// the chain. Updates are at least `min_spacing` apart, which delays them to a later block on
+    /// chains with short block times.
+    /// `update_size` limits the number of pools updated at once like for
+    /// [`Self::spawn_maintenance_task`].
+    pub fn spawn_block_maintenance_task(
+        &self,
+        block_interval: u64,
+        min_spacing: Duration,
+        update_size: Option<usize>,
+    ) {
+        tokio::spawn(update_outdated_pools_on_new_blocks(
+            Arc::downgrade(&self.0),
+            self.0.block_stream.clone(),
+            BlockSchedule::new(block_interval, min_spacing),
+            update_size,
+        ));
+    }
 }
 
 #[async_trait::async_trait]
@@ -229,41 +375,148 @@ async fn update_recently_used_outdated_pools(
 ) {
     while let Some(inner) = inner.upgrade() {
         let now = Instant::now();
+        update_outdated_pools(&inner, update_size).await;
+        tokio::time::sleep(update_interval.saturating_sub(now.elapsed())).await;
+    }
+}
 
-        let mut outdated_entries = inner
-            .cache
-            .lock()
-            .unwrap()
-            .iter()
-            .filter(|(_, cached)| now.saturating_duration_since(cached.updated_at) > inner.max_age)
-            .map(|(pool_id, cached)| (*pool_id, cached.requested_at))
-            .collect::<Vec<_>>();
-        outdated_entries.sort_by_key(|entry| std::cmp::Reverse(entry.1));
+async fn update_outdated_pools_on_new_blocks(
+    inner: Weak<UniswapV3PoolFetcher>,
+    mut block_stream: CurrentBlockStream,
+    mut schedule: BlockSchedule,
+    update_size: Option<usize>,
+) {
+    while block_stream.changed().await.is_ok() {
+        let block = block_stream.borrow().number;
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => break,
+        };
+        if schedule.is_due(block, Instant::now()) {
+            update_outdated_pools(&inner, update_size).await;
+        }
+    }
+}
 
-        let pools_to_update = outdated_entries
-            .iter()
-            .take(update_size.unwrap_or(outdated_entries.len()))
-            .map(|(pool_id, _)| *pool_id)
-            .collect::<Vec<_>>();
-
-        if !pools_to_update.is_empty() {
-            if let Err(err) = inner.get_pools_and_update_cache(&pools_to_update).await {
-                tracing::warn!(
-                    error = %err,
-                    "failed to update pools",
-                );
-            }
+/// Updates the outdated pools, the most recently requested ones first.
+async fn update_outdated_pools(inner: &UniswapV3PoolFetcher, update_size: Option<usize>) {
+    let now = Instant::now();
+    let block = inner.current_block();
+
+    let mut outdated_entries = inner
+        .cache
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, cached)| inner.is_outdated(cached, now, block))
+        .map(|(pool_id, cached)| (*pool_id, cached.requested_at))
+        .collect::<Vec<_>>();
+    outdated_entries.sort_by_key(|entry| std::cmp::Reverse(entry.1));
+
+    let pools_to_update = outdated_entries
+        .iter()
+        .take(update_size.unwrap_or(outdated_entries.len()))
+        .map(|(pool_id, _)| *pool_id)
+        .collect::<Vec<_>>();
+
+    if !pools_to_update.is_empty() {
+        if let Err(err) = inner.get_pools_and_update_cache(&pools_to_update).await {
+            tracing::warn!(
+                error = %err,
+                "failed to update pools",
+            );
         }
+    }
+}
 
-        tokio::time::sleep(update_interval.saturating_sub(now.elapsed())).await;
+/// Decides on which new blocks block-triggered maintenance runs.
+struct BlockSchedule {
+    block_interval: u64,
+    min_spacing: Duration,
+    last_update: Option<(u64, Instant)>,
+}
+
+impl BlockSchedule {
+    fn new(block_interval: u64, min_spacing: Duration) -> Self {
+        Self {
+            block_interval: block_interval.max(1),
+            min_spacing,
+            last_update: None,
+        }
+    }
+
+    /// Returns whether maintenance is due on the new block, recording the
+    /// update if it is.
+    fn is_due(&mut self, block: u64, now: Instant) -> bool {
+        if let Some((last_block, last_time)) = self.last_update {
+            if block.saturating_sub(last_block) < self.block_interval
+                || now.saturating_duration_since(last_time) < self.min_spacing
+            {
+                return false;
+            }
+        }
+        self.last_update = Some((block, now));
+        true
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::current_block::BlockInfo;
     use serde_json::json;
     use std::str::FromStr;
+    use tokio::sync::watch;
+
+    /// A block stream that stays at the same block, so that cached pools are
+    /// only outdated by their age.
+    fn block_stream() -> CurrentBlockStream {
+        watch::channel(BlockInfo::default()).1
+    }
+
+    #[test]
+    fn quotes_pool_info_in_both_directions() {
+        let token = |id: u64| Token {
+            id: H160::from_low_u64_be(id),
+            symbol: id.to_string(),
+            decimals: 18,
+        };
+        let liquidity = BigInt::from(10u64.pow(18));
+        let pool = PoolInfo {
+            address: H160::from_low_u64_be(3),
+            tokens: vec![token(1), token(2)],
+            state: PoolState {
+                sqrt_price: U256::one() << 96,
+                liquidity: U256::exp10(18),
+                tick: BigInt::zero(),
+                liquidity_net: vec![
+                    (BigInt::from(-887_220), liquidity.clone()),
+                    (BigInt::from(887_220), -liquidity),
+                ],
+                fee: Ratio::new(3_000, 1_000_000),
+            },
+            gas_stats: PoolStats {
+                mean_gas: U256::from(300_000),
+            },
+        };
+        let (token0, token1) = (token(1).id, token(2).id);
+        let amount = U256::exp10(15);
+
+        // The pool is at price 1 so quotes are symmetric.
+        for (in_token, out_token) in [(token0, token1), (token1, token0)] {
+            let out_amount = pool.get_amount_out(out_token, (amount, in_token)).unwrap();
+            assert!(out_amount < amount * 997 / 1000);
+            let in_amount = pool
+                .get_amount_in(in_token, (out_amount, out_token))
+                .unwrap();
+            assert!(in_amount <= amount && amount - in_amount <= U256::from(1));
+        }
+        assert_eq!(
+            pool.get_amount_out(token0, (amount, H160::from_low_u64_be(4))),
+            None
+        );
+        assert_eq!(pool.gas_cost(), 300_000);
+    }
 
     #[test]
     fn encode_decode_pool_info() {
@@ -345,12 +598,53 @@ mod tests {
         assert_eq!(pool, deserialized);
     }
 
+    #[test]
+    fn outdates_pools_on_new_blocks() {
+        let block = |number| BlockInfo {
+            number,
+            ..Default::default()
+        };
+        let (sender, block_stream) = watch::channel(block(10));
+        let fetcher = UniswapV3PoolFetcher {
+            graph_api: UniV3SubgraphClient::for_chain(1, Client::new()).unwrap(),
+            pools_by_token_pair: Default::default(),
+            cache: Default::default(),
+            max_age: Duration::from_secs(60),
+            block_stream,
+        };
+        let now = Instant::now();
+        let cached = CachedPool {
+            pool: Default::default(),
+            updated_at: now,
+            updated_at_block: 10,
+            requested_at: now,
+        };
+        assert!(!fetcher.is_outdated(&cached, now, fetcher.current_block()));
+        assert!(fetcher.is_outdated(&cached, now + Duration::from_secs(60), 10));
+
+        sender.send(block(11)).unwrap();
+        assert!(fetcher.is_outdated(&cached, now, fetcher.current_block()));
+    }
+
+    #[test]
+    fn schedules_maintenance_on_every_nth_block() {
+        let start = Instant::now();
+        let mut schedule = BlockSchedule::new(2, Duration::from_secs(10));
+        assert!(schedule.is_due(10, start));
+        assert!(!schedule.is_due(11, start + Duration::from_secs(20)));
+        assert!(schedule.is_due(12, start + Duration::from_secs(20)));
+        // Too soon after the previous update.
+        assert!(!schedule.is_due(14, start + Duration::from_secs(25)));
+        assert!(schedule.is_due(15, start + Duration::from_secs(30)));
+    }
+
     #[tokio::test]
     #[ignore]
     async fn uniswap_v3_pool_fetcher_test() {
-        let fetcher = UniswapV3PoolFetcher::new(1, Duration::from_secs(10), Client::new())
-            .await
-            .unwrap();
+        let fetcher =
+            UniswapV3PoolFetcher::new(1, Duration::from_secs(10), block_stream(), Client::new())
+                .await
+                .unwrap();
 
         assert!(!fetcher.pools_by_token_pair.is_empty());
         assert!(!fetcher.cache.lock().unwrap().is_empty());
@@ -359,10 +653,14 @@ mod tests {
     #[tokio::test]
     #[ignore]
     async fn caching_uniswap_v3_pool_fetcher_test() {
-        let fetcher =
-            AutoUpdatingUniswapV3PoolFetcher::new(1, Duration::from_secs(10), Client::new())
-                .await
-                .unwrap();
+        let fetcher = AutoUpdatingUniswapV3PoolFetcher::new(
+            1,
+            Duration::from_secs(10),
+            block_stream(),
+            Client::new(),
+        )
+        .await
+        .unwrap();
 
         fetcher.spawn_maintenance_task(Duration::from_secs(1), Some(50));
 
@@ -374,10 +672,14 @@ mod tests {
     #[tokio::test]
     #[ignore]
     async fn fetch_test() {
-        let fetcher =
-            AutoUpdatingUniswapV3PoolFetcher::new(1, Duration::from_secs(10), Client::new())
-                .await
-                .unwrap();
+        let fetcher = AutoUpdatingUniswapV3PoolFetcher::new(
+            1,
+            Duration::from_secs(10),
+            block_stream(),
+            Client::new(),
+        )
+        .await
+        .unwrap();
         let token_pairs = HashSet::from([TokenPair::new(
             H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap(),
             H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap(),
@@ -386,4 +688,22 @@ mod tests {
         let pools = fetcher.fetch(&token_pairs).await.unwrap();
         assert!(!pools.is_empty());
     }
+
+    #[tokio::test]
+    #[ignore]
+    async fn optimism_pool_fetcher_test() {
+        let fetcher = UniswapV3PoolFetcher::with_default_max_age(10, block_stream(), Client::new())
+            .await
+            .unwrap();
+        assert_eq!(fetcher.max_age, Duration::from_secs(10));
+
+        // WETH and USDC on Optimism.
+        let token_pairs = HashSet::from([TokenPair::new(
+            H160::from_str("0x4200000000000000000000000000000000000006").unwrap(),
+            H160::from_str("0x7F5c764cBc14f9669B88837ca1490cCa17c31607").unwrap(),
+        )
+        .unwrap()]);
+        let pools = fetcher.fetch(&token_pairs).await.unwrap();
+        assert!(!pools.is_empty());
+    }
 }
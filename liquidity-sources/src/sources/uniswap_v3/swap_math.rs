@@ -0,0 +1,838 @@
+//! Module implementing the Uniswap V3 swap math.
+//!
+//! Swaps move the square root of the price, a 96-bit fixed point number, through ranges of
+//! constant liquidity, and the liquidity changes whenever an initialized tick is crossed. This is
+//! a port of the pool's `TickMath`, `SqrtPriceMath` and `SwapMath` libraries, rounding identically
+//! to the contracts. Unlike the contracts, swap steps only end at initialized ticks and not also at
+//! the boundaries of tick bitmap words, so quotes can differ from on-chain swaps by rounding.
+
+use super::pool_fetching::PoolState;
+use crate::fixed_point::{div, mul_div, Rounding};
+use ethcontract::U256;
+use num::{rational::Ratio, ToPrimitive};
+
+/// The smallest tick supported by Uniswap V3 pools.
+pub const MIN_TICK: i32 = -887_272;
+/// The largest tick supported by Uniswap V3 pools.
+pub const MAX_TICK: i32 = 887_272;
+
+lazy_static::lazy_static! {
+    static ref Q96: U256 = U256::one() << 96;
+    /// The square root of the price at `MIN_TICK`.
+    static ref MIN_SQRT_RATIO: U256 = U256::from(4_295_128_739u64);
+    /// The square root of the price at `MAX_TICK`.
+    static ref MAX_SQRT_RATIO: U256 =
+        U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap();
+}
+
+/// Factors for computing `sqrt(1.0001^-(2^i))` as 128-bit fixed point numbers.
+const SQRT_RATIO_FACTORS: [u128; 20] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e213a,
+    0xfff2e50f5f656932ef12357cf3c7fdcc,
+    0xffe5caca7e10e4e61c3624eaa0941cd0,
+    0xffcb9843d60f6159c9db58835c926644,
+    0xff973b41fa98c081472e6896dfb254c0,
+    0xff2ea16466c96a3843ec78b326b52861,
+    0xfe5dee046a99a2a811c461f1969c3053,
+    0xfcbe86c7900a88aedcffc83b479aa3a4,
+    0xf987a7253ac413176f2b074cf7815e54,
+    0xf3392b0822b70005940c7a398e4b70f3,
+    0xe7159475a2c29b7443b29c7fa6e889d9,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e5,
+    0x70d869a156d2a1b890bb3df62baf32f7,
+    0x31be135f97d08fd981231505542fcfa6,
+    0x9aa508b5b7a84e1c677de54f3e99bc9,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe98,
+    0x48a170391f7dc42444e8fa2,
+];
+
+/// Returns the square root of the price at the specified tick as a 96-bit
+/// fixed point number.
+pub fn sqrt_ratio_at_tick(tick: i32) -> Option<U256> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return None;
+    }
+
+    let abs = tick.unsigned_abs();
+    let mut ratio = if abs & 1 != 0 {
+        U256::from(SQRT_RATIO_FACTORS[0])
+    } else {
+        U256::one() << 128
+    };
+    for (i, factor) in SQRT_RATIO_FACTORS.iter().enumerate().skip(1) {
+        if abs & (1 << i) != 0 {
+            ratio = (ratio * U256::from(*factor)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::max_value() / ratio;
+    }
+
+    // Round up when converting from 128-bit to 96-bit fixed point.
+    let rounding = !(ratio & U256::from(u32::MAX)).is_zero();
+    Some((ratio >> 32) + U256::from(rounding as u8))
+}
+
+/// Returns the largest tick whose square root price is at most the specified
+/// one, like the pool's `getTickAtSqrtRatio`.
+pub fn tick_at_sqrt_ratio(sqrt_price: U256) -> Option<i32> {
+    if sqrt_price < *MIN_SQRT_RATIO || sqrt_price >= *MAX_SQRT_RATIO {
+        return None;
+    }
+
+    // The square root price of `low` is at most the specified one and that of
+    // `high` is larger.
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if sqrt_ratio_at_tick(middle)? <= sqrt_price {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    Some(low)
+}
+
+/// Converts liquidity to a 96-bit fixed point number.
+fn shl_96(liquidity: U256) -> Option<U256> {
+    if liquidity.bits() > 160 {
+        return None;
+    }
+    Some(liquidity << 96)
+}
+
+/// Returns the amount of token 0 for the liquidity between two prices.
+fn amount0_delta(sqrt_a: U256, sqrt_b: U256, liquidity: U256, round_up: bool) -> Option<U256> {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    if sqrt_a.is_zero() {
+        return None;
+    }
+    let numerator = shl_96(liquidity)?;
+    let amount = mul_div(
+        numerator,
+        sqrt_b - sqrt_a,
+        sqrt_b,
+        Rounding::up_if(round_up),
+    )?;
+    if round_up {
+        div(amount, sqrt_a, Rounding::Up)
+    } else {
+        Some(amount / sqrt_a)
+    }
+}
+
+/// Returns the amount of token 1 for the liquidity between two prices.
+fn amount1_delta(sqrt_a: U256, sqrt_b: U256, liquidity: U256, round_up: bool) -> Option<U256> {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    mul_div(liquidity, sqrt_b - sqrt_a, *Q96, Rounding::up_if(round_up))
+}
+
+/// Returns the price after adding or removing an amount of token 0, rounding
+/// up so that the price moves far enough.
+fn next_sqrt_price_from_amount0(
+    sqrt_price: U256,
+    liquidity: U256,
+    amount: U256,
+    add: bool,
+) -> Option<U256> {
+    if amount.is_zero() {
+        return Some(sqrt_price);
+    }
+    let numerator = shl_96(liquidity)?;
+    let product = amount.checked_mul(sqrt_price);
+    if add {
+        match product.and_then(|product| numerator.checked_add(product)) {
+            Some(denominator) => mul_div(numerator, sqrt_price, denominator, Rounding::Up),
+            None => div(
+                numerator,
+                (numerator / sqrt_price).checked_add(amount)?,
+                Rounding::Up,
+            ),
+        }
+    } else {
+        let denominator = numerator.checked_sub(product?).filter(|d| !d.is_zero())?;
+        mul_div(numerator, sqrt_price, denominator, Rounding::Up)
+    }
+}
+
+/// Returns the price after adding or removing an amount of token 1, rounding
+/// down so that the price does not move too far.
+fn next_sqrt_price_from_amount1(
+    sqrt_price: U256,
+    liquidity: U256,
+    amount: U256,
+    add: bool,
+) -> Option<U256> {
+    if add {
+        sqrt_price.checked_add(mul_div(amount, *Q96, liquidity, Rounding::Down)?)
+    } else {
+        sqrt_price
+            .checked_sub(mul_div(amount, *Q96, liquidity, Rounding::Up)?)
+            .filter(|price| !price.is_zero())
+    }
+}
+
+/// The result of a swap step within a range of constant liquidity.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Step {
+    sqrt_price_next: U256,
+    amount_in: U256,
+    amount_out: U256,
+    fee_amount: U256,
+}
+
+/// Swaps within a range of constant liquidity from the current price towards the target price.
+///
+/// `remaining` is the remaining input amount including fees for exact input swaps, and the
+/// remaining output amount for exact output swaps.
+fn compute_swap_step(
+    sqrt_price: U256,
+    sqrt_price_target: U256,
+    liquidity: U256,
+    remaining: U256,
+    exact_in: bool,
+    fee: &Ratio<u32>,
+) -> Option<Step> {
+    let zero_for_one = sqrt_price >= sqrt_price_target;
+    let fee_numerator = U256::from(*fee.numer());
+    let fee_denominator = U256::from(*fee.denom());
+    let fee_complement = fee_denominator.checked_sub(fee_numerator)?;
+
+    let (amount_in_to_target, amount_out_to_target, sqrt_price_next) = if exact_in {
+        let remaining_less_fee =
+            mul_div(remaining, fee_complement, fee_denominator, Rounding::Down)?;
+        let amount_in = if zero_for_one {
+            amount0_delta(sqrt_price_target, sqrt_price, liquidity, true)?
+        } else {
+            amount1_delta(sqrt_price, sqrt_price_target, liquidity, true)?
+        };
+        let next = if remaining_less_fee >= amount_in {
+            sqrt_price_target
+        } else if zero_for_one {
+            next_sqrt_price_from_amount0(sqrt_price, liquidity, remaining_less_fee, true)?
+        } else {
+            next_sqrt_price_from_amount1(sqrt_price, liquidity, remaining_less_fee, true)?
+        };
+        (Some(amount_in), None, next)
+    } else {
+        let amount_out = if zero_for_one {
+            amount1_delta(sqrt_price_target, sqrt_price, liquidity, false)?
+        } else {
+            amount0_delta(sqrt_price, sqrt_price_target, liquidity, false)?
+        };
+        let next = if remaining >= amount_out {
+            sqrt_price_target
+        } else if zero_for_one {
+            next_sqrt_price_from_amount1(sqrt_price, liquidity, remaining, false)?
+        } else {
+            next_sqrt_price_from_amount0(sqrt_price, liquidity, remaining, false)?
+        };
+        (None, Some(amount_out), next)
+    };
+
+    // The amounts to the target price are reused if the target was reached.
+    let reached_target = sqrt_price_next == sqrt_price_target;
+    let amount_in = match amount_in_to_target {
+        Some(amount_in) if reached_target => amount_in,
+        _ if zero_for_one => amount0_delta(sqrt_price_next, sqrt_price, liquidity, true)?,
+        _ => amount1_delta(sqrt_price, sqrt_price_next, liquidity, true)?,
+    };
+    let mut amount_out = match amount_out_to_target {
+        Some(amount_out) if reached_target => amount_out,
+        _ if zero_for_one => amount1_delta(sqrt_price_next, sqrt_price, liquidity, false)?,
+        _ => amount0_delta(sqrt_price, sqrt_price_next, liquidity, false)?,
+    };
+    if !exact_in {
+        amount_out = amount_out.min(remaining);
+    }
+
+    // The rest of the input amount is taken as fee if the swap completes
+    // before the target price.
+    let fee_amount = if exact_in && !reached_target {
+        remaining.checked_sub(amount_in)?
+    } else {
+        mul_div(amount_in, fee_numerator, fee_complement, Rounding::Up)?
+    };
+    Some(Step {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+/// Returns the amount of the output token for selling the specified amount of
+/// the input token to the pool, where token 0 is sold for token 1 if
+/// `zero_for_one` is set.
+///
+/// Returns `None` if the pool does not have enough liquidity for the swap.
+pub fn get_amount_out(pool: &PoolState, zero_for_one: bool, amount_in: U256) -> Option<U256> {
+    Some(swap(pool, zero_for_one, amount_in, true)?.1)
+}
+
+/// Returns the amount of the input token, including fees, for buying the
+/// specified amount of the output token from the pool, where token 0 is sold
+/// for token 1 if `zero_for_one` is set.
+///
+/// Returns `None` if the pool does not have enough liquidity for the swap.
+pub fn get_amount_in(pool: &PoolState, zero_for_one: bool, amount_out: U256) -> Option<U256> {
+    Some(swap(pool, zero_for_one, amount_out, false)?.0)
+}
+
+/// Returns the amounts of the output token for selling each of the amounts of
+/// the input token, like [`get_amount_out`], but with a single walk over the
+/// ticks of the pool for all amounts.
+pub fn get_amounts_out(
+    pool: &PoolState,
+    zero_for_one: bool,
+    amounts_in: &[U256],
+) -> Vec<Option<U256>> {
+    swap_ladder(pool, zero_for_one, amounts_in, true)
+        .into_iter()
+        .map(|amounts| Some(amounts?.1))
+        .collect()
+}
+
+/// Returns the largest amount of the input token, including fees, that the
+/// pool can take until the price reaches its last initialized tick in the
+/// direction of the swap, where token 0 is sold for token 1 if `zero_for_one`
+/// is set.
+///
+/// There is no liquidity beyond the last initialized tick of a consistent
+/// pool, so larger swaps can't be quoted. Returns `None` if the amount
+/// overflows.
+pub fn max_amount_in(pool: &PoolState, zero_for_one: bool) -> Option<U256> {
+    amount_in_until(pool, zero_for_one, None)
+}
+
+/// Returns the amount of the input token, including fees, that the pool takes
+/// until the price reaches the specified tick, crossing all initialized ticks
+/// before it, where token 0 is sold for token 1 if `zero_for_one` is set.
+///
+/// This is the largest amount that can be swapped for pools whose initialized
+/// ticks are only known up to that tick. Returns `None` if the amount
+/// overflows.
+pub fn amount_in_until_tick(pool: &PoolState, zero_for_one: bool, tick: i32) -> Option<U256> {
+    amount_in_until(pool, zero_for_one, Some(tick))
+}
+
+fn amount_in_until(pool: &PoolState, zero_for_one: bool, limit: Option<i32>) -> Option<U256> {
+    let ticks = initialized_ticks(pool)?;
+    let tick = pool.tick.to_i32()?;
+    // The same ticks are crossed as when swapping, see `swap`.
+    let crossed: Vec<_> = if zero_for_one {
+        ticks
+            .into_iter()
+            .rev()
+            .filter(|(t, _)| *t <= tick && limit.map_or(true, |limit| *t > limit))
+            .collect()
+    } else {
+        ticks
+            .into_iter()
+            .filter(|(t, _)| *t > tick && limit.map_or(true, |limit| *t < limit))
+            .collect()
+    };
+
+    let mut sqrt_price = pool.sqrt_price;
+    let mut liquidity = pool.liquidity;
+    let mut amount_in = U256::zero();
+    for (tick_next, liquidity_net) in crossed {
+        let target = sqrt_ratio_at_tick(tick_next.clamp(MIN_TICK, MAX_TICK))?;
+        let step = compute_swap_step(sqrt_price, target, liquidity, U256::MAX, true, &pool.fee)?;
+        amount_in = amount_in
+            .checked_add(step.amount_in)?
+            .checked_add(step.fee_amount)?;
+        sqrt_price = target;
+        let delta = if zero_for_one {
+            liquidity_net.checked_neg()?
+        } else {
+            liquidity_net
+        };
+        liquidity = add_delta(liquidity, delta)?;
+    }
+
+    if let Some(limit) = limit {
+        let target = sqrt_ratio_at_tick(limit.clamp(MIN_TICK, MAX_TICK))?;
+        let towards_target = if zero_for_one {
+            target < sqrt_price
+        } else {
+            target > sqrt_price
+        };
+        if towards_target {
+            let step =
+                compute_swap_step(sqrt_price, target, liquidity, U256::MAX, true, &pool.fee)?;
+            amount_in = amount_in
+                .checked_add(step.amount_in)?
+                .checked_add(step.fee_amount)?;
+        }
+    }
+    Some(amount_in)
+}
+
+/// Returns the initialized ticks of the pool with their net liquidity, sorted
+/// by tick.
+fn initialized_ticks(pool: &PoolState) -> Option<Vec<(i32, i128)>> {
+    let mut ticks = pool
+        .liquidity_net
+        .iter()
+        .map(|(tick, liquidity_net)| Some((tick.to_i32()?, liquidity_net.to_i128()?)))
+        .collect::<Option<Vec<_>>>()?;
+    ticks.sort_unstable();
+    Some(ticks)
+}
+
+/// Simulates a swap, returning the input amount including fees and the output
+/// amount.
+fn swap(
+    pool: &PoolState,
+    zero_for_one: bool,
+    amount: U256,
+    exact_in: bool,
+) -> Option<(U256, U256)> {
+    swap_ladder(pool, zero_for_one, &[amount], exact_in).pop()?
+}
+
+/// Simulates swaps of several amounts with a single walk over the ticks,
+/// returning the input amounts including fees and the output amounts in the
+/// same order as the amounts.
+///
+/// The amounts are swapped in increasing order. The steps that a swap takes
+/// across whole ranges of constant liquidity are the same for all larger
+/// amounts, so only the last steps of each swap are taken per amount.
+fn swap_ladder(
+    pool: &PoolState,
+    zero_for_one: bool,
+    amounts: &[U256],
+    exact_in: bool,
+) -> Vec<Option<(U256, U256)>> {
+    let mut results = vec![None; amounts.len()];
+    let (ticks, mut state) = match initialized_ticks(pool).zip(SwapState::new(pool)) {
+        Some(walk) => walk,
+        None => return results,
+    };
+    let mut order = (0..amounts.len()).collect::<Vec<_>>();
+    order.sort_unstable_by_key(|index| amounts[*index]);
+
+    for index in order {
+        let amount = amounts[index];
+        results[index] = loop {
+            let remaining = match state.remaining(amount, exact_in) {
+                Some(remaining) if remaining.is_zero() => break state.amounts(),
+                Some(remaining) => remaining,
+                None => break None,
+            };
+            let mut next = state.clone();
+            match next.step(pool, &ticks, zero_for_one, remaining, exact_in) {
+                // A step that crosses a tick with some of the amount left is
+                // the same for all larger amounts.
+                Some(true) if next.remaining(amount, exact_in) != Some(U256::zero()) => {
+                    state = next
+                }
+                Some(_) => break next.finish(pool, &ticks, zero_for_one, amount, exact_in),
+                None => break None,
+            }
+        };
+    }
+    results
+}
+
+/// The state of a swap between steps.
+#[derive(Clone, Debug)]
+struct SwapState {
+    sqrt_price: U256,
+    tick: i32,
+    liquidity: U256,
+    amount_in: U256,
+    amount_out: U256,
+}
+
+impl SwapState {
+    fn new(pool: &PoolState) -> Option<Self> {
+        Some(Self {
+            sqrt_price: pool.sqrt_price,
+            tick: pool.tick.to_i32()?,
+            liquidity: pool.liquidity,
+            amount_in: U256::zero(),
+            amount_out: U256::zero(),
+        })
+    }
+
+    /// Returns the part of the amount that has not been swapped yet.
+    fn remaining(&self, amount: U256, exact_in: bool) -> Option<U256> {
+        amount.checked_sub(if exact_in {
+            self.amount_in
+        } else {
+            self.amount_out
+        })
+    }
+
+    fn amounts(&self) -> Option<(U256, U256)> {
+        Some((self.amount_in, self.amount_out))
+    }
+
+    /// Takes steps until the amount is swapped.
+    fn finish(
+        mut self,
+        pool: &PoolState,
+        ticks: &[(i32, i128)],
+        zero_for_one: bool,
+        amount: U256,
+        exact_in: bool,
+    ) -> Option<(U256, U256)> {
+        loop {
+            let remaining = self.remaining(amount, exact_in)?;
+            if remaining.is_zero() {
+                return self.amounts();
+            }
+            self.step(pool, ticks, zero_for_one, remaining, exact_in)?;
+        }
+    }
+
+    /// Swaps the remaining amount towards the next initialized tick, and
+    /// returns whether the tick was crossed.
+    fn step(
+        &mut self,
+        pool: &PoolState,
+        ticks: &[(i32, i128)],
+        zero_for_one: bool,
+        remaining: U256,
+        exact_in: bool,
+    ) -> Option<bool> {
+        let limit = if zero_for_one {
+            *MIN_SQRT_RATIO + 1
+        } else {
+            *MAX_SQRT_RATIO - 1
+        };
+        if self.sqrt_price == limit {
+            return None;
+        }
+
+        // The next initialized tick is the closest one at or below the
+        // current tick when the price moves down, and the closest one above
+        // it when the price moves up.
+        let index = ticks.partition_point(|(initialized, _)| *initialized <= self.tick);
+        let next = if zero_for_one {
+            index.checked_sub(1).map(|index| ticks[index])
+        } else {
+            ticks.get(index).copied()
+        };
+        let tick_next = match next {
+            Some((tick_next, _)) => tick_next.clamp(MIN_TICK, MAX_TICK),
+            None if zero_for_one => MIN_TICK,
+            None => MAX_TICK,
+        };
+        let sqrt_price_next = sqrt_ratio_at_tick(tick_next)?;
+        let target = if zero_for_one {
+            sqrt_price_next.max(limit)
+        } else {
+            sqrt_price_next.min(limit)
+        };
+
+        let step = compute_swap_step(
+            self.sqrt_price,
+            target,
+            self.liquidity,
+            remaining,
+            exact_in,
+            &pool.fee,
+        )?;
+        let paid = step.amount_in.checked_add(step.fee_amount)?;
+        self.amount_in = self.amount_in.checked_add(paid)?;
+        self.amount_out = self.amount_out.checked_add(step.amount_out)?;
+        self.sqrt_price = step.sqrt_price_next;
+
+        if self.sqrt_price != sqrt_price_next {
+            return Some(false);
+        }
+        if let Some((_, liquidity_net)) = next {
+            let delta = if zero_for_one {
+                liquidity_net.checked_neg()?
+            } else {
+                liquidity_net
+            };
+            self.liquidity = add_delta(self.liquidity, delta)?;
+        }
+        self.tick = if zero_for_one {
+            tick_next - 1
+        } else {
+            tick_next
+        };
+        Some(true)
+    }
+}
+
+fn add_delta(liquidity: U256, delta: i128) -> Option<U256> {
+    if delta >= 0 {
+        liquidity.checked_add(U256::from(delta as u128))
+    } else {
+        liquidity.checked_sub(U256::from(delta.unsigned_abs()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::amm::{self, Decimal};
+    use num::BigInt;
+    use serde::Deserialize;
+
+    fn e18(value: u64) -> U256 {
+        U256::from(value) * U256::exp10(18)
+    }
+
+    fn dec(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    /// Returns a pool at price 1 with the specified liquidity changes.
+    fn pool(liquidity: U256, ticks: &[(i32, i128)]) -> PoolState {
+        PoolState {
+            sqrt_price: *Q96,
+            liquidity,
+            tick: BigInt::from(0),
+            liquidity_net: ticks
+                .iter()
+                .map(|(tick, liquidity_net)| (BigInt::from(*tick), BigInt::from(*liquidity_net)))
+                .collect(),
+            fee: Ratio::new(3_000, 1_000_000),
+        }
+    }
+
+    #[test]
+    fn sqrt_ratios() {
+        assert_eq!(sqrt_ratio_at_tick(0), Some(*Q96));
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK), Some(*MIN_SQRT_RATIO));
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK), Some(*MAX_SQRT_RATIO));
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK - 1), None);
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK + 1), None);
+    }
+
+    #[test]
+    fn ticks_at_sqrt_ratios() {
+        for tick in [MIN_TICK, -100_000, -1, 0, 1, 50, 100_000, MAX_TICK - 1] {
+            let sqrt_ratio = sqrt_ratio_at_tick(tick).unwrap();
+            assert_eq!(tick_at_sqrt_ratio(sqrt_ratio), Some(tick));
+            assert_eq!(tick_at_sqrt_ratio(sqrt_ratio + 1), Some(tick));
+            assert_eq!(
+                tick_at_sqrt_ratio(sqrt_ratio - 1),
+                tick.checked_sub(1).filter(|tick| *tick >= MIN_TICK)
+            );
+        }
+        assert_eq!(tick_at_sqrt_ratio(*MAX_SQRT_RATIO), None);
+    }
+
+    #[test]
+    fn amount_deltas() {
+        #[derive(Deserialize)]
+        struct Input {
+            sqrt_price_a: Decimal,
+            sqrt_price_b: Decimal,
+            liquidity: Decimal,
+            round_up: bool,
+        }
+        #[derive(Deserialize)]
+        struct Output {
+            amount0: Decimal,
+            amount1: Decimal,
+        }
+
+        for vector in amm::vectors::<Input, Output>("uniswap_v3_amount_deltas.json") {
+            let Input {
+                sqrt_price_a: Decimal(sqrt_a),
+                sqrt_price_b: Decimal(sqrt_b),
+                liquidity: Decimal(liquidity),
+                round_up,
+            } = vector.input;
+            assert_eq!(
+                amount0_delta(sqrt_a, sqrt_b, liquidity, round_up),
+                Some(vector.output.amount0.0),
+                "{}",
+                vector.source
+            );
+            assert_eq!(
+                amount1_delta(sqrt_a, sqrt_b, liquidity, round_up),
+                Some(vector.output.amount1.0),
+                "{}",
+                vector.source
+            );
+        }
+    }
+
+    #[test]
+    fn swap_step_capped_at_target_price() {
+        #[derive(Deserialize)]
+        struct Input {
+            sqrt_price: Decimal,
+            sqrt_price_target: Decimal,
+            liquidity: Decimal,
+            amount_remaining: Decimal,
+            exact_in: bool,
+            fee_pips: u32,
+        }
+        #[derive(Deserialize)]
+        struct Output {
+            sqrt_price_next: Decimal,
+            amount_in: Decimal,
+            amount_out: Decimal,
+            fee_amount: Decimal,
+        }
+
+        for vector in amm::vectors::<Input, Output>("uniswap_v3_swap_steps.json") {
+            let input = vector.input;
+            let step = compute_swap_step(
+                input.sqrt_price.0,
+                input.sqrt_price_target.0,
+                input.liquidity.0,
+                input.amount_remaining.0,
+                input.exact_in,
+                &Ratio::new(input.fee_pips, 1_000_000),
+            );
+            let expected = Step {
+                sqrt_price_next: vector.output.sqrt_price_next.0,
+                amount_in: vector.output.amount_in.0,
+                amount_out: vector.output.amount_out.0,
+                fee_amount: vector.output.fee_amount.0,
+            };
+            assert_eq!(step, Some(expected), "{}", vector.source);
+        }
+    }
+
+    #[test]
+    fn quotes_full_range_pool() {
+        let pool = pool(
+            e18(1),
+            &[(-887_220, 10i128.pow(18)), (887_220, -10i128.pow(18))],
+        );
+        let amount = U256::exp10(15);
+        // Matches the constant product formula for the input amount less fees.
+        assert_eq!(
+            get_amount_out(&pool, true, amount),
+            Some(996_006_981_039_903u64.into())
+        );
+        assert_eq!(
+            get_amount_in(&pool, false, amount),
+            Some(1_004_013_040_121_367u64.into())
+        );
+    }
+
+    #[test]
+    fn quotes_across_initialized_ticks() {
+        let liquidity = 10i128.pow(18);
+        let pool = pool(
+            e18(2),
+            &[
+                (-120, liquidity),
+                (-60, liquidity),
+                (60, -liquidity),
+                (120, -liquidity),
+            ],
+        );
+        for zero_for_one in [true, false] {
+            assert_eq!(
+                get_amount_out(&pool, zero_for_one, U256::from(7) * U256::exp10(15)),
+                Some(6_954_265_624_962_140u64.into())
+            );
+        }
+        assert_eq!(
+            get_amount_in(&pool, false, U256::from(8) * U256::exp10(15)),
+            Some(8_058_346_597_825_099u64.into())
+        );
+        // There is not enough liquidity beyond the last initialized tick.
+        assert_eq!(get_amount_out(&pool, true, U256::exp10(17)), None);
+        assert_eq!(get_amount_in(&pool, false, U256::exp10(16)), None);
+    }
+
+    #[test]
+    fn swap_properties() {
+        let liquidity = 10i128.pow(18);
+        let pool = pool(
+            e18(2),
+            &[
+                (-120, liquidity),
+                (-60, liquidity),
+                (60, -liquidity),
+                (120, -liquidity),
+            ],
+        );
+        // Large enough to run out of liquidity beyond the last initialized
+        // tick, which is covered by the properties too.
+        let amounts = amm::amounts(U256::exp10(12), U256::exp10(17), 31);
+        for zero_for_one in [true, false] {
+            amm::check_swap_properties(
+                |amount_in| get_amount_out(&pool, zero_for_one, amount_in),
+                |amount_out| get_amount_in(&pool, zero_for_one, amount_out),
+                &amounts,
+                1e-9,
+            );
+        }
+    }
+
+    #[test]
+    fn max_amount_in_until_last_initialized_tick() {
+        let liquidity = 10i128.pow(18);
+        let in_range = pool(
+            e18(2),
+            &[
+                (-120, liquidity),
+                (-60, liquidity),
+                (60, -liquidity),
+                (120, -liquidity),
+            ],
+        );
+        for zero_for_one in [true, false] {
+            let max = max_amount_in(&in_range, zero_for_one).unwrap();
+            assert!(get_amount_out(&in_range, zero_for_one, max).is_some());
+            assert_eq!(get_amount_out(&in_range, zero_for_one, max + 1), None);
+        }
+        // Without initialized ticks in the direction of the swap there is no
+        // liquidity to trade with.
+        let above_range = pool(U256::zero(), &[(60, liquidity), (120, -liquidity)]);
+        assert_eq!(max_amount_in(&above_range, true), Some(U256::zero()));
+        assert_eq!(get_amount_out(&above_range, true, U256::one()), None);
+    }
+
+    #[test]
+    fn quotes_amount_ladders_in_one_walk() {
+        let liquidity = 10i128.pow(18);
+        let pool = pool(
+            e18(2),
+            &[
+                (-120, liquidity),
+                (-60, liquidity),
+                (60, -liquidity),
+                (120, -liquidity),
+            ],
+        );
+        // Unsorted amounts, some ending exactly at initialized ticks.
+        let mut amounts = vec![
+            U256::exp10(17),
+            U256::exp10(12),
+            U256::from(7) * U256::exp10(15),
+            U256::exp10(15),
+            U256::zero(),
+        ];
+        for zero_for_one in [true, false] {
+            amounts.push(max_amount_in(&pool, zero_for_one).unwrap());
+        }
+        for zero_for_one in [true, false] {
+            let quotes = get_amounts_out(&pool, zero_for_one, &amounts);
+            assert_eq!(quotes.len(), amounts.len());
+            for (amount, quote) in amounts.iter().zip(quotes) {
+                assert_eq!(quote, get_amount_out(&pool, zero_for_one, *amount));
+            }
+            // Buy amounts can be quoted in one walk too.
+            let ladder = swap_ladder(&pool, zero_for_one, &amounts, false);
+            for (amount, quote) in amounts.iter().zip(ladder) {
+                assert_eq!(
+                    quote.map(|(amount_in, _)| amount_in),
+                    get_amount_in(&pool, zero_for_one, *amount)
+                );
+            }
+        }
+        assert_eq!(get_amounts_out(&pool, true, &[]), Vec::new());
+    }
+}
@@ -0,0 +1,7 @@
+//! Uniswap V4 baseline liquidity source implementation.
+//!
+//! Pools may have hooks contracts that run custom logic on swaps, which can
+//! not be modelled in general. Pools are flagged with their hooks so that
+//! consumers can decide which hooked pools to use.
+pub mod pool_fetching;
+pub mod registry;
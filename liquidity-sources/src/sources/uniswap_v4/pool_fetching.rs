@@ -0,0 +1,546 @@
+//! Module for fetching the current state of Uniswap V4 pools.
+//!
+//! The pool manager does not expose getters for pool state. Instead, state is
+//! read from its storage with `extsload` through the `StateView` lens
+//! contract.
+
+use super::registry::{HookFilter, PoolKey, PoolRegistry};
+use crate::{
+    baseline_solver::BaselineSolvable,
+    maintenance::Maintaining,
+    recent_block_cache::Block,
+    sources::{
+        uniswap_v2::pool_fetching::handle_contract_error,
+        uniswap_v3::{pool_fetching::PoolState, swap_math},
+        MAX_BATCH_SIZE,
+    },
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::UniswapV4StateView;
+use ethcontract::{errors::MethodError, tokens::Bytes, BlockId, H160, U256};
+use futures::future;
+use num::{rational::Ratio, BigInt};
+use std::{collections::HashSet, ops::RangeInclusive, sync::Arc};
+
+/// The number of tick bitmap words around the current tick for which
+/// initialized ticks are fetched. Each word covers 256 multiples of the pool's
+/// tick spacing.
+const TICK_BITMAP_WORDS: i16 = 2;
+
+/// Swaps are settled through the singleton pool manager, so they don't need
+/// any token transfers between pools.
+const POOL_SWAP_GAS_COST: usize = 100_000;
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The state of a Uniswap V4 pool.
+///
+/// Liquidity is only included for the ticks within `TICK_BITMAP_WORDS` bitmap
+/// words of the current tick, so swaps moving the price beyond them can't be
+/// quoted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pool {
+    pub key: PoolKey,
+    /// The pool state, where the fee is the current LP fee of the pool. This
+    /// is also set for pools with dynamic fees.
+    pub state: PoolState,
+    /// The range of ticks for which all initialized ticks are known.
+    pub known_ticks: RangeInclusive<i32>,
+}
+
+impl Pool {
+    /// Returns whether currency 0 is sold for currency 1, or `None` if the
+    /// tokens are not the currencies of the pool.
+    fn zero_for_one(&self, in_token: H160, out_token: H160) -> Option<bool> {
+        let currencies = (self.key.currency0, self.key.currency1);
+        if currencies == (in_token, out_token) {
+            Some(true)
+        } else if currencies == (out_token, in_token) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the largest amount of the input token that can be swapped
+    /// without moving the price beyond the known ticks, or `None` if the
+    /// amount overflows.
+    fn max_known_amount_in(&self, zero_for_one: bool) -> Option<U256> {
+        let tick = if zero_for_one {
+            *self.known_ticks.start()
+        } else {
+            self.known_ticks
+                .end()
+                .saturating_add(1)
+                .min(swap_math::MAX_TICK)
+        };
+        swap_math::amount_in_until_tick(&self.state, zero_for_one, tick)
+    }
+
+    /// Returns whether swapping the specified amount keeps the price within the
+    /// known ticks. Amounts are not limited if the largest one overflows.
+    fn is_known_amount_in(&self, zero_for_one: bool, amount_in: U256) -> bool {
+        self.max_known_amount_in(zero_for_one)
+            .map_or(true, |max| amount_in <= max)
+    }
+}
+
+/// Quotes swaps with the Uniswap V3 math, which assumes that the hooks of the
+/// pool don't change the swapped amounts. Pools with such hooks need to be
+/// excluded by the hook filter of the fetcher.
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let zero_for_one = self.zero_for_one(in_token, out_token)?;
+        if !self.is_known_amount_in(zero_for_one, in_amount) {
+            return None;
+        }
+        swap_math::get_amount_out(&self.state, zero_for_one, in_amount)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let zero_for_one = self.zero_for_one(in_token, out_token)?;
+        let in_amount = swap_math::get_amount_in(&self.state, zero_for_one, out_amount)?;
+        self.is_known_amount_in(zero_for_one, in_amount)
+            .then(|| in_amount)
+    }
+
+    fn get_amounts_out(
+        &self,
+        out_token: H160,
+        (in_amounts, in_token): (&[U256], H160),
+    ) -> Vec<Option<U256>> {
+        let zero_for_one = match self.zero_for_one(in_token, out_token) {
+            Some(zero_for_one) => zero_for_one,
+            None => return vec![None; in_amounts.len()],
+        };
+        let max = self.max_known_amount_in(zero_for_one);
+        swap_math::get_amounts_out(&self.state, zero_for_one, in_amounts)
+            .into_iter()
+            .zip(in_amounts)
+            .map(|(out_amount, in_amount)| match max {
+                Some(max) if *in_amount > max => None,
+                _ => out_amount,
+            })
+            .collect()
+    }
+
+    fn gas_cost(&self) -> usize {
+        POOL_SWAP_GAS_COST
+    }
+
+    fn max_amount_in(&self, in_token: H160, out_token: H160) -> Option<U256> {
+        let zero_for_one = self.zero_for_one(in_token, out_token)?;
+        self.max_known_amount_in(zero_for_one)
+    }
+}
+
+/// Uniswap V4 pool fetcher.
+pub struct UniswapV4PoolFetcher {
+    registry: Arc<PoolRegistry>,
+    state_view: UniswapV4StateView,
+    hook_filter: HookFilter,
+}
+
+impl UniswapV4PoolFetcher {
+    /// Creates a new pool fetcher, only returning pools accepted by the
+    /// specified hook filter.
+    pub async fn new(
+        web3: &Web3,
+        registry: Arc<PoolRegistry>,
+        hook_filter: HookFilter,
+    ) -> Result<Self> {
+        Ok(Self {
+            registry,
+            state_view: UniswapV4StateView::deployed(web3).await?,
+            hook_filter,
+        })
+    }
+
+    async fn fetch_slots(&self, keys: Vec<PoolKey>, block: BlockId) -> Result<Vec<FetchedSlot>> {
+        let web3 = self.state_view.raw_instance().web3();
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let futures = keys
+            .into_iter()
+            .map(|key| {
+                let id = Bytes(key.id.0);
+                let slot0 = self
+                    .state_view
+                    .get_slot_0(id)
+                    .block(block)
+                    .batch_call(&mut batch);
+                let liquidity = self
+                    .state_view
+                    .get_liquidity(id)
+                    .block(block)
+                    .batch_call(&mut batch);
+                async move {
+                    let slot0 = handle_contract_error(slot0.await)?;
+                    let liquidity = handle_contract_error(liquidity.await)?;
+                    Result::<_>::Ok(match (slot0, liquidity) {
+                        // Pools with a zero price are not initialized.
+                        (Some((sqrt_price, tick, _, lp_fee)), Some(liquidity))
+                            if !sqrt_price.is_zero() =>
+                        {
+                            Some(FetchedSlot {
+                                key,
+                                sqrt_price,
+                                tick,
+                                lp_fee,
+                                liquidity,
+                            })
+                        }
+                        _ => None,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(futures)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    async fn fetch_initialized_ticks(
+        &self,
+        slots: &[FetchedSlot],
+        block: BlockId,
+    ) -> Result<Vec<Vec<i32>>> {
+        let web3 = self.state_view.raw_instance().web3();
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let futures = slots
+            .iter()
+            .map(|slot| {
+                let spacing = slot.key.tick_spacing;
+                let words = bitmap_words(slot.tick, spacing)
+                    .map(|word| {
+                        let bitmap = self
+                            .state_view
+                            .get_tick_bitmap(Bytes(slot.key.id.0), word)
+                            .block(block)
+                            .batch_call(&mut batch);
+                        (word, bitmap)
+                    })
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut ticks = Vec::new();
+                    for (word, bitmap) in words {
+                        if let Some(bitmap) = handle_contract_error(bitmap.await)? {
+                            ticks.extend(initialized_ticks(word, bitmap, spacing));
+                        }
+                    }
+                    Result::<_>::Ok(ticks)
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        future::try_join_all(futures).await
+    }
+
+    async fn fetch_pools(&self, slots: Vec<FetchedSlot>, block: BlockId) -> Result<Vec<Pool>> {
+        let ticks = self.fetch_initialized_ticks(&slots, block).await?;
+
+        let web3 = self.state_view.raw_instance().web3();
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let futures = slots
+            .into_iter()
+            .zip(ticks)
+            .map(|(slot, ticks)| {
+                let liquidity_net = ticks
+                    .into_iter()
+                    .map(|tick| {
+                        let liquidity = self
+                            .state_view
+                            .get_tick_liquidity(Bytes(slot.key.id.0), tick)
+                            .block(block)
+                            .batch_call(&mut batch);
+                        (tick, liquidity)
+                    })
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut fetched = Vec::new();
+                    for (tick, liquidity) in liquidity_net {
+                        fetched.push((tick, liquidity.await));
+                    }
+                    handle_results(slot, fetched)
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        Ok(future::try_join_all(futures)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for UniswapV4PoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let keys = self
+            .registry
+            .pools_for_token_pairs(&token_pairs)
+            .await
+            .into_iter()
+            .filter(|key| self.hook_filter.accepts(key))
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let block = BlockId::Number(at_block.into());
+        let slots = self.fetch_slots(keys, block).await?;
+        self.fetch_pools(slots, block).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for UniswapV4PoolFetcher {
+    async fn run_maintenance(&self) -> Result<()> {
+        self.registry.run_maintenance().await
+    }
+}
+
+/// The current price and liquidity of a pool.
+struct FetchedSlot {
+    key: PoolKey,
+    sqrt_price: U256,
+    tick: i32,
+    lp_fee: u32,
+    liquidity: u128,
+}
+
+fn handle_results(
+    slot: FetchedSlot,
+    liquidity_net: Vec<(i32, Result<(u128, i128), MethodError>)>,
+) -> Result<Option<Pool>> {
+    let known_ticks = known_ticks(
+        bitmap_words(slot.tick, slot.key.tick_spacing),
+        slot.key.tick_spacing,
+    );
+    let mut ticks = Vec::new();
+    for (tick, liquidity) in liquidity_net {
+        let (_, net) = match handle_contract_error(liquidity)? {
+            Some(liquidity) => liquidity,
+            None => return Ok(None),
+        };
+        if net != 0 {
+            ticks.push((BigInt::from(tick), BigInt::from(net)));
+        }
+    }
+
+    Ok(Some(Pool {
+        state: PoolState {
+            sqrt_price: slot.sqrt_price,
+            liquidity: slot.liquidity.into(),
+            tick: slot.tick.into(),
+            liquidity_net: ticks,
+            fee: Ratio::new(slot.lp_fee, 1_000_000u32),
+        },
+        key: slot.key,
+        known_ticks,
+    }))
+}
+
+/// Returns the tick bitmap word positions around the specified tick.
+fn bitmap_words(tick: i32, tick_spacing: i32) -> RangeInclusive<i16> {
+    let word = (tick.div_euclid(tick_spacing) >> 8) as i16;
+    word.saturating_sub(TICK_BITMAP_WORDS)..=word.saturating_add(TICK_BITMAP_WORDS)
+}
+
+/// Returns the initialized ticks of a tick bitmap word.
+fn initialized_ticks(word: i16, bitmap: U256, tick_spacing: i32) -> impl Iterator<Item = i32> {
+    (0..256)
+        .filter(move |bit| bitmap.bit(*bit))
+        .map(move |bit| ((word as i32) * 256 + bit as i32) * tick_spacing)
+}
+
+/// Returns the range of ticks covered by the specified bitmap words.
+fn known_ticks(words: RangeInclusive<i16>, tick_spacing: i32) -> RangeInclusive<i32> {
+    let word_ticks = 256 * tick_spacing as i64;
+    let start = *words.start() as i64 * word_ticks;
+    let end = (*words.end() as i64 + 1) * word_ticks - 1;
+    let clamp =
+        |tick: i64| tick.clamp(swap_math::MIN_TICK as i64, swap_math::MAX_TICK as i64) as i32;
+    clamp(start)..=clamp(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+    use ethcontract::H256;
+
+    fn slot() -> FetchedSlot {
+        FetchedSlot {
+            key: PoolKey {
+                id: H256([1; 32]),
+                currency0: H160::zero(),
+                currency1: H160([2; 20]),
+                fee: 500,
+                tick_spacing: 10,
+                hooks: H160::zero(),
+            },
+            sqrt_price: U256::one() << 96,
+            tick: 0,
+            lp_fee: 500,
+            liquidity: 1_000,
+        }
+    }
+
+    #[test]
+    fn computes_bitmap_words() {
+        assert_eq!(bitmap_words(0, 1), -2..=2);
+        assert_eq!(bitmap_words(-1, 1), -3..=1);
+        assert_eq!(bitmap_words(-600, 60), -3..=1);
+        assert_eq!(bitmap_words(15360, 60), -1..=3);
+        assert_eq!(bitmap_words(887272, 1), 3463..=3467);
+    }
+
+    #[test]
+    fn computes_initialized_ticks() {
+        let bitmap = U256::one() | (U256::one() << 255);
+        assert_eq!(
+            initialized_ticks(0, bitmap, 60).collect::<Vec<_>>(),
+            vec![0, 15300],
+        );
+        assert_eq!(
+            initialized_ticks(-1, bitmap, 60).collect::<Vec<_>>(),
+            vec![-15360, -60],
+        );
+        assert_eq!(initialized_ticks(3, U256::zero(), 60).count(), 0);
+    }
+
+    #[test]
+    fn computes_known_ticks() {
+        assert_eq!(known_ticks(-2..=2, 1), -512..=767);
+        assert_eq!(known_ticks(-2..=2, 60), -30_720..=46_079);
+        assert_eq!(
+            known_ticks(-3_467..=-3_463, 1),
+            swap_math::MIN_TICK..=-886_273
+        );
+        assert_eq!(known_ticks(3_463..=3_467, 1), 886_528..=swap_math::MAX_TICK);
+    }
+
+    #[test]
+    fn converts_pool_state() {
+        let pool = handle_results(
+            slot(),
+            vec![(-10, Ok((5, 5))), (0, Ok((0, 0))), (10, Ok((5, -5)))],
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(pool.key, slot().key);
+        assert_eq!(pool.known_ticks, -5_120..=7_679);
+        assert_eq!(
+            pool.state,
+            PoolState {
+                sqrt_price: U256::one() << 96,
+                liquidity: 1_000.into(),
+                tick: 0.into(),
+                liquidity_net: vec![(BigInt::from(-10), 5.into()), (10.into(), (-5).into())],
+                fee: Ratio::new(5, 10_000),
+            }
+        );
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let results = vec![(0, Err(ethcontract_error::testing_node_error()))];
+        assert!(handle_results(slot(), results).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let results = vec![(0, Err(ethcontract_error::testing_contract_error()))];
+        assert!(handle_results(slot(), results).unwrap().is_none());
+    }
+
+    #[test]
+    fn quotes_swaps_in_both_directions() {
+        // A full range position at a price of 1.
+        let pool = Pool {
+            key: slot().key,
+            state: PoolState {
+                sqrt_price: U256::one() << 96,
+                liquidity: 10u128.pow(18).into(),
+                tick: 0.into(),
+                liquidity_net: vec![
+                    ((-887_220).into(), 10u128.pow(18).into()),
+                    (887_220.into(), (-10i128.pow(18)).into()),
+                ],
+                fee: Ratio::new(3_000, 1_000_000),
+            },
+            known_ticks: known_ticks(bitmap_words(0, 10), 10),
+        };
+        let (currency0, currency1) = (pool.key.currency0, pool.key.currency1);
+        let amount = U256::exp10(15);
+
+        assert_eq!(
+            pool.get_amount_out(currency1, (amount, currency0)),
+            Some(996_006_981_039_903u128.into())
+        );
+        assert_eq!(
+            pool.get_amount_in(currency1, (amount, currency0)),
+            Some(1_004_013_040_121_367u128.into())
+        );
+        assert_eq!(
+            pool.get_amount_out(currency1, (amount, H160([3; 20]))),
+            None
+        );
+        assert_eq!(pool.get_amount_in(currency0, (amount, currency0)), None);
+    }
+
+    #[test]
+    fn does_not_quote_swaps_beyond_known_ticks() {
+        // Liquidity in a narrow range around the price of 1, of which only the
+        // ticks close to the current tick are known.
+        let liquidity = 10i128.pow(18);
+        let pool = Pool {
+            key: slot().key,
+            state: PoolState {
+                sqrt_price: U256::one() << 96,
+                liquidity: liquidity.into(),
+                tick: 0.into(),
+                liquidity_net: vec![
+                    (BigInt::from(-100), liquidity.into()),
+                    (100.into(), (-liquidity).into()),
+                ],
+                fee: Ratio::new(3_000, 1_000_000),
+            },
+            known_ticks: -50..=49,
+        };
+        let (currency0, currency1) = (pool.key.currency0, pool.key.currency1);
+
+        for (in_token, out_token) in [(currency0, currency1), (currency1, currency0)] {
+            let max = pool.max_amount_in(in_token, out_token).unwrap();
+            assert!(max > U256::zero());
+            assert!(max < swap_math::max_amount_in(&pool.state, in_token == currency0).unwrap());
+
+            let out_amount = pool.get_amount_out(out_token, (max, in_token)).unwrap();
+            assert_eq!(pool.get_amount_out(out_token, (max + 1, in_token)), None);
+            assert_eq!(
+                pool.get_amounts_out(out_token, (&[max, max + 1], in_token)),
+                vec![Some(out_amount), None]
+            );
+            assert!(pool
+                .get_amount_in(in_token, (out_amount, out_token))
+                .is_some());
+            assert_eq!(
+                pool.get_amount_in(in_token, (out_amount * 3 / 2, out_token)),
+                None
+            );
+        }
+    }
+}
@@ -0,0 +1,406 @@
+//! Module for indexing Uniswap V4 pools from the `PoolManager` singleton.
+//!
+//! Unlike previous Uniswap versions, V4 pools are not separate contracts but
+//! entries in the pool manager identified by the hash of their pool key. Pools
+//! are indexed from the `Initialize` events emitted by the pool manager.
+
+use crate::{
+    current_block::BlockFinality,
+    event_handling::{BlockNumber, EventHandler, EventRetrieving, EventStoring},
+    maintenance::Maintaining,
+    token_pair::TokenPair,
+    Web3,
+};
+use anyhow::{anyhow, Result};
+use contracts::{
+    uniswap_v4_pool_manager::{event_data::Initialize, Event as PoolManagerEvent},
+    UniswapV4PoolManager,
+};
+use ethcontract::{
+    common::{abi::Topic, DeploymentInformation},
+    contract::AllEventsBuilder,
+    dyns::DynTransport,
+    Event, H160, H256,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+};
+use tokio::sync::Mutex;
+
+/// The fee value marking a pool as having a dynamic fee that is set by its
+/// hooks contract.
+pub const DYNAMIC_FEE_FLAG: u32 = 0x800000;
+
+lazy_static::lazy_static! {
+    static ref INITIALIZE_TOPIC: H256 = UniswapV4PoolManager::raw_contract()
+        .abi
+        .event("Initialize")
+        .expect("missing Initialize event")
+        .signature();
+}
+
+/// The static parameters identifying a Uniswap V4 pool.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PoolKey {
+    /// The pool ID, which is the hash of the pool key.
+    pub id: H256,
+    /// The lower currency of the pool, where the zero address is native ETH.
+    pub currency0: H160,
+    pub currency1: H160,
+    /// The LP fee in hundredths of a basis point, or `DYNAMIC_FEE_FLAG`.
+    pub fee: u32,
+    pub tick_spacing: i32,
+    /// The hooks contract of the pool, or the zero address for pools without
+    /// hooks.
+    pub hooks: H160,
+}
+
+impl PoolKey {
+    /// Returns the token pair traded by the pool.
+    pub fn token_pair(&self) -> Option<TokenPair> {
+        TokenPair::new(self.currency0, self.currency1)
+    }
+
+    /// Returns `true` if the pool has a hooks contract.
+    pub fn has_hooks(&self) -> bool {
+        !self.hooks.is_zero()
+    }
+
+    /// Returns `true` if the pool's LP fee is set dynamically by its hooks.
+    pub fn has_dynamic_fee(&self) -> bool {
+        self.fee == DYNAMIC_FEE_FLAG
+    }
+
+    /// Returns the permissions of the pool's hooks contract.
+    pub fn hook_permissions(&self) -> HookPermissions {
+        HookPermissions::from_address(self.hooks)
+    }
+}
+
+impl From<Initialize> for PoolKey {
+    fn from(initialize: Initialize) -> Self {
+        Self {
+            id: H256(initialize.id.0),
+            currency0: initialize.currency_0,
+            currency1: initialize.currency_1,
+            fee: initialize.fee,
+            tick_spacing: initialize.tick_spacing,
+            hooks: initialize.hooks,
+        }
+    }
+}
+
+/// Hook permissions, which Uniswap V4 encodes in the lowest 14 bits of the
+/// hooks contract address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HookPermissions(u16);
+
+impl HookPermissions {
+    const BEFORE_SWAP: u16 = 1 << 7;
+    const AFTER_SWAP: u16 = 1 << 6;
+    const BEFORE_SWAP_RETURNS_DELTA: u16 = 1 << 3;
+    const AFTER_SWAP_RETURNS_DELTA: u16 = 1 << 2;
+
+    fn from_address(hooks: H160) -> Self {
+        Self(u16::from_be_bytes([hooks.0[18], hooks.0[19]]) & 0x3fff)
+    }
+
+    /// Returns `true` if the hooks contract is called on swaps, in which case
+    /// swap outputs can not be computed with the regular concentrated
+    /// liquidity math.
+    pub fn affects_swaps(&self) -> bool {
+        self.0
+            & (Self::BEFORE_SWAP
+                | Self::AFTER_SWAP
+                | Self::BEFORE_SWAP_RETURNS_DELTA
+                | Self::AFTER_SWAP_RETURNS_DELTA)
+            != 0
+    }
+}
+
+/// Filter for the pools with hooks that should be used for liquidity.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HookFilter {
+    /// Only use pools without hooks.
+    NoHooks,
+    /// Additionally use pools whose hooks are not called on swaps.
+    NoSwapHooks,
+    /// Additionally use pools with the specified hooks contracts.
+    Allowed(HashSet<H160>),
+}
+
+impl Default for HookFilter {
+    fn default() -> Self {
+        Self::NoHooks
+    }
+}
+
+impl HookFilter {
+    /// Returns `true` if the pool with the specified key can be used.
+    pub fn accepts(&self, key: &PoolKey) -> bool {
+        if !key.has_hooks() {
+            return true;
+        }
+        match self {
+            Self::NoHooks => false,
+            Self::NoSwapHooks => !key.hook_permissions().affects_swaps(),
+            Self::Allowed(hooks) => hooks.contains(&key.hooks),
+        }
+    }
+}
+
+pub struct PoolManagerContract(UniswapV4PoolManager);
+
+impl EventRetrieving for PoolManagerContract {
+    type Event = PoolManagerEvent;
+
+    fn get_events(&self) -> AllEventsBuilder<DynTransport, Self::Event> {
+        // The pool manager emits events for every swap and liquidity
+        // modification, so only query pool initializations.
+        self.0.all_events().topic0(Topic::This(*INITIALIZE_TOPIC))
+    }
+}
+
+/// Type alias for the internal event updater type.
+type PoolUpdater = Mutex<EventHandler<Web3, PoolManagerContract, PoolStorage>>;
+
+/// Registry of Uniswap V4 pools, kept up to date with pool manager events.
+pub struct PoolRegistry {
+    updater: PoolUpdater,
+}
+
+impl PoolRegistry {
+    /// Creates a new registry for the deployed pool manager, indexing its
+    /// events with the specified block finality.
+    ///
+    /// Note that pools are only indexed during maintenance, starting from the
+    /// pool manager deployment block.
+    pub async fn new(web3: &Web3, finality: BlockFinality) -> Result<Self> {
+        let pool_manager = UniswapV4PoolManager::deployed(web3).await?;
+        let start_sync_at_block = match pool_manager.raw_instance().deployment_information() {
+            Some(DeploymentInformation::BlockNumber(block)) => Some(block),
+            _ => None,
+        };
+
+        Ok(Self {
+            updater: Mutex::new(
+                EventHandler::new(
+                    web3.clone(),
+                    PoolManagerContract(pool_manager),
+                    PoolStorage::default(),
+                    start_sync_at_block,
+                )
+                .with_block_finality(finality),
+            ),
+        })
+    }
+
+    /// Returns the keys of all pools trading any of the specified token pairs.
+    pub async fn pools_for_token_pairs(&self, token_pairs: &HashSet<TokenPair>) -> Vec<PoolKey> {
+        self.updater
+            .lock()
+            .await
+            .store()
+            .pools_for_token_pairs(token_pairs)
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for PoolRegistry {
+    async fn run_maintenance(&self) -> Result<()> {
+        self.updater.run_maintenance().await
+    }
+}
+
+/// In-memory storage of indexed Uniswap V4 pools.
+#[derive(Default)]
+pub struct PoolStorage {
+    pools: HashMap<H256, IndexedPool>,
+    by_pair: HashMap<TokenPair, HashSet<H256>>,
+}
+
+struct IndexedPool {
+    key: PoolKey,
+    block_created: u64,
+}
+
+impl PoolStorage {
+    /// Returns the keys of all pools trading any of the specified token pairs.
+    pub fn pools_for_token_pairs(&self, token_pairs: &HashSet<TokenPair>) -> Vec<PoolKey> {
+        token_pairs
+            .iter()
+            .filter_map(|pair| self.by_pair.get(pair))
+            .flatten()
+            .filter_map(|id| Some(self.pools.get(id)?.key.clone()))
+            .collect()
+    }
+
+    fn insert_pool(&mut self, key: PoolKey, block_created: u64) {
+        if let Some(pair) = key.token_pair() {
+            self.by_pair.entry(pair).or_default().insert(key.id);
+        }
+        self.pools
+            .insert(key.id, IndexedPool { key, block_created });
+    }
+
+    fn remove_pools_newer_than_block(&mut self, block: u64) {
+        let num_pools = self.pools.len();
+        self.pools.retain(|_, pool| pool.block_created < block);
+        if num_pools == self.pools.len() {
+            return;
+        }
+
+        let pools = &self.pools;
+        self.by_pair.retain(|_, ids| {
+            ids.retain(|id| pools.contains_key(id));
+            !ids.is_empty()
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl EventStoring<PoolManagerEvent> for PoolStorage {
+    async fn replace_events(
+        &mut self,
+        events: Vec<Event<PoolManagerEvent>>,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Result<()> {
+        tracing::debug!("replacing {} events for block {:?}", events.len(), range);
+
+        self.remove_pools_newer_than_block(range.start().to_u64());
+        self.append_events(events).await
+    }
+
+    async fn append_events(&mut self, events: Vec<Event<PoolManagerEvent>>) -> Result<()> {
+        tracing::debug!("inserting {} events", events.len());
+
+        for event in events {
+            let block_created = event
+                .meta
+                .ok_or_else(|| anyhow!("event missing metadata"))?
+                .block_number;
+            let PoolManagerEvent::Initialize(initialize) = event.data;
+
+            self.insert_pool(initialize.into(), block_created);
+        }
+
+        Ok(())
+    }
+
+    async fn last_event_block(&self) -> Result<u64> {
+        Ok(self
+            .pools
+            .values()
+            .map(|pool| pool.block_created)
+            .max()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashset;
+
+    fn key(id: u8, currency0: u8, currency1: u8, hooks: H160) -> PoolKey {
+        PoolKey {
+            id: H256([id; 32]),
+            currency0: H160([currency0; 20]),
+            currency1: H160([currency1; 20]),
+            fee: 3000,
+            tick_spacing: 60,
+            hooks,
+        }
+    }
+
+    #[test]
+    fn decodes_hook_permissions() {
+        let hooks = |flags: u16| {
+            let mut address = H160([0x42; 20]);
+            address.0[18..].copy_from_slice(&flags.to_be_bytes());
+            address
+        };
+
+        // `beforeInitialize` and `afterAddLiquidity` only.
+        assert!(!HookPermissions::from_address(hooks(1 << 13 | 1 << 10)).affects_swaps());
+        assert!(HookPermissions::from_address(hooks(1 << 7)).affects_swaps());
+        assert!(HookPermissions::from_address(hooks(1 << 6)).affects_swaps());
+        assert!(HookPermissions::from_address(hooks(1 << 3)).affects_swaps());
+        assert!(HookPermissions::from_address(hooks(1 << 2)).affects_swaps());
+        // Bits above the lowest 14 are not permissions.
+        assert!(!HookPermissions::from_address(hooks(1 << 14)).affects_swaps());
+    }
+
+    #[test]
+    fn filters_hooked_pools() {
+        let no_hooks = key(1, 1, 2, H160::zero());
+        // `afterInitialize` only.
+        let passive_hooks = key(2, 1, 2, addr!("0000000000000000000000000000000000001000"));
+        // `beforeSwap` only.
+        let swap_hooks = key(3, 1, 2, addr!("0000000000000000000000000000000000000080"));
+
+        let filter = HookFilter::NoHooks;
+        assert!(filter.accepts(&no_hooks));
+        assert!(!filter.accepts(&passive_hooks));
+        assert!(!filter.accepts(&swap_hooks));
+
+        let filter = HookFilter::NoSwapHooks;
+        assert!(filter.accepts(&no_hooks));
+        assert!(filter.accepts(&passive_hooks));
+        assert!(!filter.accepts(&swap_hooks));
+
+        let filter = HookFilter::Allowed(hashset! { swap_hooks.hooks });
+        assert!(filter.accepts(&no_hooks));
+        assert!(!filter.accepts(&passive_hooks));
+        assert!(filter.accepts(&swap_hooks));
+    }
+
+    #[test]
+    fn dynamic_fee_pools() {
+        let mut pool = key(1, 1, 2, H160::zero());
+        assert!(!pool.has_dynamic_fee());
+        pool.fee = DYNAMIC_FEE_FLAG;
+        assert!(pool.has_dynamic_fee());
+    }
+
+    #[test]
+    fn indexes_pools_by_token_pair() {
+        let mut storage = PoolStorage::default();
+        storage.insert_pool(key(1, 1, 2, H160::zero()), 10);
+        storage.insert_pool(key(2, 1, 2, H160([0xff; 20])), 11);
+        storage.insert_pool(key(3, 2, 3, H160::zero()), 12);
+
+        let pair = |a: u8, b: u8| TokenPair::new(H160([a; 20]), H160([b; 20])).unwrap();
+        let ids = |keys: Vec<PoolKey>| keys.into_iter().map(|key| key.id).collect::<HashSet<_>>();
+
+        assert_eq!(
+            ids(storage.pools_for_token_pairs(&hashset! { pair(1, 2) })),
+            hashset! { H256([1; 32]), H256([2; 32]) },
+        );
+        assert_eq!(
+            ids(storage.pools_for_token_pairs(&hashset! { pair(1, 2), pair(2, 3) })),
+            hashset! { H256([1; 32]), H256([2; 32]), H256([3; 32]) },
+        );
+        assert!(storage
+            .pools_for_token_pairs(&hashset! { pair(1, 3) })
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn removes_reorged_pools() {
+        let mut storage = PoolStorage::default();
+        storage.insert_pool(key(1, 1, 2, H160::zero()), 10);
+        storage.insert_pool(key(2, 1, 2, H160::zero()), 11);
+        storage.insert_pool(key(3, 2, 3, H160::zero()), 12);
+
+        storage.remove_pools_newer_than_block(11);
+
+        assert_eq!(storage.last_event_block().await.unwrap(), 10);
+        assert_eq!(storage.by_pair.len(), 1);
+        assert_eq!(
+            storage.pools_for_token_pairs(&storage.by_pair.keys().copied().collect()),
+            vec![key(1, 1, 2, H160::zero())],
+        );
+    }
+}
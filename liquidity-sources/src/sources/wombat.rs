@@ -0,0 +1,7 @@
+//! Wombat Exchange baseline liquidity source implementation.
+//!
+//! Wombat pools hold single sided liquidity for each of their assets, and
+//! price swaps by the coverage ratios (cash over liability) of the swapped
+//! assets instead of their balances. Pools are configured by address.
+pub mod pool_fetching;
+mod swap_math;
@@ -0,0 +1,614 @@
+//! Module for fetching the current state of Wombat pools.
+
+use super::swap_math::{self, WAD};
+use crate::{
+    baseline_solver::BaselineSolvable,
+    conversions::{big_rational_to_u256_rounded, Rounding, U256Ext as _},
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::{WombatAsset, WombatPool};
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use futures::future;
+use num::{BigInt, BigRational, Zero as _};
+use std::collections::HashSet;
+
+const WOMBAT_SWAP_GAS_COST: usize = 180_000;
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The current state of a Wombat pool.
+///
+/// Only pools where all assets have the same value are supported, as dynamic
+/// pools additionally scale cash and liabilities by the relative prices of
+/// their assets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub address: H160,
+    pub assets: Vec<AssetState>,
+    /// The amplification factor with 18 decimals.
+    pub amp_factor: U256,
+    /// The haircut (fee) rate with 18 decimals.
+    pub haircut_rate: U256,
+    /// The fee charged for swaps that push the coverage ratio of the sold
+    /// asset too high, or `None` for pools without such a fee.
+    pub high_cov_ratio_fee: Option<HighCovRatioFee>,
+}
+
+/// The state of a single pool asset. Cash and liability are normalized to 18
+/// decimals.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetState {
+    pub token: H160,
+    pub decimals: u8,
+    pub cash: U256,
+    pub liability: U256,
+}
+
+/// The coverage ratios with 18 decimals at which the high coverage ratio fee
+/// starts, and at which swaps revert.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HighCovRatioFee {
+    pub start_cov_ratio: U256,
+    pub end_cov_ratio: U256,
+}
+
+impl AssetState {
+    fn to_wad(&self, amount: U256) -> Option<BigInt> {
+        let amount = if self.decimals <= 18 {
+            amount.checked_mul(U256::exp10(18 - self.decimals as usize))?
+        } else {
+            amount / U256::exp10(self.decimals as usize - 18)
+        };
+        Some(amount.to_big_int())
+    }
+
+    fn from_wad(&self, amount: &BigInt, rounding: Rounding) -> Option<U256> {
+        if *amount < BigInt::zero() {
+            return None;
+        }
+        let scale = BigInt::from(10).pow(self.decimals.into());
+        let amount = BigRational::new(amount * scale, (*WAD).clone());
+        big_rational_to_u256_rounded(&amount, rounding).ok()
+    }
+
+    fn cov_ratio(&self, cash: &BigInt) -> Option<BigInt> {
+        swap_math::wdiv(cash, &self.liability.to_big_int())
+    }
+}
+
+impl Pool {
+    fn assets(&self, in_token: H160, out_token: H160) -> Option<(&AssetState, &AssetState)> {
+        let asset = |token: H160| self.assets.iter().find(|asset| asset.token == token);
+        if in_token == out_token {
+            return None;
+        }
+        Some((asset(in_token)?, asset(out_token)?))
+    }
+
+    /// Returns the high coverage ratio fee rate for selling the specified
+    /// amount of an asset.
+    fn high_cov_ratio_fee_rate(&self, asset: &AssetState, amount: &BigInt) -> Option<BigInt> {
+        let fee = match &self.high_cov_ratio_fee {
+            Some(fee) => fee,
+            None => return Some(0.into()),
+        };
+        let cash = asset.cash.to_big_int();
+        swap_math::high_cov_ratio_fee(
+            &asset.cov_ratio(&cash)?,
+            &asset.cov_ratio(&(cash + amount))?,
+            &fee.start_cov_ratio.to_big_int(),
+            &fee.end_cov_ratio.to_big_int(),
+        )
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        let (in_asset, out_asset) = self.assets(in_token, out_token)?;
+        if in_amount.is_zero() {
+            return None;
+        }
+
+        let in_amount = in_asset.to_wad(in_amount)?;
+        let ideal_out_amount = swap_math::swap_quote(
+            &in_asset.cash.to_big_int(),
+            &out_asset.cash.to_big_int(),
+            &in_asset.liability.to_big_int(),
+            &out_asset.liability.to_big_int(),
+            &in_amount,
+            &self.amp_factor.to_big_int(),
+        )?;
+        if out_asset.cash.to_big_int() < ideal_out_amount {
+            return None;
+        }
+
+        let haircut = swap_math::wmul(&ideal_out_amount, &self.haircut_rate.to_big_int());
+        let out_amount = ideal_out_amount - haircut;
+        let fee_rate = self.high_cov_ratio_fee_rate(in_asset, &in_amount)?;
+        let out_amount = &out_amount - swap_math::wmul(&fee_rate, &out_amount);
+        out_asset.from_wad(&out_amount, Rounding::Down)
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        let (in_asset, out_asset) = self.assets(in_token, out_token)?;
+        if out_amount.is_zero() {
+            return None;
+        }
+
+        // The haircut is charged on the output amount, so the output before
+        // the haircut is larger.
+        let out_amount = swap_math::wdiv(
+            &out_asset.to_wad(out_amount)?,
+            &(&*WAD - self.haircut_rate.to_big_int()),
+        )?;
+        if out_asset.cash.to_big_int() < out_amount {
+            return None;
+        }
+        let in_amount = swap_math::swap_quote(
+            &out_asset.cash.to_big_int(),
+            &in_asset.cash.to_big_int(),
+            &out_asset.liability.to_big_int(),
+            &in_asset.liability.to_big_int(),
+            &-out_amount,
+            &self.amp_factor.to_big_int(),
+        )?;
+        let in_amount = in_asset.from_wad(&in_amount, Rounding::Up)?;
+
+        // The high coverage ratio fee of a swap depends on its input amount,
+        // which makes computing exact input amounts for swaps charged with it
+        // impractical.
+        let fee_rate = self.high_cov_ratio_fee_rate(in_asset, &in_asset.to_wad(in_amount)?)?;
+        if !fee_rate.is_zero() {
+            return None;
+        }
+        Some(in_amount)
+    }
+
+    fn gas_cost(&self) -> usize {
+        WOMBAT_SWAP_GAS_COST
+    }
+}
+
+/// Pool fetcher for a set of Wombat pools.
+pub struct WombatPoolFetcher {
+    web3: Web3,
+    pools: Vec<PoolInfo>,
+}
+
+/// The static information of a pool.
+#[derive(Clone, Debug)]
+struct PoolInfo {
+    address: H160,
+    assets: Vec<AssetInfo>,
+}
+
+#[derive(Clone, Debug)]
+struct AssetInfo {
+    token: H160,
+    address: H160,
+    decimals: u8,
+}
+
+impl PoolInfo {
+    fn contains_pair(&self, pair: TokenPair) -> bool {
+        pair.into_iter()
+            .all(|token| self.assets.iter().any(|asset| asset.token == token))
+    }
+}
+
+impl WombatPoolFetcher {
+    /// Creates a new pool fetcher for the pools at the specified addresses.
+    pub async fn new(web3: &Web3, pools: Vec<H160>) -> Result<Self> {
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let tokens = pools
+            .iter()
+            .map(|pool| {
+                WombatPool::at(web3, *pool)
+                    .get_tokens()
+                    .batch_call(&mut batch)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let tokens = future::try_join_all(tokens).await?;
+
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let assets = pools
+            .iter()
+            .zip(&tokens)
+            .map(|(pool, tokens)| {
+                let pool = WombatPool::at(web3, *pool);
+                let assets = tokens
+                    .iter()
+                    .map(|token| pool.address_of_asset(*token).batch_call(&mut batch))
+                    .collect::<Vec<_>>();
+                future::try_join_all(assets)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let assets = future::try_join_all(assets).await?;
+
+        let mut batch = Web3CallBatch::new(web3.transport().clone());
+        let decimals = assets
+            .iter()
+            .map(|assets| {
+                let decimals = assets
+                    .iter()
+                    .map(|asset| {
+                        WombatAsset::at(web3, *asset)
+                            .underlying_token_decimals()
+                            .batch_call(&mut batch)
+                    })
+                    .collect::<Vec<_>>();
+                future::try_join_all(decimals)
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+        let decimals = future::try_join_all(decimals).await?;
+
+        let pools = pools
+            .into_iter()
+            .zip(tokens)
+            .zip(assets.into_iter().zip(decimals))
+            .map(|((address, tokens), (assets, decimals))| PoolInfo {
+                address,
+                assets: tokens
+                    .into_iter()
+                    .zip(assets)
+                    .zip(decimals)
+                    .map(|((token, address), decimals)| AssetInfo {
+                        token,
+                        address,
+                        decimals,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(Self {
+            web3: web3.clone(),
+            pools,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for WombatPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let futures = self
+            .pools
+            .iter()
+            .filter(|info| token_pairs.iter().any(|pair| info.contains_pair(*pair)))
+            .map(|info| {
+                let pool = WombatPool::at(&self.web3, info.address);
+                let paused = pool.paused().block(block).batch_call(&mut batch);
+                let amp_factor = pool.amp_factor().block(block).batch_call(&mut batch);
+                let haircut_rate = pool.haircut_rate().block(block).batch_call(&mut batch);
+                let start_cov_ratio = pool.start_cov_ratio().block(block).batch_call(&mut batch);
+                let end_cov_ratio = pool.end_cov_ratio().block(block).batch_call(&mut batch);
+                let assets = info
+                    .assets
+                    .iter()
+                    .map(|asset| {
+                        let contract = WombatAsset::at(&self.web3, asset.address);
+                        let cash = contract.cash().block(block).batch_call(&mut batch);
+                        let liability = contract.liability().block(block).batch_call(&mut batch);
+                        (cash, liability)
+                    })
+                    .collect::<Vec<_>>();
+                let info = info.clone();
+                async move {
+                    let mut fetched_assets = Vec::new();
+                    for (cash, liability) in assets {
+                        fetched_assets.push((cash.await, liability.await));
+                    }
+                    handle_results(FetchedPool {
+                        info,
+                        paused: paused.await,
+                        amp_factor: amp_factor.await,
+                        haircut_rate: haircut_rate.await,
+                        start_cov_ratio: start_cov_ratio.await,
+                        end_cov_ratio: end_cov_ratio.await,
+                        assets: fetched_assets,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        future::join_all(futures)
+            .await
+            .into_iter()
+            .filter_map(|pool| pool.transpose())
+            .collect()
+    }
+}
+
+struct FetchedPool {
+    info: PoolInfo,
+    paused: Result<bool, MethodError>,
+    amp_factor: Result<U256, MethodError>,
+    haircut_rate: Result<U256, MethodError>,
+    start_cov_ratio: Result<u128, MethodError>,
+    end_cov_ratio: Result<u128, MethodError>,
+    /// The cash and liability of each asset.
+    assets: Vec<(Result<u128, MethodError>, Result<u128, MethodError>)>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let paused = handle_contract_error(fetched_pool.paused)?;
+    let amp_factor = handle_contract_error(fetched_pool.amp_factor)?;
+    let haircut_rate = handle_contract_error(fetched_pool.haircut_rate)?;
+    // Pools predating the high coverage ratio fee do not implement it.
+    let start_cov_ratio = handle_contract_error(fetched_pool.start_cov_ratio)?;
+    let end_cov_ratio = handle_contract_error(fetched_pool.end_cov_ratio)?;
+    let mut balances = Vec::new();
+    for (cash, liability) in fetched_pool.assets {
+        balances.push((
+            handle_contract_error(cash)?,
+            handle_contract_error(liability)?,
+        ));
+    }
+
+    let (amp_factor, haircut_rate) = match (paused, amp_factor, haircut_rate) {
+        (Some(false), Some(amp_factor), Some(haircut_rate)) if haircut_rate < U256::exp10(18) => {
+            (amp_factor, haircut_rate)
+        }
+        _ => return Ok(None),
+    };
+    let high_cov_ratio_fee = match (start_cov_ratio, end_cov_ratio) {
+        (Some(start), Some(end)) if start < end => Some(HighCovRatioFee {
+            start_cov_ratio: start.into(),
+            end_cov_ratio: end.into(),
+        }),
+        _ => None,
+    };
+
+    let mut assets = Vec::new();
+    for (info, balance) in fetched_pool.info.assets.into_iter().zip(balances) {
+        match balance {
+            (Some(cash), Some(liability)) => assets.push(AssetState {
+                token: info.token,
+                decimals: info.decimals,
+                cash: cash.into(),
+                liability: liability.into(),
+            }),
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(Some(Pool {
+        address: fetched_pool.info.address,
+        assets,
+        amp_factor,
+        haircut_rate,
+        high_cov_ratio_fee,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn e18(amount: u64) -> U256 {
+        U256::from(amount) * U256::exp10(18)
+    }
+
+    fn asset(byte: u8, decimals: u8, cash: u64, liability: u64) -> AssetState {
+        AssetState {
+            token: H160([byte; 20]),
+            decimals,
+            cash: e18(cash),
+            liability: e18(liability),
+        }
+    }
+
+    /// A pool with USDC (excess cash), USDT (short on cash) and DAI (with a
+    /// coverage ratio close to the high coverage ratio fee).
+    fn pool() -> Pool {
+        Pool {
+            address: H160([0xff; 20]),
+            assets: vec![
+                asset(1, 6, 1_200_000, 1_000_000),
+                asset(2, 6, 900_000, 1_000_000),
+                asset(3, 18, 1_400_000, 1_000_000),
+            ],
+            amp_factor: U256::exp10(14) * 25,
+            haircut_rate: U256::exp10(14),
+            high_cov_ratio_fee: Some(HighCovRatioFee {
+                start_cov_ratio: U256::exp10(17) * 15,
+                end_cov_ratio: U256::exp10(17) * 18,
+            }),
+        }
+    }
+
+    fn fetched_pool() -> FetchedPool {
+        FetchedPool {
+            info: PoolInfo {
+                address: H160([0xff; 20]),
+                assets: vec![
+                    AssetInfo {
+                        token: H160([1; 20]),
+                        address: H160([0x11; 20]),
+                        decimals: 6,
+                    },
+                    AssetInfo {
+                        token: H160([2; 20]),
+                        address: H160([0x22; 20]),
+                        decimals: 6,
+                    },
+                    AssetInfo {
+                        token: H160([3; 20]),
+                        address: H160([0x33; 20]),
+                        decimals: 18,
+                    },
+                ],
+            },
+            paused: Ok(false),
+            amp_factor: Ok(U256::exp10(14) * 25),
+            haircut_rate: Ok(U256::exp10(14)),
+            start_cov_ratio: Ok(15 * 10_u128.pow(17)),
+            end_cov_ratio: Ok(18 * 10_u128.pow(17)),
+            assets: vec![
+                (
+                    Ok(1_200_000 * 10_u128.pow(18)),
+                    Ok(1_000_000 * 10_u128.pow(18)),
+                ),
+                (
+                    Ok(900_000 * 10_u128.pow(18)),
+                    Ok(1_000_000 * 10_u128.pow(18)),
+                ),
+                (
+                    Ok(1_400_000 * 10_u128.pow(18)),
+                    Ok(1_000_000 * 10_u128.pow(18)),
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn computes_amounts_out() {
+        // Expected amounts computed with a reference implementation of the
+        // Wombat `PoolV2.quotePotentialSwap` and `HighCovRatioFeePoolV2`
+        // contracts.
+        let pool = pool();
+        let (usdc, usdt, dai) = (H160([1; 20]), H160([2; 20]), H160([3; 20]));
+
+        assert_eq!(
+            pool.get_amount_out(usdt, (1_000_000_000.into(), usdc)),
+            Some(998_549_126.into()),
+        );
+        assert_eq!(
+            pool.get_amount_out(usdc, (1_000_000_000.into(), usdt)),
+            Some(1_001_242_965.into()),
+        );
+        assert_eq!(
+            pool.get_amount_out(usdt, (100_000_000_000_u64.into(), usdc)),
+            Some(99_803_780_136_u64.into()),
+        );
+        assert_eq!(
+            pool.get_amount_out(dai, (1_000_000_000.into(), usdt)),
+            Some(U256::from_dec_str("1001704087187264597400").unwrap()),
+        );
+    }
+
+    #[test]
+    fn charges_high_cov_ratio_fee() {
+        let pool = pool();
+        let (usdc, dai) = (H160([1; 20]), H160([3; 20]));
+
+        assert_eq!(
+            pool.get_amount_out(usdc, (e18(200_000), dai)),
+            Some(183_138_128_477_u64.into()),
+        );
+        assert_eq!(
+            Pool {
+                high_cov_ratio_fee: None,
+                ..pool.clone()
+            }
+            .get_amount_out(usdc, (e18(200_000), dai)),
+            Some(199_787_049_248_u64.into()),
+        );
+
+        // Exceeding the end coverage ratio reverts.
+        assert_eq!(pool.get_amount_out(usdc, (e18(500_000), dai)), None);
+        // Computing input amounts charged with the fee is not supported.
+        assert_eq!(
+            pool.get_amount_in(dai, (183_138_128_477_u64.into(), usdc)),
+            None
+        );
+    }
+
+    #[test]
+    fn computes_amounts_in() {
+        let pool = pool();
+        let (usdc, usdt) = (H160([1; 20]), H160([2; 20]));
+
+        for (out_amount, in_amount) in [
+            (1_000_000_000_u64, 1_001_452_989_u64),
+            (100_000_000_000, 100_196_714_925),
+        ] {
+            assert_eq!(
+                pool.get_amount_in(usdc, (out_amount.into(), usdt)),
+                Some(in_amount.into()),
+            );
+            assert_eq!(
+                pool.get_amount_out(usdt, (in_amount.into(), usdc)),
+                Some(out_amount.into()),
+            );
+            assert!(
+                pool.get_amount_out(usdt, ((in_amount - 1).into(), usdc))
+                    .unwrap()
+                    < out_amount.into()
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_swaps() {
+        let pool = pool();
+        let (usdc, usdt) = (H160([1; 20]), H160([2; 20]));
+
+        // Not enough cash.
+        assert_eq!(
+            pool.get_amount_in(usdc, (1_000_000_000_000_u64.into(), usdt)),
+            None
+        );
+        assert_eq!(pool.get_amount_out(usdt, (0.into(), usdc)), None);
+        assert_eq!(pool.get_amount_out(usdc, (1_000.into(), usdc)), None);
+        assert_eq!(
+            pool.get_amount_out(H160([4; 20]), (1_000.into(), usdc)),
+            None
+        );
+    }
+
+    #[test]
+    fn converts_pool_state() {
+        assert_eq!(handle_results(fetched_pool()).unwrap(), Some(pool()));
+    }
+
+    #[test]
+    fn pools_without_high_cov_ratio_fee() {
+        let pool = FetchedPool {
+            start_cov_ratio: Err(ethcontract_error::testing_contract_error()),
+            end_cov_ratio: Err(ethcontract_error::testing_contract_error()),
+            ..fetched_pool()
+        };
+        assert_eq!(
+            handle_results(pool).unwrap().unwrap().high_cov_ratio_fee,
+            None
+        );
+    }
+
+    #[test]
+    fn skips_paused_pools() {
+        let pool = FetchedPool {
+            paused: Ok(true),
+            ..fetched_pool()
+        };
+        assert_eq!(handle_results(pool).unwrap(), None);
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let mut pool = fetched_pool();
+        pool.assets[1].0 = Err(ethcontract_error::testing_node_error());
+        assert!(handle_results(pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let mut pool = fetched_pool();
+        pool.assets[1].1 = Err(ethcontract_error::testing_contract_error());
+        assert_eq!(handle_results(pool).unwrap(), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! Module implementing the Wombat swap math.
+//!
+//! Wombat pools price swaps with an invariant over the coverage ratios (cash
+//! over liability) of their assets. This is a port of the `CoreV2` library and
+//! the high coverage ratio fee of `HighCovRatioFeePoolV2`. All amounts are
+//! 18 decimal fixed point numbers, and signed arithmetic truncates towards
+//! zero like in Solidity.
+
+use num::{BigInt, Signed as _, Zero as _};
+
+/// The maximum number of iterations for computing square roots. The contract
+/// loops until convergence, so a swap that does not converge would run out of
+/// gas.
+const MAX_SQRT_ITERATIONS: usize = 255;
+
+lazy_static::lazy_static! {
+    pub static ref WAD: BigInt = BigInt::from(10).pow(18);
+}
+
+/// Multiplies two fixed point numbers, rounding half away from zero for
+/// positive products.
+pub fn wmul(x: &BigInt, y: &BigInt) -> BigInt {
+    (x * y + &*WAD / 2) / &*WAD
+}
+
+/// Divides two fixed point numbers.
+pub fn wdiv(x: &BigInt, y: &BigInt) -> Option<BigInt> {
+    if y.is_zero() {
+        return None;
+    }
+    Some((x * &*WAD + y / 2) / y)
+}
+
+/// Computes the integer square root with the Babylonian method, starting
+/// from the specified guess.
+fn sqrt(y: &BigInt, guess: &BigInt) -> Option<BigInt> {
+    if y <= &BigInt::from(3) {
+        return Some(if y.is_zero() {
+            BigInt::zero()
+        } else {
+            BigInt::from(1)
+        });
+    }
+
+    let mut z = if guess.is_positive() && guess <= y {
+        guess.clone()
+    } else if guess.is_negative() && &-guess <= y {
+        -guess
+    } else {
+        y.clone()
+    };
+    let mut x = (y / &z + &z) / 2;
+    for _ in 0..MAX_SQRT_ITERATIONS {
+        if x == z {
+            return Some(z);
+        }
+        z = x;
+        x = (y / &z + &z) / 2;
+    }
+    None
+}
+
+/// Solves the quadratic equation `x^2 + b*x - c = 0` for its positive root.
+fn solve_quad(b: &BigInt, c: &BigInt) -> Option<BigInt> {
+    let discriminant = b * b + c * 4 * &*WAD;
+    Some((sqrt(&discriminant, b)? - b) / 2)
+}
+
+/// Returns the change of cash of asset Y when changing the cash of asset X by
+/// `dx`, for assets with cash `ax` and `ay` and liabilities `lx` and `ly`.
+pub fn swap_quote(
+    ax: &BigInt,
+    ay: &BigInt,
+    lx: &BigInt,
+    ly: &BigInt,
+    dx: &BigInt,
+    amp: &BigInt,
+) -> Option<BigInt> {
+    if ax.is_zero() || ay.is_zero() || lx.is_zero() || ly.is_zero() {
+        return None;
+    }
+
+    let d = ax + ay - wmul(amp, &(lx * lx / ax + ly * ly / ay));
+    let rx = wdiv(&(ax + dx), lx)?;
+    let b = lx * (&rx - wdiv(amp, &rx)?) / ly - wdiv(&d, ly)?;
+    let ry = solve_quad(&b, amp)?;
+    let dy = wmul(ly, &ry) - ay;
+    Some(dy.abs())
+}
+
+/// Returns the fee rate charged for swaps that increase the coverage ratio of
+/// the sold asset from `initial` to `last` above `start`, or `None` if the
+/// coverage ratio exceeds `end`.
+///
+/// The fee increases linearly from zero at the start coverage ratio to 100% at
+/// the end coverage ratio, and the rate is its average over the coverage
+/// ratios of the swap.
+pub fn high_cov_ratio_fee(
+    initial: &BigInt,
+    last: &BigInt,
+    start: &BigInt,
+    end: &BigInt,
+) -> Option<BigInt> {
+    if last > end {
+        return None;
+    }
+    if last <= start || last <= initial {
+        return Some(BigInt::zero());
+    }
+
+    let a = if initial <= start {
+        BigInt::zero()
+    } else {
+        (initial - start) * (initial - start)
+    };
+    let b = (last - start) * (last - start);
+    wdiv(&((b - a) / (last - initial) / 2), &(end - start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wad(amount: i64) -> BigInt {
+        BigInt::from(amount) * &*WAD
+    }
+
+    #[test]
+    fn fixed_point_arithmetic() {
+        assert_eq!(wmul(&wad(3), &(&*WAD / 2)), BigInt::from(15) * &*WAD / 10);
+        assert_eq!(wmul(&BigInt::from(1), &(&*WAD / 2)), BigInt::from(1));
+        assert_eq!(wdiv(&wad(3), &wad(2)), Some(BigInt::from(15) * &*WAD / 10));
+        assert_eq!(wdiv(&wad(1), &BigInt::zero()), None);
+    }
+
+    #[test]
+    fn square_roots() {
+        for (y, guess, root) in [(0, 0, 0), (3, 0, 1), (16, 0, 4), (17, 3, 4), (98, -5, 9)] {
+            assert_eq!(
+                sqrt(&BigInt::from(y), &BigInt::from(guess)),
+                Some(BigInt::from(root))
+            );
+        }
+        // The Babylonian method oscillates for `n^2 - 1`.
+        assert_eq!(sqrt(&BigInt::from(99), &BigInt::from(-5)), None);
+    }
+
+    #[test]
+    fn balanced_swap_quote() {
+        // Swapping between balanced assets costs slippage only.
+        let dy = swap_quote(
+            &wad(1000),
+            &wad(1000),
+            &wad(1000),
+            &wad(1000),
+            &wad(1),
+            &BigInt::from(25 * 10_i64.pow(14)),
+        )
+        .unwrap();
+        assert!(dy < wad(1) && dy > BigInt::from(999) * &*WAD / 1000);
+    }
+
+    #[test]
+    fn high_cov_ratio_fees() {
+        let (start, end) = (BigInt::from(15) * &*WAD / 10, BigInt::from(18) * &*WAD / 10);
+
+        // Below the start coverage ratio.
+        assert_eq!(
+            high_cov_ratio_fee(&wad(1), &(BigInt::from(14) * &*WAD / 10), &start, &end),
+            Some(BigInt::zero()),
+        );
+        // From the start to the end coverage ratio, the average fee is 50%.
+        assert_eq!(
+            high_cov_ratio_fee(&start, &end, &start, &end),
+            Some(&*WAD / 2)
+        );
+        // Exceeding the end coverage ratio.
+        assert_eq!(high_cov_ratio_fee(&wad(1), &wad(2), &start, &end), None);
+    }
+}
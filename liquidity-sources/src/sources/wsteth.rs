@@ -0,0 +1,245 @@
+//! wstETH wrapper baseline liquidity source implementation.
+//!
+//! The wstETH contract wraps rebasing stETH into a non-rebasing token. This
+//! source exposes wrapping and unwrapping as liquidity between stETH and
+//! wstETH at the current `stEthPerToken` and `tokensPerStEth` rates.
+
+use crate::{
+    baseline_solver::BaselineSolvable,
+    fixed_point::{div, Rounding},
+    recent_block_cache::Block,
+    sources::{uniswap_v2::pool_fetching::handle_contract_error, MAX_BATCH_SIZE},
+    token_pair::TokenPair,
+    Web3, Web3CallBatch,
+};
+use anyhow::Result;
+use contracts::WstETH;
+use ethcontract::{errors::MethodError, BlockId, H160, U256};
+use std::collections::HashSet;
+
+const WRAPPER_GAS_COST: usize = 100_000;
+
+lazy_static::lazy_static! {
+    static ref ONE: U256 = U256::exp10(18);
+}
+
+#[async_trait::async_trait]
+pub trait PoolFetching: Send + Sync {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>>;
+}
+
+/// The current wstETH wrapping rates.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pool {
+    pub wsteth: H160,
+    pub steth: H160,
+    /// The amount of stETH for one wstETH, with 18 decimals.
+    pub steth_per_token: U256,
+    /// The amount of wstETH for one stETH, with 18 decimals.
+    pub tokens_per_steth: U256,
+}
+
+impl Pool {
+    /// Returns the amount of wstETH received for wrapping stETH.
+    fn wrap(&self, steth_amount: U256) -> Option<U256> {
+        Some(steth_amount.checked_mul(self.tokens_per_steth)? / *ONE)
+    }
+
+    /// Returns the amount of stETH received for unwrapping wstETH.
+    ///
+    /// stETH balances are computed from shares and transfers can be off by
+    /// one wei, so the amount is reduced by one wei to be safe.
+    fn unwrap(&self, wsteth_amount: U256) -> Option<U256> {
+        (wsteth_amount.checked_mul(self.steth_per_token)? / *ONE).checked_sub(U256::one())
+    }
+}
+
+impl BaselineSolvable for Pool {
+    fn get_amount_out(&self, out_token: H160, (in_amount, in_token): (U256, H160)) -> Option<U256> {
+        if in_token == self.steth && out_token == self.wsteth {
+            self.wrap(in_amount)
+        } else if in_token == self.wsteth && out_token == self.steth {
+            self.unwrap(in_amount)
+        } else {
+            None
+        }
+    }
+
+    fn get_amount_in(&self, in_token: H160, (out_amount, out_token): (U256, H160)) -> Option<U256> {
+        if in_token == self.steth && out_token == self.wsteth {
+            div(
+                out_amount.checked_mul(*ONE)?,
+                self.tokens_per_steth,
+                Rounding::Up,
+            )
+        } else if in_token == self.wsteth && out_token == self.steth {
+            div(
+                out_amount.checked_add(U256::one())?.checked_mul(*ONE)?,
+                self.steth_per_token,
+                Rounding::Up,
+            )
+        } else {
+            None
+        }
+    }
+
+    fn gas_cost(&self) -> usize {
+        WRAPPER_GAS_COST
+    }
+}
+
+/// Pool fetcher for the wstETH wrapper.
+pub struct WstEthPoolFetcher {
+    wsteth: WstETH,
+    steth: H160,
+    web3: Web3,
+}
+
+impl WstEthPoolFetcher {
+    pub async fn new(web3: &Web3) -> Result<Self> {
+        let wsteth = WstETH::deployed(web3).await?;
+        let steth = wsteth.st_eth().call().await?;
+        Ok(Self {
+            wsteth,
+            steth,
+            web3: web3.clone(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl PoolFetching for WstEthPoolFetcher {
+    async fn fetch(&self, token_pairs: HashSet<TokenPair>, at_block: Block) -> Result<Vec<Pool>> {
+        match TokenPair::new(self.wsteth.address(), self.steth) {
+            Some(pair) if token_pairs.contains(&pair) => (),
+            _ => return Ok(Vec::new()),
+        }
+
+        let mut batch = Web3CallBatch::new(self.web3.transport().clone());
+        let block = BlockId::Number(at_block.into());
+        let steth_per_token = self
+            .wsteth
+            .st_eth_per_token()
+            .block(block)
+            .batch_call(&mut batch);
+        let tokens_per_steth = self
+            .wsteth
+            .tokens_per_st_eth()
+            .block(block)
+            .batch_call(&mut batch);
+        batch.execute_all(MAX_BATCH_SIZE).await;
+
+        let pool = handle_results(FetchedPool {
+            wsteth: self.wsteth.address(),
+            steth: self.steth,
+            steth_per_token: steth_per_token.await,
+            tokens_per_steth: tokens_per_steth.await,
+        })?;
+        Ok(pool.into_iter().collect())
+    }
+}
+
+struct FetchedPool {
+    wsteth: H160,
+    steth: H160,
+    steth_per_token: Result<U256, MethodError>,
+    tokens_per_steth: Result<U256, MethodError>,
+}
+
+fn handle_results(fetched_pool: FetchedPool) -> Result<Option<Pool>> {
+    let steth_per_token = handle_contract_error(fetched_pool.steth_per_token)?;
+    let tokens_per_steth = handle_contract_error(fetched_pool.tokens_per_steth)?;
+
+    Ok(match (steth_per_token, tokens_per_steth) {
+        (Some(steth_per_token), Some(tokens_per_steth)) => Some(Pool {
+            wsteth: fetched_pool.wsteth,
+            steth: fetched_pool.steth,
+            steth_per_token,
+            tokens_per_steth,
+        }),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethcontract_error;
+
+    fn wsteth() -> Pool {
+        // 1 wstETH is worth 1.25 stETH.
+        Pool {
+            wsteth: H160([1; 20]),
+            steth: H160([2; 20]),
+            steth_per_token: U256::from(125) * U256::exp10(16),
+            tokens_per_steth: U256::from(8) * U256::exp10(17),
+        }
+    }
+
+    #[test]
+    fn wraps_and_unwraps() {
+        let pool = wsteth();
+        let (wsteth, steth) = (pool.wsteth, pool.steth);
+
+        assert_eq!(
+            pool.get_amount_out(wsteth, (U256::from(10) * U256::exp10(18), steth)),
+            Some(U256::from(8) * U256::exp10(18)),
+        );
+        assert_eq!(
+            pool.get_amount_out(steth, (U256::from(8) * U256::exp10(18), wsteth)),
+            Some(U256::from(10) * U256::exp10(18) - 1),
+        );
+        assert_eq!(pool.get_amount_out(steth, (0.into(), wsteth)), None);
+    }
+
+    #[test]
+    fn computes_input_amounts() {
+        let pool = wsteth();
+        let (wsteth, steth) = (pool.wsteth, pool.steth);
+
+        assert_eq!(
+            pool.get_amount_in(steth, (U256::from(8) * U256::exp10(18), wsteth)),
+            Some(U256::from(10) * U256::exp10(18)),
+        );
+        let wsteth_in = pool
+            .get_amount_in(wsteth, (U256::from(10) * U256::exp10(18), steth))
+            .unwrap();
+        assert_eq!(wsteth_in, U256::from(8) * U256::exp10(18) + 1);
+        assert!(
+            pool.get_amount_out(steth, (wsteth_in, wsteth)).unwrap()
+                >= U256::from(10) * U256::exp10(18)
+        );
+    }
+
+    #[test]
+    fn unknown_tokens() {
+        let pool = wsteth();
+        assert_eq!(
+            pool.get_amount_out(H160([3; 20]), (1.into(), pool.steth)),
+            None
+        );
+        assert_eq!(pool.get_amount_in(pool.steth, (1.into(), pool.steth)), None);
+    }
+
+    #[test]
+    fn pool_fetcher_forwards_node_error() {
+        let fetched_pool = FetchedPool {
+            wsteth: H160([1; 20]),
+            steth: H160([2; 20]),
+            steth_per_token: Err(ethcontract_error::testing_node_error()),
+            tokens_per_steth: Ok(1.into()),
+        };
+        assert!(handle_results(fetched_pool).is_err());
+    }
+
+    #[test]
+    fn pool_fetcher_skips_contract_error() {
+        let fetched_pool = FetchedPool {
+            wsteth: H160([1; 20]),
+            steth: H160([2; 20]),
+            steth_per_token: Ok(1.into()),
+            tokens_per_steth: Err(ethcontract_error::testing_contract_error()),
+        };
+        assert!(handle_results(fetched_pool).unwrap().is_none());
+    }
+}
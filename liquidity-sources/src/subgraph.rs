@@ -54,6 +54,15 @@ impl SubgraphClient {
         })
     }
 
+    /// Creates a new subgraph client for the subgraph at the specified URL, for
+    /// example a subgraph on the decentralized network gateway.
+    pub fn with_url(subgraph_url: impl IntoUrl, client: Client) -> Result<Self> {
+        Ok(Self {
+            client,
+            subgraph_url: subgraph_url.into_url()?,
+        })
+    }
+
     /// Performs the specified GraphQL query on the current subgraph.
     pub async fn query<T>(&self, query: &str, variables: Option<Map<String, Value>>) -> Result<T>
     where
@@ -0,0 +1,128 @@
+//! Test harness for the AMM math of the liquidity sources.
+//!
+//! Reference vectors are stored as JSON fixtures next to this module, one file
+//! per function, so that they can be extended without touching the tests. Every
+//! vector records its `source`: the test suite of the reference implementation
+//! it was taken from, the console command that produced it against the
+//! deployed reference contracts, or the call of an independent port of the
+//! contracts next to the fixtures, like `balancer_math.py`. Such ports check
+//! that they reproduce the contract outputs before they print any vectors, so
+//! a failing vector always means that the Rust port diverged from the
+//! contracts.
+//!
+//! Reference vectors only cover a few points, so the math is additionally
+//! checked for properties that hold for all amounts with
+//! [`check_swap_properties`].
+
+use crate::u256_decimal;
+use ethcontract::U256;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{fs, path::Path};
+
+/// A reference vector with the inputs of a function and its expected output.
+#[derive(Debug, Deserialize)]
+pub struct Vector<I, O> {
+    pub source: String,
+    pub input: I,
+    pub output: O,
+}
+
+/// An amount encoded as a decimal string in fixtures.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct Decimal(#[serde(with = "u256_decimal")] pub U256);
+
+/// Loads the reference vectors of a fixture.
+pub fn vectors<I: DeserializeOwned, O: DeserializeOwned>(fixture: &str) -> Vec<Vector<I, O>> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/test/amm")
+        .join(fixture);
+    let json = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", path.display(), err));
+    let vectors: Vec<Vector<I, O>> = serde_json::from_str(&json)
+        .unwrap_or_else(|err| panic!("invalid vectors in {}: {}", path.display(), err));
+    assert!(!vectors.is_empty(), "no vectors in {}", path.display());
+    vectors
+}
+
+/// Returns amounts spread geometrically from `min` to `max`, inclusive.
+pub fn amounts(min: U256, max: U256, count: usize) -> Vec<U256> {
+    assert!(count >= 2 && min > U256::zero() && min < max);
+    let (min, max) = (min.to_f64_lossy(), max.to_f64_lossy());
+    let factor = (max / min).powf(1. / (count - 1) as f64);
+    (0..count)
+        .map(|i| U256::from_f64_lossy(min * factor.powi(i as i32)))
+        .collect()
+}
+
+/// Checks the quotes of a swap in one direction for properties that every
+/// pool should satisfy:
+///
+/// - Selling more never buys less, and once an amount can't be sold because
+///   the pool doesn't have enough liquidity, no larger amount can.
+/// - Buying the amount that was quoted for selling some amount never costs
+///   more than that amount, and selling the amount quoted for buying some
+///   amount buys at least that much.
+///
+/// `amount_out` and `amount_in` quote exact input and exact output swaps and
+/// `amounts` need to be increasing. Round trips are allowed to be off by the
+/// relative `tolerance` and one atom to account for approximations in the
+/// math, like the power function of weighted pools.
+pub fn check_swap_properties(
+    amount_out: impl Fn(U256) -> Option<U256>,
+    amount_in: impl Fn(U256) -> Option<U256>,
+    amounts: &[U256],
+    tolerance: f64,
+) {
+    let within = |amount: U256, bound: U256, sign: f64| {
+        let (amount, bound) = (amount.to_f64_lossy(), bound.to_f64_lossy());
+        sign * (amount - bound) <= bound * tolerance + 1.
+    };
+
+    let mut previous: Option<(U256, Option<U256>)> = None;
+    for &sold in amounts {
+        let bought = amount_out(sold);
+        if let Some((previous_sold, previous_bought)) = previous {
+            assert!(previous_sold < sold, "amounts need to be increasing");
+            match (previous_bought, bought) {
+                (Some(previous_bought), Some(bought)) => assert!(
+                    previous_bought <= bought,
+                    "selling {} buys {}, but selling {} only {}",
+                    previous_sold,
+                    previous_bought,
+                    sold,
+                    bought
+                ),
+                (None, Some(_)) => panic!(
+                    "selling {} is supported, but selling {} is not",
+                    sold, previous_sold
+                ),
+                _ => (),
+            }
+        }
+        previous = Some((sold, bought));
+
+        let bought = match bought {
+            Some(bought) if !bought.is_zero() => bought,
+            _ => continue,
+        };
+        let cost = amount_in(bought)
+            .unwrap_or_else(|| panic!("buying {} bought by selling {} fails", bought, sold));
+        assert!(
+            within(cost, sold, 1.),
+            "buying {} costs {}, but selling {} buys it",
+            bought,
+            cost,
+            sold
+        );
+        let rebought = amount_out(cost)
+            .unwrap_or_else(|| panic!("selling {} quoted for buying {} fails", cost, bought));
+        assert!(
+            within(rebought, bought, -1.),
+            "selling {} quoted for buying {} only buys {}",
+            cost,
+            bought,
+            rebought
+        );
+    }
+}
@@ -101,6 +101,18 @@ impl TestTransport {
         assert_eq!(&p[..], params);
     }
 
+    /// Assert that the next request is for the method and return its
+    /// parameters.
+    pub fn assert_request_params(&mut self, method: &str) -> Vec<Value> {
+        let mut inner = self.inner.lock().unwrap();
+        let idx = inner.asserted;
+        inner.asserted += 1;
+
+        let (m, p) = inner.requests.get(idx).expect("Expected result.").clone();
+        assert_eq!(&m, method);
+        p
+    }
+
     /// Assert that there are no more pending requests.
     pub fn assert_no_more_requests(&self) {
         let inner = self.inner.lock().unwrap();
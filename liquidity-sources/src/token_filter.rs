@@ -0,0 +1,209 @@
+//! A filter of the tokens whose liquidity is used, shared by all sources.
+//!
+//! Tokens are excluded either because they are denied, or because an
+//! allowlist is configured and they are not listed on it. Denied tokens can be
+//! added by operators at runtime through the HTTP API served by
+//! [`handle_token_filter`], and by components like the bad token quarantine,
+//! see [`crate::bad_token`].
+//!
+//! The liquidity collector applies the filter to the liquidity of all of its
+//! fetchers, so sources don't need to filter tokens themselves.
+
+use crate::{liquidity::Liquidity, token_list::TokenAllowlist, token_pair::TokenPair};
+use ethcontract::H160;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::{Arc, RwLock},
+};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+#[derive(Default)]
+pub struct TokenFilter {
+    denied: RwLock<HashSet<H160>>,
+    allowlist: Option<Arc<TokenAllowlist>>,
+}
+
+impl TokenFilter {
+    /// Creates a filter denying the tokens.
+    pub fn new(denied: impl IntoIterator<Item = H160>) -> Self {
+        Self {
+            denied: RwLock::new(denied.into_iter().collect()),
+            allowlist: None,
+        }
+    }
+
+    /// Only allows the tokens of the allowlist.
+    pub fn with_allowlist(mut self, allowlist: Arc<TokenAllowlist>) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
+
+    pub fn deny(&self, token: H160) {
+        self.denied.write().unwrap().insert(token);
+    }
+
+    /// Removes a token from the denied tokens, returning whether it was denied.
+    pub fn undeny(&self, token: &H160) -> bool {
+        self.denied.write().unwrap().remove(token)
+    }
+
+    pub fn is_denied(&self, token: &H160) -> bool {
+        self.denied.read().unwrap().contains(token)
+    }
+
+    pub fn denied(&self) -> HashSet<H160> {
+        self.denied.read().unwrap().clone()
+    }
+
+    /// Returns whether the liquidity of the token is used.
+    pub fn is_allowed(&self, token: &H160) -> bool {
+        !self.is_denied(token)
+            && match &self.allowlist {
+                Some(allowlist) => allowlist.contains(token),
+                None => true,
+            }
+    }
+
+    /// Removes the pairs with tokens that are not allowed.
+    pub fn filter_pairs(&self, token_pairs: HashSet<TokenPair>) -> HashSet<TokenPair> {
+        token_pairs
+            .into_iter()
+            .filter(|pair| {
+                let (token_a, token_b) = pair.get();
+                self.is_allowed(&token_a) && self.is_allowed(&token_b)
+            })
+            .collect()
+    }
+
+    /// Returns whether the liquidity can be used, which is the case unless it
+    /// can trade a denied token. Liquidity that can trade tokens that are not
+    /// on the allowlist is still used for trading the listed ones.
+    pub fn allows_liquidity(&self, liquidity: &Liquidity) -> bool {
+        let denied = self.denied.read().unwrap();
+        !liquidity.token_pairs().iter().any(|pair| {
+            let (token_a, token_b) = pair.get();
+            denied.contains(&token_a) || denied.contains(&token_b)
+        })
+    }
+}
+
+/// The denied tokens, as returned by the HTTP API.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DeniedTokens {
+    pub denied: BTreeSet<H160>,
+}
+
+/// Routes for inspecting and updating the denied tokens at runtime, where
+/// tokens are specified as hex encoded addresses:
+///
+/// - `GET /token_filter/denied` returns the denied tokens.
+/// - `PUT /token_filter/denied/{token}` denies a token.
+/// - `DELETE /token_filter/denied/{token}` removes a denied token, responding
+///   with 404 if it wasn't denied.
+pub fn handle_token_filter(
+    filter: Arc<TokenFilter>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let denied = warp::path!("token_filter" / "denied");
+    let denied_token = warp::path!("token_filter" / "denied" / H160);
+
+    let list = {
+        let filter = filter.clone();
+        denied.and(warp::get()).map(move || {
+            warp::reply::json(&DeniedTokens {
+                denied: filter.denied().into_iter().collect(),
+            })
+        })
+    };
+    let deny = {
+        let filter = filter.clone();
+        denied_token.and(warp::put()).map(move |token| {
+            tracing::info!(?token, "denying token");
+            filter.deny(token);
+            StatusCode::NO_CONTENT
+        })
+    };
+    let undeny = denied_token.and(warp::delete()).map(move |token: H160| {
+        if filter.undeny(&token) {
+            tracing::info!(?token, "no longer denying token");
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::NOT_FOUND
+        }
+    });
+
+    list.or(deny).or(undeny)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::uniswap_v2::pool_fetching::Pool;
+    use maplit::{btreeset, hashset};
+    use num::rational::Ratio;
+
+    #[test]
+    fn filters_pairs_and_liquidity_with_denied_tokens() {
+        let (a, b, c) = (H160([1; 20]), H160([2; 20]), H160([3; 20]));
+        let filter = TokenFilter::new([c]);
+        assert_eq!(
+            filter.filter_pairs(hashset! {
+                TokenPair::new(a, b).unwrap(),
+                TokenPair::new(b, c).unwrap(),
+            }),
+            hashset! { TokenPair::new(a, b).unwrap() }
+        );
+
+        let pool = |token_a, token_b| {
+            Liquidity::ConstantProduct(Pool {
+                tokens: TokenPair::new(token_a, token_b).unwrap(),
+                reserves: (13, 37),
+                fee: Ratio::new(3, 1000),
+            })
+        };
+        assert!(filter.allows_liquidity(&pool(a, b)));
+        assert!(!filter.allows_liquidity(&pool(a, c)));
+
+        assert!(filter.undeny(&c));
+        assert!(!filter.undeny(&c));
+        assert!(filter.allows_liquidity(&pool(a, c)));
+    }
+
+    #[tokio::test]
+    async fn updates_denied_tokens_through_api() {
+        let filter = Arc::new(TokenFilter::default());
+        let api = handle_token_filter(filter.clone());
+        let token = H160([1; 20]);
+        let path = format!("/token_filter/denied/{:x}", token);
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path(&path)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(filter.is_denied(&token));
+
+        let response = warp::test::request()
+            .path("/token_filter/denied")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<DeniedTokens>(response.body()).unwrap(),
+            DeniedTokens {
+                denied: btreeset! { token },
+            }
+        );
+
+        for status in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let response = warp::test::request()
+                .method("DELETE")
+                .path(&path)
+                .reply(&api)
+                .await;
+            assert_eq!(response.status(), status);
+        }
+        assert!(!filter.is_denied(&token));
+    }
+}
@@ -1,9 +1,14 @@
 use crate::Web3;
+use anyhow::{Context as _, Result};
 use async_trait::async_trait;
-use contracts::ERC20;
+use contracts::{ERC20Bytes32Metadata, ERC20};
 use ethcontract::{batch::CallBatch, H160};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
 
 use mockall::*;
@@ -11,24 +16,37 @@ use mockall::*;
 const MAX_BATCH_SIZE: usize = 100;
 
 #[cfg_attr(test, derive(Eq, PartialEq))]
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct TokenInfo {
     pub decimals: Option<u8>,
     pub symbol: Option<String>,
+    pub name: Option<String>,
 }
 
 pub struct TokenInfoFetcher {
     pub web3: Web3,
+    /// Token information that takes precedence over the information of the
+    /// token contracts, for tokens whose metadata can't be fetched or is
+    /// wrong. Only the fields that are set are overridden.
+    pub overrides: HashMap<H160, TokenInfo>,
 }
 
 #[automock]
 #[async_trait]
 pub trait TokenInfoFetching: Send + Sync {
-    /// Retrieves all token information.
-    /// Default implementation calls get_token_info for each token and ignores errors.
+    /// Retrieves the information of all tokens. Information that can't be
+    /// retrieved for a token, for example because it doesn't implement the
+    /// optional ERC20 metadata methods, is `None`.
     async fn get_token_infos(&self, addresses: &[H160]) -> HashMap<H160, TokenInfo>;
 }
 
+/// Fetches token information with batched `eth_call`s, so that the information
+/// of many tokens is retrieved with a few requests instead of three requests
+/// per token.
+///
+/// Some tokens predate the ERC20 standard and return their symbol and name as
+/// `bytes32`, like MKR and SAI. Metadata that can't be decoded as a non-empty
+/// string is fetched again as `bytes32` in a second batch.
 #[async_trait]
 impl TokenInfoFetching for TokenInfoFetcher {
     async fn get_token_infos(&self, addresses: &[H160]) -> HashMap<H160, TokenInfo> {
@@ -40,44 +58,189 @@ impl TokenInfoFetching for TokenInfoFetcher {
                 (
                     erc20.methods().decimals().batch_call(&mut batch),
                     erc20.methods().symbol().batch_call(&mut batch),
+                    erc20.methods().name().batch_call(&mut batch),
                 )
             })
             .collect::<Vec<_>>();
 
         batch.execute_all(MAX_BATCH_SIZE).await;
-        let mut resolved_futures = Vec::with_capacity(futures.len());
-        for (decimals, symbol) in futures {
-            resolved_futures.push((decimals.await, symbol.await));
+        let mut token_infos = Vec::with_capacity(futures.len());
+        for (address, (decimals, symbol, name)) in addresses.iter().zip(futures) {
+            let decimals = decimals.await;
+            if decimals.is_err() {
+                tracing::trace!("Failed to fetch token info for token {}", address);
+            }
+            token_infos.push(TokenInfo {
+                decimals: decimals.ok(),
+                symbol: non_empty(symbol.await),
+                name: non_empty(name.await),
+            });
+        }
+
+        let mut batch = CallBatch::new(self.web3.transport());
+        let mut fallbacks = Vec::new();
+        for (address, token_info) in addresses.iter().zip(&token_infos) {
+            if token_info.symbol.is_none() || token_info.name.is_none() {
+                let erc20 = ERC20Bytes32Metadata::at(&self.web3, *address);
+                fallbacks.push((
+                    erc20.methods().symbol().batch_call(&mut batch),
+                    erc20.methods().name().batch_call(&mut batch),
+                ));
+            }
+        }
+        if !fallbacks.is_empty() {
+            batch.execute_all(MAX_BATCH_SIZE).await;
         }
+        let mut fallbacks = fallbacks.into_iter();
+        for token_info in &mut token_infos {
+            if token_info.symbol.is_some() && token_info.name.is_some() {
+                continue;
+            }
+            let (symbol, name) = fallbacks.next().expect("fallback for every token");
+            let (symbol, name) = (symbol.await, name.await);
+            if token_info.symbol.is_none() {
+                token_info.symbol = symbol.ok().and_then(|symbol| bytes32_string(symbol.0));
+            }
+            if token_info.name.is_none() {
+                token_info.name = name.ok().and_then(|name| bytes32_string(name.0));
+            }
+        }
+
         addresses
             .iter()
-            .zip(resolved_futures)
-            .map(|(address, (decimals, symbol))| {
-                if decimals.is_err() {
-                    tracing::trace!("Failed to fetch token info for token {}", address);
+            .zip(token_infos)
+            .map(|(address, mut token_info)| {
+                if let Some(overrides) = self.overrides.get(address) {
+                    token_info.decimals = overrides.decimals.or(token_info.decimals);
+                    token_info.symbol = overrides.symbol.clone().or(token_info.symbol);
+                    token_info.name = overrides.name.clone().or(token_info.name);
                 }
-                (
-                    *address,
-                    TokenInfo {
-                        decimals: decimals.ok(),
-                        symbol: symbol.ok(),
-                    },
-                )
+                (*address, token_info)
             })
             .collect()
     }
 }
 
+/// Returns a fetched string, unless fetching it failed or it is empty.
+fn non_empty<E>(string: Result<String, E>) -> Option<String> {
+    string.ok().filter(|string| !string.is_empty())
+}
+
+/// Decodes a `bytes32` string, which is padded with zero bytes.
+fn bytes32_string(bytes: [u8; 32]) -> Option<String> {
+    let len = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    match std::str::from_utf8(&bytes[..len]) {
+        Ok(string) if !string.is_empty() => Some(string.to_string()),
+        _ => None,
+    }
+}
+
+/// Token information as persisted by a [`TokenInfoStoring`] store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredTokenInfo {
+    pub address: H160,
+    #[serde(flatten)]
+    pub info: TokenInfo,
+    /// The time the information was fetched, in seconds since the Unix epoch.
+    pub fetched_at: u64,
+}
+
+/// Persists cached token information, so that it doesn't need to be fetched
+/// again for every token after a restart.
+#[async_trait]
+pub trait TokenInfoStoring: Send + Sync {
+    /// Returns all stored token information.
+    async fn load(&self) -> Result<Vec<StoredTokenInfo>>;
+
+    /// Replaces the stored token information.
+    async fn save(&self, token_infos: &[StoredTokenInfo]) -> Result<()>;
+}
+
+/// A store that keeps token information in a JSON file.
+pub struct FileTokenInfoStore {
+    path: PathBuf,
+}
+
+impl FileTokenInfoStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl TokenInfoStoring for FileTokenInfoStore {
+    async fn load(&self) -> Result<Vec<StoredTokenInfo>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read token infos {}", self.path.display()))
+            }
+        };
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid token infos {}", self.path.display()))
+    }
+
+    async fn save(&self, token_infos: &[StoredTokenInfo]) -> Result<()> {
+        // Write to a temporary file first so that the file is never left
+        // partially written when the process is interrupted.
+        let temporary = self.path.with_extension("tmp");
+        let contents = serde_json::to_vec(token_infos)?;
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .with_context(|| format!("failed to write token infos {}", self.path.display()))
+    }
+}
+
+/// Caches the information of tokens whose decimals could be fetched.
+///
+/// Without a store, the information is cached in memory for the lifetime of
+/// the fetcher. With a store, the cache is loaded from the store on first use
+/// and saved to it whenever new information was fetched, and information is
+/// fetched again once it is older than the time to live. Expired information
+/// is still returned for as long as fetching it again fails.
 pub struct CachedTokenInfoFetcher {
     inner: Box<dyn TokenInfoFetching>,
-    cache: Arc<Mutex<HashMap<H160, TokenInfo>>>,
+    cache: Arc<Mutex<Cache>>,
+    store: Option<(Box<dyn TokenInfoStoring>, Duration)>,
+}
+
+#[derive(Default)]
+struct Cache {
+    /// Whether the cache was loaded from the store.
+    loaded: bool,
+    entries: HashMap<H160, StoredTokenInfo>,
 }
 
 impl CachedTokenInfoFetcher {
     pub fn new(inner: Box<dyn TokenInfoFetching>) -> Self {
         Self {
             inner,
-            cache: Arc::new(Mutex::new(HashMap::new())),
+            cache: Default::default(),
+            store: None,
+        }
+    }
+
+    /// Persists the cache in the store, expiring token information after the
+    /// time to live. Since decimals never change and symbols rarely do, the
+    /// time to live is usually long.
+    pub fn with_store(mut self, store: Box<dyn TokenInfoStoring>, ttl: Duration) -> Self {
+        self.store = Some((store, ttl));
+        self
+    }
+
+    fn is_expired(&self, entry: &StoredTokenInfo, now: u64) -> bool {
+        match &self.store {
+            Some((_, ttl)) => now.saturating_sub(entry.fetched_at) >= ttl.as_secs(),
+            None => false,
         }
     }
 }
@@ -86,11 +249,30 @@ impl CachedTokenInfoFetcher {
 impl TokenInfoFetching for CachedTokenInfoFetcher {
     async fn get_token_infos(&self, addresses: &[H160]) -> HashMap<H160, TokenInfo> {
         let mut cache = self.cache.lock().await;
+        let now = unix_timestamp();
+
+        if !cache.loaded {
+            if let Some((store, _)) = &self.store {
+                match store.load().await {
+                    Ok(stored) => cache.entries.extend(
+                        stored
+                            .into_iter()
+                            .filter(|entry| !self.is_expired(entry, now))
+                            .map(|entry| (entry.address, entry)),
+                    ),
+                    Err(err) => tracing::warn!(?err, "failed to load stored token infos"),
+                }
+            }
+            cache.loaded = true;
+        }
 
         // Compute set of requested addresses that are not in cache.
         let to_fetch: Vec<H160> = addresses
             .iter()
-            .filter(|address| !cache.contains_key(address))
+            .filter(|address| match cache.entries.get(*address) {
+                Some(entry) => self.is_expired(entry, now),
+                None => true,
+            })
             .cloned()
             .collect();
 
@@ -99,37 +281,203 @@ impl TokenInfoFetching for CachedTokenInfoFetcher {
             let fetched = self.inner.get_token_infos(to_fetch.as_slice()).await;
 
             // Add valid token infos to cache.
-            cache.extend(
-                fetched
-                    .into_iter()
-                    .filter(|(_, token_info)| token_info.decimals.is_some()),
-            );
+            let mut updated = false;
+            for (address, info) in fetched {
+                if info.decimals.is_some() {
+                    let entry = StoredTokenInfo {
+                        address,
+                        info,
+                        fetched_at: now,
+                    };
+                    cache.entries.insert(address, entry);
+                    updated = true;
+                }
+            }
+
+            match &self.store {
+                Some((store, _)) if updated => {
+                    let entries = cache.entries.values().cloned().collect::<Vec<_>>();
+                    if let Err(err) = store.save(&entries).await {
+                        tracing::warn!(?err, "failed to store token infos");
+                    }
+                }
+                _ => (),
+            }
         };
 
         // Return token infos from the cache.
         addresses
             .iter()
-            .map(|address| {
-                if cache.contains_key(address) {
-                    (*address, cache[address].clone())
-                } else {
-                    (
-                        *address,
-                        TokenInfo {
-                            decimals: None,
-                            symbol: None,
-                        },
-                    )
-                }
+            .map(|address| match cache.entries.get(address) {
+                Some(entry) => (*address, entry.info.clone()),
+                None => (*address, TokenInfo::default()),
             })
             .collect()
     }
 }
 
+/// Returns the current time in seconds since the Unix epoch.
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::test_transport::TestTransport;
+    use ethcontract::dyns::DynTransport;
     use maplit::hashmap;
+    use serde_json::{json, Value};
+    use web3::{ethabi::Token, types::Bytes};
+
+    fn fetcher(transport: &TestTransport, overrides: HashMap<H160, TokenInfo>) -> TokenInfoFetcher {
+        TokenInfoFetcher {
+            web3: Web3::new(DynTransport::new(transport.clone())),
+            overrides,
+        }
+    }
+
+    fn output(token: Token) -> Value {
+        serde_json::to_value(Bytes(web3::ethabi::encode(&[token]))).unwrap()
+    }
+
+    fn bytes32(string: &str) -> Value {
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.resize(32, 0);
+        output(Token::FixedBytes(bytes))
+    }
+
+    /// Asserts that the next requests are the specified number of calls to
+    /// each of the tokens.
+    fn assert_calls(transport: &mut TestTransport, tokens: &[H160], calls: usize) {
+        for token in tokens {
+            for _ in 0..calls {
+                let params = transport.assert_request_params("eth_call");
+                assert_eq!(params[0]["to"], json!(token));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn token_info_fetcher_batches_calls() {
+        let mut transport = TestTransport::new();
+        let fetcher = fetcher(&transport, HashMap::new());
+        let tokens = [H160([0x01; 20]), H160([0x02; 20])];
+
+        // The second token doesn't implement the optional metadata methods,
+        // neither as strings nor as `bytes32`.
+        transport.add_response(json!([
+            output(Token::Uint(18.into())),
+            output(Token::String("CAT".to_string())),
+            output(Token::String("Cat Token".to_string())),
+            output(Token::Uint(6.into())),
+            json!("0x"),
+            json!("0x"),
+        ]));
+        transport.add_response(json!(["0x", "0x"]));
+        let token_infos = fetcher.get_token_infos(&tokens).await;
+
+        assert_calls(&mut transport, &[tokens[0], tokens[1]], 3);
+        assert_calls(&mut transport, &[tokens[1]], 2);
+        transport.assert_no_more_requests();
+        assert_eq!(
+            token_infos,
+            hashmap! {
+                tokens[0] => TokenInfo {
+                    decimals: Some(18),
+                    symbol: Some("CAT".to_string()),
+                    name: Some("Cat Token".to_string()),
+                },
+                tokens[1] => TokenInfo {
+                    decimals: Some(6),
+                    symbol: None,
+                    name: None,
+                },
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn token_info_fetcher_handles_non_standard_metadata() {
+        let mut transport = TestTransport::new();
+        let (mkr, sai, broken) = (H160([0x01; 20]), H160([0x02; 20]), H160([0x03; 20]));
+        let fetcher = fetcher(
+            &transport,
+            hashmap! {
+                sai => TokenInfo {
+                    name: Some("Sai Stablecoin v1.0".to_string()),
+                    ..Default::default()
+                },
+                broken => TokenInfo {
+                    decimals: Some(18),
+                    symbol: Some("BRKN".to_string()),
+                    name: None,
+                },
+            },
+        );
+
+        // MKR and SAI return their metadata as `bytes32`, where SAI's name is
+        // empty, and the metadata calls of the broken token revert.
+        transport.add_response(json!([
+            output(Token::Uint(18.into())),
+            bytes32("MKR"),
+            bytes32("Maker"),
+            output(Token::Uint(18.into())),
+            bytes32("SAI"),
+            bytes32(""),
+            "0x",
+            "0x",
+            "0x",
+        ]));
+        transport.add_response(json!([
+            bytes32("MKR"),
+            bytes32("Maker"),
+            bytes32("SAI"),
+            bytes32(""),
+            "0x",
+            "0x",
+        ]));
+        let token_infos = fetcher.get_token_infos(&[mkr, sai, broken]).await;
+
+        assert_calls(&mut transport, &[mkr, sai, broken], 3);
+        assert_calls(&mut transport, &[mkr, sai, broken], 2);
+        transport.assert_no_more_requests();
+        assert_eq!(
+            token_infos,
+            hashmap! {
+                mkr => TokenInfo {
+                    decimals: Some(18),
+                    symbol: Some("MKR".to_string()),
+                    name: Some("Maker".to_string()),
+                },
+                sai => TokenInfo {
+                    decimals: Some(18),
+                    symbol: Some("SAI".to_string()),
+                    name: Some("Sai Stablecoin v1.0".to_string()),
+                },
+                broken => TokenInfo {
+                    decimals: Some(18),
+                    symbol: Some("BRKN".to_string()),
+                    name: None,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_bytes32_strings() {
+        let mut bytes = [0; 32];
+        assert_eq!(bytes32_string(bytes), None);
+        bytes[..3].copy_from_slice(b"MKR");
+        assert_eq!(bytes32_string(bytes), Some("MKR".to_string()));
+        bytes = [b'a'; 32];
+        assert_eq!(bytes32_string(bytes), Some("a".repeat(32)));
+        bytes[0] = 0xff;
+        assert_eq!(bytes32_string(bytes), None);
+    }
 
     #[tokio::test]
     async fn cached_token_info_fetcher() {
@@ -142,7 +490,7 @@ mod tests {
             .times(1)
             .return_once(move |_| {
                 hashmap! {
-                    address0 => TokenInfo { decimals: Some(18), symbol: Some("CAT".to_string()) },
+                    address0 => TokenInfo { decimals: Some(18), symbol: Some("CAT".to_string()), name: None },
                 }
             });
         mock_token_info_fetcher
@@ -150,7 +498,7 @@ mod tests {
             .times(2)
             .returning(|_| {
                 hashmap! {
-                    H160::from_low_u64_be(1) => TokenInfo { decimals: None, symbol: None },
+                    H160::from_low_u64_be(1) => TokenInfo::default(),
                 }
             });
         let cached_token_info_fetcher =
@@ -170,4 +518,50 @@ mod tests {
         // Should try to refetch the item thus satisfying the times(2) constraint above.
         cached_token_info_fetcher.get_token_infos(&[address1]).await;
     }
+
+    #[tokio::test]
+    async fn cached_token_info_fetcher_persists_token_infos() {
+        let token = H160([0x01; 20]);
+        let token_info = TokenInfo {
+            decimals: Some(18),
+            symbol: Some("CAT".to_string()),
+            name: None,
+        };
+        let path = std::env::temp_dir().join(format!("token-infos-{}", std::process::id()));
+        let store = || Box::new(FileTokenInfoStore::new(&path));
+        let ttl = Duration::from_secs(86_400);
+
+        let mut inner = MockTokenInfoFetching::new();
+        let fetched = token_info.clone();
+        inner
+            .expect_get_token_infos()
+            .times(1)
+            .return_once(move |_| hashmap! { token => fetched });
+        let fetcher = CachedTokenInfoFetcher::new(Box::new(inner)).with_store(store(), ttl);
+        assert_eq!(fetcher.get_token_infos(&[token]).await[&token], token_info);
+
+        // A restarted fetcher loads the token infos from the store instead of
+        // fetching them again, which would panic because of the missing
+        // expectation.
+        let fetcher = CachedTokenInfoFetcher::new(Box::new(MockTokenInfoFetching::new()))
+            .with_store(store(), ttl);
+        assert_eq!(fetcher.get_token_infos(&[token]).await[&token], token_info);
+
+        // Expired token infos are fetched again.
+        let mut stored = store().load().await.unwrap();
+        assert_eq!(stored.len(), 1);
+        stored[0].fetched_at -= ttl.as_secs();
+        store().save(&stored).await.unwrap();
+        let mut inner = MockTokenInfoFetching::new();
+        inner
+            .expect_get_token_infos()
+            .times(1)
+            .returning(|_| hashmap! { H160([0x01; 20]) => TokenInfo::default() });
+        let fetcher = CachedTokenInfoFetcher::new(Box::new(inner)).with_store(store(), ttl);
+        // Fetching the token info again fails, so the expired one is used.
+        assert_eq!(fetcher.get_token_infos(&[token]).await[&token], token_info);
+
+        fs::remove_file(&path).unwrap();
+        assert!(store().load().await.unwrap().is_empty());
+    }
 }
@@ -0,0 +1,317 @@
+//! Token lists for restricting liquidity to vetted tokens.
+//!
+//! Operators can constrain the liquidity that is fetched to the tokens of one
+//! or more token lists in the standard Uniswap format, see
+//! <https://tokenlists.org>. Lists are loaded from URLs or files and refreshed
+//! periodically by maintenance. Tokens of other chains are ignored.
+//!
+//! Allowlists are applied to the liquidity of all sources through the token
+//! filter, see [`crate::token_filter::TokenFilter::with_allowlist`].
+
+use crate::{maintenance::Maintaining, token_info::TokenInfo, token_pair::TokenPair};
+use anyhow::{Context as _, Result};
+use ethcontract::H160;
+use futures::future;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+/// The location of a token list.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TokenListSource {
+    Url(Url),
+    File(PathBuf),
+}
+
+impl FromStr for TokenListSource {
+    type Err = anyhow::Error;
+
+    /// Parses HTTP and HTTPS URLs as URLs and anything else as a path.
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Self::Url(s.parse()?))
+        } else {
+            Ok(Self::File(s.into()))
+        }
+    }
+}
+
+impl TokenListSource {
+    /// Loads the token list.
+    pub async fn load(&self, client: &Client) -> Result<TokenList> {
+        match self {
+            Self::Url(url) => client
+                .get(url.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .with_context(|| format!("invalid token list {}", url)),
+            Self::File(path) => {
+                let contents = fs::read(path)
+                    .with_context(|| format!("failed to read token list {}", path.display()))?;
+                serde_json::from_slice(&contents)
+                    .with_context(|| format!("invalid token list {}", path.display()))
+            }
+        }
+    }
+}
+
+/// A token list, with the fields of the standard format that are used.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenList {
+    pub name: String,
+    pub tokens: Vec<ListedToken>,
+}
+
+/// A token of a token list.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ListedToken {
+    pub chain_id: u64,
+    pub address: H160,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// The tokens of a set of token lists on one chain, along with their metadata.
+pub struct TokenAllowlist {
+    client: Client,
+    chain_id: u64,
+    sources: Vec<TokenListSource>,
+    update_interval: Duration,
+    tokens: RwLock<HashMap<H160, TokenInfo>>,
+    next_update: Mutex<Instant>,
+}
+
+impl TokenAllowlist {
+    /// Creates an allowlist with the tokens of the lists, which are loaded
+    /// again by maintenance once the update interval has passed.
+    ///
+    /// Fails if any of the lists can't be loaded.
+    pub async fn new(
+        client: Client,
+        chain_id: u64,
+        sources: Vec<TokenListSource>,
+        update_interval: Duration,
+    ) -> Result<Self> {
+        let tokens = load_tokens(&client, chain_id, &sources).await?;
+        Ok(Self {
+            client,
+            chain_id,
+            sources,
+            update_interval,
+            tokens: RwLock::new(tokens),
+            next_update: Mutex::new(Instant::now() + update_interval),
+        })
+    }
+
+    pub fn contains(&self, token: &H160) -> bool {
+        self.tokens.read().unwrap().contains_key(token)
+    }
+
+    /// Returns the metadata of a listed token.
+    pub fn token_info(&self, token: &H160) -> Option<TokenInfo> {
+        self.tokens.read().unwrap().get(token).cloned()
+    }
+
+    /// Returns the metadata of all listed tokens, for example to use as
+    /// overrides of a [`crate::token_info::TokenInfoFetcher`].
+    pub fn token_infos(&self) -> HashMap<H160, TokenInfo> {
+        self.tokens.read().unwrap().clone()
+    }
+
+    /// Removes the pairs with tokens that are not listed.
+    pub fn filter_pairs(&self, token_pairs: HashSet<TokenPair>) -> HashSet<TokenPair> {
+        let tokens = self.tokens.read().unwrap();
+        token_pairs
+            .into_iter()
+            .filter(|pair| {
+                let (token_a, token_b) = pair.get();
+                tokens.contains_key(&token_a) && tokens.contains_key(&token_b)
+            })
+            .collect()
+    }
+
+    /// Loads the lists again, keeping the current tokens if any of them fails
+    /// to load.
+    pub async fn update(&self) -> Result<()> {
+        let tokens = load_tokens(&self.client, self.chain_id, &self.sources).await?;
+        *self.tokens.write().unwrap() = tokens;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Maintaining for TokenAllowlist {
+    async fn run_maintenance(&self) -> Result<()> {
+        {
+            let mut next_update = self.next_update.lock().unwrap();
+            let now = Instant::now();
+            if now < *next_update {
+                return Ok(());
+            }
+            // Failed updates are also only retried after the interval, so
+            // that an unavailable list isn't requested on every block.
+            *next_update = now + self.update_interval;
+        }
+        self.update().await
+    }
+}
+
+/// Loads the tokens of the chain from all lists, where the metadata of tokens
+/// that are on multiple lists is taken from the first one.
+async fn load_tokens(
+    client: &Client,
+    chain_id: u64,
+    sources: &[TokenListSource],
+) -> Result<HashMap<H160, TokenInfo>> {
+    let lists = future::try_join_all(sources.iter().map(|source| source.load(client))).await?;
+    let mut tokens = HashMap::new();
+    for token in lists.into_iter().flat_map(|list| list.tokens) {
+        if token.chain_id != chain_id {
+            continue;
+        }
+        tokens.entry(token.address).or_insert(TokenInfo {
+            decimals: Some(token.decimals),
+            symbol: Some(token.symbol),
+            name: Some(token.name),
+        });
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashset;
+    use serde_json::json;
+
+    #[test]
+    fn parses_token_list_sources() {
+        assert_eq!(
+            "https://tokens.coingecko.com/uniswap/all.json"
+                .parse::<TokenListSource>()
+                .unwrap(),
+            TokenListSource::Url(
+                Url::parse("https://tokens.coingecko.com/uniswap/all.json").unwrap()
+            )
+        );
+        assert_eq!(
+            "lists/vetted.json".parse::<TokenListSource>().unwrap(),
+            TokenListSource::File("lists/vetted.json".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn loads_tokens_of_chain_from_lists() {
+        let list = |name: &str, tokens: serde_json::Value| {
+            let path = std::env::temp_dir().join(format!(
+                "token-list-{}-{}.json",
+                name,
+                std::process::id()
+            ));
+            let list = json!({
+                "name": name,
+                "timestamp": "2021-01-01T00:00:00.000Z",
+                "version": { "major": 1, "minor": 0, "patch": 0 },
+                "tokens": tokens,
+            });
+            fs::write(&path, serde_json::to_vec(&list).unwrap()).unwrap();
+            path
+        };
+        let dai = H160(hex_literal::hex!(
+            "6B175474E89094C44Da98b954EedeAC495271d0F"
+        ));
+        let usdc = H160(hex_literal::hex!(
+            "A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"
+        ));
+        let weth = H160(hex_literal::hex!(
+            "C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"
+        ));
+        let paths = [
+            list(
+                "first",
+                json!([
+                    {
+                        "chainId": 1,
+                        "address": "0x6B175474E89094C44Da98b954EedeAC495271d0F",
+                        "name": "Dai Stablecoin",
+                        "symbol": "DAI",
+                        "decimals": 18,
+                        "logoURI": "https://example.com/dai.png",
+                    },
+                    {
+                        "chainId": 100,
+                        "address": "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+                        "name": "USD Coin",
+                        "symbol": "USDC",
+                        "decimals": 6,
+                    },
+                ]),
+            ),
+            list(
+                "second",
+                json!([
+                    {
+                        "chainId": 1,
+                        "address": "0x6B175474E89094C44Da98b954EedeAC495271d0F",
+                        "name": "Dai",
+                        "symbol": "DAI",
+                        "decimals": 18,
+                        "tags": ["stablecoin"],
+                    },
+                    {
+                        "chainId": 1,
+                        "address": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+                        "name": "Wrapped Ether",
+                        "symbol": "WETH",
+                        "decimals": 18,
+                    },
+                ]),
+            ),
+        ];
+
+        let allowlist = TokenAllowlist::new(
+            Client::new(),
+            1,
+            paths.iter().cloned().map(TokenListSource::File).collect(),
+            Duration::from_secs(3600),
+        )
+        .await
+        .unwrap();
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert!(allowlist.contains(&dai) && allowlist.contains(&weth));
+        assert!(!allowlist.contains(&usdc));
+        assert_eq!(
+            allowlist.token_info(&dai).unwrap().name.as_deref(),
+            Some("Dai Stablecoin")
+        );
+        assert_eq!(
+            allowlist.filter_pairs(hashset! {
+                TokenPair::new(dai, weth).unwrap(),
+                TokenPair::new(dai, usdc).unwrap(),
+            }),
+            hashset! { TokenPair::new(dai, weth).unwrap() }
+        );
+
+        // The lists are only loaded again after the update interval, and the
+        // tokens are kept when they fail to load.
+        allowlist.run_maintenance().await.unwrap();
+        *allowlist.next_update.lock().unwrap() = Instant::now();
+        assert!(allowlist.run_maintenance().await.is_err());
+        assert!(allowlist.contains(&dai));
+    }
+}
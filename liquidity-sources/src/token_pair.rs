@@ -1,7 +1,22 @@
+use crate::chain_config::ChainConfig;
+use anyhow::{anyhow, ensure, Context as _, Result};
 use primitive_types::H160;
+use serde::{Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
 
 /// Erc20 token pair specified by two contract addresses.
-#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+///
+/// Token pairs are formatted and serialized as the two hex encoded addresses
+/// separated by a dash, like `0x0000…0001-0x0000…0002`, with the lower address
+/// first.
+#[derive(
+    Copy, Clone, Debug, DeserializeFromStr, Eq, Hash, Ord, PartialEq, PartialOrd, SerializeDisplay,
+)]
 pub struct TokenPair(H160, H160);
 
 impl TokenPair {
@@ -15,6 +30,19 @@ impl TokenPair {
         }
     }
 
+    /// Create a new token pair from two addresses, where the native token
+    /// sentinel address is replaced with the wrapped native token of the
+    /// chain, see [`ChainConfig::normalize_token`].
+    ///
+    /// Returns `None` for the native token and its wrapped token, like for
+    /// equal addresses.
+    pub fn new_normalized(token_a: H160, token_b: H160, chain: &ChainConfig) -> Option<Self> {
+        Self::new(
+            chain.normalize_token(token_a),
+            chain.normalize_token(token_b),
+        )
+    }
+
     /// Used to determine if `token` is among the pair.
     pub fn contains(&self, token: &H160) -> bool {
         self.0 == *token || self.1 == *token
@@ -55,9 +83,114 @@ impl IntoIterator for TokenPair {
     }
 }
 
+impl Display for TokenPair {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}-{:?}", self.0, self.1)
+    }
+}
+
+impl FromStr for TokenPair {
+    type Err = anyhow::Error;
+
+    /// Parses two dash separated addresses, which can be in any order but must
+    /// not be equal.
+    fn from_str(s: &str) -> Result<Self> {
+        let (token_a, token_b) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("token pair {:?} is not two dash separated addresses", s))?;
+        let parse = |token: &str| {
+            token
+                .strip_prefix("0x")
+                .unwrap_or(token)
+                .parse::<H160>()
+                .with_context(|| format!("invalid token address {:?}", token))
+        };
+        Self::new(parse(token_a)?, parse(token_b)?)
+            .ok_or_else(|| anyhow!("token pair {:?} has equal addresses", s))
+    }
+}
+
+/// A route of trades through a sequence of tokens, starting with the sold
+/// token and ending with the bought one.
+///
+/// Paths have at least two tokens and never trade a token for itself, so every
+/// two consecutive tokens form a [`TokenPair`]. Paths may visit a token more
+/// than once, like arbitrage cycles do, unless they are created with
+/// [`TokenPath::acyclic`]. They are serialized as arrays of addresses.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(try_from = "Vec<H160>", into = "Vec<H160>")]
+pub struct TokenPath(Vec<H160>);
+
+impl TokenPath {
+    /// Creates a path through the tokens.
+    ///
+    /// Fails if there are fewer than two tokens or a token is immediately
+    /// followed by itself.
+    pub fn new(tokens: Vec<H160>) -> Result<Self> {
+        ensure!(tokens.len() >= 2, "token path needs at least two tokens");
+        ensure!(
+            tokens.windows(2).all(|pair| pair[0] != pair[1]),
+            "token path trades a token for itself"
+        );
+        Ok(Self(tokens))
+    }
+
+    /// Creates a path through the tokens that visits every token only once.
+    pub fn acyclic(tokens: Vec<H160>) -> Result<Self> {
+        let path = Self::new(tokens)?;
+        ensure!(path.is_acyclic(), "token path visits a token twice");
+        Ok(path)
+    }
+
+    /// Returns `true` if no token is visited twice.
+    pub fn is_acyclic(&self) -> bool {
+        let mut visited = HashSet::new();
+        self.0.iter().all(|token| visited.insert(token))
+    }
+
+    pub fn tokens(&self) -> &[H160] {
+        &self.0
+    }
+
+    pub fn sell_token(&self) -> H160 {
+        self.0[0]
+    }
+
+    pub fn buy_token(&self) -> H160 {
+        self.0[self.0.len() - 1]
+    }
+
+    /// The number of trades along the path.
+    pub fn hops(&self) -> usize {
+        self.0.len() - 1
+    }
+
+    /// Returns the pairs of consecutive tokens, in the order they are traded.
+    pub fn pairs(&self) -> impl Iterator<Item = TokenPair> + '_ {
+        self.0.windows(2).map(|pair| {
+            TokenPair::new(pair[0], pair[1]).expect("consecutive path tokens are not equal")
+        })
+    }
+}
+
+impl TryFrom<Vec<H160>> for TokenPath {
+    type Error = anyhow::Error;
+
+    fn try_from(tokens: Vec<H160>) -> Result<Self> {
+        Self::new(tokens)
+    }
+}
+
+impl From<TokenPath> for Vec<H160> {
+    fn from(path: TokenPath) -> Self {
+        path.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sources::native::NATIVE_TOKEN;
     use std::cmp::Ordering;
 
     impl Default for TokenPair {
@@ -147,4 +280,110 @@ mod tests {
         assert_eq!(pair_bc.cmp(&pair_ca), Ordering::Greater);
         assert_eq!(pair_ab.cmp(&TokenPair::first_ord()), Ordering::Equal);
     }
+
+    #[test]
+    fn token_pair_normalizes_native_token() {
+        let chain = ChainConfig::for_chain(1).unwrap();
+        let weth = chain.wrapped_native_token.unwrap();
+        let token = H160::from_low_u64_be(1);
+
+        assert_eq!(
+            TokenPair::new_normalized(NATIVE_TOKEN, token, chain),
+            TokenPair::new(weth, token)
+        );
+        assert_eq!(
+            TokenPair::new_normalized(weth, token, chain),
+            TokenPair::new(weth, token)
+        );
+        assert_eq!(TokenPair::new_normalized(NATIVE_TOKEN, weth, chain), None);
+    }
+
+    #[test]
+    fn token_pair_string_round_trip() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let pair = TokenPair::new(token_a, token_b).unwrap();
+        let string = "0x0000000000000000000000000000000000000001-\
+                      0x0000000000000000000000000000000000000002";
+
+        assert_eq!(pair.to_string(), string);
+        assert_eq!(string.parse::<TokenPair>().unwrap(), pair);
+        assert_eq!(
+            "0x0000000000000000000000000000000000000002-\
+             0000000000000000000000000000000000000001"
+                .parse::<TokenPair>()
+                .unwrap(),
+            pair
+        );
+
+        assert_eq!(
+            serde_json::to_value(pair).unwrap(),
+            serde_json::Value::String(string.to_string())
+        );
+        assert_eq!(
+            serde_json::from_value::<TokenPair>(serde_json::Value::String(string.to_string()))
+                .unwrap(),
+            pair
+        );
+    }
+
+    #[test]
+    fn token_pair_parsing_fails_for_invalid_pairs() {
+        for invalid in [
+            "",
+            "0x0000000000000000000000000000000000000001",
+            "0x0000000000000000000000000000000000000001-0x02",
+            "0x0000000000000000000000000000000000000001-\
+             0x0000000000000000000000000000000000000001",
+        ] {
+            assert!(invalid.parse::<TokenPair>().is_err(), "{}", invalid);
+        }
+        assert!(serde_json::from_str::<TokenPair>("\"0x01-0x02\"").is_err());
+    }
+
+    #[test]
+    fn token_path_validation() {
+        let token_a = H160::from_low_u64_be(0);
+        let token_b = H160::from_low_u64_be(1);
+        let token_c = H160::from_low_u64_be(2);
+
+        assert!(TokenPath::new(vec![]).is_err());
+        assert!(TokenPath::new(vec![token_a]).is_err());
+        assert!(TokenPath::new(vec![token_a, token_b, token_b]).is_err());
+
+        let cycle = TokenPath::new(vec![token_a, token_b, token_c, token_a]).unwrap();
+        assert!(!cycle.is_acyclic());
+        assert!(TokenPath::acyclic(cycle.into()).is_err());
+
+        let path = TokenPath::acyclic(vec![token_c, token_a, token_b]).unwrap();
+        assert_eq!(path.sell_token(), token_c);
+        assert_eq!(path.buy_token(), token_b);
+        assert_eq!(path.hops(), 2);
+        assert_eq!(
+            path.pairs().collect::<Vec<_>>(),
+            [
+                TokenPair::new(token_a, token_c).unwrap(),
+                TokenPair::new(token_a, token_b).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_path_serialization() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let path = TokenPath::new(vec![token_b, token_a]).unwrap();
+        let json = serde_json::json!([
+            "0x0000000000000000000000000000000000000002",
+            "0x0000000000000000000000000000000000000001",
+        ]);
+
+        assert_eq!(serde_json::to_value(&path).unwrap(), json);
+        assert_eq!(serde_json::from_value::<TokenPath>(json).unwrap(), path);
+        assert!(serde_json::from_value::<TokenPath>(serde_json::json!([
+            "0x0000000000000000000000000000000000000001",
+            "0x0000000000000000000000000000000000000001",
+        ]))
+        .is_err());
+    }
 }
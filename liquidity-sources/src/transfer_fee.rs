@@ -0,0 +1,273 @@
+//! Detecting tokens that charge a fee on transfers.
+//!
+//! Fee-on-transfer tokens deliver less than the transferred amount to the
+//! recipient, so pools receive less than a quote sells into them and
+//! constant product math overestimates their output. Such tokens are detected
+//! by simulating a transfer from a holder of the token, usually a pool, with
+//! `trace_callMany` and measuring the balance of the recipient before and
+//! after the transfer.
+
+use crate::{
+    fixed_point::{mul_div, Rounding},
+    liquidity::interaction::encode_call,
+    Web3,
+};
+use anyhow::{ensure, Context as _, Result};
+use contracts::ERC20;
+use ethcontract::{BlockId, H160, U256};
+use std::{collections::HashMap, sync::Mutex};
+use web3::{
+    ethabi::{self, ParamType, Token},
+    types::{BlockTrace, CallRequest, TraceType},
+};
+
+/// The recipient of simulated transfers. Tokens may exempt well known
+/// addresses from fees, so this is an arbitrary address without a history.
+pub(crate) const RECIPIENT: H160 = H160([0x2f; 20]);
+
+/// The fee a token charges on transfers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransferFee {
+    /// The recipient receives the transferred amount.
+    None,
+    /// The recipient only received `received` of the `transferred` amount.
+    Fee { transferred: U256, received: U256 },
+    /// The holder can't transfer the token, for example because the token
+    /// restricts transfers or the transfer reverts.
+    Untransferable,
+}
+
+impl TransferFee {
+    /// Returns the amount that the recipient of a transfer receives, or `None`
+    /// if the token can't be transferred.
+    ///
+    /// Quotes over constant product pools need to use the received amount as
+    /// the amount sold into the pool, or exclude tokens with fees from
+    /// constant product liquidity altogether.
+    pub fn amount_received(&self, amount: U256) -> Option<U256> {
+        match self {
+            Self::None => Some(amount),
+            Self::Fee {
+                transferred,
+                received,
+            } => mul_div(amount, *received, *transferred, Rounding::Down),
+            Self::Untransferable => None,
+        }
+    }
+}
+
+/// Detects transfer fees of tokens, caching the fee of every token once it
+/// was detected.
+pub struct TransferFeeDetector {
+    web3: Web3,
+    cache: Mutex<HashMap<H160, TransferFee>>,
+}
+
+impl TransferFeeDetector {
+    pub fn new(web3: Web3) -> Self {
+        Self {
+            web3,
+            cache: Default::default(),
+        }
+    }
+
+    /// Returns the cached transfer fee of the token, or `None` if it wasn't
+    /// detected yet.
+    pub fn cached(&self, token: H160) -> Option<TransferFee> {
+        self.cache.lock().unwrap().get(&token).copied()
+    }
+
+    /// Returns the transfer fee of the token, simulating a transfer of a
+    /// fraction of the holder's balance at the block unless the fee is cached.
+    ///
+    /// Returns an error if the holder has no balance or the node doesn't
+    /// support `trace_callMany`.
+    pub async fn detect(&self, token: H160, holder: H160, block: BlockId) -> Result<TransferFee> {
+        if let Some(fee) = self.cached(token) {
+            return Ok(fee);
+        }
+
+        let amount = probe_amount(&self.web3, token, holder, block).await?;
+        let traces = simulate(
+            &self.web3,
+            vec![
+                balance_of(token, RECIPIENT),
+                transfer(token, holder, RECIPIENT, amount),
+                balance_of(token, RECIPIENT),
+            ],
+            block,
+        )
+        .await
+        .context("failed to simulate transfer")?;
+        let before = decode_balance(&traces[0])?;
+        let after = decode_balance(&traces[2])?;
+
+        // Transfers that fail by returning `false` instead of reverting don't
+        // change the balance either.
+        let received = after.saturating_sub(before);
+        let fee = if received.is_zero() {
+            TransferFee::Untransferable
+        } else if received >= amount {
+            TransferFee::None
+        } else {
+            TransferFee::Fee {
+                transferred: amount,
+                received,
+            }
+        };
+        self.cache.lock().unwrap().insert(token, fee);
+        Ok(fee)
+    }
+}
+
+/// Returns the amount of a token that is transferred from a holder for
+/// simulations, a tenth of its balance. Only a fraction of the balance is
+/// transferred for tokens limiting the amount of a transfer.
+pub(crate) async fn probe_amount(
+    web3: &Web3,
+    token: H160,
+    holder: H160,
+    block: BlockId,
+) -> Result<U256> {
+    let balance = ERC20::at(web3, token)
+        .balance_of(holder)
+        .block(block)
+        .call()
+        .await
+        .context("failed to fetch holder balance")?;
+    let amount = balance / 10;
+    ensure!(
+        !amount.is_zero(),
+        "holder {:?} has no {:?} to transfer",
+        holder,
+        token
+    );
+    Ok(amount)
+}
+
+pub(crate) fn balance_of(token: H160, owner: H160) -> CallRequest {
+    CallRequest {
+        to: Some(token),
+        data: Some(encode_call("balanceOf(address)", &[Token::Address(owner)]).into()),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn transfer(token: H160, from: H160, to: H160, amount: U256) -> CallRequest {
+    CallRequest {
+        from: Some(from),
+        to: Some(token),
+        data: Some(
+            encode_call(
+                "transfer(address,uint256)",
+                &[Token::Address(to), Token::Uint(amount)],
+            )
+            .into(),
+        ),
+        ..Default::default()
+    }
+}
+
+/// Simulates the calls one after another at the block with
+/// `trace_callMany`, so that every call sees the state changes of the
+/// previous ones, and returns their traces.
+pub(crate) async fn simulate(
+    web3: &Web3,
+    calls: Vec<CallRequest>,
+    block: BlockId,
+) -> Result<Vec<BlockTrace>> {
+    let count = calls.len();
+    let calls = calls
+        .into_iter()
+        .map(|call| (call, vec![TraceType::Trace]))
+        .collect();
+    let traces = web3.trace().call_many(calls, Some(block)).await?;
+    ensure!(traces.len() == count, "unexpected number of traces");
+    Ok(traces)
+}
+
+/// Decodes the output of a `balanceOf` call.
+pub(crate) fn decode_balance(trace: &BlockTrace) -> Result<U256> {
+    match ethabi::decode(&[ParamType::Uint(256)], &trace.output.0)
+        .context("invalid balance")?
+        .as_slice()
+    {
+        [Token::Uint(balance)] => Ok(*balance),
+        _ => unreachable!("decoded a single uint"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::test_transport::TestTransport;
+    use ethcontract::{dyns::DynTransport, BlockNumber};
+    use serde_json::{json, Value};
+    use web3::types::Bytes;
+
+    fn balance(amount: u64) -> Value {
+        json!({ "output": Bytes(ethabi::encode(&[Token::Uint(amount.into())])) })
+    }
+
+    fn block() -> BlockId {
+        BlockId::Number(BlockNumber::Number(42.into()))
+    }
+
+    #[tokio::test]
+    async fn detects_and_caches_transfer_fees() {
+        let mut transport = TestTransport::new();
+        let detector = TransferFeeDetector::new(Web3::new(DynTransport::new(transport.clone())));
+        let (token, holder) = (H160([0x01; 20]), H160([0x02; 20]));
+
+        // Transferring 100 of the holder's 1000 tokens only delivers 95.
+        transport.add_response(json!(Bytes(ethabi::encode(&[Token::Uint(1000.into())]))));
+        transport.add_response(json!([balance(7), { "output": "0x" }, balance(102)]));
+        let fee = TransferFee::Fee {
+            transferred: 100.into(),
+            received: 95.into(),
+        };
+        assert_eq!(detector.detect(token, holder, block()).await.unwrap(), fee);
+
+        transport.assert_request_params("eth_call");
+        let params = transport.assert_request_params("trace_callMany");
+        assert_eq!(params[1], json!("0x2a"));
+        let calls = params[0].as_array().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[1][0]["from"], json!(holder));
+        assert_eq!(calls[1][0]["to"], json!(token));
+        assert_eq!(calls[1][1], json!(["trace"]));
+        transport.assert_no_more_requests();
+
+        // The fee is cached.
+        assert_eq!(detector.detect(token, holder, block()).await.unwrap(), fee);
+        assert_eq!(detector.cached(token), Some(fee));
+        transport.assert_no_more_requests();
+
+        assert_eq!(fee.amount_received(1000.into()), Some(950.into()));
+        assert_eq!(fee.amount_received(1.into()), Some(0.into()));
+        assert_eq!(TransferFee::None.amount_received(1.into()), Some(1.into()));
+        assert_eq!(TransferFee::Untransferable.amount_received(1.into()), None);
+    }
+
+    #[tokio::test]
+    async fn detects_untransferable_tokens() {
+        let mut transport = TestTransport::new();
+        let detector = TransferFeeDetector::new(Web3::new(DynTransport::new(transport.clone())));
+        let (token, holder) = (H160([0x01; 20]), H160([0x02; 20]));
+
+        transport.add_response(json!(Bytes(ethabi::encode(&[Token::Uint(1000.into())]))));
+        transport.add_response(json!([balance(7), { "output": "0x" }, balance(7)]));
+        assert_eq!(
+            detector.detect(token, holder, block()).await.unwrap(),
+            TransferFee::Untransferable
+        );
+
+        // Holders without a balance can't be used for detecting fees.
+        transport.add_response(json!(Bytes(ethabi::encode(&[Token::Uint(9.into())]))));
+        assert!(detector
+            .detect(H160([0x03; 20]), holder, block())
+            .await
+            .is_err());
+        assert_eq!(detector.cached(H160([0x03; 20])), None);
+    }
+}